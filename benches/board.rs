@@ -0,0 +1,173 @@
+//! Замеры работы с доской, хранящейся одним JSON: отдачи доски, добавления задачи и изменения задачи на досках из 10, 100 и 1000 задач.
+//!
+//! Замеры вызывают функции сервера из его библиотеки. Доски хранятся в памяти (см. `storage::mock`), так что замеряется работа самого сервера: разбор, изменение и сборка JSON доски. Каждая операция замеряется дважды - по времени и по числу выделений памяти:
+//!
+//! ```bash
+//! cargo bench --features test-util
+//! ```
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use criterion::measurement::{Measurement, ValueFormatter};
+use serde_json::{json, Value as JsonValue};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::runtime::Runtime;
+
+use cc_taskboard_server::core;
+use cc_taskboard_server::model::{BoardContext, TaskSort};
+use cc_taskboard_server::storage::{BoardRow, UserRow};
+use cc_taskboard_server::storage::mock::{MockData, MockDb};
+
+/// Размеры досок в задачах.
+const SIZES: [usize; 3] = [10, 100, 1000];
+
+/// Число карточек на доске; задачи распределяются между ними поровну.
+const CARDS: usize = 10;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Системный распределитель памяти, подсчитывающий выделения.
+struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Измерение числа выделений памяти (включая перевыделения).
+struct Allocations;
+
+impl Measurement for Allocations {
+  type Intermediate = u64;
+  type Value = u64;
+
+  fn start(&self) -> u64 { ALLOCATIONS.load(Ordering::Relaxed) }
+  fn end(&self, start: u64) -> u64 { ALLOCATIONS.load(Ordering::Relaxed) - start }
+  fn add(&self, v1: &u64, v2: &u64) -> u64 { v1 + v2 }
+  fn zero(&self) -> u64 { 0 }
+  fn to_f64(&self, value: &u64) -> f64 { *value as f64 }
+  fn formatter(&self) -> &dyn ValueFormatter { &AllocationsFormatter }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+  fn scale_values(&self, _typical: f64, _values: &mut [f64]) -> &'static str { "allocs" }
+  fn scale_throughputs(&self, _typical: f64, _throughput: &Throughput, _values: &mut [f64]) -> &'static str { "allocs" }
+  fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str { "allocs" }
+}
+
+fn task(id: usize) -> JsonValue {
+  json!({
+    "id": id, "author": 1, "title": format!("Задача {}", id), "executors": [1], "exec": false,
+    "notes": "Заметка с **разметкой** и [ссылкой](https://example.com).", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 1_700_000_000 + id as i64 * 3600, "expected_time": 30 },
+    "subtasks": (1..=2).map(|st| json!({
+      "id": st, "author": 1, "title": "Подзадача", "executors": [], "exec": false, "notes": "", "tags": [],
+      "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+    })).collect::<Vec<_>>()
+  })
+}
+
+/// Хранилище с одним пользователем и его доской из `tasks` задач.
+fn storage(tasks: usize) -> MockDb {
+  let per_card = tasks / CARDS;
+  let cards: Vec<JsonValue> = (1..=CARDS).map(|card_id| json!({
+    "id": card_id, "author": 1, "title": format!("Карточка {}", card_id),
+    "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+    "tasks": (1..=per_card).map(task).collect::<Vec<_>>()
+  })).collect();
+  let board = BoardRow {
+    id: 1,
+    author: 1,
+    shared_with: String::from("[1]"),
+    header: json!({ "title": "Доска", "header_background_color": "#ffffff", "header_text_color": "#000000" }).to_string(),
+    cards: serde_json::to_string(&cards).unwrap(),
+    background: json!({ "color": "#eeeeee" }).to_string(),
+    tags: String::from("[]"),
+    revision: 0,
+    settings: String::from("{}"),
+    created_at: 0,
+    updated_at: 0,
+    lanes: String::from("[]"),
+    sprints: String::from("[]"),
+    watchers: String::from("[]"),
+    archived_at: 0,
+  };
+  let user = UserRow {
+    id: 1,
+    login: String::from("bench"),
+    shared_boards: String::from("[1]"),
+    user_creds: String::from("{}"),
+    apd: String::from("{}"),
+  };
+  MockDb::new(MockData { users: vec![user], boards: vec![board], ..MockData::default() })
+}
+
+/// Загружает доску, вернув её к исходному состоянию, чтобы каждое измерение начиналось с одной и той же доски.
+fn load(rt: &Runtime, db: &MockDb, initial: &BoardRow) -> BoardContext {
+  db.data().boards[0] = initial.clone();
+  rt.block_on(core::load_board(db, &1, &1)).unwrap()
+}
+
+fn board_ops<M: Measurement>(c: &mut Criterion<M>, measurement: &str) {
+  let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  let patch: JsonValue = json!({ "title": "Новое название", "exec": true, "notes": "Изменённая заметка" });
+  let mut group = c.benchmark_group(measurement);
+  for size in SIZES {
+    let db = storage(size);
+    let initial = db.data().boards[0].clone();
+    group.bench_with_input(BenchmarkId::new("get_board", size), &size, |b, _| b.iter_batched(
+      || load(&rt, &db, &initial),
+      |ctx| rt.block_on(core::get_board(&db, ctx, None, TaskSort::default(), true, false)).unwrap(),
+      BatchSize::PerIteration
+    ));
+    group.bench_with_input(BenchmarkId::new("insert_task", size), &size, |b, _| b.iter_batched(
+      || (load(&rt, &db, &initial), serde_json::from_value(task(0)).unwrap()),
+      |(mut ctx, task)| {
+        rt.block_on(core::insert_task(&db, &mut ctx, &1, task)).unwrap();
+        ctx
+      },
+      BatchSize::PerIteration
+    ));
+    group.bench_with_input(BenchmarkId::new("apply_patch_on_task", size), &size, |b, _| b.iter_batched(
+      || (load(&rt, &db, &initial), serde_json::from_value(patch.clone()).unwrap()),
+      |(mut ctx, patch)| {
+        rt.block_on(core::apply_patch_on_task(&db, &mut ctx, &1, &1, patch)).unwrap();
+        ctx
+      },
+      BatchSize::PerIteration
+    ));
+  };
+  group.finish();
+}
+
+fn time(c: &mut Criterion) {
+  board_ops(c, "time");
+}
+
+fn allocations(c: &mut Criterion<Allocations>) {
+  board_ops(c, "allocations");
+}
+
+criterion_group!(time_benches, time);
+criterion_group!{
+  name = allocation_benches;
+  config = Criterion::default().with_measurement(Allocations).without_plots();
+  targets = allocations
+}
+criterion_main!(time_benches, allocation_benches);
@@ -0,0 +1,194 @@
+//! Типизированный клиент API сервера CC TaskBoard.
+//!
+//! Сервер принимает тело запроса и заголовок `App-Token` в виде JSON в кодировке base64, а об ошибках сообщает JSON-объектом с полем `error` (см. `API.md`). `Client` берёт эти подробности на себя: методы принимают и возвращают модели сервера (`model`, `auth`), а ошибки сервера возвращаются как `ClientError::Api` с кодом ответа и сообщением.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), cc_taskboard_client::ClientError> {
+//! use cc_taskboard_client::Client;
+//!
+//! let mut client = Client::new("http://127.0.0.1:8004");
+//! client.sign_in("bot", "bot-password").await?;
+//! for board in client.list_boards().await? {
+//!   println!("{}: {}", board.id, board.title);
+//! };
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Методы, для которых в клиенте нет отдельной функции, можно вызвать при помощи `Client::call`.
+
+use custom_error::custom_error;
+use reqwest::Method;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+
+pub use cc_taskboard_server::model;
+pub use cc_taskboard_server::sec::auth;
+
+use auth::{RefreshCredentials, SignInCredentials, SignUpCredentials, TokenAuth};
+use model::{Board, BoardBackground, BoardHeader, BoardsShort, Card, Task};
+
+custom_error!{pub ClientError
+  Transport{source: reqwest::Error} = "Не удалось выполнить запрос к серверу: {source}",
+  Json{source: serde_json::Error} = "Не удалось обработать JSON: {source}",
+  Api{status: u16, message: String} = "Сервер вернул код {status}: {message}",
+  UnexpectedBody{body: String} = "Неожиданный ответ сервера: {body}",
+  NotSignedIn = "Клиент не прошёл аутентификацию."
+}
+
+/// Результат запроса к серверу.
+pub type CResult<T> = Result<T, ClientError>;
+
+/// Клиент сервера CC TaskBoard.
+///
+/// Клиент хранит токены пользователя, полученные при регистрации, входе или обновлении, и передаёт токен доступа во всех запросах, которым он нужен.
+#[derive(Clone)]
+pub struct Client {
+  http: reqwest::Client,
+  base_url: String,
+  auth: Option<TokenAuth>,
+}
+
+impl Client {
+  /// Создаёт клиент сервера с данным адресом, например `https://taskboard.example.com`.
+  pub fn new(base_url: &str) -> Client {
+    Client { http: reqwest::Client::new(), base_url: base_url.trim_end_matches('/').to_string(), auth: None }
+  }
+
+  /// Создаёт клиент, который работает с токенами, полученными ранее.
+  pub fn with_token(base_url: &str, auth: TokenAuth) -> Client {
+    Client { auth: Some(auth), ..Client::new(base_url) }
+  }
+
+  /// Возвращает токены пользователя, например чтобы сохранить их до следующего запуска.
+  pub fn token(&self) -> Option<&TokenAuth> {
+    self.auth.as_ref()
+  }
+
+  /// Регистрирует пользователя и сохраняет его токены.
+  pub async fn sign_up(&mut self, login: &str, pass: &str, cc_key: Option<&str>) -> CResult<&TokenAuth> {
+    let creds = SignUpCredentials { login: login.into(), pass: pass.into(), cc_key: cc_key.map(String::from) };
+    let auth = self.send(Method::PUT, "/sign-up", Some(&creds), None::<&()>).await?;
+    self.store(&auth)
+  }
+
+  /// Входит в аккаунт пользователя и сохраняет его токены.
+  pub async fn sign_in(&mut self, login: &str, pass: &str) -> CResult<&TokenAuth> {
+    let creds = SignInCredentials { login: login.into(), pass: pass.into() };
+    let auth = self.send(Method::GET, "/sign-in", Some(&creds), None::<&()>).await?;
+    self.store(&auth)
+  }
+
+  /// Обменивает токен обновления на новую пару токенов и сохраняет их.
+  pub async fn refresh(&mut self) -> CResult<&TokenAuth> {
+    let creds = match &self.auth {
+      Some(TokenAuth { id, refresh_token: Some(refresh_token), .. }) =>
+        RefreshCredentials { id: *id, refresh_token: refresh_token.clone() },
+      _ => return Err(ClientError::NotSignedIn),
+    };
+    let auth = self.send(Method::POST, "/token/refresh", Some(&creds), None::<&()>).await?;
+    self.store(&auth)
+  }
+
+  /// Возвращает доски пользователя, кроме досок в архиве.
+  pub async fn list_boards(&self) -> CResult<Vec<BoardsShort>> {
+    serde_json::from_str(&self.call(Method::GET, "/list", None::<&()>).await?).map_err(ClientError::from)
+  }
+
+  /// Возвращает доску.
+  pub async fn board(&self, board_id: i64) -> CResult<Board> {
+    let body = self.call(Method::POST, "/board", Some(&json!({ "board_id": board_id }))).await?;
+    serde_json::from_str(&body).map_err(ClientError::from)
+  }
+
+  /// Создаёт доску и возвращает её идентификатор.
+  pub async fn create_board(&self, header: &BoardHeader, background: &BoardBackground) -> CResult<i64> {
+    let body = self.call(Method::PUT, "/board", Some(&json!({ "header": header, "background": background }))).await?;
+    parse_id(body)
+  }
+
+  /// Удаляет доску.
+  pub async fn delete_board(&self, board_id: i64) -> CResult<()> {
+    self.call(Method::DELETE, "/board", Some(&json!({ "board_id": board_id }))).await.map(|_| ())
+  }
+
+  /// Добавляет на доску карточку и возвращает её идентификатор.
+  ///
+  /// Идентификатор, автора и время создания карточки и её задач задаёт сервер, поэтому их значения в `card` не учитываются.
+  pub async fn create_card(&self, board_id: i64, card: &Card) -> CResult<i64> {
+    let body = self.call(Method::PUT, "/card", Some(&json!({ "board_id": board_id, "card": card }))).await?;
+    parse_id(body)
+  }
+
+  /// Добавляет в карточку задачу и возвращает её идентификатор.
+  ///
+  /// Поля задачи, которые задаёт сервер, в `task` не учитываются (см. `API.md`).
+  pub async fn create_task(&self, board_id: i64, card_id: i64, task: &Task) -> CResult<i64> {
+    let body = json!({ "board_id": board_id, "card_id": card_id, "task": task });
+    parse_id(self.call(Method::PUT, "/task", Some(&body)).await?)
+  }
+
+  /// Вызывает метод API от имени пользователя и возвращает тело ответа.
+  ///
+  /// Тело запроса `body`, если оно передано, кодируется так, как этого ожидает сервер.
+  pub async fn call<B: Serialize + ?Sized>(&self, method: Method, path: &str, body: Option<&B>) -> CResult<String> {
+    let auth = self.auth.as_ref().ok_or(ClientError::NotSignedIn)?;
+    // Токен обновления нужен только для обновления пары токенов, поэтому в остальных запросах не передаётся.
+    let token = TokenAuth { id: auth.id, token: auth.token.clone(), refresh_token: None, lifetime: None };
+    self.send(method, path, Some(&token), body).await
+  }
+
+  /// То же, что `call`, но разбирает JSON в теле ответа.
+  pub async fn call_json<T, B>(&self, method: Method, path: &str, body: Option<&B>) -> CResult<T>
+    where
+      T: DeserializeOwned,
+      B: Serialize + ?Sized,
+  {
+    serde_json::from_str(&self.call(method, path, body).await?).map_err(ClientError::from)
+  }
+
+  /// Сохраняет токены, полученные в ответе сервера.
+  fn store(&mut self, body: &str) -> CResult<&TokenAuth> {
+    let auth = serde_json::from_str::<TokenAuth>(body)?;
+    Ok(self.auth.insert(auth))
+  }
+
+  /// Выполняет запрос с данным заголовком `App-Token` и телом и возвращает тело успешного ответа.
+  async fn send<A, B>(&self, method: Method, path: &str, token: Option<&A>, body: Option<&B>) -> CResult<String>
+    where
+      A: Serialize,
+      B: Serialize + ?Sized,
+  {
+    let mut req = self.http.request(method, format!("{}{}", self.base_url, path));
+    if let Some(token) = token {
+      req = req.header("App-Token", auth::encode_creds(token)?);
+    };
+    if let Some(body) = body {
+      req = req.body(base64::encode(&serde_json::to_string(body)?));
+    };
+    let res = req.send().await?;
+    let status = res.status();
+    let body = res.text().await?;
+    match status.is_success() {
+      true => Ok(body),
+      false => Err(ClientError::Api { status: status.as_u16(), message: error_message(body) }),
+    }
+  }
+}
+
+/// Возвращает сообщение об ошибке из тела ответа сервера, а если тело не содержит его, - тело целиком.
+fn error_message(body: String) -> String {
+  match serde_json::from_str::<JsonValue>(&body) {
+    Ok(JsonValue::Object(mut obj)) => match obj.remove("error") {
+      Some(JsonValue::String(message)) => message,
+      _ => body,
+    },
+    _ => body,
+  }
+}
+
+/// Разбирает идентификатор созданной сущности, который сервер передаёт текстом.
+fn parse_id(body: String) -> CResult<i64> {
+  body.trim().parse().map_err(|_| ClientError::UnexpectedBody { body })
+}
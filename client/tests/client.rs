@@ -0,0 +1,79 @@
+//! Работа клиента с сервером, запущенным в том же процессе на хранилище SQLite.
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cc_taskboard_client::{Client, ClientError};
+use cc_taskboard_client::model::{BoardBackground, BoardHeader, Card, Task};
+use cc_taskboard_server::setup::AppConfig;
+use cc_taskboard_server::storage::sqlite::SqliteStorage;
+use serde_json::json;
+
+/// Запускает сервер с одноразовым файлом SQLite и возвращает его адрес.
+async fn start_server() -> String {
+  let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+  let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+  let path = std::env::temp_dir().join(format!("taskboard_client_{}_{}.sqlite3", std::process::id(), nanos));
+  let cfg: AppConfig = serde_json::from_value(json!({
+    "storage": "sqlite",
+    "sqlite_path": path.to_str().unwrap(),
+    "pg": "host=127.0.0.1",
+    "admin_key": "a".repeat(64),
+    "hyper_addr": addr.to_string(),
+    "quotas": { "free": { "max_boards": 3 }, "paid": {} },
+  })).unwrap();
+  let db = Arc::new(SqliteStorage::open(&cfg.sqlite_path).unwrap());
+  tokio::spawn(cc_taskboard_server::serve(cfg, db));
+  for _ in 0..100 {
+    if tokio::net::TcpStream::connect(addr).await.is_ok() { break; };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  };
+  format!("http://{}", addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_manages_boards() {
+  let url = start_server().await;
+  let mut client = Client::new(&url);
+  assert!(matches!(client.list_boards().await, Err(ClientError::NotSignedIn)));
+  let user_id = client.sign_up("client-bot", "Kettle-Orbit-42", None).await.unwrap().id;
+
+  // Об ошибке клиент сообщает кодом ответа и сообщением сервера; второй клиент входит в тот же аккаунт.
+  let mut stranger = Client::new(&url);
+  match stranger.sign_in("client-bot", "Wrong-Orbit-42").await {
+    Err(ClientError::Api { status, message }) => assert_eq!((status, message.is_empty()), (401, false)),
+    _ => panic!("Вход с неверным паролем должен завершиться ошибкой."),
+  };
+  assert_eq!(stranger.sign_in("client-bot", "Kettle-Orbit-42").await.unwrap().id, user_id);
+  let old_token = stranger.token().unwrap().token.clone();
+  assert_ne!(stranger.refresh().await.unwrap().token, old_token);
+
+  let header = BoardHeader {
+    title: String::from("Доска бота"),
+    header_text_color: String::from("#000000"),
+    header_background_color: String::from("#ffffff"),
+  };
+  let background = BoardBackground::Color { color: String::from("#eeeeee") };
+  let board_id = client.create_board(&header, &background).await.unwrap();
+  let card: Card = serde_json::from_value(json!({
+    "id": 0, "author": 0, "title": "Входящие", "tasks": [],
+    "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+  })).unwrap();
+  let card_id = client.create_card(board_id, &card).await.unwrap();
+  let task: Task = serde_json::from_value(json!({
+    "id": 0, "author": 0, "title": "Ответить", "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+  })).unwrap();
+  let task_id = client.create_task(board_id, card_id, &task).await.unwrap();
+
+  let board = stranger.board(board_id).await.unwrap();
+  assert_eq!(board.header.title, "Доска бота");
+  assert_eq!(board.cards[0].tasks[0].id, task_id);
+  assert_eq!(board.cards[0].tasks[0].author, user_id);
+  let boards = client.list_boards().await.unwrap();
+  assert_eq!(boards.iter().map(|board| board.id).collect::<Vec<_>>(), vec![board_id]);
+
+  client.delete_board(board_id).await.unwrap();
+  assert!(matches!(client.board(board_id).await, Err(ClientError::Api { status: 401, .. })));
+}
@@ -0,0 +1,40 @@
+//! Отвечает за получение сведений об оплате от платёжных провайдеров.
+//!
+//! Провайдер присылает уведомления о платежах на `POST /billing/webhook`. Подлинность уведомления проверяется по подписи (см. `PaymentProvider::verify`), после чего сведения о платеже записываются в `AccountPlanDetails` пользователя (см. `core::register_payment`).
+
+use chrono::{DateTime, Utc};
+use hyper::HeaderMap;
+
+use crate::setup::AppConfig;
+
+mod stripe;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Платёж, о котором сообщил провайдер.
+pub struct PaymentEvent {
+  /// Идентификатор пользователя, оплатившего аккаунт.
+  pub user_id: i64,
+  /// Дата и время платежа.
+  pub paid_at: DateTime<Utc>,
+}
+
+/// Платёжный провайдер.
+pub trait PaymentProvider: Send + Sync {
+  /// Проверяет подпись уведомления.
+  fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool;
+  
+  /// Извлекает из уведомления сведения о платеже.
+  ///
+  /// Возвращает None для уведомлений, которые не относятся к оплате аккаунта.
+  fn parse_event(&self, body: &[u8]) -> MResult<Option<PaymentEvent>>;
+}
+
+/// Возвращает платёжный провайдер, заданный в конфигурации, или None, если приём платежей не настроен.
+pub fn provider(cfg: &AppConfig) -> Option<Box<dyn PaymentProvider>> {
+  let billing = cfg.billing.as_ref()?;
+  match billing.provider.as_str() {
+    "stripe" => Some(Box::new(stripe::Stripe { webhook_secret: billing.webhook_secret.clone() })),
+    _ => None,
+  }
+}
@@ -0,0 +1,62 @@
+//! Отвечает за уведомления Stripe.
+//!
+//! Stripe подписывает каждое уведомление HMAC-SHA256 от строки `<время>.<тело запроса>` и передаёт подпись в заголовке `Stripe-Signature` вида `t=<время>,v1=<подпись>`. Идентификатор пользователя передаётся в метаданных счёта (`metadata.user_id`).
+
+use chrono::{TimeZone, Utc};
+use crypto::{hmac::Hmac, mac::{Mac, MacResult}, sha2::Sha256};
+use hyper::HeaderMap;
+use serde_json::Value as JsonValue;
+
+use crate::billing::{PaymentEvent, PaymentProvider};
+use crate::sec::hex;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Число секунд, в течение которых уведомление считается свежим. Защищает от повторной отправки перехваченных уведомлений.
+const SIGNATURE_TOLERANCE: i64 = 5 * 60;
+
+/// Stripe.
+pub struct Stripe {
+  /// Секрет для проверки подписи уведомлений.
+  pub webhook_secret: String,
+}
+
+impl PaymentProvider for Stripe {
+  fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = match headers.get("Stripe-Signature").and_then(|h| h.to_str().ok()) {
+      Some(v) => v,
+      None => return false,
+    };
+    let mut timestamp: Option<i64> = None;
+    let mut signatures: Vec<Vec<u8>> = Vec::new();
+    for part in header.split(',') {
+      match part.split_once('=') {
+        Some(("t", v)) => timestamp = v.parse().ok(),
+        Some(("v1", v)) => if let Some(v) = hex::decode(v) { signatures.push(v); },
+        _ => {},
+      };
+    };
+    let timestamp = match timestamp {
+      Some(v) => v,
+      None => return false,
+    };
+    if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE { return false; };
+    let mut mac = Hmac::new(Sha256::new(), self.webhook_secret.as_bytes());
+    mac.input(format!("{}.", timestamp).as_bytes());
+    mac.input(body);
+    let expected = mac.result();
+    // Сравнение MacResult выполняется за постоянное время.
+    signatures.iter().any(|s| MacResult::new(s) == expected)
+  }
+  
+  fn parse_event(&self, body: &[u8]) -> MResult<Option<PaymentEvent>> {
+    let event: JsonValue = serde_json::from_slice(body)?;
+    if event["type"] != "invoice.paid" { return Ok(None); };
+    let user_id = match event["data"]["object"]["metadata"]["user_id"].as_str().and_then(|v| v.parse().ok()) {
+      Some(v) => v,
+      None => return Ok(None),
+    };
+    let created = event["created"].as_i64().unwrap_or_else(|| Utc::now().timestamp());
+    Ok(Some(PaymentEvent { user_id, paid_at: Utc.timestamp_opt(created, 0).single().unwrap_or_else(Utc::now) }))
+  }
+}
@@ -0,0 +1,84 @@
+//! Отвечает за журнал действий администраторов.
+//!
+//! Каждый вызов метода администратора, прошедший проверку ключа, записывается в таблицу `admin_audit`: метод и путь, ключ, адрес клиента, время вызова, затронутая сущность (например, название ключа администратора) и краткое содержание запроса без секретов. Запись делается до выполнения действия, и если её не удалось сохранить, действие не выполняется. Исключение составляют настройка базы данных и восстановление из резервной копии: до них таблицы журнала может не быть, поэтому они записываются после выполнения.
+//!
+//! Журнал попадает в резервную копию и заменяется при восстановлении из неё; само восстановление записывается уже в восстановленный журнал.
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::net::IpAddr;
+
+use crate::psql_handler::Db;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число записей, которое можно получить за один запрос.
+pub const MAX_AUDIT_PAGE: i64 = 1000;
+
+/// Вызов метода администратора, прошедший проверку ключа.
+pub struct AdminCall {
+  /// Метод и путь запроса, например, `PUT /admin/keys`.
+  pub route: String,
+  /// Название ключа, с которым вызван метод. Отсутствует, если использован корневой ключ.
+  pub key: Option<String>,
+  /// Адрес клиента (см. `sec::proxy`).
+  pub ip: IpAddr,
+}
+
+/// Запись журнала.
+#[derive(Serialize)]
+pub struct AuditRecord {
+  pub id: i64,
+  /// Время вызова в секундах Unix.
+  pub at: i64,
+  pub key: Option<String>,
+  /// Адрес клиента. Отсутствует у записей, сделанных до того, как адреса стали записываться.
+  pub ip: Option<String>,
+  pub route: String,
+  pub entity: Option<String>,
+  pub summary: JsonValue,
+}
+
+/// Условия отбора записей журнала. Незаданные условия не ограничивают выборку.
+#[derive(Default)]
+pub struct AuditFilter {
+  pub key: Option<String>,
+  pub route: Option<String>,
+  pub entity: Option<String>,
+  /// Начало периода в секундах Unix, включительно.
+  pub from: Option<i64>,
+  /// Конец периода в секундах Unix, не включительно.
+  pub to: Option<i64>,
+  /// Идентификатор записи, после которой начинается страница.
+  pub before: Option<i64>,
+}
+
+/// Записывает вызов метода администратора в журнал.
+pub async fn record(db: &Db, call: &AdminCall, entity: Option<&str>, summary: &JsonValue) -> MResult<()> {
+  db.write(
+    "insert into admin_audit (at, key_name, ip, route, entity, summary) values ($1, $2, $3, $4, $5, $6);",
+    &[&Utc::now().timestamp(), &call.key, &call.ip.to_string(), &call.route, &entity, &summary.to_string()]
+  ).await
+}
+
+/// Возвращает до `limit` записей журнала, удовлетворяющих фильтру, начиная с последних.
+pub async fn list(db: &Db, filter: &AuditFilter, limit: i64) -> MResult<Vec<AuditRecord>> {
+  let rows = db.read_all(
+    "select id, at, key_name, ip, route, entity, summary from admin_audit \
+     where ($1::varchar is null or key_name = $1) and ($2::varchar is null or route = $2) \
+       and ($3::varchar is null or entity = $3) and ($4::bigint is null or at >= $4) \
+       and ($5::bigint is null or at < $5) and ($6::bigint is null or id < $6) \
+     order by id desc limit $7;",
+    &[&filter.key, &filter.route, &filter.entity, &filter.from, &filter.to, &filter.before, &limit]
+  ).await?;
+  rows.iter().map(|row| Ok(AuditRecord {
+    id: row.get(0),
+    at: row.get(1),
+    key: row.get(2),
+    ip: row.get(3),
+    route: row.get(4),
+    entity: row.get(5),
+    summary: serde_json::from_str(row.get(6))?,
+  })).collect()
+}
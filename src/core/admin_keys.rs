@@ -0,0 +1,67 @@
+//! Отвечает за ключи администраторов.
+//!
+//! Корневой ключ задаётся в конфигурации (`admin_key`) и даёт доступ ко всем методам администратора, в том числе к управлению остальными ключами. Остальные ключи хранятся в таблице `admin_keys`: у каждого есть название, области действия (см. `AdminScope`) и, возможно, срок действия. В базе данных хранятся только хэши ключей.
+
+use chrono::{TimeZone, Utc};
+use custom_error::custom_error;
+
+use crate::core::hash_token;
+use crate::sec::auth::{AdminKey, AdminScope};
+use crate::sec::key_gen;
+use crate::setup::AppConfig;
+use crate::storage::{AdminKeyRow, Storage};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongAdminKey{reason: &'static str} = "{reason}"}
+
+/// Проверяет, что ключ является корневым.
+pub fn is_root(cfg: &AppConfig, key: &str) -> bool {
+  key == cfg.admin_key
+}
+
+/// Проверяет, что ключ действителен и его области действия включают `scope`.
+///
+/// Если это так, возвращает название ключа, а для корневого ключа - `Some(None)`. Корневой ключ проверяется без обращения к базе данных, поэтому им можно настроить базу данных, в которой ещё нет таблицы `admin_keys`.
+pub async fn authorize(db: &dyn Storage, cfg: &AppConfig, key: &str, scope: AdminScope) -> MResult<Option<Option<String>>> {
+  if is_root(cfg, key) { return Ok(Some(None)); };
+  let row = match db.admin_key_by_hash(&hash_token(key)).await? {
+    Some(row) => row,
+    None => return Ok(None),
+  };
+  let scopes: Vec<AdminScope> = serde_json::from_str(&row.scopes)?;
+  let valid = scopes.contains(&scope) && row.expires_at.is_none_or(|expires_at| expires_at > Utc::now().timestamp());
+  Ok(valid.then_some(Some(row.name)))
+}
+
+/// Выпускает ключ с данным названием, заменяя ключ с тем же названием, если он был. Возвращает новый ключ.
+pub async fn put(db: &dyn Storage, key: &AdminKey) -> MResult<String> {
+  if key.name.is_empty() || key.name.chars().count() > 64 {
+    return Err(Box::new(WrongAdminKey{ reason: "Название ключа должно содержать от 1 до 64 символов." }));
+  };
+  if key.scopes.is_empty() {
+    return Err(Box::new(WrongAdminKey{ reason: "У ключа должна быть хотя бы одна область действия." }));
+  };
+  let secret = key_gen::generate_strong(64)?;
+  let row = AdminKeyRow {
+    name: key.name.clone(),
+    scopes: serde_json::to_string(&key.scopes)?,
+    expires_at: key.expires_at.map(|expires_at| expires_at.timestamp()),
+  };
+  db.put_admin_key(&row, &hash_token(&secret)).await?;
+  Ok(secret)
+}
+
+/// Удаляет ключ с данным названием. Возвращает `false`, если такого ключа нет.
+pub async fn delete(db: &dyn Storage, name: &str) -> MResult<bool> {
+  db.delete_admin_key(name).await
+}
+
+/// Возвращает список ключей без самих ключей.
+pub async fn list(db: &dyn Storage) -> MResult<Vec<AdminKey>> {
+  db.admin_keys().await?.into_iter().map(|row| Ok(AdminKey {
+    name: row.name,
+    scopes: serde_json::from_str(&row.scopes)?,
+    expires_at: row.expires_at.and_then(|expires_at| Utc.timestamp_opt(expires_at, 0).single()),
+  })).collect()
+}
@@ -0,0 +1,200 @@
+//! Отвечает за правила автоматизации досок.
+//!
+//! Автор доски задаёт правила в её настройках (`BoardSettings::rules`): когда задача отмечается выполненной, к ней прикрепляется тег или проходит её обязательный срок, с ней выполняются действия - перенос в другую карточку, назначение исполнителя и прикрепление тега.
+//!
+//! Изменения, запускающие правила, находятся так же, как новые получатели уведомлений (см. `notifications`): доска с правилами при загрузке запоминает свои задачи, выполненные задачи и теги задач (`facts`), а `save_board` после записи публикует события `TaskCompleted` и `TagAdded` о новых. Правила запускают только изменения задач, которые были на доске до изменения: созданные, восстановленные и перенесённые задачи правил не запускают. Событие `TaskOverdue` публикует `overdue`.
+//!
+//! Подписчик канала событий (`run`) находит подходящие правила и выполняет их действия одной записью доски от имени пользователя, изменение которого запустило правила, а если такого нет или он уже не участник доски - от имени автора. Уже выполненные действия и действия со ссылками на удалённые карточки и теги или бывших участников пропускаются. Задача переносится после остальных действий, и если её переносят несколько правил, выполняется первый перенос; в карточку без места для задач (см. `Card::wip_limit`) задача не переносится.
+//!
+//! Изменения, сделанные правилами, тоже могут запускать правила. Чтобы правила не запускали друг друга бесконечно, цепочка таких запусков обрывается после `MAX_RULE_CHAIN` записей.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio_postgres::types::ToSql;
+
+use crate::core::events::{self, EventKind};
+use crate::core::validation;
+use crate::core::{check_wip_limit, load_board_as_author, notifications, save_board, task_history};
+use crate::model::{Board, BoardContext, Card, Cards, Rule, RuleAction, RuleTrigger, Tag, TaskPath};
+use crate::psql_handler::transaction;
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error::custom_error!{pub WrongRule{reason: String} = "Правило автоматизации не принято: {reason}."}
+
+/// Наибольшее число правил доски.
+pub const MAX_RULES: usize = 50;
+
+/// Наибольшее число действий правила.
+pub const MAX_RULE_ACTIONS: usize = 10;
+
+/// Наибольшее число записей доски подряд, сделанных правилами в ответ на изменения, сделанные правилами.
+pub const MAX_RULE_CHAIN: usize = 5;
+
+/// Состояние задачи, изменение которого может запустить правила.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Fact {
+  /// Задача есть на доске.
+  Exists(TaskPath),
+  /// Задача выполнена.
+  Done(TaskPath),
+  /// К задаче прикреплён тег.
+  Tagged(TaskPath, i64),
+}
+
+/// Собирает состояния задач доски. У досок без правил состояния не собираются.
+pub fn facts(board: &Board) -> HashSet<Fact> {
+  let mut facts = HashSet::new();
+  if board.settings.rules.is_empty() { return facts; };
+  for card in &board.cards {
+    for task in &card.tasks {
+      let path = TaskPath { card_id: card.id, task_id: task.id };
+      facts.insert(Fact::Exists(path));
+      if task.exec { facts.insert(Fact::Done(path)); };
+      facts.extend(task.tags.iter().map(|tag_id| Fact::Tagged(path, *tag_id)));
+    };
+  };
+  facts
+}
+
+/// Публикует события о задачах, которые были на доске до изменения и стали выполненными или получили новые теги.
+pub fn publish(board_id: i64, user_id: i64, revision: i64, before: &HashSet<Fact>, after: &HashSet<Fact>) {
+  for fact in after.difference(before) {
+    let kind = match *fact {
+      Fact::Done(path) if before.contains(&Fact::Exists(path)) =>
+        EventKind::TaskCompleted { card_id: path.card_id, task_id: path.task_id },
+      Fact::Tagged(path, tag_id) if before.contains(&Fact::Exists(path)) =>
+        EventKind::TagAdded { card_id: path.card_id, task_id: path.task_id, tag_id },
+      _ => continue,
+    };
+    events::publish(board_id, Some(user_id), revision, kind);
+  };
+}
+
+/// Проверяет правила доски с данными карточками, тегами и участниками и приводит их названия к виду, в котором они хранятся.
+pub fn validate(rules: &mut [Rule], cards: &[Card], tags: &[Tag], members: &[i64]) -> MResult<()> {
+  let wrong = |reason: String| -> MResult<()> { Err(Box::new(WrongRule{ reason })) };
+  if rules.len() > MAX_RULES { return wrong(format!("у доски может быть не больше {} правил", MAX_RULES)); };
+  let card = |id: &i64| cards.iter().any(|card| card.id == *id);
+  let tag = |id: &i64| tags.iter().any(|tag| tag.id == *id);
+  for rule in rules {
+    if !rule.title.is_empty() { rule.title = validation::title("правила", &rule.title)?; };
+    if rule.actions.is_empty() || rule.actions.len() > MAX_RULE_ACTIONS {
+      return wrong(format!("у правила должно быть от 1 до {} действий", MAX_RULE_ACTIONS));
+    };
+    if let Some(card_id) = rule.card_id.filter(|id| !card(id)) { return wrong(format!("карточки {} нет на доске", card_id)); };
+    if let RuleTrigger::TagAdded { tag_id } = rule.trigger {
+      if !tag(&tag_id) { return wrong(format!("тега {} нет в словаре доски", tag_id)); };
+    };
+    for action in &rule.actions {
+      match *action {
+        RuleAction::MoveToCard { card_id } if !card(&card_id) => return wrong(format!("карточки {} нет на доске", card_id)),
+        RuleAction::Assign { user_id } if !members.contains(&user_id) =>
+          return wrong(format!("пользователь {} не является участником доски", user_id)),
+        RuleAction::AddTag { tag_id } if !tag(&tag_id) => return wrong(format!("тега {} нет в словаре доски", tag_id)),
+        _ => {},
+      };
+    };
+  };
+  Ok(())
+}
+
+/// Выполняет правила досок по событиям, которые их запускают.
+pub async fn run(db: Arc<dyn Storage>) {
+  let mut rx = events::subscribe();
+  // Для каждой доски - последняя ревизия, записанная правилами, и число записей правил в цепочке, которая к ней привела.
+  let mut chains: HashMap<i64, (i64, usize)> = HashMap::new();
+  while let Some(event) = events::next(&mut rx).await {
+    let (path, trigger) = match event.kind {
+      EventKind::TaskCompleted { card_id, task_id } => (TaskPath { card_id, task_id }, RuleTrigger::TaskCompleted),
+      EventKind::TagAdded { card_id, task_id, tag_id } => (TaskPath { card_id, task_id }, RuleTrigger::TagAdded { tag_id }),
+      EventKind::TaskOverdue { card_id, task_id } => (TaskPath { card_id, task_id }, RuleTrigger::DeadlinePassed),
+      _ => continue,
+    };
+    let chain = match chains.get(&event.board_id) {
+      Some((revision, chain)) if *revision == event.revision => *chain,
+      _ => 0,
+    };
+    if chain >= MAX_RULE_CHAIN {
+      eprintln!("Правила автоматизации на доске {} остановлены: они запускают друг друга.", event.board_id);
+      continue;
+    };
+    match transaction::scope(apply(&*db, event.board_id, event.user_id, path, trigger)).await {
+      Ok(Some(revision)) => { chains.insert(event.board_id, (revision, chain + 1)); },
+      Ok(None) => {},
+      Err(e) => eprintln!("Не удалось выполнить правила автоматизации на доске {}: {}", event.board_id, e),
+    };
+  };
+}
+
+/// Выполняет правила, запущенные событием `trigger` задачи `path`. Возвращает ревизию доски, если правила её изменили.
+async fn apply(db: &dyn Storage, board_id: i64, actor: Option<i64>, path: TaskPath, trigger: RuleTrigger) -> MResult<Option<i64>> {
+  db.lock_board(&board_id).await?;
+  let mut ctx = load_board_as_author(db, &board_id).await?;
+  let actions: Vec<RuleAction> = ctx.board.settings.rules.iter()
+    .filter(|rule| rule.trigger == trigger && rule.card_id.is_none_or(|id| id == path.card_id))
+    .flat_map(|rule| rule.actions.iter().copied())
+    .collect();
+  if actions.is_empty() || ctx.board.cards.get_task(&path.card_id, &path.task_id).is_err() { return Ok(None); };
+  if let Some(actor) = actor.filter(|id| ctx.board.shared_with.contains(id)) { ctx.user_id = actor; };
+  let before = task_history::snapshot(&ctx, &path.card_id, &path.task_id)?;
+  let mut changed = false;
+  let mut destination = None;
+  for action in actions {
+    let task = ctx.board.cards.get_mut_task(&path.card_id, &path.task_id)?;
+    match action {
+      RuleAction::MoveToCard { card_id } => { destination = destination.or(Some(card_id)); },
+      RuleAction::Assign { user_id } => if ctx.board.shared_with.contains(&user_id) && !task.executors.contains(&user_id) {
+        task.executors.push(user_id);
+        changed = true;
+      },
+      RuleAction::AddTag { tag_id } => if ctx.board.tags.iter().any(|tag| tag.id == tag_id) && !task.tags.contains(&tag_id) {
+        task.tags.push(tag_id);
+        changed = true;
+      },
+    };
+  };
+  let changes = before.changes(&mut ctx)?;
+  let moved = match destination {
+    Some(card_id) => move_task(db, &mut ctx, path, card_id).await?,
+    None => None,
+  };
+  if !changed && moved.is_none() { return Ok(None); };
+  let target = moved.unwrap_or(path);
+  let (old_seq, new_seq) = (format!("{}_{}_{}", board_id, path.card_id, path.task_id), format!("{}_{}_{}", board_id, target.card_id, target.task_id));
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = changes.queries();
+  if moved.is_some() {
+    queries.extend([
+      (
+        "update task_history set card_id = $1, task_id = $2 where board_id = $3 and card_id = $4 and task_id = $5;",
+        vec![&target.card_id as &(dyn ToSql + Sync), &target.task_id, &board_id, &path.card_id, &path.task_id]
+      ),
+      ("delete from id_seqs where id = $1;", vec![&new_seq]),
+      ("update id_seqs set id = $1 where id = $2;", vec![&new_seq, &old_seq]),
+    ]);
+  };
+  save_board(db, &mut ctx, EventKind::RuleApplied { card_id: target.card_id, task_id: target.task_id }, queries).await?;
+  Ok(Some(ctx.board.revision))
+}
+
+/// Переносит задачу в конец другой карточки и возвращает её новый адрес.
+///
+/// Задача получает идентификатор в новой карточке, а зависимости других задач от неё переписываются. Если карточки нет, задача уже в ней или в ней нет места, ничего не делает.
+pub async fn move_task(db: &dyn Storage, ctx: &mut BoardContext, path: TaskPath, card_id: i64) -> MResult<Option<TaskPath>> {
+  let card = match ctx.board.cards.get_card(&card_id) {
+    Ok(card) if card.id != path.card_id && check_wip_limit(card, card.tasks.len() + 1).is_ok() => card,
+    _ => return Ok(None),
+  };
+  let min_task_id = card.tasks.iter().map(|task| task.id).max().unwrap_or(0) + 1;
+  let task_id = db.next_id(&format!("{}_{}", ctx.board.id, card_id), min_task_id).await?;
+  let mut task = ctx.board.cards.remove_task(&path.card_id, &path.task_id)?;
+  task.id = task_id;
+  ctx.board.cards.get_mut_card(&card_id)?.tasks.push(task);
+  let moved = TaskPath { card_id, task_id };
+  for dependency in ctx.board.cards.iter_mut().flat_map(|card| card.tasks.iter_mut()).flat_map(|task| task.depends_on.iter_mut()) {
+    if *dependency == path { *dependency = moved; };
+  };
+  notifications::moved(&mut ctx.interests, path, moved);
+  Ok(Some(moved))
+}
@@ -0,0 +1,130 @@
+//! Отвечает за кэш карточек досок.
+//!
+//! Карточки - самая большая часть доски, и разбор их JSON занимает большую часть времени загрузки доски. Кэш хранит разобранные карточки досок, к которым обращались недавно, и отдаёт их копию вместо повторного разбора. Запись кэша относится к ревизии доски и хэшу JSON карточек, поэтому карточки, изменённые в базе данных в обход сервера без смены ревизии, разбираются заново. Хэш считается во много раз быстрее разбора.
+//!
+//! Кэш пополняется и при загрузке доски, и при её записи: следующий за записью запрос получает карточки новой ревизии без разбора. Записи досок, которые изменили или удалили, удаляются по событиям (см. `events`). Когда кэш заполнен, из него удаляется доска, к которой дольше всего не обращались; записи, не использовавшиеся дольше `ttl_secs`, удаляются при следующем обращении к кэшу.
+//!
+//! Кэш работает в пределах процесса. Пока он не настроен (см. `configure`), карточки разбираются при каждой загрузке.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::core::events::{self, EventKind};
+use crate::model::Card;
+use crate::setup::BoardCacheConfig;
+
+/// Разобранные карточки одной ревизии доски.
+struct Entry {
+  revision: i64,
+  fingerprint: u64,
+  cards: Vec<Card>,
+  used_at: Instant,
+}
+
+/// Кэш карточек досок с ограниченным числом досок.
+pub struct BoardCache {
+  capacity: usize,
+  ttl: Duration,
+  entries: HashMap<i64, Entry>,
+}
+
+impl BoardCache {
+  /// Создаёт пустой кэш с данными ограничениями.
+  pub fn new(cfg: &BoardCacheConfig) -> BoardCache {
+    BoardCache { capacity: cfg.capacity, ttl: Duration::from_secs(cfg.ttl_secs), entries: HashMap::new() }
+  }
+  
+  /// Возвращает копию карточек доски, если в кэше есть карточки данной ревизии с данным хэшем JSON.
+  pub fn get(&mut self, board_id: i64, revision: i64, fingerprint: u64) -> Option<Vec<Card>> {
+    let entry = self.entries.get_mut(&board_id)?;
+    if entry.revision != revision || entry.fingerprint != fingerprint { return None; };
+    entry.used_at = Instant::now();
+    Some(entry.cards.clone())
+  }
+  
+  /// Сохраняет копию карточек, если в кэше нет более новой ревизии доски.
+  pub fn store(&mut self, board_id: i64, revision: i64, fingerprint: u64, cards: &[Card]) {
+    if self.entries.get(&board_id).is_some_and(|entry| entry.revision > revision) { return; };
+    let now = Instant::now();
+    if !self.entries.contains_key(&board_id) { self.evict(now); };
+    self.entries.insert(board_id, Entry { revision, fingerprint, cards: cards.to_vec(), used_at: now });
+  }
+  
+  /// Удаляет карточки доски, если они старше данной ревизии или, если ревизия не указана, в любом случае.
+  pub fn invalidate(&mut self, board_id: i64, revision: Option<i64>) {
+    if self.entries.get(&board_id).is_some_and(|entry| revision.is_none_or(|revision| entry.revision < revision)) {
+      self.entries.remove(&board_id);
+    };
+  }
+  
+  /// Удаляет записи, не использовавшиеся дольше `ttl`, а если места всё равно нет - запись, к которой дольше всего не обращались.
+  fn evict(&mut self, now: Instant) {
+    let ttl = self.ttl;
+    self.entries.retain(|_, entry| now.duration_since(entry.used_at) < ttl);
+    if self.entries.len() < self.capacity { return; };
+    let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.used_at).map(|(board_id, _)| *board_id);
+    if let Some(board_id) = oldest {
+      self.entries.remove(&board_id);
+    };
+  }
+}
+
+static CACHE: Mutex<Option<BoardCache>> = Mutex::new(None);
+
+/// Включает кэш с данными ограничениями. Кэш размером 0 отключается.
+pub fn configure(cfg: &BoardCacheConfig) {
+  *CACHE.lock().unwrap() = (cfg.capacity > 0).then(|| BoardCache::new(cfg));
+}
+
+/// Возвращает хэш JSON карточек.
+pub fn fingerprint(json: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  json.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Возвращает копию карточек доски из кэша или, если их там нет, разбирает `json` при помощи `parse` и сохраняет результат.
+pub fn get_or_parse<E>(board_id: i64, revision: i64, json: &str, parse: impl FnOnce(&str) -> Result<Vec<Card>, E>)
+  -> Result<Vec<Card>, E>
+{
+  if CACHE.lock().unwrap().is_none() { return parse(json); };
+  let fingerprint = fingerprint(json);
+  if let Some(cards) = CACHE.lock().unwrap().as_mut().and_then(|cache| cache.get(board_id, revision, fingerprint)) {
+    return Ok(cards);
+  };
+  // Разбор выполняется без блокировки, чтобы не задерживать запросы к другим доскам.
+  let cards = parse(json)?;
+  if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+    cache.store(board_id, revision, fingerprint, &cards);
+  };
+  Ok(cards)
+}
+
+/// Сохраняет карточки записанной ревизии доски вместе с их JSON.
+pub fn put(board_id: i64, revision: i64, json: &str, cards: &[Card]) {
+  if CACHE.lock().unwrap().is_none() { return; };
+  let fingerprint = fingerprint(json);
+  if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+    cache.store(board_id, revision, fingerprint, cards);
+  };
+}
+
+/// Удаляет карточки доски из кэша, если он включён (см. `BoardCache::invalidate`).
+fn invalidate(board_id: i64, revision: Option<i64>) {
+  if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+    cache.invalidate(board_id, revision);
+  };
+}
+
+/// Удаляет из кэша записи изменённых и удалённых досок по событиям изменения досок.
+pub async fn run() {
+  let mut rx = events::subscribe();
+  while let Some(event) = events::next(&mut rx).await {
+    match event.kind {
+      EventKind::BoardDeleted | EventKind::BoardExported { .. } => invalidate(event.board_id, None),
+      _ => invalidate(event.board_id, Some(event.revision)),
+    };
+  };
+}
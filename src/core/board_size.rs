@@ -0,0 +1,178 @@
+//! Отвечает за размеры досок: их замеры и жёсткие ограничения.
+//!
+//! Доска хранится одной строкой, а все её карточки с задачами - одним JSON, который сервер разбирает при загрузке доски и записывает при каждом изменении. Поэтому время любого запроса к доске растёт вместе с её размером, и на очень больших досках работа с ними резко замедляется.
+//!
+//! При загрузке и записи доски запоминаются размер её JSON, число карточек и задач. Замеры хранятся в пределах процесса для не больше чем `MAX_BOARDS` досок и передаются администратору вместе с самыми большими досками (см. `snapshot`).
+//!
+//! Ограничения из конфигурации (`board_limits`) проверяются при записи доски: изменение, после которого в карточке окажется больше `max_tasks_per_card` задач или JSON доски превысит `max_board_kb` КиБ, не записывается, а функция записи возвращает `BoardTooLarge`. Изменения, которые не увеличивают карточку или доску, принимаются и сверх ограничений, чтобы доску, выросшую до их включения, можно было уменьшить. Пока ограничения не настроены (см. `configure`), размер досок не ограничивается.
+
+use custom_error::custom_error;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::model::{BoardContext, Card, StoredBoard};
+use crate::setup::BoardLimits;
+use crate::storage::BoardRow;
+
+custom_error!{pub BoardTooLarge{limit: &'static str, max: u64, actual: u64} = "Превышено ограничение размера доски."}
+
+/// Наибольшее число досок, замеры которых хранятся. Доски сверх этого числа не учитываются, пока замеры других не удалят.
+const MAX_BOARDS: usize = 100_000;
+
+/// Число самых больших досок, которые передаются в `snapshot`.
+const LARGEST: usize = 20;
+
+/// Размер записанной ревизии доски.
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct BoardSize {
+  /// Размер JSON всех колонок доски в байтах.
+  pub bytes: u64,
+  pub cards: u64,
+  pub tasks: u64,
+  /// Наибольшее число задач в одной карточке.
+  pub max_card_tasks: u64,
+}
+
+impl BoardSize {
+  /// Замеряет доску по её записи в хранилище и разобранным карточкам.
+  pub fn measure(stored: &StoredBoard, cards: &[Card]) -> BoardSize {
+    BoardSize {
+      bytes: stored_bytes(stored),
+      cards: cards.len() as u64,
+      tasks: cards.iter().map(|card| card.tasks.len() as u64).sum(),
+      max_card_tasks: cards.iter().map(|card| card.tasks.len() as u64).max().unwrap_or(0),
+    }
+  }
+}
+
+/// Замеры досок и число отклонённых записей.
+#[derive(Default)]
+struct Sizes {
+  limits: BoardLimits,
+  boards: HashMap<i64, BoardSize>,
+  rejected_tasks: u64,
+  rejected_bytes: u64,
+}
+
+static SIZES: Mutex<Option<Sizes>> = Mutex::new(None);
+
+/// Доска в сводке размеров.
+#[derive(Serialize)]
+pub struct BoardSizeEntry {
+  pub board_id: i64,
+  #[serde(flatten)]
+  pub size: BoardSize,
+}
+
+/// Процентили размера JSON досок в байтах.
+#[derive(Default, Serialize)]
+pub struct BytesPercentiles {
+  pub p50: u64,
+  pub p90: u64,
+  pub p99: u64,
+  pub max: u64,
+}
+
+/// Сводка размеров досок с запуска сервера.
+#[derive(Serialize)]
+pub struct SizesSnapshot {
+  pub limits: BoardLimits,
+  /// Число досок, которые загружались или записывались с запуска сервера.
+  pub boards: usize,
+  pub bytes: BytesPercentiles,
+  /// Общее число задач на этих досках.
+  pub tasks: u64,
+  /// Число записей, отклонённых из-за `max_tasks_per_card` и `max_board_kb`.
+  pub rejected: HashMap<&'static str, u64>,
+  /// Самые большие доски по размеру JSON.
+  pub largest: Vec<BoardSizeEntry>,
+}
+
+/// Включает ограничения размера досок.
+pub fn configure(limits: &BoardLimits) {
+  SIZES.lock().unwrap().get_or_insert_with(Sizes::default).limits = limits.clone();
+}
+
+/// Запоминает размер доски.
+pub fn record(board_id: i64, size: BoardSize) {
+  let mut sizes = SIZES.lock().unwrap();
+  let sizes = sizes.get_or_insert_with(Sizes::default);
+  if sizes.boards.len() >= MAX_BOARDS && !sizes.boards.contains_key(&board_id) { return; };
+  sizes.boards.insert(board_id, size);
+}
+
+/// Удаляет замеры удалённой доски.
+pub fn forget(board_id: i64) {
+  if let Some(sizes) = SIZES.lock().unwrap().as_mut() {
+    sizes.boards.remove(&board_id);
+  };
+}
+
+/// Проверяет, что ни в одной карточке не стало больше `max_tasks_per_card` задач.
+///
+/// Число задач карточки до изменения берётся из `Card::task_count`, поэтому функцию нужно вызывать до его пересчёта. Новые карточки считаются пустыми.
+pub fn check_tasks(cards: &[Card]) -> Result<(), BoardTooLarge> {
+  let max = match SIZES.lock().unwrap().as_ref().and_then(|sizes| sizes.limits.max_tasks_per_card) {
+    Some(max) => max,
+    None => return Ok(()),
+  };
+  match cards.iter().find(|card| card.tasks.len() as u64 > max && card.tasks.len() > card.task_count) {
+    Some(card) => Err(rejected("max_tasks_per_card", max, card.tasks.len() as u64)),
+    None => Ok(()),
+  }
+}
+
+/// Проверяет, что новая ревизия доски `row` не больше `max_board_kb` или не больше записанной ревизии `ctx`.
+pub fn check_bytes(ctx: &BoardContext, row: &BoardRow) -> Result<(), BoardTooLarge> {
+  let max = match SIZES.lock().unwrap().as_ref().and_then(|sizes| sizes.limits.max_board_kb) {
+    Some(max_kb) => max_kb * 1024,
+    None => return Ok(()),
+  };
+  let bytes = json_len(&[&row.shared_with, &row.header, &row.cards, &row.background, &row.tags, &row.settings, &row.lanes, &row.sprints, &row.watchers]);
+  match bytes > max && bytes > stored_bytes(&ctx.stored) {
+    true => Err(rejected("max_board_kb", max / 1024, bytes.div_ceil(1024))),
+    false => Ok(()),
+  }
+}
+
+/// Возвращает размер JSON колонок доски в байтах.
+fn json_len(columns: &[&String]) -> u64 {
+  columns.iter().map(|column| column.len() as u64).sum()
+}
+
+/// Возвращает размер JSON записанной ревизии доски в байтах.
+fn stored_bytes(s: &StoredBoard) -> u64 {
+  json_len(&[&s.shared_with, &s.header, &s.cards, &s.background, &s.tags, &s.settings, &s.lanes, &s.sprints, &s.watchers])
+}
+
+/// Учитывает отклонённую запись и возвращает ошибку о ней.
+fn rejected(limit: &'static str, max: u64, actual: u64) -> BoardTooLarge {
+  if let Some(sizes) = SIZES.lock().unwrap().as_mut() {
+    match limit {
+      "max_tasks_per_card" => sizes.rejected_tasks += 1,
+      _ => sizes.rejected_bytes += 1,
+    };
+  };
+  BoardTooLarge{ limit, max, actual }
+}
+
+/// Возвращает сводку размеров досок.
+pub fn snapshot() -> SizesSnapshot {
+  let sizes = SIZES.lock().unwrap();
+  let empty = Sizes::default();
+  let sizes = sizes.as_ref().unwrap_or(&empty);
+  let mut boards: Vec<BoardSizeEntry> = sizes.boards.iter().map(|(board_id, size)| BoardSizeEntry { board_id: *board_id, size: *size }).collect();
+  boards.sort_unstable_by(|a, b| b.size.bytes.cmp(&a.size.bytes).then(a.board_id.cmp(&b.board_id)));
+  let percentile = |p: usize| match boards.is_empty() {
+    true => 0,
+    // Доски отсортированы по убыванию размера.
+    false => boards[(boards.len() - 1) - (boards.len() - 1) * p / 100].size.bytes,
+  };
+  let bytes = BytesPercentiles { p50: percentile(50), p90: percentile(90), p99: percentile(99), max: percentile(100) };
+  let tasks = boards.iter().map(|entry| entry.size.tasks).sum();
+  let rejected = HashMap::from([("max_tasks_per_card", sizes.rejected_tasks), ("max_board_kb", sizes.rejected_bytes)]);
+  let count = boards.len();
+  boards.truncate(LARGEST);
+  SizesSnapshot { limits: sizes.limits.clone(), boards: count, bytes, tasks, rejected, largest: boards }
+}
@@ -0,0 +1,93 @@
+//! Отвечает за планирование загрузки исполнителей доски.
+//!
+//! Загрузка исполнителя за период - сумма ожидаемого времени выполнения (`Timelines::expected_time`) невыполненных задач, плановый срок которых попадает в период. Плановым сроком считается предпочтительный срок задачи, а если он не задан - обязательный; задачи без сроков в планирование не попадают. Время задачи с несколькими исполнителями делится между ними поровну.
+//!
+//! Исполнитель перегружен, если его загрузка больше ёмкости периода - числа дней периода, умноженного на рабочее время одного дня.
+
+use custom_error::custom_error;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::model::{Board, Task};
+
+custom_error!{pub WrongWindow{reason: String} = "Период планирования не принят: {reason}."}
+
+/// Рабочее время одного дня в минутах по умолчанию.
+pub const DEFAULT_DAILY_MINUTES: u32 = 8 * 60;
+
+/// Наибольшая длина периода планирования в днях.
+const MAX_WINDOW_DAYS: i64 = 366;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Загрузка исполнителя за период.
+#[derive(Serialize)]
+pub struct ExecutorLoad {
+  pub user_id: i64,
+  /// Сумма ожидаемого времени задач исполнителя в минутах.
+  pub planned_minutes: u64,
+  /// Число задач исполнителя.
+  pub tasks: u64,
+  pub overloaded: bool,
+}
+
+/// Задачи без исполнителей, попавшие в период.
+#[derive(Default, Serialize)]
+pub struct Unassigned {
+  pub planned_minutes: u64,
+  pub tasks: u64,
+}
+
+/// Загрузка участников доски за период.
+#[derive(Serialize)]
+pub struct Capacity {
+  pub from: i64,
+  pub to: i64,
+  /// Ёмкость периода для одного исполнителя в минутах.
+  pub capacity_minutes: u64,
+  /// Загрузка всех участников доски, в том числе без задач, - сначала самые загруженные.
+  pub executors: Vec<ExecutorLoad>,
+  pub unassigned: Unassigned,
+}
+
+/// Вычисляет загрузку участников доски за период с `from` по `to` (UNIX-время в секундах) при рабочем дне в `daily_minutes` минут.
+pub fn plan(board: &Board, from: i64, to: i64, daily_minutes: u32) -> Result<Capacity, WrongWindow> {
+  if from >= to { return Err(WrongWindow{ reason: "начало периода должно быть раньше его конца".into() }); };
+  let days = (to - from + DAY_SECS - 1) / DAY_SECS;
+  if days > MAX_WINDOW_DAYS {
+    return Err(WrongWindow{ reason: format!("период не может быть длиннее {} дней", MAX_WINDOW_DAYS) });
+  };
+  let mut loads: BTreeMap<i64, (f64, u64)> = board.shared_with.iter().map(|id| (*id, (0.0, 0))).collect();
+  let mut unassigned = Unassigned::default();
+  let planned = board.cards.iter()
+    .flat_map(|card| &card.tasks)
+    .filter(|task| !task.exec && scheduled_at(task).is_some_and(|at| at >= from && at <= to));
+  for task in planned {
+    let executors: Vec<&i64> = task.executors.iter().filter(|id| loads.contains_key(id)).collect();
+    if executors.is_empty() {
+      unassigned.planned_minutes += task.timelines.expected_time as u64;
+      unassigned.tasks += 1;
+      continue;
+    };
+    let share = task.timelines.expected_time as f64 / executors.len() as f64;
+    for id in executors {
+      let load = loads.get_mut(id).unwrap();
+      load.0 += share;
+      load.1 += 1;
+    };
+  };
+  let capacity_minutes = days as u64 * daily_minutes as u64;
+  let mut executors: Vec<ExecutorLoad> = loads.into_iter()
+    .map(|(user_id, (minutes, tasks))| {
+      let planned_minutes = minutes.round() as u64;
+      ExecutorLoad { user_id, planned_minutes, tasks, overloaded: planned_minutes > capacity_minutes }
+    })
+    .collect();
+  executors.sort_by_key(|load| std::cmp::Reverse(load.planned_minutes));
+  Ok(Capacity { from, to, capacity_minutes, executors, unassigned })
+}
+
+/// Возвращает плановый срок задачи или None, если сроки не заданы.
+fn scheduled_at(task: &Task) -> Option<i64> {
+  [task.timelines.preferred_time.timestamp(), task.timelines.max_time.timestamp()].into_iter().find(|at| *at != 0)
+}
@@ -0,0 +1,50 @@
+//! Отвечает за ключи регистрации.
+//!
+//! Если в конфигурации включена регистрация только по ключам (`cc_key_required`), новый пользователь должен передать ключ, выпущенный администратором. Ключ действует однократно: при регистрации он удаляется, поэтому в таблице `cc_keys` хранятся только неиспользованные ключи.
+
+use chrono::{DateTime, TimeZone, Utc};
+use custom_error::custom_error;
+
+use crate::sec::auth::CcKey;
+use crate::sec::key_gen;
+use crate::storage::{CcKeyRow, Storage};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongCcKeysBatch{} = "За один раз можно выпустить от 1 до 1000 ключей."}
+
+/// Наибольшее число ключей, выпускаемых за один раз.
+pub const MAX_CC_KEYS_PER_BATCH: usize = 1000;
+
+/// Выпускает `count` ключей с общими заметкой и сроком действия.
+pub async fn generate(db: &dyn Storage, count: usize, note: Option<String>, expires_at: Option<DateTime<Utc>>)
+  -> MResult<Vec<CcKey>>
+{
+  if count == 0 || count > MAX_CC_KEYS_PER_BATCH { return Err(Box::new(WrongCcKeysBatch{})); };
+  let created_at = Utc::now();
+  let keys = (0..count).map(|_| key_gen::generate_strong(32)).collect::<Result<Vec<String>, _>>()?;
+  let rows: Vec<CcKeyRow> = keys.iter().map(|key| CcKeyRow {
+    key: key.clone(),
+    note: note.clone(),
+    created_at: created_at.timestamp(),
+    expires_at: expires_at.map(|expires_at| expires_at.timestamp()),
+  }).collect();
+  db.insert_cc_keys(&rows).await?;
+  Ok(keys.into_iter().map(|key| CcKey { key, note: note.clone(), created_at, expires_at }).collect())
+}
+
+/// Возвращает неиспользованные ключи, срок действия которых не истёк.
+pub async fn list_unused(db: &dyn Storage) -> MResult<Vec<CcKey>> {
+  let rows = db.unused_cc_keys(Utc::now().timestamp()).await?;
+  Ok(rows.into_iter().map(|row| CcKey {
+    key: row.key,
+    note: row.note,
+    created_at: Utc.timestamp_opt(row.created_at, 0).single().unwrap_or_else(Utc::now),
+    expires_at: row.expires_at.and_then(|expires_at| Utc.timestamp_opt(expires_at, 0).single()),
+  }).collect())
+}
+
+/// Отзывает ключи. Возвращает число отозванных ключей.
+pub async fn revoke(db: &dyn Storage, keys: &[String]) -> MResult<usize> {
+  db.delete_cc_keys(keys).await
+}
@@ -0,0 +1,361 @@
+//! Отвечает за совместимость с данными, записанными предыдущими версиями сервера.
+//!
+//! Все миграции идемпотентны: их можно запускать повторно при каждой настройке базы данных, и уже приведённые к актуальной модели данные останутся нетронутыми.
+
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use tokio_postgres::types::ToSql;
+
+use crate::model::{Card, Priority, Tag};
+use crate::psql_handler::Db;
+use crate::sec::tokens_vld::{hash_token, TOKEN_HASH_LEN};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error::custom_error!{pub DuplicateKeys{table: &'static str, ids: String} = "В таблице {table} повторяются или отсутствуют идентификаторы: {ids}."}
+
+/// Применяет все миграции по порядку.
+///
+/// Колонки JSON переводятся в `jsonb` раньше остальных миграций, чтобы те работали с колонками одного типа.
+pub async fn migrate(db: &Db) -> MResult<()> {
+  convert_json_columns(db).await?;
+  rename_tag_id_seqs(db).await?;
+  migrate_inline_tags(db).await?;
+  add_board_revision(db).await?;
+  add_descriptions(db).await?;
+  add_board_settings(db).await?;
+  add_board_activity(db).await?;
+  add_user_profiles(db).await?;
+  add_board_creation_time(db).await?;
+  add_board_lanes(db).await?;
+  add_admin_audit_ips(db).await?;
+  add_priorities(db).await?;
+  add_board_sprints(db).await?;
+  add_board_watchers(db).await?;
+  add_org_policies(db).await?;
+  add_board_archive(db).await?;
+  add_board_member_index(db).await?;
+  add_primary_keys(db).await?;
+  hash_plaintext_tokens(db).await
+}
+
+/// Колонки JSON, которые хранятся в `jsonb`: пары из таблицы и колонки.
+const JSONB_COLUMNS: [(&str, &str); 5] =
+  [("boards", "cards"), ("boards", "header"), ("boards", "shared_with"), ("users", "shared_boards"), ("users", "user_creds")];
+
+/// Переводит колонки `JSONB_COLUMNS` из строк в `jsonb`: небольшие изменения карточек записываются частично (см. `psql_handler::jsonb`), а участие в доске проверяется по индексу (см. `add_board_member_index`).
+///
+/// Значения из резервной копии, сделанной до перевода, загружаются в `jsonb` строкой JSON (см. `Db::restore`) и разбираются заново.
+async fn convert_json_columns(db: &Db) -> MResult<()> {
+  for (table, column) in JSONB_COLUMNS {
+    let data_type = db.read(
+      "select data_type::text from information_schema.columns \
+         where table_schema = current_schema() and table_name = $1 and column_name = $2;",
+      &[&table, &column]
+    ).await?;
+    if data_type.get::<_, &str>(0) != "jsonb" {
+      db.write(&format!("alter table {0} alter column {1} type jsonb using {1}::jsonb;", table, column), &[]).await?;
+    };
+    db.write(&format!("update {0} set {1} = ({1} #>> '{{}}')::jsonb where jsonb_typeof({1}) = 'string';", table, column), &[]).await?;
+  };
+  Ok(())
+}
+
+/// Добавляет индекс, по которому доски ищутся по участнику (`shared_with @> <пользователь>`).
+async fn add_board_member_index(db: &Db) -> MResult<()> {
+  db.write("create index if not exists boards_shared_with_idx on boards using gin (shared_with jsonb_path_ops);", &[]).await
+}
+
+/// Таблицы, строки которых предыдущие версии сервера создавали без первичного ключа `id`.
+const PRIMARY_KEY_TABLES: [&str; 3] = ["users", "boards", "id_seqs"];
+
+/// Объявляет первичные ключи таблиц `PRIMARY_KEY_TABLES`. Уникальное ограничение `id_seqs.id`, которое заменяет первичный ключ, удаляется.
+///
+/// Если в таблице есть повторяющиеся или пустые идентификаторы, ключ не объявляется, и настройка завершается ошибкой `DuplicateKeys`: какую из строк оставить, решает администратор.
+async fn add_primary_keys(db: &Db) -> MResult<()> {
+  for table in PRIMARY_KEY_TABLES {
+    let declared = db.read(
+      "select count(*) from information_schema.table_constraints \
+         where table_schema = current_schema() and table_name = $1 and constraint_type = 'PRIMARY KEY';",
+      &[&table]
+    ).await?;
+    if declared.get::<_, i64>(0) > 0 { continue; };
+    let duplicates = db.read_all(
+      &format!("select id::text from {} group by id having count(*) > 1 or id is null order by id limit 10;", table), &[]
+    ).await?;
+    if !duplicates.is_empty() {
+      let ids: Vec<String> = duplicates.iter().map(|row| row.get::<_, Option<String>>(0).unwrap_or_else(|| "null".into())).collect();
+      return Err(DuplicateKeys { table, ids: ids.join(", ") }.into());
+    };
+    db.write(&format!("alter table {} add primary key (id);", table), &[]).await?;
+  };
+  db.write("alter table id_seqs drop constraint if exists id_seqs_id_key;", &[]).await
+}
+
+/// Переименовывает последовательности идентификаторов тегов из `<доска>t` в `<доска>_tags`.
+///
+/// Раньше такая последовательность хранила последний выданный идентификатор, а теперь, как и все остальные, хранит следующий (см. `Db::next_id`).
+async fn rename_tag_id_seqs(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    (
+      "insert into id_seqs select left(id, -1) || '_tags', val + 1 from id_seqs where id like '%t' \
+         on conflict (id) do update set val = greatest(id_seqs.val, excluded.val);",
+      vec![]
+    ),
+    ("delete from id_seqs where id like '%t';", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам ревизию.
+async fn add_board_revision(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists revision bigint default 0;", vec![]),
+    ("update boards set revision = 0 where revision is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет пользователям публичные профили. Отображаемым именем становится логин.
+async fn add_user_profiles(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table users add column if not exists display_name varchar;", vec![]),
+    ("alter table users add column if not exists avatar_color varchar default '#808080';", vec![]),
+    ("update users set display_name = login where display_name is null;", vec![]),
+    ("update users set avatar_color = '#808080' where avatar_color is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам время последнего изменения. Доски, изменённые до миграции, считаются изменёнными раньше всех.
+async fn add_board_activity(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists updated_at bigint default 0;", vec![]),
+    ("update boards set updated_at = 0 where updated_at is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам настройки.
+async fn add_board_settings(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists settings varchar default '{}';", vec![]),
+    ("update boards set settings = '{}' where settings is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет карточкам, задачам и подзадачам описания, а подзадачам - заметки.
+async fn add_descriptions(db: &Db) -> MResult<()> {
+  let boards = db.read_all("select id, cards::text from boards;", &[]).await?;
+  for board in &boards {
+    let board_id: i64 = board.get(0);
+    let mut cards: JsonValue = serde_json::from_str(board.get(1))?;
+    let mut migrated: bool = false;
+    for card in cards.as_array_mut().into_iter().flatten() {
+      migrated |= add_empty_string(card, "description");
+      for task in card["tasks"].as_array_mut().into_iter().flatten() {
+        migrated |= add_empty_string(task, "description");
+        for subtask in task["subtasks"].as_array_mut().into_iter().flatten() {
+          migrated |= add_empty_string(subtask, "description");
+          migrated |= add_empty_string(subtask, "notes");
+        };
+      };
+    };
+    if !migrated { continue; };
+    let cards: Vec<Card> = serde_json::from_value(cards)?;
+    let cards = serde_json::to_string(&cards)?;
+    db.write("update boards set cards = $1::text::jsonb where id = $2;", &[&cards, &board_id]).await?;
+  };
+  Ok(())
+}
+
+/// Добавляет в объект пустое строковое поле, если его нет. Возвращает true, если объект изменился.
+fn add_empty_string(object: &mut JsonValue, key: &str) -> bool {
+  add_field(object, key, JsonValue::String(String::new()))
+}
+
+/// Добавляет в объект поле со значением по умолчанию, если его нет. Возвращает true, если объект изменился.
+fn add_field(object: &mut JsonValue, key: &str, value: JsonValue) -> bool {
+  match object.as_object_mut() {
+    Some(object) if !object.contains_key(key) => {
+      object.insert(key.to_string(), value);
+      true
+    },
+    _ => false,
+  }
+}
+
+/// Добавляет задачам и подзадачам обычный приоритет.
+async fn add_priorities(db: &Db) -> MResult<()> {
+  let normal = serde_json::to_value(Priority::default())?;
+  let boards = db.read_all("select id, cards::text from boards;", &[]).await?;
+  for board in &boards {
+    let board_id: i64 = board.get(0);
+    let mut cards: JsonValue = serde_json::from_str(board.get(1))?;
+    let mut migrated: bool = false;
+    for card in cards.as_array_mut().into_iter().flatten() {
+      for task in card["tasks"].as_array_mut().into_iter().flatten() {
+        migrated |= add_field(task, "priority", normal.clone());
+        for subtask in task["subtasks"].as_array_mut().into_iter().flatten() {
+          migrated |= add_field(subtask, "priority", normal.clone());
+        };
+      };
+    };
+    if !migrated { continue; };
+    let cards: Vec<Card> = serde_json::from_value(cards)?;
+    let cards = serde_json::to_string(&cards)?;
+    db.write("update boards set cards = $1::text::jsonb where id = $2;", &[&cards, &board_id]).await?;
+  };
+  Ok(())
+}
+
+/// Переносит теги, хранившиеся внутри задач и подзадач, в словарь тегов доски.
+///
+/// Одинаковые теги (совпадающие по названию и цветам) объединяются в один, а задачи и подзадачи начинают ссылаться на него по идентификатору.
+async fn migrate_inline_tags(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists tags varchar default '[]';", vec![]),
+    ("update boards set tags = '[]' where tags is null;", vec![]),
+  ]).await?;
+  let boards = db.read_all("select id, cards::text, tags from boards;", &[]).await?;
+  for board in &boards {
+    let board_id: i64 = board.get(0);
+    let mut cards: JsonValue = serde_json::from_str(board.get(1))?;
+    let mut board_tags: Vec<Tag> = serde_json::from_str(board.get(2))?;
+    let mut migrated: bool = false;
+    for card in cards.as_array_mut().into_iter().flatten() {
+      for task in card["tasks"].as_array_mut().into_iter().flatten() {
+        migrated |= tags_to_ids(&mut task["tags"], &mut board_tags)?;
+        for subtask in task["subtasks"].as_array_mut().into_iter().flatten() {
+          migrated |= tags_to_ids(&mut subtask["tags"], &mut board_tags)?;
+        };
+      };
+    };
+    if !migrated { continue; };
+    // Проверяем, что после миграции карточки соответствуют актуальной модели.
+    let cards: Vec<Card> = serde_json::from_value(cards)?;
+    let cards = serde_json::to_string(&cards)?;
+    let next_tag_id: i64 = board_tags.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+    let board_tags = serde_json::to_string(&board_tags)?;
+    let board_tags_id_seq = board_id.to_string() + "_tags";
+    let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
+      ("update boards set cards = $1::text::jsonb, tags = $2 where id = $3;", vec![&cards, &board_tags, &board_id]),
+      (
+        "insert into id_seqs values ($1, $2) on conflict (id) do update set val = greatest(id_seqs.val, excluded.val);",
+        vec![&board_tags_id_seq, &next_tag_id],
+      ),
+    ];
+    db.write_mul(queries).await?;
+  };
+  Ok(())
+}
+
+/// Заменяет теги-объекты в списке на идентификаторы тегов из словаря доски, добавляя в словарь недостающие.
+///
+/// Возвращает true, если в списке были найдены теги старого формата.
+fn tags_to_ids(tags: &mut JsonValue, board_tags: &mut Vec<Tag>) -> MResult<bool> {
+  let tags = match tags.as_array_mut() {
+    Some(tags) => tags,
+    None => return Ok(false),
+  };
+  let mut migrated: bool = false;
+  for tag in tags.iter_mut() {
+    if !tag.is_object() { continue; };
+    let inline_tag: Tag = serde_json::from_value(tag.clone())?;
+    let id = match board_tags.iter().find(|t| {
+      t.title == inline_tag.title &&
+      t.text_color == inline_tag.text_color &&
+      t.background_color == inline_tag.background_color
+    }) {
+      Some(t) => t.id,
+      None => {
+        let id = board_tags.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+        board_tags.push(Tag { id, ..inline_tag });
+        id
+      },
+    };
+    *tag = JsonValue::from(id);
+    migrated = true;
+  };
+  let mut seen: HashSet<String> = HashSet::new();
+  tags.retain(|id| seen.insert(id.to_string()));
+  Ok(migrated)
+}
+
+/// Добавляет доскам время создания. У досок, созданных до миграции, оно неизвестно и считается нулевым.
+async fn add_board_creation_time(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists created_at bigint default 0;", vec![]),
+    ("update boards set created_at = 0 where created_at is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам дорожки.
+async fn add_board_lanes(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists lanes varchar default '[]';", vec![]),
+    ("update boards set lanes = '[]' where lanes is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам спринты.
+async fn add_board_sprints(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists sprints varchar default '[]';", vec![]),
+    ("update boards set sprints = '[]' where sprints is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам наблюдателей.
+async fn add_board_watchers(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists watchers varchar default '[]';", vec![]),
+    ("update boards set watchers = '[]' where watchers is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет организациям правила. У организаций, созданных до миграции, действуют правила по умолчанию.
+async fn add_org_policies(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table organizations add column if not exists policy varchar default '{}';", vec![]),
+    ("update organizations set policy = '{}' where policy is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет доскам время переноса в архив. Доски, созданные до миграции, не в архиве.
+async fn add_board_archive(db: &Db) -> MResult<()> {
+  db.write_mul(vec![
+    ("alter table boards add column if not exists archived_at bigint default 0;", vec![]),
+    ("update boards set archived_at = 0 where archived_at is null;", vec![]),
+  ]).await
+}
+
+/// Добавляет записям журнала администраторов адрес клиента. У прежних записей адрес остаётся неизвестным.
+async fn add_admin_audit_ips(db: &Db) -> MResult<()> {
+  db.write("alter table admin_audit add column if not exists ip varchar;", &[]).await
+}
+
+/// Заменяет токены, которые предыдущие версии сервера хранили в открытом виде, их хэшами (см. `tokens_vld::hash_token`).
+///
+/// Такой токен хранится строкой или массивом байт, длина которого отличается от длины хэша.
+async fn hash_plaintext_tokens(db: &Db) -> MResult<()> {
+  let users = db.read_all("select id, user_creds::text from users where user_creds is not null;", &[]).await?;
+  for user in &users {
+    let user_id: i64 = user.get(0);
+    let mut user_creds: JsonValue = serde_json::from_str(user.get(1))?;
+    let mut migrated: bool = false;
+    for list in ["tokens", "refresh_tokens"] {
+      for token in user_creds[list].as_array_mut().into_iter().flatten() {
+        let plaintext = match &token["tk"] {
+          JsonValue::String(tk) => tk.clone(),
+          JsonValue::Array(tk) if tk.len() != TOKEN_HASH_LEN => {
+            let bytes: Vec<u8> = serde_json::from_value(token["tk"].clone())?;
+            String::from_utf8(bytes)?
+          },
+          _ => continue,
+        };
+        token["tk"] = serde_json::to_value(hash_token(&plaintext))?;
+        migrated = true;
+      };
+    };
+    if !migrated { continue; };
+    db.write("update users set user_creds = $1::text::jsonb where id = $2;", &[&user_creds.to_string(), &user_id]).await?;
+  };
+  Ok(())
+}
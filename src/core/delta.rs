@@ -0,0 +1,247 @@
+//! Отвечает за журнал изменений досок и синхронизацию клиентов, работающих без сети.
+//!
+//! При каждой записи доски, увеличивающей её ревизию, в таблицу `board_deltas` записывается патч JSON Patch (RFC 6902), который превращает доску прежней ревизии в новую. Патчи вычисляются сравнением JSON колонок доски до и после записи. Для каждой доски хранятся патчи последних `MAX_DELTAS` ревизий.
+//!
+//! Клиент, вернувшийся в сеть, передаёт последнюю известную ему ревизию и изменения, накопленные без сети. Изменения применяются по порядку патчами доски (см. `document`), после чего клиент получает патч от своей ревизии до текущей или, если журнал её уже не хранит, доску целиком.
+//!
+//! Изменение без сети сделано над ревизией `base_revision`. Если с тех пор другие пользователи или сервер изменили те же места доски, изменение конфликтует с ними: пути указателей JSON операций изменения совпадают с путями операций журнала или вложены в них. Пути сравниваются буквально, поэтому после вставки или удаления элементов массива одни и те же сущности могут оказаться по разным путям; проверка служит подсказкой клиенту, а не гарантией. Поля, которые поддерживает сервер, в проверке не учитываются.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use tokio_postgres::types::ToSql;
+
+use crate::core::document;
+use crate::core::json_patch::Operation;
+use crate::model::BoardContext;
+use crate::psql_handler::Db;
+use crate::setup::AppConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число ревизий доски, патчи которых хранятся в журнале.
+const MAX_DELTAS: i64 = 1000;
+
+/// Поля, которые поддерживает сервер. Их изменения не конфликтуют с изменениями клиентов.
+const SERVER_FIELDS: [&str; 9] =
+  ["revision", "updated_at", "created_at", "task_count", "blocked", "overdue", "due_soon", "field_stamps", "assignments"];
+
+/// Патч одной ревизии доски, подготовленный к записи вместе с доской.
+pub struct Record {
+  board_id: i64,
+  revision: i64,
+  /// Операции патча.
+  ops: Vec<JsonValue>,
+  /// JSON-массив операций патча.
+  patch: String,
+  min_revision: i64,
+}
+
+impl Record {
+  /// Вычисляет патч ревизии `revision` по JSON полей доски до и после изменения.
+  ///
+  /// `fields` - тройки (поле доски, JSON до изменения, JSON после изменения). Поля, JSON которых не изменился, не сравниваются.
+  pub fn build(board_id: i64, revision: i64, fields: &[(&str, &str, &str)]) -> MResult<Record> {
+    let mut ops = Vec::new();
+    for (field, before, after) in fields {
+      if before == after { continue; };
+      let before: JsonValue = serde_json::from_str(before)?;
+      let after: JsonValue = serde_json::from_str(after)?;
+      diff(&format!("/{}", escape(field)), &before, &after, &mut ops);
+    };
+    ops.push(json!({ "op": "replace", "path": "/revision", "value": revision }));
+    let patch = serde_json::to_string(&ops)?;
+    Ok(Record { board_id, revision, ops, patch, min_revision: revision - MAX_DELTAS })
+  }
+
+  /// Возвращает операции патча. Пути операций начинаются с названия поля доски.
+  pub fn ops(&self) -> &[JsonValue] {
+    &self.ops
+  }
+
+  /// Возвращает выражения, которые записывают патч и удаляют из журнала патчи старше `MAX_DELTAS` ревизий.
+  pub fn queries(&self) -> Vec<(&'static str, Vec<&(dyn ToSql + Sync)>)> {
+    vec![
+      (
+        "insert into board_deltas (board_id, revision, patch) values ($1, $2, $3) \
+           on conflict (board_id, revision) do update set patch = excluded.patch;",
+        vec![&self.board_id, &self.revision, &self.patch]
+      ),
+      ("delete from board_deltas where board_id = $1 and revision <= $2;", vec![&self.board_id, &self.min_revision]),
+    ]
+  }
+}
+
+/// Экранирует шаг указателя JSON.
+fn escape(token: &str) -> String {
+  token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Добавляет в `ops` операции, превращающие значение `before` по пути `path` в `after`.
+///
+/// Сущности в массивах доски обычно добавляются, удаляются или изменяются по одной, поэтому совпадающие начало и конец массивов пропускаются, а оставшиеся элементы сравниваются попарно.
+fn diff(path: &str, before: &JsonValue, after: &JsonValue, ops: &mut Vec<JsonValue>) {
+  if before == after { return; };
+  match (before, after) {
+    (JsonValue::Object(before), JsonValue::Object(after)) => {
+      for (key, value) in before {
+        let path = format!("{}/{}", path, escape(key));
+        match after.get(key) {
+          Some(new) => diff(&path, value, new, ops),
+          None => ops.push(json!({ "op": "remove", "path": path })),
+        };
+      };
+      for (key, value) in after.iter().filter(|(key, _)| !before.contains_key(*key)) {
+        ops.push(json!({ "op": "add", "path": format!("{}/{}", path, escape(key)), "value": value }));
+      };
+    },
+    (JsonValue::Array(before), JsonValue::Array(after)) => {
+      let prefix = before.iter().zip(after).take_while(|(b, a)| b == a).count();
+      let suffix = before[prefix..].iter().rev().zip(after[prefix..].iter().rev()).take_while(|(b, a)| b == a).count();
+      let (before, after) = (&before[prefix..before.len() - suffix], &after[prefix..after.len() - suffix]);
+      let common = before.len().min(after.len());
+      for i in 0..common {
+        diff(&format!("{}/{}", path, prefix + i), &before[i], &after[i], ops);
+      };
+      for _ in common..before.len() {
+        ops.push(json!({ "op": "remove", "path": format!("{}/{}", path, prefix + common) }));
+      };
+      for (i, value) in after.iter().enumerate().skip(common) {
+        ops.push(json!({ "op": "add", "path": format!("{}/{}", path, prefix + i), "value": value }));
+      };
+    },
+    _ => ops.push(json!({ "op": "replace", "path": path, "value": after })),
+  };
+}
+
+/// Возвращает операции патчей ревизий после `from` до `to` включительно, или None, если журнал хранит не все из них.
+pub async fn since(db: &Db, board_id: &i64, from: i64, to: i64) -> MResult<Option<Vec<JsonValue>>> {
+  if from > to || from < 0 { return Ok(None); };
+  let rows = db.read_all(
+    "select patch from board_deltas where board_id = $1 and revision > $2 and revision <= $3 order by revision;",
+    &[board_id, &from, &to]
+  ).await?;
+  if rows.len() as i64 != to - from { return Ok(None); };
+  let mut ops = Vec::new();
+  for row in &rows {
+    ops.extend(serde_json::from_str::<Vec<JsonValue>>(row.get(0))?);
+  };
+  Ok(Some(ops))
+}
+
+/// Поведение при конфликте изменения, сделанного без сети.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+  /// Изменение не применяется.
+  #[default]
+  Reject,
+  /// Изменение применяется поверх изменений других пользователей.
+  Overwrite,
+}
+
+/// Изменение доски, сделанное клиентом без сети.
+#[derive(Deserialize)]
+pub struct Mutation {
+  /// Идентификатор изменения на клиенте, который возвращается в результате.
+  pub id: String,
+  /// Ревизия доски, над которой сделано изменение.
+  pub base_revision: i64,
+  pub patch: Vec<Operation>,
+  #[serde(default)]
+  pub on_conflict: OnConflict,
+}
+
+/// Итог применения изменения.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+  Applied,
+  /// Изменение конфликтует с изменениями других пользователей и не применено.
+  Conflict,
+  /// Изменение не прошло проверку (см. `document::patch`) и не применено.
+  Rejected,
+}
+
+/// Результат применения изменения.
+#[derive(Serialize)]
+pub struct MutationResult {
+  pub id: String,
+  pub status: Status,
+  /// Ревизия доски после применения изменения.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub revision: Option<i64>,
+  /// Пути, изменённые другими пользователями или сервером после `base_revision`. Пустой путь означает, что журнал не хранит изменений с этой ревизии.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub conflicts: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+/// Изменения доски, которые получает клиент.
+#[derive(Serialize)]
+pub struct Delta {
+  /// Текущая ревизия доски.
+  pub revision: i64,
+  /// Патч от ревизии клиента до текущей.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub patch: Option<Vec<JsonValue>>,
+  /// Доска целиком, если журнал не хранит патча от ревизии клиента или патч больше самой доски.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub board: Option<JsonValue>,
+  pub results: Vec<MutationResult>,
+}
+
+/// Применяет изменения, сделанные без сети, и возвращает изменения доски после ревизии `revision`.
+pub async fn sync(db: &Db, cfg: &AppConfig, ctx: &mut BoardContext, revision: i64, mutations: Vec<Mutation>) -> MResult<Delta> {
+  let mut results = Vec::new();
+  // Ревизии, созданные изменениями этого же клиента, не конфликтуют с его следующими изменениями.
+  let mut own_revisions = HashSet::new();
+  for mutation in mutations {
+    let conflicts = match mutation.on_conflict {
+      OnConflict::Reject => conflicts(db, ctx, &mutation, &own_revisions).await?,
+      OnConflict::Overwrite => vec![],
+    };
+    let result = match conflicts.is_empty() {
+      false => MutationResult { id: mutation.id, status: Status::Conflict, revision: None, conflicts, error: None },
+      true => match document::patch(db, cfg, ctx, &mutation.patch).await {
+        Ok(_) => {
+          own_revisions.insert(ctx.board.revision);
+          MutationResult { id: mutation.id, status: Status::Applied, revision: Some(ctx.board.revision), conflicts, error: None }
+        },
+        Err(e) => MutationResult { id: mutation.id, status: Status::Rejected, revision: None, conflicts, error: Some(e.to_string()) },
+      },
+    };
+    results.push(result);
+  };
+  let board = serde_json::to_value(&ctx.board)?;
+  let (patch, board) = match since(db, &ctx.board.id, revision, ctx.board.revision).await? {
+    Some(patch) if serde_json::to_string(&patch)?.len() < board.to_string().len() => (Some(patch), None),
+    _ => (None, Some(board)),
+  };
+  Ok(Delta { revision: ctx.board.revision, patch, board, results })
+}
+
+/// Возвращает пути, которые изменение затрагивает вслед за другими пользователями или сервером.
+async fn conflicts(db: &Db, ctx: &BoardContext, mutation: &Mutation, own_revisions: &HashSet<i64>) -> MResult<Vec<String>> {
+  if mutation.base_revision >= ctx.board.revision { return Ok(vec![]); };
+  let rows = db.read_all(
+    "select revision, patch from board_deltas where board_id = $1 and revision > $2 order by revision;",
+    &[&ctx.board.id, &mutation.base_revision]
+  ).await?;
+  if rows.len() as i64 != ctx.board.revision - mutation.base_revision { return Ok(vec![String::new()]); };
+  let mut changed = Vec::new();
+  for row in rows.iter().filter(|row| !own_revisions.contains(&row.get::<_, i64>(0))) {
+    for op in serde_json::from_str::<Vec<JsonValue>>(row.get(1))? {
+      let path = op["path"].as_str().unwrap_or_default().to_string();
+      if path.split('/').any(|field| SERVER_FIELDS.contains(&field)) { continue; };
+      changed.push(path);
+    };
+  };
+  let overlaps = |a: &str, b: &str| a == b || a.starts_with(&format!("{}/", b)) || b.starts_with(&format!("{}/", a));
+  let mut conflicts: Vec<String> = changed.into_iter()
+    .filter(|changed| mutation.patch.iter().flat_map(|op| op.paths()).any(|path| overlaps(path, changed)))
+    .collect();
+  conflicts.dedup();
+  Ok(conflicts)
+}
@@ -0,0 +1,111 @@
+//! Отвечает за зависимости между задачами доски.
+//!
+//! Задача может зависеть от других задач той же доски (`Task::depends_on`) и считается заблокированной (`Task::blocked`), пока хотя бы одна из них не выполнена. Признак блокировки пересчитывается при каждом изменении и получении доски. Зависимости не могут образовывать цикл, иначе задачи в нём никогда не были бы разблокированы.
+
+use custom_error::custom_error;
+use std::collections::{HashMap, HashSet};
+
+use crate::core::events::EventKind;
+use crate::core::{save_board, task_history};
+use crate::model::{BoardContext, Card, Cards, Task, TaskPath};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub DependencyCycle{} = "Задача не может зависеть от задачи, которая сама зависит от неё."}
+
+/// Добавляет задаче зависимость от задачи `dependency`.
+///
+/// Если зависимость образует цикл, функция возвращает `DependencyCycle`. Уже существующая зависимость не добавляется повторно.
+pub async fn add(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64, dependency: TaskPath) -> MResult<()> {
+  let path = TaskPath { card_id: *card_id, task_id: *task_id };
+  ctx.board.cards.get_task(&dependency.card_id, &dependency.task_id)?;
+  if reaches(&ctx.board.cards, dependency, path) { return Err(Box::new(DependencyCycle{})); };
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let depends_on = &mut ctx.board.cards.get_mut_task(card_id, task_id)?.depends_on;
+  if depends_on.contains(&dependency) { return Ok(()); };
+  depends_on.push(dependency);
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
+}
+
+/// Удаляет у задачи зависимость от задачи `dependency`. Отсутствующая зависимость не считается ошибкой.
+pub async fn remove(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64, dependency: TaskPath) -> MResult<()> {
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let depends_on = &mut ctx.board.cards.get_mut_task(card_id, task_id)?.depends_on;
+  if !depends_on.contains(&dependency) { return Ok(()); };
+  depends_on.retain(|path| *path != dependency);
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
+}
+
+/// Удаляет зависимости от задач, которые удаляются с доски.
+pub fn forget(cards: &mut [Card], removed: impl Fn(&TaskPath) -> bool) {
+  for card in cards {
+    for task in &mut card.tasks {
+      task.depends_on.retain(|path| !removed(path));
+    };
+  };
+}
+
+/// Возвращает зависимости других задач от удаляемых задач в виде пар (зависимая задача, удаляемая задача).
+pub fn dependents(cards: &[Card], removed: impl Fn(&TaskPath) -> bool) -> Vec<(TaskPath, TaskPath)> {
+  index(cards).into_iter()
+    .filter(|(path, _)| !removed(path))
+    .flat_map(|(path, task)| task.depends_on.iter().filter(|dep| removed(dep)).map(move |dep| (path, *dep)))
+    .collect()
+}
+
+/// Восстанавливает зависимости, удалённые вместе с задачами (см. `dependents`).
+///
+/// Зависимости задач, которых уже нет на доске, и зависимости, которые образовали бы цикл, пропускаются.
+pub fn restore(cards: &mut [Card], edges: &[(TaskPath, TaskPath)]) {
+  for (path, dependency) in edges {
+    if !index(cards).contains_key(dependency) || reaches(cards, *dependency, *path) { continue; };
+    let task = cards.iter_mut()
+      .filter(|card| card.id == path.card_id)
+      .flat_map(|card| card.tasks.iter_mut())
+      .find(|task| task.id == path.task_id);
+    if let Some(task) = task {
+      if !task.depends_on.contains(dependency) { task.depends_on.push(*dependency); };
+    };
+  };
+}
+
+/// Проверяет, образуют ли зависимости задач доски цикл.
+pub fn has_cycle(cards: &[Card]) -> bool {
+  index(cards).into_iter()
+    .any(|(path, task)| task.depends_on.iter().any(|dependency| reaches(cards, *dependency, path)))
+}
+
+/// Пересчитывает признак блокировки у всех задач.
+pub fn refresh_blocked(cards: &mut [Card]) {
+  let done: HashMap<TaskPath, bool> = index(cards).into_iter().map(|(path, task)| (path, task.exec)).collect();
+  for card in cards {
+    for task in &mut card.tasks {
+      task.blocked = task.depends_on.iter().any(|path| done.get(path) == Some(&false));
+    };
+  };
+}
+
+/// Проверяет, совпадает ли задача `from` с задачей `target` или зависит ли от неё - напрямую или через другие задачи.
+fn reaches(cards: &[Card], from: TaskPath, target: TaskPath) -> bool {
+  let tasks = index(cards);
+  let mut visited = HashSet::new();
+  let mut stack = vec![from];
+  while let Some(path) = stack.pop() {
+    if path == target { return true; };
+    if !visited.insert(path) { continue; };
+    if let Some(task) = tasks.get(&path) {
+      stack.extend(task.depends_on.iter().copied());
+    };
+  };
+  false
+}
+
+/// Индексирует задачи доски по ссылкам на них.
+fn index(cards: &[Card]) -> HashMap<TaskPath, &Task> {
+  cards.iter()
+    .flat_map(|card| card.tasks.iter().map(move |task| (TaskPath { card_id: card.id, task_id: task.id }, task)))
+    .collect()
+}
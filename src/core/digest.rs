@@ -0,0 +1,217 @@
+//! Отвечает за дайджесты изменений досок, которые пользователи получают по электронной почте.
+//!
+//! Фоновая задача периодически находит пользователей, которым пора отправить дайджест: у них задан адрес, они не отписались, и с прошлого дайджеста прошли сутки или неделя - в зависимости от выбранной периодичности. Дайджест охватывает время с прошлого дайджеста (первый - последние сутки или неделю) и перечисляет:
+//!
+//! - задачи, исполнителем которых или исполнителем подзадач которых пользователя назначили (по уведомлениям `assigned`, см. `notifications`);
+//! - невыполненные задачи пользователя, обязательный срок которых наступит в ближайшие сутки;
+//! - изменившиеся доски.
+//!
+//! Доски, уведомления которых пользователь отключил, в дайджест не попадают. Пустой дайджест не отправляется, но время дайджеста всё равно сдвигается. Если шлюз не принял письмо, дайджест повторяется при следующем запуске задачи.
+
+use chrono::{TimeZone, Utc};
+use custom_error::custom_error;
+use std::collections::HashSet;
+
+use crate::integrations::mailer::{Mail, Mailer};
+use crate::model::{BoardHeader, Card, DigestCadence, NotificationPrefs, NotificationPrefsPatch};
+use crate::psql_handler::Db;
+use crate::setup::MailerConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongEmail{} = "Адрес электронной почты задан неверно."}
+
+/// Наибольшая длина адреса электронной почты.
+const MAX_EMAIL_LEN: usize = 254;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+const SUBJECT: &str = "Изменения на ваших досках";
+
+/// Возвращает название периодичности, под которым она хранится в базе данных.
+pub fn cadence_name(cadence: DigestCadence) -> &'static str {
+  match cadence {
+    DigestCadence::Daily => "daily",
+    DigestCadence::Weekly => "weekly",
+  }
+}
+
+/// Возвращает периодичность по её названию в базе данных.
+pub fn cadence(name: &str) -> DigestCadence {
+  match name {
+    "weekly" => DigestCadence::Weekly,
+    _ => DigestCadence::Daily,
+  }
+}
+
+/// Возвращает число секунд между дайджестами или отчётами.
+pub fn period_secs(cadence: DigestCadence) -> i64 {
+  match cadence {
+    DigestCadence::Daily => DAY_SECS,
+    DigestCadence::Weekly => 7 * DAY_SECS,
+  }
+}
+
+/// Проверяет адрес электронной почты и приводит его к виду, в котором он хранится.
+///
+/// Проверка намеренно простая: адрес без пробелов и управляющих символов, в котором до и после единственного `@` что-то есть, а в части после `@` есть точка. Существование адреса проверяет только доставка письма.
+pub fn email(email: &str) -> Result<String, WrongEmail> {
+  let email = email.trim();
+  let valid = email.len() <= MAX_EMAIL_LEN
+    && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+    && match email.split_once('@') {
+      Some((local, domain)) => !local.is_empty() && !domain.contains('@') && domain.contains('.')
+        && !domain.starts_with('.') && !domain.ends_with('.'),
+      None => false,
+    };
+  match valid {
+    true => Ok(email.to_string()),
+    false => Err(WrongEmail{}),
+  }
+}
+
+/// Возвращает настройки уведомлений пользователя.
+pub async fn prefs(db: &Db, user_id: &i64) -> MResult<NotificationPrefs> {
+  let rows = db.read_all("select email, digest, unsubscribed from notification_prefs where user_id = $1;", &[user_id]).await?;
+  Ok(rows.first().map_or_else(NotificationPrefs::default, |row| NotificationPrefs {
+    email: row.get(0),
+    digest: cadence(row.get(1)),
+    unsubscribed: row.get(2),
+  }))
+}
+
+/// Изменяет настройки уведомлений пользователя.
+pub async fn set_prefs(db: &Db, user_id: &i64, patch: &NotificationPrefsPatch) -> MResult<()> {
+  let new_email = match &patch.email {
+    Some(Some(new_email)) => Some(email(new_email)?),
+    _ => None,
+  };
+  let set_email = patch.email.is_some();
+  let digest = patch.digest.map(cadence_name);
+  db.write(
+    "insert into notification_prefs (user_id, email, digest, unsubscribed) values ($1, $2, coalesce($3, 'daily'), coalesce($4, false)) \
+       on conflict (user_id) do update set \
+         email = case when $5 then $2 else notification_prefs.email end, \
+         digest = coalesce($3, notification_prefs.digest), \
+         unsubscribed = coalesce($4, notification_prefs.unsubscribed);",
+    &[user_id, &new_email, &digest, &patch.unsubscribed, &set_email]
+  ).await
+}
+
+/// Задача, упомянутая в дайджесте.
+struct DigestTask {
+  board: String,
+  task: String,
+  max_time: i64,
+}
+
+/// Дайджест изменений досок одного пользователя.
+#[derive(Default)]
+struct Digest {
+  assigned: Vec<DigestTask>,
+  due: Vec<DigestTask>,
+  changed: Vec<String>,
+}
+
+impl Digest {
+  fn is_empty(&self) -> bool {
+    self.assigned.is_empty() && self.due.is_empty() && self.changed.is_empty()
+  }
+
+  /// Возвращает текст письма.
+  fn text(&self) -> String {
+    let mut text = String::new();
+    let mut section = |title: &str, lines: Vec<String>| {
+      if lines.is_empty() { return; };
+      text.push_str(title);
+      text.push('\n');
+      lines.iter().for_each(|line| text.push_str(&format!("- {}\n", line)));
+      text.push('\n');
+    };
+    section("Вас назначили исполнителем:", self.assigned.iter().map(|t| format!("«{}»: {}", t.board, t.task)).collect());
+    section("Срок наступает в ближайшие сутки:", self.due.iter().map(|t| {
+      let at = Utc.timestamp_opt(t.max_time, 0).single().map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string()).unwrap_or_default();
+      format!("«{}»: {} - до {}", t.board, t.task, at)
+    }).collect());
+    section("Изменились доски:", self.changed.iter().map(|board| format!("«{}»", board)).collect());
+    text.push_str("Чтобы отписаться от дайджестов, отключите их в настройках уведомлений.\n");
+    text
+  }
+}
+
+/// Собирает дайджест пользователя за время после `since` до `now`.
+async fn collect(db: &Db, user_id: &i64, since: i64, now: i64) -> MResult<Digest> {
+  let boards = db.read("select shared_boards::text from users where id = $1;", &[user_id]).await?;
+  let boards: Vec<i64> = serde_json::from_str(boards.get(0))?;
+  let rows = db.read_all(
+    "select b.id, b.header::text, b.cards::text, b.updated_at \
+       from boards b left join user_board_prefs p on p.board_id = b.id and p.user_id = $2 \
+       where b.id = any($1) and not coalesce(p.muted, false) order by b.id;",
+    &[&boards, user_id]
+  ).await?;
+  let assigned: HashSet<(i64, i64, i64)> = db.read_all(
+    "select board_id, card_id, task_id from notifications \
+       where user_id = $1 and kind = 'assigned' and at > $2 and at <= $3;",
+    &[user_id, &since, &now]
+  ).await?.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect();
+  let mut digest = Digest::default();
+  for row in &rows {
+    let board_id: i64 = row.get(0);
+    // Повреждённые доски находит и описывает `integrity::scan`.
+    let header = serde_json::from_str::<BoardHeader>(row.get(1));
+    let cards = serde_json::from_str::<Vec<Card>>(row.get(2));
+    let (header, cards) = match (header, cards) {
+      (Ok(header), Ok(cards)) => (header, cards),
+      _ => continue,
+    };
+    if row.get::<_, i64>(3) > since { digest.changed.push(header.title.clone()); };
+    for card in &cards {
+      for task in &card.tasks {
+        let max_time = task.timelines.max_time.timestamp();
+        let item = || DigestTask { board: header.title.clone(), task: task.title.clone(), max_time };
+        if assigned.contains(&(board_id, card.id, task.id)) { digest.assigned.push(item()); };
+        if !task.exec && task.executors.contains(user_id) && max_time > now && max_time <= now + DAY_SECS {
+          digest.due.push(item());
+        };
+      };
+    };
+  };
+  digest.due.sort_by_key(|task| task.max_time);
+  Ok(digest)
+}
+
+/// Отправляет дайджесты всем пользователям, которым они причитаются. Возвращает число отправленных писем.
+pub async fn send_due(db: &Db, cfg: &MailerConfig) -> MResult<usize> {
+  let now = Utc::now().timestamp();
+  let rows = db.read_all(
+    "select user_id, email, digest, digest_sent_at from notification_prefs \
+       where email is not null and not unsubscribed \
+         and digest_sent_at <= case digest when 'weekly' then $2::bigint else $1::bigint end;",
+    &[&(now - period_secs(DigestCadence::Daily)), &(now - period_secs(DigestCadence::Weekly))]
+  ).await?;
+  let mailer = Mailer::new(&cfg.url, &cfg.token, &cfg.from);
+  let mut sent = 0;
+  for row in &rows {
+    let (user_id, to, sent_at): (i64, String, i64) = (row.get(0), row.get(1), row.get(3));
+    let since = match sent_at {
+      0 => now - period_secs(cadence(row.get(2))),
+      sent_at => sent_at,
+    };
+    let digest = collect(db, &user_id, since, now).await?;
+    if !digest.is_empty() {
+      if let Err(e) = mailer.send(&Mail { to: &to, subject: SUBJECT, text: &digest.text() }).await {
+        eprintln!("Не удалось отправить дайджест пользователю {}: {}", user_id, e);
+        continue;
+      };
+      sent += 1;
+    };
+    db.write("update notification_prefs set digest_sent_at = $2 where user_id = $1;", &[&user_id, &now]).await?;
+  };
+  Ok(sent)
+}
+
+/// Периодическое задание (см. `jobs`): запускает `send_due`.
+pub async fn job(db: Db, cfg: MailerConfig) -> MResult<()> {
+  send_due(&db, &cfg).await?;
+  Ok(())
+}
@@ -0,0 +1,322 @@
+//! Отвечает за изменение доски целиком патчем JSON Patch (см. `json_patch`).
+//!
+//! Патч применяется к доске в том виде, в котором её отдаёт `POST /board`, а результат проверяется так же, как содержимое, переданное отдельным методам: заголовки, цвета, заметки и ссылки задач, ограничения `wip_limit` и тарифного плана, отсутствие циклов зависимостей. Поля, которые поддерживает сервер, - идентификатор, автор, участники и ревизия доски, авторы и время создания и изменения сущностей, `github_issue`, назначения исполнителей и признаки задач - сохраняют прежние значения.
+//!
+//! Новые карточки, задачи, подзадачи, теги, дорожки и спринты получают идентификаторы из последовательностей сервера, как при создании отдельными методами, а ссылки на их временные идентификаторы внутри доски переписываются. Ссылки на несуществующие сущности и исполнители без доступа к доске отбрасываются. Удаления, сделанные патчем, нельзя отменить (см. `undo`).
+
+use chrono::Utc;
+use custom_error::custom_error;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use tokio_postgres::types::ToSql;
+
+use crate::core::automation;
+use crate::core::dependencies::{self, DependencyCycle};
+use crate::core::events::EventKind;
+use crate::core::json_patch::{self, Operation};
+use crate::core::recycle;
+use crate::core::validation::{self, WrongLink, MAX_TASK_LINKS};
+use crate::core::{check_wip_limit, quota, save_board, sprints, task_history};
+use crate::model::{Board, BoardBackground, BoardContext, CardCover, Link, TaskPath};
+use crate::sec::color_vld::validate_color;
+use crate::sec::markdown;
+use crate::setup::AppConfig;
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongDocument{reason: String} = "Доска после применения патча не принята: {reason}."}
+custom_error!{pub NotBoardAuthor{} = "Заголовок, фон и настройки доски может изменять только её автор."}
+
+/// Применяет патч к доске и записывает её. Если доска не записана, `ctx` остаётся прежним, и к нему можно применять следующие патчи.
+///
+/// Если не проходит операция `test`, функция возвращает `json_patch::TestFailed`, если патч или результат не проходит проверку - `json_patch::WrongPatch`, `WrongDocument` или ошибки `validation`, а если пользователь без авторства меняет заголовок, фон или настройки доски - `NotBoardAuthor`.
+pub async fn patch(db: &dyn Storage, cfg: &AppConfig, ctx: &mut BoardContext, operations: &[Operation]) -> MResult<()> {
+  let mut document = serde_json::to_value(&ctx.board)?;
+  json_patch::apply(&mut document, operations)?;
+  let mut board: Board = serde_json::from_value(document).map_err(|e| wrong(e.to_string()))?;
+  let old = &ctx.board;
+  board.id = old.id;
+  board.author = old.author;
+  board.shared_with = old.shared_with.clone();
+  board.watchers = old.watchers.clone();
+  board.revision = old.revision;
+  board.created_at = old.created_at;
+  board.updated_at = old.updated_at;
+  board.archived_at = old.archived_at;
+  let restricted = differs(&board.header, &old.header)? || differs(&board.background, &old.background)? ||
+    differs(&board.settings, &old.settings)?;
+  if restricted && ctx.user_id != old.author { return Err(Box::new(NotBoardAuthor{})); };
+  validate(&mut board, old)?;
+  if board.cards.len() > old.cards.len() {
+    let quota = quota::for_user(db, cfg, &old.author).await?;
+    quota::check("max_cards_per_board", quota.max_cards_per_board, board.cards.len() as u64)?;
+  };
+  assign_ids(db, &mut board, old).await?;
+  if board.settings.rules != old.settings.rules {
+    automation::validate(&mut board.settings.rules, &board.cards, &board.tags, &board.shared_with)?;
+  };
+  if board.settings.recycle != old.settings.recycle { recycle::validate(&board.settings.recycle, &board.cards)?; };
+  if dependencies::has_cycle(&board.cards) { return Err(Box::new(DependencyCycle{})); };
+  keep_server_fields(&mut board, old, ctx.user_id, Utc::now().timestamp())?;
+  let paths = |board: &Board| -> HashSet<TaskPath> {
+    board.cards.iter().flat_map(|card| card.tasks.iter().map(|task| TaskPath { card_id: card.id, task_id: task.id })).collect()
+  };
+  let (before, after) = (paths(old), paths(&board));
+  let removed: Vec<TaskPath> = before.difference(&after).copied().collect();
+  let snapshots = before.intersection(&after)
+    .map(|path| task_history::snapshot(ctx, &path.card_id, &path.task_id))
+    .collect::<MResult<Vec<_>>>()?;
+  let previous = std::mem::replace(&mut ctx.board, board);
+  let saved = save(db, ctx, snapshots, &removed).await;
+  if saved.is_err() { ctx.board = previous; };
+  saved
+}
+
+/// Записывает доску, уже заменённую в `ctx`, вместе с изменениями задач и удалением истории удалённых задач.
+async fn save(db: &dyn Storage, ctx: &mut BoardContext, snapshots: Vec<task_history::Snapshot>, removed: &[TaskPath]) -> MResult<()> {
+  let changes = snapshots.into_iter().map(|snapshot| snapshot.changes(ctx)).collect::<MResult<Vec<_>>>()?;
+  let board_id = ctx.board.id;
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = removed.iter()
+    .map(|path| (
+      "delete from task_history where board_id = $1 and card_id = $2 and task_id = $3;",
+      vec![&board_id as &(dyn ToSql + Sync), &path.card_id, &path.task_id]
+    ))
+    .collect();
+  queries.extend(changes.iter().flat_map(|changes| changes.queries()));
+  save_board(db, ctx, EventKind::BoardUpdated, queries).await
+}
+
+fn wrong(reason: String) -> WrongDocument {
+  WrongDocument{ reason }
+}
+
+/// Сравнивает сущности по их JSON.
+fn differs<T: Serialize>(a: &T, b: &T) -> MResult<bool> {
+  Ok(serde_json::to_value(a)? != serde_json::to_value(b)?)
+}
+
+fn color(color: &str) -> Result<(), WrongDocument> {
+  validate_color(color).map_err(|_| wrong(format!("цвет \"{}\" задан неверно", color)))
+}
+
+/// Проверяет, что идентификаторы сущностей не повторяются.
+fn unique(entities: &str, ids: impl Iterator<Item = i64>) -> Result<(), WrongDocument> {
+  let mut seen = HashSet::new();
+  for id in ids {
+    if !seen.insert(id) { return Err(wrong(format!("идентификатор {} повторяется у {}", id, entities))); };
+  };
+  Ok(())
+}
+
+/// Очищает заметки, если они изменились. Сохранённые заметки уже очищены.
+fn sanitize(notes: &mut String, old: Option<&String>) {
+  if old != Some(notes) { *notes = markdown::sanitize(notes); };
+}
+
+/// Проверяет ссылки задачи и назначает идентификаторы ссылкам без уникального идентификатора.
+fn links(links: &mut [Link]) -> MResult<()> {
+  if links.len() > MAX_TASK_LINKS {
+    return Err(Box::new(WrongLink{ reason: format!("у задачи может быть не больше {} ссылок", MAX_TASK_LINKS) }));
+  };
+  let mut next_id = links.iter().map(|link| link.id).max().unwrap_or(0).max(0) + 1;
+  let mut seen = HashSet::new();
+  for link in links.iter_mut() {
+    validation::link(link)?;
+    if link.id <= 0 || !seen.insert(link.id) {
+      link.id = next_id;
+      next_id += 1;
+    };
+  };
+  Ok(())
+}
+
+/// Проверяет содержимое доски и приводит заголовки и заметки к виду, в котором они хранятся.
+fn validate(board: &mut Board, old: &Board) -> MResult<()> {
+  board.header.title = validation::title("доски", &board.header.title)?;
+  color(&board.header.header_text_color)?;
+  color(&board.header.header_background_color)?;
+  if let BoardBackground::Color { color: background } = &board.background { color(background)?; };
+  unique("тегов", board.tags.iter().map(|tag| tag.id))?;
+  for tag in &mut board.tags {
+    tag.title = validation::title("тега", &tag.title)?;
+    color(&tag.text_color)?;
+    color(&tag.background_color)?;
+  };
+  unique("дорожек", board.lanes.iter().map(|lane| lane.id))?;
+  for lane in &mut board.lanes {
+    lane.title = validation::title("дорожки", &lane.title)?;
+    color(&lane.color)?;
+  };
+  unique("спринтов", board.sprints.iter().map(|sprint| sprint.id))?;
+  for sprint in &mut board.sprints {
+    sprints::validate(sprint)?;
+  };
+  unique("карточек", board.cards.iter().map(|card| card.id))?;
+  for card in &mut board.cards {
+    let old_card = old.cards.iter().find(|c| c.id == card.id);
+    card.title = validation::title("карточки", &card.title)?;
+    color(&card.header_text_color)?;
+    color(&card.header_background_color)?;
+    color(&card.background_color)?;
+    match &mut card.cover {
+      Some(CardCover::Color { color: cover }) => color(cover)?,
+      Some(CardCover::Url { url }) => *url = validation::url(url)?,
+      None => {},
+    };
+    if let Some(theme) = &card.theme {
+      color(&theme.text_color)?;
+      color(&theme.accent_color)?;
+    };
+    // Карточка, в которой задач уже больше ограничения, не мешает изменять доску, пока задач в ней не становится больше.
+    if old_card.is_none_or(|c| card.tasks.len() > c.tasks.len() || card.wip_limit != c.wip_limit) {
+      check_wip_limit(card, card.tasks.len())?;
+    };
+    unique("задач карточки", card.tasks.iter().map(|task| task.id))?;
+    for task in &mut card.tasks {
+      let old_task = old_card.and_then(|c| c.tasks.iter().find(|t| t.id == task.id));
+      task.title = validation::title("задачи", &task.title)?;
+      sanitize(&mut task.notes, old_task.map(|t| &t.notes));
+      links(&mut task.links)?;
+      unique("подзадач задачи", task.subtasks.iter().map(|subtask| subtask.id))?;
+      for subtask in &mut task.subtasks {
+        let old_subtask = old_task.and_then(|t| t.subtasks.iter().find(|s| s.id == subtask.id));
+        subtask.title = validation::title("подзадачи", &subtask.title)?;
+        sanitize(&mut subtask.notes, old_subtask.map(|s| &s.notes));
+      };
+    };
+  };
+  Ok(())
+}
+
+/// Назначает идентификаторы сущностям, которых не было на доске, и переписывает ссылки на них.
+async fn assign_ids(db: &dyn Storage, board: &mut Board, old: &Board) -> MResult<()> {
+  let seq = board.id.to_string();
+  // Новые идентификаторы больше всех прежних. С временными они могут совпасть, но временные идентификаторы заменяются все сразу.
+  let min = |ids: &mut dyn Iterator<Item = i64>| ids.max().unwrap_or(0) + 1;
+  let mut tags = HashMap::new();
+  let min_tag_id = min(&mut old.tags.iter().map(|tag| tag.id));
+  for tag in &mut board.tags {
+    let temp_id = tag.id;
+    if !old.tags.iter().any(|t| t.id == tag.id) { tag.id = db.next_id(&(seq.clone() + "_tags"), min_tag_id).await?; };
+    tags.insert(temp_id, tag.id);
+  };
+  let mut lanes = HashMap::new();
+  let min_lane_id = min(&mut old.lanes.iter().map(|lane| lane.id));
+  for lane in &mut board.lanes {
+    let temp_id = lane.id;
+    if !old.lanes.iter().any(|l| l.id == lane.id) { lane.id = db.next_id(&(seq.clone() + "_lanes"), min_lane_id).await?; };
+    lanes.insert(temp_id, lane.id);
+  };
+  let mut sprints = HashMap::new();
+  let min_sprint_id = min(&mut old.sprints.iter().map(|sprint| sprint.id));
+  for sprint in &mut board.sprints {
+    let temp_id = sprint.id;
+    if !old.sprints.iter().any(|s| s.id == sprint.id) { sprint.id = db.next_id(&(seq.clone() + "_sprints"), min_sprint_id).await?; };
+    sprints.insert(temp_id, sprint.id);
+  };
+  let mut tasks = HashMap::new();
+  let min_card_id = min(&mut old.cards.iter().map(|card| card.id));
+  for card in &mut board.cards {
+    let temp_card_id = card.id;
+    let old_card = old.cards.iter().find(|c| c.id == card.id);
+    if old_card.is_none() { card.id = db.next_id(&seq, min_card_id).await?; };
+    let tasks_seq = format!("{}_{}", seq, card.id);
+    let min_task_id = min(&mut old_card.into_iter().flat_map(|c| &c.tasks).map(|task| task.id));
+    for task in &mut card.tasks {
+      let temp_path = TaskPath { card_id: temp_card_id, task_id: task.id };
+      let old_task = old_card.and_then(|c| c.tasks.iter().find(|t| t.id == task.id));
+      if old_task.is_none() { task.id = db.next_id(&tasks_seq, min_task_id).await?; };
+      tasks.insert(temp_path, TaskPath { card_id: card.id, task_id: task.id });
+      let subtasks_seq = format!("{}_{}", tasks_seq, task.id);
+      let min_subtask_id = min(&mut old_task.into_iter().flat_map(|t| &t.subtasks).map(|subtask| subtask.id));
+      for subtask in &mut task.subtasks {
+        if !old_task.is_some_and(|t| t.subtasks.iter().any(|s| s.id == subtask.id)) {
+          subtask.id = db.next_id(&subtasks_seq, min_subtask_id).await?;
+        };
+      };
+    };
+  };
+  let shared_with: HashSet<i64> = board.shared_with.iter().copied().collect();
+  let retag = |ids: &mut Vec<i64>| *ids = ids.iter().filter_map(|id| tags.get(id).copied()).collect();
+  for task in board.cards.iter_mut().flat_map(|card| card.tasks.iter_mut()) {
+    retag(&mut task.tags);
+    task.executors.retain(|id| shared_with.contains(id));
+    task.lane_id = task.lane_id.and_then(|id| lanes.get(&id).copied());
+    task.sprint_id = task.sprint_id.and_then(|id| sprints.get(&id).copied());
+    let mut seen = HashSet::new();
+    task.depends_on = task.depends_on.iter().filter_map(|path| tasks.get(path).copied()).filter(|path| seen.insert(*path)).collect();
+    for subtask in &mut task.subtasks {
+      retag(&mut subtask.tags);
+      subtask.executors.retain(|id| shared_with.contains(id));
+    };
+  };
+  Ok(())
+}
+
+/// Возвращает сущностям доски значения полей, которые поддерживает сервер, и обновляет время изменения изменённых сущностей.
+///
+/// Новые сущности получают автора `user_id` и время создания `now`. Статус выполнения задачи, подзадачи которой изменились, обновляется по настройке распространения (см. `Task::propagate_exec`).
+fn keep_server_fields(board: &mut Board, old: &Board, user_id: i64, now: i64) -> MResult<()> {
+  let propagation = board.settings.exec_propagation;
+  for card in &mut board.cards {
+    let old_card = old.cards.iter().find(|c| c.id == card.id);
+    for task in &mut card.tasks {
+      let old_task = match old_card.and_then(|c| c.tasks.iter().find(|t| t.id == task.id)) {
+        Some(old_task) => old_task,
+        None => {
+          task.author = user_id;
+          task.github_issue = None;
+          task.field_stamps.clear();
+          task.assignments.clear();
+          task.watchers.clear();
+          task.subtasks.iter_mut().for_each(|subtask| subtask.author = user_id);
+          task.propagate_exec(propagation);
+          task.stamp_created(now);
+          continue;
+        },
+      };
+      for subtask in &mut task.subtasks {
+        match old_task.subtasks.iter().find(|s| s.id == subtask.id) {
+          Some(old_subtask) => {
+            subtask.author = old_subtask.author;
+            subtask.created_at = old_subtask.created_at;
+            subtask.updated_at = old_subtask.updated_at;
+            if differs(subtask, old_subtask)? { subtask.updated_at = now; };
+          },
+          None => {
+            subtask.author = user_id;
+            subtask.stamp_created(now);
+          },
+        };
+      };
+      task.author = old_task.author;
+      task.github_issue = old_task.github_issue.clone();
+      task.field_stamps = old_task.field_stamps.clone();
+      task.assignments = old_task.assignments.clone();
+      task.watchers = old_task.watchers.clone();
+      task.blocked = old_task.blocked;
+      task.overdue = old_task.overdue;
+      task.due_soon = old_task.due_soon;
+      task.created_at = old_task.created_at;
+      task.updated_at = old_task.updated_at;
+      if differs(&task.subtasks, &old_task.subtasks)? { task.propagate_exec(propagation); };
+      if differs(task, old_task)? { task.updated_at = now; };
+    };
+    match old_card {
+      Some(old_card) => {
+        card.author = old_card.author;
+        card.task_count = old_card.task_count;
+        card.created_at = old_card.created_at;
+        card.updated_at = old_card.updated_at;
+        if differs(card, old_card)? { card.updated_at = now; };
+      },
+      None => {
+        card.author = user_id;
+        card.created_at = now;
+        card.updated_at = now;
+      },
+    };
+  };
+  Ok(())
+}
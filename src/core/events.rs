@@ -0,0 +1,185 @@
+//! Отвечает за события изменения досок.
+//!
+//! Каждое изменение доски в `core` публикует `BoardEvent` в общий для процесса канал. Побочные эффекты изменений (уведомления, вебхуки, журнал действий и т.д.) реализуются подписчиками канала, а не обработчиками запросов, поэтому для нового побочного эффекта не нужно править каждый обработчик.
+//!
+//! Канал не хранит события: подписчик получает только те события, которые опубликованы после подписки. Подписчик, не успевающий обрабатывать события, пропускает самые старые из них.
+//!
+//! Массовые изменения публикуют десятки событий одной доски за несколько секунд. Подписчики, которые передают изменения людям или внешним сервисам (уведомления, синхронизация с GitHub), получают события через `Coalescer`: он собирает события каждой доски за окно, заданное в конфигурации (`event_coalesce_ms`), и отдаёт их вместе, отбрасывая повторы, чтобы подписчик мог отреагировать на всю серию изменений один раз.
+
+use chrono::{DateTime, Utc, serde::ts_seconds};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, Receiver, Sender, error::RecvError};
+use tokio::time::Instant;
+
+/// Число событий, которые канал удерживает для медленных подписчиков.
+const CAPACITY: usize = 1024;
+
+static BUS: OnceLock<Sender<BoardEvent>> = OnceLock::new();
+
+/// Вид изменения доски.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+  BoardCreated,
+  BoardUpdated,
+  BoardDeleted,
+  CardCreated { card_id: i64 },
+  CardUpdated { card_id: i64 },
+  CardDeleted { card_id: i64 },
+  /// Удалённая карточка восстановлена (см. `core::undo`).
+  CardRestored { card_id: i64 },
+  TaskCreated { card_id: i64, task_id: i64 },
+  TaskUpdated { card_id: i64, task_id: i64 },
+  TaskDeleted { card_id: i64, task_id: i64 },
+  TaskRestored { card_id: i64, task_id: i64 },
+  /// В карточку импортированы задачи (см. `core::import`).
+  TasksImported { card_id: i64, task_ids: Vec<i64> },
+  /// Задачи доски синхронизированы с задачами GitHub (см. `core::github`).
+  GithubSynced,
+  /// Задача стала просроченной (см. `core::overdue`).
+  TaskOverdue { card_id: i64, task_id: i64 },
+  /// До обязательного срока задачи осталось меньше `model::DUE_SOON_SECS` (см. `core::overdue`).
+  TaskDueSoon { card_id: i64, task_id: i64 },
+  /// Пользователь назначен исполнителем задачи или подзадачи (см. `core::notifications`).
+  Assigned { card_id: i64, task_id: i64, subtask_id: Option<i64>, executor: i64 },
+  /// Логин пользователя упомянут в заметках задачи или подзадачи.
+  Mentioned { card_id: i64, task_id: i64, subtask_id: Option<i64>, login: String },
+  SubtaskCreated { card_id: i64, task_id: i64, subtask_id: i64 },
+  SubtaskUpdated { card_id: i64, task_id: i64, subtask_id: i64 },
+  SubtaskDeleted { card_id: i64, task_id: i64, subtask_id: i64 },
+  SubtaskRestored { card_id: i64, task_id: i64, subtask_id: i64 },
+  TagCreated { tag_id: i64 },
+  TagUpdated { tag_id: i64 },
+  TagDeleted { tag_id: i64 },
+  LaneCreated { lane_id: i64 },
+  LaneUpdated { lane_id: i64 },
+  LaneDeleted { lane_id: i64 },
+  SprintCreated { sprint_id: i64 },
+  SprintUpdated { sprint_id: i64 },
+  SprintDeleted { sprint_id: i64 },
+  /// Пользователю открыт доступ к доске.
+  BoardShared { member: i64 },
+  /// Доска передана другому автору.
+  BoardTransferred { author: i64 },
+  /// Участник покинул доску.
+  MemberLeft { member: i64 },
+  /// Задача отмечена выполненной. Публикуется только на досках с правилами автоматизации (см. `core::automation`).
+  TaskCompleted { card_id: i64, task_id: i64 },
+  /// К задаче прикреплён тег. Публикуется только на досках с правилами автоматизации.
+  TagAdded { card_id: i64, task_id: i64, tag_id: i64 },
+  /// Правила автоматизации изменили задачу; `card_id` и `task_id` указывают на задачу после возможного переноса.
+  RuleApplied { card_id: i64, task_id: i64 },
+  /// Пользователь начал или перестал следить за задачей или, если задача не указана, за всей доской.
+  WatchersChanged { card_id: Option<i64>, task_id: Option<i64> },
+  /// Участники доски организации приведены к составу организации: доска вошла в организацию или в организацию вступил или из неё вышел пользователь (см. `core::orgs`).
+  OrgMembersSynced { org_id: i64 },
+  /// Доска отмечена неактивной (см. `core::retention`).
+  BoardInactive { author: i64 },
+  /// Доска перенесена в архив автором или, если пользователь не указан, за неактивностью.
+  BoardArchived { author: i64 },
+  /// Неактивная доска выгружена для автора и удалена.
+  BoardExported { author: i64, export_id: i64 },
+  /// Автор вернул доску из архива.
+  BoardUnarchived,
+  /// Выполненные задачи перенесены в карточку `card_id` или, если карточка не указана, в архив задач доски (см. `core::recycle`).
+  TasksRecycled { card_id: Option<i64>, count: usize },
+}
+
+/// Событие изменения доски.
+#[derive(Clone, Debug, Serialize)]
+pub struct BoardEvent {
+  /// Идентификатор доски.
+  pub board_id: i64,
+  /// Пользователь, изменивший доску. Отсутствует у изменений, выполненных самим сервером.
+  pub user_id: Option<i64>,
+  /// Ревизия доски после изменения.
+  pub revision: i64,
+  /// Дата и время изменения.
+  #[serde(with = "ts_seconds")]
+  pub at: DateTime<Utc>,
+  /// Вид изменения.
+  #[serde(flatten)]
+  pub kind: EventKind,
+}
+
+fn bus() -> &'static Sender<BoardEvent> {
+  BUS.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Публикует событие изменения доски.
+pub fn publish(board_id: i64, user_id: Option<i64>, revision: i64, kind: EventKind) {
+  // Ошибка означает лишь то, что подписчиков нет.
+  let _ = bus().send(BoardEvent { board_id, user_id, revision, at: Utc::now(), kind });
+}
+
+/// Подписывается на события изменения досок.
+pub fn subscribe() -> Receiver<BoardEvent> {
+  bus().subscribe()
+}
+
+/// Получает следующее событие, пропуская те, которые подписчик не успел получить.
+///
+/// Возвращает None, когда канал закрыт.
+pub async fn next(rx: &mut Receiver<BoardEvent>) -> Option<BoardEvent> {
+  loop {
+    match rx.recv().await {
+      Ok(event) => return Some(event),
+      Err(RecvError::Lagged(skipped)) => eprintln!("Подписчик пропустил {} событий.", skipped),
+      Err(RecvError::Closed) => return None,
+    };
+  }
+}
+
+/// Подписка, которая собирает события каждой доски за окно и отдаёт их вместе.
+///
+/// Окно доски начинается с её первого события и длится `window`, сколько бы событий ни пришло за это время, поэтому частые изменения доски не откладывают доставку бесконечно. Окна разных досок не зависят друг от друга.
+pub struct Coalescer {
+  rx: Receiver<BoardEvent>,
+  window: Duration,
+  /// События досок, окно которых ещё не закончилось, вместе с моментом окончания окна. Окна упорядочены по времени окончания.
+  pending: VecDeque<(Instant, Vec<BoardEvent>)>,
+  closed: bool,
+}
+
+impl Coalescer {
+  /// Создаёт подписку с данным окном. С нулевым окном события отдаются по одному сразу после публикации.
+  pub fn new(rx: Receiver<BoardEvent>, window: Duration) -> Coalescer {
+    Coalescer { rx, window, pending: VecDeque::new(), closed: false }
+  }
+
+  /// Возвращает события доски, окно которой закончилось, в порядке публикации. Повторы одного и того же изменения от одного пользователя отбрасываются.
+  ///
+  /// Возвращает None, когда канал закрыт и все собранные события отданы.
+  pub async fn next(&mut self) -> Option<Vec<BoardEvent>> {
+    if self.window.is_zero() { return next(&mut self.rx).await.map(|event| vec![event]); };
+    loop {
+      let deadline = match (self.pending.front(), self.closed) {
+        (_, true) => return self.pending.pop_front().map(|(_, events)| events),
+        (Some((deadline, _)), false) => Some(*deadline),
+        (None, false) => None,
+      };
+      let received = tokio::select! {
+        event = next(&mut self.rx) => Some(event),
+        _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => None,
+      };
+      match received {
+        Some(Some(event)) => self.push(event),
+        Some(None) => self.closed = true,
+        None => return self.pending.pop_front().map(|(_, events)| events),
+      };
+    }
+  }
+
+  /// Добавляет событие в окно его доски, открывая окно, если его нет.
+  fn push(&mut self, event: BoardEvent) {
+    match self.pending.iter_mut().find(|(_, events)| events[0].board_id == event.board_id) {
+      Some((_, events)) => {
+        if !events.iter().any(|other| other.user_id == event.user_id && other.kind == event.kind) { events.push(event); };
+      },
+      None => self.pending.push_back((Instant::now() + self.window, vec![event])),
+    };
+  }
+}
@@ -0,0 +1,369 @@
+//! Отвечает за синхронизацию задач доски с задачами (issues) репозитория GitHub.
+//!
+//! Автор доски связывает её с репозиторием (`link`), передавая токен доступа GitHub и карточку, в которой будут появляться задачи репозитория. Токен хранится в таблице `github_links` зашифрованным (см. `sec::cipher`). Синхронизация выполняется в обе стороны:
+//!
+//! - периодическое задание очереди запрашивает задачи репозитория, изменённые с прошлой синхронизации (`register`, `sync`): для новых открытых задач в карточке создаются задачи доски, а у связанных обновляются название и статус выполнения;
+//! - об открытии, изменении, закрытии и повторном открытии задач GitHub сообщает вебхуком (`receive`), и они применяются сразу;
+//! - когда пользователь меняет статус выполнения связанной задачи доски, задача на GitHub закрывается или открывается (`propagate`).
+//!
+//! Связанная задача доски хранит ссылку на задачу GitHub и последний известный её статус (`Task::github_issue`): статус выполнения меняется синхронизацией, только если задачу закрыли или открыли на GitHub, поэтому ещё не переданное на GitHub изменение статуса не теряется. Описание копируется с GitHub только при создании задачи, чтобы не затирать правки, сделанные на доске. Задачи, не поместившиеся в ограничение `wip_limit` карточки, создаются при одной из следующих синхронизаций.
+
+use chrono::{TimeZone, Utc};
+use custom_error::custom_error;
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::core::events::{self, Coalescer, EventKind};
+use crate::core::jobs::{self, Queue};
+use crate::core::{load_board, save_board, validation};
+use crate::integrations::github::{self, Api, Issue};
+use crate::model::{BoardContext, Cards, GithubIssue, Priority, Task, Timelines};
+use crate::psql_handler::{transaction, Db};
+use crate::sec::cipher;
+use crate::setup::GithubConfig;
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NotLinked{} = "Доска не связана с репозиторием GitHub."}
+custom_error!{pub NotAuthor{} = "Связью доски с репозиторием GitHub управляет только автор доски."}
+custom_error!{pub WrongRepo{} = "Репозиторий должен быть задан в виде владелец/название."}
+custom_error!{pub WrongSecretKey{} = "Ключ шифрования токенов GitHub задан неверно или изменился."}
+custom_error!{pub WrongSignature{} = "Неверная подпись вебхука."}
+
+/// Периодическое задание, ставящее в очередь синхронизацию связанных досок.
+const SYNC_ALL_JOB: &str = "github.sync";
+/// Синхронизация одной доски.
+const SYNC_JOB: &str = "github.sync-board";
+/// Передача на GitHub статуса выполнения задачи.
+const PUSH_JOB: &str = "github.push";
+
+/// Задача доски, статус которой передаётся на GitHub.
+#[derive(Deserialize, Serialize)]
+struct TaskRef {
+  board_id: i64,
+  card_id: i64,
+  task_id: i64,
+}
+
+/// Связь доски с репозиторием.
+#[derive(Serialize)]
+pub struct Link {
+  /// Репозиторий в виде `владелец/название`.
+  pub repo: String,
+  /// Карточка, в которой создаются задачи репозитория.
+  pub card_id: i64,
+  /// Секрет, которым GitHub подписывает вебхуки доски.
+  pub webhook_secret: String,
+  /// Пользователь, связавший доску с репозиторием. Синхронизация изменяет доску от его имени.
+  pub linked_by: i64,
+  /// Время, по которое синхронизированы задачи репозитория (UNIX-время в секундах). Отсутствует до первой синхронизации.
+  pub synced_at: Option<i64>,
+  /// Зашифрованный токен доступа.
+  #[serde(skip)]
+  token: Vec<u8>,
+}
+
+/// Связывает доску с репозиторием и возвращает связь.
+///
+/// Токен проверяется запросом репозитория. Если доска уже связана, связь заменяется, а секрет вебхука сохраняется; задачи репозитория синхронизируются заново, уже связанные задачи доски при этом не дублируются.
+pub async fn link(db: &Db, cfg: &GithubConfig, ctx: &BoardContext, repo: &str, token: &str, card_id: &i64) -> MResult<Link> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  ctx.board.cards.get_card(card_id)?;
+  if !github::valid_repo(repo) { return Err(Box::new(WrongRepo{})); };
+  let key = cipher::key(&cfg.secret_key).ok_or(WrongSecretKey{})?;
+  Api::new(&cfg.api_url, token).repo(repo).await?;
+  let token = cipher::seal(&key, token.as_bytes())?;
+  let webhook_secret = Uuid::new_v4().simple().to_string();
+  db.write(
+    "insert into github_links (board_id, repo, card_id, token, webhook_secret, linked_by) values ($1, $2, $3, $4, $5, $6) \
+       on conflict (board_id) do update set repo = excluded.repo, card_id = excluded.card_id, token = excluded.token, \
+       linked_by = excluded.linked_by, synced_at = null;",
+    &[&ctx.board.id, &repo, card_id, &token, &webhook_secret, &ctx.user_id]
+  ).await?;
+  stored(db, &ctx.board.id).await
+}
+
+/// Возвращает связь доски с репозиторием.
+pub async fn get(db: &Db, ctx: &BoardContext) -> MResult<Link> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  stored(db, &ctx.board.id).await
+}
+
+/// Удаляет связь доски с репозиторием. Ссылки на задачи GitHub у задач доски остаются, но больше не синхронизируются.
+pub async fn unlink(db: &Db, ctx: &BoardContext) -> MResult<()> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  let rows = db.read_all("delete from github_links where board_id = $1 returning board_id;", &[&ctx.board.id]).await?;
+  match rows.is_empty() {
+    true => Err(Box::new(NotLinked{})),
+    false => Ok(()),
+  }
+}
+
+async fn stored(db: &Db, board_id: &i64) -> MResult<Link> {
+  let rows = db.read_all(
+    "select repo, card_id, webhook_secret, linked_by, synced_at, token from github_links where board_id = $1;", &[board_id]
+  ).await?;
+  let row = rows.first().ok_or(NotLinked{})?;
+  Ok(Link {
+    repo: row.get(0),
+    card_id: row.get(1),
+    webhook_secret: row.get(2),
+    linked_by: row.get(3),
+    synced_at: row.get(4),
+    token: row.get(5),
+  })
+}
+
+/// Расшифровывает токен доступа связи.
+fn token(cfg: &GithubConfig, link: &Link) -> MResult<String> {
+  let key = cipher::key(&cfg.secret_key).ok_or(WrongSecretKey{})?;
+  let token = cipher::open(&key, &link.token).ok_or(WrongSecretKey{})?;
+  Ok(String::from_utf8(token)?)
+}
+
+/// Синхронизирует доску с репозиторием и возвращает число созданных и изменённых задач доски.
+pub async fn sync(db: &Db, cfg: &GithubConfig, board_id: &i64) -> MResult<usize> {
+  let link = stored(db, board_id).await?;
+  let token = token(cfg, &link)?;
+  let started = Utc::now().timestamp();
+  let (issues, rest_since) = Api::new(&cfg.api_url, &token).issues(&link.repo, link.synced_at).await?;
+  db.lock_board(board_id).await?;
+  let mut ctx = load_board(db, &link.linked_by, board_id).await?;
+  let (changed, complete) = apply(db, &mut ctx, &link, issues).await?;
+  // Если созданы не все новые задачи, в следующий раз задачи запрашиваются с того же времени.
+  let synced_at = match complete {
+    true => rest_since.or(Some(started)),
+    false => link.synced_at,
+  };
+  let query = "update github_links set synced_at = $1 where board_id = $2;";
+  match changed {
+    0 => {
+      db.write(query, &[&synced_at, board_id]).await?;
+      db.commit().await?;
+    },
+    _ => save_board(db, &mut ctx, EventKind::GithubSynced, vec![(query, vec![&synced_at, board_id])]).await?,
+  };
+  Ok(changed)
+}
+
+/// Применяет к доске вебхук GitHub.
+///
+/// Вебхуки о событиях, отличных от открытия, изменения, закрытия и повторного открытия задач связанного репозитория, принимаются и игнорируются.
+pub async fn receive(db: &Db, board_id: &i64, headers: &HeaderMap, body: &[u8]) -> MResult<()> {
+  let link = stored(db, board_id).await?;
+  if !github::verify(&link.webhook_secret, headers, body) { return Err(Box::new(WrongSignature{})); };
+  if headers.get("X-GitHub-Event").and_then(|h| h.to_str().ok()) != Some("issues") { return Ok(()); };
+  let payload: JsonValue = serde_json::from_slice(body)?;
+  let repo = payload["repository"]["full_name"].as_str().unwrap_or_default();
+  let action = payload["action"].as_str().unwrap_or_default();
+  if !repo.eq_ignore_ascii_case(&link.repo) || !["opened", "edited", "closed", "reopened"].contains(&action) {
+    return Ok(());
+  };
+  let issue = match github::issue(&payload["issue"]) {
+    Some(issue) => issue,
+    None => return Ok(()),
+  };
+  db.lock_board(board_id).await?;
+  let mut ctx = load_board(db, &link.linked_by, board_id).await?;
+  let (changed, _) = apply(db, &mut ctx, &link, vec![issue]).await?;
+  match changed {
+    0 => Ok(()),
+    _ => save_board(db, &mut ctx, EventKind::GithubSynced, vec![]).await,
+  }
+}
+
+/// Применяет задачи GitHub к доске, не записывая её. Возвращает число созданных и изменённых задач доски и признак того, что созданы все новые задачи.
+async fn apply(db: &Db, ctx: &mut BoardContext, link: &Link, issues: Vec<Issue>) -> MResult<(usize, bool)> {
+  let now = Utc::now().timestamp();
+  let mut changed: usize = 0;
+  let mut created: Vec<Issue> = Vec::new();
+  for issue in issues {
+    let task = ctx.board.cards.iter_mut()
+      .flat_map(|card| card.tasks.iter_mut())
+      .find(|task| task.github_issue.as_ref().is_some_and(|i| i.number == issue.number && i.repo.eq_ignore_ascii_case(&link.repo)));
+    match task {
+      Some(task) => if update(task, &issue, now) { changed += 1; },
+      // Задача могла измениться, пока запрашивались следующие страницы списка; закрытые задачи на доску не переносятся.
+      None => {
+        created.retain(|i| i.number != issue.number);
+        if !issue.closed { created.push(issue); };
+      },
+    };
+  };
+  let card = ctx.board.cards.get_card(&link.card_id)?;
+  let room = match card.wip_limit {
+    Some(limit) => (limit.get() as usize).saturating_sub(card.tasks.len()),
+    None => created.len(),
+  };
+  let complete = created.len() <= room;
+  created.truncate(room);
+  if created.is_empty() { return Ok((changed, complete)); };
+  let tasks_id_seq = ctx.board.id.to_string() + "_" + &link.card_id.to_string();
+  let min_task_id = card.tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+  let first_id = db.next_ids(&tasks_id_seq, min_task_id, created.len() as i64).await?;
+  let card = ctx.board.cards.get_mut_card(&link.card_id)?;
+  for (issue, id) in created.iter().zip(first_id..) {
+    let mut task = task(link, issue);
+    task.id = id;
+    task.author = link.linked_by;
+    task.stamp_created(now);
+    card.tasks.push(task);
+  };
+  card.updated_at = now;
+  Ok((changed + created.len(), complete))
+}
+
+/// Обновляет задачу доски по задаче GitHub. Возвращает false, если задача не изменилась.
+fn update(task: &mut Task, issue: &Issue, now: i64) -> bool {
+  let title = validation::title("задачи", &issue.title).unwrap_or_else(|_| format!("#{}", issue.number));
+  let linked = match task.github_issue.as_mut() {
+    Some(linked) => linked,
+    None => return false,
+  };
+  let reopened_or_closed = linked.closed != issue.closed;
+  if !reopened_or_closed && task.title == title && linked.url == issue.url { return false; };
+  if reopened_or_closed {
+    linked.closed = issue.closed;
+    task.exec = issue.closed;
+  };
+  linked.url = issue.url.clone();
+  task.title = title;
+  task.updated_at = now;
+  true
+}
+
+/// Собирает задачу доски из открытой задачи GitHub.
+fn task(link: &Link, issue: &Issue) -> Task {
+  let epoch = Utc.timestamp_opt(0, 0).unwrap();
+  Task {
+    id: 0,
+    author: 0,
+    title: validation::title("задачи", &issue.title).unwrap_or_else(|_| format!("#{}", issue.number)),
+    executors: vec![],
+    exec: false,
+    subtasks: vec![],
+    description: issue.body.clone(),
+    notes: String::new(),
+    priority: Priority::Normal,
+    tags: vec![],
+    lane_id: None,
+    sprint_id: None,
+    story_points: None,
+    depends_on: vec![],
+    links: vec![],
+    blocked: false,
+    timelines: Timelines { preferred_time: epoch, max_time: epoch, expected_time: 0 },
+    exec_propagation: None,
+    overdue: false,
+    due_soon: false,
+    github_issue: Some(GithubIssue { repo: link.repo.clone(), number: issue.number, url: issue.url.clone(), closed: false }),
+    created_at: 0,
+    updated_at: 0,
+    field_stamps: BTreeMap::new(),
+    assignments: vec![],
+    watchers: vec![],
+  }
+}
+
+/// Ставит в очередь передачу на GitHub изменений статуса выполнения задач связанных досок, собирая события каждой доски за окно `window` (см. `events::Coalescer`).
+pub async fn propagate(db: Db, window: Duration) {
+  let mut events = Coalescer::new(events::subscribe(), window);
+  while let Some(batch) = events.next().await {
+    // Статус выполнения задачи может измениться и вместе со статусом её подзадач. Задача, изменённая несколько раз за окно событий, передаётся один раз.
+    let mut tasks: Vec<(i64, i64)> = vec![];
+    for event in &batch {
+      match event.kind {
+        EventKind::TaskUpdated { card_id, task_id }
+        | EventKind::SubtaskCreated { card_id, task_id, .. }
+        | EventKind::SubtaskUpdated { card_id, task_id, .. }
+        | EventKind::SubtaskDeleted { card_id, task_id, .. } if !tasks.contains(&(card_id, task_id)) => tasks.push((card_id, task_id)),
+        _ => {},
+      };
+    };
+    let board_id = batch[0].board_id;
+    if tasks.is_empty() { continue; };
+    let linked = match db.read_all("select 1 from github_links where board_id = $1;", &[&board_id]).await {
+      Ok(rows) => !rows.is_empty(),
+      Err(e) => {
+        eprintln!("Не удалось проверить связь доски {} с GitHub: {}", board_id, e);
+        continue;
+      },
+    };
+    if !linked { continue; };
+    for (card_id, task_id) in tasks {
+      if let Err(e) = jobs::enqueue(&db, PUSH_JOB, board_id, &TaskRef { board_id, card_id, task_id }).await {
+        eprintln!("Не удалось поставить в очередь передачу на GitHub статуса задачи {} в карточке {} на доске {}: {}", task_id, card_id, board_id, e);
+      };
+    };
+  };
+}
+
+/// Передаёт на GitHub статус выполнения задачи доски.
+///
+/// Запрос к GitHub выполняется до блокировки доски, как и в `sync`: пока GitHub отвечает, доску можно изменять. Отметка о переданном статусе записывается, только если он не изменился за это время.
+async fn push(db: &Db, cfg: &GithubConfig, board_id: &i64, card_id: &i64, task_id: &i64) -> MResult<()> {
+  let link = match stored(db, board_id).await {
+    Ok(link) => link,
+    Err(e) if e.downcast_ref::<NotLinked>().is_some() => return Ok(()),
+    Err(e) => return Err(e),
+  };
+  let mut ctx = load_board(db, &link.linked_by, board_id).await?;
+  let (number, exec) = match unpushed(&mut ctx, &link, card_id, task_id) {
+    Some((linked, exec)) => (linked.number, exec),
+    None => return Ok(()),
+  };
+  let token = token(cfg, &link)?;
+  Api::new(&cfg.api_url, &token).set_closed(&link.repo, number, exec).await?;
+  db.lock_board(board_id).await?;
+  let mut ctx = load_board(db, &link.linked_by, board_id).await?;
+  match unpushed(&mut ctx, &link, card_id, task_id) {
+    Some((linked, current)) if linked.number == number && current == exec => linked.closed = exec,
+    _ => return Ok(()),
+  };
+  save_board(db, &mut ctx, EventKind::GithubSynced, vec![]).await
+}
+
+/// Возвращает связанную с GitHub задачу репозитория связи и её статус выполнения, если он ещё не передан на GitHub.
+fn unpushed<'a>(ctx: &'a mut BoardContext, link: &Link, card_id: &i64, task_id: &i64) -> Option<(&'a mut GithubIssue, bool)> {
+  let task = ctx.board.cards.get_mut_task(card_id, task_id).ok()?;
+  let exec = task.exec;
+  match task.github_issue.as_mut() {
+    Some(linked) if linked.closed != exec && linked.repo.eq_ignore_ascii_case(&link.repo) => Some((linked, exec)),
+    _ => None,
+  }
+}
+
+/// Регистрирует задания синхронизации с GitHub в очереди.
+///
+/// Периодическое задание ставит в очередь синхронизацию каждой связанной доски, а `propagate` - передачу статуса каждой изменённой задачи. Задания одной доски выполняются по одному, а задания, которым не ответил GitHub, повторяются (см. `jobs`).
+pub fn register(queue: &mut Queue, cfg: &GithubConfig) {
+  let period = Duration::from_secs(cfg.sync_period_secs.max(1));
+  queue.every(SYNC_ALL_JOB, period, |db| async move {
+    let boards: Vec<i64> = db.read_all("select board_id from github_links;", &[]).await?.iter().map(|row| row.get(0)).collect();
+    for board_id in &boards {
+      jobs::enqueue(&db, SYNC_JOB, *board_id, board_id).await?;
+    };
+    Ok(())
+  });
+  let sync_cfg = cfg.clone();
+  queue.handle(SYNC_JOB, move |db, board_id: i64| {
+    let cfg = sync_cfg.clone();
+    async move {
+      match transaction::scope(sync(&db, &cfg, &board_id)).await {
+        // Связь успели удалить.
+        Err(e) if e.downcast_ref::<NotLinked>().is_some() => Ok(()),
+        res => res.map(|_| ()),
+      }
+    }
+  });
+  let push_cfg = cfg.clone();
+  queue.handle(PUSH_JOB, move |db, task: TaskRef| {
+    let cfg = push_cfg.clone();
+    async move { transaction::scope(push(&db, &cfg, &task.board_id, &task.card_id, &task.task_id)).await }
+  });
+}
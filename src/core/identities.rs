@@ -0,0 +1,129 @@
+//! Отвечает за привязку пользователей к аккаунтам у внешних поставщиков входа (см. `sec::oidc`) и в каталоге пользователей (см. `sec::auth::Authenticator`).
+//!
+//! Аккаунт поставщика определяется парой из имени поставщика и постоянного идентификатора пользователя у него и привязывается не больше чем к одному пользователю. При первом входе через поставщика пользователь создаётся, если вход не начат для привязки к уже существующему пользователю. Пользователи каталога привязываются так же, под именем каталога.
+
+use chrono::Utc;
+use custom_error::custom_error;
+use tokio_postgres::error::SqlState;
+use uuid::Uuid;
+
+use crate::core::new_user_data;
+use crate::psql_handler::Db;
+use crate::sec::{auth::Identity, key_gen, policy};
+use crate::setup::AppConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongState{} = "Вход через поставщика не был начат или устарел. Начните вход заново."}
+custom_error!{pub IdentityTaken{} = "Аккаунт поставщика уже привязан к другому пользователю."}
+custom_error!{pub SignUpClosed{} = "Регистрация возможна только по ключам. Войдите в существующий аккаунт и привяжите к нему аккаунт поставщика."}
+
+/// Число секунд, в течение которых пользователь должен вернуться от поставщика.
+const STATE_TTL_SECS: i64 = 10 * 60;
+
+/// Начинает вход через поставщика и возвращает значение `state`, которое клиент должен вернуть вместе с кодом авторизации.
+///
+/// Если задан `link_to`, аккаунт поставщика по завершении входа привязывается к этому пользователю. Заодно удаляются устаревшие незавершённые входы.
+pub async fn begin(db: &Db, provider: &str, link_to: Option<i64>) -> MResult<String> {
+  let state = Uuid::new_v4().simple().to_string();
+  let now = Utc::now().timestamp();
+  let expires_at = now + STATE_TTL_SECS;
+  db.write_mul(vec![
+    ("delete from oauth_states where expires_at <= $1;", vec![&now]),
+    ("insert into oauth_states values ($1, $2, $3, $4);", vec![&state, &provider, &link_to, &expires_at]),
+  ]).await?;
+  Ok(state)
+}
+
+/// Завершает вход, начатый `begin`, и возвращает пользователя, к которому нужно привязать аккаунт поставщика.
+///
+/// Каждое значение `state` принимается только один раз. Если вход не был начат для данного поставщика или устарел, функция возвращает `WrongState`.
+pub async fn finish(db: &Db, provider: &str, state: &str) -> MResult<Option<i64>> {
+  let rows = db.read_all(
+    "delete from oauth_states where state = $1 and provider = $2 and expires_at > $3 returning user_id;",
+    &[&state, &provider, &Utc::now().timestamp()]
+  ).await?;
+  match rows.first() {
+    Some(row) => Ok(row.get(0)),
+    None => Err(Box::new(WrongState{})),
+  }
+}
+
+/// Возвращает пользователя, привязанного к аккаунту поставщика.
+///
+/// Если аккаунт ещё не привязан, он привязывается к `link_to`, а если тот не задан - к новому пользователю. Если регистрация возможна только по ключам (см. `cc_keys`), новые пользователи не создаются, и функция возвращает `SignUpClosed`.
+pub async fn sign_in(db: &Db, cfg: &AppConfig, provider: &str, identity: &Identity, link_to: Option<i64>)
+  -> MResult<i64>
+{
+  if let Some(user_id) = linked(db, provider, identity).await? {
+    return match link_to {
+      Some(link_to) if link_to != user_id => Err(Box::new(IdentityTaken{})),
+      _ => Ok(user_id),
+    };
+  };
+  if let Some(user_id) = link_to {
+    db.write(LINK, &[&provider, &identity.subject, &user_id]).await?;
+    return Ok(user_id);
+  };
+  if cfg.cc_key_required { return Err(Box::new(SignUpClosed{})); };
+  create(db, cfg, provider, identity).await
+}
+
+/// Возвращает пользователя, привязанного к пользователю каталога, и создаёт его при первом входе.
+///
+/// Каталог сам решает, кто может входить, поэтому пользователи создаются и тогда, когда регистрация возможна только по ключам.
+pub async fn provision(db: &Db, cfg: &AppConfig, directory: &str, identity: &Identity) -> MResult<i64> {
+  let user_id = linked(db, directory, identity).await?;
+  match user_id {
+    Some(user_id) => Ok(user_id),
+    None => create(db, cfg, directory, identity).await,
+  }
+}
+
+/// Запрос привязки аккаунта к пользователю.
+const LINK: &str = "insert into user_identities values ($1, $2, $3);";
+
+/// Возвращает пользователя, к которому привязан аккаунт.
+async fn linked(db: &Db, provider: &str, identity: &Identity) -> MResult<Option<i64>> {
+  let rows = db.read_all(
+    "select user_id from user_identities where provider = $1 and subject = $2;",
+    &[&provider, &identity.subject]
+  ).await?;
+  Ok(rows.first().map(|row| row.get(0)))
+}
+
+/// Создаёт пользователя, привязанного к аккаунту.
+///
+/// Новому пользователю назначается случайный пароль, поэтому войти в его аккаунт можно только через поставщика или каталог.
+async fn create(db: &Db, cfg: &AppConfig, provider: &str, identity: &Identity) -> MResult<i64> {
+  let (user_credentials, billing) = new_user_data(&key_gen::generate_strong(64)?, cfg)?;
+  let insert = "insert into users (id, login, shared_boards, user_creds, apd, display_name) values ($1, $2, '[]', $3::text::jsonb, $4, $5);";
+  for login in logins(cfg, provider, identity) {
+    let id: i64 = db.read("select nextval(pg_get_serial_sequence('users', 'id'));", &[]).await?.get(0);
+    let display_name = identity.display_name.as_ref().unwrap_or(&login);
+    let res = db.write_mul(vec![
+      (insert, vec![&id, &login, &user_credentials, &billing, display_name]),
+      (LINK, vec![&provider, &identity.subject, &id]),
+    ]).await;
+    match res {
+      // Логин занят - пробуем следующий.
+      Err(e) if e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code())
+        == Some(&SqlState::UNIQUE_VIOLATION) => {},
+      res => return res.map(|_| id),
+    };
+  };
+  Err(Box::new(IdentityTaken{}))
+}
+
+/// Возвращает логины, которые можно назначить новому пользователю, в порядке предпочтения.
+///
+/// Сначала предлагается логин или адрес электронной почты из профиля поставщика, затем он же с именем поставщика, и в последнюю очередь - имя поставщика с идентификатором пользователя у него. Логины, не удовлетворяющие требованиям конфигурации, пропускаются; последний вариант предлагается всегда, поскольку он уникален.
+fn logins(cfg: &AppConfig, provider: &str, identity: &Identity) -> Vec<String> {
+  let mut logins: Vec<String> = match &identity.login {
+    Some(login) => vec![login.clone(), format!("{}-{}", login, provider)],
+    None => vec![],
+  };
+  logins.retain(|login| policy::validate(&cfg.credentials_policy, login, true, None).is_ok());
+  logins.push(format!("{}-{}", provider, identity.subject));
+  logins
+}
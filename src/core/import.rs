@@ -0,0 +1,226 @@
+//! Отвечает за импорт задач из таблиц.
+//!
+//! Таблица передаётся в формате CSV: первая строка - заголовок с названиями колонок, каждая следующая - задача. Поддерживаются колонки:
+//!
+//! - `title` - название задачи (обязательная колонка);
+//! - `executors` - логины исполнителей через запятую или пробел, с `@` или без;
+//! - `deadline` - обязательный срок выполнения (`max_time`, см. `parse_deadline`);
+//! - `tags` - названия тегов из словаря доски через запятую.
+//!
+//! Разделителем колонок служит запятая, а если её нет в заголовке - точка с запятой, как в таблицах, сохранённых Excel с русскими региональными настройками. Значения с разделителями и переводами строк заключаются в двойные кавычки, а кавычки внутри них удваиваются.
+//!
+//! Импорт выполняется целиком или не выполняется вовсе: если хотя бы одна строка не прошла проверку, ни одна задача не создаётся, а ошибки возвращаются для каждой строки (`ImportFailed`).
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::core::events::EventKind;
+use crate::core::{check_wip_limit, save_board, validation};
+use crate::model::{BoardContext, Cards, Priority, Task, Timelines};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число задач в одной таблице.
+pub const MAX_IMPORT_ROWS: usize = 1000;
+
+/// Ошибка в строке таблицы.
+#[derive(Debug, Serialize)]
+pub struct RowError {
+  /// Номер строки таблицы, начиная с заголовка, который имеет номер 1.
+  pub row: usize,
+  pub error: String,
+}
+
+#[derive(Debug)]
+pub struct ImportFailed {
+  pub rows: Vec<RowError>,
+}
+
+impl std::fmt::Display for ImportFailed {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Таблица не прошла проверку.")
+  }
+}
+
+impl std::error::Error for ImportFailed {}
+
+/// Колонка таблицы.
+#[derive(Clone, Copy, PartialEq)]
+enum Column {
+  Title,
+  Executors,
+  Deadline,
+  Tags,
+}
+
+/// Создаёт в карточке задачи из таблицы в формате CSV и возвращает их идентификаторы в порядке строк.
+///
+/// Исполнителями можно назначить только участников доски, а теги - выбрать только из словаря доски. Если задачи не поместятся в ограничение `wip_limit` карточки, функция возвращает `WipLimitReached`.
+pub async fn csv(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, text: &str) -> MResult<Vec<i64>> {
+  ctx.board.cards.get_card(card_id)?;
+  let failed = |row: usize, error: &str| ImportFailed{ rows: vec![RowError { row, error: error.to_string() }] };
+  let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+  let header_line = text.lines().next().unwrap_or("");
+  let delimiter = match !header_line.contains(',') && header_line.contains(';') {
+    true => ';',
+    false => ',',
+  };
+  let records = parse(text, delimiter).map_err(|row| failed(row, "кавычки не закрыты."))?;
+  let mut records = records.into_iter()
+    .enumerate()
+    .map(|(i, cells)| (i + 1, cells))
+    .filter(|(_, cells)| cells.iter().any(|cell| !cell.trim().is_empty()));
+  let (_, header) = records.next().ok_or(failed(1, "нет заголовка."))?;
+  let mut columns = Vec::with_capacity(header.len());
+  for name in &header {
+    columns.push(match name.trim().to_lowercase().as_str() {
+      "title" => Column::Title,
+      "executors" => Column::Executors,
+      "deadline" => Column::Deadline,
+      "tags" => Column::Tags,
+      name => return Err(Box::new(failed(1, &format!("неизвестная колонка {}.", name)))),
+    });
+  };
+  if !columns.contains(&Column::Title) { return Err(Box::new(failed(1, "нет колонки title."))); };
+  let rows: Vec<(usize, Vec<String>)> = records.collect();
+  if rows.is_empty() { return Err(Box::new(failed(2, "нет задач."))); };
+  if rows.len() > MAX_IMPORT_ROWS {
+    return Err(Box::new(failed(MAX_IMPORT_ROWS + 2, &format!("задач больше {}.", MAX_IMPORT_ROWS))));
+  };
+  let mut members: HashMap<String, i64> = HashMap::new();
+  for id in &ctx.board.shared_with {
+    members.insert(db.user(id).await?.login.to_lowercase(), *id);
+  };
+  let tags: HashMap<String, i64> = ctx.board.tags.iter().map(|tag| (tag.title.to_lowercase(), tag.id)).collect();
+  let mut tasks = Vec::with_capacity(rows.len());
+  let mut errors = Vec::new();
+  for (row, cells) in &rows {
+    match task(&columns, cells, &members, &tags) {
+      Ok(task) => tasks.push(task),
+      Err(error) => errors.push(RowError { row: *row, error }),
+    };
+  };
+  if !errors.is_empty() { return Err(Box::new(ImportFailed{ rows: errors })); };
+  let card = ctx.board.cards.get_card(card_id)?;
+  check_wip_limit(card, card.tasks.len() + tasks.len())?;
+  let tasks_id_seq = ctx.board.id.to_string() + "_" + &card_id.to_string();
+  let min_task_id = card.tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+  let first_id = db.next_ids(&tasks_id_seq, min_task_id, tasks.len() as i64).await?;
+  let task_ids: Vec<i64> = (first_id..first_id + tasks.len() as i64).collect();
+  let now = Utc::now().timestamp();
+  let card = ctx.board.cards.get_mut_card(card_id)?;
+  for (mut task, id) in tasks.into_iter().zip(&task_ids) {
+    task.id = *id;
+    task.author = ctx.user_id;
+    task.stamp_created(now);
+    card.tasks.push(task);
+  };
+  save_board(db, ctx, EventKind::TasksImported { card_id: *card_id, task_ids: task_ids.clone() }, vec![]).await?;
+  Ok(task_ids)
+}
+
+/// Собирает задачу из строки таблицы. Возвращает описание первой найденной ошибки.
+fn task(columns: &[Column], cells: &[String], members: &HashMap<String, i64>, tags: &HashMap<String, i64>)
+  -> Result<Task, String>
+{
+  if cells.len() > columns.len() { return Err(format!("колонок больше, чем в заголовке ({}).", columns.len())); };
+  let epoch = Utc.timestamp_opt(0, 0).unwrap();
+  let mut task = Task {
+    id: 0,
+    author: 0,
+    title: String::new(),
+    executors: vec![],
+    exec: false,
+    subtasks: vec![],
+    description: String::new(),
+    notes: String::new(),
+    priority: Priority::Normal,
+    tags: vec![],
+    lane_id: None,
+    sprint_id: None,
+    story_points: None,
+    depends_on: vec![],
+    links: vec![],
+    blocked: false,
+    timelines: Timelines { preferred_time: epoch, max_time: epoch, expected_time: 0 },
+    exec_propagation: None,
+    overdue: false,
+    due_soon: false,
+    github_issue: None,
+    created_at: 0,
+    updated_at: 0,
+    field_stamps: BTreeMap::new(),
+    assignments: vec![],
+    watchers: vec![],
+  };
+  for (column, cell) in columns.iter().zip(cells) {
+    let cell = cell.trim();
+    match column {
+      Column::Title => task.title = validation::title("задачи", cell).map_err(|e| e.to_string())?,
+      Column::Executors => for login in cell.split(|c: char| c == ',' || c.is_whitespace()).filter(|l| !l.is_empty()) {
+        let login = login.strip_prefix('@').unwrap_or(login);
+        let id = members.get(&login.to_lowercase()).ok_or(format!("{} не участвует в доске.", login))?;
+        if !task.executors.contains(id) { task.executors.push(*id); };
+      },
+      Column::Deadline => if !cell.is_empty() {
+        task.timelines.max_time = parse_deadline(cell).ok_or(format!("не удалось разобрать срок {}.", cell))?;
+      },
+      Column::Tags => for title in cell.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let id = tags.get(&title.to_lowercase()).ok_or(format!("тега {} нет на доске.", title))?;
+        if !task.tags.contains(id) { task.tags.push(*id); };
+      },
+    };
+  };
+  if task.title.is_empty() { return Err("не задано название задачи.".to_string()); };
+  Ok(task)
+}
+
+/// Разбирает срок выполнения в UTC.
+///
+/// Принимаются RFC 3339 (`2024-05-01T18:00:00+03:00`), `2024-05-01 18:00`, `01.05.2024 18:00` и UNIX-время в секундах. Срок, заданный одной датой (`2024-05-01` или `01.05.2024`), означает конец этого дня.
+fn parse_deadline(value: &str) -> Option<DateTime<Utc>> {
+  if let Ok(deadline) = DateTime::parse_from_rfc3339(value) { return Some(deadline.with_timezone(&Utc)); };
+  if let Ok(timestamp) = value.parse::<i64>() { return Utc.timestamp_opt(timestamp, 0).single(); };
+  for format in ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%d.%m.%Y %H:%M", "%d.%m.%Y %H:%M:%S"] {
+    if let Ok(deadline) = NaiveDateTime::parse_from_str(value, format) { return Some(deadline.and_utc()); };
+  };
+  for format in ["%Y-%m-%d", "%d.%m.%Y"] {
+    if let Ok(date) = NaiveDate::parse_from_str(value, format) { return Some(date.and_hms_opt(23, 59, 59)?.and_utc()); };
+  };
+  None
+}
+
+/// Разбирает CSV на строки и ячейки. Если кавычки не закрыты, возвращает номер строки таблицы, в которой они открыты.
+fn parse(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, usize> {
+  let mut records = vec![];
+  let mut record = vec![];
+  let mut cell = String::new();
+  let mut quoted = false;
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (quoted, c) {
+      (true, '"') if chars.peek() == Some(&'"') => {
+        chars.next();
+        cell.push('"');
+      },
+      (true, '"') => quoted = false,
+      (true, c) => cell.push(c),
+      (false, '"') if cell.is_empty() => quoted = true,
+      (false, c) if c == delimiter => record.push(std::mem::take(&mut cell)),
+      (false, '\r') if chars.peek() == Some(&'\n') => {},
+      (false, '\n') => {
+        record.push(std::mem::take(&mut cell));
+        records.push(std::mem::take(&mut record));
+      },
+      (false, c) => cell.push(c),
+    };
+  };
+  if quoted { return Err(records.len() + 1); };
+  if !cell.is_empty() || !record.is_empty() {
+    record.push(cell);
+    records.push(record);
+  };
+  Ok(records)
+}
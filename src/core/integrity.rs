@@ -0,0 +1,214 @@
+//! Отвечает за проверку и исправление содержимого досок.
+//!
+//! Содержимое доски хранится в JSON-колонках таблицы `boards`, поэтому данные, записанные старыми версиями сервера или исправленные вручную, могут не соответствовать модели. Такую доску нельзя загрузить, и запросы к ней завершаются ошибкой. Проверка заранее находит такие доски, а в остальных исправляет то, что можно исправить без потери данных:
+//!
+//! - повторяющиеся идентификаторы карточек, задач и подзадач - повторы получают новые идентификаторы;
+//! - исполнителей, у которых нет доступа к доске;
+//! - ссылки на теги, дорожки и спринты, которых нет на доске, и зависимости от несуществующих задач.
+//!
+//! Кроме того, проверка удаляет строки других таблиц, которые ссылаются на несуществующие доски и пользователей (см. `REFERENCES`). Внешних ключей у таблиц нет, поэтому такие строки остаются, если доску или пользователя удалили из базы данных вручную.
+//!
+//! Проверка запускается администратором (`POST /admin/revalidate-boards`) и периодически фоновой задачей.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use tokio_postgres::types::ToSql;
+
+use crate::core::events::{self, EventKind};
+use crate::core::{board_from_row, delta, dependencies};
+use crate::model::{Board, Card, TaskPath};
+use crate::psql_handler::Db;
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Доска, которую не удалось загрузить.
+#[derive(Serialize)]
+pub struct DamagedBoard {
+  pub board_id: i64,
+  /// Описание ошибки с названием повреждённой колонки.
+  pub reason: String,
+}
+
+/// Итог проверки досок.
+#[derive(Serialize, Default)]
+pub struct Report {
+  /// Число проверенных досок.
+  pub checked: usize,
+  /// Исправленные доски.
+  pub repaired: Vec<i64>,
+  /// Доски, которые не удалось загрузить. Они остаются нетронутыми.
+  pub corrupt: Vec<DamagedBoard>,
+  /// Удалённые строки, ссылавшиеся на несуществующие доски и пользователей.
+  pub orphans: Vec<OrphanRows>,
+}
+
+/// Строки таблицы, которые ссылались на несуществующую доску или пользователя и были удалены.
+#[derive(Serialize)]
+pub struct OrphanRows {
+  pub table: &'static str,
+  pub column: &'static str,
+  pub rows: i64,
+}
+
+/// Ссылки на доски и пользователей: таблица, колонка и таблица, на идентификатор в которой ссылается колонка.
+///
+/// Перечислены только строки, которые без доски или пользователя не имеют смысла; для досок это те же таблицы, которые очищает удаление доски (см. `Storage::delete_board`).
+const REFERENCES: [(&str, &str, &str); 19] = [
+  ("user_board_prefs", "board_id", "boards"), ("task_history", "board_id", "boards"), ("undo_log", "board_id", "boards"),
+  ("notifications", "board_id", "boards"), ("board_views", "board_id", "boards"), ("github_links", "board_id", "boards"),
+  ("board_reports", "board_id", "boards"), ("org_boards", "board_id", "boards"), ("org_board_guests", "board_id", "boards"),
+  ("board_retention", "board_id", "boards"), ("board_deltas", "board_id", "boards"),
+  ("user_board_prefs", "user_id", "users"), ("board_views", "user_id", "users"), ("notifications", "user_id", "users"),
+  ("notification_prefs", "user_id", "users"), ("user_identities", "user_id", "users"), ("org_members", "user_id", "users"),
+  ("org_board_guests", "user_id", "users"), ("guest_accounts", "user_id", "users"),
+];
+
+/// Проверяет все доски и исправляет найденные ошибки.
+///
+/// Доска, которую в это же время изменил запрос пользователя, не исправляется: она будет исправлена при следующей проверке.
+pub async fn scan(db: &Db) -> MResult<Report> {
+  let ids = db.read_all("select id from boards order by id;", &[]).await?;
+  let mut report = Report::default();
+  for id in ids.iter().map(|row| row.get::<_, i64>(0)) {
+    // Доски загружаются по одной, чтобы не держать в памяти все доски сразу.
+    let row = match db.board(&id).await? {
+      Some(row) => row,
+      None => continue,
+    };
+    report.checked += 1;
+    let mut board = match board_from_row(&row) {
+      Ok(board) => board,
+      Err(e) => {
+        report.corrupt.push(DamagedBoard { board_id: id, reason: e.to_string() });
+        continue;
+      },
+    };
+    if !repair(db, &mut board).await? { continue; };
+    let cards = serde_json::to_string(&board.cards)?;
+    let record = delta::Record::build(board.id, board.revision + 1, &[("cards", &row.cards, &cards)])?;
+    let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![(
+      "update boards set cards = $1::text::jsonb, revision = revision + 1 where id = $2 and revision = $3;",
+      vec![&cards, &board.id, &board.revision]
+    )];
+    queries.extend(record.queries());
+    let written = db.write_mul_if(queries).await?;
+    if written {
+      report.repaired.push(board.id);
+      events::publish(board.id, None, board.revision + 1, EventKind::BoardUpdated);
+    };
+  };
+  report.orphans = remove_orphans(db).await?;
+  Ok(report)
+}
+
+/// Удаляет строки, ссылающиеся на несуществующие доски и пользователей (см. `REFERENCES`), и возвращает их число по таблицам.
+async fn remove_orphans(db: &Db) -> MResult<Vec<OrphanRows>> {
+  let mut orphans = Vec::new();
+  for (table, column, target) in REFERENCES {
+    let removed = db.read(&format!(
+      "with removed as (delete from {0} where {1} is not null and not exists (select 1 from {2} where {2}.id = {0}.{1}) returning 1) \
+         select count(*) from removed;",
+      table, column, target
+    ), &[]).await?;
+    let rows: i64 = removed.get(0);
+    if rows > 0 { orphans.push(OrphanRows { table, column, rows }); };
+  };
+  Ok(orphans)
+}
+
+/// Исправляет содержимое доски. Возвращает `true`, если что-то было исправлено.
+async fn repair(db: &Db, board: &mut Board) -> MResult<bool> {
+  let mut repaired = false;
+  let shared_with: HashSet<i64> = board.shared_with.iter().copied().collect();
+  let tags: HashSet<i64> = board.tags.iter().map(|tag| tag.id).collect();
+  let lanes: HashSet<i64> = board.lanes.iter().map(|lane| lane.id).collect();
+  let sprints: HashSet<i64> = board.sprints.iter().map(|sprint| sprint.id).collect();
+  let mut retain = |ids: &mut Vec<i64>, valid: &HashSet<i64>| {
+    let len = ids.len();
+    ids.retain(|id| valid.contains(id));
+    repaired |= ids.len() != len;
+  };
+  for card in &mut board.cards {
+    for task in &mut card.tasks {
+      retain(&mut task.executors, &shared_with);
+      retain(&mut task.watchers, &shared_with);
+      retain(&mut task.tags, &tags);
+      for subtask in &mut task.subtasks {
+        retain(&mut subtask.executors, &shared_with);
+        retain(&mut subtask.tags, &tags);
+      };
+    };
+  };
+  for task in board.cards.iter_mut().flat_map(|card| card.tasks.iter_mut()) {
+    if task.lane_id.is_some_and(|id| !lanes.contains(&id)) {
+      task.lane_id = None;
+      repaired = true;
+    };
+    if task.sprint_id.is_some_and(|id| !sprints.contains(&id)) {
+      task.sprint_id = None;
+      repaired = true;
+    };
+  };
+  repaired |= reassign_duplicate_ids(db, board).await?;
+  let existing: HashSet<TaskPath> = board.cards.iter()
+    .flat_map(|card| card.tasks.iter().map(|task| TaskPath { card_id: card.id, task_id: task.id }))
+    .collect();
+  let count = |cards: &[Card]| cards.iter().flat_map(|card| &card.tasks).map(|task| task.depends_on.len()).sum::<usize>();
+  let dependencies = count(&board.cards);
+  dependencies::forget(&mut board.cards, |path| !existing.contains(path));
+  repaired |= dependencies != count(&board.cards);
+  Ok(repaired)
+}
+
+/// Назначает новые идентификаторы карточкам, задачам и подзадачам, идентификатор которых уже занят. Возвращает `true`, если такие нашлись.
+///
+/// Первая сущность с данным идентификатором сохраняет его, поэтому ссылки на неё - например, зависимости задач - остаются верными.
+async fn reassign_duplicate_ids(db: &Db, board: &mut Board) -> MResult<bool> {
+  let mut repaired = false;
+  let cards_id_seq = board.id.to_string();
+  let mut seen = HashSet::new();
+  for i in 0..board.cards.len() {
+    if !seen.insert(board.cards[i].id) {
+      let min = board.cards.iter().map(|card| card.id).max().unwrap_or(0) + 1;
+      board.cards[i].id = db.next_id(&cards_id_seq, min).await?;
+      repaired = true;
+    };
+    let card = &mut board.cards[i];
+    let tasks_id_seq = format!("{}_{}", cards_id_seq, card.id);
+    let mut seen = HashSet::new();
+    for j in 0..card.tasks.len() {
+      if !seen.insert(card.tasks[j].id) {
+        let min = card.tasks.iter().map(|task| task.id).max().unwrap_or(0) + 1;
+        card.tasks[j].id = db.next_id(&tasks_id_seq, min).await?;
+        repaired = true;
+      };
+      let task = &mut card.tasks[j];
+      let subtasks_id_seq = format!("{}_{}", tasks_id_seq, task.id);
+      let mut seen = HashSet::new();
+      for k in 0..task.subtasks.len() {
+        if !seen.insert(task.subtasks[k].id) {
+          let min = task.subtasks.iter().map(|subtask| subtask.id).max().unwrap_or(0) + 1;
+          task.subtasks[k].id = db.next_id(&subtasks_id_seq, min).await?;
+          repaired = true;
+        };
+      };
+    };
+  };
+  Ok(repaired)
+}
+
+/// Периодическое задание (см. `jobs`): запускает `scan` и записывает в журнал сервера исправленные и повреждённые доски.
+pub async fn job(db: Db) -> MResult<()> {
+  let report = scan(&db).await?;
+  if !report.repaired.is_empty() {
+    println!("Исправлены доски: {:?}.", report.repaired);
+  };
+  for board in &report.corrupt {
+    eprintln!("Доска {} повреждена: {}", board.board_id, board.reason);
+  };
+  for orphans in &report.orphans {
+    println!("Удалены строки {} ({}) без связанной записи: {}.", orphans.table, orphans.column, orphans.rows);
+  };
+  Ok(())
+}
@@ -0,0 +1,358 @@
+//! Отвечает за очередь фоновых заданий.
+//!
+//! Задания хранятся в таблице `jobs`, поэтому переживают перезапуск сервера, и каждое задание в каждый момент выполняет только один обработчик, даже если с базой данных работают несколько серверов. Задание выполняется хотя бы один раз: если сервер остановился, не успев отметить выполненное задание, оно выполнится повторно, поэтому обработчики должны быть идемпотентны. Задание бывает разовым (`enqueue`) или периодическим (`Queue::every`): разовое удаляется после выполнения, а периодическое снова ставится в очередь через свой период.
+//!
+//! Задания выполняют обработчики, которые сервер запускает в `Queue::run`. Каждое задание относится к сегменту, который определяется ключом задания (например, идентификатором доски), и у каждого сегмента один обработчик. Разовые задания одного ключа выполняются по одному в порядке постановки в очередь на всех серверах: пока более раннее задание ключа ждёт выполнения или повтора, следующие не берутся; невыполненное задание (см. ниже) очередь ключа не задерживает. Обработчик берёт только задания, виды которых зарегистрированы на этом сервере, поэтому задания отключённых в конфигурации функций ждут в очереди.
+//!
+//! Взятое задание занято обработчиком на `visibility_timeout_secs`, и, пока оно выполняется, срок продлевается. Если сервер остановился, не выполнив задание, по истечении срока задание берёт другой обработчик. Задание, завершившееся ошибкой, повторяется с нарастающей задержкой; после `max_attempts` попыток разовое задание считается невыполненным и остаётся в очереди с последней ошибкой, пока администратор не повторит его (`retry`), а периодическое просто ждёт следующего периода.
+
+use chrono::Utc;
+use custom_error::custom_error;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_postgres::error::SqlState;
+use uuid::Uuid;
+
+use crate::psql_handler::Db;
+use crate::setup::JobsConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NoSuchJob{} = "Невыполненное задание не найдено."}
+
+/// Обработчик заданий одного вида. Ошибка приводится к строке, чтобы её можно было записать в задание.
+type Handler = Arc<dyn Fn(Db, String) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// Ошибка задания, обработчик которого не завершился в срок.
+const EXPIRED: &str = "Обработчик не завершил задание в срок.";
+
+/// Наибольшее число удвоений задержки повторной попытки.
+const MAX_BACKOFF_DOUBLINGS: i64 = 10;
+
+/// Число невыполненных заданий в сводке очереди.
+const FAILED_SHOWN: i64 = 20;
+
+/// Будит обработчиков, когда задание ставится в очередь.
+static ENQUEUED: Notify = Notify::const_new();
+
+/// Очередь с зарегистрированными видами заданий.
+pub struct Queue {
+  cfg: JobsConfig,
+  handlers: HashMap<&'static str, Handler>,
+  /// Периодические задания и их периоды в секундах.
+  periodic: Vec<(&'static str, i64)>,
+}
+
+/// Задание, взятое обработчиком.
+struct Claimed {
+  id: i64,
+  kind: String,
+  payload: String,
+  attempts: i64,
+  period_secs: i64,
+  lease: String,
+}
+
+/// Число заданий одного вида в очереди.
+#[derive(Default, Serialize)]
+pub struct KindStats {
+  /// Задания, ожидающие выполнения.
+  pub queued: i64,
+  /// Задания, которые сейчас выполняются.
+  pub running: i64,
+  /// Невыполненные задания.
+  pub failed: i64,
+}
+
+/// Невыполненное задание.
+#[derive(Serialize)]
+pub struct FailedJob {
+  pub id: i64,
+  pub kind: String,
+  pub payload: JsonValue,
+  pub attempts: i64,
+  pub failed_at: i64,
+  pub error: String,
+}
+
+/// Сводка очереди заданий.
+#[derive(Serialize)]
+pub struct JobsSnapshot {
+  pub workers: u32,
+  pub kinds: BTreeMap<String, KindStats>,
+  /// Последние невыполненные задания.
+  pub failed: Vec<FailedJob>,
+}
+
+impl Queue {
+  pub fn new(cfg: &JobsConfig) -> Queue {
+    Queue { cfg: cfg.clone(), handlers: HashMap::new(), periodic: vec![] }
+  }
+
+  /// Регистрирует обработчик заданий вида `kind` с данными типа `T`.
+  ///
+  /// Данные, которые не удалось разобрать, считаются ошибкой задания.
+  pub fn handle<T, F, Fut>(&mut self, kind: &'static str, f: F)
+  where
+    T: DeserializeOwned + Send + 'static,
+    F: Fn(Db, T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = MResult<()>> + Send + 'static,
+  {
+    let f = Arc::new(f);
+    self.handlers.insert(kind, Arc::new(move |db: Db, payload: String| {
+      let f = f.clone();
+      Box::pin(async move {
+        let payload: T = serde_json::from_str(&payload).map_err(|e| e.to_string())?;
+        f(db, payload).await.map_err(|e| e.to_string())
+      })
+    }));
+  }
+
+  /// Регистрирует периодическое задание `kind`, которое выполняется раз в `period`. Первый раз оно выполняется через `period` после того, как впервые попадёт в очередь.
+  pub fn every<F, Fut>(&mut self, kind: &'static str, period: Duration, f: F)
+  where
+    F: Fn(Db) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = MResult<()>> + Send + 'static,
+  {
+    self.periodic.push((kind, period.as_secs().max(1) as i64));
+    self.handle(kind, move |db, _: JsonValue| f(db));
+  }
+
+  /// Ставит периодические задания в очередь и запускает обработчиков.
+  ///
+  /// Пока таблица заданий не создана (см. `db_setup`), постановка периодических заданий повторяется, а обработчики не запускаются.
+  pub async fn run(self, db: Db) {
+    let poll = Duration::from_millis(self.cfg.poll_ms.max(1));
+    let mut reported = false;
+    loop {
+      // Ошибка приводится к строке, чтобы её не пришлось держать во время ожидания. Отсутствие таблицы ошибкой не считается.
+      let failed = match schedule(&db, &self.periodic).await {
+        Ok(()) => break,
+        Err(e) if e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code()) == Some(&SqlState::UNDEFINED_TABLE) => None,
+        Err(e) => Some(e.to_string()),
+      };
+      match failed {
+        Some(e) if !reported => {
+          eprintln!("Не удалось поставить периодические задания в очередь: {}", e);
+          reported = true;
+        },
+        _ => {},
+      };
+      tokio::time::sleep(poll).await;
+    };
+    let kinds: Vec<String> = self.handlers.keys().map(|kind| kind.to_string()).collect();
+    let handlers = Arc::new(self.handlers);
+    let workers = self.cfg.workers.max(1);
+    for shard in 0..workers {
+      let worker = Worker { db: db.clone(), cfg: self.cfg.clone(), handlers: handlers.clone(), kinds: kinds.clone(), shard, workers };
+      tokio::spawn(worker.run());
+    };
+  }
+}
+
+/// Обработчик одного сегмента очереди.
+struct Worker {
+  db: Db,
+  cfg: JobsConfig,
+  handlers: Arc<HashMap<&'static str, Handler>>,
+  kinds: Vec<String>,
+  shard: u32,
+  workers: u32,
+}
+
+impl Worker {
+  async fn run(self) {
+    let poll = Duration::from_millis(self.cfg.poll_ms.max(1));
+    loop {
+      let claimed = match self.claim().await {
+        Ok(claimed) => claimed,
+        Err(e) => {
+          eprintln!("Не удалось получить задание из очереди: {}", e);
+          None
+        },
+      };
+      match claimed {
+        Some(job) => self.execute(job).await,
+        None => tokio::select! {
+          _ = tokio::time::sleep(poll) => {},
+          _ = ENQUEUED.notified() => {},
+        },
+      };
+    }
+  }
+
+  /// Берёт самое раннее из причитающихся заданий сегмента.
+  ///
+  /// Перед этим задания сегмента, обработчики которых не завершили в срок последнюю из допустимых попыток, считаются невыполненными, а периодические - откладываются до следующего периода.
+  async fn claim(&self) -> MResult<Option<Claimed>> {
+    let now = Utc::now().timestamp();
+    let (shard, workers, max_attempts) = (self.shard as i64, self.workers as i64, self.cfg.max_attempts as i64);
+    let timeout = self.cfg.visibility_timeout_secs as i64;
+    let lease = Uuid::new_v4().simple().to_string();
+    self.db.write_mul(vec![
+      (
+        "update jobs set failed_at = $4, locked_until = 0, lease = null, last_error = $5 \
+         where mod(abs(shard_key), $2) = $1 and period_secs = 0 and failed_at = 0 and locked_until <> 0 and locked_until <= $4 and attempts >= $3;",
+        vec![&shard, &workers, &max_attempts, &now, &EXPIRED]
+      ),
+      (
+        "update jobs set attempts = 0, locked_until = 0, lease = null, last_error = $5, run_at = $4 + period_secs \
+         where mod(abs(shard_key), $2) = $1 and period_secs > 0 and locked_until <> 0 and locked_until <= $4 and attempts >= $3;",
+        vec![&shard, &workers, &max_attempts, &now, &EXPIRED]
+      ),
+    ]).await?;
+    let rows = self.db.read_all(
+      "update jobs set attempts = attempts + 1, locked_until = $5, lease = $6 where id = (\
+         select id from jobs \
+         where mod(abs(shard_key), $2) = $1 and kind = any($3) and failed_at = 0 and run_at <= $4 and locked_until <= $4 \
+           and (period_secs > 0 or not exists (\
+             select 1 from jobs earlier where earlier.shard_key = jobs.shard_key and earlier.period_secs = 0 and earlier.failed_at = 0 and earlier.id < jobs.id\
+           )) \
+         order by run_at, id limit 1 for update skip locked\
+       ) returning id, kind, payload, attempts, period_secs;",
+      &[&shard, &workers, &self.kinds, &now, &(now + timeout), &lease]
+    ).await?;
+    Ok(rows.first().map(|row| Claimed {
+      id: row.get(0),
+      kind: row.get(1),
+      payload: row.get(2),
+      attempts: row.get(3),
+      period_secs: row.get(4),
+      lease: lease.clone(),
+    }))
+  }
+
+  /// Выполняет задание, продлевая срок, на который оно занято, и записывает результат.
+  async fn execute(&self, job: Claimed) {
+    let handler = match self.handlers.get(job.kind.as_str()) {
+      Some(handler) => handler.clone(),
+      None => return,
+    };
+    // Задание выполняется в отдельной задаче tokio, чтобы паника обработчика не остановила сегмент.
+    let mut task = tokio::spawn(handler(self.db.clone(), job.payload.clone()));
+    let extend_every = Duration::from_secs((self.cfg.visibility_timeout_secs / 2).max(1));
+    let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + extend_every, extend_every);
+    let outcome = loop {
+      tokio::select! {
+        outcome = &mut task => break outcome.unwrap_or_else(|e| Err(format!("Обработчик задания завершился аварийно: {}", e))),
+        _ = heartbeat.tick() => {
+          let until = Utc::now().timestamp() + self.cfg.visibility_timeout_secs as i64;
+          if let Err(e) = self.db.write("update jobs set locked_until = $3 where id = $1 and lease = $2;", &[&job.id, &job.lease, &until]).await {
+            eprintln!("Не удалось продлить задание {} ({}): {}", job.id, job.kind, e);
+          };
+        },
+      };
+    };
+    if let Err(e) = self.finish(&job, outcome).await {
+      eprintln!("Не удалось записать результат задания {} ({}): {}", job.id, job.kind, e);
+    };
+  }
+
+  /// Удаляет выполненное задание или планирует его следующее выполнение.
+  ///
+  /// Если задание за это время взял другой обработчик, результат не записывается.
+  async fn finish(&self, job: &Claimed, outcome: Result<(), String>) -> MResult<()> {
+    let now = Utc::now().timestamp();
+    let error = match outcome {
+      Ok(()) if job.period_secs == 0 => {
+        return self.db.write("delete from jobs where id = $1 and lease = $2;", &[&job.id, &job.lease]).await;
+      },
+      Ok(()) => None,
+      Err(e) => {
+        eprintln!("Задание {} ({}) завершилось ошибкой, попытка {}: {}", job.id, job.kind, job.attempts, e);
+        Some(e)
+      },
+    };
+    // Периодическое задание повторяется не позже, чем наступил бы его следующий период.
+    let delay = match (self.cfg.retry_delay_secs as i64) << (job.attempts - 1).clamp(0, MAX_BACKOFF_DOUBLINGS) {
+      delay if job.period_secs > 0 => delay.min(job.period_secs),
+      delay => delay,
+    };
+    let (attempts, run_at, failed_at) = match (&error, job.period_secs) {
+      (None, period) => (0, now + period, 0),
+      (Some(_), _) if job.attempts < self.cfg.max_attempts as i64 => (job.attempts, now + delay, 0),
+      (Some(_), 0) => (job.attempts, now, now),
+      (Some(_), period) => (0, now + period, 0),
+    };
+    self.db.write(
+      "update jobs set attempts = $3, run_at = $4, failed_at = $5, last_error = $6, locked_until = 0, lease = null where id = $1 and lease = $2;",
+      &[&job.id, &job.lease, &attempts, &run_at, &failed_at, &error]
+    ).await
+  }
+}
+
+/// Ставит периодические задания в очередь, если их там ещё нет, и обновляет их периоды.
+async fn schedule(db: &Db, periodic: &[(&'static str, i64)]) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  for (kind, period) in periodic {
+    db.write(
+      "insert into jobs (kind, key, shard_key, payload, period_secs, run_at, created_at) values ($1, $1, $2, 'null', $3, $4, $5) \
+       on conflict (key) do update set period_secs = excluded.period_secs, run_at = least(jobs.run_at, excluded.run_at);",
+      &[kind, &shard_key(kind), period, &(now + period), &now]
+    ).await?;
+  };
+  Ok(())
+}
+
+/// Возвращает ключ сегмента периодического задания.
+fn shard_key(kind: &str) -> i64 {
+  kind.bytes().fold(0i64, |key, byte| key.wrapping_mul(31).wrapping_add(byte as i64)) & i64::MAX
+}
+
+/// Ставит в очередь разовое задание вида `kind` с данными `payload`.
+///
+/// Задания с одинаковым ключом `shard_key` выполняются по одному в порядке постановки в очередь: следующее задание ключа ждёт, пока более раннее не будет выполнено или не станет невыполненным после всех попыток. Задание, поставленное в очередь в транзакции, становится доступно обработчикам после её фиксации.
+pub async fn enqueue<T: Serialize>(db: &Db, kind: &str, shard_key: i64, payload: &T) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  db.write(
+    "insert into jobs (kind, shard_key, payload, run_at, created_at) values ($1, $2, $3, $4, $4);",
+    &[&kind, &shard_key, &serde_json::to_string(payload)?, &now]
+  ).await?;
+  ENQUEUED.notify_waiters();
+  Ok(())
+}
+
+/// Снова ставит в очередь невыполненное задание, начиная отсчёт попыток заново.
+pub async fn retry(db: &Db, id: &i64) -> MResult<()> {
+  let rows = db.read_all(
+    "update jobs set failed_at = 0, attempts = 0, run_at = $2 where id = $1 and failed_at <> 0 returning id;",
+    &[id, &Utc::now().timestamp()]
+  ).await?;
+  if rows.is_empty() { return Err(Box::new(NoSuchJob{})); };
+  ENQUEUED.notify_waiters();
+  Ok(())
+}
+
+/// Возвращает число заданий каждого вида и последние невыполненные задания.
+pub async fn snapshot(db: &Db, cfg: &JobsConfig) -> MResult<JobsSnapshot> {
+  let now = Utc::now().timestamp();
+  let rows = db.read_all(
+    "select kind, \
+       count(*) filter (where failed_at = 0 and locked_until <= $1), \
+       count(*) filter (where failed_at = 0 and locked_until > $1), \
+       count(*) filter (where failed_at <> 0) \
+     from jobs group by kind;",
+    &[&now]
+  ).await?;
+  let kinds = rows.iter().map(|row| (row.get(0), KindStats { queued: row.get(1), running: row.get(2), failed: row.get(3) })).collect();
+  let rows = db.read_all(
+    "select id, kind, payload, attempts, failed_at, coalesce(last_error, '') from jobs where failed_at <> 0 order by failed_at desc, id desc limit $1;",
+    &[&FAILED_SHOWN]
+  ).await?;
+  let failed = rows.iter().map(|row| FailedJob {
+    id: row.get(0),
+    kind: row.get(1),
+    payload: serde_json::from_str(row.get(2)).unwrap_or(JsonValue::Null),
+    attempts: row.get(3),
+    failed_at: row.get(4),
+    error: row.get(5),
+  }).collect();
+  Ok(JobsSnapshot { workers: cfg.workers.max(1), kinds, failed })
+}
@@ -0,0 +1,128 @@
+//! Отвечает за применение патчей JSON Patch (RFC 6902) к документам JSON.
+//!
+//! Патч - массив операций `add`, `remove`, `replace`, `move`, `copy` и `test`, которые применяются по порядку. Места в документе задаются указателями JSON (RFC 6901): `/cards/0/title`, где `~1` обозначает `/`, а `~0` - `~`. Операции применяются к копии документа, поэтому если не применяется хотя бы одна из них, документ остаётся прежним.
+
+use custom_error::custom_error;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongPatch{reason: String} = "Патч не применён: {reason}."}
+custom_error!{pub TestFailed{path: String} = "Патч не применён: значение по пути \"{path}\" не совпадает с ожидаемым."}
+
+/// Операция патча. Поля, которые не относятся к операции, игнорируются, как того требует RFC 6902.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+  /// Добавляет значение в объект или вставляет его в массив; `-` в конце пути означает конец массива.
+  Add { path: String, value: JsonValue },
+  Remove { path: String },
+  Replace { path: String, value: JsonValue },
+  Move { from: String, path: String },
+  Copy { from: String, path: String },
+  /// Проверяет, что значение по пути равно `value`.
+  Test { path: String, value: JsonValue },
+}
+
+impl Operation {
+  /// Возвращает пути, которые операция изменяет или из которых берёт значение. Операция `test` ничего не изменяет, и её путь не возвращается.
+  pub fn paths(&self) -> Vec<&str> {
+    match self {
+      Operation::Add { path, .. } | Operation::Remove { path } | Operation::Replace { path, .. } => vec![path],
+      Operation::Move { from, path } | Operation::Copy { from, path } => vec![from, path],
+      Operation::Test { .. } => vec![],
+    }
+  }
+}
+
+/// Применяет операции патча к документу.
+///
+/// Если операция `test` не проходит, функция возвращает `TestFailed`, а при любой другой ошибке - `WrongPatch`.
+pub fn apply(document: &mut JsonValue, operations: &[Operation]) -> MResult<()> {
+  let mut patched = document.clone();
+  for operation in operations {
+    match operation {
+      Operation::Add { path, value } => add(&mut patched, path, value.clone())?,
+      Operation::Remove { path } => { remove(&mut patched, path)?; },
+      Operation::Replace { path, value } => *get_mut(&mut patched, path)? = value.clone(),
+      Operation::Move { from, path } => {
+        if path.starts_with(&format!("{}/", from)) {
+          return Err(Box::new(wrong(format!("значение \"{}\" нельзя переместить внутрь самого себя", from))));
+        };
+        if from != path {
+          let value = remove(&mut patched, from)?;
+          add(&mut patched, path, value)?;
+        };
+      },
+      Operation::Copy { from, path } => {
+        let value = get_mut(&mut patched, from)?.clone();
+        add(&mut patched, path, value)?;
+      },
+      Operation::Test { path, value } => if *get_mut(&mut patched, path)? != *value {
+        return Err(Box::new(TestFailed{ path: path.clone() }));
+      },
+    };
+  };
+  *document = patched;
+  Ok(())
+}
+
+fn wrong(reason: String) -> WrongPatch {
+  WrongPatch{ reason }
+}
+
+/// Возвращает значение по пути.
+fn get_mut<'a>(document: &'a mut JsonValue, path: &str) -> Result<&'a mut JsonValue, WrongPatch> {
+  if !path.is_empty() && !path.starts_with('/') {
+    return Err(wrong(format!("путь \"{}\" должен начинаться с /", path)));
+  };
+  document.pointer_mut(path).ok_or_else(|| wrong(format!("путь \"{}\" не найден", path)))
+}
+
+/// Разбивает путь на путь к родительскому значению и последний шаг без экранирования.
+fn split(path: &str) -> Result<(&str, String), WrongPatch> {
+  match path.rfind('/') {
+    Some(i) if path.starts_with('/') => Ok((&path[..i], path[i + 1..].replace("~1", "/").replace("~0", "~"))),
+    _ => Err(wrong(format!("путь \"{}\" должен начинаться с /", path))),
+  }
+}
+
+/// Разбирает номер элемента массива, который не может быть больше `max`.
+fn index(token: &str, max: usize, path: &str) -> Result<usize, WrongPatch> {
+  let valid = !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) && (token == "0" || !token.starts_with('0'));
+  match token.parse::<usize>() {
+    Ok(i) if valid && i <= max => Ok(i),
+    _ => Err(wrong(format!("в пути \"{}\" нет элемента массива {}", path, token))),
+  }
+}
+
+fn add(document: &mut JsonValue, path: &str, value: JsonValue) -> Result<(), WrongPatch> {
+  if path.is_empty() {
+    *document = value;
+    return Ok(());
+  };
+  let (parent, token) = split(path)?;
+  match get_mut(document, parent)? {
+    JsonValue::Object(map) => { map.insert(token, value); },
+    JsonValue::Array(items) => {
+      let i = match token.as_str() {
+        "-" => items.len(),
+        token => index(token, items.len(), path)?,
+      };
+      items.insert(i, value);
+    },
+    _ => return Err(wrong(format!("путь \"{}\" не указывает на объект или массив", parent))),
+  };
+  Ok(())
+}
+
+fn remove(document: &mut JsonValue, path: &str) -> Result<JsonValue, WrongPatch> {
+  if path.is_empty() { return Err(wrong("документ нельзя удалить целиком".into())); };
+  let (parent, token) = split(path)?;
+  match get_mut(document, parent)? {
+    JsonValue::Object(map) => map.remove(&token).ok_or_else(|| wrong(format!("путь \"{}\" не найден", path))),
+    JsonValue::Array(items) if !items.is_empty() => Ok(items.remove(index(&token, items.len() - 1, path)?)),
+    _ => Err(wrong(format!("путь \"{}\" не найден", path))),
+  }
+}
@@ -0,0 +1,45 @@
+//! Отвечает за ссылки задач на внешние ресурсы.
+//!
+//! Ссылка (`Task::links`) связывает задачу с запросом на слияние, документом или задачей в другом трекере. Идентификатор ссылки уникален в пределах задачи. Заголовок и адрес проверяются так же, как при создании задачи (см. `validation::link`).
+
+use custom_error::custom_error;
+
+use crate::core::events::EventKind;
+use crate::core::validation::{self, WrongLink, MAX_TASK_LINKS};
+use crate::core::{save_board, task_history};
+use crate::model::{BoardContext, Cards, Link};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NoSuchLink{} = "Ссылка не найдена."}
+
+/// Добавляет задаче ссылку или заменяет существующую и возвращает идентификатор ссылки.
+///
+/// Если `link.id` не задан, ссылка добавляется. Если задан, заменяется ссылка с этим идентификатором; отсутствие такой ссылки - ошибка `NoSuchLink`.
+pub async fn put(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64, mut link: Link) -> MResult<i64> {
+  validation::link(&mut link)?;
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let links = &mut ctx.board.cards.get_mut_task(card_id, task_id)?.links;
+  if link.id != 0 {
+    *links.iter_mut().find(|l| l.id == link.id).ok_or(NoSuchLink{})? = link.clone();
+  } else if links.len() >= MAX_TASK_LINKS {
+    return Err(Box::new(WrongLink{ reason: format!("у задачи может быть не больше {} ссылок", MAX_TASK_LINKS) }));
+  } else {
+    link.id = links.iter().map(|l| l.id).max().unwrap_or(0) + 1;
+    links.push(link.clone());
+  };
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await?;
+  Ok(link.id)
+}
+
+/// Удаляет ссылку задачи. Если ссылки нет, функция возвращает `NoSuchLink`.
+pub async fn remove(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64, link_id: i64) -> MResult<()> {
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let links = &mut ctx.board.cards.get_mut_task(card_id, task_id)?.links;
+  if !links.iter().any(|l| l.id == link_id) { return Err(Box::new(NoSuchLink{})); };
+  links.retain(|l| l.id != link_id);
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
+}
@@ -1,308 +1,1045 @@
 //! Отвечает за реализацию логики приложения.
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use custom_error::custom_error;
-use futures::future;
-use serde_json::Value as JsonValue;
-use sha3::{Digest, Sha3_256};
+use hyper::Body;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::collections::HashSet;
 use tokio_postgres::types::ToSql;
 
-use crate::model::{Board, BoardsShort, BoardHeader, BoardBackground, Cards, Card, Task, Subtask, Tag, Timelines};
-use crate::psql_handler::Db;
-use crate::sec::auth::{Token, TokenAuth, SignInCredentials, SignUpCredentials, UserCredentials, AccountPlanDetails};
+pub mod admin_audit;
+pub mod admin_keys;
+pub mod automation;
+pub mod board_cache;
+pub mod board_size;
+pub mod capacity;
+pub mod cc_keys;
+pub mod compat;
+pub mod delta;
+pub mod dependencies;
+pub mod digest;
+pub mod document;
+pub mod events;
+pub mod github;
+pub mod identities;
+pub mod import;
+pub mod integrity;
+pub mod jobs;
+pub mod json_patch;
+pub mod links;
+pub mod notifications;
+pub mod orgs;
+pub mod overdue;
+pub mod quota;
+pub mod recycle;
+pub mod reports;
+pub mod retention;
+pub mod security_events;
+pub mod signup;
+pub mod sprints;
+pub mod stats;
+pub mod task_history;
+pub mod undo;
+pub mod validation;
+pub mod views;
+
+#[cfg(test)]
+mod tests;
+
+use crate::model::{
+  Assignment, Board, BoardContext, BoardFilter, BoardHeader, BoardPatch, BoardPrefsPatch, BoardSort, BoardsShort, BoardBackground, Cards, Card, CardPatch, Lane,
+  LanePatch, NewBoard, NewCard, NewSubtask, NewTask, ProfilePatch, Task, TaskPatch, TaskSort, Subtask, SubtaskPatch, StoredBoard, Tag, TagPatch,
+  Timelines, UserProfile
+};
+use crate::core::board_size::BoardSize;
+use crate::core::events::EventKind;
+use crate::core::sprints::NoSuchSprint;
+use crate::core::stats::BoardSummary;
+use crate::core::task_history::TaskConflict;
+use crate::psql_handler::{jsonb, Db};
+use crate::sec::auth::{
+  self, ClientInfo, Session, Token, TokenAuth, TokenLifetime, RefreshCredentials, SignInCredentials, SignUpCredentials, UserCredentials,
+  AccountPlanDetails, CredentialsPatch
+};
 use crate::sec::color_vld::validate_color;
 use crate::sec::key_gen;
+use crate::sec::markdown;
+use crate::sec::policy;
+use crate::sec::tokens_vld::{hash_token, is_alive};
+use crate::setup::{AppConfig, Quota};
+use crate::storage::{BoardListRow, BoardRow, NewUser, PostgresRequired, Storage};
 
 type MResult<T> = Result<T, Box<dyn std::error::Error>>;
 
 custom_error!{NFO{}  = "Не удалось получить данные."}
 custom_error!{WDE{}  = "Не удалось записать данные."}
 custom_error!{TNF{}  = "Не удалось найти тег по идентификатору."}
+custom_error!{LNF{}  = "Не удалось найти дорожку по идентификатору."}
+custom_error!{pub SignInLocked{until: i64} = "Вход в аккаунт временно заблокирован."}
+custom_error!{pub WrongCcKey{} = "Ключ регистрации недействителен."}
+custom_error!{pub WrongPassword{} = "Неверный пароль."}
+custom_error!{pub LoginTaken{} = "Логин уже занят."}
+custom_error!{pub WrongCursor{} = "Неверный курсор списка досок."}
+custom_error!{pub InaccessibleBoards{ids: String} = "Доски вам недоступны: {ids}."}
+custom_error!{pub WipLimitReached{limit: u32} = "В карточке не может быть больше {limit} задач."}
+custom_error!{pub NotMember{user_id: i64} = "Пользователь {user_id} не является участником доски."}
+custom_error!{pub NotOwner{} = "Передать доску может только её автор."}
+custom_error!{pub AuthorCannotLeave{} = "Автор не может покинуть доску, не передав её другому участнику."}
+custom_error!{pub BoardArchived{} = "Доска в архиве и доступна только для чтения."}
+custom_error!{pub NotArchiver{} = "Перенести доску в архив и вернуть её может только автор доски."}
+custom_error!{pub CorruptBoard{column: &'static str, reason: String} = "Данные доски повреждены ({column}): {reason}"}
+
+/// Таблицы базы данных. Каждая из них либо попадает в резервную копию (`BACKUP_TABLES`), либо явно из неё исключена (`NOT_BACKED_UP`).
+const SCHEMA: [&str; 31] = [
+  "create table if not exists taskboard_keys (key varchar unique, value varchar);",
+  "create table if not exists admin_keys (name varchar unique, key_hash bytea unique, scopes varchar, expires_at bigint);",
+  "create table if not exists cc_keys (key varchar unique, note varchar, created_at bigint, expires_at bigint);",
+  "create table if not exists users (id bigserial primary key, login varchar unique, shared_boards jsonb, user_creds jsonb, apd varchar, display_name varchar, avatar_color varchar default '#808080');",
+  "create table if not exists boards (id bigserial primary key, author bigint, shared_with jsonb, header jsonb, cards jsonb, background varchar, tags varchar default '[]', lanes varchar default '[]', revision bigint default 0, settings varchar default '{}', updated_at bigint default 0, created_at bigint default 0, sprints varchar default '[]', watchers varchar default '[]', archived_at bigint default 0);",
+  "create table if not exists id_seqs (id varchar primary key, val bigint);",
+  "create table if not exists user_board_prefs (user_id bigint, board_id bigint, favorite boolean default false, muted boolean default false, position bigint, unique (user_id, board_id));",
+  "create table if not exists sign_in_failures (login varchar unique, failures bigint, first_failure bigint, locked_until bigint);",
+  "create table if not exists user_identities (provider varchar, subject varchar, user_id bigint, unique (provider, subject));",
+  "create table if not exists oauth_states (state varchar unique, provider varchar, user_id bigint, expires_at bigint);",
+  "create table if not exists admin_audit (id bigserial, at bigint, key_name varchar, ip varchar, route varchar, entity varchar, summary varchar);",
+  "create table if not exists task_history (id bigserial, board_id bigint, card_id bigint, task_id bigint, field varchar, old_value varchar, new_value varchar, actor bigint, at bigint);",
+  "create table if not exists undo_log (id bigserial, board_id bigint, user_id bigint, at bigint, record varchar);",
+  "create table if not exists notifications (id bigserial, user_id bigint, kind varchar, board_id bigint, card_id bigint, task_id bigint, subtask_id bigint, actor bigint, at bigint, read boolean default false);",
+  "create table if not exists board_views (id bigserial, user_id bigint, board_id bigint, name varchar, filter varchar, sort varchar, unique (user_id, board_id, name));",
+  "create table if not exists github_links (board_id bigint unique, repo varchar, card_id bigint, token bytea, webhook_secret varchar, linked_by bigint, synced_at bigint);",
+  "create table if not exists board_deltas (board_id bigint, revision bigint, patch varchar, unique (board_id, revision));",
+  "create table if not exists notification_prefs (user_id bigint unique, email varchar, digest varchar default 'daily', unsubscribed boolean default false, digest_sent_at bigint default 0);",
+  "create table if not exists security_events (id bigserial, user_id bigint, kind varchar, ip varchar, country varchar, user_agent varchar, at bigint);",
+  "create table if not exists board_reports (id bigserial, board_id bigint, cadence varchar, format varchar, target varchar, sent_at bigint default 0);",
+  "create table if not exists organizations (id bigserial, name varchar, max_boards bigint, max_members bigint, created_at bigint, policy varchar default '{}');",
+  "create table if not exists org_members (org_id bigint, user_id bigint, role varchar, joined_at bigint, unique (org_id, user_id));",
+  "create table if not exists org_boards (board_id bigint unique, org_id bigint);",
+  "create table if not exists org_board_guests (org_id bigint, board_id bigint, user_id bigint, unique (board_id, user_id));",
+  "create table if not exists guest_accounts (user_id bigint unique, org_id bigint);",
+  "create table if not exists board_retention (board_id bigint unique, flagged_at bigint default 0, kept_at bigint default 0);",
+  "create table if not exists board_exports (id bigserial, user_id bigint, board_id bigint, title varchar, exported_at bigint, document varchar);",
+  "create table if not exists recycled_tasks (id bigserial, board_id bigint, card_id bigint, task varchar, recycled_at bigint);",
+  "create table if not exists pending_users (login varchar unique, email varchar, user_creds varchar, apd varchar, token_hash bytea unique, created_at bigint, expires_at bigint, sent_at bigint);",
+  "create table if not exists signup_attempts (ip varchar, at bigint);",
+  "create table if not exists jobs (id bigserial primary key, kind varchar, key varchar unique, shard_key bigint, payload varchar, period_secs bigint default 0, run_at bigint, attempts bigint default 0, locked_until bigint default 0, lease varchar, failed_at bigint default 0, last_error varchar, created_at bigint);",
+];
 
 /// Настраивает базу данных.
 ///
-/// Создаёт таблицы, которые будут предназначаться для хранения данных приложения.
+/// Создаёт таблицы, которые будут предназначаться для хранения данных приложения (см. `SCHEMA`), после чего приводит уже имеющиеся данные к актуальной модели (см. `compat`).
 pub async fn db_setup(db: &Db) -> MResult<()> {
-  db.write_mul(vec![
-    ("create table if not exists taskboard_keys (key varchar unique, value varchar);", vec![]),
-    ("create table if not exists users (id bigserial, login varchar unique, shared_boards varchar, user_creds varchar, apd varchar);", vec![]),
-    ("create table if not exists boards (id bigserial, author bigint, shared_with varchar, header varchar, cards varchar, background varchar);", vec![]),
-    ("create table if not exists id_seqs (id varchar unique, val bigint);", vec![])
-  ]).await
+  db.write_mul(SCHEMA.iter().map(|query| (*query, vec![])).collect()).await?;
+  compat::migrate(db).await
+}
+
+/// Таблицы, попадающие в резервную копию, в порядке их восстановления.
+const BACKUP_TABLES: [&str; 24] = [
+  "taskboard_keys", "admin_keys", "cc_keys", "users", "boards", "id_seqs", "user_board_prefs", "user_identities",
+  "admin_audit", "task_history", "notifications", "board_views", "github_links", "notification_prefs", "security_events",
+  "board_reports", "organizations", "org_members", "org_boards", "org_board_guests", "guest_accounts", "board_retention",
+  "board_exports", "recycled_tasks"
+];
+
+/// Таблицы, не попадающие в резервную копию: счётчики попыток, незавершённые входы и регистрации, журналы изменений и отмены досок и очередь заданий теряют смысл после восстановления. Список сверяется со `SCHEMA` в тестах.
+#[cfg(test)]
+const NOT_BACKED_UP: [&str; 7] = ["sign_in_failures", "oauth_states", "undo_log", "board_deltas", "pending_users", "signup_attempts", "jobs"];
+
+/// Выгружает резервную копию базы данных.
+///
+/// Если `with_secrets` не установлен, в копию не попадают данные аутентификации пользователей, ключи администраторов, ключи регистрации, связи досок с репозиториями GitHub, хранящие токены доступа, и отчёты о досках, адреса которых могут содержать секреты: после восстановления из такой копии пользователям придётся восстанавливать доступ к аккаунтам, ключи - выпускать заново, а доски - связывать с репозиториями и настраивать отчёты заново.
+pub async fn backup(db: &Db, with_secrets: bool) -> MResult<Body> {
+  let queries = BACKUP_TABLES.iter().map(|table| {
+    let source = match (*table, with_secrets) {
+      ("users", false) => "(select id, login, shared_boards, apd, display_name, avatar_color from users)",
+      ("admin_keys", false) => "(select * from admin_keys where false)",
+      ("cc_keys", false) => "(select * from cc_keys where false)",
+      ("github_links", false) => "(select * from github_links where false)",
+      ("board_reports", false) => "(select * from board_reports where false)",
+      _ => table,
+    };
+    (*table, format!("select row_to_json(t)::text from {} t;", source))
+  }).collect();
+  db.dump(queries).await
+}
+
+/// Восстанавливает базу данных из резервной копии, полностью заменяя текущие данные.
+///
+/// Перед загрузкой создаются недостающие таблицы, а после - продвигаются последовательности идентификаторов, очищаются журнал изменений досок (см. `delta`) и журнал отмены действий (см. `undo`), и к данным применяются миграции (см. `compat`), поэтому восстанавливать можно и копии, сделанные предыдущими версиями сервера. Возвращает количество загруженных строк.
+pub async fn restore(db: &Db, body: Body) -> MResult<u64> {
+  db_setup(db).await?;
+  let count = db.restore(&BACKUP_TABLES, body, &[
+    "select setval(pg_get_serial_sequence('users', 'id'), coalesce(max(id), 0) + 1, false) from users;",
+    "select setval(pg_get_serial_sequence('boards', 'id'), coalesce(max(id), 0) + 1, false) from boards;",
+    "select setval(pg_get_serial_sequence('admin_audit', 'id'), coalesce(max(id), 0) + 1, false) from admin_audit;",
+    "select setval(pg_get_serial_sequence('task_history', 'id'), coalesce(max(id), 0) + 1, false) from task_history;",
+    "select setval(pg_get_serial_sequence('notifications', 'id'), coalesce(max(id), 0) + 1, false) from notifications;",
+    "select setval(pg_get_serial_sequence('board_views', 'id'), coalesce(max(id), 0) + 1, false) from board_views;",
+    "select setval(pg_get_serial_sequence('security_events', 'id'), coalesce(max(id), 0) + 1, false) from security_events;",
+    "select setval(pg_get_serial_sequence('board_reports', 'id'), coalesce(max(id), 0) + 1, false) from board_reports;",
+    "select setval(pg_get_serial_sequence('organizations', 'id'), coalesce(max(id), 0) + 1, false) from organizations;",
+    "select setval(pg_get_serial_sequence('board_exports', 'id'), coalesce(max(id), 0) + 1, false) from board_exports;",
+    "select setval(pg_get_serial_sequence('recycled_tasks', 'id'), coalesce(max(id), 0) + 1, false) from recycled_tasks;",
+    "delete from board_deltas;",
+    "delete from undo_log;",
+  ]).await?;
+  compat::migrate(db).await?;
+  Ok(count)
 }
 
 /// Создаёт пользователя.
 ///
-/// Функция генерирует соль, хэширует пароль и соль - и записывает в базу данных. Возвращает идентификатор пользователя.
-pub async fn create_user(db: &Db, sign_up_credentials: &SignUpCredentials) -> MResult<i64> {
-  let (salt, salted_pass) = key_gen::salt_pass(sign_up_credentials.pass.clone())?;
-  let id: i64 = db.read("select nextval(pg_get_serial_sequence('users', 'id'));", &[]).await?.get(0);
-  let user_credentials = UserCredentials { salt, salted_pass, tokens: vec![] };
-  let user_credentials = serde_json::to_string(&user_credentials)?;
+/// Функция генерирует соль, хэширует пароль и соль - и записывает в базу данных. Отображаемым именем пользователя становится его логин. Возвращает идентификатор пользователя.
+///
+/// Если регистрация возможна только по ключам (см. `cc_keys`), ключ регистрации удаляется в той же транзакции, в которой создаётся пользователь; если ключ недействителен, функция возвращает `WrongCcKey`.
+///
+/// Логин, занятый неподтверждённой регистрацией (см. `signup`), не выдаётся: функция возвращает `LoginTaken`.
+pub async fn create_user(db: &dyn Storage, cfg: &AppConfig, sign_up_credentials: &SignUpCredentials) -> MResult<i64> {
+  if let Some(pg) = db.postgres() {
+    let pending = pg.read_all(
+      "select 1 from pending_users where login = $1 and expires_at > $2;",
+      &[&sign_up_credentials.login, &Utc::now().timestamp()]
+    ).await?;
+    if !pending.is_empty() { return Err(Box::new(LoginTaken{})); };
+  };
+  let (user_credentials, billing) = new_user_data(&sign_up_credentials.pass, cfg)?;
+  let user = NewUser { login: &sign_up_credentials.login, user_creds: &user_credentials, apd: &billing };
+  let cc_key = match cfg.cc_key_required {
+    true => Some((sign_up_credentials.cc_key.as_deref().unwrap_or(""), Utc::now().timestamp())),
+    false => None,
+  };
+  match db.insert_user(&user, cc_key).await? {
+    Some(id) => Ok(id),
+    None => Err(Box::new(WrongCcKey{})),
+  }
+}
+
+/// Возвращает сведения авторизации и оплаты нового пользователя с данным паролем, подготовленные к записи в базу данных.
+fn new_user_data(pass: &str, cfg: &AppConfig) -> MResult<(String, String)> {
+  let mut user_credentials = UserCredentials::default();
+  key_gen::set_pass(&mut user_credentials, pass, &cfg.password_hashing)?;
   let billing = AccountPlanDetails {
     billed_forever: false,
     payment_data: String::new(),
     is_paid_whenever: false,
     last_payment: Utc::now()
   };
-  let billing = serde_json::to_string(&billing)?;
-  db.write(
-    "insert into users values ($1, $2, '[]', $3, $4);",
-    &[&id, &sign_up_credentials.login, &user_credentials, &billing]
-  ).await?;
-  Ok(id)
+  Ok((serde_json::to_string(&user_credentials)?, serde_json::to_string(&billing)?))
 }
 
 /// Возвращает идентификатор пользователя по логину и паролю.
-pub async fn sign_in_creds_to_id(db: &Db, sign_in_credentials: &SignInCredentials) -> MResult<i64> {
+///
+/// Пароль, хэшированный устаревшим алгоритмом или с устаревшими параметрами, при успешном входе хэшируется заново (см. `key_gen::needs_rehash`).
+///
+/// Неудачные попытки входа подсчитываются по логину. Если за `sign_in_failures_window_secs` их набирается `sign_in_max_failures`, вход блокируется на `sign_in_lockout_secs` (см. `AppConfig`), и функция возвращает `SignInLocked` - даже при верном пароле.
+///
+/// Если задан каталог пользователей (см. `AppConfig::ldap`), логин и пароль, не подошедшие к пользователю сервера, проверяются в каталоге, и при первом входе пользователь каталога получает учётную запись на сервере. Если каталог недоступен, функция возвращает `DirectoryUnavailable`, а попытка входа не подсчитывается. Учётные записи пользователей каталога хранятся только в PostgreSQL: с другим хранилищем вход через каталог возвращает `PostgresRequired`.
+pub async fn sign_in_creds_to_id(db: &dyn Storage, cfg: &AppConfig, sign_in_credentials: &SignInCredentials) -> MResult<i64> {
   custom_error!{IncorrectPassword{} = "Неверный пароль!"};
-  let id_and_credentials = db.read(
-    "select id, user_creds from users where login = $1;", &[&sign_in_credentials.login]
-  ).await?;
-  let user_credentials: UserCredentials = serde_json::from_str(id_and_credentials.get(1))?;
-  match key_gen::check_pass(
-    user_credentials.salt,
-    user_credentials.salted_pass,
-    &sign_in_credentials.pass
-  ) {
-    true => Ok(id_and_credentials.get(0)),
-    _ => Err(Box::new(IncorrectPassword{})),
+  let login = &sign_in_credentials.login;
+  let now = Utc::now().timestamp();
+  if let Some(locked_until) = db.sign_in_locked_until(login).await? {
+    if locked_until > now { return Err(Box::new(SignInLocked{ until: locked_until })); };
+  };
+  let allow_local_users = cfg.ldap.as_ref().is_none_or(|ldap| ldap.allow_local_users);
+  let local = match allow_local_users {
+    true => db.user_by_login(login).await?,
+    false => None,
+  };
+  let mut id = match local {
+    Some(user) => {
+      let mut user_credentials: UserCredentials = serde_json::from_str(&user.user_creds)?;
+      match key_gen::check_pass(&user_credentials, &sign_in_credentials.pass) {
+        true => {
+          if key_gen::needs_rehash(&user_credentials, &cfg.password_hashing) {
+            key_gen::set_pass(&mut user_credentials, &sign_in_credentials.pass, &cfg.password_hashing)?;
+            db.set_user_creds(&user.id, &serde_json::to_string(&user_credentials)?).await?;
+          };
+          Some(user.id)
+        },
+        _ => None,
+      }
+    },
+    // Попытки входа в несуществующий аккаунт тоже подсчитываются, чтобы по ответам нельзя было отличить его от существующего.
+    None => None,
+  };
+  if let (None, Some(directory)) = (id, auth::authenticator(cfg)) {
+    let identity = directory.authenticate(login, &sign_in_credentials.pass).await?;
+    if let Some(identity) = identity {
+      let db = db.postgres().ok_or(PostgresRequired{})?;
+      id = Some(identities::provision(db, cfg, directory.name(), &identity).await?);
+    };
+  };
+  if let Some(id) = id {
+    db.clear_sign_in_failures(login).await?;
+    return Ok(id);
+  };
+  let failures = db.add_sign_in_failure(login, now, now - cfg.sign_in_failures_window_secs).await?;
+  if failures < cfg.sign_in_max_failures { return Err(Box::new(IncorrectPassword{})); };
+  let locked_until = now + cfg.sign_in_lockout_secs;
+  db.lock_sign_in(login, locked_until).await?;
+  Err(Box::new(SignInLocked{ until: locked_until }))
+}
+
+/// Периодическое задание (см. `jobs`): удаляет неудачные попытки входа, которые уже не учитываются и не блокируют вход, и записывает в журнал сервера число очищенных логинов.
+pub async fn sign_in_failures_job(db: Db, window_secs: i64) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  let removed = db.read_all(
+    "delete from sign_in_failures where first_failure <= $1 and locked_until <= $2 returning login;", &[&(now - window_secs), &now]
+  ).await?.len();
+  if removed > 0 { println!("Удалены устаревшие неудачные попытки входа: {}.", removed); };
+  Ok(())
+}
+
+/// Изменяет логин и/или пароль пользователя.
+///
+/// Изменение подтверждается текущим паролем; если он неверен, функция возвращает `WrongPassword`. Новые значения проверяются на соответствие требованиям конфигурации, нарушения возвращаются в `policy::PolicyViolations`. Выданные пользователю токены остаются действительными.
+pub async fn patch_user_creds(db: &dyn Storage, cfg: &AppConfig, id: &i64, patch: &CredentialsPatch) -> MResult<()> {
+  let user = db.user(id).await?;
+  let login = user.login;
+  let mut user_credentials: UserCredentials = serde_json::from_str(&user.user_creds)?;
+  if !key_gen::check_pass(&user_credentials, &patch.pass) {
+    return Err(Box::new(WrongPassword{}));
+  };
+  let new_login = patch.new_login.as_ref().filter(|new_login| **new_login != login);
+  policy::validate(
+    &cfg.credentials_policy,
+    new_login.unwrap_or(&login),
+    new_login.is_some(),
+    patch.new_pass.as_deref()
+  )?;
+  if let Some(new_pass) = &patch.new_pass {
+    key_gen::set_pass(&mut user_credentials, new_pass, &cfg.password_hashing)?;
+  };
+  let user_credentials = serde_json::to_string(&user_credentials)?;
+  match db.set_login_and_creds(id, new_login.unwrap_or(&login), &user_credentials).await? {
+    true => Ok(()),
+    false => Err(Box::new(LoginTaken{})),
   }
 }
 
-/// Создаёт новый токен и возвращает его.
-pub async fn get_new_token(db: &Db, id: &i64) -> MResult<TokenAuth> {
-  let user_credentials = db.read("select user_creds from users where id = $1;", &[id]).await?;
-  let mut user_credentials: UserCredentials = serde_json::from_str(user_credentials.get(0))?;
+/// Выпускает пару из токена доступа и токена обновления, добавляя их в сведения авторизации пользователя.
+///
+/// Срок `token_absolute_ttl_days` токена обновления отсчитывается от момента `created` - входа, с которого начался сеанс, поэтому обмен токенов не продлевает сеанс сверх этого срока.
+fn issue_token_pair(user_credentials: &mut UserCredentials, id: &i64, cfg: &AppConfig, client: &ClientInfo, created: DateTime<Utc>) -> MResult<TokenAuth> {
+  let now = Utc::now();
+  let access_expires_at = now + chrono::Duration::minutes(cfg.access_token_ttl_minutes);
   let token = key_gen::generate_strong(64)?;
-  let mut hasher = Sha3_256::new();
-  hasher.update(&token);
-  let hashed = hasher.finalize();
-  let token_info = Token {
-    tk: hashed.to_vec(),
-    from_dt: Utc::now(),
-  };
-  user_credentials.tokens.push(token_info.clone());
-  let user_credentials = serde_json::to_string(&user_credentials)?;
-  db.write("update users set user_creds = $1 where id = $2;", &[&user_credentials, id]).await?;
-  let token_auth = TokenAuth { id: *id, token };
+  user_credentials.tokens.push(Token {
+    tk: hash_token(&token),
+    from_dt: now,
+    created_dt: Some(now),
+    expires_dt: Some(access_expires_at),
+    client: client.clone(),
+  });
+  let refresh_token = key_gen::generate_strong(64)?;
+  user_credentials.refresh_tokens.push(Token {
+    tk: hash_token(&refresh_token),
+    from_dt: now,
+    created_dt: Some(created),
+    expires_dt: None,
+    client: client.clone(),
+  });
+  let lifetime = TokenLifetime {
+    access_ttl_minutes: cfg.access_token_ttl_minutes,
+    access_expires_at,
+    ttl_days: cfg.token_ttl_days,
+    absolute_ttl_days: cfg.token_absolute_ttl_days,
+    expires_at: created + chrono::Duration::days(cfg.token_absolute_ttl_days),
+  };
+  Ok(TokenAuth { id: *id, token, refresh_token: Some(refresh_token), lifetime: Some(lifetime) })
+}
+
+/// Создаёт новую пару токенов и возвращает её вместе со сроками действия.
+///
+/// Сведения о клиенте `client` сохраняются вместе с токенами, чтобы пользователь мог узнать сеанс в их списке (см. `list_sessions`). С этого момента отсчитывается срок `token_absolute_ttl_days` сеанса.
+pub async fn get_new_token(db: &dyn Storage, id: &i64, cfg: &AppConfig, client: &ClientInfo) -> MResult<TokenAuth> {
+  let mut user_credentials: UserCredentials = serde_json::from_str(&db.user(id).await?.user_creds)?;
+  let now = Utc::now();
+  let client = ClientInfo { signed_in_at: Some(now), ..client.clone() };
+  let token_auth = issue_token_pair(&mut user_credentials, id, cfg, &client, now)?;
+  db.set_user_creds(id, &serde_json::to_string(&user_credentials)?).await?;
   Ok(token_auth)
 }
 
-/// Получает все токены пользователя.
-pub async fn get_tokens_and_billing(db: &Db, id: &i64) -> MResult<(Vec<Token>, AccountPlanDetails)> {
-  let user_data = db.read("select user_creds, apd from users where id = $1;", &[id]).await?;
-  let user_credentials: UserCredentials = serde_json::from_str(user_data.get(0))?;
-  let billing: AccountPlanDetails = serde_json::from_str(user_data.get(1))?;
-  Ok((user_credentials.tokens, billing))
+/// Обменивает токен обновления на новую пару токенов.
+///
+/// Использованный токен обновления удаляется, поэтому каждый из них можно использовать только один раз. Вместо токена обновления принимается и долгоживущий токен, выданный предыдущими версиями сервера.
+///
+/// Сведения авторизации записываются, только если не изменились с момента чтения (см. `Storage::swap_user_creds`): из одновременных обменов одного токена удаётся только один, а обмен, совпавший с другим изменением сведений, повторяется.
+///
+/// Новая пара получает сведения о клиенте `client`; недостающие сведения и дата входа берутся из использованного токена. Срок `token_absolute_ttl_days` новой пары отсчитывается от выдачи первого токена сеанса, а у долгоживущего токена - от его последнего использования.
+pub async fn refresh_token(db: &dyn Storage, refresh_credentials: &RefreshCredentials, cfg: &AppConfig, client: &ClientInfo) -> MResult<TokenAuth> {
+  custom_error!{InvalidRefreshToken{} = "Токен обновления недействителен."};
+  const MAX_ATTEMPTS: usize = 5;
+  let id = &refresh_credentials.id;
+  let hashed = hash_token(&refresh_credentials.refresh_token);
+  for _ in 0..MAX_ATTEMPTS {
+    let stored = db.user(id).await?.user_creds;
+    let mut user_credentials: UserCredentials = serde_json::from_str(&stored)?;
+    let now = Utc::now();
+    user_credentials.tokens.retain(|t| is_alive(t, &now, cfg));
+    user_credentials.refresh_tokens.retain(|t| is_alive(t, &now, cfg));
+    let used = user_credentials.refresh_tokens.iter()
+      .chain(user_credentials.tokens.iter().filter(|t| t.expires_dt.is_none()))
+      .find(|t| t.tk == hashed)
+      .map(|t| (t.client.clone(), t.created_dt.unwrap_or(t.from_dt)));
+    let (previous, created) = match used {
+      Some(v) => v,
+      None => return Err(Box::new(InvalidRefreshToken{})),
+    };
+    user_credentials.refresh_tokens.retain(|t| t.tk != hashed);
+    user_credentials.tokens.retain(|t| t.expires_dt.is_some() || t.tk != hashed);
+    let client = ClientInfo {
+      user_agent: client.user_agent.clone().or(previous.user_agent),
+      device_name: client.device_name.clone().or(previous.device_name),
+      ip: client.ip.clone().or(previous.ip),
+      signed_in_at: previous.signed_in_at,
+    };
+    let token_auth = issue_token_pair(&mut user_credentials, id, cfg, &client, created)?;
+    if db.swap_user_creds(id, &stored, &serde_json::to_string(&user_credentials)?).await? { return Ok(token_auth); };
+  };
+  Err(Box::new(InvalidRefreshToken{}))
 }
 
-/// Обновляет все токены пользователя.
-pub async fn write_tokens(db: &Db, id: &i64, tokens: &[Token]) -> MResult<()> {
-  let user_credentials = db.read("select user_creds from users where id = $1;", &[id]).await?;
-  let mut user_credentials: UserCredentials = serde_json::from_str(user_credentials.get(0))?;
-  user_credentials.tokens = tokens.to_owned();
-  let user_credentials = serde_json::to_string(&user_credentials)?;
-  db.write("update users set user_creds = $1 where id = $2;", &[&user_credentials, id]).await
+/// Возвращает действующие сеансы пользователя, начиная с последнего обновлённого.
+///
+/// Сеансом считается токен обновления, а также долгоживущий токен, выданный предыдущими версиями сервера.
+pub async fn list_sessions(db: &dyn Storage, id: &i64, cfg: &AppConfig) -> MResult<Vec<Session>> {
+  let user_credentials: UserCredentials = serde_json::from_str(&db.user(id).await?.user_creds)?;
+  let now = Utc::now();
+  let mut sessions: Vec<Session> = user_credentials.refresh_tokens.iter()
+    .chain(user_credentials.tokens.iter().filter(|t| t.expires_dt.is_none()))
+    .filter(|t| is_alive(t, &now, cfg))
+    .map(|t| Session { refreshed_at: t.from_dt, client: t.client.clone() })
+    .collect();
+  sessions.sort_by_key(|s| std::cmp::Reverse(s.refreshed_at));
+  Ok(sessions)
+}
+
+/// Получает все токены пользователя: токены доступа вместе с долгоживущими токенами предыдущих версий сервера и токены обновления, - и данные оплаты.
+pub async fn get_tokens_and_billing(db: &dyn Storage, id: &i64) -> MResult<(Vec<Token>, Vec<Token>, AccountPlanDetails)> {
+  let user = db.user(id).await?;
+  let user_credentials: UserCredentials = serde_json::from_str(&user.user_creds)?;
+  let billing: AccountPlanDetails = serde_json::from_str(&user.apd)?;
+  Ok((user_credentials.tokens, user_credentials.refresh_tokens, billing))
+}
+
+/// Записывает платёж за аккаунт пользователя.
+///
+/// Дата последнего платежа только увеличивается, поэтому повторные и пришедшие не по порядку уведомления ничего не портят.
+pub async fn register_payment(db: &dyn Storage, id: &i64, paid_at: &DateTime<Utc>) -> MResult<()> {
+  let mut billing: AccountPlanDetails = serde_json::from_str(&db.user(id).await?.apd)?;
+  if billing.is_paid_whenever && billing.last_payment >= *paid_at { return Ok(()); };
+  billing.is_paid_whenever = true;
+  billing.last_payment = *paid_at;
+  db.set_billing(id, &serde_json::to_string(&billing)?).await
+}
+
+/// Наибольшее число профилей, которое можно получить за один запрос.
+pub const MAX_RESOLVED_PROFILES: usize = 100;
+
+/// Возвращает публичные профили пользователей.
+///
+/// Несуществующие идентификаторы пропускаются.
+pub async fn get_profiles(db: &dyn Storage, ids: &[i64]) -> MResult<Vec<UserProfile>> {
+  db.profiles(ids).await
+}
+
+/// Изменяет публичный профиль пользователя.
+///
+/// Патч может содержать поля `display_name` и `avatar_color`.
+pub async fn apply_patch_on_profile(db: &dyn Storage, id: &i64, patch: &ProfilePatch) -> MResult<()> {
+  custom_error!{WrongDisplayName{} = "Отображаемое имя должно содержать от 1 до 64 символов."};
+  let display_name = patch.display_name.as_deref().map(str::trim);
+  if let Some(display_name) = display_name {
+    if display_name.is_empty() || display_name.chars().count() > 64 { return Err(Box::new(WrongDisplayName{})); };
+  };
+  if let Some(avatar_color) = &patch.avatar_color {
+    validate_color(avatar_color)?;
+  };
+  if display_name.is_none() && patch.avatar_color.is_none() { return Ok(()); };
+  db.set_profile(id, display_name, patch.avatar_color.as_deref()).await
+}
+
+/// Наибольшее число досок на одной странице списка.
+pub const MAX_BOARDS_PAGE: i64 = 100;
+
+/// Позиция в списке досок, после которой начинается следующая страница.
+///
+/// Клиент получает её в виде непрозрачной строки - JSON, закодированного в base64 - и не должен полагаться на её содержимое.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "sort", rename_all = "snake_case")]
+pub enum BoardsCursor {
+  Added { pos: i32 },
+  Title { title: String, id: i64 },
+  Activity { updated_at: i64, id: i64 },
+  Created { created_at: i64, id: i64 },
+  Custom { position: i64, pos: i32 },
+}
+
+impl BoardsCursor {
+  /// Возвращает позицию после данной доски.
+  fn after(sort: BoardSort, pos: i32, board: &BoardsShort) -> BoardsCursor {
+    match sort {
+      BoardSort::Added => BoardsCursor::Added { pos },
+      BoardSort::Title => BoardsCursor::Title { title: board.title.clone(), id: board.id },
+      BoardSort::Activity => BoardsCursor::Activity { updated_at: board.updated_at, id: board.id },
+      BoardSort::Created => BoardsCursor::Created { created_at: board.created_at, id: board.id },
+      BoardSort::Custom => BoardsCursor::Custom { position: board.position.unwrap_or(i64::MAX), pos },
+    }
+  }
+  
+  fn encode(&self) -> MResult<String> {
+    Ok(base64::encode_config(&serde_json::to_string(self)?, base64::URL_SAFE_NO_PAD))
+  }
+  
+  /// Декодирует позицию, проверяя, что она получена для того же порядка досок.
+  fn decode(cursor: &str, sort: BoardSort) -> Result<BoardsCursor, WrongCursor> {
+    let cursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| WrongCursor{})?;
+    let cursor: BoardsCursor = serde_json::from_slice(&cursor).map_err(|_| WrongCursor{})?;
+    match (&cursor, sort) {
+      (BoardsCursor::Added { .. }, BoardSort::Added)
+      | (BoardsCursor::Title { .. }, BoardSort::Title)
+      | (BoardsCursor::Activity { .. }, BoardSort::Activity)
+      | (BoardsCursor::Created { .. }, BoardSort::Created)
+      | (BoardsCursor::Custom { .. }, BoardSort::Custom) => Ok(cursor),
+      _ => Err(WrongCursor{}),
+    }
+  }
 }
 
 /// Отдаёт список досок пользователя.
-pub async fn list_boards(db: &Db, id: &i64) -> MResult<String> {
-  let boards = db.read("select shared_boards from users where id = $1;", &[id]).await?;
-  let boards: Vec<i64> = serde_json::from_str(boards.get(0))?;
-  let mut shorts: Vec<BoardsShort> = vec![];
-  for board in &boards {
-    let header: String = db.read("select header from boards where id = $1;", &[board]).await?.get(0);
-    let header: JsonValue = serde_json::from_str(&header)?;
-    let short = BoardsShort {
-      id: *board,
-      title: header["title"].as_str().unwrap().to_string(),
-      header_text_color: header["header_text_color"].as_str().unwrap().to_string(),
-      header_background_color: header["header_background_color"].as_str().unwrap().to_string(),
+///
+/// Доски в архиве (см. `archive_board`) перечисляются отдельно от остальных: если установлен `archived`, отдаются только они, иначе - только доски вне архива. Заголовки досок считываются одним запросом в порядке `sort` вместе с настройками досок, заданными пользователем (см. `set_board_prefs`). Если задан `limit`, отдаётся не больше `limit` досок, а вместе с ними - курсор, с которого начинается следующая страница (`None`, если досок больше нет). Курсор, полученный для другого порядка, отклоняется с ошибкой `WrongCursor`.
+pub async fn list_boards(db: &dyn Storage, id: &i64, archived: bool, sort: BoardSort, cursor: Option<&str>, limit: Option<i64>)
+  -> MResult<(Vec<BoardsShort>, Option<String>)>
+{
+  let cursor = match cursor {
+    Some(cursor) => Some(BoardsCursor::decode(cursor, sort)?),
+    None => None,
+  };
+  let boards: Vec<i64> = serde_json::from_str(&db.user(id).await?.shared_boards)?;
+  // Запрашивается на одну доску больше, чтобы узнать, есть ли следующая страница.
+  let fetch = limit.map(|limit| limit + 1);
+  let rows = db.list_boards(id, &boards, archived, sort, cursor.as_ref(), fetch).await?;
+  let mut shorts: Vec<(i32, BoardsShort)> = vec![];
+  for row in rows {
+    shorts.push((row.pos, short_from_row(row)?));
+  };
+  let next_cursor = match limit {
+    Some(limit) if shorts.len() as i64 > limit => {
+      shorts.truncate(limit as usize);
+      let (pos, last) = shorts.last().ok_or(NFO{})?;
+      Some(BoardsCursor::after(sort, *pos, last).encode()?)
+    },
+    _ => None,
+  };
+  Ok((shorts.into_iter().map(|(_, short)| short).collect(), next_cursor))
+}
+
+/// Собирает краткую информацию о доске из её строки в списке досок.
+fn short_from_row(row: BoardListRow) -> MResult<BoardsShort> {
+  let header: BoardHeader = serde_json::from_str(&row.header)?;
+  Ok(BoardsShort {
+    id: row.id,
+    title: header.title,
+    header_text_color: header.header_text_color,
+    header_background_color: header.header_background_color,
+    created_at: row.created_at,
+    updated_at: row.updated_at,
+    favorite: row.favorite,
+    muted: row.muted,
+    position: row.position,
+  })
+}
+
+/// Доска в ответе на запрос нескольких досок: краткая информация и, если запрошена, сводка по задачам.
+#[derive(Serialize)]
+pub struct BulkBoard {
+  #[serde(flatten)]
+  pub board: BoardsShort,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub summary: Option<BoardSummary>,
+}
+
+/// Возвращает краткую информацию о досках `ids` в порядке запроса и, если установлен `with_summary`, сводки по их задачам (см. `stats::summarize`).
+///
+/// Все доски должны быть в списке досок пользователя, иначе функция возвращает `InaccessibleBoards` с перечнем недоступных досок. Повторы и доски в архиве пропускаются.
+pub async fn bulk_boards(db: &dyn Storage, user_id: &i64, ids: &[i64], with_summary: bool) -> MResult<Vec<BulkBoard>> {
+  let boards: Vec<i64> = serde_json::from_str(&db.user(user_id).await?.shared_boards)?;
+  let inaccessible: Vec<String> = ids.iter().filter(|id| !boards.contains(id)).map(i64::to_string).collect();
+  if !inaccessible.is_empty() { return Err(Box::new(InaccessibleBoards{ ids: inaccessible.join(", ") })); };
+  let mut seen = HashSet::new();
+  let ids: Vec<i64> = ids.iter().copied().filter(|id| seen.insert(*id)).collect();
+  // В порядке `added` доски идут так же, как в переданном списке.
+  let rows = db.list_boards(user_id, &ids, false, BoardSort::Added, None, None).await?;
+  let now = Utc::now();
+  let mut bulk = Vec::with_capacity(rows.len());
+  for row in rows {
+    let summary = match with_summary {
+      true => Some(stats::summarize(&load_board(db, user_id, &row.id).await?.board, &now)),
+      false => None,
     };
-    shorts.push(short);
-  }
-  let shorts = serde_json::to_string(&shorts)?;
-  Ok(shorts)
+    bulk.push(BulkBoard { board: short_from_row(row)?, summary });
+  };
+  Ok(bulk)
 }
 
 /// Создаёт доску.
-pub async fn create_board(db: &Db, author: &i64, board: &Board) -> MResult<i64> {
-  custom_error!{EmptyTitle{} = "У доски пустой заголовок."};
-  if board.header.title.is_empty() { return Err(Box::new(EmptyTitle{})); };
+///
+/// Гостевые учётные записи не могут создавать доски. Если задана организация, доска сразу передаётся ей (см. `orgs::add_board`).
+pub async fn create_board(db: &dyn Storage, quota: &Quota, author: &i64, mut board: NewBoard) -> MResult<i64> {
+  board.header.title = validation::title("доски", &board.header.title)?;
+  match db.postgres() {
+    Some(pg) => orgs::check_board_creation(pg, author, board.org_id.as_ref()).await?,
+    None if board.org_id.is_some() => return Err(Box::new(PostgresRequired{})),
+    None => {},
+  };
+  quota::check("max_boards", quota.max_boards, count_boards(db, author).await? + 1)?;
   if let BoardBackground::Color { color } = &board.background {
     validate_color(color)?;
   };
   validate_color(&board.header.header_background_color)?;
   validate_color(&board.header.header_text_color)?;
-  let data = db.read_mul(vec![
-    ("select nextval(pg_get_serial_sequence('boards', 'id'));", vec![]),
-    ("select shared_boards from users where id = $1;", vec![author])
-  ]).await?;
-  let id: i64 = data[0].get(0);
-  let mut shared_boards = serde_json::from_str::<Vec<i64>>(data[1].get(0))?;
-  shared_boards.push(id);
-  let shared_with = vec![*author];
-  let shared_with = serde_json::to_string(&shared_with)?;
-  let shared_boards = serde_json::to_string(&shared_boards)?;
-  let header = serde_json::to_string(&board.header)?;
-  let background = serde_json::to_string(&board.background)?;
-  let board_queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    (
-      "insert into boards values ($1, $2, $3, $4, '[]', $5);",
-      vec![&id, author, &shared_with, &header, &background]
-    ),
-    ("update users set shared_boards = $1 where id = $2;", vec![&shared_boards, author])
-  ];
-  db.write_mul(board_queries).await?;
+  // На новой доске ещё нет карточек и тегов, поэтому правила могут ссылаться только на автора.
+  automation::validate(&mut board.settings.rules, &[], &[], &[*author])?;
+  recycle::validate(&board.settings.recycle, &[])?;
+  let now = Utc::now().timestamp();
+  let id = db.insert_board(&BoardRow {
+    id: 0,
+    author: *author,
+    shared_with: serde_json::to_string(&[*author])?,
+    header: serde_json::to_string(&board.header)?,
+    cards: String::from("[]"),
+    background: serde_json::to_string(&board.background)?,
+    tags: String::from("[]"),
+    revision: 0,
+    settings: serde_json::to_string(&board.settings)?,
+    created_at: now,
+    updated_at: now,
+    lanes: String::from("[]"),
+    sprints: String::from("[]"),
+    watchers: String::from("[]"),
+    archived_at: 0,
+  }).await?;
+  events::publish(id, Some(*author), 0, EventKind::BoardCreated);
+  if let (Some(pg), Some(org_id)) = (db.postgres(), board.org_id) {
+    orgs::add_board(pg, author, &org_id, &id).await?;
+  };
   Ok(id)
 }
 
-/// Отдаёт доску пользователю.
-pub async fn get_board(db: &Db, board_id: &i64) -> MResult<String> {
-  let board_data = db.read(
-    "select author, shared_with, header, cards, background from boards where id = $1;",
-    &[board_id]
-  ).await?;
-  let author: i64 = board_data.get(0);
-  let shared_with: String = board_data.get(1);
-  let header: String = board_data.get(2);
-  let cards: String = board_data.get(3);
-  let background: String = board_data.get(4);
-  Ok(
-    format!(
-      r#"{{"id":{},"author":{},"shared_with":{},"header":{},"cards":{},"background":"{}"}}"#,
-      *board_id, author, shared_with, header, cards, background
-    )
-  )
+/// Загружает доску, к которой у пользователя есть доступ.
+///
+/// Доска считывается одним запросом и далее передаётся в функции изменения доски, поэтому повторно её строка из базы данных не читается. Доступ проверяется в том же запросе (см. `Storage::member_board`), поэтому доска, к которой у пользователя нет доступа, не считывается и не разбирается.
+pub async fn load_board(db: &dyn Storage, user_id: &i64, board_id: &i64) -> MResult<BoardContext> {
+  let row = db.member_board(board_id, user_id).await?.ok_or(NFO{})?;
+  Ok(BoardContext { user_id: *user_id, ..context_from_row(row)? })
+}
+
+/// Загружает доску от имени её автора без проверки доступа. Используется фоновыми задачами сервера.
+pub async fn load_board_as_author(db: &dyn Storage, board_id: &i64) -> MResult<BoardContext> {
+  context_from_row(db.board(board_id).await?.ok_or(NFO{})?)
+}
+
+/// Собирает контекст доски из её записи в хранилище от имени автора доски.
+fn context_from_row(row: BoardRow) -> MResult<BoardContext> {
+  let mut board = board_from_row(&row)?;
+  // Число задач карточек, записанных прежними версиями сервера, может быть не заполнено, а от него зависит проверка `board_size::check_tasks`.
+  board.cards.refresh_task_counts();
+  let interests = notifications::interests(&board);
+  let facts = automation::facts(&board);
+  let stored = StoredBoard {
+    author: row.author.to_string(),
+    shared_with: row.shared_with,
+    header: row.header,
+    cards: row.cards,
+    background: row.background,
+    tags: row.tags,
+    settings: row.settings,
+    lanes: row.lanes,
+    sprints: row.sprints,
+    watchers: row.watchers,
+  };
+  board_size::record(board.id, BoardSize::measure(&stored, &board.cards));
+  Ok(BoardContext { user_id: board.author, board, interests, facts, stored })
+}
+
+/// Собирает доску из её записи в хранилище.
+///
+/// Если JSON в одной из колонок не соответствует модели, функция возвращает `CorruptBoard` с названием колонки. Карточки, которые уже разбирались, берутся из кэша (см. `board_cache`).
+fn board_from_row(row: &BoardRow) -> Result<Board, CorruptBoard> {
+  fn parse<T: DeserializeOwned>(json: &str, column: &'static str) -> Result<T, CorruptBoard> {
+    serde_json::from_str(json).map_err(|e| CorruptBoard{ column, reason: e.to_string() })
+  }
+  Ok(Board {
+    id: row.id,
+    author: row.author,
+    shared_with: parse(&row.shared_with, "shared_with")?,
+    header: parse(&row.header, "header")?,
+    cards: board_cache::get_or_parse(row.id, row.revision, &row.cards, |json| parse(json, "cards"))?,
+    background: parse(&row.background, "background")?,
+    tags: parse(&row.tags, "tags")?,
+    revision: row.revision,
+    settings: parse(&row.settings, "settings")?,
+    created_at: row.created_at,
+    updated_at: row.updated_at,
+    lanes: parse(&row.lanes, "lanes")?,
+    sprints: parse(&row.sprints, "sprints")?,
+    watchers: parse(&row.watchers, "watchers")?,
+    archived_at: row.archived_at,
+  })
+}
+
+/// Записывает доску в хранилище вместе с дополнительными выражениями (см. `Storage::update_board`).
+///
+/// Доска записывается только тогда, когда её ревизия в базе данных совпадает с загруженной; в противном случае доску уже изменил параллельный запрос, и ничего не записывается.
+///
+/// Перед записью по событию `event` обновляется время изменения затронутой сущности и всех, в которые она вложена (см. `touch`).
+///
+/// Вместе с доской в журнал изменений записывается патч новой ревизии (см. `delta`), а карточки записанной ревизии попадают в кэш (см. `board_cache`). Если карточки изменились немного, хранилище получает только изменения из патча (см. `psql_handler::jsonb`).
+///
+/// Доска в архиве не записывается: функция возвращает `BoardArchived` (см. `retention`). Изменение, после которого доска превысит ограничения размера из конфигурации, тоже не записывается: функция возвращает `BoardTooLarge` (см. `board_size`).
+///
+/// Запись фиксирует транзакцию, в которой доска заблокирована для изменения (см. `Storage::lock_board`), поэтому следующий запрос к доске получает её уже записанной.
+///
+/// После записи публикуется событие `event` (см. `events`), события о задачах, ставших просроченными или близкими к сроку, события о новых получателях уведомлений (см. `notifications`) и события, запускающие правила автоматизации (см. `automation`).
+async fn save_board<'a>(
+  db: &dyn Storage,
+  ctx: &'a mut BoardContext,
+  event: EventKind,
+  queries: Vec<(&'a str, Vec<&'a (dyn ToSql + Sync)>)>,
+) -> MResult<()> {
+  save_board_and_members(db, ctx, event, queries, &[]).await
+}
+
+/// Записывает доску так же, как `save_board`, вместе с новыми списками досок пользователей, когда меняется состав участников доски.
+///
+/// `shared_boards` - пары из идентификатора пользователя и его списка досок в виде JSON (см. `Storage::update_board`).
+async fn save_board_and_members<'a>(
+  db: &dyn Storage,
+  ctx: &'a mut BoardContext,
+  event: EventKind,
+  queries: Vec<(&'a str, Vec<&'a (dyn ToSql + Sync)>)>,
+  shared_boards: &[(i64, String)],
+) -> MResult<()> {
+  custom_error!{RevisionConflict{} = "Доска была изменена параллельным запросом."};
+  if ctx.board.archived_at != 0 { return Err(Box::new(BoardArchived{})); };
+  let now = Utc::now();
+  let overdue_changes = ctx.board.cards.refresh_overdue(&now);
+  let due_soon_changes = ctx.board.cards.refresh_due_soon(&now);
+  let updated_at = now.timestamp();
+  touch(&mut ctx.board.cards, &event, updated_at);
+  notifications::record_assignments(&mut ctx.board.cards, &ctx.interests, ctx.user_id, updated_at);
+  board_size::check_tasks(&ctx.board.cards)?;
+  ctx.board.cards.refresh_task_counts();
+  dependencies::refresh_blocked(&mut ctx.board.cards);
+  let author = ctx.board.author.to_string();
+  let shared_with = jsonb::to_string(&ctx.board.shared_with)?;
+  let header = jsonb::to_string(&ctx.board.header)?;
+  let cards = jsonb::to_string(&ctx.board.cards)?;
+  let background = serde_json::to_string(&ctx.board.background)?;
+  let tags = serde_json::to_string(&ctx.board.tags)?;
+  let settings = serde_json::to_string(&ctx.board.settings)?;
+  let lanes = serde_json::to_string(&ctx.board.lanes)?;
+  let sprints = serde_json::to_string(&ctx.board.sprints)?;
+  let watchers = serde_json::to_string(&ctx.board.watchers)?;
+  let (before_updated_at, after_updated_at) = (ctx.board.updated_at.to_string(), updated_at.to_string());
+  let record = delta::Record::build(ctx.board.id, ctx.board.revision + 1, &[
+    ("author", &ctx.stored.author, &author),
+    ("shared_with", &ctx.stored.shared_with, &shared_with),
+    ("header", &ctx.stored.header, &header),
+    ("cards", &ctx.stored.cards, &cards),
+    ("background", &ctx.stored.background, &background),
+    ("tags", &ctx.stored.tags, &tags),
+    ("settings", &ctx.stored.settings, &settings),
+    ("lanes", &ctx.stored.lanes, &lanes),
+    ("sprints", &ctx.stored.sprints, &sprints),
+    ("watchers", &ctx.stored.watchers, &watchers),
+    ("updated_at", &before_updated_at, &after_updated_at),
+  ])?;
+  let row = BoardRow {
+    id: ctx.board.id,
+    author: ctx.board.author,
+    shared_with,
+    header,
+    cards,
+    background,
+    tags,
+    revision: ctx.board.revision,
+    settings,
+    created_at: ctx.board.created_at,
+    updated_at,
+    lanes,
+    sprints,
+    watchers,
+    archived_at: ctx.board.archived_at,
+  };
+  board_size::check_bytes(ctx, &row)?;
+  let cards_edits = jsonb::edits(record.ops(), "/cards", &row.cards);
+  let mut board_queries = record.queries();
+  board_queries.extend(queries);
+  let written = db.update_board(&row, cards_edits.as_deref(), shared_boards, board_queries).await?;
+  match written {
+    true => {
+      db.commit().await?;
+      ctx.board.revision += 1;
+      ctx.board.updated_at = updated_at;
+      let BoardRow { shared_with, header, cards, background, tags, settings, lanes, sprints, watchers, .. } = row;
+      ctx.stored = StoredBoard { author, shared_with, header, cards, background, tags, settings, lanes, sprints, watchers };
+      board_cache::put(ctx.board.id, ctx.board.revision, &ctx.stored.cards, &ctx.board.cards);
+      board_size::record(ctx.board.id, BoardSize::measure(&ctx.stored, &ctx.board.cards));
+      events::publish(ctx.board.id, Some(ctx.user_id), ctx.board.revision, event);
+      overdue::publish(ctx.board.id, ctx.board.revision, &overdue_changes, &due_soon_changes);
+      let interests = notifications::interests(&ctx.board);
+      notifications::publish(ctx.board.id, ctx.user_id, ctx.board.revision, &ctx.interests, &interests);
+      ctx.interests = interests;
+      let facts = automation::facts(&ctx.board);
+      automation::publish(ctx.board.id, ctx.user_id, ctx.board.revision, &ctx.facts, &facts);
+      ctx.facts = facts;
+      Ok(())
+    },
+    _ => Err(Box::new(RevisionConflict{})),
+  }
+}
+
+/// Обновляет время создания и изменения сущностей, затронутых событием.
+///
+/// У созданной сущности время создания и изменения устанавливается вместе со всеми вложенными сущностями. У изменённой сущности, а также у родителей созданной, изменённой или удалённой сущности обновляется время изменения. Время изменения самой доски обновляет `save_board`.
+fn touch(cards: &mut [Card], event: &EventKind, now: i64) {
+  let (card_id, task_id, subtask_id, created) = match *event {
+    EventKind::CardCreated { card_id } => (card_id, None, None, true),
+    EventKind::CardUpdated { card_id } | EventKind::CardRestored { card_id } | EventKind::TasksImported { card_id, .. } =>
+      (card_id, None, None, false),
+    EventKind::TaskCreated { card_id, task_id } => (card_id, Some(task_id), None, true),
+    EventKind::TaskUpdated { card_id, task_id } | EventKind::TaskRestored { card_id, task_id } |
+    EventKind::RuleApplied { card_id, task_id } => (card_id, Some(task_id), None, false),
+    EventKind::TaskDeleted { card_id, .. } => (card_id, None, None, false),
+    EventKind::SubtaskCreated { card_id, task_id, subtask_id } => (card_id, Some(task_id), Some(subtask_id), true),
+    EventKind::SubtaskUpdated { card_id, task_id, subtask_id } | EventKind::SubtaskRestored { card_id, task_id, subtask_id } =>
+      (card_id, Some(task_id), Some(subtask_id), false),
+    EventKind::SubtaskDeleted { card_id, task_id, .. } => (card_id, Some(task_id), None, false),
+    _ => return,
+  };
+  let card = match cards.iter_mut().find(|card| card.id == card_id) {
+    Some(card) => card,
+    None => return,
+  };
+  card.updated_at = now;
+  let task = match task_id {
+    Some(task_id) => match card.get_mut_task(&task_id) {
+      Ok(task) => task,
+      _ => return,
+    },
+    None => {
+      if created { card.stamp_created(now); };
+      return;
+    },
+  };
+  task.updated_at = now;
+  match subtask_id {
+    Some(subtask_id) => if let Ok(subtask) = task.get_mut_subtask(&subtask_id) {
+      match created {
+        true => subtask.stamp_created(now),
+        false => subtask.updated_at = now,
+      };
+    },
+    None => if created { task.stamp_created(now); },
+  };
+}
+
+/// Доска вместе с профилями упомянутых на ней пользователей.
+#[derive(Serialize)]
+struct BoardWithProfiles<'a> {
+  #[serde(flatten)]
+  board: &'a Board,
+  profiles: Vec<UserProfile>,
+}
+
+/// Отдаёт доску пользователю в виде JSON.
+///
+/// Если передан фильтр, в карточках остаются только удовлетворяющие ему задачи; сами карточки сохраняются, даже если оказываются пустыми, а их `task_count` и признаки `blocked` задач по-прежнему учитывают все задачи. Затем задачи в карточках упорядочиваются согласно `sort`. Если установлен `with_profiles`, в ответ добавляются профили всех упомянутых на доске пользователей. Если установлен `render_html`, заметки задач и подзадач заменяются очищенным HTML.
+///
+/// Доска сериализуется сразу в буфер ответа, без промежуточного дерева JSON и копирования строки.
+pub async fn get_board(
+  db: &dyn Storage,
+  mut ctx: BoardContext,
+  filter: Option<&BoardFilter>,
+  sort: TaskSort,
+  with_profiles: bool,
+  render_html: bool,
+) -> MResult<Vec<u8>> {
+  ctx.board.cards.refresh_task_counts();
+  dependencies::refresh_blocked(&mut ctx.board.cards);
+  if let Some(filter) = filter {
+    let now = Utc::now();
+    for card in &mut ctx.board.cards {
+      card.tasks.retain(|task| task.matches(filter, &now));
+    };
+  };
+  for card in &mut ctx.board.cards {
+    sort.apply(&mut card.tasks);
+  };
+  if render_html {
+    for task in ctx.board.cards.iter_mut().flat_map(|card| card.tasks.iter_mut()) {
+      task.notes = markdown::render(&task.notes);
+      for subtask in &mut task.subtasks {
+        subtask.notes = markdown::render(&subtask.notes);
+      };
+    };
+  };
+  // Записанная доска - хорошая оценка размера ответа.
+  let mut body = Vec::with_capacity(ctx.stored.cards.len() + 1024);
+  match with_profiles {
+    true => {
+      let profiles = get_profiles(db, &ctx.board.mentioned_users()).await?;
+      serde_json::to_writer(&mut body, &BoardWithProfiles { board: &ctx.board, profiles })?;
+    },
+    false => serde_json::to_writer(&mut body, &ctx.board)?,
+  };
+  Ok(body)
+}
+
+/// Проверяет, что в карточке может быть `task_count` задач.
+fn check_wip_limit(card: &Card, task_count: usize) -> Result<(), WipLimitReached> {
+  match card.wip_limit {
+    Some(limit) if task_count > limit.get() as usize => Err(WipLimitReached{ limit: limit.get() }),
+    _ => Ok(()),
+  }
 }
 
 /// Применяет патч на доску.
-pub async fn apply_patch_on_board(db: &Db, user_id: &i64, board_id: &i64, patch: &JsonValue)
-  -> MResult<()>
-{
+pub async fn apply_patch_on_board(db: &dyn Storage, ctx: &mut BoardContext, patch: BoardPatch) -> MResult<()> {
   custom_error!{NTA{} = "Пользователь не может редактировать доску."};
-  let author_id_and_header = db.read("select author, header from boards where id = $1;", &[board_id]).await?;
-  let author_id: i64 = author_id_and_header.get(0);
-  if *user_id != author_id { return Err(Box::new(NTA{})); };
-  let header: String = author_id_and_header.get(1);
-  let mut header: BoardHeader = serde_json::from_str(&header)?;
-  let mut header_patched: bool = false;
-  if let Some(title) = patch.get("title") {
-    let title = String::from(title.as_str().ok_or(NFO{})?);
-    validate_color(&title)?;
-    header.title = title;
-    header_patched = true;
-  };
-  if let Some(background) = patch.get("background") {
-    let background_as_struct: BoardBackground = serde_json::from_value(background.clone())?;
-    if let BoardBackground::Color { color } = background_as_struct {
-      validate_color(&color)?;
+  if ctx.user_id != ctx.board.author { return Err(Box::new(NTA{})); };
+  let header = &mut ctx.board.header;
+  if let Some(title) = patch.title {
+    header.title = validation::title("доски", &title)?;
+  };
+  if let Some(background) = patch.background {
+    if let BoardBackground::Color { color } = &background {
+      validate_color(color)?;
     };
-    let background = serde_json::to_string(&background)?;
-    let r: Vec<&(dyn ToSql + Sync)> = vec![&background, board_id];
-    db.write("update boards set background = $1 where id = $2;", &r).await?;
+    ctx.board.background = background;
   };
-  if let Some(header_background_color) = patch.get("header_background_color") {
-    let header_background_color = String::from(header_background_color.as_str().ok_or(NFO{})?);
+  if let Some(header_background_color) = patch.header_background_color {
     validate_color(&header_background_color)?;
     header.header_background_color = header_background_color;
-    header_patched = true;
   };
-  if let Some(header_text_color) = patch.get("header_text_color") {
-    let header_text_color = String::from(header_text_color.as_str().ok_or(NFO{})?);
+  if let Some(header_text_color) = patch.header_text_color {
     validate_color(&header_text_color)?;
     header.header_text_color = header_text_color;
-    header_patched = true;
   };
-  if header_patched {
-    let header = serde_json::to_string(&header)?;
-    let r: Vec<&(dyn ToSql + Sync)> = vec![&header, board_id];
-    db.write("update boards set header = $1 where id = $2;", &r).await?;
-  }
-  Ok(())
+  if let Some(mut settings) = patch.settings {
+    automation::validate(&mut settings.rules, &ctx.board.cards, &ctx.board.tags, &ctx.board.shared_with)?;
+    recycle::validate(&settings.recycle, &ctx.board.cards)?;
+    ctx.board.settings = settings;
+  };
+  save_board(db, ctx, EventKind::BoardUpdated, vec![]).await
 }
 
 /// Удаляет доску, если её автор - данный пользователь.
 ///
 /// И обходит всех пользователей, удаляя у них id доски. Также удаляет последовательности идентификаторов.
-pub async fn remove_board(db: &Db, user_id: &i64, board_id: &i64) -> MResult<()> {
+pub async fn remove_board(db: &dyn Storage, ctx: BoardContext) -> MResult<()> {
   custom_error!{NTA{} = "Пользователь не может редактировать доску."};
-  let author_id_and_shared_with = db.read("select author, shared_with from boards where id = $1;", &[board_id]).await?;
-  let author_id: i64 = author_id_and_shared_with.get(0);
-  if author_id != *user_id { return Err(Box::new(NTA{})); };
-  let shared_with: Vec<i64> = serde_json::from_str(author_id_and_shared_with.get(1))?;
-  let mut shared_boards_queries = Vec::new();
-  shared_with.iter().for_each(|v| {
-    let r: Vec<&(dyn ToSql + Sync)> = vec![v];
-    shared_boards_queries.push(("select shared_boards from users where id = $1;", r));
-  });
-  let shared_boards: Vec<Vec<i64>> = db.read_mul(shared_boards_queries).await?
-                                       .iter()
-                                       .map(|v| { serde_json::from_str::<Vec<i64>>(v.get(0)).unwrap() })
-                                       .collect();
-  let ids_and_shared_boards: Vec<(i64, Vec<i64>)> = shared_with.into_iter()
-                                                      .zip(shared_boards.into_iter())
-                                                      .collect();
-  let mut tasks = Vec::new();
-  for id_and_shared_board in &ids_and_shared_boards {
-    let board_id = *board_id;
-    let pair = (id_and_shared_board.0, id_and_shared_board.1.clone());
-    let task = tokio::task::spawn(async move {
-      let user_id = pair.0;
-      let mut shared_boards = pair.1;
-      let this_board = shared_boards.iter().position(|id| *id == board_id).ok_or(NFO{})?;
-      shared_boards.swap_remove(this_board);
-      let shared_boards = serde_json::to_string(&shared_boards)?;
-      Result::<(String, i64), Box<dyn std::error::Error + Send + Sync>>::Ok((shared_boards, user_id))
-    });
-    tasks.push(task);
-  };
-  let results = future::try_join_all(tasks).await?;
-  let mut _results = Vec::new();
-  for result in &results {
-    _results.push(result.as_ref().unwrap());
-  };
-  let results: Vec<&(String, i64)> = _results;
-  let mut shared_boards_queries = Vec::new();
-  for result in &results {
-    let r: Vec<&(dyn ToSql + Sync)> = vec![&result.0, &result.1];
-    shared_boards_queries.push(("update users set shared_boards = $1 where id = $2;", r));
-  };
-  shared_boards_queries.push(("delete from boards where id = $1;", vec![board_id]));
-  let board_id_as_str = board_id.to_string();
-  shared_boards_queries.push((
-    "delete from id_seqs where id like concat($1, '_%');", vec![&board_id_as_str]
-  ));
-  db.write_mul(shared_boards_queries).await
-}
-
-/// Подсчитывает все доски пользователя.
-pub async fn count_boards(db: &Db, id: &i64) -> MResult<usize> {
-  Ok(
-    serde_json::from_str::<JsonValue>(
-      db.read("select shared_boards from users where id = $1;", &[id])
-        .await?
-        .get(0)
-    )?.as_array()
-      .ok_or(NFO{})?
-      .len())
-}
-
-/// Проверяет, есть ли доступ у пользователя к данной доске.
-pub async fn in_shared_with(db: &Db, user_id: &i64, board_id: &i64) -> MResult<()> {
-  let mut iter = db.read_mul(vec![
-    ("select shared_boards from users where id = $1;", vec![user_id]),
-    ("select shared_with from boards where id = $1;", vec![board_id]),
-  ]).await?
-    .into_iter()
-    .map(|v| { serde_json::from_str::<Vec<i64>>(v.get(0)).unwrap() });
-  match iter.next().ok_or(NFO{})?.iter().any(|id| *id == *board_id) && 
-        iter.next().ok_or(NFO{})?.iter().any(|id| *id == *user_id) {
-    false => Err(Box::new(NFO{})),
-    _ => Ok(()),
-  }
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NTA{})); };
+  let board_id = &ctx.board.id;
+  let mut shared_boards = Vec::new();
+  for user_id in &ctx.board.shared_with {
+    let mut boards: Vec<i64> = serde_json::from_str(&db.user(user_id).await?.shared_boards)?;
+    let this_board = boards.iter().position(|id| id == board_id).ok_or(NFO{})?;
+    boards.swap_remove(this_board);
+    shared_boards.push((*user_id, serde_json::to_string(&boards)?));
+  };
+  db.delete_board(board_id, &shared_boards).await?;
+  db.commit().await?;
+  board_size::forget(*board_id);
+  events::publish(*board_id, Some(ctx.user_id), ctx.board.revision, EventKind::BoardDeleted);
+  Ok(())
+}
+
+/// Переносит доску в архив или, если `archived` не установлен, возвращает её из архива.
+///
+/// Доска в архиве доступна только для чтения (см. `save_board`) и перечисляется в списке досок отдельно от остальных (см. `list_boards`). Переносить доску в архив и возвращать её может только её автор. В PostgreSQL возвращение доски заодно продлевает её хранение (см. `retention::keep`), иначе правила хранения могли бы сразу перенести доску обратно.
+pub async fn archive_board(db: &dyn Storage, ctx: &BoardContext, archived: bool) -> MResult<()> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotArchiver{})); };
+  if (ctx.board.archived_at != 0) == archived { return Ok(()); };
+  if let (false, Some(pg)) = (archived, db.postgres()) {
+    retention::keep(pg, ctx).await?;
+    return db.commit().await;
+  };
+  let (archived_at, kind) = match archived {
+    true => (Utc::now().timestamp(), EventKind::BoardArchived { author: ctx.board.author }),
+    false => (0, EventKind::BoardUnarchived),
+  };
+  db.set_board_archived(&ctx.board.id, archived_at).await?;
+  db.commit().await?;
+  events::publish(ctx.board.id, Some(ctx.user_id), ctx.board.revision, kind);
+  Ok(())
+}
+
+/// Передаёт доску другому участнику доски.
+///
+/// Передать доску может только её автор. Новый автор должен быть участником доски, не гостевой учётной записью (см. `orgs`), а его доски вместе с этой - укладываться в ограничение его тарифного плана. Прежний автор остаётся участником доски, а новый получает уведомление (см. `notifications`).
+pub async fn transfer_board(db: &dyn Storage, cfg: &AppConfig, ctx: &mut BoardContext, author: &i64) -> MResult<()> {
+  if ctx.user_id != ctx.board.author { return Err(Box::new(NotOwner{})); };
+  if !ctx.board.shared_with.contains(author) { return Err(Box::new(NotMember{ user_id: *author })); };
+  if *author == ctx.board.author { return Ok(()); };
+  if let Some(pg) = db.postgres() {
+    if orgs::is_guest(pg, author).await? { return Err(Box::new(orgs::GuestAccount{})); };
+  };
+  let quota = quota::for_user(db, cfg, author).await?;
+  quota::check("max_boards", quota.max_boards, count_boards(db, author).await? + 1)?;
+  ctx.board.author = *author;
+  save_board(db, ctx, EventKind::BoardTransferred { author: *author }, vec![]).await
+}
+
+/// Удаляет пользователя из участников и наблюдателей доски и из исполнителей и наблюдателей всех её задач и подзадач за один обход дерева карточек.
+///
+/// Вызывается везде, где пользователь теряет доступ к доске, чтобы его идентификатор не оставался в исполнителях и не получал уведомлений о доске. Записи о назначениях удаляются при записи доски (см. `notifications::record_assignments`). Возвращает `true`, если пользователь был исполнителем хотя бы одной задачи или подзадачи.
+pub fn purge_user_from_board(board: &mut Board, user_id: &i64) -> bool {
+  board.shared_with.retain(|id| id != user_id);
+  board.watchers.retain(|id| id != user_id);
+  let mut purged = false;
+  let mut purge = |executors: &mut Vec<i64>| {
+    let len = executors.len();
+    executors.retain(|id| id != user_id);
+    purged |= executors.len() != len;
+  };
+  for task in board.cards.iter_mut().flat_map(|card| card.tasks.iter_mut()) {
+    purge(&mut task.executors);
+    task.watchers.retain(|id| id != user_id);
+    for subtask in &mut task.subtasks {
+      purge(&mut subtask.executors);
+    };
+  };
+  purged
+}
+
+/// Удаляет пользователя из участников доски по его собственному запросу.
+///
+/// Автор не может покинуть доску, пока не передаст её (см. `transfer_board`). Вместе с участием пользователь перестаёт быть исполнителем задач и подзадач доски (см. `purge_user_from_board`), а доска удаляется из его списка досок в той же транзакции.
+pub async fn leave_board(db: &dyn Storage, ctx: &mut BoardContext) -> MResult<()> {
+  if ctx.user_id == ctx.board.author { return Err(Box::new(AuthorCannotLeave{})); };
+  let (user_id, board_id) = (ctx.user_id, ctx.board.id);
+  let mut boards: Vec<i64> = serde_json::from_str(&db.user(&user_id).await?.shared_boards)?;
+  boards.retain(|id| *id != board_id);
+  purge_user_from_board(&mut ctx.board, &user_id);
+  let shared_boards = [(user_id, serde_json::to_string(&boards)?)];
+  save_board_and_members(db, ctx, EventKind::MemberLeft { member: user_id }, vec![], &shared_boards).await
+}
+
+/// Изменяет настройки доски, заданные пользователем: отметку избранного, отключение уведомлений и позицию в списке досок.
+///
+/// Настройки хранятся отдельно для каждого пользователя и видны только ему. Незаданные в патче настройки не изменяются.
+pub async fn set_board_prefs(db: &dyn Storage, user_id: &i64, board_id: &i64, patch: &BoardPrefsPatch) -> MResult<()> {
+  db.set_board_prefs(user_id, board_id, patch).await
+}
+
+/// Подсчитывает доски, автором которых является пользователь.
+pub async fn count_boards(db: &dyn Storage, id: &i64) -> MResult<u64> {
+  db.count_boards(id).await
 }
 
 /// Добавляет карточку в доску.
@@ -310,33 +1047,36 @@ pub async fn in_shared_with(db: &Db, user_id: &i64, board_id: &i64) -> MResult<(
 /// Поскольку содержимое карточки валидируется при десериализации, его безопасно добавлять в базу данных. Но существует возможность добавления нескольких задач/подзадач с идентичными id, поэтому данная функция их переназначает. Помимо этого, по причине авторства пользователя переназначаются идентификаторы авторов во всех вложенных задачах и подзадачах.
 ///
 /// Функция не возвращает идентификаторы задач/подзадач, только id карточки.
-pub async fn insert_card(db: &Db, user_id: &i64, board_id: &i64, mut card: Card) -> MResult<i64> {
+pub async fn insert_card(db: &dyn Storage, cfg: &AppConfig, ctx: &mut BoardContext, card: NewCard) -> MResult<i64> {
+  let mut card = Card::from(card);
+  validation::card(&mut card)?;
+  check_wip_limit(&card, card.tasks.len())?;
   validate_color(&card.background_color)?;
   validate_color(&card.header_text_color)?;
   validate_color(&card.header_background_color)?;
-  let cards_id_seq = board_id.to_string();
-  let mut next_card_id: i64 = match db.read("select val from id_seqs where id = $1;", &[&cards_id_seq]).await {
-    Ok(res) => res.get(0),
-    _ => 1,
-  };
-  let card_id = next_card_id;
-  card.id = next_card_id;
-  card.author = *user_id;
-  let tasks_id_seq = cards_id_seq.clone() + "_" + &next_card_id.to_string();
-  next_card_id += 1;
+  let quota = quota::for_user(db, cfg, &ctx.board.author).await?;
+  quota::check("max_cards_per_board", quota.max_cards_per_board, ctx.board.cards.len() as u64 + 1)?;
+  let cards_id_seq = ctx.board.id.to_string();
+  let min_card_id = ctx.board.cards.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+  let card_id = db.next_id(&cards_id_seq, min_card_id).await?;
+  card.id = card_id;
+  card.author = ctx.user_id;
+  let tasks_id_seq = cards_id_seq + "_" + &card_id.to_string();
   // Все таски и сабтаски у нас новые, поэтому будем обходить их с новыми подпоследовательностями.
   let mut next_task_id: i64 = 1;
-  let shared_with = db.read("select shared_with from boards where id = $1;", &[board_id]).await?;
-  let shared_with: Vec<i64> = serde_json::from_str(shared_with.get(0))?;
-  let shared_with: HashSet<i64> = shared_with.into_iter().collect();
+  let shared_with: HashSet<i64> = ctx.board.shared_with.iter().copied().collect();
+  let board_tags: HashSet<i64> = ctx.board.tags.iter().map(|t| t.id).collect();
+  let board_lanes: HashSet<i64> = ctx.board.lanes.iter().map(|l| l.id).collect();
+  let board_sprints: HashSet<i64> = ctx.board.sprints.iter().map(|s| s.id).collect();
   let mut id_seqs_queries_data: Vec<(String, i64)> = Vec::new();
   for i in 0..card.tasks.len() {
-    for j in 0..card.tasks[i].tags.len() {
-      validate_color(&card.tasks[i].tags[j].background_color)?;
-      validate_color(&card.tasks[i].tags[j].text_color)?;
-    };
+    // Идентификаторы задач переназначаются, поэтому ссылки между ними теряют смысл.
+    card.tasks[i].depends_on.clear();
+    card.tasks[i].tags.retain(|id| board_tags.contains(id));
+    card.tasks[i].lane_id = card.tasks[i].lane_id.filter(|id| board_lanes.contains(id));
+    card.tasks[i].sprint_id = card.tasks[i].sprint_id.filter(|id| board_sprints.contains(id));
     card.tasks[i].id = next_task_id;
-    card.tasks[i].author = *user_id;
+    card.tasks[i].author = ctx.user_id;
     let subtasks_id_seq = tasks_id_seq.clone() + "_" + &next_task_id.to_string();
     next_task_id += 1;
     let mut executors: Vec<i64> = Vec::new();
@@ -344,12 +1084,9 @@ pub async fn insert_card(db: &Db, user_id: &i64, board_id: &i64, mut card: Card)
     card.tasks[i].executors = executors;
     let mut next_subtask_id: i64 = 1;
     for j in 0..card.tasks[i].subtasks.len() {
-      for k in 0..card.tasks[i].subtasks[j].tags.len() {
-        validate_color(&card.tasks[i].subtasks[j].tags[k].background_color)?;
-        validate_color(&card.tasks[i].subtasks[j].tags[k].text_color)?;
-      };
+      card.tasks[i].subtasks[j].tags.retain(|id| board_tags.contains(id));
       card.tasks[i].subtasks[j].id = next_subtask_id;
-      card.tasks[i].subtasks[j].author = *user_id;
+      card.tasks[i].subtasks[j].author = ctx.user_id;
       next_subtask_id += 1;
       let mut executors: Vec<i64> = Vec::new();
       card.tasks[i].subtasks[j].executors
@@ -361,499 +1098,526 @@ pub async fn insert_card(db: &Db, user_id: &i64, board_id: &i64, mut card: Card)
     id_seqs_queries_data.push((subtasks_id_seq, next_subtask_id));
   };
   id_seqs_queries_data.push((tasks_id_seq, next_task_id));
-  id_seqs_queries_data.push((cards_id_seq, next_card_id));
+  ctx.board.cards.push(card);
   let mut id_seqs_queries = Vec::new();
   let query = "insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;";
   for id_seq_query in &id_seqs_queries_data {
     let r: Vec<&(dyn ToSql + Sync)> = vec![&id_seq_query.0, &id_seq_query.1];
     id_seqs_queries.push((query, r));
   };
-  db.write_mul(id_seqs_queries).await?;
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = match serde_json::from_str(cards.get(0)) {
-    Ok(v) => v,
-    _ => Vec::new(),
-  };
-  cards.push(card);
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await?;
+  save_board(db, ctx, EventKind::CardCreated { card_id }, id_seqs_queries).await?;
   Ok(card_id)
 }
 
 /// Применяет патч на карточку.
-pub async fn apply_patch_on_card(db: &Db, board_id: &i64, card_id: &i64, patch: &JsonValue)
+///
+/// Ограничение числа задач можно установить и ниже текущего числа задач: тогда в карточку нельзя добавлять задачи, пока их не станет меньше.
+pub async fn apply_patch_on_card(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, patch: CardPatch)
   -> MResult<()>
 {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let card = cards.get_mut_card(card_id)?;
-  if let Some(title) = patch.get("title") {
-    card.title = String::from(title.as_str().ok_or(NFO{})?);
-  };
-  if let Some(background_color) = patch.get("background_color") {
-    let background_color = String::from(background_color.as_str().ok_or(NFO{})?);
+  let card = ctx.board.cards.get_mut_card(card_id)?;
+  if let Some(title) = patch.title {
+    card.title = validation::title("карточки", &title)?;
+  };
+  if let Some(description) = patch.description {
+    card.description = description;
+  };
+  if let Some(background_color) = patch.background_color {
     validate_color(&background_color)?;
     card.background_color = background_color;
   };
-  if let Some(header_text_color) = patch.get("header_text_color") {
-    let header_text_color = String::from(header_text_color.as_str().ok_or(NFO{})?);
+  if let Some(header_text_color) = patch.header_text_color {
     validate_color(&header_text_color)?;
     card.header_text_color = header_text_color;
   };
-  if let Some(header_background_color) = patch.get("header_background_color") {
-    let header_background_color = String::from(header_background_color.as_str().ok_or(NFO{})?);
+  if let Some(header_background_color) = patch.header_background_color {
     validate_color(&header_background_color)?;
     card.header_background_color = header_background_color;
   };
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  if let Some(mut cover) = patch.cover {
+    if let Some(cover) = &mut cover { validation::cover(cover)?; };
+    card.cover = cover;
+  };
+  if let Some(theme) = patch.theme {
+    if let Some(theme) = &theme { validation::theme(theme)?; };
+    card.theme = theme;
+  };
+  if let Some(wip_limit) = patch.wip_limit {
+    card.wip_limit = wip_limit;
+  };
+  save_board(db, ctx, EventKind::CardUpdated { card_id: *card_id }, vec![]).await
 }
 
 /// Удаляет карточку.
-pub async fn remove_card(db: &Db, board_id: &i64, card_id: &i64) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  cards.remove_card(card_id)?;
-  let cards = serde_json::to_string(&cards)?;
-  let tasks_id_seq = board_id.to_string() + "_" + &card_id.to_string() + "%";
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
+///
+/// Зависимости других задач от задач карточки также удаляются. Удаление можно отменить (см. `undo`).
+pub async fn remove_card(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64) -> MResult<()> {
+  let undo_entry = undo::card(ctx, card_id)?;
+  ctx.board.cards.remove_card(card_id)?;
+  dependencies::forget(&mut ctx.board.cards, |path| path.card_id == *card_id);
+  let tasks_id_seq = ctx.board.id.to_string() + "_" + &card_id.to_string() + "%";
+  let event = EventKind::CardDeleted { card_id: *card_id };
+  let board_id = ctx.board.id;
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
     ("delete from id_seqs where id like $1;", vec![&tasks_id_seq]),
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
+    ("delete from task_history where board_id = $1 and card_id = $2;", vec![&board_id, card_id]),
   ];
-  db.write_mul(queries).await
+  queries.extend(undo_entry.queries());
+  save_board(db, ctx, event, queries).await
 }
 
 /// Создаёт задачу.
-pub async fn insert_task(db: &Db, user_id: &i64, board_id: &i64, card_id: &i64, mut task: Task) 
-  -> MResult<i64> 
-{
-  for i in 0..task.tags.len() {
-    validate_color(&task.tags[i].background_color)?;
-    validate_color(&task.tags[i].text_color)?;
-  };
-  let tasks_id_seq = board_id.to_string() + "_" + &card_id.to_string();
-  let data = db.read("select cards, shared_with from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(data.get(0))?;
-  let shared_with: Vec<i64> = serde_json::from_str(data.get(1))?;
-  let shared_with: HashSet<i64> = shared_with.into_iter().collect();
-  let mut next_task_id: i64 = match db.read("select val from id_seqs where id = $1;", &[&tasks_id_seq]).await {
-    Ok(res) => res.get(0),
-    _ => 1,
-  };
-  task.id = next_task_id;
-  let task_id = next_task_id;
-  task.author = *user_id;
-  next_task_id += 1;
+///
+/// Если в карточке уже столько задач, сколько позволяет её ограничение `wip_limit`, функция возвращает `WipLimitReached`.
+pub async fn insert_task(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task: NewTask) -> MResult<i64> {
+  let mut task = Task::from(task);
+  validation::task(&mut task)?;
+  let card = ctx.board.cards.get_mut_card(card_id)?;
+  check_wip_limit(card, card.tasks.len() + 1)?;
+  let tasks_id_seq = ctx.board.id.to_string() + "_" + &card_id.to_string();
+  let shared_with: HashSet<i64> = ctx.board.shared_with.iter().copied().collect();
+  let board_tags: HashSet<i64> = ctx.board.tags.iter().map(|t| t.id).collect();
+  task.tags.retain(|id| board_tags.contains(id));
+  task.lane_id = task.lane_id.filter(|id| ctx.board.lanes.iter().any(|l| l.id == *id));
+  task.sprint_id = task.sprint_id.filter(|id| ctx.board.sprints.iter().any(|s| s.id == *id));
+  // Новая задача может зависеть только от существующих: от неё самой пока ничего не зависит, поэтому цикла не возникает.
+  let mut seen = HashSet::new();
+  task.depends_on.retain(|path| ctx.board.cards.get_task(&path.card_id, &path.task_id).is_ok() && seen.insert(*path));
+  let min_task_id = ctx.board.cards.get_mut_card(card_id)?.tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+  let task_id = db.next_id(&tasks_id_seq, min_task_id).await?;
+  task.id = task_id;
+  task.author = ctx.user_id;
   let mut executors: Vec<i64> = Vec::new();
   task.executors.iter().filter(|e| shared_with.contains(e)).for_each(|i| executors.push(*i));
   task.executors = executors;
-  let subtasks_id_seq = tasks_id_seq.clone() + "_" + &next_task_id.to_string();
+  let subtasks_id_seq = tasks_id_seq + "_" + &task_id.to_string();
   let mut next_subtask_id: i64 = 1;
   for i in 0..task.subtasks.len() {
-    for j in 0..task.subtasks[i].tags.len() {
-      validate_color(&task.subtasks[i].tags[j].background_color)?;
-      validate_color(&task.subtasks[i].tags[j].text_color)?;
-    };
+    task.subtasks[i].tags.retain(|id| board_tags.contains(id));
     task.subtasks[i].id = next_subtask_id;
-    task.subtasks[i].author = *user_id;
+    task.subtasks[i].author = ctx.user_id;
     next_subtask_id += 1;
     let mut executors: Vec<i64> = Vec::new();
     task.subtasks[i].executors.iter().filter(|e| shared_with.contains(e)).for_each(|i| executors.push(*i));
     task.subtasks[i].executors = executors;
   };
-  cards.get_mut_card(card_id)?.tasks.push(task);
-  let cards = serde_json::to_string(&cards)?;
+  ctx.board.cards.get_mut_card(card_id)?.tasks.push(task);
   let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
     ("insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;", vec![&subtasks_id_seq, &next_subtask_id]),
-    ("insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;", vec![&tasks_id_seq, &next_task_id]),
   ];
-  db.write_mul(queries).await?;
+  save_board(db, ctx, EventKind::TaskCreated { card_id: *card_id, task_id }, queries).await?;
   Ok(task_id)
 }
 
 /// Применяет патч на задачу.
+///
+/// Если в патче задана базовая ревизия и после неё кто-то другой изменил поле, которое патч меняет на другое значение, функция возвращает `TaskConflict` с описанием таких полей, и задача не изменяется.
 pub async fn apply_patch_on_task(
-  db: &Db,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
-  patch: &JsonValue
+  patch: TaskPatch
 ) -> MResult<()> {
-  let data = db.read("select cards, shared_with from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(data.get(0))?;
-  let task = cards.get_mut_task(card_id, task_id)?;
-  if let Some(title) = patch.get("title") {
-    task.title = String::from(title.as_str().ok_or(NFO{})?);
-  };
-  if let Some(executors) = patch.get("executors") {
-    let shared_with: Vec<i64> = serde_json::from_str(data.get(1))?;
-    let shared_with: HashSet<i64> = shared_with.into_iter().collect();
-    let executors: Vec<i64> = serde_json::from_value(executors.clone())?;
+  let shared_with = &ctx.board.shared_with;
+  if let Some(Some(lane_id)) = patch.lane_id {
+    if !ctx.board.lanes.iter().any(|l| l.id == lane_id) { return Err(Box::new(LNF{})); };
+  };
+  if let Some(Some(sprint_id)) = patch.sprint_id {
+    if !ctx.board.sprints.iter().any(|s| s.id == sprint_id) { return Err(Box::new(NoSuchSprint{})); };
+  };
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let (fields, base_revision) = (patch.fields(), patch.base_revision);
+  let task = ctx.board.cards.get_mut_task(card_id, task_id)?;
+  if let Some(title) = patch.title {
+    task.title = validation::title("задачи", &title)?;
+  };
+  if let Some(executors) = patch.executors {
     task.executors = Vec::new();
     executors.iter()
              .filter(|e| shared_with.contains(e))
              .for_each(|i| task.executors.push(*i));
   };
-  if let Some(exec) = patch.get("exec") {
-    task.exec = exec.as_bool().ok_or(NFO{})?;
+  if let Some(exec) = patch.exec {
+    task.exec = exec;
+  };
+  if let Some(description) = patch.description {
+    task.description = description;
+  };
+  if let Some(notes) = patch.notes {
+    task.notes = markdown::sanitize(&notes);
+  };
+  if let Some(priority) = patch.priority {
+    task.priority = priority;
+  };
+  if let Some(exec_propagation) = patch.exec_propagation {
+    task.exec_propagation = exec_propagation;
   };
-  if let Some(notes) = patch.get("notes") {
-    task.notes = String::from(notes.as_str().ok_or(NFO{})?);
+  if let Some(lane_id) = patch.lane_id {
+    task.lane_id = lane_id;
+  };
+  if let Some(sprint_id) = patch.sprint_id {
+    task.sprint_id = sprint_id;
+  };
+  if let Some(story_points) = patch.story_points {
+    task.story_points = story_points;
+  };
+  if let Some(base_revision) = base_revision {
+    let conflicts = task_history::conflicts(ctx, &before, &fields, base_revision)?;
+    if !conflicts.is_empty() { return Err(Box::new(TaskConflict{ conflicts })); };
+  };
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
+}
+
+/// Назначает исполнителей задачи.
+///
+/// В отличие от патча задачи, который отбрасывает исполнителей без доступа к доске, функция возвращает `NotMember`, если хотя бы один из исполнителей не участник доски, и задача не изменяется. Повторы в списке отбрасываются. Новые исполнители получают уведомления о назначении (см. `notifications`).
+///
+/// Возвращает записи о назначении исполнителей задачи после изменения.
+pub async fn set_task_executors(
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
+  card_id: &i64,
+  task_id: &i64,
+  executors: &[i64],
+) -> MResult<Vec<Assignment>> {
+  if let Some(executor) = executors.iter().find(|id| !ctx.board.shared_with.contains(id)) {
+    return Err(Box::new(NotMember{ user_id: *executor }));
+  };
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let mut seen = HashSet::new();
+  ctx.board.cards.get_mut_task(card_id, task_id)?.executors = executors.iter().copied().filter(|id| seen.insert(*id)).collect();
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await?;
+  Ok(ctx.board.cards.get_task(card_id, task_id)?.assignments.clone())
+}
+
+/// Включает или отключает наблюдение пользователя за задачей.
+///
+/// Следить за задачей может любой участник доски, не только её исполнители. Наблюдатели получают уведомления об изменениях задачи (см. `notifications`). Если пользователь уже следит или уже не следит за задачей, доска не записывается.
+pub async fn watch_task(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64, watch: bool) -> MResult<()> {
+  let user_id = ctx.user_id;
+  if !toggle_watcher(&mut ctx.board.cards.get_mut_task(card_id, task_id)?.watchers, user_id, watch) { return Ok(()); };
+  save_board(db, ctx, EventKind::WatchersChanged { card_id: Some(*card_id), task_id: Some(*task_id) }, vec![]).await
+}
+
+/// Включает или отключает наблюдение пользователя за всеми изменениями доски так же, как `watch_task`.
+pub async fn watch_board(db: &dyn Storage, ctx: &mut BoardContext, watch: bool) -> MResult<()> {
+  let user_id = ctx.user_id;
+  if !toggle_watcher(&mut ctx.board.watchers, user_id, watch) { return Ok(()); };
+  save_board(db, ctx, EventKind::WatchersChanged { card_id: None, task_id: None }, vec![]).await
+}
+
+/// Добавляет пользователя в наблюдатели или удаляет из них. Возвращает `true`, если список наблюдателей изменился.
+fn toggle_watcher(watchers: &mut Vec<i64>, user_id: i64, watch: bool) -> bool {
+  if watchers.contains(&user_id) == watch { return false; };
+  match watch {
+    true => watchers.push(user_id),
+    false => watchers.retain(|id| *id != user_id),
   };
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  true
 }
 
 /// Удаляет задачу.
-pub async fn remove_task(db: &Db, board_id: &i64, card_id: &i64, task_id: &i64)
-  -> MResult<()>
-{
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  cards.remove_task(card_id, task_id)?;
-  let cards = serde_json::to_string(&cards)?;
-  let subtasks_id_seq = board_id.to_string() + "_" + &card_id.to_string() + "_" + &task_id.to_string();
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
+///
+/// Зависимости других задач от неё также удаляются. Удаление можно отменить (см. `undo`).
+pub async fn remove_task(db: &dyn Storage, ctx: &mut BoardContext, card_id: &i64, task_id: &i64) -> MResult<()> {
+  let undo_entry = undo::task(ctx, card_id, task_id)?;
+  ctx.board.cards.remove_task(card_id, task_id)?;
+  dependencies::forget(&mut ctx.board.cards, |path| path.card_id == *card_id && path.task_id == *task_id);
+  let subtasks_id_seq = ctx.board.id.to_string() + "_" + &card_id.to_string() + "_" + &task_id.to_string();
+  let board_id = ctx.board.id;
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
     ("delete from id_seqs where id = $1;", vec![&subtasks_id_seq]),
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
+    ("delete from task_history where board_id = $1 and card_id = $2 and task_id = $3;", vec![&board_id, card_id, task_id]),
   ];
-  db.write_mul(queries).await
+  queries.extend(undo_entry.queries());
+  save_board(db, ctx, EventKind::TaskDeleted { card_id: *card_id, task_id: *task_id }, queries).await
 }
 
 /// Устанавливает временные рамки на задачу.
 pub async fn set_timelines_on_task(
-  db: &Db,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   timelines: &Timelines,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  cards.get_mut_task(card_id, task_id)?.timelines = timelines.clone();
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  ctx.board.cards.get_mut_task(card_id, task_id)?.timelines = timelines.clone();
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
 }
 
 /// Создаёт подзадачу.
 pub async fn insert_subtask(
-  db: &Db,
-  user_id: &i64,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
-  mut subtask: Subtask,
+  subtask: NewSubtask,
 ) -> MResult<i64> {
-  for i in 0..subtask.tags.len() {
-    validate_color(&subtask.tags[i].background_color)?;
-    validate_color(&subtask.tags[i].text_color)?;
-  };
-  let subtasks_id_seq = board_id.to_string() + "_" + &card_id.to_string() + "_" + &task_id.to_string();
-  let data = db.read("select cards, shared_with from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(data.get(0))?;
-  let shared_with: Vec<i64> = serde_json::from_str(data.get(1))?;
-  let shared_with: HashSet<i64> = shared_with.into_iter().collect();
-  let mut next_subtask_id: i64 = match db.read("select val from id_seqs where id = $1;", &[&subtasks_id_seq]).await {
-    Ok(res) => res.get(0),
-    _ => 1,
-  };
-  subtask.id = next_subtask_id;
-  let subtask_id = next_subtask_id;
-  subtask.author = *user_id;
-  next_subtask_id += 1;
+  let mut subtask = Subtask::from(subtask);
+  validation::subtask(&mut subtask)?;
+  let subtasks_id_seq = ctx.board.id.to_string() + "_" + &card_id.to_string() + "_" + &task_id.to_string();
+  let shared_with: HashSet<i64> = ctx.board.shared_with.iter().copied().collect();
+  let board_tags: HashSet<i64> = ctx.board.tags.iter().map(|t| t.id).collect();
+  subtask.tags.retain(|id| board_tags.contains(id));
+  let task = ctx.board.cards.get_mut_task(card_id, task_id)?;
+  let min_subtask_id = task.subtasks.iter().map(|st| st.id).max().unwrap_or(0) + 1;
+  let subtask_id = db.next_id(&subtasks_id_seq, min_subtask_id).await?;
+  subtask.id = subtask_id;
+  subtask.author = ctx.user_id;
   let mut executors: Vec<i64> = Vec::new();
   subtask.executors.iter().filter(|e| shared_with.contains(e)).for_each(|i| executors.push(*i));
   subtask.executors = executors;
-  cards.get_mut_task(card_id, task_id)?.subtasks.push(subtask);
-  let cards = serde_json::to_string(&cards)?;
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
-    ("insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;", vec![&subtasks_id_seq, &next_subtask_id]),
-  ];
-  db.write_mul(queries).await?;
+  ctx.board.cards.get_mut_task(card_id, task_id)?.subtasks.push(subtask);
+  save_board(db, ctx, EventKind::SubtaskCreated { card_id: *card_id, task_id: *task_id, subtask_id }, vec![]).await?;
   Ok(subtask_id)
 }
 
 /// Применяет патч на подзадачу.
 pub async fn apply_patch_on_subtask(
-  db: &Db,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   subtask_id: &i64,
-  patch: &JsonValue,
+  patch: SubtaskPatch,
 ) -> MResult<()> {
-  let data = db.read("select cards, shared_with from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(data.get(0))?;
-  let subtask = cards.get_mut_subtask(card_id, task_id, subtask_id)?;
-  if let Some(title) = patch.get("title") {
-    subtask.title = String::from(title.as_str().ok_or(NFO{})?);
-  };
-  if let Some(executors) = patch.get("executors") {
-    let shared_with: Vec<i64> = serde_json::from_str(data.get(1))?;
-    let shared_with: HashSet<i64> = shared_with.into_iter().collect();
-    let executors: Vec<i64> = serde_json::from_value(executors.clone())?;
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let shared_with = &ctx.board.shared_with;
+  let subtask = ctx.board.cards.get_mut_subtask(card_id, task_id, subtask_id)?;
+  if let Some(title) = patch.title {
+    subtask.title = validation::title("подзадачи", &title)?;
+  };
+  if let Some(executors) = patch.executors {
     subtask.executors = Vec::new();
     executors.iter()
              .filter(|e| shared_with.contains(e))
              .for_each(|i| subtask.executors.push(*i));
   };
-  if let Some(exec) = patch.get("exec") {
-    subtask.exec = exec.as_bool().ok_or(NFO{})?;
+  if let Some(exec) = patch.exec {
+    subtask.exec = exec;
+  };
+  if let Some(description) = patch.description {
+    subtask.description = description;
+  };
+  if let Some(notes) = patch.notes {
+    subtask.notes = markdown::sanitize(&notes);
   };
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  if let Some(priority) = patch.priority {
+    subtask.priority = priority;
+  };
+  if patch.exec.is_some() {
+    let board_default = ctx.board.settings.exec_propagation;
+    ctx.board.cards.get_mut_task(card_id, task_id)?.propagate_exec(board_default);
+  };
+  // Статус выполнения подзадачи может распространиться на задачу.
+  let changes = before.changes(ctx)?;
+  let event = EventKind::SubtaskUpdated { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id };
+  save_board(db, ctx, event, changes.queries()).await
 }
 
-/// Удаляет подзадачу.
+/// Удаляет подзадачу. Удаление можно отменить (см. `undo`).
 pub async fn remove_subtask(
-  db: &Db,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   subtask_id: &i64,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  cards.remove_subtask(card_id, task_id, subtask_id)?;
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  let undo_entry = undo::subtask(ctx, card_id, task_id, subtask_id)?;
+  ctx.board.cards.remove_subtask(card_id, task_id, subtask_id)?;
+  let event = EventKind::SubtaskDeleted { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id };
+  save_board(db, ctx, event, undo_entry.queries()).await
 }
 
 /// Устанавливает временные рамки на подзадачу.
 pub async fn set_timelines_on_subtask(
-  db: &Db,
-  board_id: &i64,
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   subtask_id: &i64,
   timelines: &Timelines,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  cards.get_mut_subtask(card_id, task_id, subtask_id)?.timelines = timelines.clone();
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  ctx.board.cards.get_mut_subtask(card_id, task_id, subtask_id)?.timelines = timelines.clone();
+  save_board(db, ctx, EventKind::SubtaskUpdated { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id }, vec![]).await
 }
 
 /// Получает теги подзадачи.
-pub async fn get_subtask_tags(
-  db: &Db,
-  board_id: &i64,
-  card_id: &i64,
-  task_id: &i64,
-  subtask_id: &i64,
-) -> MResult<String> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let tags = &cards.get_subtask(card_id, task_id, subtask_id)?.tags;
+pub fn get_subtask_tags(ctx: &BoardContext, card_id: &i64, task_id: &i64, subtask_id: &i64) -> MResult<String> {
+  let tag_ids = &ctx.board.cards.get_subtask(card_id, task_id, subtask_id)?.tags;
+  let tags: Vec<&Tag> = ctx.board.tags.iter().filter(|t| tag_ids.contains(&t.id)).collect();
   Ok(serde_json::to_string(&tags)?)
 }
 
+/// Получает историю изменений задачи (см. `task_history`).
+pub async fn get_task_history(db: &Db, ctx: &BoardContext, card_id: &i64, task_id: &i64) -> MResult<String> {
+  ctx.board.cards.get_task(card_id, task_id)?;
+  let history = task_history::list(db, &ctx.board.id, card_id, task_id).await?;
+  Ok(serde_json::to_string(&history)?)
+}
+
 /// Получает теги задачи.
-pub async fn get_task_tags(
-  db: &Db,
-  board_id: &i64,
-  card_id: &i64,
-  task_id: &i64,
-) -> MResult<String> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let tags = &cards.get_task(card_id, task_id)?.tags;
+pub fn get_task_tags(ctx: &BoardContext, card_id: &i64, task_id: &i64) -> MResult<String> {
+  let tag_ids = &ctx.board.cards.get_task(card_id, task_id)?.tags;
+  let tags: Vec<&Tag> = ctx.board.tags.iter().filter(|t| tag_ids.contains(&t.id)).collect();
   Ok(serde_json::to_string(&tags)?)
 }
 
-/// Создаёт тег у подзадачи.
-pub async fn create_tag_at_subtask(
-  db: &Db,
-  board_id: &i64,
-  card_id: &i64,
-  task_id: &i64,
-  subtask_id: &i64,
-  tag: &Tag,
-) -> MResult<i64> {
+/// Создаёт тег в словаре доски.
+pub async fn create_board_tag(db: &dyn Storage, ctx: &mut BoardContext, tag: &Tag) -> MResult<i64> {
+  let title = validation::title("тега", &tag.title)?;
   validate_color(&tag.text_color)?;
   validate_color(&tag.background_color)?;
-  let subtask_tags_id_seq = 
-    board_id.to_string() + "_" + 
-    &card_id.to_string() + "_" + 
-    &task_id.to_string() + "_" +
-    &subtask_id.to_string() + "t";
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("select cards from boards where id = $1;", vec![board_id]),
-    ("select val from id_seqs where id = $1;", vec![&subtask_tags_id_seq]),
-  ];
-  let results = db.read_mul(queries).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(results[0].get(0))?;
-  let mut id: i64 = results[1].try_get(0).unwrap_or(0);
-  id += 1;
+  let board_tags_id_seq = ctx.board.id.to_string() + "_tags";
+  let min_tag_id = ctx.board.tags.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+  let id = db.next_id(&board_tags_id_seq, min_tag_id).await?;
   let mut tag = tag.clone();
   tag.id = id;
-  cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags.push(tag);
-  let cards = serde_json::to_string(&cards)?;
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
-    (
-      "insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;",
-      vec![&subtask_tags_id_seq, &id],
-    ),
-  ];
-  db.write_mul(queries).await?;
+  tag.title = title;
+  ctx.board.tags.push(tag);
+  save_board(db, ctx, EventKind::TagCreated { tag_id: id }, vec![]).await?;
   Ok(id)
 }
 
-/// Создаёт тег у задачи.
-pub async fn create_tag_at_task(
-  db: &Db,
-  board_id: &i64,
-  card_id: &i64,
-  task_id: &i64,
-  tag: &Tag,
-) -> MResult<i64> {
-  validate_color(&tag.text_color)?;
-  validate_color(&tag.background_color)?;
-  let task_tags_id_seq = 
-    board_id.to_string() + "_" + 
-    &card_id.to_string() + "_" + 
-    &task_id.to_string() + "t";
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("select cards from boards where id = $1;", vec![board_id]),
-    ("select val from id_seqs where id = $1;", vec![&task_tags_id_seq]),
-  ];
-  let results = db.read_mul(queries).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(results[0].get(0))?;
-  let mut id: i64 = results[1].try_get(0).unwrap_or(0);
-  id += 1;
-  let mut tag = tag.clone();
-  tag.id = id;
-  cards.get_mut_task(card_id, task_id)?.tags.push(tag);
-  let cards = serde_json::to_string(&cards)?;
-  let queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![
-    ("update boards set cards = $1 where id = $2;", vec![&cards, board_id]),
-    (
-      "insert into id_seqs values ($1, $2) on conflict (id) do update set val = excluded.val;",
-      vec![&task_tags_id_seq, &id],
-    ),
-  ];
-  db.write_mul(queries).await?;
+/// Редактирует тег в словаре доски.
+///
+/// Изменения тега видны во всех задачах и подзадачах, которые на него ссылаются.
+pub async fn patch_board_tag(db: &dyn Storage, ctx: &mut BoardContext, tag_id: &i64, patch: TagPatch) -> MResult<()> {
+  let tag = ctx.board.tags.iter_mut().find(|t| t.id == *tag_id).ok_or(TNF{})?;
+  if let Some(title) = patch.title {
+    tag.title = validation::title("тега", &title)?;
+  };
+  if let Some(background_color) = patch.background_color {
+    validate_color(&background_color)?;
+    tag.background_color = background_color;
+  };
+  if let Some(text_color) = patch.text_color {
+    validate_color(&text_color)?;
+    tag.text_color = text_color;
+  };
+  save_board(db, ctx, EventKind::TagUpdated { tag_id: *tag_id }, vec![]).await
+}
+
+/// Удаляет тег из словаря доски.
+///
+/// Вместе с тегом удаляются и все ссылки на него из задач и подзадач доски.
+pub async fn delete_board_tag(db: &dyn Storage, ctx: &mut BoardContext, tag_id: &i64) -> MResult<()> {
+  let board_tags = &mut ctx.board.tags;
+  board_tags.remove(board_tags.iter().position(|t| t.id == *tag_id).ok_or(TNF{})?);
+  for card in &mut ctx.board.cards {
+    for task in &mut card.tasks {
+      task.tags.retain(|id| *id != *tag_id);
+      for subtask in &mut task.subtasks {
+        subtask.tags.retain(|id| *id != *tag_id);
+      };
+    };
+  };
+  save_board(db, ctx, EventKind::TagDeleted { tag_id: *tag_id }, vec![]).await
+}
+
+/// Создаёт дорожку доски.
+pub async fn create_board_lane(db: &dyn Storage, ctx: &mut BoardContext, lane: &Lane) -> MResult<i64> {
+  let title = validation::title("дорожки", &lane.title)?;
+  validate_color(&lane.color)?;
+  let board_lanes_id_seq = ctx.board.id.to_string() + "_lanes";
+  let min_lane_id = ctx.board.lanes.iter().map(|l| l.id).max().unwrap_or(0) + 1;
+  let id = db.next_id(&board_lanes_id_seq, min_lane_id).await?;
+  let mut lane = lane.clone();
+  lane.id = id;
+  lane.title = title;
+  ctx.board.lanes.push(lane);
+  save_board(db, ctx, EventKind::LaneCreated { lane_id: id }, vec![]).await?;
   Ok(id)
 }
 
-/// Редактирует тег в подзадаче.
-pub async fn patch_tag_at_subtask(
-  db: &Db,
-  board_id: &i64,
+/// Редактирует дорожку доски.
+pub async fn patch_board_lane(db: &dyn Storage, ctx: &mut BoardContext, lane_id: &i64, patch: LanePatch) -> MResult<()> {
+  let lane = ctx.board.lanes.iter_mut().find(|l| l.id == *lane_id).ok_or(LNF{})?;
+  if let Some(title) = patch.title {
+    lane.title = validation::title("дорожки", &title)?;
+  };
+  if let Some(color) = patch.color {
+    validate_color(&color)?;
+    lane.color = color;
+  };
+  save_board(db, ctx, EventKind::LaneUpdated { lane_id: *lane_id }, vec![]).await
+}
+
+/// Удаляет дорожку доски.
+///
+/// Задачи, находившиеся в дорожке, остаются на доске вне дорожек.
+pub async fn delete_board_lane(db: &dyn Storage, ctx: &mut BoardContext, lane_id: &i64) -> MResult<()> {
+  let board_lanes = &mut ctx.board.lanes;
+  board_lanes.remove(board_lanes.iter().position(|l| l.id == *lane_id).ok_or(LNF{})?);
+  for card in &mut ctx.board.cards {
+    for task in &mut card.tasks {
+      if task.lane_id == Some(*lane_id) { task.lane_id = None; };
+    };
+  };
+  save_board(db, ctx, EventKind::LaneDeleted { lane_id: *lane_id }, vec![]).await
+}
+
+/// Прикрепляет тег из словаря доски к подзадаче.
+pub async fn attach_tag_to_subtask(
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   subtask_id: &i64,
   tag_id: &i64,
-  patch: &JsonValue,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let mut tags = cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags.clone();
-  let mut patched: bool = false;
-  for tag in &mut tags {
-    if tag.id == *tag_id {
-      patched = true;
-      if let Some(title) = patch.get("title") {
-        tag.title = String::from(title.as_str().ok_or(NFO{})?);
-      };
-      if let Some(background_color) = patch.get("background_color") {
-        let background_color = String::from(background_color.as_str().ok_or(NFO{})?);
-        validate_color(&background_color)?;
-        tag.background_color = background_color;
-      };
-      if let Some(text_color) = patch.get("text_color") {
-        let text_color = String::from(text_color.as_str().ok_or(NFO{})?);
-        validate_color(&text_color)?;
-        tag.text_color = text_color;
-      };
-      break;
-    };
-  };
-  if patched {
-    cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags = tags.to_vec();
-    let cards = serde_json::to_string(&cards)?;
-    db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
-  } else {
-    Err(Box::new(TNF{}))
-  }
+  if !ctx.board.tags.iter().any(|t| t.id == *tag_id) { return Err(Box::new(TNF{})); };
+  let subtask = ctx.board.cards.get_mut_subtask(card_id, task_id, subtask_id)?;
+  if !subtask.tags.contains(tag_id) { subtask.tags.push(*tag_id); };
+  save_board(db, ctx, EventKind::SubtaskUpdated { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id }, vec![]).await
 }
 
-/// Редактирует тег в задаче.
-pub async fn patch_tag_at_task(
-  db: &Db,
-  board_id: &i64,
+/// Прикрепляет тег из словаря доски к задаче.
+pub async fn attach_tag_to_task(
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   tag_id: &i64,
-  patch: &JsonValue,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let mut tags = cards.get_mut_task(card_id, task_id)?.tags.clone();
-  let mut patched: bool = false;
-  for tag in &mut tags {
-    if tag.id == *tag_id {
-      patched = true;
-      if let Some(title) = patch.get("title") {
-        tag.title = String::from(title.as_str().ok_or(NFO{})?);
-      };
-      if let Some(background_color) = patch.get("background_color") {
-        let background_color = String::from(background_color.as_str().ok_or(NFO{})?);
-        validate_color(&background_color)?;
-        tag.background_color = background_color;
-      };
-      if let Some(text_color) = patch.get("text_color") {
-        let text_color = String::from(text_color.as_str().ok_or(NFO{})?);
-        validate_color(&text_color)?;
-        tag.text_color = text_color;
-      };
-      break;
-    };
-  };
-  if patched {
-    cards.get_mut_task(card_id, task_id)?.tags = tags.to_vec();
-    let cards = serde_json::to_string(&cards)?;
-    db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
-  } else {
-    Err(Box::new(TNF{}))
-  }
+  if !ctx.board.tags.iter().any(|t| t.id == *tag_id) { return Err(Box::new(TNF{})); };
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let task = ctx.board.cards.get_mut_task(card_id, task_id)?;
+  if !task.tags.contains(tag_id) { task.tags.push(*tag_id); };
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
 }
 
-/// Удаляет тег подзадачи.
-pub async fn delete_tag_at_subtask(
-  db: &Db,
-  board_id: &i64,
+/// Открепляет тег от подзадачи.
+pub async fn detach_tag_from_subtask(
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   subtask_id: &i64,
   tag_id: &i64,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let mut tags = cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags.clone();
-  tags.remove(tags.iter().position(|x| x.id == *tag_id).ok_or(NFO{})?);
-  cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags = tags.to_vec();
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
-}
-
-/// Удаляет тег задачи.
-pub async fn delete_tag_at_task(
-  db: &Db,
-  board_id: &i64,
+  let tags = &mut ctx.board.cards.get_mut_subtask(card_id, task_id, subtask_id)?.tags;
+  tags.remove(tags.iter().position(|id| *id == *tag_id).ok_or(TNF{})?);
+  save_board(db, ctx, EventKind::SubtaskUpdated { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id }, vec![]).await
+}
+
+/// Открепляет тег от задачи.
+pub async fn detach_tag_from_task(
+  db: &dyn Storage,
+  ctx: &mut BoardContext,
   card_id: &i64,
   task_id: &i64,
   tag_id: &i64,
 ) -> MResult<()> {
-  let cards = db.read("select cards from boards where id = $1;", &[board_id]).await?;
-  let mut cards: Vec<Card> = serde_json::from_str(cards.get(0))?;
-  let mut tags = cards.get_mut_task(card_id, task_id)?.tags.clone();
-  tags.remove(tags.iter().position(|x| x.id == *tag_id).ok_or(NFO{})?);
-  cards.get_mut_task(card_id, task_id)?.tags = tags.to_vec();
-  let cards = serde_json::to_string(&cards)?;
-  db.write("update boards set cards = $1 where id = $2;", &[&cards, board_id]).await
+  let before = task_history::snapshot(ctx, card_id, task_id)?;
+  let tags = &mut ctx.board.cards.get_mut_task(card_id, task_id)?.tags;
+  tags.remove(tags.iter().position(|id| *id == *tag_id).ok_or(TNF{})?);
+  let changes = before.changes(ctx)?;
+  save_board(db, ctx, EventKind::TaskUpdated { card_id: *card_id, task_id: *task_id }, changes.queries()).await
 }
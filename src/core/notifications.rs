@@ -0,0 +1,362 @@
+//! Отвечает за уведомления пользователей внутри приложения.
+//!
+//! Уведомления создаются подписчиком канала событий (см. `events`, `run`) и хранятся в таблице `notifications`, откуда клиенты периодически их забирают. Пользователь получает уведомление, когда:
+//!
+//! - его назначают исполнителем задачи или подзадачи;
+//! - его логин упоминают в заметках задачи или подзадачи (`@login`);
+//! - до обязательного срока задачи, исполнителем которой он назначен или за которой он следит, остаётся меньше суток;
+//! - меняется задача, за которой он следит, или что-либо на доске, за которой он следит (см. `core::watch_task`, `core::watch_board`);
+//! - ему открывают доступ к доске;
+//! - ему передают доску;
+//! - доска, автором которой он является, отмечена неактивной, перенесена в архив или выгружена и удалена (см. `retention`);
+//! - в его аккаунт входят из нового места или вход в аккаунт блокируется после неудачных попыток (см. `security_events`).
+//!
+//! Чтобы отличить новые назначения и упоминания от уже существовавших, доска при загрузке запоминает своих получателей уведомлений (`interests`), а `save_board` после записи публикует события только о новых. Пользователь не получает уведомлений о собственных действиях и уведомлений с досок, уведомления которых он отключил. Прочитанные уведомления удаляются через `READ_TTL_SECS`.
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::core::events::{self, Coalescer, EventKind};
+use crate::model::{Assignment, Board, Card, Cards, TaskPath};
+use crate::psql_handler::Db;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число уведомлений, которое можно получить за один запрос.
+pub const MAX_NOTIFICATIONS_PAGE: i64 = 100;
+
+/// Число секунд, после которых удаляются прочитанные уведомления.
+const READ_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Задача или подзадача доски.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+  pub card_id: i64,
+  pub task_id: i64,
+  pub subtask_id: Option<i64>,
+}
+
+/// Получатель уведомлений на доске и причина, по которой он их получает.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Interest {
+  /// Участник доски.
+  Member(i64),
+  /// Исполнитель задачи или подзадачи.
+  Executor(Target, i64),
+  /// Логин, упомянутый в заметках задачи или подзадачи.
+  Mention(Target, String),
+}
+
+/// Уведомление.
+#[derive(Serialize)]
+pub struct Notification {
+  pub id: i64,
+  /// Вид уведомления: `assigned`, `mentioned`, `due_soon`, `task_changed`, `board_changed`, `board_shared`, `board_transferred`, `board_inactive`, `board_archived`, `board_exported`, `new_location` или `sign_in_locked`.
+  pub kind: String,
+  /// Доска уведомления. Отсутствует у уведомлений, относящихся к аккаунту.
+  pub board_id: Option<i64>,
+  pub card_id: Option<i64>,
+  pub task_id: Option<i64>,
+  pub subtask_id: Option<i64>,
+  /// Пользователь, действие которого вызвало уведомление. Отсутствует у уведомлений, созданных сервером.
+  pub actor: Option<i64>,
+  /// Время создания в секундах Unix.
+  pub at: i64,
+  pub read: bool,
+}
+
+/// Собирает получателей уведомлений на доске.
+pub fn interests(board: &Board) -> HashSet<Interest> {
+  let mut interests: HashSet<Interest> = board.shared_with.iter().map(|id| Interest::Member(*id)).collect();
+  for card in &board.cards {
+    for task in &card.tasks {
+      let target = Target { card_id: card.id, task_id: task.id, subtask_id: None };
+      interests.extend(task.executors.iter().map(|id| Interest::Executor(target, *id)));
+      interests.extend(mentions(&task.notes).into_iter().map(|login| Interest::Mention(target, login)));
+      for subtask in &task.subtasks {
+        let target = Target { subtask_id: Some(subtask.id), ..target };
+        interests.extend(subtask.executors.iter().map(|id| Interest::Executor(target, *id)));
+        interests.extend(mentions(&subtask.notes).into_iter().map(|login| Interest::Mention(target, login)));
+      };
+    };
+  };
+  interests
+}
+
+/// Публикует события о получателях уведомлений, которых не было на доске до изменения.
+pub fn publish(board_id: i64, user_id: i64, revision: i64, before: &HashSet<Interest>, after: &HashSet<Interest>) {
+  for interest in after.difference(before) {
+    let kind = match interest.clone() {
+      Interest::Member(member) => EventKind::BoardShared { member },
+      Interest::Executor(Target { card_id, task_id, subtask_id }, executor) =>
+        EventKind::Assigned { card_id, task_id, subtask_id, executor },
+      Interest::Mention(Target { card_id, task_id, subtask_id }, login) =>
+        EventKind::Mentioned { card_id, task_id, subtask_id, login },
+    };
+    events::publish(board_id, Some(user_id), revision, kind);
+  };
+}
+
+/// Переносит получателей уведомлений задачи на её новый адрес, чтобы перенос задачи в другую карточку не считался новыми назначениями и упоминаниями.
+pub fn moved(interests: &mut HashSet<Interest>, from: TaskPath, to: TaskPath) {
+  let retarget = |target: Target| match target.card_id == from.card_id && target.task_id == from.task_id {
+    true => Target { card_id: to.card_id, task_id: to.task_id, ..target },
+    false => target,
+  };
+  *interests = interests.drain().map(|interest| match interest {
+    Interest::Executor(target, executor) => Interest::Executor(retarget(target), executor),
+    Interest::Mention(target, login) => Interest::Mention(retarget(target), login),
+    member => member,
+  }).collect();
+}
+
+/// Отмечает в задачах, кто и когда назначил их исполнителей.
+///
+/// Записи о снятых исполнителях удаляются. Исполнитель, которого до изменения не было среди получателей уведомлений (`before`), считается назначенным пользователем `actor` в момент `now`. У исполнителей, назначенных раньше, чем сервер стал вести записи о назначениях, записей нет.
+pub fn record_assignments(cards: &mut [Card], before: &HashSet<Interest>, actor: i64, now: i64) {
+  for card in cards {
+    for task in &mut card.tasks {
+      let target = Target { card_id: card.id, task_id: task.id, subtask_id: None };
+      task.assignments.retain(|assignment| task.executors.contains(&assignment.executor));
+      for executor in &task.executors {
+        if before.contains(&Interest::Executor(target, *executor)) { continue; };
+        if task.assignments.iter().any(|assignment| assignment.executor == *executor) { continue; };
+        task.assignments.push(Assignment { executor: *executor, assigned_by: actor, at: now });
+      };
+    };
+  };
+}
+
+/// Находит логины, упомянутые в тексте в виде `@login`.
+///
+/// Упоминание должно начинаться в начале текста или после символа, не являющегося буквой или цифрой, поэтому адреса электронной почты упоминаниями не считаются. Точка в конце упоминания считается концом предложения.
+pub fn mentions(text: &str) -> Vec<String> {
+  let mut found = Vec::new();
+  let mut previous: Option<char> = None;
+  for (i, c) in text.char_indices() {
+    if c == '@' && !previous.is_some_and(|p| p.is_alphanumeric()) {
+      let login: String = text[i + 1..].chars()
+        .take_while(|c| c.is_alphanumeric() || "._-+".contains(*c))
+        .collect();
+      let login = login.trim_end_matches('.');
+      if !login.is_empty() && !found.iter().any(|f| f == login) { found.push(login.to_string()); };
+    };
+    previous = Some(c);
+  };
+  found
+}
+
+/// Создаёт уведомления по событиям изменения досок.
+///
+/// Уведомления, адресованные конкретному пользователю, создаются сразу после события. Уведомления наблюдателей создаются по событиям, собранным за окно `window` (см. `events::Coalescer`), чтобы массовое изменение доски не создавало наблюдателю десятки уведомлений.
+pub async fn run(db: Db, window: Duration) {
+  tokio::join!(direct(&db), watched(&db, window));
+}
+
+async fn direct(db: &Db) {
+  let mut rx = events::subscribe();
+  while let Some(event) = events::next(&mut rx).await {
+    let handled = handle(db, event.board_id, event.user_id, &event.kind).await;
+    if let Err(e) = handled {
+      eprintln!("Не удалось создать уведомление на доске {}: {}", event.board_id, e);
+    };
+  };
+}
+
+async fn watched(db: &Db, window: Duration) {
+  let mut events = Coalescer::new(events::subscribe(), window);
+  while let Some(batch) = events.next().await {
+    let board_id = batch[0].board_id;
+    // Изменения каждого пользователя в порядке событий, без повторов.
+    let mut changes: Vec<(Option<i64>, Vec<Option<Target>>)> = vec![];
+    for event in &batch {
+      let Some(target) = watched_change(&event.kind) else { continue };
+      match changes.iter_mut().find(|(actor, _)| *actor == event.user_id) {
+        Some((_, targets)) => if !targets.contains(&target) { targets.push(target); },
+        None => changes.push((event.user_id, vec![target])),
+      };
+    };
+    for (actor, targets) in changes {
+      if let Err(e) = notify_watchers(db, board_id, &targets, actor).await {
+        eprintln!("Не удалось уведомить наблюдателей доски {}: {}", board_id, e);
+      };
+    };
+  };
+}
+
+async fn handle(db: &Db, board_id: i64, actor: Option<i64>, kind: &EventKind) -> MResult<()> {
+  match kind {
+    EventKind::BoardShared { member } => notify(db, *member, "board_shared", board_id, None, actor).await,
+    EventKind::BoardTransferred { author } => notify(db, *author, "board_transferred", board_id, None, actor).await,
+    EventKind::BoardInactive { author } => notify_author(db, *author, "board_inactive", board_id).await,
+    // Автор, сам перенёсший доску в архив, не уведомляется.
+    EventKind::BoardArchived { author } if actor.is_none() => notify_author(db, *author, "board_archived", board_id).await,
+    EventKind::BoardExported { author, .. } => notify_author(db, *author, "board_exported", board_id).await,
+    EventKind::Assigned { card_id, task_id, subtask_id, executor } => {
+      let target = Target { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id };
+      notify(db, *executor, "assigned", board_id, Some(target), actor).await
+    },
+    EventKind::Mentioned { card_id, task_id, subtask_id, login } => {
+      // Упомянуть можно только участника доски.
+      let rows = db.read_all(
+        "select u.id from users u join boards b on b.id = $2 \
+           where u.login = $1 and b.shared_with @> to_jsonb(u.id);",
+        &[login, &board_id]
+      ).await?;
+      match rows.first() {
+        Some(row) => {
+          let target = Target { card_id: *card_id, task_id: *task_id, subtask_id: *subtask_id };
+          notify(db, row.get(0), "mentioned", board_id, Some(target), actor).await
+        },
+        None => Ok(()),
+      }
+    },
+    EventKind::TaskDueSoon { card_id, task_id } => {
+      let rows = db.read_all("select cards::text from boards where id = $1;", &[&board_id]).await?;
+      let cards: Vec<Card> = match rows.first() {
+        Some(row) => serde_json::from_str(row.get(0))?,
+        None => return Ok(()),
+      };
+      let task = match cards.get_task(card_id, task_id) {
+        Ok(task) => task,
+        _ => return Ok(()),
+      };
+      let target = Target { card_id: *card_id, task_id: *task_id, subtask_id: None };
+      let mut recipients = task.executors.clone();
+      recipients.extend(task.watchers.iter().filter(|id| !task.executors.contains(id)));
+      for recipient in recipients {
+        notify(db, recipient, "due_soon", board_id, Some(target), None).await?;
+      };
+      Ok(())
+    },
+    _ => Ok(()),
+  }
+}
+
+/// Определяет, о каком изменении доски нужно уведомить наблюдателей.
+///
+/// Возвращает `Some(Some(target))` для изменений задачи или подзадачи, `Some(None)` для остальных изменений содержимого доски и None для событий, о которых наблюдатели не уведомляются: создания и удаления доски, изменений участников и наблюдателей и событий, по которым уведомления создаются отдельно.
+fn watched_change(kind: &EventKind) -> Option<Option<Target>> {
+  let target = |card_id: &i64, task_id: &i64, subtask_id: Option<i64>| Some(Some(Target { card_id: *card_id, task_id: *task_id, subtask_id }));
+  match kind {
+    EventKind::TaskCreated { card_id, task_id } | EventKind::TaskUpdated { card_id, task_id } |
+    EventKind::TaskDeleted { card_id, task_id } | EventKind::TaskRestored { card_id, task_id } |
+    EventKind::RuleApplied { card_id, task_id } => target(card_id, task_id, None),
+    EventKind::SubtaskCreated { card_id, task_id, subtask_id } | EventKind::SubtaskUpdated { card_id, task_id, subtask_id } |
+    EventKind::SubtaskDeleted { card_id, task_id, subtask_id } | EventKind::SubtaskRestored { card_id, task_id, subtask_id } =>
+      target(card_id, task_id, Some(*subtask_id)),
+    EventKind::BoardUpdated | EventKind::GithubSynced |
+    EventKind::CardCreated { .. } | EventKind::CardUpdated { .. } | EventKind::CardDeleted { .. } | EventKind::CardRestored { .. } |
+    EventKind::TasksImported { .. } |
+    EventKind::TagCreated { .. } | EventKind::TagUpdated { .. } | EventKind::TagDeleted { .. } |
+    EventKind::LaneCreated { .. } | EventKind::LaneUpdated { .. } | EventKind::LaneDeleted { .. } |
+    EventKind::SprintCreated { .. } | EventKind::SprintUpdated { .. } | EventKind::SprintDeleted { .. } => Some(None),
+    _ => None,
+  }
+}
+
+/// Уведомляет наблюдателей доски и изменённых задач об изменениях, внесённых пользователем `actor` за окно событий.
+///
+/// Если изменение одно, наблюдатели доски получают то же уведомление, что и наблюдатели изменённой задачи. Если изменений несколько, наблюдатели доски получают одно уведомление об изменении доски, а наблюдатели задач - по уведомлению о каждой изменённой задаче, за которой они следят. Наблюдатели удалённой задачи уже не известны, поэтому о её удалении узнают только наблюдатели доски.
+async fn notify_watchers(db: &Db, board_id: i64, targets: &[Option<Target>], actor: Option<i64>) -> MResult<()> {
+  // Пустые списки наблюдателей задач не сериализуются, поэтому доски, за задачами которых никто не следит, не считываются.
+  let rows = db.read_all(
+    "select cards::text, watchers from boards where id = $1 and (watchers <> '[]' or strpos(cards::text, '\"watchers\"') > 0);",
+    &[&board_id]
+  ).await?;
+  let row = match rows.first() {
+    Some(row) => row,
+    None => return Ok(()),
+  };
+  let watchers: Vec<i64> = serde_json::from_str(row.get(1))?;
+  let (kind, target) = match targets {
+    [Some(target)] => ("task_changed", Some(*target)),
+    _ => ("board_changed", None),
+  };
+  for watcher in &watchers {
+    notify(db, *watcher, kind, board_id, target, actor).await?;
+  };
+  if targets.iter().all(Option::is_none) { return Ok(()); };
+  let cards: Vec<Card> = serde_json::from_str(row.get(0))?;
+  for target in targets.iter().flatten() {
+    if let Ok(task) = cards.get_task(&target.card_id, &target.task_id) {
+      for watcher in task.watchers.iter().filter(|watcher| !watchers.contains(watcher)) {
+        notify(db, *watcher, "task_changed", board_id, Some(*target), actor).await?;
+      };
+    };
+  };
+  Ok(())
+}
+
+/// Создаёт уведомление, если оно не вызвано действием самого пользователя и пользователь не отключил уведомления доски.
+async fn notify(db: &Db, user_id: i64, kind: &str, board_id: i64, target: Option<Target>, actor: Option<i64>) -> MResult<()> {
+  if actor == Some(user_id) { return Ok(()); };
+  let now = Utc::now().timestamp();
+  db.write(
+    "with purged as (delete from notifications where user_id = $1 and read and at < $9::bigint - $10::bigint) \
+     insert into notifications (user_id, kind, board_id, card_id, task_id, subtask_id, actor, at) \
+       select $1, $2, $3, $4, $5, $6, $7, $8 \
+       where not exists (select 1 from user_board_prefs where user_id = $1 and board_id = $3 and muted);",
+    &[
+      &user_id, &kind, &board_id, &target.map(|t| t.card_id), &target.map(|t| t.task_id),
+      &target.and_then(|t| t.subtask_id), &actor, &now, &now, &READ_TTL_SECS,
+    ]
+  ).await
+}
+
+/// Создаёт автору доски уведомление о хранении доски (см. `retention`). Такие уведомления приходят и тогда, когда автор отключил уведомления доски.
+async fn notify_author(db: &Db, user_id: i64, kind: &str, board_id: i64) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  db.write(
+    "with purged as (delete from notifications where user_id = $1 and read and at < $4::bigint - $5::bigint) \
+     insert into notifications (user_id, kind, board_id, at) values ($1, $2, $3, $4);",
+    &[&user_id, &kind, &board_id, &now, &READ_TTL_SECS]
+  ).await
+}
+
+/// Создаёт уведомление, относящееся к аккаунту пользователя, а не к доске.
+pub async fn notify_user(db: &Db, user_id: i64, kind: &str) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  db.write(
+    "with purged as (delete from notifications where user_id = $1 and read and at < $3::bigint - $4::bigint) \
+     insert into notifications (user_id, kind, at) values ($1, $2, $3);",
+    &[&user_id, &kind, &now, &READ_TTL_SECS]
+  ).await
+}
+
+/// Возвращает число непрочитанных уведомлений пользователя и до `limit` его уведомлений, начиная с последних.
+///
+/// Если задан `before`, возвращаются уведомления с меньшими идентификаторами; если установлен `unread_only` - только непрочитанные.
+pub async fn list(db: &Db, user_id: &i64, unread_only: bool, before: Option<i64>, limit: i64)
+  -> MResult<(i64, Vec<Notification>)>
+{
+  let unread = db.read("select count(*) from notifications where user_id = $1 and not read;", &[user_id]).await?;
+  let rows = db.read_all(
+    "select id, kind, board_id, card_id, task_id, subtask_id, actor, at, read from notifications \
+       where user_id = $1 and (not $2 or not read) and ($3::bigint is null or id < $3) \
+       order by id desc limit $4;",
+    &[user_id, &unread_only, &before, &limit]
+  ).await?;
+  let notifications = rows.iter().map(|row| Notification {
+    id: row.get(0),
+    kind: row.get(1),
+    board_id: row.get(2),
+    card_id: row.get(3),
+    task_id: row.get(4),
+    subtask_id: row.get(5),
+    actor: row.get(6),
+    at: row.get(7),
+    read: row.get(8),
+  }).collect();
+  Ok((unread.get(0), notifications))
+}
+
+/// Отмечает уведомления пользователя прочитанными: перечисленные в `ids` или, если они не заданы, все.
+pub async fn mark_read(db: &Db, user_id: &i64, ids: Option<&[i64]>) -> MResult<()> {
+  db.write(
+    "update notifications set read = true where user_id = $1 and not read and ($2::bigint[] is null or id = any($2));",
+    &[user_id, &ids]
+  ).await
+}
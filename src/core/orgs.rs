@@ -0,0 +1,422 @@
+//! Отвечает за организации.
+//!
+//! Организация объединяет пользователей и доски, чтобы доступом к доскам команды управляли её администраторы, а не авторы каждой доски по отдельности. Создатель организации становится её администратором; администраторы добавляют и удаляют участников, назначают им роли и задают ограничения организации (см. `OrgQuota`) - число её участников и досок.
+//!
+//! Автор доски, состоящий в организации, может передать ей доску; доска принадлежит не больше чем одной организации. Участники организации становятся участниками всех её досок: при вступлении в организацию пользователь получает доступ к её доскам, при передаче доски организации к доске получают доступ все участники организации, а при выходе из организации пользователь теряет доступ к её доскам (см. `purge_user_from_board`), кроме собственных. Если доска перестаёт принадлежать организации или организация удаляется, участники досок не меняются.
+//!
+//! Гости организации (роль `guest`) не становятся участниками всех её досок: администратор организации или автор доски делится с гостем отдельными досками (см. `share_board`). Администраторы могут и создать гостевую учётную запись (см. `create_guest`) для того, кто работает с командой со стороны: такой пользователь не может создавать доски и организации и получать в организациях другие роли.
+//!
+//! Правила организации (см. `OrgPolicy`) определяют, кто может создавать доски в организации и передавать ей доски, и какую роль получает пользователь, добавленный в организацию без указания роли.
+//!
+//! Ограничения тарифных планов (см. `quota`) действуют для досок организации так же, как и для остальных: они определяются планом автора доски. Организации хранятся только в PostgreSQL.
+
+use chrono::Utc;
+use custom_error::custom_error;
+
+use crate::core::{load_board_as_author, new_user_data, purge_user_from_board, save_board_and_members, validation, LoginTaken};
+use crate::core::events::EventKind;
+use crate::model::{BoardCreators, OrgMember, OrgPatch, OrgPolicy, OrgQuota, OrgRole, OrgSummary, Organization};
+use crate::psql_handler::Db;
+use crate::setup::AppConfig;
+use crate::storage::{NewUser, Storage};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NoSuchOrg{} = "Организация не найдена."}
+custom_error!{pub NotOrgAdmin{} = "Это действие доступно только администраторам организации."}
+custom_error!{pub NotOrgMember{} = "Пользователь не состоит в организации."}
+custom_error!{pub NoSuchUser{} = "Пользователь с таким логином не найден."}
+custom_error!{pub LastOrgAdmin{} = "В организации должен остаться хотя бы один администратор."}
+custom_error!{pub NotAuthor{} = "Передать доску организации может только её автор."}
+custom_error!{pub NotOrgBoard{} = "Доска не принадлежит организации."}
+custom_error!{pub BoardInOtherOrg{} = "Доска уже принадлежит другой организации."}
+custom_error!{pub NotOrgGuest{} = "Пользователь не является гостем организации."}
+custom_error!{pub GuestAccount{} = "Гостевая учётная запись не может создавать доски и организации и становиться автором доски."}
+custom_error!{pub GuestRoleOnly{} = "Гостевой учётной записи можно назначить только роль guest."}
+custom_error!{pub BoardCreationDenied{} = "Правила организации не позволяют вам добавлять в неё доски."}
+custom_error!{pub WrongOrgPolicy{} = "Ролью по умолчанию может быть только member или guest."}
+custom_error!{pub OrgLimitReached{limit_name: &'static str, limit: u64} = "Достигнуто ограничение организации {limit_name}: {limit}."}
+
+fn role_name(role: OrgRole) -> &'static str {
+  match role {
+    OrgRole::Admin => "admin",
+    OrgRole::Member => "member",
+    OrgRole::Guest => "guest",
+  }
+}
+
+fn role(name: &str) -> OrgRole {
+  match name {
+    "admin" => OrgRole::Admin,
+    "guest" => OrgRole::Guest,
+    _ => OrgRole::Member,
+  }
+}
+
+/// Переводит ограничение организации в значение колонки.
+fn limit(value: Option<u64>) -> Option<i64> {
+  value.map(|value| value.min(i64::MAX as u64) as i64)
+}
+
+/// Возвращает роль пользователя в организации или None, если он в ней не состоит.
+async fn member_role(db: &Db, org_id: &i64, user_id: &i64) -> MResult<Option<OrgRole>> {
+  let rows = db.read_all("select role from org_members where org_id = $1 and user_id = $2;", &[org_id, user_id]).await?;
+  Ok(rows.first().map(|row| role(row.get(0))))
+}
+
+/// Проверяет, что пользователь - администратор организации.
+///
+/// Тем, кто в организации не состоит, организация не видна, поэтому для них возвращается `NoSuchOrg`.
+async fn check_admin(db: &Db, org_id: &i64, user_id: &i64) -> MResult<()> {
+  match member_role(db, org_id, user_id).await? {
+    Some(OrgRole::Admin) => Ok(()),
+    Some(_) => Err(Box::new(NotOrgAdmin{})),
+    None => Err(Box::new(NoSuchOrg{})),
+  }
+}
+
+/// Проверяет, что учётная запись пользователя - гостевая (см. `create_guest`).
+pub async fn is_guest(db: &Db, user_id: &i64) -> MResult<bool> {
+  Ok(!db.read_all("select 1 from guest_accounts where user_id = $1;", &[user_id]).await?.is_empty())
+}
+
+/// Проверяет, что пользователь может создать доску или, если задана организация `org_id`, добавить доску в неё.
+///
+/// Гостевые учётные записи не могут создавать доски; в организацию доски добавляют те, кому это позволяют её правила (см. `OrgPolicy`), и только пока в ней есть место.
+pub async fn check_board_creation(db: &Db, user_id: &i64, org_id: Option<&i64>) -> MResult<()> {
+  if is_guest(db, user_id).await? { return Err(Box::new(GuestAccount{})); };
+  let org_id = match org_id {
+    Some(org_id) => org_id,
+    None => return Ok(()),
+  };
+  let role = member_role(db, org_id, user_id).await?;
+  let board_creators = policy(db, org_id).await?.board_creators;
+  match (role, board_creators) {
+    (None, _) => return Err(Box::new(NoSuchOrg{})),
+    (Some(OrgRole::Admin), _) | (Some(OrgRole::Member), BoardCreators::Members) => {},
+    _ => return Err(Box::new(BoardCreationDenied{})),
+  };
+  let max_boards = quota(db, org_id).await?.max_boards;
+  let count: i64 = db.read("select count(*) from org_boards where org_id = $1;", &[org_id]).await?.get(0);
+  match max_boards {
+    Some(limit) if count as u64 >= limit => Err(Box::new(OrgLimitReached{ limit_name: "max_boards", limit })),
+    _ => Ok(()),
+  }
+}
+
+async fn admins_count(db: &Db, org_id: &i64) -> MResult<i64> {
+  Ok(db.read("select count(*) from org_members where org_id = $1 and role = 'admin';", &[org_id]).await?.get(0))
+}
+
+async fn quota(db: &Db, org_id: &i64) -> MResult<OrgQuota> {
+  let row = db.read_all("select max_boards, max_members from organizations where id = $1;", &[org_id]).await?;
+  let row = row.first().ok_or(NoSuchOrg{})?;
+  let (max_boards, max_members): (Option<i64>, Option<i64>) = (row.get(0), row.get(1));
+  Ok(OrgQuota { max_boards: max_boards.map(|v| v as u64), max_members: max_members.map(|v| v as u64) })
+}
+
+async fn policy(db: &Db, org_id: &i64) -> MResult<OrgPolicy> {
+  let policy: String = db.read("select policy from organizations where id = $1;", &[org_id]).await?.get(0);
+  Ok(serde_json::from_str(&policy)?)
+}
+
+async fn members(db: &Db, org_id: &i64) -> MResult<Vec<OrgMember>> {
+  let rows = db.read_all("select user_id, role from org_members where org_id = $1 order by joined_at, user_id;", &[org_id]).await?;
+  Ok(rows.iter().map(|row| OrgMember { user_id: row.get(0), role: role(row.get(1)) }).collect())
+}
+
+async fn boards(db: &Db, org_id: &i64) -> MResult<Vec<i64>> {
+  let rows = db.read_all("select board_id from org_boards where org_id = $1 order by board_id;", &[org_id]).await?;
+  Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Возвращает доски организации, которыми не поделились с пользователем явно.
+async fn boards_not_shared(db: &Db, org_id: &i64, user_id: &i64) -> MResult<Vec<i64>> {
+  let rows = db.read_all(
+    "select b.board_id from org_boards b where b.org_id = $1 and not exists ( \
+       select 1 from org_board_guests g where g.board_id = b.board_id and g.user_id = $2) order by b.board_id;",
+    &[org_id, user_id]
+  ).await?;
+  Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Добавляет пользователей `added` в участники доски и удаляет из них пользователей `removed`, кроме автора доски.
+///
+/// Изменения записываются от имени `actor` вместе со списками досок пользователей; новые участники получают уведомление о доступе к доске (см. `notifications`).
+async fn sync_board(db: &Db, actor: &i64, org_id: &i64, board_id: &i64, added: &[i64], removed: &[i64]) -> MResult<()> {
+  db.lock_board(board_id).await?;
+  let mut ctx = load_board_as_author(db, board_id).await?;
+  ctx.user_id = *actor;
+  let mut shared_boards = Vec::new();
+  for user_id in added {
+    if ctx.board.shared_with.contains(user_id) { continue; };
+    let mut boards: Vec<i64> = serde_json::from_str(&db.user(user_id).await?.shared_boards)?;
+    if !boards.contains(board_id) { boards.push(*board_id); };
+    ctx.board.shared_with.push(*user_id);
+    shared_boards.push((*user_id, serde_json::to_string(&boards)?));
+  };
+  for user_id in removed {
+    if *user_id == ctx.board.author || !ctx.board.shared_with.contains(user_id) { continue; };
+    let mut boards: Vec<i64> = serde_json::from_str(&db.user(user_id).await?.shared_boards)?;
+    boards.retain(|id| id != board_id);
+    purge_user_from_board(&mut ctx.board, user_id);
+    shared_boards.push((*user_id, serde_json::to_string(&boards)?));
+  };
+  if shared_boards.is_empty() { return Ok(()); };
+  save_board_and_members(db, &mut ctx, EventKind::OrgMembersSynced { org_id: *org_id }, vec![], &shared_boards).await
+}
+
+/// Создаёт организацию, администратором которой становится её создатель. Возвращает идентификатор организации.
+pub async fn create(db: &Db, user_id: &i64, name: &str) -> MResult<i64> {
+  if is_guest(db, user_id).await? { return Err(Box::new(GuestAccount{})); };
+  let name = validation::title("организации", name)?;
+  let now = Utc::now().timestamp();
+  let org_id: i64 = db.read(
+    "insert into organizations (name, created_at) values ($1, $2) returning id;",
+    &[&name, &now]
+  ).await?.get(0);
+  db.write(
+    "insert into org_members (org_id, user_id, role, joined_at) values ($1, $2, 'admin', $3);",
+    &[&org_id, user_id, &now]
+  ).await?;
+  Ok(org_id)
+}
+
+/// Возвращает организацию, в которой состоит пользователь.
+pub async fn get(db: &Db, user_id: &i64, org_id: &i64) -> MResult<Organization> {
+  if member_role(db, org_id, user_id).await?.is_none() { return Err(Box::new(NoSuchOrg{})); };
+  let name: String = db.read("select name from organizations where id = $1;", &[org_id]).await?.get(0);
+  let quota = quota(db, org_id).await?;
+  let policy = policy(db, org_id).await?;
+  let members = members(db, org_id).await?;
+  let boards = boards(db, org_id).await?;
+  Ok(Organization { id: *org_id, name, quota, policy, members, boards })
+}
+
+/// Возвращает организации, в которых состоит пользователь, в порядке создания.
+pub async fn list(db: &Db, user_id: &i64) -> MResult<Vec<OrgSummary>> {
+  let rows = db.read_all(
+    "select o.id, o.name, m.role from organizations o join org_members m on m.org_id = o.id where m.user_id = $1 order by o.id;",
+    &[user_id]
+  ).await?;
+  Ok(rows.iter().map(|row| OrgSummary { id: row.get(0), name: row.get(1), role: role(row.get(2)) }).collect())
+}
+
+/// Изменяет название, ограничения и правила организации.
+///
+/// Ограничения можно установить и ниже текущего числа участников или досок: тогда в организацию нельзя добавлять новых, пока их не станет меньше.
+pub async fn patch(db: &Db, user_id: &i64, org_id: &i64, patch: &OrgPatch) -> MResult<()> {
+  check_admin(db, org_id, user_id).await?;
+  if let Some(name) = &patch.name {
+    let name = validation::title("организации", name)?;
+    db.write("update organizations set name = $2 where id = $1;", &[org_id, &name]).await?;
+  };
+  if let Some(quota) = &patch.quota {
+    db.write(
+      "update organizations set max_boards = $2, max_members = $3 where id = $1;",
+      &[org_id, &limit(quota.max_boards), &limit(quota.max_members)]
+    ).await?;
+  };
+  if let Some(policy) = &patch.policy {
+    if policy.default_role == OrgRole::Admin { return Err(Box::new(WrongOrgPolicy{})); };
+    db.write("update organizations set policy = $2 where id = $1;", &[org_id, &serde_json::to_string(policy)?]).await?;
+  };
+  Ok(())
+}
+
+/// Удаляет организацию. Участники её досок не меняются.
+pub async fn delete(db: &Db, user_id: &i64, org_id: &i64) -> MResult<()> {
+  check_admin(db, org_id, user_id).await?;
+  db.write_mul(vec![
+    ("delete from org_board_guests where org_id = $1;", vec![org_id]),
+    ("delete from org_boards where org_id = $1;", vec![org_id]),
+    ("delete from org_members where org_id = $1;", vec![org_id]),
+    ("delete from organizations where id = $1;", vec![org_id]),
+  ]).await
+}
+
+/// Добавляет пользователя с данным логином в организацию или изменяет его роль. Возвращает идентификатор пользователя.
+///
+/// Если роль не задана, новый участник получает роль по умолчанию из правил организации, а гостевая учётная запись - роль `guest`; роль уже состоящего в организации пользователя не меняется. Участник или администратор получает доступ ко всем доскам организации, а гость - только к тем, которыми с ним поделились; участник, ставший гостем, теряет доступ к остальным доскам.
+pub async fn put_member(db: &Db, actor: &i64, org_id: &i64, login: &str, new_role: Option<OrgRole>) -> MResult<i64> {
+  check_admin(db, org_id, actor).await?;
+  let user_id = db.user_by_login(login).await?.ok_or(NoSuchUser{})?.id;
+  let guest_account = is_guest(db, &user_id).await?;
+  let current = member_role(db, org_id, &user_id).await?;
+  let new_role = match (new_role, current) {
+    (Some(role), _) => role,
+    (None, Some(current)) => current,
+    (None, None) if guest_account => OrgRole::Guest,
+    (None, None) => policy(db, org_id).await?.default_role,
+  };
+  if guest_account && new_role != OrgRole::Guest { return Err(Box::new(GuestRoleOnly{})); };
+  if let Some(current) = current {
+    if current == new_role { return Ok(user_id); };
+    if current == OrgRole::Admin && admins_count(db, org_id).await? <= 1 { return Err(Box::new(LastOrgAdmin{})); };
+    db.write(
+      "update org_members set role = $3 where org_id = $1 and user_id = $2;",
+      &[org_id, &user_id, &role_name(new_role)]
+    ).await?;
+    let (added, removed): (&[i64], &[i64]) = match (current, new_role) {
+      (OrgRole::Guest, _) => (&[user_id], &[]),
+      (_, OrgRole::Guest) => (&[], &[user_id]),
+      _ => return Ok(user_id),
+    };
+    let boards = boards_not_shared(db, org_id, &user_id).await?;
+    for board_id in boards {
+      sync_board(db, actor, org_id, &board_id, added, removed).await?;
+    };
+    return Ok(user_id);
+  };
+  let max_members = quota(db, org_id).await?.max_members;
+  let rows = db.read_all(
+    "insert into org_members (org_id, user_id, role, joined_at) \
+       select $1, $2, $3, $4 where $5::bigint is null or (select count(*) from org_members where org_id = $1) < $5 \
+       on conflict (org_id, user_id) do nothing returning user_id;",
+    &[org_id, &user_id, &role_name(new_role), &Utc::now().timestamp(), &limit(max_members)]
+  ).await?;
+  if rows.is_empty() {
+    // Пользователя мог уже добавить параллельный запрос.
+    let joined = member_role(db, org_id, &user_id).await?.is_some();
+    return match max_members {
+      Some(limit) if !joined => Err(Box::new(OrgLimitReached{ limit_name: "max_members", limit })),
+      _ => Ok(user_id),
+    };
+  };
+  if new_role == OrgRole::Guest { return Ok(user_id); };
+  let boards = boards(db, org_id).await?;
+  for board_id in boards {
+    sync_board(db, actor, org_id, &board_id, &[user_id], &[]).await?;
+  };
+  Ok(user_id)
+}
+
+/// Создаёт гостевую учётную запись и добавляет её в организацию с ролью `guest`. Возвращает идентификатор пользователя.
+///
+/// Требования к логину и паролю проверяет вызывающий (см. `sec::policy`). Ключ регистрации (см. `cc_keys`) для гостевой учётной записи не нужен: её создаёт администратор организации.
+pub async fn create_guest(db: &Db, cfg: &AppConfig, actor: &i64, org_id: &i64, login: &str, pass: &str) -> MResult<i64> {
+  check_admin(db, org_id, actor).await?;
+  if db.user_by_login(login).await?.is_some() { return Err(Box::new(LoginTaken{})); };
+  let max_members = quota(db, org_id).await?.max_members;
+  let count: i64 = db.read("select count(*) from org_members where org_id = $1;", &[org_id]).await?.get(0);
+  if let Some(limit) = max_members.filter(|limit| count as u64 >= *limit) {
+    return Err(Box::new(OrgLimitReached{ limit_name: "max_members", limit }));
+  };
+  let (user_creds, apd) = new_user_data(pass, cfg)?;
+  let user_id = db.insert_user(&NewUser { login, user_creds: &user_creds, apd: &apd }, None).await?.ok_or(LoginTaken{})?;
+  db.write_mul(vec![
+    ("insert into guest_accounts (user_id, org_id) values ($1, $2);", vec![&user_id, org_id]),
+    (
+      "insert into org_members (org_id, user_id, role, joined_at) values ($1, $2, 'guest', $3);",
+      vec![org_id, &user_id, &Utc::now().timestamp()]
+    ),
+  ]).await?;
+  Ok(user_id)
+}
+
+/// Удаляет пользователя из организации.
+///
+/// Удалить участника может администратор организации, а выйти из организации - любой её участник. Вместе с участием пользователь теряет доступ к доскам организации, кроме собственных.
+pub async fn remove_member(db: &Db, actor: &i64, org_id: &i64, user_id: &i64) -> MResult<()> {
+  if actor != user_id { check_admin(db, org_id, actor).await?; };
+  let member_role = member_role(db, org_id, user_id).await?;
+  match member_role {
+    None if actor == user_id => return Err(Box::new(NoSuchOrg{})),
+    None => return Err(Box::new(NotOrgMember{})),
+    Some(OrgRole::Admin) => if admins_count(db, org_id).await? <= 1 { return Err(Box::new(LastOrgAdmin{})); },
+    Some(_) => {},
+  };
+  db.write_mul(vec![
+    ("delete from org_members where org_id = $1 and user_id = $2;", vec![org_id, user_id]),
+    ("delete from org_board_guests where org_id = $1 and user_id = $2;", vec![org_id, user_id]),
+  ]).await?;
+  let boards = boards(db, org_id).await?;
+  for board_id in boards {
+    sync_board(db, actor, org_id, &board_id, &[], &[*user_id]).await?;
+  };
+  Ok(())
+}
+
+/// Передаёт доску организации.
+///
+/// Передать доску может её автор, если ему это позволяют правила организации (см. `check_board_creation`). Все участники организации, кроме гостей, получают доступ к доске.
+pub async fn add_board(db: &Db, actor: &i64, org_id: &i64, board_id: &i64) -> MResult<()> {
+  if member_role(db, org_id, actor).await?.is_none() { return Err(Box::new(NoSuchOrg{})); };
+  if db.board(board_id).await?.map(|row| row.author) != Some(*actor) { return Err(Box::new(NotAuthor{})); };
+  let rows = db.read_all("select org_id from org_boards where board_id = $1;", &[board_id]).await?;
+  match rows.first().map(|row| row.get::<_, i64>(0)) {
+    Some(id) if id == *org_id => return Ok(()),
+    Some(_) => return Err(Box::new(BoardInOtherOrg{})),
+    None => {},
+  };
+  check_board_creation(db, actor, Some(org_id)).await?;
+  let max_boards = quota(db, org_id).await?.max_boards;
+  let rows = db.read_all(
+    "insert into org_boards (board_id, org_id) \
+       select $1, $2 where $3::bigint is null or (select count(*) from org_boards where org_id = $2) < $3 \
+       on conflict (board_id) do nothing returning board_id;",
+    &[board_id, org_id, &limit(max_boards)]
+  ).await?;
+  if rows.is_empty() {
+    return match max_boards {
+      Some(limit) => Err(Box::new(OrgLimitReached{ limit_name: "max_boards", limit })),
+      None => Err(Box::new(BoardInOtherOrg{})),
+    };
+  };
+  let members: Vec<i64> = members(db, org_id).await?.iter()
+    .filter(|member| member.role != OrgRole::Guest)
+    .map(|member| member.user_id)
+    .collect();
+  sync_board(db, actor, org_id, board_id, &members, &[]).await
+}
+
+/// Исключает доску из организации. Участники доски, в том числе гости, которыми с ней поделились, не меняются.
+///
+/// Исключить доску может администратор организации или автор доски.
+pub async fn remove_board(db: &Db, actor: &i64, org_id: &i64, board_id: &i64) -> MResult<()> {
+  let actor_role = member_role(db, org_id, actor).await?;
+  let rows = db.read_all("select 1 from org_boards where board_id = $1 and org_id = $2;", &[board_id, org_id]).await?;
+  if rows.is_empty() {
+    return match actor_role {
+      Some(_) => Err(Box::new(NotOrgBoard{})),
+      None => Err(Box::new(NoSuchOrg{})),
+    };
+  };
+  if actor_role != Some(OrgRole::Admin) && db.board(board_id).await?.map(|row| row.author) != Some(*actor) {
+    return match actor_role {
+      Some(_) => Err(Box::new(NotOrgAdmin{})),
+      None => Err(Box::new(NoSuchOrg{})),
+    };
+  };
+  db.write_mul(vec![
+    ("delete from org_board_guests where board_id = $1;", vec![board_id]),
+    ("delete from org_boards where board_id = $1 and org_id = $2;", vec![board_id, org_id]),
+  ]).await
+}
+
+/// Делится доской организации с её гостем (`share`) или закрывает гостю доступ к доске.
+///
+/// Делиться доской может администратор организации или автор доски. Гость, которым поделились с доской, становится её участником; закрывая доступ, гость удаляется из участников доски (см. `purge_user_from_board`).
+pub async fn share_board(db: &Db, actor: &i64, org_id: &i64, board_id: &i64, user_id: &i64, share: bool) -> MResult<()> {
+  let actor_role = member_role(db, org_id, actor).await?;
+  if actor_role.is_none() { return Err(Box::new(NoSuchOrg{})); };
+  let rows = db.read_all("select 1 from org_boards where board_id = $1 and org_id = $2;", &[board_id, org_id]).await?;
+  if rows.is_empty() { return Err(Box::new(NotOrgBoard{})); };
+  if actor_role != Some(OrgRole::Admin) && db.board(board_id).await?.map(|row| row.author) != Some(*actor) {
+    return Err(Box::new(NotOrgAdmin{}));
+  };
+  if member_role(db, org_id, user_id).await? != Some(OrgRole::Guest) { return Err(Box::new(NotOrgGuest{})); };
+  match share {
+    true => {
+      db.write(
+        "insert into org_board_guests (org_id, board_id, user_id) values ($1, $2, $3) on conflict (board_id, user_id) do nothing;",
+        &[org_id, board_id, user_id]
+      ).await?;
+      sync_board(db, actor, org_id, board_id, &[*user_id], &[]).await
+    },
+    false => {
+      db.write("delete from org_board_guests where board_id = $1 and user_id = $2;", &[board_id, user_id]).await?;
+      sync_board(db, actor, org_id, board_id, &[], &[*user_id]).await
+    },
+  }
+}
@@ -0,0 +1,75 @@
+//! Отвечает за отслеживание просроченных задач.
+//!
+//! Признаки просроченности (`Task::overdue`) и близости срока (`Task::due_soon`) пересчитываются при каждом изменении доски, но срок выполнения может пройти и тогда, когда доску никто не меняет. Поэтому фоновая задача периодически просматривает все доски и обновляет признаки сама, чтобы клиентам не приходилось вычислять их по своим часам.
+
+use chrono::Utc;
+use tokio_postgres::types::ToSql;
+
+use crate::core::delta;
+use crate::core::events::{self, EventKind};
+use crate::model::{Card, Cards};
+use crate::psql_handler::Db;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Публикует события о задачах, ставших просроченными или близкими к сроку.
+///
+/// Принимает изменения признаков в том виде, в котором их возвращают `Cards::refresh_overdue` и `Cards::refresh_due_soon`.
+pub fn publish(board_id: i64, revision: i64, overdue: &[(i64, i64, bool)], due_soon: &[(i64, i64, bool)]) {
+  for (card_id, task_id, _) in overdue.iter().filter(|(_, _, overdue)| *overdue) {
+    events::publish(board_id, None, revision, EventKind::TaskOverdue { card_id: *card_id, task_id: *task_id });
+  };
+  for (card_id, task_id, _) in due_soon.iter().filter(|(_, _, due_soon)| *due_soon) {
+    events::publish(board_id, None, revision, EventKind::TaskDueSoon { card_id: *card_id, task_id: *task_id });
+  };
+}
+
+/// Записывает в журнал сервера сообщения о задачах, ставших просроченными.
+pub async fn log() {
+  let mut rx = events::subscribe();
+  while let Some(event) = events::next(&mut rx).await {
+    if let EventKind::TaskOverdue { card_id, task_id } = event.kind {
+      println!("Задача {} в карточке {} на доске {} просрочена.", task_id, card_id, event.board_id);
+    };
+  };
+}
+
+/// Просматривает все доски и обновляет признаки просроченности и близости срока задач. Возвращает число обновлённых досок.
+///
+/// Доска, которую в это же время изменил запрос пользователя, пропускается: признак на ней уже пересчитан при записи.
+pub async fn scan(db: &Db) -> MResult<usize> {
+  let now = Utc::now();
+  let boards = db.read_all("select id, cards::text, revision from boards where archived_at = 0;", &[]).await?;
+  let mut updated: usize = 0;
+  for board in &boards {
+    let board_id: i64 = board.get(0);
+    let revision: i64 = board.get(2);
+    // Повреждённые доски находит и описывает `integrity::scan`.
+    let mut cards: Vec<Card> = match serde_json::from_str(board.get(1)) {
+      Ok(cards) => cards,
+      Err(_) => continue,
+    };
+    let overdue = cards.refresh_overdue(&now);
+    let due_soon = cards.refresh_due_soon(&now);
+    if overdue.is_empty() && due_soon.is_empty() { continue; };
+    let cards = serde_json::to_string(&cards)?;
+    let record = delta::Record::build(board_id, revision + 1, &[("cards", board.get(1), &cards)])?;
+    let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![(
+      "update boards set cards = $1::text::jsonb, revision = revision + 1 where id = $2 and revision = $3;",
+      vec![&cards, &board_id, &revision]
+    )];
+    queries.extend(record.queries());
+    let written = db.write_mul_if(queries).await?;
+    if written {
+      updated += 1;
+      publish(board_id, revision + 1, &overdue, &due_soon);
+    };
+  };
+  Ok(updated)
+}
+
+/// Периодическое задание (см. `jobs`): запускает `scan`.
+pub async fn job(db: Db) -> MResult<()> {
+  scan(&db).await?;
+  Ok(())
+}
@@ -0,0 +1,38 @@
+//! Отвечает за ограничения тарифных планов.
+//!
+//! Ограничения задаются в конфигурации (см. `setup::PlanQuotas`) отдельно для бесплатных и оплаченных аккаунтов. Ограничения доски определяются тарифным планом её автора, а не того, кто изменяет доску.
+
+use custom_error::custom_error;
+
+use crate::sec::auth::AccountPlanDetails;
+use crate::sec::tokens_vld::is_billed;
+use crate::setup::{AppConfig, Quota};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub QuotaExceeded{quota: &'static str, limit: u64} = "Превышено ограничение тарифного плана."}
+
+/// Возвращает ограничения тарифного плана.
+pub fn for_plan(cfg: &AppConfig, billed: bool) -> &Quota {
+  match billed {
+    true => &cfg.quotas.paid,
+    false => &cfg.quotas.free,
+  }
+}
+
+/// Возвращает ограничения тарифного плана пользователя.
+pub async fn for_user<'a>(db: &dyn Storage, cfg: &'a AppConfig, user_id: &i64) -> MResult<&'a Quota> {
+  let billing: AccountPlanDetails = serde_json::from_str(&db.user(user_id).await?.apd)?;
+  Ok(for_plan(cfg, is_billed(&billing)))
+}
+
+/// Проверяет, что после операции количество `count` не превысит ограничение `limit`.
+///
+/// Отсутствующее ограничение означает, что количество не ограничено.
+pub fn check(quota: &'static str, limit: Option<u64>, count: u64) -> MResult<()> {
+  match limit {
+    Some(limit) if count > limit => Err(Box::new(QuotaExceeded{ quota, limit })),
+    _ => Ok(()),
+  }
+}
@@ -0,0 +1,175 @@
+//! Отвечает за уборку выполненных задач досок.
+//!
+//! Автор доски включает уборку в её настройках (`BoardSettings::recycle`). Фоновая задача периодически просматривает доски с уборкой и переносит задачи, выполненные больше `after_days` дней назад, в выбранную карточку, а если карточка не выбрана - в архив задач доски (таблица `recycled_tasks`), чтобы на доске оставались только актуальные задачи. Временем выполнения задачи считается время последнего изменения её поля `exec` (см. `Task::field_stamps`), а если оно неизвестно - время последнего изменения задачи.
+//!
+//! Задача, перенесённая в карточку, получает в ней новый идентификатор, как при переносе правилом автоматизации (см. `automation`); в карточку без места для задач (см. `Card::wip_limit`) переносится столько задач, сколько в ней помещается. Задачи из архива задач только просматриваются и на доску не возвращаются. История изменений задач сохраняется.
+
+use chrono::Utc;
+use custom_error::custom_error;
+use serde::Serialize;
+use tokio_postgres::types::ToSql;
+
+use crate::core::events::EventKind;
+use crate::core::{automation, dependencies, load_board_as_author, save_board};
+use crate::model::{BoardContext, Card, Cards, RecycleSettings, Task, TaskPath};
+use crate::psql_handler::{transaction, Db};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongRecycle{reason: String} = "Уборка выполненных задач не принята: {reason}."}
+
+/// Наибольшее число дней, через которое убираются выполненные задачи.
+pub const MAX_AFTER_DAYS: u32 = 3650;
+
+/// Наибольшее число задач архива, которое можно получить за один запрос.
+pub const MAX_RECYCLED_PAGE: i64 = 100;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Задача в архиве задач доски.
+#[derive(Serialize)]
+pub struct RecycledTask {
+  /// Идентификатор записи в архиве.
+  pub id: i64,
+  /// Карточка, из которой убрана задача.
+  pub card_id: i64,
+  /// Время уборки (UNIX-время в секундах).
+  pub recycled_at: i64,
+  pub task: Task,
+}
+
+/// Проверяет настройку уборки доски с данными карточками.
+pub fn validate(recycle: &Option<RecycleSettings>, cards: &[Card]) -> MResult<()> {
+  let wrong = |reason: String| -> MResult<()> { Err(Box::new(WrongRecycle{ reason })) };
+  let recycle = match recycle {
+    Some(recycle) => recycle,
+    None => return Ok(()),
+  };
+  if recycle.after_days > MAX_AFTER_DAYS { return wrong(format!("задачи можно убирать не позже чем через {} дней", MAX_AFTER_DAYS)); };
+  match recycle.card_id {
+    Some(card_id) if !cards.iter().any(|card| card.id == card_id) => wrong(format!("карточки {} нет на доске", card_id)),
+    _ => Ok(()),
+  }
+}
+
+/// Возвращает время выполнения задачи.
+fn completed_at(task: &Task) -> i64 {
+  task.field_stamps.get("exec").map_or(task.updated_at, |stamp| stamp.at)
+}
+
+/// Просматривает доски с уборкой и убирает их выполненные задачи. Возвращает число убранных задач.
+pub async fn scan(db: &Db) -> MResult<usize> {
+  let rows = db.read_all("select id from boards where archived_at = 0 and strpos(settings, '\"recycle\"') > 0;", &[]).await?;
+  let mut recycled: usize = 0;
+  for row in &rows {
+    let board_id: i64 = row.get(0);
+    match transaction::scope(recycle_board(db, board_id)).await {
+      Ok(count) => recycled += count,
+      Err(e) => eprintln!("Не удалось убрать выполненные задачи доски {}: {}", board_id, e),
+    };
+  };
+  Ok(recycled)
+}
+
+/// Убирает выполненные задачи доски и возвращает их число.
+async fn recycle_board(db: &Db, board_id: i64) -> MResult<usize> {
+  db.lock_board(&board_id).await?;
+  let mut ctx = load_board_as_author(db, &board_id).await?;
+  let recycle = match ctx.board.settings.recycle.clone() {
+    Some(recycle) => recycle,
+    None => return Ok(0),
+  };
+  let deadline = Utc::now().timestamp() - recycle.after_days as i64 * DAY_SECS;
+  let due: Vec<TaskPath> = ctx.board.cards.iter()
+    .filter(|card| Some(card.id) != recycle.card_id)
+    .flat_map(|card| card.tasks.iter().map(move |task| (card.id, task)))
+    .filter(|(_, task)| task.exec && completed_at(task) <= deadline)
+    .map(|(card_id, task)| TaskPath { card_id, task_id: task.id })
+    .collect();
+  if due.is_empty() { return Ok(0); };
+  match recycle.card_id {
+    Some(card_id) => move_to_card(db, &mut ctx, &due, card_id).await,
+    None => move_to_archive(db, &mut ctx, &due).await,
+  }
+}
+
+/// Переносит задачи в карточку, пока в ней есть место.
+async fn move_to_card(db: &Db, ctx: &mut BoardContext, due: &[TaskPath], card_id: i64) -> MResult<usize> {
+  if ctx.board.cards.iter().all(|card| card.id != card_id) { return Ok(0); };
+  let mut moves: Vec<(TaskPath, TaskPath, String, String)> = vec![];
+  for path in due {
+    let moved = match automation::move_task(db, ctx, *path, card_id).await? {
+      Some(moved) => moved,
+      None => break,
+    };
+    let board_id = ctx.board.id;
+    moves.push((*path, moved, format!("{}_{}_{}", board_id, path.card_id, path.task_id), format!("{}_{}_{}", board_id, moved.card_id, moved.task_id)));
+  };
+  if moves.is_empty() { return Ok(0); };
+  let board_id = ctx.board.id;
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![];
+  for (from, to, old_seq, new_seq) in &moves {
+    queries.extend([
+      (
+        "update task_history set card_id = $1, task_id = $2 where board_id = $3 and card_id = $4 and task_id = $5;",
+        vec![&to.card_id as &(dyn ToSql + Sync), &to.task_id, &board_id, &from.card_id, &from.task_id]
+      ),
+      ("delete from id_seqs where id = $1;", vec![new_seq]),
+      ("update id_seqs set id = $1 where id = $2;", vec![new_seq, old_seq]),
+    ]);
+  };
+  save_board(db, ctx, EventKind::TasksRecycled { card_id: Some(card_id), count: moves.len() }, queries).await?;
+  Ok(moves.len())
+}
+
+/// Убирает задачи с доски в её архив задач.
+async fn move_to_archive(db: &Db, ctx: &mut BoardContext, due: &[TaskPath]) -> MResult<usize> {
+  let now = Utc::now().timestamp();
+  let mut removed: Vec<(i64, String, String)> = vec![];
+  for path in due {
+    let task = ctx.board.cards.remove_task(&path.card_id, &path.task_id)?;
+    let subtasks_id_seq = format!("{}_{}_{}", ctx.board.id, path.card_id, path.task_id);
+    removed.push((path.card_id, serde_json::to_string(&task)?, subtasks_id_seq));
+  };
+  dependencies::forget(&mut ctx.board.cards, |path| due.contains(path));
+  let board_id = ctx.board.id;
+  let mut queries: Vec<(&str, Vec<&(dyn ToSql + Sync)>)> = vec![];
+  for (card_id, task, subtasks_id_seq) in &removed {
+    queries.extend([
+      (
+        "insert into recycled_tasks (board_id, card_id, task, recycled_at) values ($1, $2, $3, $4);",
+        vec![&board_id as &(dyn ToSql + Sync), card_id, task, &now]
+      ),
+      ("delete from id_seqs where id = $1;", vec![subtasks_id_seq]),
+    ]);
+  };
+  save_board(db, ctx, EventKind::TasksRecycled { card_id: None, count: removed.len() }, queries).await?;
+  Ok(removed.len())
+}
+
+/// Возвращает задачи из архива задач доски, начиная с последних убранных.
+///
+/// Если передан `before`, возвращаются записи архива с меньшими идентификаторами.
+pub async fn list(db: &Db, ctx: &BoardContext, before: Option<i64>, limit: i64) -> MResult<Vec<RecycledTask>> {
+  let rows = db.read_all(
+    "select id, card_id, task, recycled_at from recycled_tasks \
+       where board_id = $1 and ($2::bigint is null or id < $2) order by id desc limit $3;",
+    &[&ctx.board.id, &before, &limit]
+  ).await?;
+  rows.iter().map(|row| Ok(RecycledTask {
+    id: row.get(0),
+    card_id: row.get(1),
+    task: serde_json::from_str(row.get(2))?,
+    recycled_at: row.get(3),
+  })).collect()
+}
+
+/// Периодическое задание (см. `jobs`): запускает `scan` и записывает в журнал сервера число убранных задач.
+pub async fn job(db: Db) -> MResult<()> {
+  match scan(&db).await? {
+    0 => {},
+    recycled => println!("Убраны выполненные задачи: {}.", recycled),
+  };
+  Ok(())
+}
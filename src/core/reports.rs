@@ -0,0 +1,229 @@
+//! Отвечает за отчёты о досках, которые сервер отправляет по расписанию.
+//!
+//! Автор доски настраивает отчёты: раз в сутки или в неделю сервер собирает статистику доски (см. `stats`) и число задач, отмеченных выполненными за период по истории изменений задач (см. `task_history`), и отправляет отчёт на вебхук или в чат Telegram. На вебхук отчёт передаётся в виде JSON (`json`) или текстом в поле `text` (`markdown`) - в таком виде сообщения принимают входящие вебхуки Slack и Mattermost; в Telegram отчёт всегда отправляется текстом.
+//!
+//! Фоновая задача периодически находит отчёты, которым пора: с прошлой отправки прошли сутки или неделя - в зависимости от периодичности. Первый отчёт отправляется при ближайшем запуске задачи после настройки и охватывает последние сутки или неделю. Если сервис не принял отчёт, отправка повторяется при следующем запуске задачи. Отчёты хранятся в таблице `board_reports`, не попадают в резервную копию без секретов (адреса вебхуков часто их содержат) и удаляются вместе с доской.
+
+use chrono::{TimeZone, Utc};
+use custom_error::custom_error;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+
+use crate::core::{digest, get_profiles, load_board_as_author, stats::{self, BoardStats}};
+use crate::integrations::{telegram::{self, Bot}, webhook};
+use crate::model::{BoardContext, BoardReport, DigestCadence, ReportFormat, ReportTarget};
+use crate::psql_handler::Db;
+use crate::setup::ReportsConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NotAuthor{} = "Отчётами о доске управляет только автор доски."}
+custom_error!{pub NoSuchReport{} = "Отчёт не найден."}
+custom_error!{pub TooManyReports{max: i64} = "У доски не может быть больше {max} отчётов."}
+custom_error!{pub WrongReportTarget{reason: String} = "Получатель отчёта задан неверно: {reason}."}
+
+/// Наибольшее число отчётов одной доски.
+pub const MAX_BOARD_REPORTS: i64 = 10;
+
+fn format_name(format: ReportFormat) -> &'static str {
+  match format {
+    ReportFormat::Markdown => "markdown",
+    ReportFormat::Json => "json",
+  }
+}
+
+fn format(name: &str) -> ReportFormat {
+  match name {
+    "json" => ReportFormat::Json,
+    _ => ReportFormat::Markdown,
+  }
+}
+
+/// Проверяет получателя отчёта.
+fn validate(cfg: &ReportsConfig, target: &ReportTarget) -> Result<(), WrongReportTarget> {
+  let reason = match target {
+    ReportTarget::Webhook { url } if !webhook::valid_url(url, cfg.allow_http_webhooks) => match cfg.allow_http_webhooks {
+      true => "нужен адрес HTTP или HTTPS",
+      false => "нужен адрес HTTPS",
+    },
+    ReportTarget::Telegram { .. } if cfg.telegram_bot_token.is_none() => "на сервере не настроен бот Telegram",
+    ReportTarget::Telegram { chat_id } if !telegram::valid_chat_id(chat_id) => "нужен числовой идентификатор чата или имя канала вида @channel",
+    _ => return Ok(()),
+  };
+  Err(WrongReportTarget{ reason: reason.into() })
+}
+
+/// Сохраняет отчёт о доске и возвращает его идентификатор.
+///
+/// Если `report.id` не задан, создаётся новый отчёт, иначе заменяется отчёт с этим идентификатором; отсутствие такого отчёта - ошибка `NoSuchReport`. Время последней отправки при замене сохраняется.
+pub async fn save(db: &Db, cfg: &ReportsConfig, ctx: &BoardContext, report: BoardReport) -> MResult<i64> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  validate(cfg, &report.target)?;
+  let (cadence, format, target) = (digest::cadence_name(report.cadence), format_name(report.format), serde_json::to_string(&report.target)?);
+  if report.id != 0 {
+    let rows = db.read_all(
+      "update board_reports set cadence = $3, format = $4, target = $5 where id = $1 and board_id = $2 returning id;",
+      &[&report.id, &ctx.board.id, &cadence, &format, &target]
+    ).await?;
+    return match rows.first() {
+      Some(row) => Ok(row.get(0)),
+      None => Err(Box::new(NoSuchReport{})),
+    };
+  };
+  let rows = db.read_all(
+    "insert into board_reports (board_id, cadence, format, target) \
+       select $1, $2, $3, $4 where (select count(*) from board_reports where board_id = $1) < $5 \
+       returning id;",
+    &[&ctx.board.id, &cadence, &format, &target, &MAX_BOARD_REPORTS]
+  ).await?;
+  match rows.first() {
+    Some(row) => Ok(row.get(0)),
+    None => Err(Box::new(TooManyReports{ max: MAX_BOARD_REPORTS })),
+  }
+}
+
+/// Возвращает отчёты о доске в порядке создания.
+pub async fn list(db: &Db, ctx: &BoardContext) -> MResult<Vec<BoardReport>> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  let rows = db.read_all(
+    "select id, cadence, format, target, sent_at from board_reports where board_id = $1 order by id;",
+    &[&ctx.board.id]
+  ).await?;
+  rows.iter().map(|row| Ok(BoardReport {
+    id: row.get(0),
+    cadence: digest::cadence(row.get(1)),
+    format: format(row.get(2)),
+    target: serde_json::from_str(row.get(3))?,
+    sent_at: row.get(4),
+  })).collect()
+}
+
+/// Удаляет отчёт о доске.
+pub async fn delete(db: &Db, ctx: &BoardContext, report_id: &i64) -> MResult<()> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  let rows = db.read_all(
+    "delete from board_reports where id = $1 and board_id = $2 returning id;",
+    &[report_id, &ctx.board.id]
+  ).await?;
+  match rows.is_empty() {
+    true => Err(Box::new(NoSuchReport{})),
+    false => Ok(()),
+  }
+}
+
+/// Отчёт о доске за период.
+struct Report {
+  cadence: DigestCadence,
+  from: i64,
+  to: i64,
+  /// Число задач, отмеченных выполненными за период.
+  completed: i64,
+  stats: BoardStats,
+  /// Отображаемые имена участников доски.
+  names: HashMap<i64, String>,
+}
+
+impl Report {
+  fn json(&self) -> JsonValue {
+    json!({
+      "board_id": self.stats.board_id,
+      "cadence": digest::cadence_name(self.cadence),
+      "from": self.from,
+      "to": self.to,
+      "completed": self.completed,
+      "stats": self.stats,
+    })
+  }
+
+  /// Возвращает текст отчёта. Разметка ограничена списками, поэтому текст читается и там, где Markdown не поддерживается.
+  fn text(&self) -> String {
+    let at = |ts: i64| Utc.timestamp_opt(ts, 0).single().map(|at| at.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+    let stats = &self.stats;
+    let mut text = format!("Отчёт о доске «{}» с {} по {} UTC\n\n", stats.title, at(self.from), at(self.to));
+    text.push_str(&format!(
+      "Задач: {}, выполнено: {}, просрочено: {}, без исполнителей: {}.\nОтмечено выполненными за период: {}.\n",
+      stats.tasks, stats.done, stats.overdue, stats.unassigned, self.completed
+    ));
+    if !stats.cards.is_empty() {
+      text.push_str("\nКарточки:\n");
+      stats.cards.iter().for_each(|card| text.push_str(&format!("- {}: {} (выполнено {})\n", card.title, card.tasks, card.done)));
+    };
+    let executors: Vec<String> = stats.executors.iter().filter(|executor| executor.open > 0).map(|executor| {
+      let name = self.names.get(&executor.user_id).cloned().unwrap_or_else(|| executor.user_id.to_string());
+      format!("- {}: {} (просрочено {})\n", name, executor.open, executor.overdue)
+    }).collect();
+    if !executors.is_empty() {
+      text.push_str("\nНевыполненные задачи участников:\n");
+      executors.iter().for_each(|line| text.push_str(line));
+    };
+    text
+  }
+}
+
+/// Собирает отчёт о доске за время после `since` до `now`.
+async fn collect(db: &Db, board_id: &i64, cadence: DigestCadence, since: i64, now: i64) -> MResult<Report> {
+  let ctx = load_board_as_author(db, board_id).await?;
+  let completed: i64 = db.read(
+    "select count(distinct (card_id, task_id)) from task_history \
+       where board_id = $1 and field = 'exec' and new_value = 'true' and at > $2 and at <= $3;",
+    &[board_id, &since, &now]
+  ).await?.get(0);
+  let names = get_profiles(db, &ctx.board.shared_with).await?.into_iter()
+    .map(|profile| (profile.id, profile.display_name))
+    .collect();
+  let stats = stats::collect(&ctx.board, &Utc.timestamp_opt(now, 0).single().unwrap_or_else(Utc::now));
+  Ok(Report { cadence, from: since, to: now, completed, stats, names })
+}
+
+/// Отправляет отчёт получателю.
+async fn deliver(cfg: &ReportsConfig, target: &ReportTarget, format: ReportFormat, report: &Report) -> MResult<()> {
+  match target {
+    ReportTarget::Webhook { url } => match format {
+      ReportFormat::Json => webhook::post(url, &report.json()).await,
+      ReportFormat::Markdown => webhook::post(url, &json!({ "text": report.text() })).await,
+    },
+    ReportTarget::Telegram { chat_id } => match &cfg.telegram_bot_token {
+      Some(token) => Bot::new(&cfg.telegram_api_url, token).send_message(chat_id, &report.text()).await,
+      None => Err(Box::new(WrongReportTarget{ reason: "на сервере не настроен бот Telegram".into() })),
+    },
+  }
+}
+
+/// Отправляет все отчёты, которым пора. Возвращает число отправленных отчётов.
+pub async fn send_due(db: &Db, cfg: &ReportsConfig) -> MResult<usize> {
+  let now = Utc::now().timestamp();
+  let rows = db.read_all(
+    "select id, board_id, cadence, format, target, sent_at from board_reports \
+       where sent_at <= case cadence when 'weekly' then $2::bigint else $1::bigint end order by id;",
+    &[&(now - digest::period_secs(DigestCadence::Daily)), &(now - digest::period_secs(DigestCadence::Weekly))]
+  ).await?;
+  let mut sent = 0;
+  for row in &rows {
+    let (report_id, board_id, cadence, sent_at): (i64, i64, DigestCadence, i64) = (row.get(0), row.get(1), digest::cadence(row.get(2)), row.get(5));
+    let since = match sent_at {
+      0 => now - digest::period_secs(cadence),
+      sent_at => sent_at,
+    };
+    // Ошибки переводятся в текст сразу: `Box<dyn Error>` нельзя держать через `.await` фоновой задачи.
+    let report = collect(db, &board_id, cadence, since, now).await.map_err(|e| e.to_string());
+    let target = serde_json::from_str::<ReportTarget>(row.get(4)).map_err(|e| e.to_string());
+    let delivered = match (report, target) {
+      (Ok(report), Ok(target)) => deliver(cfg, &target, format(row.get(3)), &report).await.map_err(|e| e.to_string()),
+      (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    // Отчёт, который не удалось собрать или отправить, не мешает остальным.
+    if let Err(e) = delivered {
+      eprintln!("Не удалось отправить отчёт {} о доске {}: {}", report_id, board_id, e);
+      continue;
+    };
+    sent += 1;
+    db.write("update board_reports set sent_at = $2 where id = $1;", &[&report_id, &now]).await?;
+  };
+  Ok(sent)
+}
+
+/// Периодическое задание (см. `jobs`): запускает `send_due`.
+pub async fn job(db: Db, cfg: ReportsConfig) -> MResult<()> {
+  send_due(&db, &cfg).await?;
+  Ok(())
+}
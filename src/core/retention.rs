@@ -0,0 +1,196 @@
+//! Отвечает за хранение неактивных досок.
+//!
+//! Доска неактивна, если её не изменяли дольше `inactive_months` месяцев по 30 дней. Правила хранения задаются в конфигурации для тарифного плана автора доски (см. `setup::RetentionConfig`). Фоновая задача периодически просматривает доски: доска, ставшая неактивной, получает отметку, а её автор - уведомление (см. `notifications`). Если за `grace_days` дней после отметки доску так и не изменили, к ней применяется действие тарифного плана:
+//!
+//! - `flag` - доска остаётся как есть;
+//! - `archive` - доска переносится в архив так же, как это делает автор (см. `core::archive_board`): она перечисляется в списках досок участников отдельно и становится доступна только для чтения;
+//! - `export_and_delete` - доска выгружается в таблицу `board_exports` и удаляется; автор может получить выгрузку в течение `export_ttl_days` дней.
+//!
+//! Изменение доски после отметки снимает её. Автор может продлить хранение доски (`keep`): отметка снимается, доска возвращается из архива, а срок неактивности отсчитывается заново. Отметки хранятся в таблице `board_retention` и удаляются вместе с доской.
+
+use chrono::Utc;
+use custom_error::custom_error;
+use serde::Serialize;
+
+use crate::core::events::{self, EventKind};
+use crate::core::{load_board_as_author, remove_board};
+use crate::model::BoardContext;
+use crate::psql_handler::{transaction, Db};
+use crate::sec::auth::AccountPlanDetails;
+use crate::sec::tokens_vld::is_billed;
+use crate::setup::{RetentionAction, RetentionConfig, RetentionPolicy};
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NotAuthor{} = "Продлить хранение доски может только её автор."}
+custom_error!{pub NoSuchExport{} = "Выгрузка не найдена."}
+
+/// Число секунд в сутках.
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Число секунд в месяце, которым измеряется срок неактивности.
+const MONTH_SECS: i64 = 30 * DAY_SECS;
+
+/// Выгрузка доски, удалённой за неактивностью. Содержимое доски отдаёт `export`.
+#[derive(Serialize)]
+pub struct BoardExport {
+  pub id: i64,
+  pub board_id: i64,
+  pub title: String,
+  /// Время выгрузки в секундах Unix.
+  pub exported_at: i64,
+}
+
+/// Итог просмотра досок.
+#[derive(Default)]
+pub struct Outcome {
+  /// Доски, впервые отмеченные неактивными.
+  pub flagged: Vec<i64>,
+  /// Доски, перенесённые в архив.
+  pub archived: Vec<i64>,
+  /// Доски, выгруженные и удалённые.
+  pub exported: Vec<i64>,
+}
+
+/// Возвращает правила хранения досок тарифного плана.
+fn for_plan(cfg: &RetentionConfig, billed: bool) -> &RetentionPolicy {
+  match billed {
+    true => &cfg.paid,
+    false => &cfg.free,
+  }
+}
+
+/// Просматривает доски вне архива и применяет к неактивным правила хранения. Заодно удаляет устаревшие выгрузки.
+///
+/// Доска, которую изменили после того, как она была считана, не переносится в архив и не удаляется.
+pub async fn scan(db: &Db, cfg: &RetentionConfig) -> MResult<Outcome> {
+  let now = Utc::now().timestamp();
+  db.write("delete from board_exports where exported_at < $1;", &[&(now - cfg.export_ttl_days as i64 * DAY_SECS)]).await?;
+  let rows = db.read_all(
+    "select b.id, b.author, b.updated_at, coalesce(r.kept_at, 0), coalesce(r.flagged_at, 0), b.revision, u.apd \
+       from boards b join users u on u.id = b.author left join board_retention r on r.board_id = b.id \
+       where b.archived_at = 0 order by b.id;",
+    &[]
+  ).await?;
+  let mut outcome = Outcome::default();
+  for row in &rows {
+    let (board_id, author, updated_at, kept_at, flagged_at, revision): (i64, i64, i64, i64, i64, i64) =
+      (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5));
+    let billing: AccountPlanDetails = match serde_json::from_str(row.get(6)) {
+      Ok(billing) => billing,
+      Err(_) => continue,
+    };
+    let policy = for_plan(cfg, is_billed(&billing));
+    let active_at = updated_at.max(kept_at);
+    let inactive = policy.inactive_months.is_some_and(|months| active_at < now - months as i64 * MONTH_SECS);
+    // Отметка снимается, если доску изменили после неё или правила плана перестали считать доску неактивной.
+    if flagged_at != 0 && (!inactive || active_at > flagged_at) {
+      db.write("update board_retention set flagged_at = 0 where board_id = $1;", &[&board_id]).await?;
+      continue;
+    };
+    if !inactive { continue; };
+    if flagged_at == 0 {
+      db.write(
+        "insert into board_retention (board_id, flagged_at) values ($1, $2) \
+           on conflict (board_id) do update set flagged_at = $2;",
+        &[&board_id, &now]
+      ).await?;
+      events::publish(board_id, None, revision, EventKind::BoardInactive { author });
+      outcome.flagged.push(board_id);
+      continue;
+    };
+    if flagged_at > now - cfg.grace_days as i64 * DAY_SECS { continue; };
+    match policy.action {
+      RetentionAction::Flag => {},
+      RetentionAction::Archive => {
+        let rows = db.read_all(
+          "update boards set archived_at = $2 where id = $1 and updated_at = $3 and archived_at = 0 returning id;",
+          &[&board_id, &now, &updated_at]
+        ).await?;
+        if rows.is_empty() { continue; };
+        events::publish(board_id, None, revision, EventKind::BoardArchived { author });
+        outcome.archived.push(board_id);
+      },
+      RetentionAction::ExportAndDelete => {
+        // Ошибка одной доски не мешает остальным.
+        let exported = transaction::scope(export_and_delete(db, &board_id, updated_at, now)).await.map_err(|e| e.to_string());
+        match exported {
+          Ok(true) => outcome.exported.push(board_id),
+          Ok(false) => {},
+          Err(e) => eprintln!("Не удалось выгрузить и удалить доску {}: {}", board_id, e),
+        };
+      },
+    };
+  };
+  Ok(outcome)
+}
+
+/// Выгружает доску для автора и удаляет её. Возвращает `false`, если доску изменили после `updated_at`, и она не удалена.
+///
+/// Доска блокируется до удаления (см. `Storage::lock_board`), поэтому изменение, сделанное после проверки `updated_at`, не теряется. Выгрузка записывается в той же транзакции и откатывается, если доску удалить не удалось.
+async fn export_and_delete(db: &Db, board_id: &i64, updated_at: i64, now: i64) -> MResult<bool> {
+  db.lock_board(board_id).await?;
+  let ctx = load_board_as_author(db, board_id).await?;
+  if ctx.board.updated_at != updated_at { return Ok(false); };
+  let (author, revision) = (ctx.board.author, ctx.board.revision);
+  let document = serde_json::to_string(&ctx.board)?;
+  let export_id: i64 = db.read(
+    "insert into board_exports (user_id, board_id, title, exported_at, document) values ($1, $2, $3, $4, $5) returning id;",
+    &[&author, board_id, &ctx.board.header.title, &now, &document]
+  ).await?.get(0);
+  remove_board(db, ctx).await?;
+  events::publish(*board_id, None, revision, EventKind::BoardExported { author, export_id });
+  Ok(true)
+}
+
+/// Продлевает хранение доски: снимает отметку о неактивности, возвращает доску из архива и отсчитывает срок неактивности заново.
+pub async fn keep(db: &Db, ctx: &BoardContext) -> MResult<()> {
+  if ctx.board.author != ctx.user_id { return Err(Box::new(NotAuthor{})); };
+  let now = Utc::now().timestamp();
+  db.write_mul(vec![
+    (
+      "insert into board_retention (board_id, kept_at) values ($1, $2) \
+         on conflict (board_id) do update set flagged_at = 0, kept_at = $2;",
+      vec![&ctx.board.id, &now]
+    ),
+    ("update boards set archived_at = 0 where id = $1;", vec![&ctx.board.id]),
+  ]).await?;
+  if ctx.board.archived_at != 0 {
+    events::publish(ctx.board.id, Some(ctx.user_id), ctx.board.revision, EventKind::BoardUnarchived);
+  };
+  Ok(())
+}
+
+/// Возвращает выгрузки досок пользователя, начиная с последних.
+pub async fn exports(db: &Db, user_id: &i64) -> MResult<Vec<BoardExport>> {
+  let rows = db.read_all(
+    "select id, board_id, title, exported_at from board_exports where user_id = $1 order by id desc;",
+    &[user_id]
+  ).await?;
+  Ok(rows.iter().map(|row| BoardExport { id: row.get(0), board_id: row.get(1), title: row.get(2), exported_at: row.get(3) }).collect())
+}
+
+/// Возвращает содержимое выгрузки доски пользователя в виде JSON.
+pub async fn export(db: &Db, user_id: &i64, export_id: &i64) -> MResult<String> {
+  let rows = db.read_all("select document from board_exports where id = $1 and user_id = $2;", &[export_id, user_id]).await?;
+  match rows.first() {
+    Some(row) => Ok(row.get(0)),
+    None => Err(Box::new(NoSuchExport{})),
+  }
+}
+
+/// Периодическое задание (см. `jobs`): запускает `scan` и записывает в журнал сервера доски, к которым применены правила хранения.
+pub async fn job(db: Db, cfg: RetentionConfig) -> MResult<()> {
+  let outcome = scan(&db, &cfg).await?;
+  if !outcome.flagged.is_empty() {
+    println!("Отмечены неактивные доски: {:?}.", outcome.flagged);
+  };
+  if !outcome.archived.is_empty() {
+    println!("Перенесены в архив неактивные доски: {:?}.", outcome.archived);
+  };
+  if !outcome.exported.is_empty() {
+    println!("Выгружены и удалены неактивные доски: {:?}.", outcome.exported);
+  };
+  Ok(())
+}
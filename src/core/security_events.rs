@@ -0,0 +1,101 @@
+//! Отвечает за журнал входов в аккаунты и оповещения о подозрительных входах.
+//!
+//! Каждый успешный вход записывается в таблицу `security_events` вместе с адресом клиента, его страной (если задан источник GeoIP, см. `sec::geoip`) и заголовком `User-Agent`. Записи о входах служат списком привычных мест пользователя: если он уже входил в аккаунт, а новый вход выполнен с адреса и из страны (если она известна), с которых входов не было, вход записывается как `new_location`. Блокировка входа после повторных неудачных попыток (см. `core::sign_in_creds_to_id`) записывается как `sign_in_locked` - не чаще раза за время блокировки.
+//!
+//! О событиях `new_location` и `sign_in_locked` пользователь получает уведомление (см. `notifications::notify_user`). Записи журнала хранятся только в PostgreSQL и не попадают в резервную копию.
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::core::notifications;
+use crate::psql_handler::Db;
+use crate::sec::auth::ClientInfo;
+use crate::sec::geoip;
+use crate::setup::AppConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число записей, которое можно получить за один запрос.
+pub const MAX_SECURITY_EVENTS_PAGE: i64 = 100;
+
+/// Запись журнала.
+#[derive(Serialize)]
+pub struct SecurityEvent {
+  pub id: i64,
+  /// Вид события: `sign_in`, `new_location` или `sign_in_locked`.
+  pub kind: String,
+  /// Адрес клиента.
+  pub ip: Option<String>,
+  /// Код страны клиента, если её удалось определить.
+  pub country: Option<String>,
+  pub user_agent: Option<String>,
+  /// Время события в секундах Unix.
+  pub at: i64,
+}
+
+/// Записывает успешный вход пользователя и оповещает его, если вход выполнен из нового места.
+pub async fn record_sign_in(db: &Db, cfg: &AppConfig, user_id: i64, client: &ClientInfo) -> MResult<()> {
+  let country = country(cfg, client);
+  let rows = db.read_all(
+    "select count(*), count(*) filter (where ip = $3 or country = $2) from security_events \
+       where user_id = $1 and kind in ('sign_in', 'new_location');",
+    &[&user_id, &country, &client.ip]
+  ).await?;
+  let (sign_ins, known): (i64, i64) = rows.first().map(|row| (row.get(0), row.get(1))).unwrap_or_default();
+  let kind = match sign_ins > 0 && known == 0 {
+    true => "new_location",
+    false => "sign_in",
+  };
+  insert(db, user_id, kind, client, &country).await?;
+  if kind == "new_location" { notifications::notify_user(db, user_id, kind).await?; };
+  Ok(())
+}
+
+/// Записывает блокировку входа в аккаунт с данным логином и оповещает владельца аккаунта.
+///
+/// Повторные попытки входа во время блокировки не записываются. Если аккаунта с таким логином нет, ничего не делает.
+pub async fn record_lockout(db: &Db, cfg: &AppConfig, login: &str, client: &ClientInfo) -> MResult<()> {
+  let rows = db.read_all(
+    "select u.id from users u where u.login = $1 and not exists ( \
+       select 1 from security_events e where e.user_id = u.id and e.kind = 'sign_in_locked' and e.at > $2);",
+    &[&login, &(Utc::now().timestamp() - cfg.sign_in_lockout_secs)]
+  ).await?;
+  let user_id: i64 = match rows.first() {
+    Some(row) => row.get(0),
+    None => return Ok(()),
+  };
+  insert(db, user_id, "sign_in_locked", client, &country(cfg, client)).await?;
+  notifications::notify_user(db, user_id, "sign_in_locked").await
+}
+
+/// Возвращает до `limit` записей журнала пользователя, начиная с последних.
+///
+/// Если задан `before`, возвращаются записи с меньшими идентификаторами.
+pub async fn list(db: &Db, user_id: &i64, before: Option<i64>, limit: i64) -> MResult<Vec<SecurityEvent>> {
+  let rows = db.read_all(
+    "select id, kind, ip, country, user_agent, at from security_events \
+       where user_id = $1 and ($2::bigint is null or id < $2) order by id desc limit $3;",
+    &[user_id, &before, &limit]
+  ).await?;
+  Ok(rows.iter().map(|row| SecurityEvent {
+    id: row.get(0),
+    kind: row.get(1),
+    ip: row.get(2),
+    country: row.get(3),
+    user_agent: row.get(4),
+    at: row.get(5),
+  }).collect())
+}
+
+/// Определяет страну клиента, если задан источник GeoIP.
+fn country(cfg: &AppConfig, client: &ClientInfo) -> Option<String> {
+  let ip = client.ip.as_ref()?.parse().ok()?;
+  geoip::provider(cfg)?.country(&ip)
+}
+
+async fn insert(db: &Db, user_id: i64, kind: &str, client: &ClientInfo, country: &Option<String>) -> MResult<()> {
+  db.write(
+    "insert into security_events (user_id, kind, ip, country, user_agent, at) values ($1, $2, $3, $4, $5, $6);",
+    &[&user_id, &kind, &client.ip, country, &client.user_agent, &Utc::now().timestamp()]
+  ).await
+}
@@ -0,0 +1,179 @@
+//! Отвечает за открытую регистрацию с подтверждением адреса электронной почты.
+//!
+//! Пользователь, зарегистрировавшийся через `PUT /sign-up/email`, сначала попадает в таблицу неподтверждённых регистраций (`pending_users`) и получает письмо со ссылкой подтверждения. Аккаунт создаётся, только когда клиент передаст токен из ссылки, пока тот не истёк; до этого логин занят регистрацией. В базе данных хранится только хэш токена, поэтому повторная отправка письма выпускает новую ссылку, а прежняя перестаёт действовать.
+//!
+//! Регистрации и повторные отправки писем подсчитываются по адресу клиента (таблица `signup_attempts`), чтобы с одного адреса нельзя было рассылать письма без ограничений. Фоновая задача периодически удаляет регистрации с истёкшими ссылками и устаревшие попытки.
+
+use chrono::Utc;
+use custom_error::custom_error;
+use serde::Serialize;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
+
+use crate::core::digest;
+use crate::core::{new_user_data, LoginTaken};
+use crate::integrations::mailer::{Mail, Mailer, MailerError};
+use crate::psql_handler::{transaction, Db};
+use crate::sec::auth::EmailSignUpCredentials;
+use crate::sec::tokens_vld::hash_token;
+use crate::setup::{AppConfig, MailerConfig, PublicSignupConfig};
+use crate::storage::{NewUser, Storage};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub SignUpLimited{until: i64} = "Слишком много регистраций с вашего адреса. Повторите позже."}
+custom_error!{pub WrongConfirmation{} = "Ссылка подтверждения недействительна или устарела. Зарегистрируйтесь заново."}
+
+/// Число секунд, за которые подсчитываются попытки регистрации с одного адреса.
+const ATTEMPTS_WINDOW_SECS: i64 = 60 * 60;
+
+const SUBJECT: &str = "Подтверждение регистрации";
+
+/// Неподтверждённая регистрация, о которой сообщается клиенту.
+#[derive(Serialize)]
+pub struct PendingSignUp {
+  /// Время, до которого действует ссылка подтверждения (UNIX-время в секундах).
+  pub expires_at: i64,
+}
+
+/// Регистрирует пользователя до подтверждения адреса и отправляет ему письмо со ссылкой подтверждения.
+///
+/// Логин, занятый пользователем или неподтверждённой регистрацией с другим адресом, не принимается (`LoginTaken`). Регистрация с тем же логином и адресом заменяет прежнюю. Если с адреса клиента `ip` за последний час было слишком много попыток, функция возвращает `SignUpLimited`. Если письмо отправить не удалось, регистрация отменяется.
+pub async fn request(
+  db: &Db,
+  cfg: &AppConfig,
+  signup: &PublicSignupConfig,
+  mailer: &MailerConfig,
+  creds: &EmailSignUpCredentials,
+  ip: &str,
+) -> MResult<PendingSignUp> {
+  let email = digest::email(&creds.email)?;
+  let now = Utc::now().timestamp();
+  count_attempt(db, signup, ip, now).await?;
+  let taken = db.read_all("select 1 from users where login = $1;", &[&creds.login]).await?;
+  if !taken.is_empty() { return Err(Box::new(LoginTaken{})); };
+  let (user_credentials, billing) = new_user_data(&creds.pass, cfg)?;
+  let token = new_token();
+  let (token_hash, expires_at) = (hash_token(&token), now + signup.token_ttl_secs);
+  let queries = vec![
+    ("delete from pending_users where login = $1 and (expires_at <= $2 or email = $3);", vec![&creds.login as &(dyn ToSql + Sync), &now, &email]),
+    (
+      "insert into pending_users values ($1, $2, $3, $4, $5, $6, $7, $6);",
+      vec![&creds.login, &email, &user_credentials, &billing, &token_hash, &now, &expires_at]
+    ),
+  ];
+  if let Err(e) = db.write_mul(queries).await {
+    return match e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code()) == Some(&SqlState::UNIQUE_VIOLATION) {
+      true => Err(Box::new(LoginTaken{})),
+      false => Err(e),
+    };
+  };
+  // Ошибка отправки приводится к `MailerError`, чтобы её можно было держать, пока регистрация отменяется.
+  let failed = match send(signup, mailer, &email, &token).await {
+    Ok(()) => None,
+    Err(e) => Some(e.downcast::<MailerError>().map_or_else(|e| MailerError{ reason: e.to_string() }, |e| *e)),
+  };
+  if let Some(e) = failed {
+    db.write("delete from pending_users where login = $1;", &[&creds.login]).await?;
+    return Err(Box::new(e));
+  };
+  Ok(PendingSignUp { expires_at })
+}
+
+/// Отправляет письмо со ссылкой подтверждения повторно.
+///
+/// Письмо можно отправить повторно не раньше чем через `resend_cooldown_secs` после предыдущего, иначе функция возвращает `SignUpLimited`. Чтобы по ответу нельзя было узнать, кто регистрируется, для логина без неподтверждённой регистрации функция ничего не делает и завершается успешно.
+pub async fn resend(db: &Db, signup: &PublicSignupConfig, mailer: &MailerConfig, login: &str, ip: &str) -> MResult<()> {
+  let now = Utc::now().timestamp();
+  count_attempt(db, signup, ip, now).await?;
+  let rows = db.read_all("select email, sent_at from pending_users where login = $1 and expires_at > $2;", &[&login, &now]).await?;
+  let (email, sent_at): (String, i64) = match rows.first() {
+    Some(row) => (row.get(0), row.get(1)),
+    None => return Ok(()),
+  };
+  if sent_at + signup.resend_cooldown_secs > now {
+    return Err(Box::new(SignUpLimited{ until: sent_at + signup.resend_cooldown_secs }));
+  };
+  let token = new_token();
+  db.write(
+    "update pending_users set token_hash = $2, expires_at = $3, sent_at = $4 where login = $1;",
+    &[&login, &hash_token(&token), &(now + signup.token_ttl_secs), &now]
+  ).await?;
+  send(signup, mailer, &email, &token).await
+}
+
+/// Подтверждает регистрацию по токену из ссылки и возвращает идентификатор созданного пользователя.
+///
+/// Каждый токен принимается только один раз. Адрес, на который пришло письмо, становится адресом для [дайджестов](crate::core::digest) пользователя.
+pub async fn confirm(db: &Db, token: &str) -> MResult<i64> {
+  transaction::scope(create_confirmed(db, token)).await
+}
+
+/// Создаёт пользователя неподтверждённой регистрации с данным токеном.
+async fn create_confirmed(db: &Db, token: &str) -> MResult<i64> {
+  let rows = db.read_all(
+    "delete from pending_users where token_hash = $1 and expires_at > $2 returning login, email, user_creds, apd;",
+    &[&hash_token(token), &Utc::now().timestamp()]
+  ).await?;
+  let row = rows.first().ok_or(WrongConfirmation{})?;
+  let (login, email, user_creds, apd): (String, String, String, String) = (row.get(0), row.get(1), row.get(2), row.get(3));
+  let user = NewUser { login: &login, user_creds: &user_creds, apd: &apd };
+  let id = match db.insert_user(&user, None).await {
+    // Логин успели занять регистрацией без подтверждения.
+    Err(e) if e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code())
+      == Some(&SqlState::UNIQUE_VIOLATION) => return Err(Box::new(LoginTaken{})),
+    res => res?.ok_or(WrongConfirmation{})?,
+  };
+  db.write("insert into notification_prefs (user_id, email) values ($1, $2);", &[&id, &email]).await?;
+  Ok(id)
+}
+
+/// Удаляет регистрации с истёкшими ссылками и попытки регистрации, которые больше не учитываются.
+pub async fn cleanup(db: &Db) -> MResult<u64> {
+  let now = Utc::now().timestamp();
+  let removed = db.read_all("delete from pending_users where expires_at <= $1 returning login;", &[&now]).await?.len() as u64;
+  db.write("delete from signup_attempts where at <= $1;", &[&(now - ATTEMPTS_WINDOW_SECS)]).await?;
+  Ok(removed)
+}
+
+/// Периодическое задание (см. `jobs`): запускает `cleanup` и записывает в журнал сервера число удалённых регистраций.
+pub async fn job(db: Db) -> MResult<()> {
+  match cleanup(&db).await? {
+    0 => {},
+    removed => println!("Удалены неподтверждённые регистрации: {}.", removed),
+  };
+  Ok(())
+}
+
+/// Учитывает попытку регистрации с адреса клиента или возвращает `SignUpLimited`, если попыток за последний час уже слишком много.
+async fn count_attempt(db: &Db, signup: &PublicSignupConfig, ip: &str, now: i64) -> MResult<()> {
+  let row = db.read(
+    "select count(*), min(at) from signup_attempts where ip = $1 and at > $2;",
+    &[&ip, &(now - ATTEMPTS_WINDOW_SECS)]
+  ).await?;
+  let (attempts, first): (i64, Option<i64>) = (row.get(0), row.get(1));
+  if attempts >= signup.max_per_ip_per_hour {
+    return Err(Box::new(SignUpLimited{ until: first.unwrap_or(now) + ATTEMPTS_WINDOW_SECS }));
+  };
+  db.write("insert into signup_attempts values ($1, $2);", &[&ip, &now]).await?;
+  Ok(())
+}
+
+/// Возвращает новый токен подтверждения.
+fn new_token() -> String {
+  format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Отправляет письмо со ссылкой подтверждения.
+async fn send(signup: &PublicSignupConfig, mailer: &MailerConfig, email: &str, token: &str) -> MResult<()> {
+  let separator = match signup.confirm_url.contains('?') {
+    true => '&',
+    false => '?',
+  };
+  let text = format!(
+    "Чтобы завершить регистрацию в CC TaskBoard, перейдите по ссылке:\n\n{}{}token={}\n\nСсылка действует {} ч. Если вы не регистрировались, проигнорируйте это письмо.",
+    signup.confirm_url, separator, token, (signup.token_ttl_secs + 3599) / 3600
+  );
+  Mailer::new(&mailer.url, &mailer.token, &mailer.from).send(&Mail { to: email, subject: SUBJECT, text: &text }).await
+}
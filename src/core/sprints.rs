@@ -0,0 +1,174 @@
+//! Отвечает за спринты досок и диаграммы сгорания задач.
+//!
+//! Спринт - период, за который команда планирует выполнить часть задач доски. Задача входит в спринт, если ссылается на него (`Task::sprint_id`), и может быть оценена в очках (`Task::story_points`).
+//!
+//! Диаграмма сгорания строится по истории изменений задач (см. `task_history`): для конца каждого дня спринта восстанавливаются статус, спринт и оценка каждой задачи доски - прежнее значение из первого изменения поля после этого момента или, если поле с тех пор не менялось, текущее значение. История хранит ограниченное число изменений каждой задачи, поэтому для задач, которые меняли очень часто, начало диаграммы может быть неточным.
+
+use chrono::{TimeZone, Utc};
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+use crate::core::events::EventKind;
+use crate::core::save_board;
+use crate::core::validation;
+use crate::model::{BoardContext, Sprint, SprintPatch, Task};
+use crate::psql_handler::Db;
+use crate::storage::Storage;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NoSuchSprint{} = "Не удалось найти спринт по идентификатору."}
+custom_error!{pub WrongSprint{reason: String} = "Спринт не принят: {reason}."}
+
+/// Наибольшая длина спринта в днях.
+const MAX_SPRINT_DAYS: i64 = 366;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Поля задачи, по истории которых строится диаграмма сгорания.
+const BURNDOWN_FIELDS: [&str; 3] = ["exec", "sprint_id", "story_points"];
+
+/// Единица объёма работ на диаграмме сгорания.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unit {
+  /// Число задач.
+  #[default]
+  Tasks,
+  /// Сумма оценок задач в очках; задачи без оценки не учитываются.
+  Points,
+}
+
+/// Объём работ спринта на конец дня.
+#[derive(Serialize)]
+pub struct Day {
+  /// Дата начала дня спринта (UTC) в формате `ГГГГ-ММ-ДД`.
+  pub date: String,
+  /// Объём всех задач спринта.
+  pub scope: u64,
+  /// Объём невыполненных задач спринта.
+  pub remaining: u64,
+  /// Объём, который должен был остаться при равномерной работе с первого дня спринта.
+  pub ideal: f64,
+}
+
+/// Диаграмма сгорания спринта.
+#[derive(Serialize)]
+pub struct Report {
+  pub sprint_id: i64,
+  pub unit: Unit,
+  pub start: i64,
+  pub end: i64,
+  /// Дни спринта от его начала до конца или до текущего момента, если спринт ещё идёт.
+  pub days: Vec<Day>,
+}
+
+/// Проверяет спринт и приводит его название к виду, в котором оно хранится.
+pub fn validate(sprint: &mut Sprint) -> MResult<()> {
+  sprint.title = validation::title("спринта", &sprint.title)?;
+  if sprint.start >= sprint.end {
+    return Err(Box::new(WrongSprint{ reason: "начало спринта должно быть раньше его конца".into() }));
+  };
+  if sprint.end - sprint.start > MAX_SPRINT_DAYS * DAY_SECS {
+    return Err(Box::new(WrongSprint{ reason: format!("спринт не может быть длиннее {} дней", MAX_SPRINT_DAYS) }));
+  };
+  Ok(())
+}
+
+/// Создаёт спринт доски.
+pub async fn create(db: &dyn Storage, ctx: &mut BoardContext, sprint: &Sprint) -> MResult<i64> {
+  let mut sprint = sprint.clone();
+  validate(&mut sprint)?;
+  let board_sprints_id_seq = ctx.board.id.to_string() + "_sprints";
+  let min_sprint_id = ctx.board.sprints.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+  sprint.id = db.next_id(&board_sprints_id_seq, min_sprint_id).await?;
+  let id = sprint.id;
+  ctx.board.sprints.push(sprint);
+  save_board(db, ctx, EventKind::SprintCreated { sprint_id: id }, vec![]).await?;
+  Ok(id)
+}
+
+/// Редактирует спринт доски.
+pub async fn patch(db: &dyn Storage, ctx: &mut BoardContext, sprint_id: &i64, patch: SprintPatch) -> MResult<()> {
+  let sprint = ctx.board.sprints.iter_mut().find(|s| s.id == *sprint_id).ok_or(NoSuchSprint{})?;
+  let mut patched = sprint.clone();
+  if let Some(title) = patch.title { patched.title = title; };
+  if let Some(start) = patch.start { patched.start = start; };
+  if let Some(end) = patch.end { patched.end = end; };
+  validate(&mut patched)?;
+  *sprint = patched;
+  save_board(db, ctx, EventKind::SprintUpdated { sprint_id: *sprint_id }, vec![]).await
+}
+
+/// Удаляет спринт доски.
+///
+/// Задачи спринта остаются на доске вне спринтов.
+pub async fn delete(db: &dyn Storage, ctx: &mut BoardContext, sprint_id: &i64) -> MResult<()> {
+  let board_sprints = &mut ctx.board.sprints;
+  board_sprints.remove(board_sprints.iter().position(|s| s.id == *sprint_id).ok_or(NoSuchSprint{})?);
+  for card in &mut ctx.board.cards {
+    for task in &mut card.tasks {
+      if task.sprint_id == Some(*sprint_id) { task.sprint_id = None; };
+    };
+  };
+  save_board(db, ctx, EventKind::SprintDeleted { sprint_id: *sprint_id }, vec![]).await
+}
+
+/// Изменение поля задачи из истории: время изменения и прежнее значение.
+type FieldChanges = HashMap<String, Vec<(i64, JsonValue)>>;
+
+/// Строит диаграмму сгорания спринта.
+pub async fn report(db: &Db, ctx: &BoardContext, sprint_id: &i64, unit: Unit) -> MResult<Report> {
+  let sprint = ctx.board.sprints.iter().find(|s| s.id == *sprint_id).ok_or(NoSuchSprint{})?;
+  let rows = db.read_all(
+    "select card_id, task_id, field, old_value, at from task_history \
+       where board_id = $1 and at > $2 and field in ($3, $4, $5) order by id;",
+    &[&ctx.board.id, &sprint.start, &BURNDOWN_FIELDS[0], &BURNDOWN_FIELDS[1], &BURNDOWN_FIELDS[2]]
+  ).await?;
+  let mut history: HashMap<(i64, i64), FieldChanges> = HashMap::new();
+  for row in &rows {
+    let old: JsonValue = serde_json::from_str(row.get(3))?;
+    history.entry((row.get(0), row.get(1))).or_default().entry(row.get(2)).or_default().push((row.get(4), old));
+  };
+  let history = &history;
+  let tasks: Vec<(&Task, Option<&FieldChanges>)> = ctx.board.cards.iter()
+    .flat_map(|card| card.tasks.iter().map(move |task| (task, history.get(&(card.id, task.id)))))
+    .collect();
+  // Объём работ спринта на момент `at`: все задачи и невыполненные задачи.
+  let volume = |at: i64| -> MResult<(u64, u64)> {
+    let (mut scope, mut remaining) = (0, 0);
+    for (task, changes) in tasks.iter().filter(|(task, _)| task.created_at <= at) {
+      let value = |field: &str, current: JsonValue| changes
+        .and_then(|changes| changes.get(field))
+        .and_then(|changes| changes.iter().find(|(changed_at, _)| *changed_at > at))
+        .map_or(current, |(_, old)| old.clone());
+      let sprint_id: Option<i64> = serde_json::from_value(value("sprint_id", serde_json::to_value(task.sprint_id)?))?;
+      if sprint_id != Some(sprint.id) { continue; };
+      let weight = match unit {
+        Unit::Tasks => 1,
+        Unit::Points => serde_json::from_value::<Option<u32>>(value("story_points", serde_json::to_value(task.story_points)?))?
+          .unwrap_or(0) as u64,
+      };
+      scope += weight;
+      if !serde_json::from_value::<bool>(value("exec", JsonValue::Bool(task.exec)))? { remaining += weight; };
+    };
+    Ok((scope, remaining))
+  };
+  let total_days = (sprint.end - sprint.start + DAY_SECS - 1) / DAY_SECS;
+  let now = Utc::now().timestamp();
+  let mut days = Vec::new();
+  for i in 0..total_days {
+    let day_start = sprint.start + i * DAY_SECS;
+    if day_start > now { break; };
+    let at = (day_start + DAY_SECS - 1).min(sprint.end).min(now);
+    let (scope, remaining) = volume(at)?;
+    // Задачи обычно добавляют в спринт в его первый день, поэтому идеальная линия начинается с объёма на конец первого дня.
+    let initial = days.first().map_or(scope, |day: &Day| day.scope);
+    let ideal = initial as f64 * (total_days - i - 1) as f64 / total_days as f64;
+    let date = Utc.timestamp_opt(day_start, 0).single().map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default();
+    days.push(Day { date, scope, remaining, ideal: (ideal * 100.0).round() / 100.0 });
+  };
+  Ok(Report { sprint_id: sprint.id, unit, start: sprint.start, end: sprint.end, days })
+}
@@ -0,0 +1,141 @@
+//! Отвечает за статистику доски.
+//!
+//! Статистика описывает доску в момент запроса: сколько на ней задач, сколько из них выполнено, просрочено и не назначено, как задачи распределены по карточкам и сколько невыполненных задач у каждого участника. Подзадачи в статистику не попадают. Ту же статистику сервер отправляет в отчётах о доске (см. `reports`).
+//!
+//! По невыполненным задачам участников сервер также предлагает, кого назначить исполнителем задачи (см. `suggest_executors`).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::model::{Board, Cards, GetTaskError};
+
+/// Задачи карточки.
+#[derive(Serialize)]
+pub struct CardStats {
+  pub card_id: i64,
+  pub title: String,
+  pub tasks: u64,
+  pub done: u64,
+}
+
+/// Невыполненные задачи участника доски.
+#[derive(Serialize)]
+pub struct ExecutorStats {
+  pub user_id: i64,
+  pub open: u64,
+  /// Невыполненные задачи, обязательный срок которых прошёл.
+  pub overdue: u64,
+}
+
+/// Статистика доски.
+#[derive(Serialize)]
+pub struct BoardStats {
+  pub board_id: i64,
+  pub title: String,
+  /// Время, на которое собрана статистика, в секундах Unix.
+  pub at: i64,
+  pub tasks: u64,
+  pub done: u64,
+  /// Невыполненные задачи, обязательный срок которых прошёл.
+  pub overdue: u64,
+  /// Невыполненные задачи без исполнителей.
+  pub unassigned: u64,
+  /// Карточки в порядке на доске.
+  pub cards: Vec<CardStats>,
+  /// Все участники доски, в том числе без задач, - сначала те, у кого больше невыполненных задач.
+  pub executors: Vec<ExecutorStats>,
+}
+
+/// Участник доски, предложенный в исполнители задачи.
+#[derive(Serialize)]
+pub struct ExecutorSuggestion {
+  pub user_id: i64,
+  pub open: u64,
+  /// Невыполненные задачи, обязательный срок которых прошёл.
+  pub overdue: u64,
+  /// Сумма ожидаемого времени невыполненных задач участника в минутах.
+  pub open_minutes: u64,
+}
+
+/// Сводка по задачам доски - итоги статистики без разбивки по карточкам и участникам.
+#[derive(Serialize)]
+pub struct BoardSummary {
+  pub cards: u64,
+  pub tasks: u64,
+  pub done: u64,
+  /// Невыполненные задачи, обязательный срок которых прошёл.
+  pub overdue: u64,
+  /// Невыполненные задачи без исполнителей.
+  pub unassigned: u64,
+}
+
+/// Собирает сводку по задачам доски на момент `now`.
+pub fn summarize(board: &Board, now: &DateTime<Utc>) -> BoardSummary {
+  let stats = collect(board, now);
+  BoardSummary {
+    cards: stats.cards.len() as u64,
+    tasks: stats.tasks,
+    done: stats.done,
+    overdue: stats.overdue,
+    unassigned: stats.unassigned,
+  }
+}
+
+/// Собирает статистику доски на момент `now`.
+pub fn collect(board: &Board, now: &DateTime<Utc>) -> BoardStats {
+  let mut executors: BTreeMap<i64, (u64, u64)> = board.shared_with.iter().map(|id| (*id, (0, 0))).collect();
+  let mut stats = BoardStats {
+    board_id: board.id,
+    title: board.header.title.clone(),
+    at: now.timestamp(),
+    tasks: 0,
+    done: 0,
+    overdue: 0,
+    unassigned: 0,
+    cards: vec![],
+    executors: vec![],
+  };
+  for card in &board.cards {
+    let done = card.tasks.iter().filter(|task| task.exec).count() as u64;
+    stats.cards.push(CardStats { card_id: card.id, title: card.title.clone(), tasks: card.tasks.len() as u64, done });
+    stats.tasks += card.tasks.len() as u64;
+    stats.done += done;
+    for task in card.tasks.iter().filter(|task| !task.exec) {
+      let overdue = task.timelines.is_overdue(now);
+      if overdue { stats.overdue += 1; };
+      if task.executors.is_empty() { stats.unassigned += 1; };
+      for executor in &task.executors {
+        // Исполнители, потерявшие доступ к доске, в статистику не попадают.
+        if let Some((open, late)) = executors.get_mut(executor) {
+          *open += 1;
+          if overdue { *late += 1; };
+        };
+      };
+    };
+  };
+  stats.executors = executors.into_iter().map(|(user_id, (open, overdue))| ExecutorStats { user_id, open, overdue }).collect();
+  stats.executors.sort_by_key(|executor| std::cmp::Reverse(executor.open));
+  stats
+}
+
+/// Предлагает исполнителей задачи на момент `now`: участников доски, ещё не назначенных её исполнителями, - сначала наименее загруженных.
+///
+/// Загрузка участника - сумма ожидаемого времени его невыполненных задач, а при равенстве - число этих задач и число просроченных среди них. Ожидаемое время задачи с несколькими исполнителями учитывается у каждого из них целиком.
+pub fn suggest_executors(board: &Board, card_id: &i64, task_id: &i64, now: &DateTime<Utc>) -> Result<Vec<ExecutorSuggestion>, GetTaskError> {
+  let assigned = &board.cards.get_task(card_id, task_id)?.executors;
+  let mut minutes: BTreeMap<i64, u64> = BTreeMap::new();
+  for task in board.cards.iter().flat_map(|card| &card.tasks).filter(|task| !task.exec) {
+    for executor in &task.executors {
+      *minutes.entry(*executor).or_default() += task.timelines.expected_time as u64;
+    };
+  };
+  let mut suggestions: Vec<ExecutorSuggestion> = collect(board, now).executors.into_iter()
+    .filter(|executor| !assigned.contains(&executor.user_id))
+    .map(|ExecutorStats { user_id, open, overdue }| ExecutorSuggestion {
+      user_id, open, overdue, open_minutes: minutes.get(&user_id).copied().unwrap_or(0),
+    })
+    .collect();
+  suggestions.sort_by_key(|s| (s.open_minutes, s.open, s.overdue, s.user_id));
+  Ok(suggestions)
+}
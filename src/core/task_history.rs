@@ -0,0 +1,178 @@
+//! Отвечает за историю изменений задач.
+//!
+//! Каждое изменение поля задачи - названия, исполнителей, статуса, сроков и т.д. - записывается в таблицу `task_history` вместе с прежним и новым значением, автором изменения и временем. Запись делается в одной транзакции с доской (см. `save_board`), поэтому история не расходится с содержимым доски. Для каждой задачи хранятся только последние `MAX_TASK_HISTORY` изменений; история удаляется вместе с задачей.
+//!
+//! Функции, изменяющие задачу, снимают её поля до изменения (`snapshot`), а перед записью доски сравнивают их с полями после изменения (`Snapshot::changes`).
+//!
+//! Кроме того, у каждого изменённого поля в задаче отмечаются ревизия доски и время изменения (`Task::field_stamps`). По ним изменение, сделанное клиентом над устаревшей ревизией, сливается с изменениями других пользователей по полям: поля, которые другие пользователи не меняли, изменяются, а поля, изменённые обеими сторонами по-разному, описываются конфликтами (`conflicts`).
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Map, Value as JsonValue};
+use tokio_postgres::types::ToSql;
+
+use crate::model::{BoardContext, Cards, FieldStamp};
+use crate::psql_handler::Db;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Наибольшее число изменений, которое хранится для одной задачи.
+pub const MAX_TASK_HISTORY: i64 = 100;
+
+/// Поля задачи, которые не попадают в историю: неизменяемые, поддерживаемые сервером и подзадачи, у которых своя история изменений.
+const UNTRACKED_FIELDS: [&str; 12] = [
+  "id", "author", "subtasks", "blocked", "overdue", "due_soon", "github_issue", "created_at", "updated_at", "field_stamps", "assignments",
+  "watchers",
+];
+
+#[derive(Debug)]
+pub struct TaskConflict {
+  pub conflicts: Vec<FieldConflict>,
+}
+
+impl std::fmt::Display for TaskConflict {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Поля задачи изменены после ревизии, над которой сделано изменение.")
+  }
+}
+
+impl std::error::Error for TaskConflict {}
+
+/// Поле задачи, которое изменили и клиент, и после базовой ревизии клиента кто-то другой.
+#[derive(Debug, Serialize)]
+pub struct FieldConflict {
+  /// Название поля, как в JSON задачи.
+  pub field: String,
+  /// Текущее значение поля.
+  pub server_value: JsonValue,
+  /// Значение поля, которое передал клиент.
+  pub client_value: JsonValue,
+  /// Последнее изменение поля на сервере.
+  pub stamp: FieldStamp,
+}
+
+/// Изменение поля задачи.
+#[derive(Serialize)]
+pub struct TaskChange {
+  /// Название поля, как в JSON задачи.
+  pub field: String,
+  pub old: JsonValue,
+  pub new: JsonValue,
+  /// Пользователь, изменивший поле.
+  pub actor: i64,
+  /// Время изменения в секундах Unix.
+  pub at: i64,
+}
+
+/// Поля задачи до изменения.
+pub struct Snapshot {
+  card_id: i64,
+  task_id: i64,
+  fields: Map<String, JsonValue>,
+}
+
+/// Изменения полей задачи, подготовленные к записи вместе с доской.
+pub struct Changes {
+  board_id: i64,
+  card_id: i64,
+  task_id: i64,
+  actor: i64,
+  at: i64,
+  /// JSON-массив изменений вида `{"field": ..., "old": ..., "new": ...}`.
+  changes: String,
+  empty: bool,
+}
+
+/// Снимает поля задачи до изменения.
+pub fn snapshot(ctx: &BoardContext, card_id: &i64, task_id: &i64) -> MResult<Snapshot> {
+  Ok(Snapshot { card_id: *card_id, task_id: *task_id, fields: tracked_fields(ctx, card_id, task_id)? })
+}
+
+impl Snapshot {
+  /// Сравнивает снятые поля с текущими полями задачи и отмечает у задачи изменение изменившихся полей в следующей ревизии доски.
+  pub fn changes(self, ctx: &mut BoardContext) -> MResult<Changes> {
+    let after = tracked_fields(ctx, &self.card_id, &self.task_id)?;
+    let changed: Vec<(&String, &JsonValue, &JsonValue)> = after.iter()
+      .map(|(field, new)| (field, self.fields.get(field).unwrap_or(&JsonValue::Null), new))
+      .filter(|(_, old, new)| old != new)
+      .collect();
+    let at = Utc::now().timestamp();
+    let stamp = FieldStamp { revision: ctx.board.revision + 1, at };
+    let task = ctx.board.cards.get_mut_task(&self.card_id, &self.task_id)?;
+    changed.iter().for_each(|(field, _, _)| { task.field_stamps.insert(field.to_string(), stamp.clone()); });
+    let changes: Vec<JsonValue> = changed.iter()
+      .map(|(field, old, new)| json!({ "field": field, "old": old, "new": new }))
+      .collect();
+    Ok(Changes {
+      board_id: ctx.board.id,
+      card_id: self.card_id,
+      task_id: self.task_id,
+      actor: ctx.user_id,
+      at,
+      empty: changes.is_empty(),
+      changes: serde_json::to_string(&changes)?,
+    })
+  }
+}
+
+impl Changes {
+  /// Возвращает выражение, которое записывает изменения и удаляет из истории задачи изменения сверх `MAX_TASK_HISTORY`.
+  pub fn queries(&self) -> Vec<(&'static str, Vec<&(dyn ToSql + Sync)>)> {
+    if self.empty { return vec![]; };
+    vec![(
+      "with changes as (select c, n from json_array_elements($6::varchar::json) with ordinality as t (c, n)), \
+         trimmed as (delete from task_history where board_id = $1 and card_id = $2 and task_id = $3 and id not in ( \
+           select id from task_history where board_id = $1 and card_id = $2 and task_id = $3 \
+           order by id desc limit greatest($7 - (select count(*) from changes), 0))) \
+       insert into task_history (board_id, card_id, task_id, field, old_value, new_value, actor, at) \
+         select $1, $2, $3, c->>'field', (c->'old')::text, (c->'new')::text, $4, $5 from changes order by n;",
+      vec![&self.board_id, &self.card_id, &self.task_id, &self.actor, &self.at, &self.changes, &MAX_TASK_HISTORY]
+    )]
+  }
+}
+
+/// Возвращает конфликты изменения задачи, сделанного клиентом над ревизией доски `base_revision`.
+///
+/// Вызывается после изменения задачи в памяти и до `Snapshot::changes`. Конфликтом считается поле из `fields`, которое после `base_revision` изменил кто-то другой, а изменение клиента меняет его значение.
+pub fn conflicts(ctx: &BoardContext, before: &Snapshot, fields: &[&str], base_revision: i64) -> MResult<Vec<FieldConflict>> {
+  let after = tracked_fields(ctx, &before.card_id, &before.task_id)?;
+  let task = ctx.board.cards.get_task(&before.card_id, &before.task_id)?;
+  Ok(fields.iter()
+    .filter_map(|field| {
+      let stamp = task.field_stamps.get(*field).filter(|stamp| stamp.revision > base_revision)?;
+      let (old, new) = (before.fields.get(*field)?, after.get(*field)?);
+      (old != new).then(|| FieldConflict {
+        field: field.to_string(),
+        server_value: old.clone(),
+        client_value: new.clone(),
+        stamp: stamp.clone(),
+      })
+    })
+    .collect())
+}
+
+/// Возвращает историю изменений задачи, начиная с последних.
+pub async fn list(db: &Db, board_id: &i64, card_id: &i64, task_id: &i64) -> MResult<Vec<TaskChange>> {
+  let rows = db.read_all(
+    "select field, old_value, new_value, actor, at from task_history \
+       where board_id = $1 and card_id = $2 and task_id = $3 order by id desc;",
+    &[board_id, card_id, task_id]
+  ).await?;
+  rows.iter().map(|row| Ok(TaskChange {
+    field: row.get(0),
+    old: serde_json::from_str(row.get(1))?,
+    new: serde_json::from_str(row.get(2))?,
+    actor: row.get(3),
+    at: row.get(4),
+  })).collect()
+}
+
+/// Возвращает поля задачи, изменения которых попадают в историю.
+fn tracked_fields(ctx: &BoardContext, card_id: &i64, task_id: &i64) -> MResult<Map<String, JsonValue>> {
+  let mut fields = match serde_json::to_value(ctx.board.cards.get_task(card_id, task_id)?)? {
+    JsonValue::Object(fields) => fields,
+    _ => Map::new(),
+  };
+  fields.retain(|field, _| !UNTRACKED_FIELDS.contains(&field.as_str()));
+  Ok(fields)
+}
@@ -0,0 +1,538 @@
+//! Проверка логики приложения на хранилище в памяти (см. `storage::mock`).
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::{self, AuthorCannotLeave, NotMember, NotOwner, SignInLocked};
+use crate::core::automation::WrongRule;
+use crate::core::board_cache::{self, BoardCache};
+use crate::core::validation::WrongLink;
+use crate::model::{Board, BoardPatch, Card, BoardPrefsPatch, BoardSort, CardPatch, NewBoard, NewCard, NewTask, TaskSort};
+use crate::sec::auth::TokenAuth;
+use crate::sec::tokens_vld;
+use crate::setup::{AppConfig, BoardCacheConfig, Quota};
+use crate::storage::{CcKeyRow, Storage};
+use crate::storage::mock::{InjectedFailure, MockData, MockDb};
+
+fn from_json<T: DeserializeOwned>(value: JsonValue) -> T {
+  serde_json::from_value(value).unwrap()
+}
+
+fn config() -> AppConfig {
+  from_json(json!({ "pg": "", "admin_key": "", "hyper_addr": "127.0.0.1:0", "quotas": { "free": {}, "paid": {} } }))
+}
+
+fn board(title: &str) -> NewBoard {
+  from_json(json!({
+    "background": { "color": "#eeeeee" },
+    "header": { "title": title, "header_background_color": "#ffffff", "header_text_color": "#000000" }
+  }))
+}
+
+fn card(title: &str) -> NewCard {
+  from_json(json!({
+    "title": title, "tasks": [],
+    "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+  }))
+}
+
+async fn sign_up(db: &MockDb, login: &str) -> i64 {
+  core::create_user(db, &config(), &from_json(json!({ "login": login, "pass": "Kettle-Orbit-42" }))).await.unwrap()
+}
+
+async fn titles(db: &MockDb, user_id: &i64, sort: BoardSort, cursor: Option<&str>, limit: Option<i64>) -> (Vec<String>, Option<String>) {
+  let (boards, cursor) = core::list_boards(db, user_id, false, sort, cursor, limit).await.unwrap();
+  (boards.into_iter().map(|b| b.title).collect(), cursor)
+}
+
+#[tokio::test]
+async fn boards_are_listed_by_pages() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  for title in ["Вторая", "Первая", "Третья"] {
+    core::create_board(&db, &Quota::default(), &user_id, board(title)).await.unwrap();
+  };
+  let patch: BoardPrefsPatch = from_json(json!({ "position": 1 }));
+  core::set_board_prefs(&db, &user_id, &3, &patch).await.unwrap();
+  assert_eq!(titles(&db, &user_id, BoardSort::Custom, None, None).await.0, vec!["Третья", "Вторая", "Первая"]);
+
+  let (page, cursor) = titles(&db, &user_id, BoardSort::Title, None, Some(2)).await;
+  assert_eq!(page, vec!["Вторая", "Первая"]);
+  let (page, cursor) = titles(&db, &user_id, BoardSort::Title, cursor.as_deref(), Some(2)).await;
+  assert_eq!((page, cursor), (vec!["Третья".to_string()], None));
+  let (_, cursor) = titles(&db, &user_id, BoardSort::Title, None, Some(1)).await;
+  assert!(core::list_boards(&db, &user_id, false, BoardSort::Added, cursor.as_deref(), Some(1)).await.is_err());
+}
+
+#[tokio::test]
+async fn board_quota_is_checked() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let quota = Quota { max_boards: Some(1), ..Quota::default() };
+  core::create_board(&db, &quota, &user_id, board("Первая")).await.unwrap();
+  assert!(core::create_board(&db, &quota, &user_id, board("Вторая")).await.is_err());
+  assert_eq!(db.data().boards.len(), 1);
+}
+
+#[tokio::test]
+async fn failed_write_keeps_board() {
+  let db = MockDb::default();
+  let cfg = config();
+  let user_id = sign_up(&db, "olga").await;
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, board("Доска")).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  core::insert_card(&db, &cfg, &mut ctx, card("Первая")).await.unwrap();
+  assert_eq!(db.data().boards[0].revision, 1);
+
+  db.fail("update_board");
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let err = core::insert_card(&db, &cfg, &mut ctx, card("Вторая")).await.unwrap_err();
+  assert!(err.is::<InjectedFailure>());
+  db.recover("update_board");
+  let ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  assert_eq!((ctx.board.revision, ctx.board.cards.len()), (1, 1));
+}
+
+#[tokio::test]
+async fn removed_board_leaves_members() {
+  let db = MockDb::default();
+  let author = sign_up(&db, "olga").await;
+  let member = sign_up(&db, "boris").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  {
+    let mut data = db.data();
+    data.boards[0].shared_with = json!([author, member]).to_string();
+    data.users[1].shared_boards = json!([board_id]).to_string();
+  };
+  let ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  assert!(core::remove_board(&db, ctx).await.is_err());
+  let ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  core::remove_board(&db, ctx).await.unwrap();
+  assert!(db.board(&board_id).await.unwrap().is_none());
+  assert_eq!(db.user(&member).await.unwrap().shared_boards, "[]");
+  assert_eq!(db.user(&author).await.unwrap().shared_boards, "[]");
+}
+
+#[tokio::test]
+async fn sign_in_is_locked_after_failures() {
+  let db = MockDb::default();
+  let cfg = config();
+  let user_id = sign_up(&db, "olga").await;
+  let creds = |pass: &str| from_json(json!({ "login": "olga", "pass": pass }));
+  assert_eq!(core::sign_in_creds_to_id(&db, &cfg, &creds("Kettle-Orbit-42")).await.unwrap(), user_id);
+  for _ in 1..cfg.sign_in_max_failures {
+    let err = core::sign_in_creds_to_id(&db, &cfg, &creds("wrong")).await.unwrap_err();
+    assert!(!err.is::<SignInLocked>());
+  };
+  let err = core::sign_in_creds_to_id(&db, &cfg, &creds("wrong")).await.unwrap_err();
+  assert!(err.is::<SignInLocked>());
+  let err = core::sign_in_creds_to_id(&db, &cfg, &creds("Kettle-Orbit-42")).await.unwrap_err();
+  assert!(err.is::<SignInLocked>());
+}
+
+#[tokio::test]
+async fn legacy_password_is_rehashed_on_sign_in() {
+  use passwords::hasher::{bcrypt, gen_salt};
+  let db = MockDb::default();
+  let mut cfg = config();
+  let user_id = sign_up(&db, "olga").await;
+  let creds = |pass: &str| from_json(json!({ "login": "olga", "pass": pass }));
+  let stored = |db: &MockDb| db.data().users[0].user_creds.parse::<JsonValue>().unwrap();
+  assert_eq!(stored(&db)["pass_hash"], "argon2id");
+  // Так сведения авторизации записывали предыдущие версии сервера.
+  let salt = gen_salt();
+  db.data().users[0].user_creds = json!({
+    "salt": salt.to_vec(), "salted_pass": bcrypt(10, &salt, "Kettle-Orbit-42").unwrap().to_vec(), "tokens": []
+  }).to_string();
+  assert!(core::sign_in_creds_to_id(&db, &cfg, &creds("wrong")).await.is_err());
+  assert_eq!(stored(&db).get("pass_hash"), None);
+  assert_eq!(core::sign_in_creds_to_id(&db, &cfg, &creds("Kettle-Orbit-42")).await.unwrap(), user_id);
+  let rehashed = stored(&db);
+  assert_eq!(rehashed["pass_hash"], "argon2id");
+  assert_eq!(core::sign_in_creds_to_id(&db, &cfg, &creds("Kettle-Orbit-42")).await.unwrap(), user_id);
+  assert_eq!(stored(&db), rehashed);
+  // Хэш с устаревшими параметрами тоже заменяется.
+  cfg.password_hashing.iterations = 3;
+  assert_eq!(core::sign_in_creds_to_id(&db, &cfg, &creds("Kettle-Orbit-42")).await.unwrap(), user_id);
+  let salted_pass: Vec<u8> = from_json(stored(&db)["salted_pass"].clone());
+  assert!(String::from_utf8(salted_pass).unwrap().starts_with("$argon2id$v=19$m=19456,t=3,p=1$"));
+}
+
+#[tokio::test]
+async fn only_token_hashes_are_stored() {
+  let db = MockDb::default();
+  let cfg = config();
+  let user_id = sign_up(&db, "olga").await;
+  let token_auth = core::get_new_token(&db, &user_id, &cfg, &Default::default()).await.unwrap();
+  let stored = db.data().users[0].user_creds.clone();
+  assert!(!stored.contains(&token_auth.token) && !stored.contains(token_auth.refresh_token.as_ref().unwrap()));
+  assert!(tokens_vld::verify_user(&db, &token_auth, &cfg).await.0);
+  let forged = TokenAuth { token: format!("{}x", token_auth.token), ..token_auth.clone() };
+  assert!(!tokens_vld::verify_user(&db, &forged, &cfg).await.0);
+}
+
+#[tokio::test]
+async fn expired_access_tokens_are_pruned() {
+  let db = MockDb::default();
+  let cfg = config();
+  let user_id = sign_up(&db, "olga").await;
+  let stale = core::get_new_token(&db, &user_id, &cfg, &Default::default()).await.unwrap();
+  let fresh = core::get_new_token(&db, &user_id, &cfg, &Default::default()).await.unwrap();
+  let mut creds: JsonValue = serde_json::from_str(&db.data().users[0].user_creds).unwrap();
+  creds["tokens"][0]["expires_dt"] = json!(1);
+  db.data().users[0].user_creds = creds.to_string();
+  assert!(!tokens_vld::verify_user(&db, &stale, &cfg).await.0);
+  // Проверка удаляет только истёкший токен доступа, не затрагивая токены обновления.
+  let creds: JsonValue = serde_json::from_str(&db.data().users[0].user_creds).unwrap();
+  assert_eq!((creds["tokens"].as_array().unwrap().len(), creds["refresh_tokens"].as_array().unwrap().len()), (1, 2));
+  assert!(tokens_vld::verify_user(&db, &fresh, &cfg).await.0);
+}
+
+#[tokio::test]
+async fn sign_up_takes_cc_key() {
+  let key = |key: &str, expires_at: Option<i64>| CcKeyRow { key: key.to_string(), note: None, created_at: 0, expires_at };
+  let db = MockDb::new(MockData { cc_keys: vec![key("expired", Some(1)), key("fresh", None)], ..MockData::default() });
+  let cfg = AppConfig { cc_key_required: true, ..config() };
+  let sign_up = |cc_key: &str| from_json(json!({ "login": "olga", "pass": "Kettle-Orbit-42", "cc_key": cc_key }));
+  assert!(core::create_user(&db, &cfg, &sign_up("expired")).await.is_err());
+  assert!(core::create_user(&db, &cfg, &sign_up("fresh")).await.is_ok());
+  assert!(core::create_user(&db, &cfg, &sign_up("fresh")).await.is_err());
+  assert_eq!(db.data().cc_keys.iter().map(|k| k.key.as_str()).collect::<Vec<_>>(), vec!["expired"]);
+}
+
+#[tokio::test]
+async fn board_is_created_from_returned_board() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, board("Исходная")).await.unwrap();
+  let ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let body = core::get_board(&db, ctx, None, TaskSort::default(), false, false).await.unwrap();
+  // Клиент может отправить полученную доску целиком: служебные поля, в том числе `archived_at`, не учитываются.
+  let returned: JsonValue = serde_json::from_slice(&body).unwrap();
+  assert!(returned.get("archived_at").is_some());
+  let copy_id = core::create_board(&db, &Quota::default(), &user_id, from_json(returned)).await.unwrap();
+  assert_ne!(copy_id, board_id);
+}
+
+#[tokio::test]
+async fn board_json_escapes_text() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let title = "Кавычки \" и \\ косая </script> \u{2028} 😀";
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, board(title)).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  core::insert_card(&db, &config(), &mut ctx, card(title)).await.unwrap();
+  // Строки, попавшие в хранилище в обход проверок, тоже экранируются.
+  let display_name = "Имя \"в кавычках\"\n\t\u{1}";
+  db.data().profiles.get_mut(&user_id).unwrap().display_name = display_name.to_string();
+  for with_profiles in [false, true] {
+    let ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+    let body = core::get_board(&db, ctx, None, TaskSort::default(), with_profiles, false).await.unwrap();
+    let board: JsonValue = serde_json::from_slice(&body).unwrap();
+    assert_eq!((&board["header"]["title"], &board["cards"][0]["title"]), (&json!(title), &json!(title)));
+    assert_eq!(board["profiles"][0]["display_name"], if with_profiles { json!(display_name) } else { JsonValue::Null });
+  };
+}
+
+#[tokio::test]
+async fn board_json_reads_back_as_board() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let url = "https://example.com/фон.png?title=\"Доска\"&path=C:\\img";
+  let mut new_board = board("Доска \"с кавычками\"");
+  new_board.background = from_json(json!({ "url": url }));
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, new_board).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  core::insert_card(&db, &config(), &mut ctx, card("Карточка")).await.unwrap();
+  let body = core::get_board(&db, ctx, None, TaskSort::default(), false, false).await.unwrap();
+  let json: JsonValue = serde_json::from_slice(&body).unwrap();
+  assert_eq!(json["background"], json!({ "url": url }));
+  let board: Board = serde_json::from_slice(&body).unwrap();
+  assert_eq!(serde_json::to_value(&board).unwrap(), json);
+}
+
+#[tokio::test]
+async fn server_fields_of_new_entities_are_ignored() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let board: NewBoard = from_json(json!({
+    "id": 7, "author": 99, "shared_with": [99], "cards": [{ "id": 1 }], "revision": 5, "background": { "color": "#eeeeee" },
+    "header": { "title": "Доска", "header_background_color": "#ffffff", "header_text_color": "#000000" }
+  }));
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, board).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  assert_eq!((ctx.board.author, &ctx.board.shared_with, ctx.board.revision), (user_id, &vec![user_id], 0));
+
+  let task = json!({
+    "id": 5, "author": 99, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 },
+    "blocked": true, "github_issue": { "number": 1 }, "field_stamps": { "title": 1 }
+  });
+  let mut card = card("Карточка");
+  card.tasks.push(from_json(task.clone()));
+  let card_id = core::insert_card(&db, &config(), &mut ctx, card).await.unwrap();
+  let task_id = core::insert_task(&db, &mut ctx, &card_id, from_json(task.clone())).await.unwrap();
+  let ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  for task in &ctx.board.cards[0].tasks {
+    assert_eq!((task.author, task.blocked, task.github_issue.is_none(), task.field_stamps.len()), (user_id, false, true, 0));
+  };
+  assert_eq!(ctx.board.cards[0].tasks[1].id, task_id);
+
+  let mut unknown = task;
+  unknown["owner"] = json!(99);
+  assert!(serde_json::from_value::<NewTask>(unknown).is_err());
+}
+
+#[tokio::test]
+async fn card_cover_and_theme_are_validated() {
+  let db = MockDb::default();
+  let user_id = sign_up(&db, "olga").await;
+  let board_id = core::create_board(&db, &Quota::default(), &user_id, board("Доска")).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let mut themed = card("Карточка");
+  themed.cover = Some(from_json(json!({ "url": " https://example.com/cover.png " })));
+  themed.theme = Some(from_json(json!({ "text_color": "#222222", "accent_color": "#ff8800" })));
+  let card_id = core::insert_card(&db, &config(), &mut ctx, themed).await.unwrap();
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let stored = serde_json::to_value(&ctx.board.cards[0]).unwrap();
+  assert_eq!(stored["cover"], json!({ "url": "https://example.com/cover.png" }));
+  assert_eq!(stored["theme"], json!({ "text_color": "#222222", "accent_color": "#ff8800", "density": "comfortable" }));
+
+  let mut unsafe_cover = card("Карточка");
+  unsafe_cover.cover = Some(from_json(json!({ "url": "javascript:alert(1)" })));
+  assert!(core::insert_card(&db, &config(), &mut ctx, unsafe_cover).await.unwrap_err().is::<WrongLink>());
+  let patch: CardPatch = from_json(json!({ "theme": { "text_color": "222222", "accent_color": "#ff8800" } }));
+  assert!(core::apply_patch_on_card(&db, &mut ctx, &card_id, patch).await.is_err());
+
+  // `null` убирает обложку и возвращает оформление по умолчанию, и в JSON карточки их больше нет.
+  let mut ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let patch: CardPatch = from_json(json!({ "cover": null, "theme": null }));
+  core::apply_patch_on_card(&db, &mut ctx, &card_id, patch).await.unwrap();
+  let ctx = core::load_board(&db, &user_id, &board_id).await.unwrap();
+  let stored = serde_json::to_value(&ctx.board.cards[0]).unwrap();
+  assert!(stored.get("cover").is_none() && stored.get("theme").is_none());
+}
+
+#[tokio::test]
+async fn board_is_transferred_to_member() {
+  let db = MockDb::default();
+  let cfg = config();
+  let author = sign_up(&db, "olga").await;
+  let member = sign_up(&db, "boris").await;
+  let outsider = sign_up(&db, "roman").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  {
+    let mut data = db.data();
+    data.boards[0].shared_with = json!([author, member]).to_string();
+    data.users[1].shared_boards = json!([board_id]).to_string();
+  };
+  let mut ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  assert!(core::transfer_board(&db, &cfg, &mut ctx, &member).await.unwrap_err().is::<NotOwner>());
+  let mut ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  assert!(core::transfer_board(&db, &cfg, &mut ctx, &outsider).await.unwrap_err().is::<NotMember>());
+  core::transfer_board(&db, &cfg, &mut ctx, &member).await.unwrap();
+  assert_eq!(db.data().boards[0].author, member);
+  assert_eq!(db.count_boards(&author).await.unwrap(), 0);
+
+  // Прежний автор остаётся участником, но управлять доской теперь может только новый.
+  let ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  assert!(core::remove_board(&db, ctx).await.is_err());
+  let ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  core::remove_board(&db, ctx).await.unwrap();
+}
+
+#[tokio::test]
+async fn member_leaves_board() {
+  let db = MockDb::default();
+  let author = sign_up(&db, "olga").await;
+  let member = sign_up(&db, "boris").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  {
+    let mut data = db.data();
+    data.boards[0].shared_with = json!([author, member]).to_string();
+    data.users[1].shared_boards = json!([board_id]).to_string();
+  };
+  let mut ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  let mut card = card("Карточка");
+  card.tasks.push(from_json(json!({
+    "title": "Задача", "executors": [author, member], "exec": false, "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 },
+    "subtasks": [{
+      "title": "Подзадача", "executors": [member], "exec": false, "notes": "", "tags": [],
+      "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+    }]
+  })));
+  core::insert_card(&db, &config(), &mut ctx, card).await.unwrap();
+  assert!(core::leave_board(&db, &mut ctx).await.unwrap_err().is::<AuthorCannotLeave>());
+
+  let mut ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  core::leave_board(&db, &mut ctx).await.unwrap();
+  assert!(core::load_board(&db, &member, &board_id).await.is_err());
+  assert_eq!(db.user(&member).await.unwrap().shared_boards, "[]");
+  let ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  let task = &ctx.board.cards[0].tasks[0];
+  assert_eq!((&ctx.board.shared_with, &task.executors, &task.subtasks[0].executors), (&vec![author], &vec![author], &vec![]));
+}
+
+#[tokio::test]
+async fn purged_user_is_removed_from_all_executors() {
+  let db = MockDb::default();
+  let author = sign_up(&db, "olga").await;
+  let member = sign_up(&db, "boris").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  db.data().boards[0].shared_with = json!([author, member]).to_string();
+  let mut ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  let timelines = json!({ "preferred_time": 0, "max_time": 0, "expected_time": 0 });
+  let subtask = json!({ "title": "Подзадача", "executors": [member], "exec": false, "notes": "", "tags": [], "timelines": timelines });
+  for executors in [json!([member, author]), json!([author])] {
+    let mut card = card("Карточка");
+    card.tasks.push(from_json(json!({
+      "title": "Задача", "executors": executors, "exec": false, "notes": "", "tags": [], "timelines": timelines, "subtasks": [subtask]
+    })));
+    core::insert_card(&db, &config(), &mut ctx, card).await.unwrap();
+  };
+
+  assert!(core::purge_user_from_board(&mut ctx.board, &member));
+  assert_eq!(ctx.board.shared_with, vec![author]);
+  for card in &ctx.board.cards {
+    assert_eq!((&card.tasks[0].executors, &card.tasks[0].subtasks[0].executors), (&vec![author], &vec![]));
+  };
+  assert!(!core::purge_user_from_board(&mut ctx.board, &member));
+}
+
+#[tokio::test]
+async fn watchers_are_toggled_and_purged() {
+  let db = MockDb::default();
+  let author = sign_up(&db, "olga").await;
+  let member = sign_up(&db, "boris").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  db.data().boards[0].shared_with = json!([author, member]).to_string();
+  let mut ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  let mut card = card("Карточка");
+  card.tasks.push(from_json(json!({
+    "title": "Задача", "executors": [author], "exec": false, "notes": "", "tags": [], "subtasks": [], "watchers": [author],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+  })));
+  let card_id = core::insert_card(&db, &config(), &mut ctx, card).await.unwrap();
+  // Наблюдателей задачи, переданных клиентом, сервер не принимает.
+  assert!(ctx.board.cards[0].tasks[0].watchers.is_empty());
+
+  let mut ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  for _ in 0..2 {
+    core::watch_task(&db, &mut ctx, &card_id, &1, true).await.unwrap();
+    core::watch_board(&db, &mut ctx, true).await.unwrap();
+  };
+  assert_eq!(ctx.board.revision, 3);
+  let ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  assert_eq!((&ctx.board.watchers, &ctx.board.cards[0].tasks[0].watchers), (&vec![member], &vec![member]));
+  let mut ctx = core::load_board(&db, &member, &board_id).await.unwrap();
+  assert!(core::watch_task(&db, &mut ctx, &card_id, &2, true).await.is_err());
+  core::watch_task(&db, &mut ctx, &card_id, &1, false).await.unwrap();
+  assert!(ctx.board.cards[0].tasks[0].watchers.is_empty());
+
+  // Наблюдение не делает пользователя исполнителем, но исчезает вместе с доступом к доске.
+  core::watch_task(&db, &mut ctx, &card_id, &1, true).await.unwrap();
+  assert!(!core::purge_user_from_board(&mut ctx.board, &member));
+  assert!(ctx.board.watchers.is_empty() && ctx.board.cards[0].tasks[0].watchers.is_empty());
+}
+
+#[tokio::test]
+async fn rules_refer_to_board_entities() {
+  let db = MockDb::default();
+  let author = sign_up(&db, "olga").await;
+  let board_id = core::create_board(&db, &Quota::default(), &author, board("Доска")).await.unwrap();
+  let mut ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  let card_id = core::insert_card(&db, &config(), &mut ctx, card("Готово")).await.unwrap();
+  let tag_id = core::create_board_tag(&db, &mut ctx, &from_json(json!({
+    "id": 0, "title": "Срочно", "text_color": "#000000", "background_color": "#ffffff"
+  }))).await.unwrap();
+  let patch = |actions: JsonValue| -> BoardPatch { from_json(json!({ "settings": { "rules": [{
+    "title": " Закрытие ", "trigger": { "type": "tag_added", "tag_id": tag_id }, "actions": actions
+  }] } })) };
+  for actions in [
+    json!([]),
+    json!([{ "type": "move_to_card", "card_id": card_id + 1 }]),
+    json!([{ "type": "assign", "user_id": author + 1 }]),
+    json!([{ "type": "add_tag", "tag_id": tag_id + 1 }]),
+  ] {
+    let e = core::apply_patch_on_board(&db, &mut ctx, patch(actions)).await.unwrap_err();
+    assert!(e.downcast_ref::<WrongRule>().is_some(), "{}", e);
+  };
+  assert!(ctx.board.settings.rules.is_empty());
+
+  let actions = json!([{ "type": "move_to_card", "card_id": card_id }, { "type": "assign", "user_id": author }]);
+  core::apply_patch_on_board(&db, &mut ctx, patch(actions)).await.unwrap();
+  let ctx = core::load_board(&db, &author, &board_id).await.unwrap();
+  assert_eq!((ctx.board.settings.rules.len(), ctx.board.settings.rules[0].title.as_str()), (1, "Закрытие"));
+}
+
+#[test]
+fn every_table_is_backed_up_or_excluded() {
+  for query in core::SCHEMA {
+    let table = query.trim_start_matches("create table if not exists ").split_whitespace().next().unwrap();
+    let (backed_up, excluded) = (core::BACKUP_TABLES.contains(&table), core::NOT_BACKED_UP.contains(&table));
+    assert!(backed_up != excluded, "Таблица {} должна либо попадать в резервную копию, либо быть исключена из неё.", table);
+  };
+  assert_eq!(core::BACKUP_TABLES.len() + core::NOT_BACKED_UP.len(), core::SCHEMA.len());
+}
+
+#[test]
+fn board_cache_keeps_recent_revisions() {
+  let cards = |title: &str| -> Vec<Card> { from_json(json!([{
+    "id": 1, "author": 1, "title": title, "tasks": [],
+    "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+  }])) };
+  let mut cache = BoardCache::new(&BoardCacheConfig { capacity: 2, ttl_secs: 600 });
+  let (old, new) = (board_cache::fingerprint("[1]"), board_cache::fingerprint("[2]"));
+  cache.store(1, 5, old, &cards("Первая"));
+  assert_eq!(cache.get(1, 5, old).unwrap()[0].title, "Первая");
+  // Карточки, изменённые в обход сервера без смены ревизии, в кэше не находятся.
+  assert!(cache.get(1, 5, new).is_none());
+  assert!(cache.get(1, 6, old).is_none());
+
+  // Более старая ревизия не заменяет новую, а событие о новой ревизии удаляет старую.
+  cache.store(1, 4, new, &cards("Старая"));
+  assert_eq!(cache.get(1, 5, old).unwrap()[0].title, "Первая");
+  cache.invalidate(1, Some(5));
+  assert!(cache.get(1, 5, old).is_some());
+  cache.invalidate(1, Some(6));
+  assert!(cache.get(1, 5, old).is_none());
+
+  // Заполненный кэш освобождает место от доски, к которой дольше всего не обращались.
+  cache.store(1, 1, old, &cards("Первая"));
+  cache.store(2, 1, old, &cards("Вторая"));
+  cache.get(1, 1, old).unwrap();
+  cache.store(3, 1, old, &cards("Третья"));
+  assert!(cache.get(2, 1, old).is_none());
+  assert!(cache.get(1, 1, old).is_some() && cache.get(3, 1, old).is_some());
+}
+
+#[tokio::test]
+async fn board_events_are_coalesced() {
+  use crate::core::events::{BoardEvent, Coalescer, EventKind};
+  use std::time::Duration;
+  let event = |board_id: i64, user_id: i64, kind: EventKind| BoardEvent { board_id, user_id: Some(user_id), revision: 0, at: chrono::Utc::now(), kind };
+  let kinds = |batch: Vec<BoardEvent>| batch.into_iter().map(|e| (e.board_id, e.user_id.unwrap(), e.kind)).collect::<Vec<_>>();
+  let updated = EventKind::TaskUpdated { card_id: 1, task_id: 1 };
+  let (tx, rx) = tokio::sync::broadcast::channel(16);
+  let mut events = Coalescer::new(rx, Duration::from_millis(100));
+  // Повтор изменения отбрасывается, а изменение того же вида другим пользователем и события другой доски - нет.
+  for (board_id, user_id) in [(1, 1), (2, 1), (1, 1), (1, 2)] {
+    tx.send(event(board_id, user_id, updated.clone())).unwrap();
+  };
+  tx.send(event(1, 1, EventKind::BoardUpdated)).unwrap();
+  assert_eq!(kinds(events.next().await.unwrap()), vec![(1, 1, updated.clone()), (1, 2, updated.clone()), (1, 1, EventKind::BoardUpdated)]);
+  assert_eq!(kinds(events.next().await.unwrap()), vec![(2, 1, updated.clone())]);
+  // Собранные события отдаются и после закрытия канала.
+  tx.send(event(3, 1, updated.clone())).unwrap();
+  drop(tx);
+  assert_eq!(kinds(events.next().await.unwrap()), vec![(3, 1, updated.clone())]);
+  assert!(events.next().await.is_none());
+
+  let (tx, rx) = tokio::sync::broadcast::channel(16);
+  let mut events = Coalescer::new(rx, Duration::ZERO);
+  tx.send(event(1, 1, updated.clone())).unwrap();
+  tx.send(event(1, 1, updated.clone())).unwrap();
+  assert_eq!(events.next().await.unwrap().len(), 1);
+  assert_eq!(events.next().await.unwrap().len(), 1);
+}
@@ -0,0 +1,173 @@
+//! Отвечает за отмену удаления карточек, задач и подзадач.
+//!
+//! Удаляемая сущность записывается в таблицу `undo_log` вместе с её местом на доске и зависимостями других задач от неё - в одной транзакции с доской (см. `save_board`). В течение `UNDO_WINDOW_SECS` пользователь может отменить своё последнее удаление на доске: сущность возвращается на прежнее место, а запись удаляется, поэтому одно удаление нельзя отменить дважды.
+//!
+//! Пока сущность была удалена, доска могла измениться, поэтому при восстановлении из неё удаляются ссылки на исполнителей без доступа к доске, удалённые теги и дорожки и несуществующие задачи. История изменений удалённых задач не восстанавливается (см. `task_history`).
+
+use chrono::Utc;
+use custom_error::custom_error;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use tokio_postgres::types::ToSql;
+
+use crate::core::events::EventKind;
+use crate::core::{check_wip_limit, dependencies, quota, save_board};
+use crate::model::{BoardContext, Card, Cards, Subtask, Task, TaskPath};
+use crate::psql_handler::Db;
+use crate::setup::AppConfig;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NothingToUndo{} = "Нечего отменять."}
+custom_error!{pub CannotUndo{reason: &'static str} = "Удаление нельзя отменить: {reason}"}
+
+/// Число секунд после удаления, в течение которых его можно отменить.
+pub const UNDO_WINDOW_SECS: i64 = 30 * 60;
+
+/// Удалённая сущность и её место на доске.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Deleted {
+  Card { position: usize, card: Card },
+  Task { card_id: i64, position: usize, task: Box<Task> },
+  Subtask { card_id: i64, task_id: i64, position: usize, subtask: Subtask },
+}
+
+/// Запись об удалении.
+#[derive(Deserialize)]
+struct Record {
+  deleted: Deleted,
+  /// Зависимости других задач от удалённых задач (см. `dependencies::dependents`).
+  #[serde(default)]
+  dependents: Vec<(TaskPath, TaskPath)>,
+}
+
+/// Запись об удалении, подготовленная к записи вместе с доской.
+pub struct Entry {
+  board_id: i64,
+  user_id: i64,
+  at: i64,
+  record: String,
+}
+
+impl Entry {
+  /// Возвращает выражение, которое записывает удаление и удаляет записи, которые уже нельзя отменить.
+  pub fn queries(&self) -> Vec<(&'static str, Vec<&(dyn ToSql + Sync)>)> {
+    vec![(
+      "with expired as (delete from undo_log where at < $3::bigint - $5::bigint) \
+       insert into undo_log (board_id, user_id, at, record) values ($1, $2, $3, $4);",
+      vec![&self.board_id, &self.user_id, &self.at, &self.record, &UNDO_WINDOW_SECS]
+    )]
+  }
+}
+
+/// Записывает карточку перед удалением.
+pub fn card(ctx: &BoardContext, card_id: &i64) -> MResult<Entry> {
+  let position = ctx.board.cards.iter().position(|card| card.id == *card_id).ok_or(CannotUndo{ reason: "карточка не найдена." })?;
+  let card = &ctx.board.cards[position];
+  let dependents = dependencies::dependents(&ctx.board.cards, |path| path.card_id == *card_id);
+  entry(ctx, json!({ "deleted": { "kind": "card", "position": position, "card": card }, "dependents": dependents }))
+}
+
+/// Записывает задачу перед удалением.
+pub fn task(ctx: &BoardContext, card_id: &i64, task_id: &i64) -> MResult<Entry> {
+  let card = ctx.board.cards.get_card(card_id)?;
+  let position = card.tasks.iter().position(|task| task.id == *task_id).ok_or(CannotUndo{ reason: "задача не найдена." })?;
+  let task = &card.tasks[position];
+  let dependents = dependencies::dependents(
+    &ctx.board.cards, |path| path.card_id == *card_id && path.task_id == *task_id
+  );
+  entry(ctx, json!({
+    "deleted": { "kind": "task", "card_id": card_id, "position": position, "task": task },
+    "dependents": dependents
+  }))
+}
+
+/// Записывает подзадачу перед удалением.
+pub fn subtask(ctx: &BoardContext, card_id: &i64, task_id: &i64, subtask_id: &i64) -> MResult<Entry> {
+  let task = ctx.board.cards.get_task(card_id, task_id)?;
+  let position = task.subtasks.iter().position(|subtask| subtask.id == *subtask_id)
+    .ok_or(CannotUndo{ reason: "подзадача не найдена." })?;
+  entry(ctx, json!({
+    "deleted": {
+      "kind": "subtask", "card_id": card_id, "task_id": task_id, "position": position, "subtask": &task.subtasks[position]
+    }
+  }))
+}
+
+fn entry(ctx: &BoardContext, record: JsonValue) -> MResult<Entry> {
+  Ok(Entry { board_id: ctx.board.id, user_id: ctx.user_id, at: Utc::now().timestamp(), record: record.to_string() })
+}
+
+/// Отменяет последнее удаление, сделанное пользователем на доске за последние `UNDO_WINDOW_SECS` секунд. Возвращает событие восстановления.
+///
+/// Если отменять нечего, функция возвращает `NothingToUndo`; если восстановить сущность нельзя - например, карточку удалённой задачи тоже удалили, - `CannotUndo`. Восстановление подчиняется тем же ограничениям, что и создание: ограничению числа задач в карточке и ограничениям тарифного плана.
+pub async fn undo(db: &Db, cfg: &AppConfig, ctx: &mut BoardContext) -> MResult<EventKind> {
+  let since = Utc::now().timestamp() - UNDO_WINDOW_SECS;
+  let rows = db.read_all(
+    "select id, record from undo_log where board_id = $1 and user_id = $2 and at >= $3 order by id desc limit 1;",
+    &[&ctx.board.id, &ctx.user_id, &since]
+  ).await?;
+  let row = rows.first().ok_or(NothingToUndo{})?;
+  let id: i64 = row.get(0);
+  let record: Record = serde_json::from_str(row.get(1))?;
+  let event = match record.deleted {
+    Deleted::Card { position, mut card } => {
+      if ctx.board.cards.get_card(&card.id).is_ok() { return Err(Box::new(CannotUndo{ reason: "карточка уже есть на доске." })); };
+      let quota = quota::for_user(db, cfg, &ctx.board.author).await?;
+      quota::check("max_cards_per_board", quota.max_cards_per_board, ctx.board.cards.len() as u64 + 1)?;
+      card.tasks.iter_mut().for_each(|task| reconcile(ctx, task));
+      let card_id = card.id;
+      ctx.board.cards.insert(position.min(ctx.board.cards.len()), card);
+      EventKind::CardRestored { card_id }
+    },
+    Deleted::Task { card_id, position, mut task } => {
+      reconcile(ctx, &mut task);
+      let card = ctx.board.cards.get_mut_card(&card_id).map_err(|_| CannotUndo{ reason: "карточка задачи удалена." })?;
+      if card.get_task(&task.id).is_ok() { return Err(Box::new(CannotUndo{ reason: "задача уже есть на доске." })); };
+      check_wip_limit(card, card.tasks.len() + 1)?;
+      let task_id = task.id;
+      card.tasks.insert(position.min(card.tasks.len()), *task);
+      EventKind::TaskRestored { card_id, task_id }
+    },
+    Deleted::Subtask { card_id, task_id, position, mut subtask } => {
+      let shared_with: HashSet<i64> = ctx.board.shared_with.iter().copied().collect();
+      let tags: HashSet<i64> = ctx.board.tags.iter().map(|tag| tag.id).collect();
+      subtask.executors.retain(|id| shared_with.contains(id));
+      subtask.tags.retain(|id| tags.contains(id));
+      let board_default = ctx.board.settings.exec_propagation;
+      let task = ctx.board.cards.get_mut_task(&card_id, &task_id).map_err(|_| CannotUndo{ reason: "задача подзадачи удалена." })?;
+      if task.subtasks.iter().any(|s| s.id == subtask.id) {
+        return Err(Box::new(CannotUndo{ reason: "подзадача уже есть на доске." }));
+      };
+      let subtask_id = subtask.id;
+      task.subtasks.insert(position.min(task.subtasks.len()), subtask);
+      task.propagate_exec(board_default);
+      EventKind::SubtaskRestored { card_id, task_id, subtask_id }
+    },
+  };
+  // Ссылки восстановленных задач на задачи, удалённые за это время, отбрасываются.
+  let existing: HashSet<TaskPath> = ctx.board.cards.iter()
+    .flat_map(|card| card.tasks.iter().map(|task| TaskPath { card_id: card.id, task_id: task.id }))
+    .collect();
+  dependencies::forget(&mut ctx.board.cards, |path| !existing.contains(path));
+  dependencies::restore(&mut ctx.board.cards, &record.dependents);
+  save_board(db, ctx, event.clone(), vec![("delete from undo_log where id = $1;", vec![&id])]).await?;
+  Ok(event)
+}
+
+/// Удаляет из восстанавливаемой задачи ссылки на исполнителей, наблюдателей, теги и дорожки, которых уже нет на доске.
+fn reconcile(ctx: &BoardContext, task: &mut Task) {
+  let shared_with: HashSet<i64> = ctx.board.shared_with.iter().copied().collect();
+  let tags: HashSet<i64> = ctx.board.tags.iter().map(|tag| tag.id).collect();
+  task.executors.retain(|id| shared_with.contains(id));
+  task.watchers.retain(|id| shared_with.contains(id));
+  task.tags.retain(|id| tags.contains(id));
+  task.lane_id = task.lane_id.filter(|id| ctx.board.lanes.iter().any(|lane| lane.id == *id));
+  task.sprint_id = task.sprint_id.filter(|id| ctx.board.sprints.iter().any(|sprint| sprint.id == *id));
+  for subtask in &mut task.subtasks {
+    subtask.executors.retain(|id| shared_with.contains(id));
+    subtask.tags.retain(|id| tags.contains(id));
+  };
+}
@@ -0,0 +1,114 @@
+//! Отвечает за проверку заголовков сущностей, ссылок задач и оформления карточек и очистку заметок перед записью.
+//!
+//! Заголовки хранятся внутри JSON доски, поэтому без ограничения длины один запрос мог бы раздуть доску до любого размера. Длина считается в символах Unicode, а не в байтах, чтобы ограничение было одинаковым для любых алфавитов. Заметки очищаются от опасного HTML (см. `sec::markdown`). Адреса ссылок ограничены схемами `http` и `https`, поскольку клиенты открывают их по нажатию; адреса картинок обложек карточек проверяются так же, поскольку клиенты их загружают.
+
+use custom_error::custom_error;
+
+use crate::model::{Card, CardCover, CardTheme, Link, Subtask, Task};
+use crate::sec::color_vld::validate_color;
+use crate::sec::markdown;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WrongTitle{entity: &'static str, max: usize} = "Заголовок {entity} должен содержать от 1 до {max} символов."}
+custom_error!{pub WrongLink{reason: String} = "Ссылка не принята: {reason}."}
+
+/// Наибольшая длина заголовка в символах.
+pub const MAX_TITLE_LEN: usize = 256;
+
+/// Наибольшая длина адреса ссылки в символах.
+pub const MAX_URL_LEN: usize = 2048;
+
+/// Наибольшее число ссылок у одной задачи.
+pub const MAX_TASK_LINKS: usize = 50;
+
+/// Приводит заголовок к виду, в котором он хранится, и проверяет его длину.
+///
+/// Управляющие символы (переводы строк, табуляции и т. п.) удаляются, пробелы в начале и в конце обрезаются. `entity` - название сущности в родительном падеже для текста ошибки.
+pub fn title(entity: &'static str, title: &str) -> Result<String, WrongTitle> {
+  let title: String = title.chars().filter(|c| !c.is_control()).collect();
+  let title = title.trim();
+  match title.is_empty() || title.chars().count() > MAX_TITLE_LEN {
+    true => Err(WrongTitle{ entity, max: MAX_TITLE_LEN }),
+    false => Ok(title.to_string()),
+  }
+}
+
+/// Проверяет заголовки и оформление карточки и заголовки всех её задач и подзадач и очищает их заметки.
+pub fn card(card: &mut Card) -> MResult<()> {
+  card.title = title("карточки", &card.title)?;
+  if let Some(c) = &mut card.cover { cover(c)?; };
+  if let Some(t) = &card.theme { theme(t)?; };
+  card.tasks.iter_mut().try_for_each(task)
+}
+
+/// Проверяет обложку карточки. Адрес картинки проверяется так же, как адрес ссылки (см. `url`).
+pub fn cover(cover: &mut CardCover) -> MResult<()> {
+  match cover {
+    CardCover::Color { color } => validate_color(color)?,
+    CardCover::Url { url: address } => *address = url(address)?,
+  };
+  Ok(())
+}
+
+/// Проверяет цвета оформления карточки.
+pub fn theme(theme: &CardTheme) -> MResult<()> {
+  validate_color(&theme.text_color)?;
+  validate_color(&theme.accent_color)?;
+  Ok(())
+}
+
+/// Проверяет заголовки задачи и всех её подзадач и ссылки задачи и очищает заметки.
+///
+/// Идентификаторы ссылок переназначаются по порядку.
+pub fn task(task: &mut Task) -> MResult<()> {
+  task.title = title("задачи", &task.title)?;
+  task.notes = markdown::sanitize(&task.notes);
+  if task.links.len() > MAX_TASK_LINKS {
+    return Err(Box::new(WrongLink{ reason: format!("у задачи может быть не больше {} ссылок", MAX_TASK_LINKS) }));
+  };
+  for (i, l) in task.links.iter_mut().enumerate() {
+    link(l)?;
+    l.id = i as i64 + 1;
+  };
+  task.subtasks.iter_mut().try_for_each(subtask)?;
+  Ok(())
+}
+
+/// Проверяет заголовок подзадачи и очищает её заметки.
+pub fn subtask(subtask: &mut Subtask) -> Result<(), WrongTitle> {
+  subtask.title = title("подзадачи", &subtask.title)?;
+  subtask.notes = markdown::sanitize(&subtask.notes);
+  Ok(())
+}
+
+/// Проверяет заголовок и адрес ссылки.
+pub fn link(link: &mut Link) -> MResult<()> {
+  link.title = title("ссылки", &link.title)?;
+  link.url = url(&link.url)?;
+  Ok(())
+}
+
+/// Проверяет адрес ссылки и возвращает его без пробелов в начале и в конце.
+///
+/// Адрес должен быть абсолютным адресом `http` или `https` с именем сервера и без имени пользователя: адрес вида `https://good.example@evil.example` ведёт не туда, куда кажется.
+pub fn url(url: &str) -> Result<String, WrongLink> {
+  let wrong = |reason: &str| Err(WrongLink{ reason: reason.to_string() });
+  let url = url.trim();
+  if url.chars().count() > MAX_URL_LEN { return Err(WrongLink{ reason: format!("адрес длиннее {} символов", MAX_URL_LEN) }); };
+  if url.chars().any(|c| c.is_whitespace() || c.is_control()) { return wrong("адрес содержит пробелы или управляющие символы"); };
+  let rest = match url.split_once("://") {
+    Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") => rest,
+    _ => return wrong("адрес должен начинаться с http:// или https://"),
+  };
+  let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+  if authority.contains('@') { return wrong("адрес не должен содержать имя пользователя"); };
+  let host = match authority.strip_prefix('[') {
+    Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+    None => authority.split(':').next().unwrap_or_default(),
+  };
+  match host.is_empty() {
+    true => wrong("в адресе нет имени сервера"),
+    false => Ok(url.to_string()),
+  }
+}
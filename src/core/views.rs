@@ -0,0 +1,85 @@
+//! Отвечает за представления досок.
+//!
+//! Представление - сохранённые пользователем фильтр задач (`BoardFilter`) и порядок задач в карточках (`TaskSort`), которые можно применить при получении доски, передав идентификатор представления вместо фильтра. Представления хранятся в таблице `board_views`, видны только своему автору и удаляются вместе с доской.
+
+use custom_error::custom_error;
+
+use crate::core::validation;
+use crate::model::{BoardContext, BoardFilter, BoardView, TaskSort};
+use crate::psql_handler::Db;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub NoSuchView{} = "Представление не найдено."}
+custom_error!{pub TooManyViews{max: i64} = "У пользователя не может быть больше {max} представлений одной доски."}
+
+/// Наибольшее число представлений одной доски у пользователя.
+pub const MAX_BOARD_VIEWS: i64 = 50;
+
+/// Сохраняет представление доски и возвращает его идентификатор.
+///
+/// Если `view.id` не задан, сохраняется новое представление, а если у пользователя уже есть представление с таким названием - оно заменяется. Если `view.id` задан, заменяется представление с этим идентификатором; отсутствие такого представления - ошибка `NoSuchView`.
+pub async fn save(db: &Db, ctx: &BoardContext, mut view: BoardView) -> MResult<i64> {
+  view.name = validation::title("представления", &view.name)?;
+  let filter = serde_json::to_string(&view.filter)?;
+  let sort = serde_json::to_string(&view.sort)?;
+  if view.id != 0 {
+    let rows = db.read_all(
+      "update board_views set name = $4, filter = $5, sort = $6 where id = $1 and user_id = $2 and board_id = $3 returning id;",
+      &[&view.id, &ctx.user_id, &ctx.board.id, &view.name, &filter, &sort]
+    ).await?;
+    return match rows.first() {
+      Some(row) => Ok(row.get(0)),
+      None => Err(Box::new(NoSuchView{})),
+    };
+  };
+  let rows = db.read_all(
+    "insert into board_views (user_id, board_id, name, filter, sort) \
+       select $1, $2, $3::varchar, $4, $5 \
+       where exists (select 1 from board_views where user_id = $1 and board_id = $2 and name = $3) \
+         or (select count(*) from board_views where user_id = $1 and board_id = $2) < $6 \
+       on conflict (user_id, board_id, name) do update set filter = excluded.filter, sort = excluded.sort \
+       returning id;",
+    &[&ctx.user_id, &ctx.board.id, &view.name, &filter, &sort, &MAX_BOARD_VIEWS]
+  ).await?;
+  match rows.first() {
+    Some(row) => Ok(row.get(0)),
+    None => Err(Box::new(TooManyViews{ max: MAX_BOARD_VIEWS })),
+  }
+}
+
+/// Возвращает представления доски, сохранённые пользователем, в порядке создания.
+pub async fn list(db: &Db, ctx: &BoardContext) -> MResult<Vec<BoardView>> {
+  let rows = db.read_all(
+    "select id, name, filter, sort from board_views where user_id = $1 and board_id = $2 order by id;",
+    &[&ctx.user_id, &ctx.board.id]
+  ).await?;
+  rows.iter().map(|row| Ok(BoardView {
+    id: row.get(0),
+    name: row.get(1),
+    filter: serde_json::from_str(row.get(2))?,
+    sort: serde_json::from_str(row.get(3))?,
+  })).collect()
+}
+
+/// Возвращает фильтр и порядок задач представления.
+pub async fn get(db: &Db, ctx: &BoardContext, view_id: &i64) -> MResult<(Option<BoardFilter>, TaskSort)> {
+  let rows = db.read_all(
+    "select filter, sort from board_views where id = $1 and user_id = $2 and board_id = $3;",
+    &[view_id, &ctx.user_id, &ctx.board.id]
+  ).await?;
+  let row = rows.first().ok_or(NoSuchView{})?;
+  Ok((serde_json::from_str(row.get(0))?, serde_json::from_str(row.get(1))?))
+}
+
+/// Удаляет представление доски.
+pub async fn delete(db: &Db, ctx: &BoardContext, view_id: &i64) -> MResult<()> {
+  let rows = db.read_all(
+    "delete from board_views where id = $1 and user_id = $2 and board_id = $3 returning id;",
+    &[view_id, &ctx.user_id, &ctx.board.id]
+  ).await?;
+  match rows.is_empty() {
+    true => Err(Box::new(NoSuchView{})),
+    false => Ok(()),
+  }
+}
@@ -0,0 +1,355 @@
+//! Отвечает за извлечение параметров из тела запроса.
+//!
+//! Тело запроса десериализуется один раз, после чего из него извлекаются типизированные ссылки на сущности (`BoardRef`, `CardRef` и т.д.). Если параметр отсутствует или имеет неверный тип, обработчик сразу получает готовый ответ 400 с описанием ошибки, одинаковым для всех методов.
+//!
+//! Для методов, работающих с содержимым доски, `board_params` дополнительно загружает доску и проверяет доступ пользователя к ней. Методы администратора так же получают проверенный ключ администратора при помощи `admin_call` и `root_call`.
+
+// Ошибка извлечения - это готовый ответ сервера, который обработчик возвращает как есть.
+#![allow(clippy::result_large_err)]
+
+use hyper::{Body, Method};
+use hyper::http::{Request, Response};
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+
+use crate::core::{self, BoardArchived};
+use crate::core::admin_audit::AdminCall;
+use crate::core::admin_keys;
+use crate::hyper_router::resp;
+use crate::model::{extract, BoardContext, ExtractionError, Workspace, MAX_BODY_LEN};
+use crate::psql_handler::Db;
+use crate::sec::auth::{extract_creds, AdminCredentials, AdminScope, ClientInfo};
+use crate::sec::oidc;
+use crate::setup::{AppConfig, MailerConfig, PublicSignupConfig};
+use crate::storage::{PostgresRequired, Storage};
+
+/// Параметры, которые можно извлечь из тела запроса.
+pub trait FromBody: Sized {
+  /// Извлекает параметры или возвращает ответ 400.
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>>;
+}
+
+/// Параметры, относящиеся к определённой доске.
+pub trait OnBoard {
+  /// Возвращает идентификатор доски.
+  fn board_id(&self) -> i64;
+}
+
+/// Ссылка на доску.
+pub struct BoardRef {
+  pub board_id: i64,
+}
+
+/// Ссылка на карточку.
+pub struct CardRef {
+  pub board_id: i64,
+  pub card_id: i64,
+}
+
+/// Ссылка на задачу.
+pub struct TaskRef {
+  pub board_id: i64,
+  pub card_id: i64,
+  pub task_id: i64,
+}
+
+/// Ссылка на подзадачу.
+pub struct SubtaskRef {
+  pub board_id: i64,
+  pub card_id: i64,
+  pub task_id: i64,
+  pub subtask_id: i64,
+}
+
+/// Ссылка на задачу или, если передан subtask_id, на её подзадачу.
+pub struct TaskOrSubtaskRef {
+  pub board_id: i64,
+  pub card_id: i64,
+  pub task_id: i64,
+  pub subtask_id: Option<i64>,
+}
+
+/// Ссылка на тег в словаре доски.
+pub struct BoardTagRef {
+  pub board_id: i64,
+  pub tag_id: i64,
+}
+
+/// Ссылка на дорожку доски.
+pub struct BoardLaneRef {
+  pub board_id: i64,
+  pub lane_id: i64,
+}
+
+/// Ссылка на спринт доски.
+pub struct BoardSprintRef {
+  pub board_id: i64,
+  pub sprint_id: i64,
+}
+
+/// Ссылка на организацию.
+pub struct OrgRef {
+  pub org_id: i64,
+}
+
+impl FromBody for OrgRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(OrgRef { org_id: id(body, "org_id")? })
+  }
+}
+
+impl FromBody for BoardRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(BoardRef { board_id: id(body, "board_id")? })
+  }
+}
+
+impl FromBody for CardRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(CardRef { board_id: id(body, "board_id")?, card_id: id(body, "card_id")? })
+  }
+}
+
+impl FromBody for TaskRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(TaskRef {
+      board_id: id(body, "board_id")?,
+      card_id: id(body, "card_id")?,
+      task_id: id(body, "task_id")?,
+    })
+  }
+}
+
+impl FromBody for SubtaskRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(SubtaskRef {
+      board_id: id(body, "board_id")?,
+      card_id: id(body, "card_id")?,
+      task_id: id(body, "task_id")?,
+      subtask_id: id(body, "subtask_id")?,
+    })
+  }
+}
+
+impl FromBody for TaskOrSubtaskRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(TaskOrSubtaskRef {
+      board_id: id(body, "board_id")?,
+      card_id: id(body, "card_id")?,
+      task_id: id(body, "task_id")?,
+      subtask_id: opt_id(body, "subtask_id")?,
+    })
+  }
+}
+
+impl FromBody for BoardTagRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(BoardTagRef { board_id: id(body, "board_id")?, tag_id: id(body, "tag_id")? })
+  }
+}
+
+impl FromBody for BoardLaneRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(BoardLaneRef { board_id: id(body, "board_id")?, lane_id: id(body, "lane_id")? })
+  }
+}
+
+impl FromBody for BoardSprintRef {
+  fn from_body(body: &JsonValue) -> Result<Self, Response<Body>> {
+    Ok(BoardSprintRef { board_id: id(body, "board_id")?, sprint_id: id(body, "sprint_id")? })
+  }
+}
+
+macro_rules! on_board {
+  ($($t:ty),*) => {
+    $(impl OnBoard for $t {
+      fn board_id(&self) -> i64 { self.board_id }
+    })*
+  };
+}
+
+on_board!(BoardRef, CardRef, TaskRef, SubtaskRef, TaskOrSubtaskRef, BoardTagRef, BoardLaneRef, BoardSprintRef);
+
+/// Десериализует тело запроса и извлекает из него параметры.
+///
+/// Возвращает параметры вместе с телом запроса, чтобы обработчик мог взять из него остальные данные (патч, сущность и т.д.).
+pub async fn params<T: FromBody>(req: Request<Body>) -> Result<(T, JsonValue), Response<Body>> {
+  let body = match extract::<JsonValue>(req).await {
+    Ok(v) => v,
+    Err(e) => return Err(extraction_failed(e)),
+  };
+  let params = T::from_body(&body)?;
+  Ok((params, body))
+}
+
+/// Считывает тело запроса без декодирования, например для проверки подписи вебхука.
+///
+/// Тело считывается не больше чем на `MAX_BODY_LEN` байт; более длинное тело получает ответ 413.
+pub async fn raw_body(mut body: Body) -> Result<Vec<u8>, Response<Body>> {
+  let mut bytes: Vec<u8> = Vec::new();
+  while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+    let chunk = match chunk {
+      Ok(chunk) => chunk,
+      Err(_) => return Err(extraction_failed(ExtractionError::FromBody)),
+    };
+    if bytes.len() + chunk.len() > MAX_BODY_LEN { return Err(extraction_failed(ExtractionError::TooLarge)); };
+    bytes.extend_from_slice(&chunk);
+  };
+  Ok(bytes)
+}
+
+/// Формирует ответ на ошибку извлечения данных из тела запроса: 413, если тело слишком большое, иначе 400 с описанием ошибки.
+pub fn extraction_failed(e: ExtractionError) -> Response<Body> {
+  match e {
+    ExtractionError::TooLarge => resp::from_code_and_msg(413, Some(&e.to_string())),
+    e => resp::from_code_and_msg(400, Some(&e.to_string())),
+  }
+}
+
+/// Методы `POST`, которые изменяют доску.
+const CHANGING_POSTS: [&str; 2] = ["/board/undo", "/board/transfer"];
+
+/// Извлекает параметры и загружает доску, на которую они ссылаются.
+///
+/// Служит промежуточным обработчиком для всех методов, работающих с содержимым доски: доска считывается один раз, а пользователь, не имеющий к ней доступа, получает ответ 401. Методы `PUT`, `PATCH` и `DELETE` изменяют доску, поэтому для доски в архиве (см. `core::retention`) они получают ответ 423.
+///
+/// Перед загрузкой доски методы, изменяющие её, блокируют доску до её записи (см. `Storage::lock_board`), поэтому параллельные изменения одной доски выполняются по очереди.
+pub async fn board_params<T: FromBody + OnBoard>(req: Request<Body>, db: &dyn Storage, user_id: &i64)
+  -> Result<(T, JsonValue, BoardContext), Response<Body>>
+{
+  let changes = matches!(*req.method(), Method::PUT | Method::PATCH | Method::DELETE);
+  let locks = changes || (req.method() == Method::POST && CHANGING_POSTS.contains(&req.uri().path()));
+  let (params, body) = params::<T>(req).await?;
+  if locks && db.lock_board(&params.board_id()).await.is_err() {
+    return Err(resp::from_code_and_msg(500, Some("Не удалось заблокировать доску для изменения.")));
+  };
+  match core::load_board(db, user_id, &params.board_id()).await {
+    Ok(ctx) if changes && ctx.board.archived_at != 0 => Err(resp::from_code_and_msg(423, Some(&BoardArchived{}.to_string()))),
+    Ok(ctx) => Ok((params, body, ctx)),
+    _ => Err(resp::from_code_and_msg(401, Some("Данная доска вам недоступна."))),
+  }
+}
+
+/// Возвращает подключение к PostgreSQL для методов, данные которых хранятся только в нём, или ответ 501, если выбрано другое хранилище (см. `storage`).
+pub fn postgres(db: &dyn Storage) -> Result<&Db, Response<Body>> {
+  db.postgres().ok_or_else(|| resp::from_code_and_msg(501, Some(&PostgresRequired{}.to_string())))
+}
+
+/// Возвращает настройки открытой регистрации и почтового шлюза. Если регистрация с подтверждением не включена, возвращает ответ 404, а если регистрация возможна только по ключам - ответ 403.
+pub fn public_signup(cfg: &AppConfig) -> Result<(&PublicSignupConfig, &MailerConfig), Response<Body>> {
+  match (&cfg.public_signup, &cfg.mailer) {
+    _ if cfg.cc_key_required => Err(resp::from_code_and_msg(403, Some("Регистрация возможна только по ключам."))),
+    (Some(signup), Some(mailer)) => Ok((signup, mailer)),
+    _ => Err(resp::from_code_and_msg(404, Some("Регистрация с подтверждением адреса электронной почты не включена."))),
+  }
+}
+
+/// Наибольшая длина сохраняемых сведений о клиенте (см. `client_info`).
+const MAX_CLIENT_INFO_LEN: usize = 256;
+
+/// Возвращает сведения о клиенте, отправившем запрос, для сохранения вместе с выдаваемыми токенами.
+///
+/// Название устройства передаётся в заголовке `Device-Name` в процентной кодировке, чтобы в нём можно было использовать любые символы. Слишком длинные значения обрезаются.
+pub fn client_info(ws: &Workspace) -> ClientInfo {
+  let header = |name: &str| ws.req.headers().get(name).and_then(|v| v.to_str().ok());
+  let clip = |v: String| match v.is_empty() {
+    true => None,
+    false => Some(v.chars().take(MAX_CLIENT_INFO_LEN).collect()),
+  };
+  ClientInfo {
+    user_agent: header("User-Agent").map(str::to_string).and_then(clip),
+    device_name: header("Device-Name").and_then(oidc::decode).and_then(clip),
+    ip: Some(ws.client_ip.to_string()),
+    signed_in_at: None,
+  }
+}
+
+/// Возвращает значение параметра из строки запроса.
+pub fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+  req.uri().query()?.split('&').find_map(|p| p.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Проверяет, что запрос отправлен администратором, ключ которого действует в области `scope`.
+///
+/// Возвращает вызов для записи в журнал (см. `core::admin_audit`) или ответ с ошибкой, если это не так.
+pub async fn admin_call(ws: &Workspace, scope: AdminScope) -> Result<AdminCall, Response<Body>> {
+  let key = match extract_creds::<AdminCredentials>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v.key,
+    _ => return Err(resp::from_code_and_msg(401, Some("Не получен валидный токен."))),
+  };
+  match admin_keys::authorize(&*ws.db, &ws.cfg, &key, scope).await {
+    Ok(Some(key)) => Ok(AdminCall { route: format!("{} {}", ws.req.method(), ws.req.uri().path()), key, ip: ws.client_ip }),
+    _ => Err(resp::from_code_and_msg(401, None)),
+  }
+}
+
+/// Проверяет, что запрос отправлен с корневым ключом администратора.
+///
+/// Возвращает вызов для записи в журнал (см. `core::admin_audit`) или ответ с ошибкой, если это не так.
+pub fn root_call(ws: &Workspace) -> Result<AdminCall, Response<Body>> {
+  let key = match extract_creds::<AdminCredentials>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v.key,
+    _ => return Err(resp::from_code_and_msg(401, Some("Не получен валидный токен."))),
+  };
+  match admin_keys::is_root(&ws.cfg, &key) {
+    true => Ok(AdminCall { route: format!("{} {}", ws.req.method(), ws.req.uri().path()), key: None, ip: ws.client_ip }),
+    _ => Err(resp::from_code_and_msg(401, None)),
+  }
+}
+
+/// Извлекает необязательное число из строки запроса.
+pub fn opt_query_id(req: &Request<Body>, name: &str) -> Result<Option<i64>, Response<Body>> {
+  match query_param(req, name).map(|v| v.parse::<i64>()) {
+    None => Ok(None),
+    Some(Ok(v)) => Ok(Some(v)),
+    Some(Err(_)) => Err(resp::from_code_and_msg(400, Some(&format!("{} должен быть числом.", name)))),
+  }
+}
+
+/// Извлекает обязательный числовой идентификатор.
+pub fn id(body: &JsonValue, key: &str) -> Result<i64, Response<Body>> {
+  match opt_id(body, key)? {
+    Some(v) => Ok(v),
+    None => Err(resp::from_code_and_msg(400, Some(&format!("Не получен {}.", key)))),
+  }
+}
+
+/// Извлекает необязательный числовой идентификатор.
+pub fn opt_id(body: &JsonValue, key: &str) -> Result<Option<i64>, Response<Body>> {
+  match body.get(key) {
+    None => Ok(None),
+    Some(v) => match v.as_i64() {
+      Some(v) => Ok(Some(v)),
+      None => Err(resp::from_code_and_msg(400, Some(&format!("{} должен быть числом.", key)))),
+    },
+  }
+}
+
+/// Десериализует патч сущности из тела запроса.
+///
+/// Патч передаётся в теле запроса рядом со ссылкой на сущность (`board_id`, `card_id` и т.д.); эти поля в патч не попадают.
+pub fn patch<T: DeserializeOwned>(body: &JsonValue) -> Result<T, Response<Body>> {
+  match serde_json::from_value(body.clone()) {
+    Ok(v) => Ok(v),
+    Err(e) => Err(resp::from_code_and_msg(400, Some(&format!("Не удалось десериализовать патч: {}", e)))),
+  }
+}
+
+/// Десериализует обязательную вложенную структуру.
+pub fn entity<T: DeserializeOwned>(body: &JsonValue, key: &str) -> Result<T, Response<Body>> {
+  match opt_entity(body, key)? {
+    Some(v) => Ok(v),
+    None => Err(resp::from_code_and_msg(400, Some(&format!("Не получен {}.", key)))),
+  }
+}
+
+/// Десериализует необязательную вложенную структуру.
+pub fn opt_entity<T: DeserializeOwned>(body: &JsonValue, key: &str) -> Result<Option<T>, Response<Body>> {
+  match body.get(key) {
+    None => Ok(None),
+    Some(v) => match serde_json::from_value(v.clone()) {
+      Ok(v) => Ok(Some(v)),
+      Err(e) => Err(resp::from_code_and_msg(400, Some(&format!("Не удалось десериализовать {}: {}", key, e)))),
+    },
+  }
+}
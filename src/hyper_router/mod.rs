@@ -1,57 +1,270 @@
 //! Отвечает за управление аутентификацией и вызов необходимых методов работы с базами данных.
 
-use hyper::{Body, Method, http::{Request, Response}};
-use std::{convert::Infallible, net::SocketAddr};
+use hyper::{Body, Method, http::{HeaderValue, Request, Response}};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use uuid::Uuid;
 
+mod extractors;
 mod resp;
 mod routes;
+mod schema;
 
-use crate::model::Workspace;
-use crate::psql_handler::Db;
+use crate::model::{is_msgpack, Workspace};
+use crate::psql_handler::{metrics, transaction};
+use crate::sec::proxy;
+use crate::setup::{AppConfig, LiveConfig};
+use crate::storage::Storage;
 
-/// Обрабатывает сигнал завершения работы сервера.
+/// Обрабатывает сигнал завершения работы сервера: Ctrl+C или, в Unix, `SIGTERM`, которым сервер останавливают systemd и Docker.
 pub async fn shutdown() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).expect("Не удалось подписаться на сигнал SIGTERM.");
+    tokio::select! {
+      res = tokio::signal::ctrl_c() => res.expect("Не удалось установить комбинацию Ctrl+C как завершающую работу."),
+      _ = terminate.recv() => {},
+    };
+  }
+  #[cfg(not(unix))]
   tokio::signal::ctrl_c().await.expect("Не удалось установить комбинацию Ctrl+C как завершающую работу.");
 }
 
+/// Наибольшая длина идентификатора запроса, принимаемого от клиента.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Возвращает идентификатор запроса.
+///
+/// Если клиент или стоящий перед сервером прокси передал заголовок `X-Request-Id`, используется его значение; иначе идентификатор генерируется. Значения, которые небезопасно выводить в журнал, заменяются сгенерированными.
+fn request_id(req: &Request<Body>) -> String {
+  match req.headers().get("X-Request-Id").and_then(|id| id.to_str().ok()) {
+    Some(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN
+      && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) => id.to_string(),
+    _ => Uuid::new_v4().to_string(),
+  }
+}
+
+/// Возвращает адрес клиента, которому разрешено читать ответ в браузере.
+///
+/// Если адрес, с которого отправлен запрос, есть в списке разрешённых, возвращается он; иначе - первый адрес из списка.
+fn allowed_origin<'a>(req: &'a Request<Body>, cfg: &'a AppConfig) -> Option<&'a str> {
+  let origin = req.headers().get("Origin").and_then(|origin| origin.to_str().ok());
+  match origin {
+    Some(origin) if cfg.cors_origins.iter().any(|allowed| allowed == origin) => Some(origin),
+    _ => cfg.cors_origins.first().map(String::as_str),
+  }
+}
+
 /// Обрабатывает запросы клиентов.
-pub async fn router(req: Request<Body>, db: Db, admin_key: String, _addr: SocketAddr)
+///
+/// Каждому запросу назначается идентификатор, который возвращается в заголовке `X-Request-Id`. Ответы с ошибкой дополнительно записываются в журнал сервера вместе с этим идентификатором, а сам идентификатор передаётся в теле ответа, чтобы по сообщению пользователя можно было найти запрос в журнале.
+///
+/// Клиенты, передавшие `application/msgpack` в заголовке `Accept`, получают ответы в JSON, включая ответы с ошибкой, в кодировке MessagePack (см. `resp::to_msgpack`).
+///
+/// Запросы к Postgres, выполненные при обработке, замеряются и относятся к методу и пути запроса (см. `psql_handler::metrics`).
+///
+/// Если обработчик начал транзакцию, заблокировав доску (см. `Storage::lock_board`), и не зафиксировал её сам, транзакция фиксируется после успешного ответа и откатывается после ответа с ошибкой (см. `psql_handler::transaction`).
+///
+/// Запрос обрабатывается со снимком конфигурации, действующей на момент его получения. Адрес клиента определяется с учётом доверенных прокси (см. `sec::proxy`).
+///
+/// Тела запросов к методам, для которых есть схема, проверяются по ней до вызова обработчика, а в отладочной сборке по схемам проверяются и ответы (см. `schema`).
+///
+/// Все ответы, в том числе ответы с ошибкой, получают заголовки CORS и заголовки безопасности из конфигурации (см. `resp::with_common_headers`).
+///
+/// Если обработчик не укладывается в `request_timeout_secs` (для методов администратора - в `admin_request_timeout_secs`), он прерывается, и клиент получает ответ 504. Вместе с обработчиком прерываются и его запросы к Postgres, а их соединения возвращаются в пул; запрос, уже отправленный в Postgres, завершается там не позднее `db_statement_timeout_secs`. Вычисления без ожидания - например, разбор JSON доски - прервать нельзя: обработчик прерывается при следующем ожидании.
+pub async fn router(req: Request<Body>, db: Arc<dyn Storage>, live_cfg: LiveConfig, addr: SocketAddr)
   -> Result<Response<Body>, Infallible>
 {
-  let ws = Workspace { req, db };
-  Ok(match (ws.req.method(), ws.req.uri().path()) {
+  let cfg = live_cfg.get();
+  let request_id = request_id(&req);
+  let client_ip = proxy::client_ip(&req, addr.ip(), &cfg.trusted_proxies);
+  let origin = allowed_origin(&req, &cfg).and_then(|origin| HeaderValue::from_str(origin).ok());
+  let security_headers = cfg.security_headers.clone();
+  let (method, path) = (req.method().clone(), req.uri().path().to_string());
+  let msgpack = is_msgpack(req.headers().get("Accept"));
+  let timeout = match is_admin(&path) {
+    true => cfg.admin_request_timeout_secs,
+    false => cfg.request_timeout_secs,
+  };
+  let route = format!("{} {}", method, path);
+  let handling = metrics::scope(request_id.clone(), route, transaction::scope(async {
+    // Обработчик большой, поэтому его состояние хранится в куче, а не на стеке потока.
+    let res = match schema::check_request(req).await {
+      Ok(req) => Box::pin(handle(Workspace { req, db: db.clone(), cfg, client_ip }, &live_cfg)).await,
+      Err(res) => res,
+    };
+    let res = match cfg!(debug_assertions) {
+      true => schema::check_response(&method, &path, res).await,
+      false => res,
+    };
+    match res.status().as_u16() < 400 {
+      true => match db.commit().await {
+        Ok(_) => res,
+        Err(_) => resp::from_code_and_msg(500, Some("Не удалось записать изменения.")),
+      },
+      false => res,
+    }
+  }));
+  let mut res = match timeout {
+    0 => handling.await,
+    secs => tokio::time::timeout(Duration::from_secs(secs), handling).await
+      .unwrap_or_else(|_| resp::from_code_and_msg(504, Some("Сервер не успел обработать запрос."))),
+  };
+  if res.status().as_u16() >= 400 {
+    let (parts, body) = res.into_parts();
+    let msg = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let msg = String::from_utf8_lossy(&msg);
+    eprintln!("[{}] {} {} {} - {}: {}", request_id, client_ip, method, path, parts.status.as_u16(), msg);
+    res = resp::with_error_body(parts, &msg, &request_id);
+  };
+  if msgpack { res = resp::to_msgpack(res).await; };
+  if let Ok(id) = HeaderValue::from_str(&request_id) {
+    res.headers_mut().insert("X-Request-Id", id);
+    res.headers_mut().insert("Access-Control-Expose-Headers", HeaderValue::from_static("X-Request-Id"));
+  };
+  resp::with_common_headers(&mut res, origin, &security_headers);
+  Ok(res)
+}
+
+/// Проверяет, что путь ведёт к методу администратора.
+fn is_admin(path: &str) -> bool {
+  path == "/pg-setup" || path.starts_with("/admin/")
+}
+
+/// Проверяет, что путь имеет вид `/oauth/<поставщик>/<action>`.
+fn is_oauth(path: &str, action: &str) -> bool {
+  match path.strip_prefix("/oauth/").and_then(|rest| rest.split_once('/')) {
+    Some((provider, rest)) => !provider.is_empty() && rest == action,
+    None => false,
+  }
+}
+
+/// Вызывает обработчик, соответствующий запросу.
+async fn handle(ws: Workspace, live_cfg: &LiveConfig) -> Response<Body> {
+  match (ws.req.method(), ws.req.uri().path()) {
     (    &Method::GET,     "/favicon.ico")  => resp  ::from_code_and_msg  (404, None),
-    (    &Method::GET,     "/pg-setup")     => routes::db_setup           (ws, admin_key)      .await,
+    (    &Method::GET,     "/schemas")      => routes::get_schemas        (),
+    (    &Method::GET,     "/pg-setup")     => routes::db_setup           (ws)                 .await,
+    (    &Method::GET,     "/admin/backup") => routes::backup             (ws)                 .await,
+    (    &Method::PUT,     "/admin/restore")=> routes::restore            (ws)                 .await,
+    (    &Method::POST,    "/admin/reload-config")=>routes::reload_config(ws, live_cfg)        .await,
+    (    &Method::POST,    "/admin/revalidate-boards")=>routes::revalidate_boards(ws)         .await,
+    (    &Method::GET,     "/admin/db-metrics")=>routes::db_metrics       (ws)                 .await,
+    (    &Method::GET,     "/admin/board-sizes")=>routes::board_sizes     (ws)                 .await,
+    (    &Method::GET,     "/admin/jobs")   => routes::list_jobs          (ws)                 .await,
+    (    &Method::POST,    "/admin/jobs/retry")=>routes::retry_job        (ws)                 .await,
+    (    &Method::GET,     "/admin/keys")   => routes::list_admin_keys    (ws)                 .await,
+    (    &Method::PUT,     "/admin/keys")   => routes::put_admin_key      (ws)                 .await,
+    (    &Method::DELETE,  "/admin/keys")   => routes::delete_admin_key   (ws)                 .await,
+    (    &Method::GET,     "/admin/audit")  => routes::list_admin_audit   (ws)                 .await,
+    (    &Method::GET,     "/admin/cc-keys")=> routes::list_cc_keys       (ws)                 .await,
+    (    &Method::POST,    "/admin/cc-keys")=> routes::generate_cc_keys   (ws)                 .await,
+    (    &Method::DELETE,  "/admin/cc-keys")=> routes::revoke_cc_keys     (ws)                 .await,
     (    &Method::PUT,     "/sign-up")      => routes::sign_up            (ws)                 .await,
+    (    &Method::PUT,     "/sign-up/email")=> routes::sign_up_by_email   (ws)                 .await,
+    (    &Method::POST,    "/sign-up/resend")=>routes::resend_sign_up     (ws)                 .await,
+    (    &Method::POST,    "/sign-up/confirm")=>routes::confirm_sign_up   (ws)                 .await,
     (    &Method::GET,     "/sign-in")      => routes::sign_in            (ws)                 .await,
-    (    &Method::OPTIONS, _)               => routes::pre_request        ()                   .await,
+    (    &Method::POST,    "/token/refresh")=> routes::refresh_token      (ws)                 .await,
+    (    &Method::POST,    "/billing/webhook")=>routes::billing_webhook   (ws)                 .await,
+    (    &Method::POST,    "/integrations/github/webhook")=>routes::github_webhook(ws)         .await,
+    (    &Method::GET,     path) if is_oauth(path, "start")   => routes::oauth_start    (ws)         .await,
+    (    &Method::GET,     path) if is_oauth(path, "callback")=> routes::oauth_callback (ws)         .await,
+    (    &Method::OPTIONS, _)               => routes::pre_request        (ws)                 .await,
     (method, path) => match routes::auth_by_token(&ws).await {
       Ok((user_id, billed)) => match (method, path) {
         (&Method::GET,     "/list")         => routes::list_boards        (ws, user_id)        .await,
+        (&Method::POST,    "/boards/bulk")  => routes::bulk_boards        (ws, user_id)        .await,
         (&Method::PUT,     "/board")        => routes::create_board       (ws, user_id, billed).await,
         (&Method::POST,    "/board")        => routes::get_board          (ws, user_id)        .await,
         (&Method::PATCH,   "/board")        => routes::patch_board        (ws, user_id)        .await,
         (&Method::DELETE,  "/board")        => routes::delete_board       (ws, user_id)        .await,
+        (&Method::POST,    "/board/undo")   => routes::undo_deletion      (ws, user_id)        .await,
+        (&Method::POST,    "/board/transfer")=>routes::transfer_board     (ws, user_id)        .await,
+        (&Method::PATCH,   "/board/keep")   => routes::keep_board         (ws, user_id)        .await,
+        (&Method::PATCH,   "/board/archive")=>routes::archive_board      (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/membership")=>routes::leave_board      (ws, user_id)        .await,
+        (&Method::PUT,     "/board/watch")  => routes::watch_board        (ws, user_id, true)  .await,
+        (&Method::DELETE,  "/board/watch")  => routes::watch_board        (ws, user_id, false) .await,
+        (&Method::PATCH,   "/board/document")=>routes::patch_board_document(ws, user_id)       .await,
+        (&Method::POST,    "/board/delta")  => routes::sync_board         (ws, user_id)        .await,
+        (&Method::POST,    "/board/capacity")=>routes::get_board_capacity (ws, user_id)        .await,
+        (&Method::POST,    "/board/stats")  => routes::get_board_stats    (ws, user_id)        .await,
+        (&Method::POST,    "/board/suggest-assignment")=>routes::suggest_executors(ws, user_id)  .await,
+        (&Method::POST,    "/board/recycled-tasks")=>routes::get_recycled_tasks(ws, user_id)   .await,
         (&Method::PUT,     "/card")         => routes::create_card        (ws, user_id)        .await,
         (&Method::PATCH,   "/card")         => routes::patch_card         (ws, user_id)        .await,
         (&Method::DELETE,  "/card")         => routes::delete_card        (ws, user_id)        .await,
+        (&Method::PUT,     "/card/import/csv")=>routes::import_csv        (ws, user_id)        .await,
         (&Method::PUT,     "/task")         => routes::create_task        (ws, user_id)        .await,
         (&Method::PATCH,   "/task")         => routes::patch_task         (ws, user_id)        .await,
         (&Method::DELETE,  "/task")         => routes::delete_task        (ws, user_id)        .await,
         (&Method::PATCH,   "/task/time")    => routes::patch_task_time    (ws, user_id)        .await,
+        (&Method::PATCH,   "/task/executors")=>routes::patch_task_executors(ws, user_id)       .await,
+        (&Method::PUT,     "/task/watch")   => routes::watch_task         (ws, user_id, true)  .await,
+        (&Method::DELETE,  "/task/watch")   => routes::watch_task         (ws, user_id, false) .await,
+        (&Method::POST,    "/task/history") => routes::get_task_history   (ws, user_id)        .await,
+        (&Method::PUT,     "/task/dependency")=>routes::add_task_dependency(ws, user_id)       .await,
+        (&Method::DELETE,  "/task/dependency")=>routes::delete_task_dependency(ws, user_id)    .await,
+        (&Method::PUT,     "/task/link")    => routes::put_task_link      (ws, user_id)        .await,
+        (&Method::DELETE,  "/task/link")    => routes::delete_task_link   (ws, user_id)        .await,
         (&Method::PUT,     "/subtask")      => routes::create_subtask     (ws, user_id)        .await,
         (&Method::PATCH,   "/subtask")      => routes::patch_subtask      (ws, user_id)        .await,
         (&Method::DELETE,  "/subtask")      => routes::delete_subtask     (ws, user_id)        .await,
         (&Method::PATCH,   "/subtask/time") => routes::patch_subtask_time (ws, user_id)        .await,
         (&Method::GET,     "/tags")         => routes::get_tags           (ws, user_id)        .await,
-        (&Method::PUT,     "/tag")          => routes::create_tag         (ws, user_id)        .await,
-        (&Method::PATCH,   "/tag")          => routes::patch_tag          (ws, user_id)        .await,
-        (&Method::DELETE,  "/tag")          => routes::delete_tag         (ws, user_id)        .await,
+        (&Method::PUT,     "/tag")          => routes::attach_tag         (ws, user_id)        .await,
+        (&Method::DELETE,  "/tag")          => routes::detach_tag         (ws, user_id)        .await,
+        (&Method::PUT,     "/board/tag")    => routes::create_board_tag   (ws, user_id)        .await,
+        (&Method::PATCH,   "/board/tag")    => routes::patch_board_tag    (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/tag")    => routes::delete_board_tag   (ws, user_id)        .await,
+        (&Method::PUT,     "/board/lane")   => routes::create_board_lane  (ws, user_id)        .await,
+        (&Method::PATCH,   "/board/lane")   => routes::patch_board_lane   (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/lane")   => routes::delete_board_lane  (ws, user_id)        .await,
+        (&Method::PUT,     "/board/sprint") => routes::create_board_sprint(ws, user_id)        .await,
+        (&Method::PATCH,   "/board/sprint") => routes::patch_board_sprint (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/sprint") => routes::delete_board_sprint(ws, user_id)        .await,
+        (&Method::POST,    "/board/sprint/report")=>routes::get_sprint_report(ws, user_id)     .await,
+        (&Method::PUT,     "/board/view")   => routes::put_board_view     (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/view")   => routes::delete_board_view  (ws, user_id)        .await,
+        (&Method::GET,     "/board/views")  => routes::get_board_views    (ws, user_id)        .await,
+        (&Method::PUT,     "/board/report") => routes::put_board_report   (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/report") => routes::delete_board_report(ws, user_id)        .await,
+        (&Method::GET,     "/board/reports")=> routes::get_board_reports  (ws, user_id)        .await,
+        (&Method::PUT,     "/board/github") => routes::put_board_github   (ws, user_id)        .await,
+        (&Method::GET,     "/board/github") => routes::get_board_github   (ws, user_id)        .await,
+        (&Method::DELETE,  "/board/github") => routes::delete_board_github(ws, user_id)        .await,
+        (&Method::POST,    "/board/github/sync")=>routes::sync_board_github(ws, user_id)        .await,
+        (&Method::PUT,     "/org")          => routes::create_org         (ws, user_id)        .await,
+        (&Method::POST,    "/org")          => routes::get_org            (ws, user_id)        .await,
+        (&Method::PATCH,   "/org")          => routes::patch_org          (ws, user_id)        .await,
+        (&Method::DELETE,  "/org")          => routes::delete_org         (ws, user_id)        .await,
+        (&Method::GET,     "/org/list")     => routes::list_orgs          (ws, user_id)        .await,
+        (&Method::PUT,     "/org/member")   => routes::put_org_member     (ws, user_id)        .await,
+        (&Method::DELETE,  "/org/member")   => routes::delete_org_member  (ws, user_id)        .await,
+        (&Method::PUT,     "/org/guest")    => routes::create_org_guest   (ws, user_id)        .await,
+        (&Method::PUT,     "/org/board")    => routes::org_board          (ws, user_id, true)  .await,
+        (&Method::DELETE,  "/org/board")    => routes::org_board          (ws, user_id, false) .await,
+        (&Method::PUT,     "/org/board/guest")=>routes::share_org_board   (ws, user_id, true)  .await,
+        (&Method::DELETE,  "/org/board/guest")=>routes::share_org_board   (ws, user_id, false) .await,
         (&Method::PATCH,   "/user/creds")   => routes::patch_user_creds   (ws, user_id)        .await,
         (&Method::PATCH,   "/user/billing") => routes::patch_user_billing (ws, user_id)        .await,
+        (&Method::PATCH,   "/user/profile") => routes::patch_user_profile (ws, user_id)        .await,
+        (&Method::PATCH,   "/user/board-prefs")=>routes::patch_board_prefs(ws, user_id)        .await,
+        (&Method::GET,     "/user/notification-prefs")=>routes::get_notification_prefs(ws, user_id).await,
+        (&Method::PATCH,   "/user/notification-prefs")=>routes::patch_notification_prefs(ws, user_id).await,
+        (&Method::GET,     "/user/quota")   => routes::get_quota          (ws, user_id, billed).await,
+        (&Method::GET,     "/user/sessions")=> routes::list_sessions      (ws, user_id)        .await,
+        (&Method::GET,     "/user/security-events")=>routes::get_security_events(ws, user_id).await,
+        (&Method::GET,     "/user/notifications")=>routes::get_notifications(ws, user_id)      .await,
+        (&Method::GET,     "/user/board-exports")=>routes::get_board_exports(ws, user_id)      .await,
+        (&Method::GET,     "/user/board-export")=>routes::get_board_export(ws, user_id)        .await,
+        (&Method::POST,    "/user/signed-url")=>routes::create_signed_url (ws, user_id)        .await,
+        (&Method::PATCH,   "/user/notifications/read")=>routes::read_notifications(ws, user_id).await,
+        (&Method::GET,     "/users/resolve")=> routes::resolve_users      (ws)                 .await,
         _ => resp::from_code_and_msg(404, Some("Запрашиваемый ресурс не существует.")),
       },
       Err((code, msg)) => resp::from_code_and_msg(code, Some(&msg)),
     },
-  })
+  }
 }
@@ -1,14 +1,21 @@
 //! Отвечает за формирование Response для hyper.
 
+use chrono::Utc;
 use hyper::Body;
-use hyper::http::Response;
+use hyper::http::{HeaderValue, Response, response::Parts};
+use serde_json::Value as JsonValue;
+
+use crate::core::board_size::BoardTooLarge;
+use crate::core::import::RowError;
+use crate::hyper_router::schema::SchemaViolation;
+use crate::model::MSGPACK_CONTENT_TYPE;
+use crate::sec::policy::Violation;
+use crate::setup::SecurityHeaders;
 
 /// Формирует ответ из кода HTTP.
 pub fn from_code_and_msg(code: u16, msg: Option<&str>) -> Response<Body> {
   Response::builder()
     .header("Content-Type", "text/html; charset=utf-8")
-    .header("Access-Control-Allow-Origin", "http://localhost:3000")
-    .header("Access-Control-Allow-Credentials", "true")
     .status(code)
     .body(match msg {
       None => Body::empty(),
@@ -17,17 +24,179 @@ pub fn from_code_and_msg(code: u16, msg: Option<&str>) -> Response<Body> {
     .unwrap()
 }
 
-/// Разрешает все запросы к серверу.
-pub fn options_answer() -> Response<Body> {
+/// Формирует ответ 429 с моментом времени, до которого запросы отклоняются.
+///
+/// Момент передаётся в теле ответа как JSON `{"locked_until": <UNIX-время>}`, а также в заголовке `Retry-After` в секундах.
+pub fn too_many_requests(until: i64) -> Response<Body> {
+  let retry_after = (until - Utc::now().timestamp()).max(0);
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .header("Retry-After", retry_after.to_string())
+    .status(429)
+    .body(Body::from(format!(r#"{{"locked_until":{}}}"#, until)))
+    .unwrap()
+}
+
+/// Формирует ответ 402 о превышении ограничения тарифного плана.
+///
+/// В теле ответа передаётся JSON `{"quota": <название ограничения>, "limit": <значение ограничения>}`.
+pub fn payment_required(quota: &str, limit: u64) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(402)
+    .body(Body::from(serde_json::json!({ "quota": quota, "limit": limit }).to_string()))
+    .unwrap()
+}
+
+/// Формирует ответ 413 о превышении ограничения размера доски.
+///
+/// В теле ответа передаётся JSON `{"error": <описание>, "limit": <название ограничения>, "max": <значение ограничения>, "actual": <значение после изменения>}`.
+pub fn board_too_large(e: &BoardTooLarge) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(413)
+    .body(Body::from(serde_json::json!({ "error": e.to_string(), "limit": e.limit, "max": e.max, "actual": e.actual }).to_string()))
+    .unwrap()
+}
+
+/// Формирует ответ 400 о несоответствии логина или пароля требованиям.
+///
+/// В теле ответа передаётся JSON `{"violations": [{"field": <поле>, "rule": <требование>, "message": <описание>}, ...]}`.
+pub fn validation_failed(violations: &[Violation]) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(400)
+    .body(Body::from(serde_json::json!({ "violations": violations }).to_string()))
+    .unwrap()
+}
+
+/// Формирует ответ 400 о строках импортируемой таблицы, не прошедших проверку.
+///
+/// В теле ответа передаётся JSON `{"rows": [{"row": <номер строки>, "error": <описание>}, ...]}`.
+pub fn import_failed(rows: &[RowError]) -> Response<Body> {
   Response::builder()
-    .header("Access-Control-Allow-Origin", "http://localhost:3000")
-    .header("Access-Control-Allow-Credentials", "true")
-    .header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE, OPTIONS")
-    .header("Access-Control-Allow-Headers", "App-Token")
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(400)
+    .body(Body::from(serde_json::json!({ "rows": rows }).to_string()))
+    .unwrap()
+}
+
+/// Формирует ответ о несоответствии тела запроса (код 400) или ответа (код 500) схеме метода.
+///
+/// В теле ответа передаётся JSON `{"error": <описание>, "violations": [{"path": <путь к значению>, "message": <описание>}, ...]}`.
+pub fn schema_failed(code: u16, error: &str, violations: &[SchemaViolation]) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(code)
+    .body(Body::from(serde_json::json!({ "error": error, "violations": violations }).to_string()))
+    .unwrap()
+}
+
+/// Формирует ответ 200 с JSON, уже записанным в буфер.
+pub fn from_json(body: Vec<u8>) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/json; charset=utf-8")
+    .status(200)
+    .body(Body::from(body))
+    .unwrap()
+}
+
+/// Формирует ответ 200 с телом, которое передаётся по частям.
+pub fn from_stream(body: Body) -> Response<Body> {
+  Response::builder()
+    .header("Content-Type", "application/x-ndjson; charset=utf-8")
+    .status(200)
+    .body(body)
+    .unwrap()
+}
+
+/// Перекодирует JSON в теле ответа (`Content-Type: application/json`) в MessagePack для клиентов, которые его принимают.
+///
+/// Ответы с другим типом содержимого, в том числе передаваемые по частям, возвращаются как есть.
+pub async fn to_msgpack(res: Response<Body>) -> Response<Body> {
+  let is_json = res.headers().get("Content-Type")
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.starts_with("application/json"));
+  if !is_json { return res; };
+  let (mut parts, body) = res.into_parts();
+  let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+  let encoded = serde_json::from_slice::<JsonValue>(&body).ok().and_then(|value| rmp_serde::to_vec_named(&value).ok());
+  let body = match encoded {
+    Some(encoded) => {
+      parts.headers.insert("Content-Type", MSGPACK_CONTENT_TYPE.parse().unwrap());
+      Body::from(encoded)
+    },
+    None => Body::from(body),
+  };
+  parts.headers.remove("Content-Length");
+  Response::from_parts(parts, body)
+}
+
+/// Формирует тело ответа с ошибкой, содержащее идентификатор запроса.
+///
+/// Текст ошибки передаётся в JSON `{"error": <текст ошибки>, "request_id": <идентификатор запроса>}`. Если ошибка уже передаётся в виде JSON-объекта, он дополняется полем `request_id`.
+pub fn with_error_body(mut parts: Parts, msg: &str, request_id: &str) -> Response<Body> {
+  let body = match serde_json::from_str::<JsonValue>(msg) {
+    Ok(JsonValue::Object(mut body)) => {
+      body.insert("request_id".into(), request_id.into());
+      JsonValue::Object(body)
+    },
+    _ if msg.is_empty() => serde_json::json!({ "request_id": request_id }),
+    _ => serde_json::json!({ "error": msg, "request_id": request_id }),
+  };
+  parts.headers.insert("Content-Type", "application/json; charset=utf-8".parse().unwrap());
+  parts.headers.remove("Content-Length");
+  Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+/// Методы, которые браузер может вызывать с разрешённых адресов.
+pub const CORS_METHODS: [&str; 5] = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+/// Формирует ответ 204 на предзапрос браузера, разрешая метод и заголовки, которые браузер запросил.
+pub fn options_answer(headers: &[&str], max_age_secs: u64) -> Response<Body> {
+  let mut res = Response::builder()
+    .header("Access-Control-Allow-Methods", CORS_METHODS.join(", "))
+    .header("Access-Control-Max-Age", max_age_secs.to_string())
+    .header("Vary", "Access-Control-Request-Method, Access-Control-Request-Headers");
+  if !headers.is_empty() {
+    res = res.header("Access-Control-Allow-Headers", headers.join(", "));
+  };
+  res.status(204).body(Body::empty()).unwrap()
+}
+
+/// Формирует ответ 204 на запрос `OPTIONS`, не являющийся предзапросом браузера, перечисляя методы сервера.
+pub fn allow_answer() -> Response<Body> {
+  Response::builder()
+    .header("Allow", format!("{}, OPTIONS", CORS_METHODS.join(", ")))
+    .status(204)
     .body(Body::empty())
     .unwrap()
 }
 
+/// Дополняет ответ заголовками, общими для всех ответов сервера: разрешением CORS для адреса клиента `origin` и заголовками безопасности из конфигурации.
+pub fn with_common_headers(res: &mut Response<Body>, origin: Option<HeaderValue>, cfg: &SecurityHeaders) {
+  let headers = res.headers_mut();
+  if let Some(origin) = origin {
+    headers.insert("Access-Control-Allow-Origin", origin);
+    headers.insert("Access-Control-Allow-Credentials", HeaderValue::from_static("true"));
+    headers.append("Vary", HeaderValue::from_static("Origin"));
+  };
+  headers.append("Vary", HeaderValue::from_static("Accept"));
+  if cfg.hsts_max_age_secs > 0 {
+    let hsts = match cfg.hsts_include_subdomains {
+      true => format!("max-age={}; includeSubDomains", cfg.hsts_max_age_secs),
+      false => format!("max-age={}", cfg.hsts_max_age_secs),
+    };
+    headers.insert("Strict-Transport-Security", HeaderValue::from_str(&hsts).unwrap());
+  };
+  if cfg.nosniff {
+    headers.insert("X-Content-Type-Options", HeaderValue::from_static("nosniff"));
+  };
+  if let Ok(policy) = HeaderValue::from_str(&cfg.referrer_policy) {
+    if !policy.is_empty() { headers.insert("Referrer-Policy", policy); };
+  };
+}
+
 // Выдаёт ошибук 400 BAD REQUEST.
 // Выдаёт ошибку 401 UNAUTHORIZED.
 // Выдаёт ошибку 402 PAYMENT REQUIRED.
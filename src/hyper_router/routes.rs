@@ -1,845 +1,2522 @@
 //! Отвечает за отдачу методов, в том числе результаты запроса, статус-коды и текст ошибок.
 //!
-//! У всех методов должны проверяться права человека на доску путём просмотра списка shared_with:
+//! У всех методов должны проверяться права человека на доску путём просмотра списка shared_with. Для этого методы, работающие с содержимым доски, извлекают параметры при помощи `board_params`, который загружает доску один раз на запрос:
 //!
-//! ```rust
-//! if core::in_shared_with(&ws.db, &token_auth.id, &board_id).await.is_err() {
-//!   return resp::from_code_and_msg(500, Some("Пользователь не имеет доступа к доске."));
+//! ```rust,ignore
+//! let (card, patch, mut ctx) = match board_params::<CardRef>(ws.req, &*ws.db, &user_id).await {
+//!   Ok(v) => v,
+//!   Err(res) => return res,
 //! };
 //! ```
 //!
 //! Следствие этого правила: те, кто имеют доступ к доске, могут редактировать всё её содержимое, кроме параметров самой доски.
 //!
-//! Роутер, в отличие от логики базы данных, отвечает за проверку наличия необходимых параметров в теле запросов. Поэтому все обязательные значения, включая структуры, должны десериализовываться в данном модуле, чтобы в случае чего оперативно предоставить в ответе сервера конкретную ошибку.
+//! Роутер, в отличие от логики базы данных, отвечает за проверку наличия необходимых параметров в теле запросов. Поэтому все обязательные значения, включая структуры, должны десериализовываться в данном модуле (при помощи `extractors`), чтобы в случае чего оперативно предоставить в ответе сервера конкретную ошибку.
 
-use hyper::Body;
+use chrono::{TimeZone, Utc};
+use hyper::{Body, Method};
 use hyper::http::Response;
-use serde_json::Value as JsonValue;
+use serde_json::{json, Value as JsonValue};
 
+use crate::billing;
 use crate::core;
-use crate::hyper_router::resp;
-use crate::model::{extract, Board, Card, Task, Subtask, Tag, Timelines, Workspace};
-use crate::sec::auth::{extract_creds, AdminCredentials, TokenAuth, SignInCredentials, SignUpCredentials};
+use crate::core::admin_audit::{self, AdminCall, AuditFilter};
+use crate::core::admin_keys::{self, WrongAdminKey};
+use crate::core::automation::WrongRule;
+use crate::core::board_size::{self, BoardTooLarge};
+use crate::core::recycle::{self, WrongRecycle};
+use crate::core::capacity;
+use crate::core::compat::DuplicateKeys;
+use crate::core::cc_keys::{self, WrongCcKeysBatch};
+use crate::core::delta::{self, Mutation};
+use crate::core::jobs::{self, NoSuchJob};
+use crate::core::dependencies::{self, DependencyCycle};
+use crate::core::digest::{self, WrongEmail};
+use crate::core::document::{self, NotBoardAuthor, WrongDocument};
+use crate::core::github::{self, NotAuthor, NotLinked, WrongRepo, WrongSignature};
+use crate::core::identities::{self, IdentityTaken, SignUpClosed, WrongState};
+use crate::core::import::{self, ImportFailed};
+use crate::core::json_patch::{Operation, TestFailed, WrongPatch};
+use crate::core::links::{self, NoSuchLink};
+use crate::core::notifications;
+use crate::core::orgs::{
+  self, BoardCreationDenied, BoardInOtherOrg, GuestAccount, GuestRoleOnly, LastOrgAdmin, NoSuchOrg, NotOrgAdmin, NotOrgBoard, NotOrgGuest, NotOrgMember,
+  OrgLimitReached, WrongOrgPolicy
+};
+use crate::core::quota::{self, QuotaExceeded};
+use crate::core::reports::{self, NoSuchReport, TooManyReports, WrongReportTarget};
+use crate::core::retention::{self, NoSuchExport};
+use crate::core::security_events;
+use crate::core::signup::{self, SignUpLimited, WrongConfirmation};
+use crate::core::sprints::{self, NoSuchSprint, Unit, WrongSprint};
+use crate::core::stats;
+use crate::core::task_history::TaskConflict;
+use crate::core::undo::{CannotUndo, NothingToUndo};
+use crate::core::validation::{WrongLink, WrongTitle};
+use crate::core::views::{self, NoSuchView, TooManyViews};
+use crate::hyper_router::extractors::{
+  admin_call, board_params, client_info, entity, extraction_failed, id, opt_entity, opt_id, opt_query_id, params, patch, postgres, raw_body,
+  public_signup, query_param, root_call, BoardLaneRef, BoardRef, BoardSprintRef, BoardTagRef, CardRef, OrgRef, SubtaskRef, TaskOrSubtaskRef, TaskRef
+};
+use crate::hyper_router::{resp, schema};
+use crate::integrations::github::GithubError;
+use crate::integrations::mailer::MailerError;
+use crate::model::{
+  extract, BoardFilter, BoardPatch, BoardPrefsPatch, BoardReport, BoardSort, BoardView, BulkBoardsRequest, CardPatch, GetMutTaskError, Lane, LanePatch, Link, NewBoard, NewCard,
+  NewSubtask, NewTask, NotificationPrefsPatch, NotificationsRead, OrgPatch, OrgRole, ProfilePatch, SignedUrlRequest, Sprint, SprintPatch, TaskPatch, TaskPath, TaskSort,
+  SubtaskPatch, Tag, TagPatch, Timelines, Workspace
+};
+use crate::psql_handler::metrics;
+use crate::sec::auth::{
+  extract_creds, AdminKey, AdminScope, ClientInfo, CredentialsPatch, DirectoryUnavailable, EmailSignUpCredentials, RefreshCredentials, TokenAuth,
+  SignInCredentials, SignUpCredentials
+};
+use crate::sec::oidc;
+use crate::sec::policy::{self, PolicyViolations};
+use crate::sec::signing;
 use crate::sec::tokens_vld;
+use crate::setup::LiveConfig;
+use crate::storage::{PostgresRequired, Storage};
 
-/// Отвечает на предзапросы браузера.
-pub async fn pre_request() -> Response<Body> {
-  resp::options_answer()
+/// Формирует ответ на ошибку создания или изменения содержимого доски.
+///
+/// Если данные не прошли проверку (см. `core::validation` и `core::automation`), возвращается код 400 с описанием ошибки, если в карточке не осталось места для задач - код 409, а если доска в архиве (см. `core::retention`) - код 423; иначе - код 500 с текстом `msg`.
+fn write_failed(e: &(dyn std::error::Error + 'static), msg: &str) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<core::BoardArchived>() {
+    return resp::from_code_and_msg(423, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<core::WipLimitReached>() {
+    return resp::from_code_and_msg(409, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<BoardTooLarge>() {
+    return resp::board_too_large(e);
+  };
+  if let Some(e) = e.downcast_ref::<WrongLink>() {
+    return resp::from_code_and_msg(400, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<WrongSprint>() {
+    return resp::from_code_and_msg(400, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<NoSuchSprint>() {
+    return resp::from_code_and_msg(404, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<WrongRule>() {
+    return resp::from_code_and_msg(400, Some(&e.to_string()));
+  };
+  if let Some(e) = e.downcast_ref::<WrongRecycle>() {
+    return resp::from_code_and_msg(400, Some(&e.to_string()));
+  };
+  match e.downcast_ref::<WrongTitle>() {
+    Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+    None => resp::from_code_and_msg(500, Some(msg)),
+  }
 }
 
-/// Отвечает за авторизацию администратора и первоначальную настройку базы данных.
-pub async fn db_setup(ws: Workspace, admin_key: String) -> Response<Body> {
-  let key = match extract_creds::<AdminCredentials>(ws.req.headers().get("App-Token")) {
-    Ok(v) => v.key,
-    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
+/// Формирует ответ на ошибку работы с отчётами о доске.
+///
+/// Если пользователь не автор доски, возвращается код 403; если отчёта нет - 404; если получатель задан неверно - 400; если у доски уже слишком много отчётов - 409; иначе - код 500 с текстом `msg`.
+fn reports_failed(e: &(dyn std::error::Error + 'static), msg: &str) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<reports::NotAuthor>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NoSuchReport>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongReportTarget>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  match e.downcast_ref::<TooManyReports>() {
+    Some(e) => resp::from_code_and_msg(409, Some(&e.to_string())),
+    None => resp::from_code_and_msg(500, Some(msg)),
+  }
+}
+
+/// Формирует ответ на ошибку работы с организациями.
+///
+/// Если организации, участника, гостя, пользователя или доски организации нет, возвращается код 404; если действие недоступно пользователю - 403; если роль или правила организации неверны - 400; если изменение нарушает состав организации или её ограничения или логин занят - 409; если выбрано хранилище без организаций - 501. Остальные ошибки разбирает `write_failed`.
+fn orgs_failed(e: &(dyn std::error::Error + 'static), msg: &str) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<NoSuchOrg>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotOrgMember>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotOrgGuest>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<GuestAccount>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<BoardCreationDenied>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<GuestRoleOnly>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongOrgPolicy>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<core::LoginTaken>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<PostgresRequired>() { return resp::from_code_and_msg(501, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<orgs::NoSuchUser>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotOrgBoard>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotOrgAdmin>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<orgs::NotAuthor>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<LastOrgAdmin>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<BoardInOtherOrg>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  match e.downcast_ref::<OrgLimitReached>() {
+    Some(e) => resp::from_code_and_msg(409, Some(&e.to_string())),
+    None => write_failed(e, msg),
+  }
+}
+
+/// Формирует ответ на ошибку работы со связью доски с репозиторием GitHub.
+///
+/// Если пользователь не автор доски, возвращается код 403; если доска не связана с репозиторием - 404; если репозиторий задан неверно - 400; если GitHub вернул ошибку - 502. Остальные ошибки разбирает `write_failed`.
+fn github_failed(e: &(dyn std::error::Error + 'static), msg: &str) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<NotAuthor>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotLinked>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongRepo>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  match e.downcast_ref::<GithubError>() {
+    Some(e) => resp::from_code_and_msg(502, Some(&e.to_string())),
+    None => write_failed(e, msg),
+  }
+}
+
+/// Формирует ответ на ошибку применения патча JSON Patch к доске.
+///
+/// Если не проходит операция `test` или зависимости задач образуют цикл, возвращается код 409; если пользователь без авторства меняет заголовок, фон или настройки доски - 403; если патч или результат не проходит проверку - 400; если превышена квота - 402. Остальные ошибки разбирает `write_failed`.
+fn document_failed(e: &(dyn std::error::Error + 'static)) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<TestFailed>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<DependencyCycle>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<NotBoardAuthor>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongPatch>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongDocument>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  match e.downcast_ref::<QuotaExceeded>() {
+    Some(exceeded) => resp::payment_required(exceeded.quota, exceeded.limit),
+    None => write_failed(e, "Не удалось применить патч к доске."),
+  }
+}
+
+/// Отвечает на предзапросы браузера согласно настройкам CORS.
+///
+/// Предзапрос с адреса не из `cors_origins`, к методу, которого нет среди `resp::CORS_METHODS`, или с заголовками не из `cors_allowed_headers` отклоняется с кодом 403. Разрешённые заголовки перечисляются в ответе так, как их запросил браузер. Запрос `OPTIONS` без заголовков предзапроса получает список методов сервера.
+pub async fn pre_request(ws: Workspace) -> Response<Body> {
+  let header = |name: &str| ws.req.headers().get(name).and_then(|value| value.to_str().ok());
+  let (origin, method) = match (header("Origin"), header("Access-Control-Request-Method")) {
+    (Some(origin), Some(method)) => (origin, method),
+    _ => return resp::allow_answer(),
   };
-  let status_code = match key == admin_key {
-    true => match core::db_setup(&ws.db).await {
-      Ok(_) => 200,
-      _ => 500,
-    },
-    _ => 401,
+  if !ws.cfg.cors_origins.iter().any(|allowed| allowed == origin) {
+    return resp::from_code_and_msg(403, Some("Запросы с этого адреса не разрешены."));
+  };
+  if !resp::CORS_METHODS.contains(&method) {
+    return resp::from_code_and_msg(403, Some(&format!("Метод {} не разрешён.", method)));
+  };
+  let requested: Vec<&str> = header("Access-Control-Request-Headers").unwrap_or_default()
+    .split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+  if let Some(name) = requested.iter().find(|name| !ws.cfg.cors_allowed_headers.iter().any(|allowed| allowed.eq_ignore_ascii_case(name))) {
+    return resp::from_code_and_msg(403, Some(&format!("Заголовок {} не разрешён.", name)));
   };
-  resp::from_code_and_msg(status_code, None)
+  resp::options_answer(&requested, ws.cfg.cors_max_age_secs)
 }
 
-/// Отвечает за регистрацию нового пользователя. 
+/// Записывает вызов метода администратора в журнал (см. `core::admin_audit`).
 ///
-/// Создаёт аккаунт и возвращает данные аутентификации (новый токен и идентификатор).
-pub async fn sign_up(ws: Workspace) -> Response<Body> {
-  let su_creds = match extract_creds::<SignUpCredentials>(ws.req.headers().get("App-Token")) {
+/// Возвращает ответ с ошибкой, если записать вызов не удалось. Журнал хранится только в PostgreSQL: с другим хранилищем вызовы не записываются.
+async fn audit(db: &dyn Storage, call: &AdminCall, entity: Option<&str>, summary: JsonValue) -> Option<Response<Body>> {
+  let db = db.postgres()?;
+  match admin_audit::record(db, call, entity, &summary).await {
+    Ok(_) => None,
+    _ => Some(resp::from_code_and_msg(500, Some("Не удалось записать действие в журнал администраторов."))),
+  }
+}
+
+/// Отвечает за авторизацию администратора и первоначальную настройку базы данных.
+///
+/// Таблицы других хранилищ создаются при запуске сервера, поэтому с ними метод только проверяет ключ администратора.
+pub async fn db_setup(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
+    Err(res) => return res,
+  };
+  if let Some(db) = ws.db.postgres() {
+    if let Err(e) = core::db_setup(db).await {
+      return match e.downcast_ref::<DuplicateKeys>() {
+        Some(e) => resp::from_code_and_msg(409, Some(&e.to_string())),
+        None => resp::from_code_and_msg(500, None),
+      };
+    };
   };
-  if su_creds.pass.len() < 8 {
-    return resp::from_code_and_msg(400, Some("Пароль слишком короткий."));
+  match audit(&*ws.db, &call, None, json!({})).await {
+    Some(res) => res,
+    None => resp::from_code_and_msg(200, None),
+  }
+}
+
+/// Выгружает резервную копию базы данных.
+///
+/// Если в строке запроса передан параметр `no-secrets`, данные аутентификации пользователей в копию не попадают.
+pub async fn backup(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Backup).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let id = match core::create_user(&ws.db, &su_creds).await {
+  let db = match postgres(&*ws.db) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(500, Some("Не удалось создать пользователя.")),
+    Err(res) => return res,
   };
-  match core::get_new_token(&ws.db, &id).await {
-    Ok(token_auth) => resp::from_code_and_msg(200, Some(&serde_json::to_string(&token_auth).unwrap())),
-    _ => resp::from_code_and_msg(500, Some("Не удалось создать токен.")),
+  let with_secrets = !ws.req.uri().query().unwrap_or("").split('&').any(|p| p == "no-secrets");
+  if let Some(res) = audit(db, &call, None, json!({ "with_secrets": with_secrets })).await { return res; };
+  match core::backup(db, with_secrets).await {
+    Ok(body) => resp::from_stream(body),
+    _ => resp::from_code_and_msg(500, Some("Не удалось выгрузить резервную копию.")),
   }
 }
 
-/// Отвечает за аутентификацию пользователей в приложении.
-pub async fn sign_in(ws: Workspace) -> Response<Body> {
-  let si_creds = match extract_creds::<SignInCredentials>(ws.req.headers().get("App-Token")) {
+/// Восстанавливает базу данных из резервной копии, переданной в теле запроса.
+pub async fn restore(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Backup).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
+    Err(res) => return res,
   };
-  let id = match core::sign_in_creds_to_id(&ws.db, &si_creds).await {
+  let db = match postgres(&*ws.db) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(401, None),
+    Err(res) => return res,
   };
-  let token_auth = match core::get_new_token(&ws.db, &id).await {
+  let count = match core::restore(db, ws.req.into_body()).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(500, None),
+    Err(e) => return resp::from_code_and_msg(500, Some(&format!("Не удалось восстановить резервную копию: {}", e))),
   };
-  match serde_json::to_string(&token_auth) {
-    Ok(body) => resp::from_code_and_msg(200, Some(&body)),
-    _ => resp::from_code_and_msg(500, None),
+  match audit(db, &call, None, json!({ "rows": count })).await {
+    Some(res) => res,
+    None => resp::from_code_and_msg(200, Some(&count.to_string())),
   }
 }
 
-/// Аутенцифицирует пользователя по токену, возвращая его идентификатор и данные по оплате аккаунта.
-pub async fn auth_by_token(ws: &Workspace) -> Result<(i64, bool), (u16, String)> {
-  let token_auth = match extract_creds::<TokenAuth>(ws.req.headers().get("App-Token")) {
+/// Выпускает ключ администратора или заменяет ключ с тем же названием.
+///
+/// Возвращает выпущенный ключ: сервер хранит только его хэш, поэтому получить ключ повторно нельзя.
+pub async fn put_admin_key(ws: Workspace) -> Response<Body> {
+  let call = match root_call(&ws) {
     Ok(v) => v,
-    _ => return Err((401, "Не получен валидный токен.".into())),
+    Err(res) => return res,
   };
-  let (valid, billed) = tokens_vld::verify_user(&ws.db, &token_auth).await;
-  if !valid {
-    return Err((401, "Неверный токен. Пройдите аутентификацию заново.".into()));
+  let key = match extract::<AdminKey>(ws.req).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  Ok((token_auth.id, billed))
-}
-
-/// Отправляет список доступных для пользователя досок.
-pub async fn list_boards(ws: Workspace, user_id: i64) -> Response<Body> {
-  match core::list_boards(&ws.db, &user_id).await {
-    Ok(list) => resp::from_code_and_msg(200, Some(&list)),
-    _ => resp::from_code_and_msg(500, Some("Не удалось получить список досок.")),
+  if let Some(res) = audit(&*ws.db, &call, Some(&key.name), json!(key)).await { return res; };
+  match admin_keys::put(&*ws.db, &key).await {
+    Ok(secret) => {
+      let mut res = serde_json::to_value(&key).unwrap();
+      res["key"] = secret.into();
+      resp::from_json(res.to_string().into_bytes())
+    },
+    Err(e) => match e.downcast_ref::<WrongAdminKey>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось выпустить ключ.")),
+    },
   }
 }
 
-/// Создаёт доску для пользователя.
-pub async fn create_board(ws: Workspace, user_id: i64, billed: bool) -> Response<Body> {
-  if !billed {
-    let boards_n = match core::count_boards(&ws.db, &user_id).await {
-      Ok(v) => v,
-      _ => return resp::from_code_and_msg(500, Some("Невозможно сосчитать число имеющихся досок у пользователя.")),
-    };
-    if boards_n > 0 {
-      return resp::from_code_and_msg(402, Some("Вы не можете использовать больше одной доски на бесплатном аккаунте."));
-    };
+/// Удаляет ключ администратора.
+pub async fn delete_admin_key(ws: Workspace) -> Response<Body> {
+  let call = match root_call(&ws) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let board = match extract::<Board>(ws.req).await {
+  let body = match extract::<JsonValue>(ws.req).await {
     Ok(v) => v,
     _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  match core::create_board(&ws.db, &user_id, &board).await {
-    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
-    _ => resp::from_code_and_msg(500, Some("Не удалось создать доску.")),
+  let name = match body.get("name").and_then(|name| name.as_str()) {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(400, Some("Не получен name.")),
+  };
+  if let Some(res) = audit(&*ws.db, &call, Some(name), json!({})).await { return res; };
+  match admin_keys::delete(&*ws.db, name).await {
+    Ok(true) => resp::from_code_and_msg(200, None),
+    Ok(false) => resp::from_code_and_msg(404, Some("Ключ не найден.")),
+    _ => resp::from_code_and_msg(500, Some("Не удалось удалить ключ.")),
   }
 }
 
-/// Передаёт доску пользователю.
-pub async fn get_board(ws: Workspace, user_id: i64) -> Response<Body> {
-  let board_id = match extract::<JsonValue>(ws.req).await {
-    Ok(v) => match v["board_id"].as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(401, Some("Данная доска вам недоступна."));
+/// Возвращает список ключей администраторов без самих ключей.
+pub async fn list_admin_keys(ws: Workspace) -> Response<Body> {
+  let call = match root_call(&ws) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::get_board(&ws.db, &board_id).await {
-    Ok(board) => resp::from_code_and_msg(200, Some(&board)),
-     _ => resp::from_code_and_msg(500, None),
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  match admin_keys::list(&*ws.db).await {
+    Ok(keys) => resp::from_json(serde_json::to_vec(&keys).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить ключи.")),
   }
 }
 
-/// Патчит доску, изменяя в ней определённые свойства.
+/// Возвращает записи журнала администраторов, начиная с последних.
 ///
-/// Для доски это - title, background_color, header_background_color и header_text_color. Дочерними карточками управляют методы карточек.
-///
-/// Запрос представляет из себя JSON с id доски. Изменения принимаются только тогда, когда автором доски является данный пользователь.
-pub async fn patch_board(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Параметры строки запроса: `key`, `route` и `entity` - точные значения полей записи, `from` и `to` - период в секундах Unix, `limit` - число записей и `before` - идентификатор записи, после которой начинается страница.
+pub async fn list_admin_audit(ws: Workspace) -> Response<Body> {
+  let call = match root_call(&ws) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
-  };
-  let board_id = match patch.get("board_id") {
-    Some(id) => match id.as_i64() {
-      Some(id) => id,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
+    Err(res) => return res,
+  };
+  let text = |name: &str| query_param(&ws.req, name).and_then(oidc::decode);
+  let filter = AuditFilter {
+    key: text("key"),
+    route: text("route"),
+    entity: text("entity"),
+    from: match opt_query_id(&ws.req, "from") {
+      Ok(v) => v,
+      Err(res) => return res,
+    },
+    to: match opt_query_id(&ws.req, "to") {
+      Ok(v) => v,
+      Err(res) => return res,
+    },
+    before: match opt_query_id(&ws.req, "before") {
+      Ok(v) => v,
+      Err(res) => return res,
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
   };
-  match core::apply_patch_on_board(&ws.db, &user_id, &board_id, &patch).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось применить патч к доске.")),
+  let limit = match opt_query_id(&ws.req, "limit") {
+    Ok(None) => 100,
+    Ok(Some(limit)) if (1..=admin_audit::MAX_AUDIT_PAGE).contains(&limit) => limit,
+    _ => return resp::from_code_and_msg(
+      400, Some(&format!("limit должен быть числом от 1 до {}.", admin_audit::MAX_AUDIT_PAGE))
+    ),
+  };
+  let summary = json!({
+    "key": filter.key, "route": filter.route, "entity": filter.entity,
+    "from": filter.from, "to": filter.to, "before": filter.before, "limit": limit
+  });
+  if let Some(res) = audit(&*ws.db, &call, None, summary).await { return res; };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match admin_audit::list(db, &filter, limit).await {
+    Ok(records) => resp::from_json(serde_json::to_vec(&records).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить журнал.")),
   }
 }
 
-/// Удаляет доску.
-pub async fn delete_board(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Выпускает несколько ключей регистрации.
+///
+/// В теле запроса передаются число ключей `count`, а также необязательные заметка `note` и срок действия `expires_at`.
+pub async fn generate_cc_keys(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::UserManagement).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let body = match extract::<JsonValue>(ws.req).await {
     Ok(v) => v,
     _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  let board_id = match patch.get("board_id") {
-    Some(id) => match id.as_i64() {
-      Some(id) => id,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
+  let count = match id(&body, "count") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let note = match opt_entity::<String>(&body, "note") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let expires_at = match opt_id(&body, "expires_at") {
+    Ok(v) => v.map(|expires_at| Utc.timestamp_opt(expires_at, 0).single()),
+    Err(res) => return res,
+  };
+  let expires_at = match expires_at {
+    Some(None) => return resp::from_code_and_msg(400, Some("Неверный срок действия ключей.")),
+    Some(Some(v)) => Some(v),
+    None => None,
+  };
+  let summary = json!({ "count": count, "note": note, "expires_at": expires_at.map(|expires_at| expires_at.timestamp()) });
+  if let Some(res) = audit(&*ws.db, &call, None, summary).await { return res; };
+  match cc_keys::generate(&*ws.db, count.max(0) as usize, note, expires_at).await {
+    Ok(keys) => resp::from_json(serde_json::to_vec(&keys).unwrap()),
+    Err(e) => match e.downcast_ref::<WrongCcKeysBatch>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось выпустить ключи.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  }
+}
+
+/// Возвращает неиспользованные ключи регистрации.
+pub async fn list_cc_keys(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::UserManagement).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::remove_board(&ws.db, &user_id, &board_id).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось удалить доску.")),
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  match cc_keys::list_unused(&*ws.db).await {
+    Ok(keys) => resp::from_json(serde_json::to_vec(&keys).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить ключи.")),
   }
 }
 
-/// Создаёт карточку в заданной доске.
-pub async fn create_card(ws: Workspace, user_id: i64) -> Response<Body> {
+/// Отзывает ключи регистрации, переданные в теле запроса в массиве `keys`.
+///
+/// Возвращает число отозванных ключей.
+pub async fn revoke_cc_keys(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::UserManagement).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
   let body = match extract::<JsonValue>(ws.req).await {
     Ok(v) => v,
     _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  let board_id = match body.get("board_id") {
-    Some(id) => match id.as_i64() {
-      Some(id) => id,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Пользователь не имеет доступа к доске."));
-  };
-  let card: Card = match body.get("card") {
-    Some(card) => match serde_json::from_value(card.clone()) {
-      Ok(card) => card,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать карточку.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получена карточка.")),
+  let keys = match entity::<Vec<String>>(&body, "keys") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::insert_card(&ws.db, &user_id, &board_id, card).await {
-    Ok(card_id) => resp::from_code_and_msg(200, Some(&card_id.to_string())),
-    _ => resp::from_code_and_msg(500, Some("Не удалось добавить карточку.")),
+  // Сами ключи в журнал не попадают: неотозванными ключами можно было бы воспользоваться.
+  if let Some(res) = audit(&*ws.db, &call, None, json!({ "count": keys.len() })).await { return res; };
+  match cc_keys::revoke(&*ws.db, &keys).await {
+    Ok(count) => resp::from_code_and_msg(200, Some(&count.to_string())),
+    _ => resp::from_code_and_msg(500, Some("Не удалось отозвать ключи.")),
   }
 }
 
-/// Патчит карточку, изменяя определённые свойства в ней.
-///
-/// Для карточки это - title, background_color, header_background_color и header_text_color.
-pub async fn patch_card(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Перечитывает конфигурацию сервера и применяет параметры, которые можно изменить без перезапуска.
+pub async fn reload_config(ws: Workspace, cfg: &LiveConfig) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(res) => return res,
   };
-  let board_id = match patch.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  match cfg.reload() {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => resp::from_code_and_msg(500, Some(&format!("Не удалось перезагрузить конфигурацию: {}", e))),
+  }
+}
+
+/// Проверяет содержимое всех досок, исправляет найденные ошибки и возвращает отчёт (см. `core::integrity`).
+pub async fn revalidate_boards(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match patch.get("card_id") {
-    Some(id) => match id.as_i64() {
-      Some(id) => id,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::apply_patch_on_card(&ws.db, &board_id, &card_id, &patch).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось применить патч к доске.")),
+  match core::integrity::scan(db).await {
+    Ok(report) => resp::from_json(serde_json::to_vec(&report).unwrap()),
+    Err(e) => resp::from_code_and_msg(500, Some(&format!("Не удалось проверить доски: {}", e))),
   }
 }
 
-/// Удаляет карточку.
-pub async fn delete_card(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Возвращает замеры времени запросов к Postgres по методам API с запуска сервера (см. `psql_handler::metrics`).
+pub async fn db_metrics(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(res) => return res,
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  let res = json!({ "slow_query_ms": ws.cfg.db_slow_query_ms, "routes": metrics::snapshot() });
+  resp::from_json(res.to_string().into_bytes())
+}
+
+/// Возвращает размеры досок, загружавшихся или записывавшихся с запуска сервера, и ограничения их размера (см. `core::board_size`).
+pub async fn board_sizes(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  resp::from_json(serde_json::to_vec(&board_size::snapshot()).unwrap())
+}
+
+/// Возвращает число заданий фоновой очереди по видам и последние невыполненные задания (см. `core::jobs`).
+pub async fn list_jobs(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  if let Some(res) = audit(&*ws.db, &call, None, json!({})).await { return res; };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::remove_card(&ws.db, &board_id, &card_id).await {
-    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить карточку.")),
-    _ => resp::from_code_and_msg(200, None),
+  match jobs::snapshot(db, &ws.cfg.jobs).await {
+    Ok(snapshot) => resp::from_json(serde_json::to_vec(&snapshot).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить задания.")),
   }
 }
 
-/// Создаёт задачу.
-pub async fn create_task(ws: Workspace, user_id: i64) -> Response<Body> {
+/// Снова ставит в очередь невыполненное задание.
+pub async fn retry_job(ws: Workspace) -> Response<Body> {
+  let call = match admin_call(&ws, AdminScope::Setup).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
   let body = match extract::<JsonValue>(ws.req).await {
     Ok(v) => v,
     _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
-  };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let id = match body.get("id").and_then(|id| id.as_i64()) {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(400, Some("Не получен id.")),
   };
-  let task: Task = match body.get("task") {
-    Some(task) => match serde_json::from_value(task.clone()) {
-      Ok(task) => task,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать задачу.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получена задача.")),
+  if let Some(res) = audit(&*ws.db, &call, Some(&id.to_string()), json!({})).await { return res; };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::insert_task(&ws.db, &user_id, &board_id, &card_id, task).await {
-    Ok(task_id) => resp::from_code_and_msg(200, Some(&task_id.to_string())),
-    _ => resp::from_code_and_msg(500, Some("Не удалось добавить задачу.")),
+  match jobs::retry(db, &id).await {
+    Ok(()) => resp::from_code_and_msg(200, None),
+    Err(e) if e.downcast_ref::<NoSuchJob>().is_some() => resp::from_code_and_msg(404, Some(&e.to_string())),
+    _ => resp::from_code_and_msg(500, Some("Не удалось повторить задание.")),
   }
 }
 
-/// Патчит задачу.
+/// Принимает уведомление об оплате от платёжного провайдера.
 ///
-/// В задаче можно поменять:
-/// 1. Название задачи.
-/// 2. Назначенных исполнителей задачи.
-/// 3. Статус выполнения задачи (выполнена/не выполнена).
-/// 4. Заметки к задаче.
-pub async fn patch_task(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Уведомления, не относящиеся к оплате аккаунта, принимаются с кодом 200 и игнорируются, чтобы провайдер не отправлял их повторно.
+pub async fn billing_webhook(ws: Workspace) -> Response<Body> {
+  let provider = match billing::provider(&ws.cfg) {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(404, Some("Приём платежей не настроен.")),
+  };
+  let (parts, body) = ws.req.into_parts();
+  let body = match raw_body(body).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
-  };
-  let board_id = match patch.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  if !provider.verify(&parts.headers, &body) {
+    return resp::from_code_and_msg(400, Some("Неверная подпись уведомления."));
   };
-  let card_id = match patch.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
-  };
-  let task_id = match patch.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  let event = match provider.parse_event(&body) {
+    Ok(Some(v)) => v,
+    Ok(None) => return resp::from_code_and_msg(200, None),
+    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
   };
-  match core::apply_patch_on_task(&ws.db, &board_id, &card_id, &task_id, &patch).await {
+  match core::register_payment(&*ws.db, &event.user_id, &event.paid_at).await {
     Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось применить патч к задаче.")),
+    _ => resp::from_code_and_msg(500, Some("Не удалось записать платёж.")),
   }
 }
 
-/// Удаляет задачу.
-pub async fn delete_task(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Принимает вебхук GitHub для доски, переданной параметром `board_id` строки запроса (см. `core::github`).
+pub async fn github_webhook(ws: Workspace) -> Response<Body> {
+  if ws.cfg.github.is_none() { return resp::from_code_and_msg(404, Some("Интеграция с GitHub не настроена.")); };
+  let board_id = match opt_query_id(&ws.req, "board_id") {
+    Ok(Some(v)) => v,
+    Ok(None) => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+    Err(res) => return res,
+  };
+  let (parts, body) = ws.req.into_parts();
+  let body = match raw_body(body).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(res) => return res,
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match github::receive(db, &board_id, &parts.headers, &body).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match (e.downcast_ref::<NotLinked>(), e.downcast_ref::<WrongSignature>()) {
+      (Some(e), _) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      (_, Some(e)) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      _ => write_failed(e.as_ref(), "Не удалось применить вебхук."),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  }
+}
+
+/// Передаёт JSON Schema тел запросов и ответов, которые сервер проверяет (см. `schema`).
+pub fn get_schemas() -> Response<Body> {
+  resp::from_json(schema::to_json().to_string().into_bytes())
+}
+
+/// Отвечает за регистрацию нового пользователя. 
+///
+/// Создаёт аккаунт и возвращает данные аутентификации (новый токен и идентификатор). Если включена открытая регистрация, а ключи регистрации не требуются, аккаунт без подтверждения адреса не создаётся: регистрироваться нужно через `PUT /sign-up/email`.
+pub async fn sign_up(ws: Workspace) -> Response<Body> {
+  if ws.cfg.public_signup.is_some() && !ws.cfg.cc_key_required {
+    return resp::from_code_and_msg(403, Some("Регистрация возможна только с подтверждением адреса электронной почты."));
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let su_creds = match extract_creds::<SignUpCredentials>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  if let Err(e) = policy::validate(&ws.cfg.credentials_policy, &su_creds.login, true, Some(&su_creds.pass)) {
+    return resp::validation_failed(&e.violations);
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
+  let id = match core::create_user(&*ws.db, &ws.cfg, &su_creds).await {
+    Ok(v) => v,
+    Err(e) => return match (e.downcast_ref::<core::WrongCcKey>(), e.downcast_ref::<core::LoginTaken>()) {
+      (Some(e), _) => resp::from_code_and_msg(401, Some(&e.to_string())),
+      (_, Some(e)) => resp::from_code_and_msg(409, Some(&e.to_string())),
+      _ => resp::from_code_and_msg(500, Some("Не удалось создать пользователя.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
   };
-  match core::remove_task(&ws.db, &board_id, &card_id, &task_id).await {
-    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить задачу.")),
-    _ => resp::from_code_and_msg(200, None),
+  match core::get_new_token(&*ws.db, &id, &ws.cfg, &client_info(&ws)).await {
+    Ok(token_auth) => resp::from_json(serde_json::to_vec(&token_auth).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось создать токен.")),
   }
 }
 
-/// Изменяет временные рамки задачи.
-pub async fn patch_task_time(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Регистрирует пользователя до подтверждения адреса электронной почты и отправляет ему ссылку подтверждения.
+pub async fn sign_up_by_email(ws: Workspace) -> Response<Body> {
+  let (signup, mailer) = match public_signup(&ws.cfg) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
-  };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+    Err(res) => return res,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let creds = match extract_creds::<EmailSignUpCredentials>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  if let Err(e) = policy::validate(&ws.cfg.credentials_policy, &creds.login, true, Some(&creds.pass)) {
+    return resp::validation_failed(&e.violations);
   };
-  let timelines: Timelines = match body.get("timelines") {
-    Some(timelines) => match serde_json::from_value(timelines.clone()) {
-      Ok(timelines) => timelines,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать временные рамки.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получены временные рамки.")),
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::set_timelines_on_task(&ws.db, &board_id, &card_id, &task_id, &timelines).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось присвоить временные рамки для задачи.")),
+  match signup::request(db, &ws.cfg, signup, mailer, &creds, &ws.client_ip.to_string()).await {
+    Ok(pending) => resp::from_json(serde_json::to_vec(&pending).unwrap()),
+    Err(e) => signup_failed(&*e),
   }
 }
 
-/// Создаёт подзадачу.
-pub async fn create_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Отправляет ссылку подтверждения регистрации повторно.
+pub async fn resend_sign_up(ws: Workspace) -> Response<Body> {
+  let (signup, mailer) = match public_signup(&ws.cfg) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
-  };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
-  };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+    Err(res) => return res,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let client_ip = ws.client_ip.to_string();
+  let body = match extract::<JsonValue>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  let login: String = match entity(&body, "login") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let subtask: Subtask = match body.get("subtask") {
-    Some(subtask) => match serde_json::from_value(subtask.clone()) {
-      Ok(subtask) => subtask,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать подзадачу.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получена подзадача.")),
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match core::insert_subtask(&ws.db, &user_id, &board_id, &card_id, &task_id, subtask).await {
-    Ok(subtask_id) => resp::from_code_and_msg(200, Some(&subtask_id.to_string())),
-    _ => resp::from_code_and_msg(500, Some("Не удалось добавить подзадачу.")),
+  match signup::resend(db, signup, mailer, &login, &client_ip).await {
+    Ok(()) => resp::from_code_and_msg(200, None),
+    Err(e) => signup_failed(&*e),
   }
 }
 
-/// Изменяет подзадачу.
-///
-/// В подзадаче можно поменять:
-/// 1. Название подзадачи.
-/// 2. Назначенных исполнителей подзадачи.
-/// 3. Статус выполнения подзадачи (выполнена/не выполнена).
-pub async fn patch_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Подтверждает регистрацию по токену из ссылки и отправляет пару токенов нового пользователя.
+pub async fn confirm_sign_up(ws: Workspace) -> Response<Body> {
+  if let Err(res) = public_signup(&ws.cfg) { return res; };
+  let client = client_info(&ws);
+  let (db_handle, cfg) = (ws.db.clone(), ws.cfg.clone());
+  let body = match extract::<JsonValue>(ws.req).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(e) => return extraction_failed(e),
   };
-  let board_id = match patch.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  let token: String = match entity(&body, "token") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let db = match postgres(&*db_handle) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match patch.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let id = match signup::confirm(db, &token).await {
+    Ok(v) => v,
+    Err(e) => return signup_failed(&*e),
   };
-  let task_id = match patch.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  match core::get_new_token(db, &id, &cfg, &client).await {
+    Ok(token_auth) => resp::from_json(serde_json::to_vec(&token_auth).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось создать токен.")),
+  }
+}
+
+/// Формирует ответ на ошибку открытой регистрации.
+fn signup_failed(e: &(dyn std::error::Error + 'static)) -> Response<Body> {
+  if let Some(e) = e.downcast_ref::<SignUpLimited>() { return resp::too_many_requests(e.until); };
+  if let Some(e) = e.downcast_ref::<WrongEmail>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<core::LoginTaken>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<WrongConfirmation>() { return resp::from_code_and_msg(404, Some(&e.to_string())); };
+  if let Some(e) = e.downcast_ref::<MailerError>() { return resp::from_code_and_msg(502, Some(&e.to_string())); };
+  resp::from_code_and_msg(500, Some("Не удалось зарегистрировать пользователя."))
+}
+
+/// Отвечает за аутентификацию пользователей в приложении.
+pub async fn sign_in(ws: Workspace) -> Response<Body> {
+  let si_creds = match extract_creds::<SignInCredentials>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
   };
-  let subtask_id = match patch.get("subtask_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
+  let client = client_info(&ws);
+  let signed_in = core::sign_in_creds_to_id(&*ws.db, &ws.cfg, &si_creds).await.map_err(|e| {
+    match (e.downcast_ref::<core::SignInLocked>(), e.downcast_ref::<DirectoryUnavailable>()) {
+      (Some(locked), _) => resp::too_many_requests(locked.until),
+      (_, Some(e)) => resp::from_code_and_msg(503, Some(&e.to_string())),
+      _ if e.is::<PostgresRequired>() => resp::from_code_and_msg(501, Some(&e.to_string())),
+      _ => resp::from_code_and_msg(401, None),
+    }
+  });
+  let id = match signed_in {
+    Ok(v) => v,
+    // Ответ 429 означает, что вход в аккаунт заблокирован.
+    Err(res) if res.status() == 429 => {
+      record_lockout(&ws, &si_creds.login, &client).await;
+      return res;
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен subtask_id.")),
+    Err(res) => return res,
   };
-  match core::apply_patch_on_subtask(
-    &ws.db, &board_id, &card_id, &task_id, &subtask_id, &patch
-  ).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось применить патч к подзадаче.")),
+  record_sign_in(&ws, id, &client).await;
+  let token_auth = match core::get_new_token(&*ws.db, &id, &ws.cfg, &client).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(500, None),
+  };
+  match serde_json::to_vec(&token_auth) {
+    Ok(body) => resp::from_json(body),
+    _ => resp::from_code_and_msg(500, None),
   }
 }
 
-/// Удаляет подзадачу.
-pub async fn delete_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
-    Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+/// Записывает вход в журнал безопасности, если данные хранятся в PostgreSQL (см. `security_events`).
+///
+/// Ошибка записи не мешает входу: она только выводится в журнал сервера.
+async fn record_sign_in(ws: &Workspace, user_id: i64, client: &ClientInfo) {
+  let db = match ws.db.postgres() {
+    Some(v) => v,
+    None => return,
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  if let Err(e) = security_events::record_sign_in(db, &ws.cfg, user_id, client).await {
+    eprintln!("Не удалось записать вход пользователя {} в журнал безопасности: {}", user_id, e);
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+}
+
+/// Записывает блокировку входа в журнал безопасности так же, как `record_sign_in`.
+async fn record_lockout(ws: &Workspace, login: &str, client: &ClientInfo) {
+  let db = match ws.db.postgres() {
+    Some(v) => v,
+    None => return,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  if let Err(e) = security_events::record_lockout(db, &ws.cfg, login, client).await {
+    eprintln!("Не удалось записать блокировку входа {} в журнал безопасности: {}", login, e);
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+}
+
+/// Возвращает имя поставщика входа из адреса вида `/oauth/<поставщик>/...`.
+fn oauth_provider_name(ws: &Workspace) -> String {
+  ws.req.uri().path().split('/').nth(2).unwrap_or_default().to_string()
+}
+
+/// Начинает вход через внешнего поставщика и отправляет адрес его страницы входа.
+///
+/// Если в заголовке `App-Token` передан токен, по завершении входа аккаунт поставщика привязывается к пользователю токена.
+pub async fn oauth_start(ws: Workspace) -> Response<Body> {
+  let provider = match oidc::provider(&ws.cfg, &oauth_provider_name(&ws)) {
+    Ok(v) => v,
+    Err(e) => return resp::from_code_and_msg(404, Some(&e.to_string())),
   };
-  let subtask_id = match body.get("subtask_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
+  let link_to = match ws.req.headers().contains_key("App-Token") {
+    true => match auth_by_token(&ws).await {
+      Ok((user_id, _)) => Some(user_id),
+      Err((code, msg)) => return resp::from_code_and_msg(code, Some(&msg)),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен subtask_id.")),
+    false => None,
   };
-  match core::remove_subtask(&ws.db, &board_id, &card_id, &task_id, &subtask_id).await {
-    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить подзадачу.")),
-    _ => resp::from_code_and_msg(200, None),
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match identities::begin(db, &provider.name, link_to).await {
+    Ok(state) => resp::from_json(serde_json::json!({ "url": oidc::authorize_url(&provider, &state) }).to_string().into_bytes()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось начать вход через поставщика.")),
   }
 }
 
-/// Изменяет временные рамки подзадачи.
-pub async fn patch_subtask_time(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Завершает вход через внешнего поставщика и отправляет пару токенов.
+///
+/// Параметры строки запроса `code` и `state` - те, с которыми поставщик вернул пользователя на страницу клиента.
+pub async fn oauth_callback(ws: Workspace) -> Response<Body> {
+  let provider = match oidc::provider(&ws.cfg, &oauth_provider_name(&ws)) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(e) => return resp::from_code_and_msg(404, Some(&e.to_string())),
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  if let Some(error) = query_param(&ws.req, "error") {
+    let error = oidc::decode(error).unwrap_or_default();
+    return resp::from_code_and_msg(401, Some(&format!("Поставщик отклонил вход: {}", error)));
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let (code, state) = match (
+    query_param(&ws.req, "code").and_then(oidc::decode),
+    query_param(&ws.req, "state").and_then(oidc::decode),
+  ) {
+    (Some(code), Some(state)) => (code, state),
+    _ => return resp::from_code_and_msg(400, Some("Не получены code и state.")),
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
+  let link_to = match identities::finish(db, &provider.name, &state).await {
+    Ok(v) => v,
+    Err(e) => return match e.downcast_ref::<WrongState>() {
+      Some(e) => resp::from_code_and_msg(401, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, None),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
   };
-  let subtask_id = match body.get("subtask_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен subtask_id.")),
+  let identity = match oidc::fetch_identity(&provider, &code).await {
+    Ok(v) => v,
+    Err(e) => return resp::from_code_and_msg(502, Some(&e.to_string())),
   };
-  let timelines: Timelines = match body.get("timelines") {
-    Some(timelines) => match serde_json::from_value(timelines.clone()) {
-      Ok(timelines) => timelines,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать временные рамки.")),
+  let id = match identities::sign_in(db, &ws.cfg, &provider.name, &identity, link_to).await {
+    Ok(v) => v,
+    Err(e) => return match (e.downcast_ref::<IdentityTaken>(), e.downcast_ref::<SignUpClosed>()) {
+      (Some(e), _) => resp::from_code_and_msg(409, Some(&e.to_string())),
+      (_, Some(e)) => resp::from_code_and_msg(403, Some(&e.to_string())),
+      _ => resp::from_code_and_msg(500, Some("Не удалось создать пользователя.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получены временные рамки.")),
   };
-  match core::set_timelines_on_subtask(
-    &ws.db, &board_id, &card_id, &task_id, &subtask_id, &timelines
-  ).await {
-    Ok(_) => resp::from_code_and_msg(200, None),
-    _ => resp::from_code_and_msg(500, Some("Не удалось присвоить временные рамки для подзадачи.")),
+  let client = client_info(&ws);
+  record_sign_in(&ws, id, &client).await;
+  let token_auth = match core::get_new_token(db, &id, &ws.cfg, &client).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(500, None),
+  };
+  match serde_json::to_vec(&token_auth) {
+    Ok(body) => resp::from_json(body),
+    _ => resp::from_code_and_msg(500, None),
   }
 }
 
-/// Получает теги задачи/подзадачи.
-pub async fn get_tags(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Обменивает токен обновления на новую пару токенов.
+pub async fn refresh_token(ws: Workspace) -> Response<Body> {
+  let refresh_creds = match extract_creds::<RefreshCredentials>(ws.req.headers().get("App-Token")) {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    _ => return resp::from_code_and_msg(401, Some("Не получен валидный токен.")),
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  let token_auth = match core::refresh_token(&*ws.db, &refresh_creds, &ws.cfg, &client_info(&ws)).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Токен обновления недействителен. Пройдите аутентификацию заново.")),
+  };
+  match serde_json::to_vec(&token_auth) {
+    Ok(body) => resp::from_json(body),
+    _ => resp::from_code_and_msg(500, None),
+  }
+}
+
+/// Аутенцифицирует пользователя по токену, возвращая его идентификатор и данные по оплате аккаунта.
+///
+/// Запросы `GET` вместо токена могут передавать подпись ссылки (см. `sec::signing`).
+pub async fn auth_by_token(ws: &Workspace) -> Result<(i64, bool), (u16, String)> {
+  if ws.req.method() == Method::GET {
+    let uri = ws.req.uri();
+    match signing::verify(&ws.cfg.admin_key, uri.path(), uri.query(), Utc::now().timestamp()) {
+      Ok(Some(user_id)) => return match tokens_vld::verify_signed_user(&*ws.db, &user_id, &ws.cfg).await {
+        Some(billed) => Ok((user_id, billed)),
+        None => Err((401, signing::WrongSignature{}.to_string())),
+      },
+      Err(e) => return Err((401, e.to_string())),
+      Ok(None) => {},
+    };
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let token_auth = match extract_creds::<TokenAuth>(ws.req.headers().get("App-Token")) {
+    Ok(v) => v,
+    _ => return Err((401, "Не получен валидный токен.".into())),
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let (valid, billed) = tokens_vld::verify_user(&*ws.db, &token_auth, &ws.cfg).await;
+  if !valid {
+    return Err((401, "Неверный токен. Пройдите аутентификацию заново.".into()));
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
+  Ok((token_auth.id, billed))
+}
+
+/// Отправляет список доступных для пользователя досок.
+///
+/// Параметры строки запроса: `archived` (`true`, чтобы получить доски в архиве), `sort` (`added`, `title`, `activity`, `created` или `custom`), `limit` и `cursor`. Если передан `limit` или `cursor`, список отдаётся постранично: вместе с досками передаётся курсор следующей страницы.
+pub async fn list_boards(ws: Workspace, user_id: i64) -> Response<Body> {
+  let sort = match query_param(&ws.req, "sort") {
+    None | Some("added") => BoardSort::Added,
+    Some("title") => BoardSort::Title,
+    Some("activity") => BoardSort::Activity,
+    Some("created") => BoardSort::Created,
+    Some("custom") => BoardSort::Custom,
+    _ => return resp::from_code_and_msg(400, Some("sort должен быть одним из: added, title, activity, created, custom.")),
+  };
+  let archived = query_param(&ws.req, "archived") == Some("true");
+  let cursor = query_param(&ws.req, "cursor");
+  let limit = match query_param(&ws.req, "limit").map(|limit| limit.parse::<i64>()) {
+    None if cursor.is_none() => None,
+    None => Some(core::MAX_BOARDS_PAGE),
+    Some(Ok(limit)) if (1..=core::MAX_BOARDS_PAGE).contains(&limit) => Some(limit),
+    _ => return resp::from_code_and_msg(
+      400, Some(&format!("limit должен быть числом от 1 до {}.", core::MAX_BOARDS_PAGE))
+    ),
+  };
+  let (boards, next_cursor) = match core::list_boards(&*ws.db, &user_id, archived, sort, cursor, limit).await {
+    Ok(v) => v,
+    Err(e) => return match e.downcast_ref::<core::WrongCursor>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось получить список досок.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
   };
-  match body.get("subtask_id") {
-    Some(subtask_id) => match subtask_id.as_i64() {
-      Some(subtask_id) => match core::get_subtask_tags(
-        &ws.db, &board_id, &card_id, &task_id, &subtask_id
-      ).await {
-        Ok(tags) => resp::from_code_and_msg(200, Some(&tags)),
-        _ => resp::from_code_and_msg(500, Some("Не удалось получить теги подзадачи.")),
-      },
-      _ => resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
-    },
-    _ => match core::get_task_tags(
-      &ws.db, &board_id, &card_id, &task_id
-    ).await {
-      Ok(tags) => resp::from_code_and_msg(200, Some(&tags)),
-      _ => resp::from_code_and_msg(500, Some("Не удалось получить теги задачи.")),
+  let body = match limit {
+    None => serde_json::json!(boards),
+    Some(_) => serde_json::json!({ "boards": boards, "next_cursor": next_cursor }),
+  };
+  resp::from_json(body.to_string().into_bytes())
+}
+
+/// Отдаёт краткую информацию о нескольких досках пользователя и, по запросу, сводки по их задачам.
+///
+/// Число досок ограничено `MAX_BOARDS_PAGE`. Если хотя бы одна доска пользователю недоступна, возвращается код 401 с перечнем таких досок.
+pub async fn bulk_boards(ws: Workspace, user_id: i64) -> Response<Body> {
+  let req = match extract::<BulkBoardsRequest>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  if req.board_ids.is_empty() || req.board_ids.len() as i64 > core::MAX_BOARDS_PAGE {
+    return resp::from_code_and_msg(400, Some(&format!("board_ids должен содержать от 1 до {} досок.", core::MAX_BOARDS_PAGE)));
+  };
+  match core::bulk_boards(&*ws.db, &user_id, &req.board_ids, req.summary).await {
+    Ok(boards) => resp::from_json(serde_json::to_vec(&boards).unwrap()),
+    Err(e) => match e.downcast_ref::<core::InaccessibleBoards>() {
+      Some(e) => resp::from_code_and_msg(401, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось получить доски.")),
     },
   }
 }
 
-/// Создаёт тег в задаче/подзадаче.
-pub async fn create_tag(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Создаёт доску для пользователя.
+///
+/// Число досок ограничено тарифным планом пользователя (см. `core::quota`).
+pub async fn create_board(ws: Workspace, user_id: i64, billed: bool) -> Response<Body> {
+  let board = match extract::<NewBoard>(ws.req).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(e) => return extraction_failed(e),
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
+  match core::create_board(&*ws.db, quota::for_plan(&ws.cfg, billed), &user_id, board).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => match e.downcast_ref::<QuotaExceeded>() {
+      Some(exceeded) => resp::payment_required(exceeded.quota, exceeded.limit),
+      None => orgs_failed(e.as_ref(), "Не удалось создать доску."),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  }
+}
+
+/// Передаёт доску пользователю.
+///
+/// Если в запросе передан фильтр, в доске останутся только удовлетворяющие ему задачи, а если передан порядок - задачи в карточках будут упорядочены. Вместо них можно передать `view_id` сохранённого представления доски (см. `core::views`). Если передан `render: "html"`, заметки задач и подзадач передаются в виде очищенного HTML.
+pub async fn get_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let filter = match opt_entity::<BoardFilter>(&body, "filter") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
+  let sort = match opt_entity::<TaskSort>(&body, "sort") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let (filter, sort) = match opt_id(&body, "view_id") {
+    Ok(None) => (filter, sort.unwrap_or_default()),
+    Ok(Some(_)) if filter.is_some() || sort.is_some() =>
+      return resp::from_code_and_msg(400, Some("view_id нельзя передавать вместе с filter и sort.")),
+    Ok(Some(view_id)) => {
+      let db = match postgres(&*ws.db) {
+        Ok(v) => v,
+        Err(res) => return res,
+      };
+      match views::get(db, &ctx, &view_id).await {
+        Ok(v) => v,
+        Err(e) => return match e.downcast_ref::<NoSuchView>() {
+          Some(e) => resp::from_code_and_msg(404, Some(&e.to_string())),
+          None => resp::from_code_and_msg(500, Some("Не удалось получить представление.")),
+        },
+      }
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+    Err(res) => return res,
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
+  let with_profiles = match body.get("with_profiles") {
+    None => false,
+    Some(v) => match v.as_bool() {
       Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
+      None => return resp::from_code_and_msg(400, Some("with_profiles должен быть логическим значением.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
   };
-  let tag: Tag = match body.get("tag") {
-    Some(tag) => match serde_json::from_value(tag.clone()) {
-      Ok(tag) => tag,
-      _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать тег.")),
+  let render_html = match body.get("render") {
+    None => false,
+    Some(v) => match v.as_str() {
+      Some("html") => true,
+      _ => return resp::from_code_and_msg(400, Some("render может принимать только значение html.")),
     },
-    _ => return resp::from_code_and_msg(400, Some("Не получен тег.")),
   };
-  match body.get("subtask_id") {
-    Some(subtask_id) => match subtask_id.as_i64() {
-      Some(subtask_id) => match core::create_tag_at_subtask(
-        &ws.db, &board_id, &card_id, &task_id, &subtask_id, &tag
-      ).await {
-        Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
-        _ => resp::from_code_and_msg(500, Some("Не удалось прикрепить тег к подзадаче.")),
-      },
-      _ => resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
-    },
-    _ => match core::create_tag_at_task(
-      &ws.db, &board_id, &card_id, &task_id, &tag
-    ).await {
-      Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
-      _ => resp::from_code_and_msg(500, Some("Не удалось прикрепить тег к задаче.")),
-    },
+  match core::get_board(&*ws.db, ctx, filter.as_ref(), sort, with_profiles, render_html).await {
+    Ok(board) => resp::from_json(board),
+     _ => resp::from_code_and_msg(500, None),
   }
 }
 
-/// Редактирует тег в задаче/подзадаче.
-pub async fn patch_tag(ws: Workspace, user_id: i64) -> Response<Body> {
-  let patch = match extract::<JsonValue>(ws.req).await {
+/// Патчит доску, изменяя в ней определённые свойства.
+///
+/// Для доски это - title, background_color, header_background_color и header_text_color. Дочерними карточками управляют методы карточек.
+///
+/// Запрос представляет из себя JSON с id доски. Изменения принимаются только тогда, когда автором доски является данный пользователь.
+pub async fn patch_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(res) => return res,
   };
-  let board_id = match patch.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  let patch = match patch::<BoardPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  match core::apply_patch_on_board(&*ws.db, &mut ctx, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось применить патч к доске."),
+  }
+}
+
+/// Применяет к доске патч JSON Patch (RFC 6902) и передаёт изменённую доску.
+pub async fn patch_board_document(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match patch.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let operations = match entity::<Vec<Operation>>(&body, "patch") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let task_id = match patch.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  if let Err(e) = document::patch(&*ws.db, &ws.cfg, &mut ctx, &operations).await {
+    return document_failed(e.as_ref());
   };
-  let tag_id = match patch.get("tag_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("tag_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен tag_id.")),
+  match core::get_board(&*ws.db, ctx, None, TaskSort::default(), false, false).await {
+    Ok(board) => resp::from_json(board),
+    _ => resp::from_code_and_msg(500, Some("Не удалось передать доску.")),
+  }
+}
+
+/// Применяет изменения доски, сделанные клиентом без сети, и передаёт изменения доски после известной клиенту ревизии.
+///
+/// Изменения, которые не применены из-за конфликта или ошибки, описываются в результатах, а не кодом ответа.
+pub async fn sync_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match patch.get("subtask_id") {
-    Some(subtask_id) => match subtask_id.as_i64() {
-      Some(subtask_id) => match core::patch_tag_at_subtask(
-        &ws.db, &board_id, &card_id, &task_id, &subtask_id, &tag_id, &patch
-      ).await {
-        Ok(_) => resp::from_code_and_msg(200, None),
-        _ => resp::from_code_and_msg(500, Some("Не удалось изменить тег.")),
-      },
-      _ => resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
-    },
-    _ => match core::patch_tag_at_task(
-      &ws.db, &board_id, &card_id, &task_id, &tag_id, &patch
-    ).await {
-      Ok(_) => resp::from_code_and_msg(200, None),
-      _ => resp::from_code_and_msg(500, Some("Не удалось изменить тег.")),
-    },
+  let revision = match entity::<i64>(&body, "revision") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let mutations = match opt_entity::<Vec<Mutation>>(&body, "mutations") {
+    Ok(v) => v.unwrap_or_default(),
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match delta::sync(db, &ws.cfg, &mut ctx, revision, mutations).await {
+    Ok(delta) => resp::from_json(serde_json::to_vec(&delta).unwrap()),
+    Err(e) => write_failed(e.as_ref(), "Не удалось передать изменения доски."),
   }
 }
 
-/// Удаляет тег из подзадачи/задачи.
-pub async fn delete_tag(ws: Workspace, user_id: i64) -> Response<Body> {
-  let body = match extract::<JsonValue>(ws.req).await {
+/// Передаёт загрузку участников доски за период (см. `core::capacity`).
+pub async fn get_board_capacity(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
     Ok(v) => v,
-    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+    Err(res) => return res,
   };
-  let board_id = match body.get("board_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("board_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен board_id.")),
+  let from = match entity::<i64>(&body, "from") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  if core::in_shared_with(&ws.db, &user_id, &board_id).await.is_err() {
-    return resp::from_code_and_msg(500, Some("Не удалось проверить права пользователя на доску."));
+  let to = match entity::<i64>(&body, "to") {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let card_id = match body.get("card_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("card_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен card_id.")),
+  let daily_minutes = match opt_entity::<u32>(&body, "daily_minutes") {
+    Ok(v) => v.unwrap_or(capacity::DEFAULT_DAILY_MINUTES),
+    Err(res) => return res,
   };
-  let task_id = match body.get("task_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("task_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен task_id.")),
+  match capacity::plan(&ctx.board, from, to, daily_minutes) {
+    Ok(capacity) => resp::from_json(serde_json::to_vec(&capacity).unwrap()),
+    Err(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+  }
+}
+
+/// Передаёт статистику доски (см. `core::stats`).
+pub async fn get_board_stats(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  let tag_id = match body.get("tag_id") {
-    Some(v) => match v.as_i64() {
-      Some(v) => v,
-      _ => return resp::from_code_and_msg(400, Some("tag_id должен быть числом.")),
-    },
-    _ => return resp::from_code_and_msg(400, Some("Не получен tag_id.")),
+  resp::from_json(serde_json::to_vec(&stats::collect(&ctx.board, &Utc::now())).unwrap())
+}
+
+/// Предлагает исполнителей задачи по загрузке участников доски (см. `stats::suggest_executors`).
+pub async fn suggest_executors(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, _, ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
   };
-  match body.get("subtask_id") {
-    Some(subtask_id) => match subtask_id.as_i64() {
-      Some(subtask_id) => match core::delete_tag_at_subtask(
-        &ws.db, &board_id, &card_id, &task_id, &subtask_id, &tag_id
-      ).await {
-        Ok(_) => resp::from_code_and_msg(200, None),
-        _ => resp::from_code_and_msg(500, Some("Не удалось удалить тег.")),
-      },
-      _ => resp::from_code_and_msg(400, Some("subtask_id должен быть числом.")),
+  match stats::suggest_executors(&ctx.board, &task.card_id, &task.task_id, &Utc::now()) {
+    Ok(suggestions) => resp::from_json(serde_json::to_vec(&suggestions).unwrap()),
+    Err(_) => resp::from_code_and_msg(404, Some("Задача не найдена.")),
+  }
+}
+
+/// Передаёт задачи из архива задач доски (см. `core::recycle`), начиная с последних убранных.
+///
+/// Параметры тела запроса: `limit` - число задач и `before` - идентификатор записи архива, после которой начинается страница.
+pub async fn get_recycled_tasks(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let before = match opt_entity::<i64>(&body, "before") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let limit = match opt_entity::<i64>(&body, "limit") {
+    Ok(None) => 50,
+    Ok(Some(limit)) if (1..=recycle::MAX_RECYCLED_PAGE).contains(&limit) => limit,
+    _ => return resp::from_code_and_msg(400, Some(&format!("limit должен быть числом от 1 до {}.", recycle::MAX_RECYCLED_PAGE))),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match recycle::list(db, &ctx, before, limit).await {
+    Ok(tasks) => resp::from_json(serde_json::to_vec(&tasks).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить архив задач.")),
+  }
+}
+
+/// Удаляет доску.
+pub async fn delete_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::remove_board(&*ws.db, ctx).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось удалить доску.")),
+  }
+}
+
+/// Передаёт доску другому участнику доски.
+///
+/// Если передающий пользователь не автор доски или новый автор - гостевая учётная запись, возвращается код 403; если новый автор не участник доски - 400; если у нового автора нет места для доски в тарифном плане - 402; если доска в архиве - 423.
+pub async fn transfer_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let author = match id(&body, "author") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::transfer_board(&*ws.db, &ws.cfg, &mut ctx, &author).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => {
+      if let Some(e) = e.downcast_ref::<core::NotOwner>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+      if let Some(e) = e.downcast_ref::<core::NotMember>() { return resp::from_code_and_msg(400, Some(&e.to_string())); };
+      if let Some(e) = e.downcast_ref::<GuestAccount>() { return resp::from_code_and_msg(403, Some(&e.to_string())); };
+      match e.downcast_ref::<QuotaExceeded>() {
+        Some(exceeded) => resp::payment_required(exceeded.quota, exceeded.limit),
+        None => write_failed(e.as_ref(), "Не удалось передать доску."),
+      }
     },
-    _ => match core::delete_tag_at_task(
-      &ws.db, &board_id, &card_id, &task_id, &tag_id
-    ).await {
-      Ok(_) => resp::from_code_and_msg(200, None),
-      _ => resp::from_code_and_msg(500, Some("Не удалось удалить тег.")),
+  }
+}
+
+/// Удаляет пользователя из участников доски.
+///
+/// Если доску пытается покинуть её автор, возвращается код 400.
+pub async fn leave_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, _, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::leave_board(&*ws.db, &mut ctx).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<core::AuthorCannotLeave>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось покинуть доску.")),
     },
   }
 }
 
-/// Изменяет данные аутентификации пользователя.
-pub async fn patch_user_creds(_ws: Workspace, _user_id: i64) -> Response<Body> {
-  unimplemented!();
+/// Включает (`watch`) или отключает наблюдение пользователя за всеми изменениями доски.
+pub async fn watch_board(ws: Workspace, user_id: i64, watch: bool) -> Response<Body> {
+  let (_, _, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::watch_board(&*ws.db, &mut ctx, watch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось изменить наблюдение за доской.")),
+  }
+}
+
+/// Создаёт карточку в заданной доске.
+pub async fn create_card(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let card = match entity::<NewCard>(&body, "card") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::insert_card(&*ws.db, &ws.cfg, &mut ctx, card).await {
+    Ok(card_id) => resp::from_code_and_msg(200, Some(&card_id.to_string())),
+    Err(e) => match e.downcast_ref::<QuotaExceeded>() {
+      Some(exceeded) => resp::payment_required(exceeded.quota, exceeded.limit),
+      None => write_failed(e.as_ref(), "Не удалось добавить карточку."),
+    },
+  }
+}
+
+/// Патчит карточку, изменяя определённые свойства в ней.
+///
+/// Для карточки это - title, background_color, header_background_color, header_text_color, cover, theme и wip_limit.
+pub async fn patch_card(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (card, body, mut ctx) = match board_params::<CardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<CardPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::apply_patch_on_card(&*ws.db, &mut ctx, &card.card_id, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось применить патч к доске."),
+  }
+}
+
+/// Удаляет карточку.
+pub async fn delete_card(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (card, _, mut ctx) = match board_params::<CardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::remove_card(&*ws.db, &mut ctx, &card.card_id).await {
+    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить карточку.")),
+    _ => resp::from_code_and_msg(200, None),
+  }
+}
+
+/// Создаёт в карточке задачи из таблицы в формате CSV и передаёт их идентификаторы.
+///
+/// Если строки таблицы не прошли проверку, возвращается код 400 с ошибкой для каждой строки (см. `core::import`).
+pub async fn import_csv(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (card, body, mut ctx) = match board_params::<CardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let csv = match body.get("csv").and_then(|csv| csv.as_str()) {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(400, Some("Не получен csv.")),
+  };
+  match import::csv(&*ws.db, &mut ctx, &card.card_id, csv).await {
+    Ok(ids) => resp::from_json(serde_json::to_vec(&ids).unwrap()),
+    Err(e) => match e.downcast_ref::<ImportFailed>() {
+      Some(e) => resp::import_failed(&e.rows),
+      None => write_failed(e.as_ref(), "Не удалось импортировать задачи."),
+    },
+  }
+}
+
+/// Отменяет последнее удаление карточки, задачи или подзадачи, сделанное пользователем на доске.
+pub async fn undo_deletion(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, _, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let restored = core::undo::undo(db, &ws.cfg, &mut ctx).await;
+  match restored {
+    Ok(event) => resp::from_json(serde_json::to_vec(&event).unwrap()),
+    Err(e) => match (e.downcast_ref::<QuotaExceeded>(), e.downcast_ref::<NothingToUndo>(), e.downcast_ref::<CannotUndo>()) {
+      (Some(exceeded), _, _) => resp::payment_required(exceeded.quota, exceeded.limit),
+      (_, Some(e), _) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      (_, _, Some(e)) => resp::from_code_and_msg(409, Some(&e.to_string())),
+      _ => write_failed(e.as_ref(), "Не удалось отменить удаление."),
+    },
+  }
+}
+
+/// Создаёт задачу.
+pub async fn create_task(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (card, body, mut ctx) = match board_params::<CardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let task = match entity::<NewTask>(&body, "task") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::insert_task(&*ws.db, &mut ctx, &card.card_id, task).await {
+    Ok(task_id) => resp::from_code_and_msg(200, Some(&task_id.to_string())),
+    Err(e) => write_failed(e.as_ref(), "Не удалось добавить задачу."),
+  }
+}
+
+/// Добавляет задаче зависимость от другой задачи доски.
+pub async fn add_task_dependency(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let dependency = match entity::<TaskPath>(&body, "depends_on") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match dependencies::add(&*ws.db, &mut ctx, &task.card_id, &task.task_id, dependency).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<DependencyCycle>() {
+      Some(e) => resp::from_code_and_msg(409, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось добавить зависимость.")),
+    },
+  }
+}
+
+/// Удаляет у задачи зависимость от другой задачи доски.
+pub async fn delete_task_dependency(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let dependency = match entity::<TaskPath>(&body, "depends_on") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match dependencies::remove(&*ws.db, &mut ctx, &task.card_id, &task.task_id, dependency).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось удалить зависимость.")),
+  }
+}
+
+/// Добавляет задаче ссылку на внешний ресурс или заменяет существующую и возвращает идентификатор ссылки.
+pub async fn put_task_link(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let link = match entity::<Link>(&body, "link") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match links::put(&*ws.db, &mut ctx, &task.card_id, &task.task_id, link).await {
+    Ok(link_id) => resp::from_code_and_msg(200, Some(&link_id.to_string())),
+    Err(e) => match e.downcast_ref::<NoSuchLink>() {
+      Some(e) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      None => write_failed(e.as_ref(), "Не удалось сохранить ссылку."),
+    },
+  }
+}
+
+/// Удаляет ссылку задачи.
+pub async fn delete_task_link(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let link_id = match entity::<i64>(&body, "link_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match links::remove(&*ws.db, &mut ctx, &task.card_id, &task.task_id, link_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<NoSuchLink>() {
+      Some(e) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось удалить ссылку.")),
+    },
+  }
+}
+
+/// Патчит задачу.
+///
+/// В задаче можно поменять:
+/// 1. Название задачи.
+/// 2. Назначенных исполнителей задачи.
+/// 3. Статус выполнения задачи (выполнена/не выполнена).
+/// 4. Заметки к задаче.
+///
+/// Если поля, изменённые патчем, после его базовой ревизии изменил кто-то другой, возвращается код 409 с описанием конфликтов.
+pub async fn patch_task(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<TaskPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::apply_patch_on_task(&*ws.db, &mut ctx, &task.card_id, &task.task_id, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<TaskConflict>() {
+      Some(conflict) => resp::from_code_and_msg(409, Some(&json!({ "conflicts": conflict.conflicts }).to_string())),
+      None => write_failed(e.as_ref(), "Не удалось применить патч к задаче."),
+    },
+  }
+}
+
+/// Назначает исполнителей задачи.
+pub async fn patch_task_executors(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let executors = match entity::<Vec<i64>>(&body, "executors") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::set_task_executors(&*ws.db, &mut ctx, &task.card_id, &task.task_id, &executors).await {
+    Ok(assignments) => resp::from_json(serde_json::to_vec(&assignments).unwrap()),
+    Err(e) => match e.downcast_ref::<core::NotMember>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => write_failed(e.as_ref(), "Не удалось назначить исполнителей задачи."),
+    },
+  }
+}
+
+/// Включает (`watch`) или отключает наблюдение пользователя за задачей.
+///
+/// Если задачи нет, возвращается код 404.
+pub async fn watch_task(ws: Workspace, user_id: i64, watch: bool) -> Response<Body> {
+  let (task, _, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::watch_task(&*ws.db, &mut ctx, &task.card_id, &task.task_id, watch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<GetMutTaskError>() {
+      Some(_) => resp::from_code_and_msg(404, Some("Задача не найдена.")),
+      None => resp::from_code_and_msg(500, Some("Не удалось изменить наблюдение за задачей.")),
+    },
+  }
+}
+
+/// Удаляет задачу.
+pub async fn delete_task(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, _, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::remove_task(&*ws.db, &mut ctx, &task.card_id, &task.task_id).await {
+    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить задачу.")),
+    _ => resp::from_code_and_msg(200, None),
+  }
+}
+
+/// Изменяет временные рамки задачи.
+pub async fn patch_task_time(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let timelines = match entity::<Timelines>(&body, "timelines") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::set_timelines_on_task(&*ws.db, &mut ctx, &task.card_id, &task.task_id, &timelines).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось присвоить временные рамки для задачи.")),
+  }
+}
+
+/// Возвращает историю изменений задачи.
+pub async fn get_task_history(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, _, ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::get_task_history(db, &ctx, &task.card_id, &task.task_id).await {
+    Ok(history) => resp::from_json(history.into_bytes()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить историю задачи.")),
+  }
+}
+
+/// Создаёт подзадачу.
+pub async fn create_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (task, body, mut ctx) = match board_params::<TaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let subtask = match entity::<NewSubtask>(&body, "subtask") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::insert_subtask(&*ws.db, &mut ctx, &task.card_id, &task.task_id, subtask).await {
+    Ok(subtask_id) => resp::from_code_and_msg(200, Some(&subtask_id.to_string())),
+    Err(e) => write_failed(e.as_ref(), "Не удалось добавить подзадачу."),
+  }
+}
+
+/// Изменяет подзадачу.
+///
+/// В подзадаче можно поменять:
+/// 1. Название подзадачи.
+/// 2. Назначенных исполнителей подзадачи.
+/// 3. Статус выполнения подзадачи (выполнена/не выполнена).
+pub async fn patch_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (subtask, body, mut ctx) = match board_params::<SubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<SubtaskPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::apply_patch_on_subtask(
+    &*ws.db, &mut ctx, &subtask.card_id, &subtask.task_id, &subtask.subtask_id, patch
+  ).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось применить патч к подзадаче."),
+  }
+}
+
+/// Удаляет подзадачу.
+pub async fn delete_subtask(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (subtask, _, mut ctx) = match board_params::<SubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::remove_subtask(
+    &*ws.db, &mut ctx, &subtask.card_id, &subtask.task_id, &subtask.subtask_id
+  ).await {
+    Err(_) => resp::from_code_and_msg(500, Some("Не удалось удалить подзадачу.")),
+    _ => resp::from_code_and_msg(200, None),
+  }
+}
+
+/// Изменяет временные рамки подзадачи.
+pub async fn patch_subtask_time(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (subtask, body, mut ctx) = match board_params::<SubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let timelines = match entity::<Timelines>(&body, "timelines") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::set_timelines_on_subtask(
+    &*ws.db, &mut ctx, &subtask.card_id, &subtask.task_id, &subtask.subtask_id, &timelines
+  ).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось присвоить временные рамки для подзадачи.")),
+  }
+}
+
+/// Получает теги задачи/подзадачи.
+pub async fn get_tags(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (item, _, ctx) = match board_params::<TaskOrSubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match item.subtask_id {
+    Some(subtask_id) => match core::get_subtask_tags(
+      &ctx, &item.card_id, &item.task_id, &subtask_id
+    ) {
+      Ok(tags) => resp::from_json(tags.into_bytes()),
+      _ => resp::from_code_and_msg(500, Some("Не удалось получить теги подзадачи.")),
+    },
+    None => match core::get_task_tags(&ctx, &item.card_id, &item.task_id) {
+      Ok(tags) => resp::from_json(tags.into_bytes()),
+      _ => resp::from_code_and_msg(500, Some("Не удалось получить теги задачи.")),
+    },
+  }
+}
+
+/// Прикрепляет тег из словаря доски к задаче/подзадаче.
+pub async fn attach_tag(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (item, body, mut ctx) = match board_params::<TaskOrSubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let tag_id = match id(&body, "tag_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match item.subtask_id {
+    Some(subtask_id) => match core::attach_tag_to_subtask(
+      &*ws.db, &mut ctx, &item.card_id, &item.task_id, &subtask_id, &tag_id
+    ).await {
+      Ok(_) => resp::from_code_and_msg(200, None),
+      _ => resp::from_code_and_msg(500, Some("Не удалось прикрепить тег к подзадаче.")),
+    },
+    None => match core::attach_tag_to_task(
+      &*ws.db, &mut ctx, &item.card_id, &item.task_id, &tag_id
+    ).await {
+      Ok(_) => resp::from_code_and_msg(200, None),
+      _ => resp::from_code_and_msg(500, Some("Не удалось прикрепить тег к задаче.")),
+    },
+  }
+}
+
+/// Открепляет тег от подзадачи/задачи.
+pub async fn detach_tag(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (item, body, mut ctx) = match board_params::<TaskOrSubtaskRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let tag_id = match id(&body, "tag_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match item.subtask_id {
+    Some(subtask_id) => match core::detach_tag_from_subtask(
+      &*ws.db, &mut ctx, &item.card_id, &item.task_id, &subtask_id, &tag_id
+    ).await {
+      Ok(_) => resp::from_code_and_msg(200, None),
+      _ => resp::from_code_and_msg(500, Some("Не удалось открепить тег.")),
+    },
+    None => match core::detach_tag_from_task(
+      &*ws.db, &mut ctx, &item.card_id, &item.task_id, &tag_id
+    ).await {
+      Ok(_) => resp::from_code_and_msg(200, None),
+      _ => resp::from_code_and_msg(500, Some("Не удалось открепить тег.")),
+    },
+  }
+}
+
+/// Создаёт тег в словаре доски.
+pub async fn create_board_tag(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let tag = match entity::<Tag>(&body, "tag") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::create_board_tag(&*ws.db, &mut ctx, &tag).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => write_failed(e.as_ref(), "Не удалось создать тег."),
+  }
+}
+
+/// Редактирует тег в словаре доски.
+pub async fn patch_board_tag(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (tag, body, mut ctx) = match board_params::<BoardTagRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<TagPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::patch_board_tag(&*ws.db, &mut ctx, &tag.tag_id, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось изменить тег."),
+  }
+}
+
+/// Удаляет тег из словаря доски, а также из всех задач и подзадач.
+pub async fn delete_board_tag(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (tag, _, mut ctx) = match board_params::<BoardTagRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::delete_board_tag(&*ws.db, &mut ctx, &tag.tag_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось удалить тег.")),
+  }
+}
+
+/// Создаёт дорожку доски.
+pub async fn create_board_lane(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let lane = match entity::<Lane>(&body, "lane") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::create_board_lane(&*ws.db, &mut ctx, &lane).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => write_failed(e.as_ref(), "Не удалось создать дорожку."),
+  }
+}
+
+/// Редактирует дорожку доски.
+pub async fn patch_board_lane(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (lane, body, mut ctx) = match board_params::<BoardLaneRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<LanePatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::patch_board_lane(&*ws.db, &mut ctx, &lane.lane_id, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось изменить дорожку."),
+  }
+}
+
+/// Удаляет дорожку доски, убирая из неё все задачи.
+pub async fn delete_board_lane(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (lane, _, mut ctx) = match board_params::<BoardLaneRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::delete_board_lane(&*ws.db, &mut ctx, &lane.lane_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось удалить дорожку.")),
+  }
+}
+
+/// Создаёт спринт доски.
+pub async fn create_board_sprint(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, mut ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let sprint = match entity::<Sprint>(&body, "sprint") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match sprints::create(&*ws.db, &mut ctx, &sprint).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => write_failed(e.as_ref(), "Не удалось создать спринт."),
+  }
+}
+
+/// Редактирует спринт доски.
+pub async fn patch_board_sprint(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (sprint, body, mut ctx) = match board_params::<BoardSprintRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<SprintPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match sprints::patch(&*ws.db, &mut ctx, &sprint.sprint_id, patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось изменить спринт."),
+  }
+}
+
+/// Удаляет спринт доски, убирая из него все задачи.
+pub async fn delete_board_sprint(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (sprint, _, mut ctx) = match board_params::<BoardSprintRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match sprints::delete(&*ws.db, &mut ctx, &sprint.sprint_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => write_failed(e.as_ref(), "Не удалось удалить спринт."),
+  }
+}
+
+/// Возвращает диаграмму сгорания спринта.
+pub async fn get_sprint_report(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (sprint, body, ctx) = match board_params::<BoardSprintRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let unit = match opt_entity::<Unit>(&body, "unit") {
+    Ok(v) => v.unwrap_or_default(),
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match sprints::report(db, &ctx, &sprint.sprint_id, unit).await {
+    Ok(report) => resp::from_json(serde_json::to_vec(&report).unwrap()),
+    Err(e) => write_failed(e.as_ref(), "Не удалось построить диаграмму сгорания спринта."),
+  }
+}
+
+/// Сохраняет представление доски - фильтр и порядок задач - и возвращает его идентификатор.
+pub async fn put_board_view(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let view = match entity::<BoardView>(&body, "view") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match views::save(db, &ctx, view).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => match (e.downcast_ref::<NoSuchView>(), e.downcast_ref::<TooManyViews>()) {
+      (Some(e), _) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      (_, Some(e)) => resp::from_code_and_msg(409, Some(&e.to_string())),
+      _ => write_failed(e.as_ref(), "Не удалось сохранить представление."),
+    },
+  }
+}
+
+/// Передаёт представления доски, сохранённые пользователем.
+pub async fn get_board_views(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match views::list(db, &ctx).await {
+    Ok(views) => resp::from_json(serde_json::to_vec(&views).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить представления.")),
+  }
+}
+
+/// Удаляет представление доски.
+pub async fn delete_board_view(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let view_id = match id(&body, "view_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match views::delete(db, &ctx, &view_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<NoSuchView>() {
+      Some(e) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось удалить представление.")),
+    },
+  }
+}
+
+/// Сохраняет отчёт о доске (см. `core::reports`) и передаёт его идентификатор.
+pub async fn put_board_report(ws: Workspace, user_id: i64) -> Response<Body> {
+  let cfg = match ws.cfg.reports {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(404, Some("Отчёты о досках не настроены.")),
+  };
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let report = match entity::<BoardReport>(&body, "report") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match reports::save(db, &cfg, &ctx, report).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => reports_failed(e.as_ref(), "Не удалось сохранить отчёт."),
+  }
+}
+
+/// Передаёт отчёты о доске.
+pub async fn get_board_reports(ws: Workspace, user_id: i64) -> Response<Body> {
+  if ws.cfg.reports.is_none() { return resp::from_code_and_msg(404, Some("Отчёты о досках не настроены.")); };
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match reports::list(db, &ctx).await {
+    Ok(reports) => resp::from_json(serde_json::to_vec(&reports).unwrap()),
+    Err(e) => reports_failed(e.as_ref(), "Не удалось получить отчёты."),
+  }
+}
+
+/// Удаляет отчёт о доске.
+pub async fn delete_board_report(ws: Workspace, user_id: i64) -> Response<Body> {
+  if ws.cfg.reports.is_none() { return resp::from_code_and_msg(404, Some("Отчёты о досках не настроены.")); };
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let report_id = match id(&body, "report_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match reports::delete(db, &ctx, &report_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => reports_failed(e.as_ref(), "Не удалось удалить отчёт."),
+  }
+}
+
+/// Продлевает хранение доски и возвращает её из архива (см. `core::retention`).
+///
+/// Доска загружается без `board_params`, поскольку метод должен работать и с доской в архиве. Если пользователь не автор доски, возвращается код 403.
+pub async fn keep_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (board, _) = match params::<BoardRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let ctx = match core::load_board(&*ws.db, &user_id, &board.board_id).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Данная доска вам недоступна.")),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match retention::keep(db, &ctx).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<retention::NotAuthor>() {
+      Some(e) => resp::from_code_and_msg(403, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось продлить хранение доски.")),
+    },
+  }
+}
+
+/// Переносит доску в архив или, если в теле запроса передан `archived: false`, возвращает её из архива.
+///
+/// Доска в архиве доступна только для изменения этим методом, поэтому доска загружается без `board_params`. Если пользователь не автор доски, возвращается код 403.
+pub async fn archive_board(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (board, body) = match params::<BoardRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let archived = match opt_entity::<bool>(&body, "archived") {
+    Ok(v) => v.unwrap_or(true),
+    Err(res) => return res,
+  };
+  if ws.db.lock_board(&board.board_id).await.is_err() {
+    return resp::from_code_and_msg(500, Some("Не удалось заблокировать доску для изменения."));
+  };
+  let ctx = match core::load_board(&*ws.db, &user_id, &board.board_id).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(401, Some("Данная доска вам недоступна.")),
+  };
+  match core::archive_board(&*ws.db, &ctx, archived).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<core::NotArchiver>() {
+      Some(e) => resp::from_code_and_msg(403, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось перенести доску в архив.")),
+    },
+  }
+}
+
+/// Отдаёт выгрузки досок пользователя, удалённых за неактивностью.
+pub async fn get_board_exports(ws: Workspace, user_id: i64) -> Response<Body> {
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match retention::exports(db, &user_id).await {
+    Ok(exports) => resp::from_json(serde_json::to_vec(&exports).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить выгрузки досок.")),
+  }
+}
+
+/// Отдаёт содержимое выгрузки доски, номер которой передан в параметре `export_id` строки запроса.
+pub async fn get_board_export(ws: Workspace, user_id: i64) -> Response<Body> {
+  let export_id = match opt_query_id(&ws.req, "export_id") {
+    Ok(Some(v)) => v,
+    Ok(None) => return resp::from_code_and_msg(400, Some("Не получен export_id.")),
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match retention::export(db, &user_id, &export_id).await {
+    Ok(document) => resp::from_json(document.into_bytes()),
+    Err(e) => match e.downcast_ref::<NoSuchExport>() {
+      Some(e) => resp::from_code_and_msg(404, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось получить выгрузку доски.")),
+    },
+  }
+}
+
+/// Создаёт организацию и передаёт её идентификатор.
+pub async fn create_org(ws: Workspace, user_id: i64) -> Response<Body> {
+  let body = match extract::<JsonValue>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  let name = match entity::<String>(&body, "name") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::create(db, &user_id, &name).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось создать организацию."),
+  }
+}
+
+/// Передаёт организацию вместе с её участниками и досками.
+pub async fn get_org(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, _) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::get(db, &user_id, &org.org_id).await {
+    Ok(org) => resp::from_json(serde_json::to_vec(&org).unwrap()),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось получить организацию."),
+  }
+}
+
+/// Передаёт организации, в которых состоит пользователь.
+pub async fn list_orgs(ws: Workspace, user_id: i64) -> Response<Body> {
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::list(db, &user_id).await {
+    Ok(orgs) => resp::from_json(serde_json::to_vec(&orgs).unwrap()),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось получить организации."),
+  }
+}
+
+/// Изменяет название и ограничения организации.
+pub async fn patch_org(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let org_patch = match patch::<OrgPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::patch(db, &user_id, &org.org_id, &org_patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось изменить организацию."),
+  }
+}
+
+/// Удаляет организацию.
+pub async fn delete_org(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, _) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::delete(db, &user_id, &org.org_id).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось удалить организацию."),
+  }
+}
+
+/// Добавляет пользователя в организацию или изменяет его роль и передаёт идентификатор пользователя.
+pub async fn put_org_member(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let login = match entity::<String>(&body, "login") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let role = match opt_entity::<OrgRole>(&body, "role") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::put_member(db, &user_id, &org.org_id, &login, role).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось добавить участника организации."),
+  }
+}
+
+/// Создаёт гостевую учётную запись в организации и передаёт идентификатор пользователя.
+///
+/// Логин и пароль гостя должны соответствовать тем же требованиям, что и при регистрации.
+pub async fn create_org_guest(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let (login, pass) = match (entity::<String>(&body, "login"), entity::<String>(&body, "pass")) {
+    (Ok(login), Ok(pass)) => (login, pass),
+    (Err(res), _) | (_, Err(res)) => return res,
+  };
+  if let Err(e) = policy::validate(&ws.cfg.credentials_policy, &login, true, Some(&pass)) {
+    return resp::validation_failed(&e.violations);
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::create_guest(db, &ws.cfg, &user_id, &org.org_id, &login, &pass).await {
+    Ok(id) => resp::from_code_and_msg(200, Some(&id.to_string())),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось создать гостевую учётную запись."),
+  }
+}
+
+/// Удаляет пользователя из организации.
+pub async fn delete_org_member(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let member = match id(&body, "user_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::remove_member(db, &user_id, &org.org_id, &member).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось удалить участника организации."),
+  }
+}
+
+/// Передаёт доску организации (`add`) или исключает её из организации.
+pub async fn org_board(ws: Workspace, user_id: i64, add: bool) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let board_id = match id(&body, "board_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let res = match add {
+    true => orgs::add_board(db, &user_id, &org.org_id, &board_id).await,
+    false => orgs::remove_board(db, &user_id, &org.org_id, &board_id).await,
+  };
+  match res {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось изменить доски организации."),
+  }
+}
+
+/// Делится доской организации с гостем (`share`) или закрывает ему доступ к доске.
+pub async fn share_org_board(ws: Workspace, user_id: i64, share: bool) -> Response<Body> {
+  let (org, body) = match params::<OrgRef>(ws.req).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let (board_id, guest) = match (id(&body, "board_id"), id(&body, "user_id")) {
+    (Ok(board_id), Ok(guest)) => (board_id, guest),
+    (Err(res), _) | (_, Err(res)) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match orgs::share_board(db, &user_id, &org.org_id, &board_id, &guest, share).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => orgs_failed(e.as_ref(), "Не удалось изменить доступ гостя к доске."),
+  }
+}
+
+/// Связывает доску с репозиторием GitHub и передаёт связь.
+pub async fn put_board_github(ws: Workspace, user_id: i64) -> Response<Body> {
+  let cfg = match ws.cfg.github {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(404, Some("Интеграция с GitHub не настроена.")),
+  };
+  let (_, body, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let card_id = match id(&body, "card_id") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let (repo, token) = match (body.get("repo").and_then(|v| v.as_str()), body.get("token").and_then(|v| v.as_str())) {
+    (Some(repo), Some(token)) => (repo, token),
+    (None, _) => return resp::from_code_and_msg(400, Some("Не получен repo.")),
+    (_, None) => return resp::from_code_and_msg(400, Some("Не получен token.")),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match github::link(db, &cfg, &ctx, repo, token, &card_id).await {
+    Ok(link) => resp::from_json(serde_json::to_vec(&link).unwrap()),
+    Err(e) => github_failed(e.as_ref(), "Не удалось связать доску с репозиторием."),
+  }
+}
+
+/// Передаёт связь доски с репозиторием GitHub.
+pub async fn get_board_github(ws: Workspace, user_id: i64) -> Response<Body> {
+  if ws.cfg.github.is_none() { return resp::from_code_and_msg(404, Some("Интеграция с GitHub не настроена.")); };
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match github::get(db, &ctx).await {
+    Ok(link) => resp::from_json(serde_json::to_vec(&link).unwrap()),
+    Err(e) => github_failed(e.as_ref(), "Не удалось получить связь доски с репозиторием."),
+  }
+}
+
+/// Удаляет связь доски с репозиторием GitHub.
+pub async fn delete_board_github(ws: Workspace, user_id: i64) -> Response<Body> {
+  if ws.cfg.github.is_none() { return resp::from_code_and_msg(404, Some("Интеграция с GitHub не настроена.")); };
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match github::unlink(db, &ctx).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => github_failed(e.as_ref(), "Не удалось удалить связь доски с репозиторием."),
+  }
+}
+
+/// Синхронизирует доску с репозиторием GitHub, не дожидаясь фоновой синхронизации, и передаёт число созданных и изменённых задач.
+pub async fn sync_board_github(ws: Workspace, user_id: i64) -> Response<Body> {
+  let cfg = match ws.cfg.github {
+    Some(v) => v,
+    None => return resp::from_code_and_msg(404, Some("Интеграция с GitHub не настроена.")),
+  };
+  let (_, _, ctx) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  if let Err(e) = github::get(db, &ctx).await {
+    return github_failed(e.as_ref(), "Не удалось получить связь доски с репозиторием.");
+  };
+  match github::sync(db, &cfg, &ctx.board.id).await {
+    Ok(changed) => resp::from_code_and_msg(200, Some(&changed.to_string())),
+    Err(e) => github_failed(e.as_ref(), "Не удалось синхронизировать доску с репозиторием."),
+  }
+}
+
+/// Изменяет данные аутентификации пользователя.
+pub async fn patch_user_creds(ws: Workspace, user_id: i64) -> Response<Body> {
+  let patch = match extract::<CredentialsPatch>(ws.req).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(400, Some("Не удалось десериализовать данные.")),
+  };
+  if let Err(e) = core::patch_user_creds(&*ws.db, &ws.cfg, &user_id, &patch).await {
+    if let Some(e) = e.downcast_ref::<PolicyViolations>() { return resp::validation_failed(&e.violations); };
+    if let Some(e) = e.downcast_ref::<core::WrongPassword>() { return resp::from_code_and_msg(401, Some(&e.to_string())); };
+    if let Some(e) = e.downcast_ref::<core::LoginTaken>() { return resp::from_code_and_msg(409, Some(&e.to_string())); };
+    return resp::from_code_and_msg(500, Some("Не удалось изменить учётные данные."));
+  };
+  resp::from_code_and_msg(200, None)
+}
+
+/// Отдаёт действующие сеансы пользователя вместе со сведениями о клиентах, получивших их токены.
+pub async fn list_sessions(ws: Workspace, user_id: i64) -> Response<Body> {
+  match core::list_sessions(&*ws.db, &user_id, &ws.cfg).await {
+    Ok(sessions) => resp::from_json(serde_json::to_vec(&sessions).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить список сеансов.")),
+  }
+}
+
+/// Отдаёт журнал входов в аккаунт пользователя.
+pub async fn get_security_events(ws: Workspace, user_id: i64) -> Response<Body> {
+  let before = match opt_query_id(&ws.req, "before") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let limit = match opt_query_id(&ws.req, "limit") {
+    Ok(None) => 50,
+    Ok(Some(limit)) if (1..=security_events::MAX_SECURITY_EVENTS_PAGE).contains(&limit) => limit,
+    _ => return resp::from_code_and_msg(
+      400, Some(&format!("limit должен быть числом от 1 до {}.", security_events::MAX_SECURITY_EVENTS_PAGE))
+    ),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match security_events::list(db, &user_id, before, limit).await {
+    Ok(events) => resp::from_json(serde_json::to_vec(&events).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить журнал входов.")),
+  }
+}
+
+/// Отдаёт ограничения тарифного плана пользователя и число созданных им досок.
+pub async fn get_quota(ws: Workspace, user_id: i64, billed: bool) -> Response<Body> {
+  let boards = match core::count_boards(&*ws.db, &user_id).await {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(500, Some("Невозможно сосчитать число имеющихся досок у пользователя.")),
+  };
+  let body = serde_json::json!({
+    "billed": billed,
+    "quota": quota::for_plan(&ws.cfg, billed),
+    "usage": { "boards": boards },
+  });
+  resp::from_json(body.to_string().into_bytes())
+}
+
+/// Изменяет настройки доски, заданные пользователем.
+pub async fn patch_board_prefs(ws: Workspace, user_id: i64) -> Response<Body> {
+  let (board, body, _) = match board_params::<BoardRef>(ws.req, &*ws.db, &user_id).await {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let patch = match patch::<BoardPrefsPatch>(&body) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match core::set_board_prefs(&*ws.db, &user_id, &board.board_id, &patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось изменить настройки доски.")),
+  }
+}
+
+/// Возвращает настройки уведомлений пользователя по электронной почте.
+pub async fn get_notification_prefs(ws: Workspace, user_id: i64) -> Response<Body> {
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match digest::prefs(db, &user_id).await {
+    Ok(prefs) => resp::from_json(serde_json::to_vec(&prefs).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить настройки уведомлений.")),
+  }
+}
+
+/// Изменяет настройки уведомлений пользователя по электронной почте.
+pub async fn patch_notification_prefs(ws: Workspace, user_id: i64) -> Response<Body> {
+  let patch = match extract::<NotificationPrefsPatch>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match digest::set_prefs(db, &user_id, &patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    Err(e) => match e.downcast_ref::<WrongEmail>() {
+      Some(e) => resp::from_code_and_msg(400, Some(&e.to_string())),
+      None => resp::from_code_and_msg(500, Some("Не удалось изменить настройки уведомлений.")),
+    },
+  }
+}
+
+/// Изменяет публичный профиль пользователя.
+pub async fn patch_user_profile(ws: Workspace, user_id: i64) -> Response<Body> {
+  let patch = match extract::<ProfilePatch>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  match core::apply_patch_on_profile(&*ws.db, &user_id, &patch).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(400, Some("Не удалось изменить профиль.")),
+  }
+}
+
+/// Выдаёт подписанную ссылку на метод чтения, по которой его можно вызвать без заголовка `App-Token` (см. `sec::signing`).
+///
+/// Если срок действия не задан, ссылка действует `signing::DEFAULT_TTL_SECS` секунд.
+pub async fn create_signed_url(ws: Workspace, user_id: i64) -> Response<Body> {
+  let request = match extract::<SignedUrlRequest>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  if !request.path.starts_with('/') {
+    return resp::from_code_and_msg(400, Some("Путь должен начинаться с /."));
+  };
+  let ttl_secs = match request.ttl_secs {
+    None => signing::DEFAULT_TTL_SECS,
+    Some(ttl_secs) if (1..=signing::MAX_TTL_SECS).contains(&ttl_secs) => ttl_secs,
+    _ => return resp::from_code_and_msg(400, Some(&format!("ttl_secs должен быть числом от 1 до {}.", signing::MAX_TTL_SECS))),
+  };
+  let expires_at = Utc::now().timestamp() + ttl_secs;
+  let url = signing::sign(&ws.cfg.admin_key, user_id, &request.path, expires_at);
+  resp::from_json(json!({ "url": url, "expires_at": expires_at }).to_string().into_bytes())
+}
+
+/// Возвращает уведомления пользователя, начиная с последних, и число непрочитанных уведомлений.
+///
+/// Параметры строки запроса: `unread=true` - только непрочитанные уведомления, `limit` - число уведомлений и `before` - идентификатор уведомления, после которого начинается страница.
+pub async fn get_notifications(ws: Workspace, user_id: i64) -> Response<Body> {
+  let unread_only = query_param(&ws.req, "unread") == Some("true");
+  let before = match opt_query_id(&ws.req, "before") {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  let limit = match opt_query_id(&ws.req, "limit") {
+    Ok(None) => 50,
+    Ok(Some(limit)) if (1..=notifications::MAX_NOTIFICATIONS_PAGE).contains(&limit) => limit,
+    _ => return resp::from_code_and_msg(
+      400, Some(&format!("limit должен быть числом от 1 до {}.", notifications::MAX_NOTIFICATIONS_PAGE))
+    ),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match notifications::list(db, &user_id, unread_only, before, limit).await {
+    Ok((unread, notifications)) => resp::from_json(json!({ "unread": unread, "notifications": notifications }).to_string().into_bytes()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить уведомления.")),
+  }
+}
+
+/// Отмечает уведомления пользователя прочитанными.
+pub async fn read_notifications(ws: Workspace, user_id: i64) -> Response<Body> {
+  let read = match extract::<NotificationsRead>(ws.req).await {
+    Ok(v) => v,
+    Err(e) => return extraction_failed(e),
+  };
+  let db = match postgres(&*ws.db) {
+    Ok(v) => v,
+    Err(res) => return res,
+  };
+  match notifications::mark_read(db, &user_id, read.ids.as_deref()).await {
+    Ok(_) => resp::from_code_and_msg(200, None),
+    _ => resp::from_code_and_msg(500, Some("Не удалось отметить уведомления прочитанными.")),
+  }
+}
+
+/// Возвращает публичные профили пользователей.
+///
+/// Идентификаторы передаются в строке запроса через запятую: `?ids=1,2,3`.
+pub async fn resolve_users(ws: Workspace) -> Response<Body> {
+  let ids = query_param(&ws.req, "ids").unwrap_or("");
+  let ids = match ids.split(',').filter(|id| !id.is_empty()).map(|id| id.parse::<i64>()).collect::<Result<Vec<i64>, _>>() {
+    Ok(v) => v,
+    _ => return resp::from_code_and_msg(400, Some("ids должны быть числами.")),
+  };
+  if ids.len() > core::MAX_RESOLVED_PROFILES {
+    return resp::from_code_and_msg(400, Some("Запрошено слишком много профилей."));
+  };
+  match core::get_profiles(&*ws.db, &ids).await {
+    Ok(profiles) => resp::from_json(serde_json::to_vec(&profiles).unwrap()),
+    _ => resp::from_code_and_msg(500, Some("Не удалось получить профили.")),
+  }
 }
 
 /// Изменяет способы оплаты аккаунта пользователя.
@@ -0,0 +1,568 @@
+//! Отвечает за проверку тел запросов и ответов по схемам методов.
+//!
+//! Схемы не пишутся вручную, а строятся по типам, в которые обработчики разбирают тело запроса (`TaskPatch`, `NewCard` и т.д.): `of` проходит по реализации `Deserialize` типа и записывает, какие поля он принимает и какого они вида. Получается подмножество JSON Schema: типы значений, поля объектов, допустимые значения перечислений и значения `null`. Обязательность полей схема не описывает - отсутствующие поля по-прежнему находит сам обработчик.
+//!
+//! Тело запроса к методу из `routes` проверяется до вызова обработчика. Схема запрещает поля, которых не принимает ни ссылка на сущность, ни её патч, поэтому опечатка в названии поля патча отклоняется с кодом 400, а не игнорируется молча. В отладочной сборке так же проверяются ответы методов, которые клиенты разбирают в типы сервера (см. клиент `cc-taskboard-client`), чтобы расхождение ответа с типом обнаруживалось в тестах, а не у клиентов.
+//!
+//! Значения, вид которых по `Deserialize` не определить (поля `JsonValue`, перечисления без внешнего тега), схема принимает любыми - их проверяет обработчик.
+
+use hyper::{Body, Method};
+use hyper::http::{Request, Response};
+use serde::Serialize;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, Visitor};
+use serde::de::value::{BorrowedStrDeserializer, Error as TraceError};
+use serde_json::{json, Map, Value as JsonValue};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use crate::core::json_patch::Operation;
+use crate::hyper_router::{extractors::extraction_failed, resp};
+use crate::model::{
+  extract, is_msgpack, Board, BoardPatch, BoardPrefsPatch, BoardReport, BoardView, CardPatch, Lane, LanePatch, Link, NewBoard, NewCard,
+  NewSubtask, NewTask, NotificationPrefsPatch, NotificationsRead, OrgPatch, ProfilePatch, Sprint, SprintPatch, SubtaskPatch, Tag, TagPatch, TaskPatch,
+  Timelines, ExtractionError, MAX_BODY_LEN
+};
+use crate::sec::auth::TokenAuth;
+
+/// Наибольшее число нарушений схемы, которое перечисляется в ответе.
+const MAX_VIOLATIONS: usize = 20;
+
+/// Наибольшая вложенность, до которой `of` проходит по типу. Глубже значения принимаются любыми.
+const MAX_TRACE_DEPTH: usize = 16;
+
+/// Схема значения JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schema {
+  /// Любое значение.
+  Any,
+  Null,
+  Boolean,
+  /// Целое число не меньше `minimum`, если он задан.
+  Integer { minimum: Option<i64> },
+  Number,
+  String,
+  /// Массив значений одной схемы.
+  Array(Box<Schema>),
+  /// Объект с данными полями. Если `additional` не задан, других полей в объекте быть не может; иначе они должны соответствовать `additional`.
+  Object { properties: Vec<(&'static str, Schema)>, additional: Option<Box<Schema>> },
+  /// Одна из строк.
+  Enum(&'static [&'static str]),
+  /// Значение схемы или `null`.
+  Nullable(Box<Schema>),
+}
+
+/// Нарушение схемы.
+#[derive(Clone, Debug, Serialize)]
+pub struct SchemaViolation {
+  /// Путь к значению в виде JSON Pointer (RFC 6901), например `/task/title`.
+  pub path: String,
+  pub message: String,
+}
+
+impl Schema {
+  /// Возвращает схему в виде JSON Schema.
+  pub fn to_json(&self) -> JsonValue {
+    match self {
+      Schema::Any => json!({}),
+      Schema::Null => json!({ "type": "null" }),
+      Schema::Boolean => json!({ "type": "boolean" }),
+      Schema::Integer { minimum: Some(minimum) } => json!({ "type": "integer", "minimum": minimum }),
+      Schema::Integer { minimum: None } => json!({ "type": "integer" }),
+      Schema::Number => json!({ "type": "number" }),
+      Schema::String => json!({ "type": "string" }),
+      Schema::Array(items) => json!({ "type": "array", "items": items.to_json() }),
+      Schema::Object { properties, additional } => {
+        let properties: Map<String, JsonValue> = properties.iter().map(|(name, schema)| (name.to_string(), schema.to_json())).collect();
+        let additional = additional.as_ref().map_or(JsonValue::Bool(false), |schema| schema.to_json());
+        json!({ "type": "object", "properties": properties, "additionalProperties": additional })
+      },
+      Schema::Enum(values) => json!({ "type": "string", "enum": values }),
+      Schema::Nullable(schema) => json!({ "anyOf": [schema.to_json(), { "type": "null" }] }),
+    }
+  }
+
+  /// Проверяет значение и возвращает найденные нарушения (не больше `MAX_VIOLATIONS`).
+  pub fn validate(&self, value: &JsonValue) -> Vec<SchemaViolation> {
+    let mut violations = vec![];
+    self.check(value, &mut String::new(), &mut violations);
+    violations
+  }
+
+  fn check(&self, value: &JsonValue, path: &mut String, violations: &mut Vec<SchemaViolation>) {
+    if violations.len() >= MAX_VIOLATIONS { return; };
+    let mut violation = |message: String| violations.push(SchemaViolation { path: path.clone(), message });
+    match (self, value) {
+      (Schema::Any, _) | (Schema::Null, JsonValue::Null) | (Schema::Boolean, JsonValue::Bool(_)) | (Schema::Nullable(_), JsonValue::Null) => {},
+      (Schema::Number, JsonValue::Number(_)) | (Schema::String, JsonValue::String(_)) => {},
+      (Schema::Integer { minimum }, JsonValue::Number(n)) if n.is_i64() || n.is_u64() => match (minimum, n.as_i64()) {
+        (Some(minimum), Some(n)) if n < *minimum => violation(format!("значение должно быть не меньше {}", minimum)),
+        _ => {},
+      },
+      (Schema::Enum(values), JsonValue::String(s)) => if !values.contains(&s.as_str()) {
+        violation(format!("допустимые значения: {}", values.join(", ")));
+      },
+      (Schema::Nullable(schema), value) => schema.check(value, path, violations),
+      (Schema::Array(items), JsonValue::Array(values)) => for (i, value) in values.iter().enumerate() {
+        let len = path.len();
+        path.push_str(&format!("/{}", i));
+        items.check(value, path, violations);
+        path.truncate(len);
+      },
+      (Schema::Object { properties, additional }, JsonValue::Object(fields)) => for (name, value) in fields {
+        let len = path.len();
+        path.push('/');
+        path.push_str(&name.replace('~', "~0").replace('/', "~1"));
+        match (properties.iter().find(|(property, _)| property == name), additional) {
+          (Some((_, schema)), _) => schema.check(value, path, violations),
+          (None, Some(schema)) => schema.check(value, path, violations),
+          (None, None) => if violations.len() < MAX_VIOLATIONS {
+            violations.push(SchemaViolation { path: path.clone(), message: String::from("поле не предусмотрено") });
+          },
+        };
+        path.truncate(len);
+      },
+      (schema, _) => violation(format!("ожидается {}", schema.kind())),
+    }
+  }
+
+  /// Возвращает название вида значений схемы для сообщений о нарушениях.
+  fn kind(&self) -> &'static str {
+    match self {
+      Schema::Any => "любое значение",
+      Schema::Null => "null",
+      Schema::Boolean => "логическое значение",
+      Schema::Integer { .. } => "целое число",
+      Schema::Number => "число",
+      Schema::String | Schema::Enum(_) => "строка",
+      Schema::Array(_) => "массив",
+      Schema::Object { .. } => "объект",
+      Schema::Nullable(schema) => schema.kind(),
+    }
+  }
+}
+
+/// Строит схему значений, которые принимает тип `T`.
+///
+/// Тип разбирается из подставных значений, а схема записывается по тому, какие значения запрашивает его `Deserialize`. Если подставное значение не подходит типу (например, перечислению без внешнего тега), проход повторяется, а значение по этому пути принимается любым: поле `Option` получает `null`, остальные поля пропускаются. Если без пропущенного поля тип не разбирается, любым принимается уже весь объект.
+pub fn of<T: DeserializeOwned>() -> Schema {
+  let trace = RefCell::new(Trace::default());
+  loop {
+    let mut schema = Schema::Any;
+    trace.borrow_mut().failed = None;
+    let res = T::deserialize(Tracer { trace: &trace, path: String::new(), slot: &mut schema, depth: 0 });
+    let failed = trace.borrow_mut().failed.take();
+    let mut trace = trace.borrow_mut();
+    match (res, failed) {
+      (Ok(_), _) => return schema,
+      // Корень не удаётся разобрать даже с пропусками: остаётся схема, записанная до ошибки.
+      (Err(_), None) => return trace.partial.remove("").unwrap_or(Schema::Any),
+      (Err(_), Some(path)) if path.is_empty() => return trace.partial.remove("").unwrap_or(Schema::Any),
+      (Err(_), Some(path)) => if !trace.opaque.insert(path.clone()) && !trace.skipped.insert(path) {
+        return trace.partial.remove("").unwrap_or(Schema::Any);
+      },
+    };
+  }
+}
+
+/// Состояние прохода по типу, общее для всех уровней вложенности.
+#[derive(Default)]
+struct Trace {
+  /// Пути, значения по которым принимаются любыми.
+  opaque: HashSet<String>,
+  /// Пути полей, которые не передаются типу.
+  skipped: HashSet<String>,
+  /// Схемы структур, которые не удалось разобрать, по путям. Такая структура принимается объектом с уже записанными полями: например, структура с обязательным полем-перечислением без внешнего тега получает схемы всех полей, кроме него.
+  partial: HashMap<String, Schema>,
+  /// Самый глубокий путь, на котором тип не принял подставное значение в текущем проходе.
+  failed: Option<String>,
+}
+
+/// Подставляет значения типу и записывает его схему в `slot`.
+struct Tracer<'t, 's> {
+  trace: &'t RefCell<Trace>,
+  path: String,
+  slot: &'s mut Schema,
+  depth: usize,
+}
+
+impl<'t> Tracer<'t, '_> {
+  /// Разбирает значение вложенного пути и запоминает путь, если тип не принял подставное значение.
+  fn nested<'de, S: DeserializeSeed<'de>>(trace: &'t RefCell<Trace>, path: String, slot: &mut Schema, depth: usize, seed: S)
+    -> Result<S::Value, TraceError>
+  {
+    let res = seed.deserialize(Tracer { trace, path: path.clone(), slot, depth });
+    if res.is_err() { trace.borrow_mut().failed.get_or_insert(path); };
+    res
+  }
+
+  fn is_opaque(&self) -> bool {
+    self.depth > MAX_TRACE_DEPTH || self.trace.borrow().opaque.contains(&self.path)
+  }
+
+  /// Записывает схему значения, если путь не принимает любые значения, и возвращает ошибку для такого пути.
+  fn record(self, schema: Schema) -> Result<(), TraceError> {
+    match self.is_opaque() {
+      true => Err(de::Error::custom("значение принимается любым")),
+      false => {
+        *self.slot = schema;
+        Ok(())
+      },
+    }
+  }
+}
+
+macro_rules! trace_scalar {
+  ($($method:ident => $schema:expr, $visit:ident($($value:expr)?);)*) => {
+    $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+      self.record($schema)?;
+      visitor.$visit($($value)?)
+    })*
+  };
+}
+
+impl<'de> Deserializer<'de> for Tracer<'_, '_> {
+  type Error = TraceError;
+
+  trace_scalar! {
+    deserialize_bool => Schema::Boolean, visit_bool(false);
+    deserialize_i8 => Schema::Integer { minimum: None }, visit_i64(0);
+    deserialize_i16 => Schema::Integer { minimum: None }, visit_i64(0);
+    deserialize_i32 => Schema::Integer { minimum: None }, visit_i64(0);
+    deserialize_i64 => Schema::Integer { minimum: None }, visit_i64(0);
+    // Беззнаковые числа получают единицу, чтобы их принимали и типы вроде `NonZeroU32`.
+    deserialize_u8 => Schema::Integer { minimum: Some(0) }, visit_u64(1);
+    deserialize_u16 => Schema::Integer { minimum: Some(0) }, visit_u64(1);
+    deserialize_u32 => Schema::Integer { minimum: Some(0) }, visit_u64(1);
+    deserialize_u64 => Schema::Integer { minimum: Some(0) }, visit_u64(1);
+    deserialize_f32 => Schema::Number, visit_f64(0.0);
+    deserialize_f64 => Schema::Number, visit_f64(0.0);
+    deserialize_char => Schema::String, visit_char('a');
+    deserialize_str => Schema::String, visit_str("");
+    deserialize_string => Schema::String, visit_str("");
+    deserialize_bytes => Schema::Any, visit_bytes(&[]);
+    deserialize_byte_buf => Schema::Any, visit_bytes(&[]);
+    deserialize_unit => Schema::Null, visit_unit();
+    deserialize_identifier => Schema::Any, visit_str("");
+    deserialize_ignored_any => Schema::Any, visit_unit();
+  }
+
+  // Вид значения определяет сам тип: `JsonValue` примет `null`, а перечисление без внешнего тега его отклонит, и путь станет непрозрачным.
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    self.record(Schema::Any)?;
+    visitor.visit_unit()
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    if self.is_opaque() { return visitor.visit_none(); };
+    let mut inner = Schema::Any;
+    let res = visitor.visit_some(Tracer { trace: self.trace, path: self.path.clone(), slot: &mut inner, depth: self.depth + 1 });
+    *self.slot = match inner {
+      Schema::Any => Schema::Any,
+      inner @ Schema::Nullable(_) => inner,
+      inner => Schema::Nullable(Box::new(inner)),
+    };
+    res
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+    self.deserialize_unit(visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    if self.is_opaque() { return Err(de::Error::custom("значение принимается любым")); };
+    let item_path = format!("{}/*", self.path);
+    let mut items = Schema::Any;
+    let opaque_items = self.depth >= MAX_TRACE_DEPTH || self.trace.borrow().opaque.contains(&item_path);
+    let res = visitor.visit_seq(Items {
+      trace: self.trace, path: item_path, slot: &mut items, depth: self.depth + 1, left: usize::from(!opaque_items),
+    });
+    *self.slot = Schema::Array(Box::new(items));
+    res
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+    let (trace, path, depth) = (self.trace, self.path.clone(), self.depth + 1);
+    self.record(Schema::Any)?;
+    let mut scratch = Schema::Any;
+    visitor.visit_seq(Items { trace, path, slot: &mut scratch, depth, left: len })
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+    self.deserialize_tuple(len, visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    self.record(Schema::Object { properties: vec![], additional: Some(Box::new(Schema::Any)) })?;
+    visitor.visit_map(de::value::MapDeserializer::<std::iter::Empty<((), ())>, TraceError>::new(std::iter::empty()))
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V)
+    -> Result<V::Value, TraceError>
+  {
+    if self.is_opaque() {
+      if let Some(partial) = self.trace.borrow().partial.get(&self.path) { *self.slot = partial.clone(); };
+      return Err(de::Error::custom("значение принимается любым"));
+    };
+    // Поля известны заранее: те, до которых проход не дойдёт, всё равно попадают в схему и принимают любые значения.
+    let mut properties: Vec<(&'static str, Schema)> = fields.iter().map(|field| (*field, Schema::Any)).collect();
+    let res = visitor.visit_map(Fields { trace: self.trace, path: &self.path, properties: &mut properties, next: 0, depth: self.depth + 1 });
+    *self.slot = Schema::Object { properties, additional: None };
+    if res.is_err() { self.trace.borrow_mut().partial.insert(self.path.clone(), self.slot.clone()); };
+    res
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, variants: &'static [&'static str], visitor: V)
+    -> Result<V::Value, TraceError>
+  {
+    self.record(Schema::Enum(variants))?;
+    match variants.first() {
+      Some(variant) => visitor.visit_enum(UnitVariant(variant)),
+      None => Err(de::Error::custom("перечисление без вариантов")),
+    }
+  }
+}
+
+/// Поля структуры, которые по очереди передаются её `Deserialize`.
+struct Fields<'t, 'p, 's> {
+  trace: &'t RefCell<Trace>,
+  path: &'p str,
+  properties: &'s mut Vec<(&'static str, Schema)>,
+  next: usize,
+  depth: usize,
+}
+
+impl Fields<'_, '_, '_> {
+  fn field_path(&self, field: &str) -> String {
+    format!("{}/{}", self.path, field)
+  }
+}
+
+impl<'de> de::MapAccess<'de> for Fields<'_, '_, '_> {
+  type Error = TraceError;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, TraceError> {
+    while let Some((field, _)) = self.properties.get(self.next) {
+      let field: &'static str = field;
+      if !self.trace.borrow().skipped.contains(&self.field_path(field)) {
+        return seed.deserialize(BorrowedStrDeserializer::new(field)).map(Some);
+      };
+      self.next += 1;
+    };
+    Ok(None)
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, TraceError> {
+    let path = self.field_path(self.properties[self.next].0);
+    let slot = &mut self.properties[self.next].1;
+    self.next += 1;
+    Tracer::nested(self.trace, path, slot, self.depth, seed)
+  }
+}
+
+/// Элементы массива: один подставной элемент, по которому записывается схема элементов, или ни одного.
+struct Items<'t, 's> {
+  trace: &'t RefCell<Trace>,
+  path: String,
+  slot: &'s mut Schema,
+  depth: usize,
+  left: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Items<'_, '_> {
+  type Error = TraceError;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+    if self.left == 0 { return Ok(None); };
+    self.left -= 1;
+    Tracer::nested(self.trace, self.path.clone(), self.slot, self.depth, seed).map(Some)
+  }
+}
+
+/// Первый вариант перечисления. Перечисления сервера с внешним тегом состоят из вариантов без данных.
+struct UnitVariant(&'static str);
+
+impl<'de> de::EnumAccess<'de> for UnitVariant {
+  type Error = TraceError;
+  type Variant = Self;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), TraceError> {
+    let variant = seed.deserialize(self.0.into_deserializer())?;
+    Ok((variant, self))
+  }
+}
+
+impl<'de> de::VariantAccess<'de> for UnitVariant {
+  type Error = TraceError;
+
+  fn unit_variant(self) -> Result<(), TraceError> {
+    Ok(())
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, TraceError> {
+    Err(de::Error::custom("вариант с данными"))
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, TraceError> {
+    Err(de::Error::custom("вариант с данными"))
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value, TraceError> {
+    Err(de::Error::custom("вариант с данными"))
+  }
+}
+
+/// Схемы метода.
+pub struct RouteSchema {
+  pub method: Method,
+  pub path: &'static str,
+  /// Схема тела запроса.
+  pub request: Option<Schema>,
+  /// Схема тела успешного ответа.
+  pub response: Option<Schema>,
+}
+
+/// Объект из идентификаторов, ссылающихся на сущность, и полей типа `T` (например, патча), переданных рядом с ними.
+fn flat<T: DeserializeOwned>(ids: &[&'static str]) -> Schema {
+  let mut properties: Vec<(&'static str, Schema)> = ids.iter().map(|id| (*id, Schema::Integer { minimum: None })).collect();
+  match of::<T>() {
+    Schema::Object { properties: fields, additional } => {
+      properties.extend(fields);
+      Schema::Object { properties, additional }
+    },
+    _ => Schema::Object { properties, additional: Some(Box::new(Schema::Any)) },
+  }
+}
+
+/// Объект из идентификаторов, ссылающихся на сущность, и значения типа `T` в поле `key`.
+fn wrapped<T: DeserializeOwned>(ids: &[&'static str], key: &'static str) -> Schema {
+  let mut properties: Vec<(&'static str, Schema)> = ids.iter().map(|id| (*id, Schema::Integer { minimum: None })).collect();
+  properties.push((key, of::<T>()));
+  Schema::Object { properties, additional: None }
+}
+
+/// Доска в ответе `POST /board`. По запросу с `with_profiles` в доску добавляются профили её участников.
+fn board_response() -> Schema {
+  match of::<Board>() {
+    Schema::Object { mut properties, additional } => {
+      properties.push(("profiles", Schema::Any));
+      Schema::Object { properties, additional }
+    },
+    schema => schema,
+  }
+}
+
+const BOARD: &[&str] = &["board_id"];
+const CARD: &[&str] = &["board_id", "card_id"];
+const TASK: &[&str] = &["board_id", "card_id", "task_id"];
+const SUBTASK: &[&str] = &["board_id", "card_id", "task_id", "subtask_id"];
+
+/// Возвращает методы, тела запросов или ответов которых проверяются по схемам.
+pub fn routes() -> &'static [RouteSchema] {
+  static ROUTES: OnceLock<Vec<RouteSchema>> = OnceLock::new();
+  ROUTES.get_or_init(|| {
+    let request = |method: Method, path: &'static str, schema: Schema| RouteSchema { method, path, request: Some(schema), response: None };
+    let response = |method: Method, path: &'static str, schema: Schema| RouteSchema { method, path, request: None, response: Some(schema) };
+    vec![
+      request(Method::PUT, "/board", of::<NewBoard>()),
+      response(Method::POST, "/board", board_response()),
+      request(Method::PATCH, "/board", flat::<BoardPatch>(BOARD)),
+      request(Method::PATCH, "/board/document", wrapped::<Vec<Operation>>(BOARD, "patch")),
+      request(Method::PUT, "/card", wrapped::<NewCard>(BOARD, "card")),
+      request(Method::PATCH, "/card", flat::<CardPatch>(CARD)),
+      request(Method::PUT, "/task", wrapped::<NewTask>(CARD, "task")),
+      request(Method::PATCH, "/task", flat::<TaskPatch>(TASK)),
+      request(Method::PATCH, "/task/time", wrapped::<Timelines>(TASK, "timelines")),
+      request(Method::PUT, "/task/link", wrapped::<Link>(TASK, "link")),
+      request(Method::PUT, "/subtask", wrapped::<NewSubtask>(TASK, "subtask")),
+      request(Method::PATCH, "/subtask", flat::<SubtaskPatch>(SUBTASK)),
+      request(Method::PATCH, "/subtask/time", wrapped::<Timelines>(SUBTASK, "timelines")),
+      request(Method::PUT, "/board/tag", wrapped::<Tag>(BOARD, "tag")),
+      request(Method::PATCH, "/board/tag", flat::<TagPatch>(&["board_id", "tag_id"])),
+      request(Method::PUT, "/board/lane", wrapped::<Lane>(BOARD, "lane")),
+      request(Method::PATCH, "/board/lane", flat::<LanePatch>(&["board_id", "lane_id"])),
+      request(Method::PUT, "/board/sprint", wrapped::<Sprint>(BOARD, "sprint")),
+      request(Method::PATCH, "/board/sprint", flat::<SprintPatch>(&["board_id", "sprint_id"])),
+      request(Method::PUT, "/board/view", wrapped::<BoardView>(BOARD, "view")),
+      request(Method::PUT, "/board/report", wrapped::<BoardReport>(BOARD, "report")),
+      request(Method::PATCH, "/org", flat::<OrgPatch>(&["org_id"])),
+      request(Method::PATCH, "/user/profile", of::<ProfilePatch>()),
+      request(Method::PATCH, "/user/board-prefs", flat::<BoardPrefsPatch>(BOARD)),
+      request(Method::PATCH, "/user/notification-prefs", of::<NotificationPrefsPatch>()),
+      request(Method::PATCH, "/user/notifications/read", of::<NotificationsRead>()),
+      response(Method::PUT, "/sign-up", of::<TokenAuth>()),
+      response(Method::POST, "/sign-up/confirm", of::<TokenAuth>()),
+      response(Method::GET, "/sign-in", of::<TokenAuth>()),
+      response(Method::POST, "/token/refresh", of::<TokenAuth>()),
+    ]
+  })
+}
+
+fn route(method: &Method, path: &str) -> Option<&'static RouteSchema> {
+  routes().iter().find(|route| route.method == method && route.path == path)
+}
+
+/// Проверяет тело запроса по схеме метода и возвращает запрос с тем же телом.
+///
+/// Тело, которое не удаётся декодировать, не проверяется: ошибку декодирования сообщит обработчик. Если тело не соответствует схеме, возвращается ответ 400 с перечнем нарушений.
+pub async fn check_request(req: Request<Body>) -> Result<Request<Body>, Response<Body>> {
+  let schema = match route(req.method(), req.uri().path()).and_then(|route| route.request.as_ref()) {
+    Some(schema) => schema,
+    None => return Ok(req),
+  };
+  let (parts, mut body) = req.into_parts();
+  let mut bytes: Vec<u8> = Vec::new();
+  while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+    let chunk = match chunk {
+      Ok(chunk) => chunk,
+      Err(_) => return Err(extraction_failed(ExtractionError::FromBody)),
+    };
+    if bytes.len() + chunk.len() > MAX_BODY_LEN { return Err(extraction_failed(ExtractionError::TooLarge)); };
+    bytes.extend_from_slice(&chunk);
+  };
+  let mut copy = Request::new(Body::from(bytes.clone()));
+  if is_msgpack(parts.headers.get("Content-Type")) {
+    copy.headers_mut().insert("Content-Type", parts.headers["Content-Type"].clone());
+  };
+  if let Ok(value) = extract::<JsonValue>(copy).await {
+    let violations = schema.validate(&value);
+    if !violations.is_empty() {
+      return Err(resp::schema_failed(400, "Тело запроса не соответствует схеме метода.", &violations));
+    };
+  };
+  Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+/// Проверяет тело успешного ответа в JSON по схеме метода. Ответ, не соответствующий схеме, заменяется ответом 500 с перечнем нарушений.
+pub async fn check_response(method: &Method, path: &str, res: Response<Body>) -> Response<Body> {
+  let schema = match route(method, path).and_then(|route| route.response.as_ref()) {
+    Some(schema) => schema,
+    None => return res,
+  };
+  let is_json = res.headers().get("Content-Type")
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.starts_with("application/json"));
+  if res.status() != 200 || !is_json { return res; };
+  let (parts, body) = res.into_parts();
+  let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+  let violations = serde_json::from_slice::<JsonValue>(&body).map(|value| schema.validate(&value)).unwrap_or_default();
+  match violations.is_empty() {
+    true => Response::from_parts(parts, Body::from(body)),
+    false => resp::schema_failed(500, "Ответ сервера не соответствует схеме метода.", &violations),
+  }
+}
+
+/// Возвращает схемы методов в виде JSON Schema: `{"<метод> <путь>": {"request": <схема>, "response": <схема>}, ...}`.
+pub fn to_json() -> JsonValue {
+  let routes: Map<String, JsonValue> = routes().iter().map(|route| {
+    let mut schemas = Map::new();
+    if let Some(request) = &route.request { schemas.insert("request".into(), request.to_json()); };
+    if let Some(response) = &route.response { schemas.insert("response".into(), response.to_json()); };
+    (format!("{} {}", route.method, route.path), JsonValue::Object(schemas))
+  }).collect();
+  JsonValue::Object(routes)
+}
@@ -0,0 +1,160 @@
+//! Отвечает за обращения к GitHub REST API и проверку подписи вебхуков GitHub.
+//!
+//! Запросы выполняются с токеном доступа пользователя (personal access token), которому нужен доступ на чтение и запись задач (issues) репозитория. GitHub подписывает каждый вебхук HMAC-SHA256 от тела запроса секретом вебхука и передаёт подпись в заголовке `X-Hub-Signature-256` вида `sha256=<подпись>`.
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use crypto::{hmac::Hmac, mac::{Mac, MacResult}, sha2::Sha256};
+use custom_error::custom_error;
+use hyper::{Body, Client, HeaderMap, Method, Request, body::to_bytes, client::HttpConnector};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+use crate::sec::hex;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub GithubError{reason: String} = "GitHub вернул ошибку: {reason}"}
+
+/// Число секунд, в течение которых сервер ожидает ответа GitHub.
+const GITHUB_TIMEOUT_SECS: u64 = 10;
+
+/// Число задач на одной странице списка.
+const PER_PAGE: usize = 100;
+
+/// Наибольшее число страниц, запрашиваемых за одну синхронизацию. Остальные задачи запрашиваются при следующей.
+const MAX_PAGES: usize = 10;
+
+/// Задача (issue) GitHub.
+pub struct Issue {
+  pub number: i64,
+  pub title: String,
+  pub body: String,
+  /// Адрес страницы задачи.
+  pub url: String,
+  pub closed: bool,
+}
+
+/// Клиент GitHub REST API с токеном доступа пользователя.
+pub struct Api<'a> {
+  url: &'a str,
+  token: &'a str,
+}
+
+impl<'a> Api<'a> {
+  pub fn new(url: &'a str, token: &'a str) -> Self {
+    Api { url: url.trim_end_matches('/'), token }
+  }
+
+  /// Проверяет, что репозиторий существует и доступен с токеном.
+  pub async fn repo(&self, repo: &str) -> MResult<()> {
+    self.send(Method::GET, &format!("/repos/{}", repo), None).await?;
+    Ok(())
+  }
+
+  /// Возвращает задачи репозитория в порядке изменения, а если получены не все задачи - ещё и время изменения последней полученной, с которого нужно продолжить.
+  ///
+  /// Если задан `since`, возвращаются открытые и закрытые задачи, изменённые не раньше этого времени, а иначе - все открытые. Запросы на слияние (pull requests), которые GitHub отдаёт вместе с задачами, пропускаются.
+  pub async fn issues(&self, repo: &str, since: Option<i64>) -> MResult<(Vec<Issue>, Option<i64>)> {
+    let filter = match since.and_then(|since| Utc.timestamp_opt(since, 0).single()) {
+      Some(since) => format!("state=all&since={}", since.to_rfc3339_opts(SecondsFormat::Secs, true).replace(':', "%3A")),
+      None => String::from("state=open"),
+    };
+    let mut issues = Vec::new();
+    let mut last_updated = None;
+    for page in 1..=MAX_PAGES {
+      let path = format!("/repos/{}/issues?{}&sort=updated&direction=asc&per_page={}&page={}", repo, filter, PER_PAGE, page);
+      let items = match self.send(Method::GET, &path, None).await? {
+        JsonValue::Array(items) => items,
+        _ => return Err(Box::new(GithubError{ reason: "ответ не является списком задач".into() })),
+      };
+      issues.extend(items.iter().filter_map(issue));
+      if items.len() < PER_PAGE { return Ok((issues, None)); };
+      last_updated = items.last().and_then(|item| item["updated_at"].as_str()).and_then(updated_at);
+    };
+    Ok((issues, last_updated))
+  }
+
+  /// Закрывает или открывает задачу.
+  pub async fn set_closed(&self, repo: &str, number: i64, closed: bool) -> MResult<()> {
+    let state = if closed { "closed" } else { "open" };
+    self.send(Method::PATCH, &format!("/repos/{}/issues/{}", repo, number), Some(json!({ "state": state }))).await?;
+    Ok(())
+  }
+
+  /// Выполняет запрос и возвращает ответ в виде JSON.
+  async fn send(&self, method: Method, path: &str, body: Option<JsonValue>) -> MResult<JsonValue> {
+    let req = Request::builder()
+      .method(method)
+      .uri(format!("{}{}", self.url, path))
+      .header("Authorization", format!("Bearer {}", self.token))
+      .header("Accept", "application/vnd.github+json")
+      .header("X-GitHub-Api-Version", "2022-11-28")
+      // GitHub отклоняет запросы без заголовка User-Agent.
+      .header("User-Agent", "cc-taskboard-server")
+      .body(body.map(|body| Body::from(body.to_string())).unwrap_or_else(Body::empty))?;
+    let res = tokio::time::timeout(Duration::from_secs(GITHUB_TIMEOUT_SECS), client().request(req)).await
+      .map_err(|_| GithubError{ reason: "GitHub не ответил вовремя".into() })??;
+    let status = res.status();
+    let body = to_bytes(res.into_body()).await?;
+    let body: JsonValue = serde_json::from_slice(&body)
+      .map_err(|_| GithubError{ reason: format!("ответ с кодом {} не является JSON", status.as_u16()) })?;
+    match status.is_success() {
+      true => Ok(body),
+      false => Err(Box::new(GithubError{ reason: match body["message"].as_str() {
+        Some(message) => format!("{} (код {})", message, status.as_u16()),
+        None => format!("ответ с кодом {}", status.as_u16()),
+      }})),
+    }
+  }
+}
+
+/// Создаёт клиент для запросов к GitHub.
+///
+/// GitHub Enterprise Server может быть доступен и по HTTP - например, внутри частной сети.
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+  let connector = HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+  Client::builder().build(connector)
+}
+
+/// Извлекает задачу из ответа GitHub. Возвращает None для запросов на слияние.
+pub fn issue(value: &JsonValue) -> Option<Issue> {
+  if value.get("pull_request").is_some() { return None; };
+  Some(Issue {
+    number: value["number"].as_i64()?,
+    title: value["title"].as_str()?.to_string(),
+    body: value["body"].as_str().unwrap_or_default().to_string(),
+    url: value["html_url"].as_str()?.to_string(),
+    closed: value["state"] == "closed",
+  })
+}
+
+/// Разбирает время изменения задачи в UNIX-время в секундах.
+fn updated_at(value: &str) -> Option<i64> {
+  DateTime::parse_from_rfc3339(value).ok().map(|updated_at| updated_at.timestamp())
+}
+
+/// Проверяет, что репозиторий задан в виде `владелец/название`.
+pub fn valid_repo(repo: &str) -> bool {
+  let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+  match repo.split_once('/') {
+    Some((owner, name)) => valid(owner) && valid(name) && name != "." && name != "..",
+    None => false,
+  }
+}
+
+/// Проверяет подпись вебхука.
+pub fn verify(secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+  let signature = match headers.get("X-Hub-Signature-256")
+    .and_then(|h| h.to_str().ok())
+    .and_then(|h| h.strip_prefix("sha256="))
+    .and_then(hex::decode)
+  {
+    Some(v) => v,
+    None => return false,
+  };
+  let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+  mac.input(body);
+  // Сравнение MacResult выполняется за постоянное время.
+  MacResult::new(&signature) == mac.result()
+}
@@ -0,0 +1,66 @@
+//! Отвечает за отправку писем через почтовый шлюз.
+//!
+//! Сервер не говорит по SMTP сам, а передаёт письма шлюзу по HTTP: `POST` на адрес шлюза с токеном в заголовке `Authorization: Bearer <токен>` и JSON вида `{"from": ..., "to": ..., "subject": ..., "text": ...}`. Такой приём писем есть у большинства почтовых сервисов, а для своего SMTP-сервера шлюз - небольшой сервис-посредник. Любой ответ с кодом 2xx считается успешной отправкой.
+
+use custom_error::custom_error;
+use hyper::{Body, Client, Method, Request, body::to_bytes, client::HttpConnector};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub MailerError{reason: String} = "Почтовый шлюз вернул ошибку: {reason}"}
+
+/// Число секунд, в течение которых сервер ожидает ответа шлюза.
+const MAILER_TIMEOUT_SECS: u64 = 10;
+
+/// Наибольшая длина текста ошибки шлюза, которая попадает в журнал сервера.
+const MAX_REASON_LEN: usize = 200;
+
+/// Письмо.
+pub struct Mail<'a> {
+  pub to: &'a str,
+  pub subject: &'a str,
+  pub text: &'a str,
+}
+
+/// Клиент почтового шлюза.
+pub struct Mailer<'a> {
+  url: &'a str,
+  token: &'a str,
+  from: &'a str,
+}
+
+impl<'a> Mailer<'a> {
+  /// `from` - адрес отправителя всех писем.
+  pub fn new(url: &'a str, token: &'a str, from: &'a str) -> Self {
+    Mailer { url, token, from }
+  }
+
+  /// Передаёт письмо шлюзу.
+  pub async fn send(&self, mail: &Mail<'_>) -> MResult<()> {
+    let body = json!({ "from": self.from, "to": mail.to, "subject": mail.subject, "text": mail.text });
+    let req = Request::builder()
+      .method(Method::POST)
+      .uri(self.url)
+      .header("Authorization", format!("Bearer {}", self.token))
+      .header("Content-Type", "application/json")
+      .body(Body::from(body.to_string()))?;
+    let res = tokio::time::timeout(Duration::from_secs(MAILER_TIMEOUT_SECS), client().request(req)).await
+      .map_err(|_| MailerError{ reason: "шлюз не ответил вовремя".into() })??;
+    let status = res.status();
+    if status.is_success() { return Ok(()); };
+    let body = to_bytes(res.into_body()).await?;
+    let reason: String = String::from_utf8_lossy(&body).chars().take(MAX_REASON_LEN).collect();
+    Err(Box::new(MailerError{ reason: format!("{} (код {})", reason.trim(), status.as_u16()) }))
+  }
+}
+
+/// Создаёт клиент для запросов к шлюзу.
+///
+/// Шлюз может быть доступен и по HTTP - например, на том же сервере или внутри частной сети.
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+  let connector = HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+  Client::builder().build(connector)
+}
@@ -0,0 +1,8 @@
+//! Отвечает за обращения к внешним сервисам: тем, с которыми синхронизируются доски, почтовому шлюзу и сервисам, которые получают отчёты о досках.
+//!
+//! Модули этого уровня только обмениваются данными с сервисами; то, как эти данные меняют доски, описывается в `core`.
+
+pub mod github;
+pub mod mailer;
+pub mod telegram;
+pub mod webhook;
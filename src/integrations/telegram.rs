@@ -0,0 +1,65 @@
+//! Отвечает за отправку сообщений в чаты Telegram через Bot API.
+//!
+//! Сообщения отправляет бот, токен которого задан в конфигурации сервера; чтобы бот мог писать в чат, его нужно добавить в чат.
+
+use custom_error::custom_error;
+use hyper::{Body, Client, Method, Request, body::to_bytes, client::HttpConnector};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub TelegramError{reason: String} = "Telegram вернул ошибку: {reason}"}
+
+/// Число секунд, в течение которых сервер ожидает ответа Telegram.
+const TELEGRAM_TIMEOUT_SECS: u64 = 10;
+
+/// Наибольшая длина сообщения Telegram в символах. Более длинные сообщения обрезаются.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Проверяет идентификатор чата: число или имя публичного канала вида `@channel`.
+pub fn valid_chat_id(chat_id: &str) -> bool {
+  match chat_id.strip_prefix('@') {
+    Some(name) => !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+    None => chat_id.parse::<i64>().is_ok(),
+  }
+}
+
+/// Бот Telegram.
+pub struct Bot<'a> {
+  api_url: &'a str,
+  token: &'a str,
+}
+
+impl<'a> Bot<'a> {
+  pub fn new(api_url: &'a str, token: &'a str) -> Self {
+    Bot { api_url, token }
+  }
+
+  /// Отправляет в чат сообщение без разметки.
+  pub async fn send_message(&self, chat_id: &str, text: &str) -> MResult<()> {
+    let text: String = text.chars().take(MAX_MESSAGE_LEN).collect();
+    let body = json!({ "chat_id": chat_id, "text": text, "disable_web_page_preview": true });
+    let req = Request::builder()
+      .method(Method::POST)
+      .uri(format!("{}/bot{}/sendMessage", self.api_url.trim_end_matches('/'), self.token))
+      .header("Content-Type", "application/json")
+      .body(Body::from(body.to_string()))?;
+    let res = tokio::time::timeout(Duration::from_secs(TELEGRAM_TIMEOUT_SECS), client().request(req)).await
+      .map_err(|_| TelegramError{ reason: "Telegram не ответил вовремя".into() })??;
+    let status = res.status();
+    if status.is_success() { return Ok(()); };
+    // Bot API описывает ошибку в поле `description`.
+    let body = to_bytes(res.into_body()).await?;
+    let reason = serde_json::from_slice::<JsonValue>(&body).ok()
+      .and_then(|body| body["description"].as_str().map(str::to_string))
+      .unwrap_or_default();
+    Err(Box::new(TelegramError{ reason: format!("{} (код {})", reason, status.as_u16()) }))
+  }
+}
+
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+  let connector = HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+  Client::builder().build(connector)
+}
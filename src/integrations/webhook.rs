@@ -0,0 +1,56 @@
+//! Отвечает за передачу данных на вебхуки внешних сервисов.
+//!
+//! Данные передаются запросом `POST` с JSON в теле. Любой ответ с кодом 2xx считается успешной передачей.
+
+use custom_error::custom_error;
+use hyper::{Body, Client, Method, Request, Uri, body::to_bytes, client::HttpConnector};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::Value as JsonValue;
+use std::time::Duration;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub WebhookError{reason: String} = "Вебхук вернул ошибку: {reason}"}
+
+/// Число секунд, в течение которых сервер ожидает ответа вебхука.
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Наибольшая длина адреса вебхука.
+const MAX_URL_LEN: usize = 2048;
+
+/// Наибольшая длина текста ошибки вебхука, которая попадает в журнал сервера.
+const MAX_REASON_LEN: usize = 200;
+
+/// Проверяет адрес вебхука: абсолютный адрес HTTPS, а если `allow_http` установлен, - также HTTP.
+pub fn valid_url(url: &str, allow_http: bool) -> bool {
+  if url.len() > MAX_URL_LEN { return false; };
+  match url.parse::<Uri>() {
+    Ok(uri) => uri.host().is_some_and(|host| !host.is_empty()) && match uri.scheme_str() {
+      Some("https") => true,
+      Some("http") => allow_http,
+      _ => false,
+    },
+    Err(_) => false,
+  }
+}
+
+/// Передаёт JSON на вебхук.
+pub async fn post(url: &str, body: &JsonValue) -> MResult<()> {
+  let req = Request::builder()
+    .method(Method::POST)
+    .uri(url)
+    .header("Content-Type", "application/json")
+    .body(Body::from(body.to_string()))?;
+  let res = tokio::time::timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS), client().request(req)).await
+    .map_err(|_| WebhookError{ reason: "вебхук не ответил вовремя".into() })??;
+  let status = res.status();
+  if status.is_success() { return Ok(()); };
+  let body = to_bytes(res.into_body()).await?;
+  let reason: String = String::from_utf8_lossy(&body).chars().take(MAX_REASON_LEN).collect();
+  Err(Box::new(WebhookError{ reason: format!("{} (код {})", reason.trim(), status.as_u16()) }))
+}
+
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+  let connector = HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+  Client::builder().build(connector)
+}
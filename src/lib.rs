@@ -0,0 +1,110 @@
+//! Сервер CC TaskBoard в виде библиотеки.
+//!
+//! Исполняемый файл сервера (`main.rs`) только разбирает команду и конфигурацию, а сервер запускает `serve`, поэтому сервер можно запустить и внутри другой программы - например, в тестах. Модели API (`model`) и данные аутентификации (`sec::auth`) нужны и программам, работающим с сервером по сети: их вместе с типизированным клиентом предоставляет пакет `cc-taskboard-client` (каталог `client/`). Остальные модули служат самому серверу и могут меняться в любой версии, поэтому скрыты из документации.
+
+#[doc(hidden)]
+pub mod billing;
+#[doc(hidden)]
+pub mod core;
+#[doc(hidden)]
+pub mod hyper_router;
+#[doc(hidden)]
+pub mod integrations;
+pub mod model;
+#[doc(hidden)]
+pub mod psql_handler;
+pub mod sec;
+#[doc(hidden)]
+pub mod setup;
+#[doc(hidden)]
+pub mod storage;
+#[doc(hidden)]
+pub mod systemd;
+
+use std::sync::Arc;
+
+use setup::AppConfig;
+use storage::Storage;
+
+/// Запускает фоновые задачи и сервер и работает до его выключения.
+pub async fn serve(cfg: AppConfig, db: Arc<dyn Storage>) {
+  let hyper_addr = cfg.hyper_addr;
+  core::board_cache::configure(&cfg.board_cache);
+  core::board_size::configure(&cfg.board_limits);
+  tokio::spawn(core::board_cache::run());
+  tokio::spawn(core::overdue::log());
+  tokio::spawn(core::automation::run(db.clone()));
+  // Фоновые задачи работают с данными, которые хранятся только в PostgreSQL.
+  if let Some(pg) = db.postgres() {
+    let coalesce_window = std::time::Duration::from_millis(cfg.event_coalesce_ms);
+    let secs = std::time::Duration::from_secs;
+    let mut queue = core::jobs::Queue::new(&cfg.jobs);
+    tokio::spawn(core::notifications::run(pg.clone(), coalesce_window));
+    queue.every("overdue", secs(cfg.overdue_scan_period_secs.max(1)), core::overdue::job);
+    queue.every("recycle", secs(cfg.recycle_scan_period_secs.max(1)), core::recycle::job);
+    let window = cfg.sign_in_failures_window_secs.max(1);
+    queue.every("sign-in-failures-cleanup", secs(window as u64), move |db| core::sign_in_failures_job(db, window));
+    if let Some(github) = &cfg.github {
+      core::github::register(&mut queue, github);
+      tokio::spawn(core::github::propagate(pg.clone(), coalesce_window));
+    };
+    if let Some(mailer) = &cfg.mailer {
+      let mailer = mailer.clone();
+      queue.every("digest", secs(mailer.digest_period_secs), move |db| core::digest::job(db, mailer.clone()));
+    };
+    if let Some(signup) = &cfg.public_signup {
+      queue.every("signup-cleanup", secs(signup.cleanup_period_secs), core::signup::job);
+    };
+    if let Some(reports) = &cfg.reports {
+      let reports = reports.clone();
+      queue.every("reports", secs(reports.period_secs), move |db| core::reports::job(db, reports.clone()));
+    };
+    if let Some(retention) = &cfg.retention {
+      let retention = retention.clone();
+      queue.every("retention", secs(retention.period_secs), move |db| core::retention::job(db, retention.clone()));
+    };
+    if cfg.revalidate_period_secs > 0 {
+      queue.every("revalidate", secs(cfg.revalidate_period_secs), core::integrity::job);
+    };
+    tokio::spawn(queue.run(pg.clone()));
+  };
+  let _pid_file = match &cfg.pid_file {
+    Some(path) => match systemd::PidFile::create(path) {
+      Ok(pid_file) => Some(pid_file),
+      Err(e) => return eprintln!("Не удалось записать PID-файл {}: {}", path, e),
+    },
+    None => None,
+  };
+  let cfg = setup::LiveConfig::new(cfg);
+  #[cfg(unix)]
+  tokio::spawn(setup::reload_on_sighup(cfg.clone()));
+  let service = hyper::service::make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
+    let db = db.clone();
+    let cfg = cfg.clone();
+    let addr = conn.remote_addr();
+    let service = hyper::service::service_fn(move |req| {
+      hyper_router::router(req, db.clone(), cfg.clone(), addr)
+    });
+    async move { Ok::<_, std::convert::Infallible>(service) }
+  });
+  // При активации сокетом systemd уже слушает адрес, и сервер принимает соединения на переданном сокете.
+  let builder = match systemd::listener() {
+    Ok(Some(listener)) => hyper::Server::from_tcp(listener),
+    Ok(None) => hyper::Server::try_bind(&hyper_addr),
+    Err(e) => return eprintln!("Не удалось принять сокет от systemd: {}", e),
+  };
+  let server = match builder {
+    Ok(builder) => builder.serve(service),
+    Err(e) => return eprintln!("Не удалось начать слушать адрес {}: {}", hyper_addr, e),
+  };
+  println!("Сервер слушает по адресу http://{}", server.local_addr());
+  systemd::notify("READY=1");
+  let finisher = server.with_graceful_shutdown(async {
+    hyper_router::shutdown().await;
+    systemd::notify("STOPPING=1");
+  });
+  match finisher.await {
+    Err(e) => eprintln!("Ошибка сервера: {}", e),
+    _ => println!("\nСервер успешно выключен."),
+  }
+}
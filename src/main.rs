@@ -1,34 +1,65 @@
 //! Сервер CC TaskBoard.
 
-mod core;
-mod hyper_router;
-mod model;
-mod psql_handler;
-mod sec;
-mod setup;
+use std::sync::Arc;
 
-use psql_handler::Db;
+use cc_taskboard_server::{core, serve, setup, storage};
+use cc_taskboard_server::psql_handler::Db;
+use cc_taskboard_server::setup::{Command, StorageBackend};
+use cc_taskboard_server::storage::Storage;
 
-#[tokio::main]
-pub async fn main() {
+/// Размер стека потоков tokio.
+///
+/// Обработчики запросов глубоко вложены друг в друга, а в отладочной сборке их состояния на стеке не совмещаются, поэтому самым глубоким из них - например, восстановлению резервной копии - не хватает стека, выделяемого tokio по умолчанию (2 МиБ).
+const THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+pub fn main() {
+  let runtime = tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .thread_stack_size(THREAD_STACK_SIZE)
+    .build()
+    .expect("Не удалось запустить среду выполнения tokio.");
+  runtime.block_on(run());
+}
+
+/// Выполняет команду, переданную серверу при запуске.
+async fn run() {
+  let command = setup::get_command();
   let cfg = setup::get_config();
-  let manager = bb8_postgres::PostgresConnectionManager::new_from_stringlike(cfg.pg.clone(), tokio_postgres::NoTls).unwrap();
-  let pool = bb8::Pool::builder().max_size(15).build(manager).await.unwrap();
-  let db = Db::new(pool);
-  let service = hyper::service::make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
-    let db = db.clone();
-    let admin_key = cfg.admin_key.clone();
-    let addr = conn.remote_addr();
-    let service = hyper::service::service_fn(move |req| {
-      hyper_router::router(req, db.clone(), admin_key.clone(), addr)
-    });
-    async move { Ok::<_, std::convert::Infallible>(service) }
-  });
-  let server = hyper::Server::bind(&cfg.hyper_addr).serve(service);
-  println!("Сервер слушает по адресу http://{}", cfg.hyper_addr);
-  let finisher = server.with_graceful_shutdown(hyper_router::shutdown());
-  match finisher.await {
-    Err(e) => eprintln!("Ошибка сервера: {}", e),
-    _ => println!("\nСервер успешно выключен."),
-  }
+  let db: Arc<dyn Storage> = match cfg.storage {
+    StorageBackend::Postgres => Arc::new(Db::connect(&cfg).await.unwrap()),
+    #[cfg(feature = "sqlite")]
+    StorageBackend::Sqlite => Arc::new(storage::sqlite::SqliteStorage::open(&cfg.sqlite_path).unwrap()),
+  };
+  let res = match command {
+    Command::Serve => {
+      serve(cfg, db).await;
+      Ok(())
+    },
+    command => run_command(&*db, command).await,
+  };
+  if let Err(e) = res {
+    eprintln!("{}", e);
+    std::process::exit(1);
+  };
+}
+
+/// Выполняет разовую команду обслуживания сервера (см. `setup::Command`).
+async fn run_command(db: &dyn Storage, command: Command) -> Result<(), Box<dyn std::error::Error>> {
+  match command {
+    Command::Serve => {},
+    Command::Migrate => match db.postgres() {
+      Some(pg) => {
+        core::db_setup(pg).await?;
+        eprintln!("База данных настроена.");
+      },
+      // Таблицы SQLite создаются и обновляются при открытии файла.
+      None => eprintln!("Файл базы данных SQLite настроен."),
+    },
+    Command::CreateAdminKey(key) => println!("{}", core::admin_keys::put(db, &key).await?),
+    Command::Export { board_id } => {
+      let ctx = core::load_board_as_author(db, &board_id).await?;
+      println!("{}", serde_json::to_string_pretty(&ctx.board)?);
+    },
+  };
+  Ok(())
 }
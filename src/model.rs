@@ -2,11 +2,21 @@
 
 use chrono::{DateTime, Utc, serde::ts_seconds};
 use custom_error::custom_error;
-use hyper::{Body, body::to_bytes, http::Request};
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use hyper::{Body, body::HttpBody, http::Request};
+use serde::{Deserialize, Deserializer, Serialize, de::{DeserializeOwned, IgnoredAny}};
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::Arc;
 
-use crate::psql_handler::Db;
-use crate::sec::auth::UserCredentials;
+use crate::core::automation::Fact;
+use crate::core::notifications::Interest;
+use crate::setup::AppConfig;
+use crate::storage::Storage;
+
+#[cfg(test)]
+#[path = "model/tests.rs"]
+mod tests;
 
 custom_error!{ pub GetMutCardError{} = "Не удалось получить мутабельную карточку." }
 custom_error!{ pub GetMutTaskError{} = "Не удалось получить мутабельную задачу." }
@@ -22,8 +32,12 @@ custom_error!{ pub SubtaskRemoveError{} = "Не удалось удалить п
 pub struct Workspace {
   /// Запрос, полученный от клиента. Содержит заголовки и тело.
   pub req: Request<Body>,
-  /// Клиент PostgreSQL.
-  pub db: Db,
+  /// Хранилище данных.
+  pub db: Arc<dyn Storage>,
+  /// Конфигурация сервера.
+  pub cfg: AppConfig,
+  /// Адрес клиента с учётом доверенных прокси (см. `sec::proxy`).
+  pub client_ip: IpAddr,
 }
 
 /// Временные рамки для задач и подзадач.
@@ -40,9 +54,11 @@ pub struct Timelines {
 }
 
 /// Метка.
+///
+/// Метки хранятся в словаре доски, а задачи и подзадачи ссылаются на них по идентификатору.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Tag {
-  /// Уникальный идентификатор тега в текущем списке тегов сущности.
+  /// Уникальный идентификатор тега в словаре тегов доски.
   pub id: i64,
   /// Название метки.
   pub title: String,
@@ -52,8 +68,87 @@ pub struct Tag {
   pub background_color: String,
 }
 
+/// Дорожка доски.
+///
+/// Дорожки делят доску на горизонтальные полосы - например, по направлениям работы, - а задачи ссылаются на них по идентификатору.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Lane {
+  /// Уникальный идентификатор дорожки в пределах доски.
+  pub id: i64,
+  /// Название дорожки.
+  pub title: String,
+  /// Цвет дорожки.
+  pub color: String,
+}
+
+/// Спринт доски - период, за который команда планирует выполнить часть задач доски. Задачи ссылаются на спринт по идентификатору.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Sprint {
+  /// Уникальный идентификатор спринта в пределах доски.
+  pub id: i64,
+  /// Название спринта.
+  pub title: String,
+  /// Начало спринта (UNIX-время в секундах).
+  pub start: i64,
+  /// Конец спринта (UNIX-время в секундах).
+  pub end: i64,
+}
+
+/// Ссылка на задачу доски.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TaskPath {
+  /// Идентификатор карточки.
+  pub card_id: i64,
+  /// Идентификатор задачи в карточке.
+  pub task_id: i64,
+}
+
+/// Вид ресурса, на который ссылается задача.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+  /// Запрос на слияние (pull request, merge request).
+  PullRequest,
+  /// Документ.
+  Document,
+  /// Задача во внешнем трекере.
+  Ticket,
+  #[default]
+  Other,
+}
+
+/// Ссылка задачи на внешний ресурс (см. `core::links`).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+  /// Уникальный идентификатор ссылки в пределах задачи.
+  #[serde(default)]
+  pub id: i64,
+  /// Название ссылки.
+  pub title: String,
+  /// Абсолютный адрес `http` или `https`.
+  pub url: String,
+  /// Вид ресурса.
+  #[serde(default)]
+  pub kind: LinkKind,
+}
+
+/// Задача (issue) репозитория GitHub, с которой синхронизируется задача доски (см. `core::github`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct GithubIssue {
+  /// Репозиторий в виде `владелец/название`.
+  pub repo: String,
+  /// Номер задачи в репозитории.
+  pub number: i64,
+  /// Адрес страницы задачи на GitHub.
+  pub url: String,
+  /// Задача закрыта на GitHub. Расходится со статусом выполнения задачи доски, пока его изменение не передано на GitHub.
+  pub closed: bool,
+}
+
 /// Подзадача.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Subtask {
   /// Уникальный идентификатор подзадачи в пределах задачи.
   pub id: i64,
@@ -65,14 +160,30 @@ pub struct Subtask {
   pub executors: Vec<i64>,
   /// Статус выполнения подзадачи (выполнена/не выполнена).
   pub exec: bool,
-  /// Теги подзадачи.
-  pub tags: Vec<Tag>,
+  /// Описание подзадачи.
+  #[serde(default)]
+  pub description: String,
+  /// Заметки к подзадаче.
+  #[serde(default)]
+  pub notes: String,
+  /// Приоритет подзадачи.
+  #[serde(default)]
+  pub priority: Priority,
+  /// Идентификаторы тегов подзадачи из словаря доски.
+  pub tags: Vec<i64>,
   /// Временные рамки для подзадачи.
   pub timelines: Timelines,
+  /// Время создания (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub created_at: i64,
+  /// Время последнего изменения (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub updated_at: i64,
 }
 
 /// Задача.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Task {
   /// Уникальный идентификатор задачи в пределах карточки.
   pub id: i64,
@@ -86,16 +197,92 @@ pub struct Task {
   pub exec: bool,
   /// Список подзадач.
   pub subtasks: Vec<Subtask>,
+  /// Описание задачи.
+  #[serde(default)]
+  pub description: String,
   /// Заметки к задаче.
   pub notes: String,
-  /// Теги задачи.
-  pub tags: Vec<Tag>,
+  /// Приоритет задачи.
+  #[serde(default)]
+  pub priority: Priority,
+  /// Идентификаторы тегов задачи из словаря доски.
+  pub tags: Vec<i64>,
+  /// Дорожка доски, в которой находится задача.
+  #[serde(default)]
+  pub lane_id: Option<i64>,
+  /// Спринт доски, в который входит задача.
+  #[serde(default)]
+  pub sprint_id: Option<i64>,
+  /// Оценка задачи в очках.
+  #[serde(default)]
+  pub story_points: Option<u32>,
+  /// Задачи доски, которые должны быть выполнены раньше этой (см. `core::dependencies`).
+  #[serde(default)]
+  pub depends_on: Vec<TaskPath>,
+  /// Ссылки на внешние ресурсы: запросы на слияние, документы, задачи в других трекерах.
+  #[serde(default)]
+  pub links: Vec<Link>,
+  /// Хотя бы одна из задач `depends_on` не выполнена. Поддерживается сервером.
+  #[serde(default)]
+  pub blocked: bool,
   /// Временные рамки для задачи.
   pub timelines: Timelines,
+  /// Распространение статуса выполнения подзадач на задачу.
+  ///
+  /// Если не задано, используется настройка доски.
+  #[serde(default)]
+  pub exec_propagation: Option<ExecPropagation>,
+  /// Задача не выполнена, а обязательный срок её выполнения уже прошёл.
+  ///
+  /// Поддерживается сервером: значение, переданное клиентом, пересчитывается при каждом изменении доски и периодически фоновой задачей (см. `core::overdue`).
+  #[serde(default)]
+  pub overdue: bool,
+  /// Задача не выполнена, а до обязательного срока её выполнения осталось не больше `DUE_SOON_SECS`. Поддерживается сервером так же, как `overdue`.
+  #[serde(default)]
+  pub due_soon: bool,
+  /// Задача GitHub, с которой синхронизируется задача. Поддерживается сервером.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub github_issue: Option<GithubIssue>,
+  /// Время создания (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub created_at: i64,
+  /// Время последнего изменения (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub updated_at: i64,
+  /// Последние изменения полей задачи по названиям полей. Поддерживается сервером (см. `core::task_history`).
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub field_stamps: BTreeMap<String, FieldStamp>,
+  /// Кто и когда назначил исполнителей задачи. Поддерживается сервером (см. `core::notifications::record_assignments`).
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub assignments: Vec<Assignment>,
+  /// Участники доски, следящие за изменениями задачи (см. `core::notifications`). Изменяется отдельными методами.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub watchers: Vec<i64>,
+}
+
+/// Последнее изменение поля задачи.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FieldStamp {
+  /// Ревизия доски, в которой поле изменено.
+  pub revision: i64,
+  /// Время изменения (UNIX-время в секундах).
+  pub at: i64,
+}
+
+/// Назначение исполнителя задачи.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Assignment {
+  /// Исполнитель.
+  pub executor: i64,
+  /// Пользователь, назначивший исполнителя.
+  pub assigned_by: i64,
+  /// Время назначения (UNIX-время в секундах).
+  pub at: i64,
 }
 
 /// Карточка.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Card {
   /// Уникальный идентификатор карточки в пределах доски.
   pub id: i64,
@@ -103,6 +290,9 @@ pub struct Card {
   pub author: i64,
   /// Название карточки.
   pub title: String,
+  /// Описание карточки.
+  #[serde(default)]
+  pub description: String,
   /// Список задач.
   pub tasks: Vec<Task>,
   // Цвет текста заголовка.
@@ -111,6 +301,242 @@ pub struct Card {
   pub header_background_color: String,
   /// Цвет фона карточки.
   pub background_color: String,
+  /// Обложка карточки.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cover: Option<CardCover>,
+  /// Оформление карточки. Если не задано, клиенты оформляют карточку по умолчанию.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub theme: Option<CardTheme>,
+  /// Наибольшее число задач в карточке. Если не задано, число задач не ограничено.
+  #[serde(default)]
+  pub wip_limit: Option<NonZeroU32>,
+  /// Число задач в карточке, которое можно сравнить с `wip_limit`. Поддерживается сервером.
+  #[serde(default)]
+  pub task_count: usize,
+  /// Время создания (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub created_at: i64,
+  /// Время последнего изменения (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub updated_at: i64,
+}
+
+/// Обложка карточки, которая выводится над её заголовком.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CardCover {
+  /// Однотонный цвет.
+  Color { color: String },
+  /// Картинка с удалённого ресурса.
+  Url { url: String },
+}
+
+/// Плотность отображения задач карточки.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CardDensity {
+  /// Задачи выводятся вместе с описанием и метками.
+  #[default]
+  Comfortable,
+  /// Задачи выводятся одной строкой.
+  Compact,
+}
+
+/// Оформление карточки сверх цветов заголовка и фона.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CardTheme {
+  /// Цвет текста задач.
+  pub text_color: String,
+  /// Цвет акцентов: отметок выполнения, счётчиков и рамки карточки.
+  pub accent_color: String,
+  /// Плотность отображения задач.
+  #[serde(default)]
+  pub density: CardDensity,
+}
+
+/// Новая подзадача, которую передаёт клиент.
+///
+/// Идентификатор, автора и время создания задаёт сервер. Эти поля, которые передают клиенты, отправляющие подзадачу целиком, принимаются для совместимости и не учитываются.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewSubtask {
+  pub title: String,
+  pub executors: Vec<i64>,
+  pub exec: bool,
+  #[serde(default)]
+  pub description: String,
+  #[serde(default)]
+  pub notes: String,
+  #[serde(default)]
+  pub priority: Priority,
+  pub tags: Vec<i64>,
+  pub timelines: Timelines,
+  #[serde(default, rename = "id")]
+  _id: IgnoredAny,
+  #[serde(default, rename = "author")]
+  _author: IgnoredAny,
+  #[serde(default, rename = "created_at")]
+  _created_at: IgnoredAny,
+  #[serde(default, rename = "updated_at")]
+  _updated_at: IgnoredAny,
+}
+
+impl From<NewSubtask> for Subtask {
+  fn from(subtask: NewSubtask) -> Subtask {
+    Subtask {
+      id: 0,
+      author: 0,
+      title: subtask.title,
+      executors: subtask.executors,
+      exec: subtask.exec,
+      description: subtask.description,
+      notes: subtask.notes,
+      priority: subtask.priority,
+      tags: subtask.tags,
+      timelines: subtask.timelines,
+      created_at: 0,
+      updated_at: 0,
+    }
+  }
+}
+
+/// Новая задача, которую передаёт клиент.
+///
+/// Идентификатор, автора, время создания и поля, которые поддерживает сервер (`blocked`, `overdue`, `due_soon`, `github_issue`, `field_stamps`, `assignments`), задаёт сервер. Эти поля, которые передают клиенты, отправляющие задачу целиком, принимаются для совместимости и не учитываются.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewTask {
+  pub title: String,
+  pub executors: Vec<i64>,
+  pub exec: bool,
+  pub subtasks: Vec<NewSubtask>,
+  #[serde(default)]
+  pub description: String,
+  pub notes: String,
+  #[serde(default)]
+  pub priority: Priority,
+  pub tags: Vec<i64>,
+  #[serde(default)]
+  pub lane_id: Option<i64>,
+  #[serde(default)]
+  pub sprint_id: Option<i64>,
+  #[serde(default)]
+  pub story_points: Option<u32>,
+  #[serde(default)]
+  pub depends_on: Vec<TaskPath>,
+  #[serde(default)]
+  pub links: Vec<Link>,
+  pub timelines: Timelines,
+  #[serde(default)]
+  pub exec_propagation: Option<ExecPropagation>,
+  #[serde(default, rename = "id")]
+  _id: IgnoredAny,
+  #[serde(default, rename = "author")]
+  _author: IgnoredAny,
+  #[serde(default, rename = "blocked")]
+  _blocked: IgnoredAny,
+  #[serde(default, rename = "overdue")]
+  _overdue: IgnoredAny,
+  #[serde(default, rename = "due_soon")]
+  _due_soon: IgnoredAny,
+  #[serde(default, rename = "github_issue")]
+  _github_issue: IgnoredAny,
+  #[serde(default, rename = "created_at")]
+  _created_at: IgnoredAny,
+  #[serde(default, rename = "updated_at")]
+  _updated_at: IgnoredAny,
+  #[serde(default, rename = "field_stamps")]
+  _field_stamps: IgnoredAny,
+  #[serde(default, rename = "assignments")]
+  _assignments: IgnoredAny,
+  #[serde(default, rename = "watchers")]
+  _watchers: IgnoredAny,
+}
+
+impl From<NewTask> for Task {
+  fn from(task: NewTask) -> Task {
+    Task {
+      id: 0,
+      author: 0,
+      title: task.title,
+      executors: task.executors,
+      exec: task.exec,
+      subtasks: task.subtasks.into_iter().map(Subtask::from).collect(),
+      description: task.description,
+      notes: task.notes,
+      priority: task.priority,
+      tags: task.tags,
+      lane_id: task.lane_id,
+      sprint_id: task.sprint_id,
+      story_points: task.story_points,
+      depends_on: task.depends_on,
+      links: task.links,
+      blocked: false,
+      timelines: task.timelines,
+      exec_propagation: task.exec_propagation,
+      overdue: false,
+      due_soon: false,
+      github_issue: None,
+      created_at: 0,
+      updated_at: 0,
+      field_stamps: BTreeMap::new(),
+      assignments: Vec::new(),
+      watchers: Vec::new(),
+    }
+  }
+}
+
+/// Новая карточка, которую передаёт клиент.
+///
+/// Идентификатор, автора, число задач и время создания задаёт сервер. Эти поля, которые передают клиенты, отправляющие карточку целиком, принимаются для совместимости и не учитываются.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewCard {
+  pub title: String,
+  #[serde(default)]
+  pub description: String,
+  pub tasks: Vec<NewTask>,
+  pub header_text_color: String,
+  pub header_background_color: String,
+  pub background_color: String,
+  #[serde(default)]
+  pub cover: Option<CardCover>,
+  #[serde(default)]
+  pub theme: Option<CardTheme>,
+  #[serde(default)]
+  pub wip_limit: Option<NonZeroU32>,
+  #[serde(default, rename = "id")]
+  _id: IgnoredAny,
+  #[serde(default, rename = "author")]
+  _author: IgnoredAny,
+  #[serde(default, rename = "task_count")]
+  _task_count: IgnoredAny,
+  #[serde(default, rename = "created_at")]
+  _created_at: IgnoredAny,
+  #[serde(default, rename = "updated_at")]
+  _updated_at: IgnoredAny,
+}
+
+impl From<NewCard> for Card {
+  fn from(card: NewCard) -> Card {
+    Card {
+      id: 0,
+      author: 0,
+      title: card.title,
+      description: card.description,
+      tasks: card.tasks.into_iter().map(Task::from).collect(),
+      header_text_color: card.header_text_color,
+      header_background_color: card.header_background_color,
+      background_color: card.background_color,
+      cover: card.cover,
+      theme: card.theme,
+      wip_limit: card.wip_limit,
+      task_count: 0,
+      created_at: 0,
+      updated_at: 0,
+    }
+  }
 }
 
 /// Краткая информация о досках пользователя.
@@ -124,6 +550,36 @@ pub struct BoardsShort {
   pub header_text_color: String,
   /// Цвет фона заголовка.
   pub header_background_color: String,
+  /// Время создания доски (UNIX-время в секундах).
+  #[serde(default)]
+  pub created_at: i64,
+  /// Время последнего изменения доски (UNIX-время в секундах).
+  #[serde(default)]
+  pub updated_at: i64,
+  /// Доска отмечена пользователем как избранная.
+  #[serde(default)]
+  pub favorite: bool,
+  /// Пользователь отключил уведомления об изменениях доски.
+  #[serde(default)]
+  pub muted: bool,
+  /// Позиция доски в списке, заданная пользователем.
+  #[serde(default)]
+  pub position: Option<i64>,
+}
+
+/// Порядок досок в списке.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BoardSort {
+  /// В порядке, в котором доски появились у пользователя.
+  Added,
+  /// По названию.
+  Title,
+  /// Сначала доски, изменённые последними.
+  Activity,
+  /// Сначала доски, созданные последними.
+  Created,
+  /// По позициям, заданным пользователем; доски без позиции - в конце, в порядке появления у пользователя.
+  Custom,
 }
 
 /// Заголовок доски.
@@ -147,8 +603,95 @@ pub enum BoardBackground {
   Url { url: String }
 }
 
+/// Приоритет задачи или подзадачи.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+  Low,
+  #[default]
+  Normal,
+  High,
+  Urgent,
+}
+
+/// Распространение статуса выполнения подзадач на задачу.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecPropagation {
+  /// Статус задачи не зависит от подзадач.
+  #[default]
+  Off,
+  /// Задача отмечается выполненной, когда выполнены все её подзадачи.
+  Complete,
+  /// Задача отмечается выполненной, когда выполнены все её подзадачи, и снова становится невыполненной, когда с какой-либо из них снимают отметку.
+  CompleteAndReopen,
+}
+
+/// Настройки доски.
+#[derive(Default, Deserialize, Serialize)]
+pub struct BoardSettings {
+  /// Распространение статуса выполнения подзадач на задачи доски.
+  #[serde(default)]
+  pub exec_propagation: ExecPropagation,
+  /// Правила автоматизации доски (см. `core::automation`).
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub rules: Vec<Rule>,
+  /// Уборка выполненных задач доски (см. `core::recycle`). Если не задана, выполненные задачи остаются на месте.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub recycle: Option<RecycleSettings>,
+}
+
+/// Уборка выполненных задач: задачи, выполненные больше `after_days` дней назад, переносятся в карточку `card_id` или в архив задач доски.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RecycleSettings {
+  pub after_days: u32,
+  /// Карточка для выполненных задач. Если не задана, задачи убираются с доски в её архив задач.
+  #[serde(default)]
+  pub card_id: Option<i64>,
+}
+
+/// Правило автоматизации: когда с задачей доски происходит событие `trigger`, с ней выполняются действия `actions`.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+  /// Название правила.
+  #[serde(default)]
+  pub title: String,
+  pub trigger: RuleTrigger,
+  /// Правило срабатывает только для задач этой карточки; если карточка не задана - для задач всей доски.
+  #[serde(default)]
+  pub card_id: Option<i64>,
+  pub actions: Vec<RuleAction>,
+}
+
+/// Событие, при котором срабатывает правило автоматизации.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleTrigger {
+  /// Задача отмечена выполненной.
+  TaskCompleted,
+  /// К задаче прикреплён тег.
+  TagAdded { tag_id: i64 },
+  /// Прошёл обязательный срок выполнения задачи (см. `Task::overdue`).
+  DeadlinePassed,
+}
+
+/// Действие правила автоматизации над задачей.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+  /// Перенести задачу в карточку.
+  MoveToCard { card_id: i64 },
+  /// Назначить участника доски исполнителем задачи.
+  Assign { user_id: i64 },
+  /// Прикрепить к задаче тег из словаря доски.
+  AddTag { tag_id: i64 },
+}
+
 /// Доска.
 #[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Board {
   /// Уникальный идентификатор доски в базе данных.
   pub id: i64,
@@ -162,20 +705,664 @@ pub struct Board {
   pub cards: Vec<Card>,
   /// Фон доски.
   pub background: BoardBackground,
+  /// Словарь тегов доски.
+  #[serde(default)]
+  pub tags: Vec<Tag>,
+  /// Дорожки доски.
+  #[serde(default)]
+  pub lanes: Vec<Lane>,
+  /// Спринты доски.
+  #[serde(default)]
+  pub sprints: Vec<Sprint>,
+  /// Ревизия доски, увеличивается при каждом изменении.
+  #[serde(default)]
+  pub revision: i64,
+  /// Настройки доски.
+  #[serde(default)]
+  pub settings: BoardSettings,
+  /// Участники доски, следящие за всеми её изменениями (см. `core::notifications`).
+  #[serde(default)]
+  pub watchers: Vec<i64>,
+  /// Время создания (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub created_at: i64,
+  /// Время последнего изменения (UNIX-время в секундах). Поддерживается сервером.
+  #[serde(default)]
+  pub updated_at: i64,
+  /// Время переноса доски в архив (UNIX-время в секундах); 0 - доска не в архиве. Доска в архиве доступна только для чтения (см. `core::retention`). Поддерживается сервером.
+  #[serde(default)]
+  pub archived_at: i64,
+}
+
+/// Новая доска, которую передаёт клиент.
+///
+/// Автором доски становится создающий её пользователь, а участников, содержимое и служебные поля задаёт сервер. Эти поля, которые передают клиенты, отправляющие доску целиком, принимаются для совместимости и не учитываются.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewBoard {
+  pub header: BoardHeader,
+  pub background: BoardBackground,
+  #[serde(default)]
+  pub settings: BoardSettings,
+  /// Организация, в которой создаётся доска (см. `core::orgs`).
+  #[serde(default)]
+  pub org_id: Option<i64>,
+  #[serde(default, rename = "id")]
+  _id: IgnoredAny,
+  #[serde(default, rename = "author")]
+  _author: IgnoredAny,
+  #[serde(default, rename = "shared_with")]
+  _shared_with: IgnoredAny,
+  #[serde(default, rename = "cards")]
+  _cards: IgnoredAny,
+  #[serde(default, rename = "tags")]
+  _tags: IgnoredAny,
+  #[serde(default, rename = "lanes")]
+  _lanes: IgnoredAny,
+  #[serde(default, rename = "sprints")]
+  _sprints: IgnoredAny,
+  #[serde(default, rename = "watchers")]
+  _watchers: IgnoredAny,
+  #[serde(default, rename = "revision")]
+  _revision: IgnoredAny,
+  #[serde(default, rename = "created_at")]
+  _created_at: IgnoredAny,
+  #[serde(default, rename = "updated_at")]
+  _updated_at: IgnoredAny,
+  #[serde(default, rename = "archived_at")]
+  _archived_at: IgnoredAny,
+}
+
+/// Доска, загруженная один раз на запрос.
+///
+/// Создаётся роутером после проверки доступа пользователя и передаётся в функции `core`, которые изменяют доску в памяти и записывают её одним выражением.
+pub struct BoardContext {
+  /// Пользователь, выполняющий запрос.
+  pub user_id: i64,
+  /// Загруженная доска.
+  pub board: Board,
+  /// Получатели уведомлений на доске в момент загрузки или последней записи (см. `core::notifications`).
+  pub interests: HashSet<Interest>,
+  /// Выполненные задачи и теги задач в момент загрузки или последней записи (см. `core::automation`).
+  pub facts: HashSet<Fact>,
+  /// Доска в том виде, в котором она записана в базе данных, в момент загрузки или последней записи (см. `core::delta`).
+  pub stored: StoredBoard,
+}
+
+/// JSON колонок доски, изменения которых попадают в журнал изменений (см. `core::delta`).
+pub struct StoredBoard {
+  pub author: String,
+  pub shared_with: String,
+  pub header: String,
+  pub cards: String,
+  pub background: String,
+  pub tags: String,
+  pub settings: String,
+  pub lanes: String,
+  pub sprints: String,
+  pub watchers: String,
+}
+
+/// Запрос нескольких досок пользователя.
+#[derive(Deserialize)]
+pub struct BulkBoardsRequest {
+  pub board_ids: Vec<i64>,
+  /// Добавить к доскам сводки по их задачам.
+  #[serde(default)]
+  pub summary: bool,
+}
+
+/// Фильтр задач доски.
+///
+/// Задача попадает в выдачу, если удовлетворяет всем заданным условиям; незаданные условия не проверяются.
+#[derive(Deserialize, Serialize)]
+pub struct BoardFilter {
+  /// У задачи есть хотя бы один из перечисленных тегов.
+  pub tags: Option<Vec<i64>>,
+  /// Пользователь назначен исполнителем задачи или одной из её подзадач.
+  pub executor: Option<i64>,
+  /// Хотя бы один из перечисленных пользователей назначен исполнителем задачи или одной из её подзадач.
+  pub executors: Option<Vec<i64>>,
+  /// Статус выполнения задачи.
+  pub exec: Option<bool>,
+  /// Задача не выполнена, а обязательный срок её выполнения уже прошёл.
+  pub overdue: Option<bool>,
+  /// Задача находится в данной дорожке.
+  pub lane_id: Option<i64>,
+  /// Задача входит в данный спринт.
+  pub sprint_id: Option<i64>,
+  /// У задачи есть невыполненные зависимости.
+  pub blocked: Option<bool>,
+  /// Приоритет задачи - один из перечисленных.
+  pub priorities: Option<Vec<Priority>>,
+  /// Текст, который без учёта регистра встречается в названии, описании или заметках задачи или одной из её подзадач.
+  pub query: Option<String>,
+}
+
+/// Порядок задач в карточках.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskSort {
+  /// В порядке, в котором задачи расположены в карточке.
+  #[default]
+  Position,
+  /// По названию.
+  Title,
+  /// По обязательному сроку выполнения; задачи без срока - в конце.
+  MaxTime,
+  /// Сначала задачи, изменённые последними.
+  Updated,
+  /// Сначала задачи, созданные последними.
+  Created,
+  /// Сначала задачи с наивысшим приоритетом.
+  Priority,
+}
+
+/// Представление доски - сохранённые пользователем фильтр и порядок задач.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardView {
+  /// Уникальный идентификатор представления. Назначается сервером.
+  #[serde(default)]
+  pub id: i64,
+  /// Название представления, уникальное среди представлений пользователя на доске.
+  pub name: String,
+  /// Фильтр задач.
+  #[serde(default)]
+  pub filter: Option<BoardFilter>,
+  /// Порядок задач.
+  #[serde(default)]
+  pub sort: TaskSort,
+}
+
+/// Патч доски. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct BoardPatch {
+  /// Название доски.
+  pub title: Option<String>,
+  /// Фон доски.
+  pub background: Option<BoardBackground>,
+  /// Цвет фона заголовка.
+  pub header_background_color: Option<String>,
+  /// Цвет текста заголовка.
+  pub header_text_color: Option<String>,
+  /// Настройки доски.
+  pub settings: Option<BoardSettings>,
+}
+
+/// Патч карточки. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct CardPatch {
+  /// Название карточки.
+  pub title: Option<String>,
+  /// Описание карточки.
+  pub description: Option<String>,
+  /// Цвет фона карточки.
+  pub background_color: Option<String>,
+  /// Цвет текста заголовка.
+  pub header_text_color: Option<String>,
+  /// Цвет фона заголовка.
+  pub header_background_color: Option<String>,
+  /// Обложка карточки.
+  ///
+  /// Значение `null` убирает обложку.
+  #[serde(default, deserialize_with = "nullable")]
+  pub cover: Option<Option<CardCover>>,
+  /// Оформление карточки.
+  ///
+  /// Значение `null` возвращает оформление по умолчанию.
+  #[serde(default, deserialize_with = "nullable")]
+  pub theme: Option<Option<CardTheme>>,
+  /// Наибольшее число задач в карточке.
+  ///
+  /// Значение `null` снимает ограничение.
+  #[serde(default, deserialize_with = "nullable")]
+  pub wip_limit: Option<Option<NonZeroU32>>,
+}
+
+/// Патч задачи. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct TaskPatch {
+  /// Название задачи.
+  pub title: Option<String>,
+  /// Назначенные исполнители задачи.
+  pub executors: Option<Vec<i64>>,
+  /// Статус выполнения задачи.
+  pub exec: Option<bool>,
+  /// Описание задачи.
+  pub description: Option<String>,
+  /// Заметки к задаче.
+  pub notes: Option<String>,
+  /// Приоритет задачи.
+  pub priority: Option<Priority>,
+  /// Распространение статуса выполнения подзадач на задачу.
+  ///
+  /// Значение `null` возвращает задаче настройку доски.
+  #[serde(default, deserialize_with = "nullable")]
+  pub exec_propagation: Option<Option<ExecPropagation>>,
+  /// Дорожка доски.
+  ///
+  /// Значение `null` убирает задачу из дорожки.
+  #[serde(default, deserialize_with = "nullable")]
+  pub lane_id: Option<Option<i64>>,
+  /// Спринт доски.
+  ///
+  /// Значение `null` убирает задачу из спринта.
+  #[serde(default, deserialize_with = "nullable")]
+  pub sprint_id: Option<Option<i64>>,
+  /// Оценка задачи в очках.
+  ///
+  /// Значение `null` убирает оценку.
+  #[serde(default, deserialize_with = "nullable")]
+  pub story_points: Option<Option<u32>>,
+  /// Ревизия доски, над которой сделано изменение.
+  ///
+  /// Если задана, поля, которые после неё изменил кто-то другой, изменяются только тогда, когда патч не меняет их значения; в противном случае патч не применяется (см. `core::task_history::conflicts`). Если не задана, патч применяется поверх любых изменений.
+  pub base_revision: Option<i64>,
+}
+
+impl TaskPatch {
+  /// Возвращает названия полей задачи, которые изменяет патч.
+  pub fn fields(&self) -> Vec<&'static str> {
+    [
+      ("title", self.title.is_some()),
+      ("executors", self.executors.is_some()),
+      ("exec", self.exec.is_some()),
+      ("description", self.description.is_some()),
+      ("notes", self.notes.is_some()),
+      ("priority", self.priority.is_some()),
+      ("exec_propagation", self.exec_propagation.is_some()),
+      ("lane_id", self.lane_id.is_some()),
+      ("sprint_id", self.sprint_id.is_some()),
+      ("story_points", self.story_points.is_some()),
+    ].into_iter().filter(|(_, set)| *set).map(|(field, _)| field).collect()
+  }
+}
+
+/// Патч подзадачи. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct SubtaskPatch {
+  /// Название подзадачи.
+  pub title: Option<String>,
+  /// Назначенные исполнители подзадачи.
+  pub executors: Option<Vec<i64>>,
+  /// Статус выполнения подзадачи.
+  pub exec: Option<bool>,
+  /// Описание подзадачи.
+  pub description: Option<String>,
+  /// Заметки к подзадаче.
+  pub notes: Option<String>,
+  /// Приоритет подзадачи.
+  pub priority: Option<Priority>,
+}
+
+/// Патч тега в словаре доски. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct TagPatch {
+  /// Название метки.
+  pub title: Option<String>,
+  /// Цвет текста метки.
+  pub text_color: Option<String>,
+  /// Цвет фона метки.
+  pub background_color: Option<String>,
+}
+
+/// Патч дорожки доски. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct LanePatch {
+  /// Название дорожки.
+  pub title: Option<String>,
+  /// Цвет дорожки.
+  pub color: Option<String>,
 }
 
-/// Пользователь.
+/// Патч спринта доски. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct SprintPatch {
+  /// Название спринта.
+  pub title: Option<String>,
+  /// Начало спринта.
+  pub start: Option<i64>,
+  /// Конец спринта.
+  pub end: Option<i64>,
+}
+
+/// Патч настроек доски, заданных пользователем. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct BoardPrefsPatch {
+  /// Отметить доску как избранную.
+  pub favorite: Option<bool>,
+  /// Отключить уведомления об изменениях доски.
+  pub muted: Option<bool>,
+  /// Позиция доски в списке. Значение `null` убирает позицию.
+  #[serde(default, deserialize_with = "nullable")]
+  pub position: Option<Option<i64>>,
+}
+
+/// Отметка уведомлений прочитанными.
+#[derive(Deserialize)]
+pub struct NotificationsRead {
+  /// Идентификаторы уведомлений. Если не заданы, прочитанными отмечаются все уведомления пользователя.
+  pub ids: Option<Vec<i64>>,
+}
+
+/// Периодичность дайджеста изменений досок и отчётов о досках.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestCadence {
+  #[default]
+  Daily,
+  Weekly,
+}
+
+/// Настройки уведомлений пользователя по электронной почте.
+#[derive(Default, Serialize)]
+pub struct NotificationPrefs {
+  /// Адрес, на который отправляются дайджесты. Пока он не задан, дайджесты не отправляются.
+  pub email: Option<String>,
+  pub digest: DigestCadence,
+  /// Пользователь отписался от дайджестов.
+  pub unsubscribed: bool,
+}
+
+/// Патч настроек уведомлений пользователя. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct NotificationPrefsPatch {
+  /// Адрес для дайджестов. Значение `null` убирает адрес.
+  #[serde(default, deserialize_with = "nullable")]
+  pub email: Option<Option<String>>,
+  pub digest: Option<DigestCadence>,
+  pub unsubscribed: Option<bool>,
+}
+
+/// Отчёт о доске, который сервер отправляет по расписанию (см. `core::reports`).
 #[derive(Deserialize, Serialize)]
-pub struct User {
+#[serde(deny_unknown_fields)]
+pub struct BoardReport {
+  /// Идентификатор отчёта. Если не задан, создаётся новый отчёт.
+  #[serde(default)]
+  pub id: i64,
+  pub cadence: DigestCadence,
+  #[serde(default)]
+  pub format: ReportFormat,
+  pub target: ReportTarget,
+  /// Время последней отправки отчёта в секундах Unix. Поддерживается сервером: значение, переданное клиентом, игнорируется.
+  #[serde(default)]
+  pub sent_at: i64,
+}
+
+/// Вид отчёта о доске.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+  /// Текст в разметке Markdown.
+  #[default]
+  Markdown,
+  /// JSON со статистикой доски.
+  Json,
+}
+
+/// Получатель отчёта о доске.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReportTarget {
+  /// Вебхук, на который отчёт передаётся запросом `POST`.
+  Webhook { url: String },
+  /// Чат Telegram, в который отчёт отправляет бот сервера.
+  Telegram { chat_id: String },
+}
+
+/// Роль участника организации.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgRole {
+  /// Управляет составом организации, её досками, ограничениями и правилами.
+  Admin,
+  /// Становится участником всех досок организации.
+  #[default]
+  Member,
+  /// Получает доступ только к доскам организации, которыми с ним поделились явно.
+  Guest,
+}
+
+/// Кто может добавлять доски в организацию.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoardCreators {
+  /// Участники и администраторы.
+  #[default]
+  Members,
+  /// Только администраторы.
+  Admins,
+}
+
+/// Правила организации, которые задают её администраторы.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OrgPolicy {
+  /// Кто может создавать доски в организации и передавать ей свои доски. Гости не могут этого никогда.
+  pub board_creators: BoardCreators,
+  /// Роль, которую получает пользователь, добавленный в организацию без указания роли: `member` или `guest`.
+  pub default_role: OrgRole,
+}
+
+/// Ограничения организации, которые задают её администраторы. Отсутствующее ограничение означает, что количество не ограничено.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrgQuota {
+  /// Число досок организации.
+  pub max_boards: Option<u64>,
+  /// Число участников организации.
+  pub max_members: Option<u64>,
+}
+
+/// Участник организации.
+#[derive(Serialize)]
+pub struct OrgMember {
+  pub user_id: i64,
+  pub role: OrgRole,
+}
+
+/// Организация (см. `core::orgs`).
+#[derive(Serialize)]
+pub struct Organization {
+  pub id: i64,
+  pub name: String,
+  pub quota: OrgQuota,
+  pub policy: OrgPolicy,
+  /// Участники организации в порядке вступления.
+  pub members: Vec<OrgMember>,
+  /// Доски организации.
+  pub boards: Vec<i64>,
+}
+
+/// Организация в списке организаций пользователя.
+#[derive(Serialize)]
+pub struct OrgSummary {
+  pub id: i64,
+  pub name: String,
+  /// Роль пользователя в организации.
+  pub role: OrgRole,
+}
+
+/// Патч организации. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct OrgPatch {
+  pub name: Option<String>,
+  /// Ограничения организации. Заменяются целиком.
+  pub quota: Option<OrgQuota>,
+  /// Правила организации. Заменяются целиком.
+  pub policy: Option<OrgPolicy>,
+}
+
+/// Патч публичного профиля пользователя. Незаданные поля не изменяются.
+#[derive(Deserialize)]
+pub struct ProfilePatch {
+  /// Отображаемое имя.
+  pub display_name: Option<String>,
+  /// Цвет аватара.
+  pub avatar_color: Option<String>,
+}
+
+/// Запрос подписанной ссылки (см. `sec::signing`).
+#[derive(Deserialize)]
+pub struct SignedUrlRequest {
+  /// Путь метода чтения вместе с параметрами строки запроса.
+  pub path: String,
+  /// Число секунд, в течение которых действует ссылка.
+  pub ttl_secs: Option<i64>,
+}
+
+/// Десериализует поле, которое может отсутствовать (`None`) или быть равным `null` (`Some(None)`).
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+  where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+  Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Публичный профиль пользователя.
+///
+/// Позволяет клиентам отображать авторов и исполнителей не по идентификаторам, а по именам.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UserProfile {
   /// Идентификатор пользователя в базе данных.
   pub id: i64,
-  /// Доступные доски.
-  pub shared_boards: Vec<i64>,
-  /// Сведения авторизации пользователя.
-  pub user_creds: UserCredentials,
+  /// Отображаемое имя.
+  pub display_name: String,
+  /// Цвет аватара в формате `#RRGGBB`.
+  pub avatar_color: String,
+}
+
+impl Timelines {
+  /// Проверяет, прошёл ли обязательный срок выполнения.
+  ///
+  /// Нулевой срок означает, что временные рамки не установлены.
+  pub fn is_overdue(&self, now: &DateTime<Utc>) -> bool {
+    self.max_time.timestamp() != 0 && self.max_time < *now
+  }
+  
+  /// Проверяет, что обязательный срок выполнения ещё не прошёл, но наступит не позже, чем через `DUE_SOON_SECS`.
+  pub fn is_due_soon(&self, now: &DateTime<Utc>) -> bool {
+    self.max_time.timestamp() != 0 && self.max_time >= *now && (self.max_time - *now).num_seconds() <= DUE_SOON_SECS
+  }
+}
+
+/// Число секунд до обязательного срока выполнения, начиная с которого задача считается близкой к сроку.
+pub const DUE_SOON_SECS: i64 = 24 * 60 * 60;
+
+impl TaskSort {
+  /// Упорядочивает задачи карточки. Задачи с равными ключами сохраняют взаимный порядок.
+  pub fn apply(self, tasks: &mut [Task]) {
+    match self {
+      TaskSort::Position => {},
+      TaskSort::Title => tasks.sort_by_cached_key(|task| task.title.to_lowercase()),
+      TaskSort::MaxTime => tasks.sort_by_key(|task| match task.timelines.max_time.timestamp() {
+        0 => i64::MAX,
+        max_time => max_time,
+      }),
+      TaskSort::Updated => tasks.sort_by_key(|task| std::cmp::Reverse(task.updated_at)),
+      TaskSort::Created => tasks.sort_by_key(|task| std::cmp::Reverse(task.created_at)),
+      TaskSort::Priority => tasks.sort_by_key(|task| std::cmp::Reverse(task.priority)),
+    };
+  }
+}
+
+impl Board {
+  /// Собирает идентификаторы всех пользователей, упомянутых на доске: автора, участников, авторов, исполнителей и наблюдателей.
+  pub fn mentioned_users(&self) -> Vec<i64> {
+    let mut ids: Vec<i64> = vec![self.author];
+    ids.extend(&self.shared_with);
+    for card in &self.cards {
+      ids.push(card.author);
+      for task in &card.tasks {
+        ids.push(task.author);
+        ids.extend(&task.executors);
+        ids.extend(&task.watchers);
+        for subtask in &task.subtasks {
+          ids.push(subtask.author);
+          ids.extend(&subtask.executors);
+        };
+      };
+    };
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+  }
+}
+
+impl Subtask {
+  /// Отмечает подзадачу как созданную в момент `now`.
+  pub fn stamp_created(&mut self, now: i64) {
+    self.created_at = now;
+    self.updated_at = now;
+  }
 }
 
 impl Task {
+  /// Отмечает задачу вместе с подзадачами как созданную в момент `now`.
+  pub fn stamp_created(&mut self, now: i64) {
+    self.created_at = now;
+    self.updated_at = now;
+    self.subtasks.iter_mut().for_each(|subtask| subtask.stamp_created(now));
+  }
+  
+  /// Обновляет статус выполнения задачи по статусам её подзадач.
+  ///
+  /// Режим задаётся самой задачей, а если он в ней не задан - переданной настройкой доски. Задачи без подзадач не затрагиваются.
+  pub fn propagate_exec(&mut self, board_default: ExecPropagation) {
+    if self.subtasks.is_empty() { return; };
+    let all_exec = self.subtasks.iter().all(|s| s.exec);
+    match self.exec_propagation.unwrap_or(board_default) {
+      ExecPropagation::Off => {},
+      ExecPropagation::Complete => if all_exec { self.exec = true; },
+      ExecPropagation::CompleteAndReopen => self.exec = all_exec,
+    };
+  }
+
+  /// Проверяет, удовлетворяет ли задача фильтру.
+  pub fn matches(&self, filter: &BoardFilter, now: &DateTime<Utc>) -> bool {
+    if let Some(tags) = &filter.tags {
+      if !self.tags.iter().any(|id| tags.contains(id)) { return false; };
+    };
+    if let Some(executor) = &filter.executor {
+      if !self.executors.contains(executor) &&
+         !self.subtasks.iter().any(|st| st.executors.contains(executor)) { return false; };
+    };
+    if let Some(executors) = &filter.executors {
+      if !self.executors.iter().any(|id| executors.contains(id)) &&
+         !self.subtasks.iter().flat_map(|st| &st.executors).any(|id| executors.contains(id)) { return false; };
+    };
+    if let Some(exec) = filter.exec {
+      if self.exec != exec { return false; };
+    };
+    if let Some(overdue) = filter.overdue {
+      if (!self.exec && self.timelines.is_overdue(now)) != overdue { return false; };
+    };
+    if let Some(lane_id) = filter.lane_id {
+      if self.lane_id != Some(lane_id) { return false; };
+    };
+    if let Some(sprint_id) = filter.sprint_id {
+      if self.sprint_id != Some(sprint_id) { return false; };
+    };
+    if let Some(blocked) = filter.blocked {
+      if self.blocked != blocked { return false; };
+    };
+    if let Some(priorities) = &filter.priorities {
+      if !priorities.contains(&self.priority) { return false; };
+    };
+    if let Some(query) = &filter.query {
+      let query = query.to_lowercase();
+      let found = |text: &str| text.to_lowercase().contains(&query);
+      if ![&self.title, &self.description, &self.notes].into_iter().any(|text| found(text)) &&
+         !self.subtasks.iter().any(|st| [&st.title, &st.description, &st.notes].into_iter().any(|text| found(text))) {
+        return false;
+      };
+    };
+    true
+  }
+  
   /// Возвращает мутабельную ссылку на подзадачу.
   pub fn get_mut_subtask(&mut self, subtask_id: &i64) -> Result<&mut Subtask, GetMutSubtaskError> {
     let subtask_index: Option<usize> = self.subtasks.iter().position(|st| st.id == *subtask_id);
@@ -208,6 +1395,13 @@ impl Task {
 }
 
 impl Card {
+  /// Отмечает карточку вместе с задачами и подзадачами как созданную в момент `now`.
+  pub fn stamp_created(&mut self, now: i64) {
+    self.created_at = now;
+    self.updated_at = now;
+    self.tasks.iter_mut().for_each(|task| task.stamp_created(now));
+  }
+  
   /// Возвращает мутабельную ссылку на задачу.
   pub fn get_mut_task(&mut self, task_id: &i64) -> Result<&mut Task, GetMutTaskError> {
     let task_index: Option<usize> = self.tasks.iter().position(|t| t.id == *task_id);
@@ -278,6 +1472,9 @@ pub trait Cards {
   fn remove_card(&mut self, card_id: &i64) -> Result<Card, CardRemoveError>;
   fn remove_task(&mut self, card_id: &i64, task_id: &i64) -> Result<Task, TaskRemoveError>;
   fn remove_subtask(&mut self, card_id: &i64, task_id: &i64, subtask_id: &i64) -> Result<Subtask, SubtaskRemoveError>;
+  fn refresh_overdue(&mut self, now: &DateTime<Utc>) -> Vec<(i64, i64, bool)>;
+  fn refresh_due_soon(&mut self, now: &DateTime<Utc>) -> Vec<(i64, i64, bool)>;
+  fn refresh_task_counts(&mut self);
 }
 
 impl Cards for Vec<Card> {
@@ -366,30 +1563,100 @@ impl Cards for Vec<Card> {
     let card_index: usize = card_index.unwrap();
     self[card_index].remove_subtask(task_id, subtask_id)
   }
+  
+  /// Пересчитывает признак просроченности у всех задач.
+  ///
+  /// Возвращает задачи, у которых признак изменился: идентификаторы карточки и задачи и новое значение признака.
+  fn refresh_overdue(&mut self, now: &DateTime<Utc>) -> Vec<(i64, i64, bool)> {
+    let mut changed = vec![];
+    for card in self.iter_mut() {
+      for task in &mut card.tasks {
+        let overdue = !task.exec && task.timelines.is_overdue(now);
+        if overdue != task.overdue { changed.push((card.id, task.id, overdue)); };
+        task.overdue = overdue;
+      };
+    };
+    changed
+  }
+  
+  /// Пересчитывает признак близости срока у всех задач.
+  ///
+  /// Возвращает изменения признака в виде троек (карточка, задача, новое значение).
+  fn refresh_due_soon(&mut self, now: &DateTime<Utc>) -> Vec<(i64, i64, bool)> {
+    let mut changed = vec![];
+    for card in self.iter_mut() {
+      for task in &mut card.tasks {
+        let due_soon = !task.exec && task.timelines.is_due_soon(now);
+        if due_soon != task.due_soon { changed.push((card.id, task.id, due_soon)); };
+        task.due_soon = due_soon;
+      };
+    };
+    changed
+  }
+  
+  /// Пересчитывает число задач во всех карточках.
+  fn refresh_task_counts(&mut self) {
+    for card in self.iter_mut() {
+      card.task_count = card.tasks.len();
+    };
+  }
 }
 
+/// Наибольший размер тела запроса в байтах (в кодировке base64 или MessagePack).
+pub const MAX_BODY_LEN: usize = 8 * 1024 * 1024;
+
+/// Наибольшая глубина вложенности JSON или MessagePack в теле запроса.
+///
+/// Самая глубокая сущность модели - временные рамки подзадачи в доске - вложена на 11 уровней.
+pub const MAX_JSON_DEPTH: usize = 32;
+
 // Возможные ошибки при извлечении данных из тела HTTP-запроса.
 custom_error!{ pub ExtractionError
   FromBody = "Не удалось получить данные из тела запроса.",
+  TooLarge = "Тело запроса слишком большое.",
   FromBytes = "Не удалось создать строку из набора байт тела запроса.",
   FromBase64 = "Не удалось декодировать данные из base64.",
-  FromJson = "Не удалось десериализовать JSON."
+  TooDeep = "Слишком глубокая вложенность JSON.",
+  FromJson{reason: String} = "Не удалось десериализовать JSON: {reason}",
+  FromMsgpack{reason: String} = "Не удалось десериализовать MessagePack: {reason}"
+}
+
+/// Тип содержимого тела в кодировке MessagePack.
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// Проверяет, что значение заголовка `Content-Type` или `Accept` называет кодировку MessagePack.
+pub fn is_msgpack(header: Option<&hyper::header::HeaderValue>) -> bool {
+  header.and_then(|value| value.to_str().ok()).is_some_and(|value| {
+    value.split(',').any(|media| media.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(MSGPACK_CONTENT_TYPE))
+  })
 }
 
 /// Извлекает данные из тела HTTP-запроса.
 ///
 /// Преобразует тело запроса в строку, декодирует кодировку base64, парсит результат в тип T и возвращает.
+///
+/// Если в заголовке `Content-Type` передан `application/msgpack`, тело вместо JSON в base64 содержит те же данные в кодировке MessagePack, а его структуры должны быть словарями с именами полей.
+///
+/// Тело считывается не больше чем на `MAX_BODY_LEN` байт, а JSON с вложенностью глубже `MAX_JSON_DEPTH` отклоняется до десериализации. Сущности доски не допускают неизвестных полей: ошибка десериализации называет лишнее поле.
 pub async fn extract<T>(req: Request<Body>) -> Result<T, ExtractionError>
   where
     T: DeserializeOwned,
 {
-  let body = match to_bytes(req.into_body()).await {
-    Err(_) => return Err(ExtractionError::FromBody),
-    Ok(v) => v,
+  let msgpack = is_msgpack(req.headers().get("Content-Type"));
+  let mut req_body = req.into_body();
+  let mut body: Vec<u8> = Vec::new();
+  while let Some(chunk) = req_body.data().await {
+    let chunk = match chunk {
+      Err(_) => return Err(ExtractionError::FromBody),
+      Ok(v) => v,
+    };
+    if body.len() + chunk.len() > MAX_BODY_LEN { return Err(ExtractionError::TooLarge); };
+    body.extend_from_slice(&chunk);
   };
-  let body = match String::from_utf8(body.to_vec()) {
+  if msgpack { return from_msgpack(&body); };
+  let body = match String::from_utf8(body) {
     Err(_) => return Err(ExtractionError::FromBytes),
-    Ok(v) => v.clone(),
+    Ok(v) => v,
   };
   let body = match base64::decode(&body) {
     Err(_) => return Err(ExtractionError::FromBase64),
@@ -398,8 +1665,43 @@ pub async fn extract<T>(req: Request<Body>) -> Result<T, ExtractionError>
       Ok(v) => v,
     },
   };
+  if json_depth(&body) > MAX_JSON_DEPTH { return Err(ExtractionError::TooDeep); };
   match serde_json::from_str::<T>(&body) {
-    Err(_) => Err(ExtractionError::FromJson),
+    Err(e) => Err(ExtractionError::FromJson{ reason: e.to_string() }),
     Ok(v) => Ok(v),
   }
 }
+
+/// Десериализует тело в кодировке MessagePack, отклоняя вложенность глубже `MAX_JSON_DEPTH`.
+///
+/// Значения читаются так же, как из JSON: например, адреса и даты передаются строками.
+fn from_msgpack<T: DeserializeOwned>(body: &[u8]) -> Result<T, ExtractionError> {
+  let mut de = rmp_serde::Deserializer::from_read_ref(body).with_human_readable();
+  de.set_max_depth(MAX_JSON_DEPTH);
+  T::deserialize(&mut de).map_err(|e| match e {
+    rmp_serde::decode::Error::DepthLimitExceeded => ExtractionError::TooDeep,
+    e => ExtractionError::FromMsgpack{ reason: e.to_string() },
+  })
+}
+
+/// Возвращает наибольшую глубину вложенности массивов и объектов в JSON, не разбирая его.
+fn json_depth(json: &str) -> usize {
+  let (mut depth, mut max_depth) = (0usize, 0usize);
+  let (mut in_string, mut escaped) = (false, false);
+  for b in json.bytes() {
+    match (in_string, b) {
+      (true, _) if escaped => escaped = false,
+      (true, b'\\') => escaped = true,
+      (true, b'"') => in_string = false,
+      (true, _) => {},
+      (false, b'"') => in_string = true,
+      (false, b'[' | b'{') => {
+        depth += 1;
+        max_depth = max_depth.max(depth);
+      },
+      (false, b']' | b'}') => depth = depth.saturating_sub(1),
+      _ => {},
+    };
+  };
+  max_depth
+}
@@ -0,0 +1,171 @@
+//! Проверка операций над деревом карточек (`Cards`) на случайных деревьях и последовательностях операций.
+
+use proptest::collection::{btree_map, btree_set, vec};
+use proptest::prelude::*;
+use serde_json::json;
+
+use crate::model::{Card, Cards, Subtask, Task};
+
+/// Дерево карточек в виде идентификаторов: карточки, их задачи и подзадачи в порядке хранения.
+type Shape = Vec<(i64, Vec<(i64, Vec<i64>)>)>;
+
+fn subtask(id: i64) -> Subtask {
+  serde_json::from_value(json!({
+    "id": id, "author": 1, "title": "Подзадача", "executors": [], "exec": false, "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+  })).unwrap()
+}
+
+fn task(id: i64, subtasks: &[i64]) -> Task {
+  let mut task: Task = serde_json::from_value(json!({
+    "id": id, "author": 1, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": 0 }
+  })).unwrap();
+  task.subtasks = subtasks.iter().map(|id| subtask(*id)).collect();
+  task
+}
+
+fn card(id: i64, tasks: &[(i64, Vec<i64>)]) -> Card {
+  let mut card: Card = serde_json::from_value(json!({
+    "id": id, "author": 1, "title": "Карточка", "tasks": [],
+    "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+  })).unwrap();
+  card.tasks = tasks.iter().map(|(id, subtasks)| task(*id, subtasks)).collect();
+  card
+}
+
+fn shape(cards: &[Card]) -> Shape {
+  cards.iter().map(|c| (c.id, c.tasks.iter().map(|t| (t.id, t.subtasks.iter().map(|st| st.id).collect())).collect())).collect()
+}
+
+/// Деревья с уникальными в своих списках идентификаторами, хранящимися в случайном порядке.
+fn shapes() -> impl Strategy<Value = Shape> {
+  let subtasks = btree_set(1..8i64, 0..4).prop_map(Vec::from_iter).prop_shuffle();
+  let tasks = btree_map(1..12i64, subtasks, 0..5).prop_map(Vec::from_iter).prop_shuffle();
+  btree_map(1..16i64, tasks, 0..6).prop_map(Vec::from_iter).prop_shuffle()
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+  GetCard(i64),
+  GetTask(i64, i64),
+  GetSubtask(i64, i64, i64),
+  InsertCard,
+  InsertTask(i64),
+  InsertSubtask(i64, i64),
+  RemoveCard(i64),
+  RemoveTask(i64, i64),
+  RemoveSubtask(i64, i64, i64),
+}
+
+/// Операции над случайными идентификаторами: часть из них есть в дереве, часть - нет.
+fn ops() -> impl Strategy<Value = Vec<Op>> {
+  let (card, task, subtask) = (0..18i64, 0..14i64, 0..10i64);
+  vec(prop_oneof![
+    card.clone().prop_map(Op::GetCard),
+    (card.clone(), task.clone()).prop_map(|(c, t)| Op::GetTask(c, t)),
+    (card.clone(), task.clone(), subtask.clone()).prop_map(|(c, t, st)| Op::GetSubtask(c, t, st)),
+    Just(Op::InsertCard),
+    card.clone().prop_map(Op::InsertTask),
+    (card.clone(), task.clone()).prop_map(|(c, t)| Op::InsertSubtask(c, t)),
+    card.clone().prop_map(Op::RemoveCard),
+    (card.clone(), task.clone()).prop_map(|(c, t)| Op::RemoveTask(c, t)),
+    (card, task, subtask).prop_map(|(c, t, st)| Op::RemoveSubtask(c, t, st)),
+  ], 0..40)
+}
+
+/// Следующий свободный идентификатор в списке - так же, как его выдаёт последовательность идентификаторов.
+fn next_id(ids: impl Iterator<Item = i64>) -> i64 {
+  ids.max().unwrap_or(0) + 1
+}
+
+fn find<T>(list: &[(i64, T)], id: i64) -> Option<usize> {
+  list.iter().position(|(i, _)| *i == id)
+}
+
+fn unique(ids: impl Iterator<Item = i64>) -> bool {
+  let mut seen = std::collections::HashSet::new();
+  ids.into_iter().all(|id| seen.insert(id))
+}
+
+proptest! {
+  #[test]
+  fn cards_follow_reference_model(initial in shapes(), ops in ops()) {
+    let mut cards: Vec<Card> = initial.iter().map(|(id, tasks)| card(*id, tasks)).collect();
+    let mut model = initial;
+    for op in ops {
+      match op {
+        Op::GetCard(c) => {
+          let expected = find(&model, c).is_some();
+          prop_assert_eq!(cards.get_card(&c).map(|card| card.id).ok(), expected.then_some(c));
+          prop_assert_eq!(cards.get_mut_card(&c).map(|card| card.id).ok(), expected.then_some(c));
+        },
+        Op::GetTask(c, t) => {
+          let expected = find(&model, c).and_then(|ci| find(&model[ci].1, t)).is_some();
+          prop_assert_eq!(cards.get_task(&c, &t).map(|task| task.id).ok(), expected.then_some(t));
+          prop_assert_eq!(cards.get_mut_task(&c, &t).map(|task| task.id).ok(), expected.then_some(t));
+        },
+        Op::GetSubtask(c, t, st) => {
+          let expected = find(&model, c)
+            .and_then(|ci| find(&model[ci].1, t).map(|ti| &model[ci].1[ti].1))
+            .is_some_and(|subtasks| subtasks.contains(&st));
+          prop_assert_eq!(cards.get_subtask(&c, &t, &st).map(|st| st.id).ok(), expected.then_some(st));
+          prop_assert_eq!(cards.get_mut_subtask(&c, &t, &st).map(|st| st.id).ok(), expected.then_some(st));
+        },
+        Op::InsertCard => {
+          let id = next_id(model.iter().map(|(id, _)| *id));
+          cards.push(card(id, &[]));
+          model.push((id, vec![]));
+        },
+        Op::InsertTask(c) => match cards.get_mut_card(&c) {
+          Ok(card) => {
+            let ci = find(&model, c).unwrap();
+            let id = next_id(card.tasks.iter().map(|t| t.id));
+            card.tasks.push(task(id, &[]));
+            model[ci].1.push((id, vec![]));
+          },
+          Err(_) => prop_assert!(find(&model, c).is_none()),
+        },
+        Op::InsertSubtask(c, t) => match cards.get_mut_task(&c, &t) {
+          Ok(task) => {
+            let ci = find(&model, c).unwrap();
+            let ti = find(&model[ci].1, t).unwrap();
+            let id = next_id(task.subtasks.iter().map(|st| st.id));
+            task.subtasks.push(subtask(id));
+            model[ci].1[ti].1.push(id);
+          },
+          Err(_) => prop_assert!(find(&model, c).and_then(|ci| find(&model[ci].1, t)).is_none()),
+        },
+        Op::RemoveCard(c) => {
+          let removed = cards.remove_card(&c).map(|card| card.id).ok();
+          prop_assert_eq!(removed, find(&model, c).map(|_| c));
+          model.retain(|(id, _)| *id != c);
+        },
+        Op::RemoveTask(c, t) => {
+          let removed = cards.remove_task(&c, &t).map(|task| task.id).ok();
+          let ci = find(&model, c);
+          prop_assert_eq!(removed, ci.and_then(|ci| find(&model[ci].1, t)).map(|_| t));
+          if let Some(ci) = ci { model[ci].1.retain(|(id, _)| *id != t); };
+        },
+        Op::RemoveSubtask(c, t, st) => {
+          let removed = cards.remove_subtask(&c, &t, &st).map(|st| st.id).ok();
+          let subtasks = find(&model, c).and_then(|ci| find(&model[ci].1, t).map(|ti| &mut model[ci].1[ti].1));
+          let expected = subtasks.as_ref().is_some_and(|subtasks| subtasks.contains(&st)).then_some(st);
+          prop_assert_eq!(removed, expected);
+          if let Some(subtasks) = subtasks { subtasks.retain(|id| *id != st); };
+        },
+      };
+      // Удаление не затрагивает другие сущности и не меняет их порядок, а идентификаторы остаются уникальными.
+      prop_assert_eq!(&shape(&cards), &model);
+      prop_assert!(unique(cards.iter().map(|c| c.id)));
+      for card in &cards {
+        prop_assert!(unique(card.tasks.iter().map(|t| t.id)));
+        for task in &card.tasks {
+          prop_assert!(unique(task.subtasks.iter().map(|st| st.id)));
+        };
+      };
+    };
+    cards.refresh_task_counts();
+    prop_assert!(cards.iter().all(|c| c.task_count == c.tasks.len()));
+  }
+}
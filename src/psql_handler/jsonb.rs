@@ -0,0 +1,111 @@
+//! Отвечает за частичную запись колонок типа `jsonb`.
+//!
+//! Большую колонку, в которой изменилась небольшая часть, дешевле изменить операторами `jsonb` - `jsonb_set`, `jsonb_insert` и `#-` по путям к изменённым значениям, - чем передавать и разбирать заново весь документ. Изменения получаются из операций JSON Patch, которые сервер и так вычисляет для журнала изменений досок (см. `core::delta`).
+//!
+//! Postgres хранит `jsonb` в собственном виде и при чтении выводит его по-своему: с пробелами после `:` и `,` и с ключами объектов, упорядоченными сначала по длине, а затем побайтово. `to_string` выводит значение так же, поэтому JSON, записанный сервером, совпадает с прочитанным из базы данных, и сравнивать их можно как строки.
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tokio_postgres::types::ToSql;
+
+/// Наибольшее число изменений одной колонки. Если изменений больше, колонка записывается целиком.
+const MAX_EDITS: usize = 64;
+
+/// Изменение части значения `jsonb`. Путь состоит из ключей объектов и индексов массивов.
+pub enum Edit {
+  /// Заменяет значение по пути или добавляет ключ объекта.
+  Set { path: Vec<String>, value: String },
+  /// Вставляет элемент массива перед элементом по пути или, если индекс за концом массива, в конец.
+  Insert { path: Vec<String>, value: String },
+  /// Удаляет элемент массива или ключ объекта.
+  Remove { path: Vec<String> },
+}
+
+/// Возвращает изменения колонки по операциям JSON Patch над документом, в котором колонка находится по указателю `pointer`.
+///
+/// Операции над другими колонками пропускаются. Возвращает None, если колонку выгоднее записать целиком: изменений больше `MAX_EDITS`, их значения занимают больше половины нового JSON колонки `full`, или заменяется вся колонка.
+pub fn edits(ops: &[JsonValue], pointer: &str, full: &str) -> Option<Vec<Edit>> {
+  let mut edits = Vec::new();
+  let mut size = 0;
+  for op in ops {
+    let rest = match op["path"].as_str()?.strip_prefix(pointer) {
+      Some("") => return None,
+      Some(rest) if rest.starts_with('/') => rest,
+      _ => continue,
+    };
+    let path: Vec<String> = rest[1..].split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect();
+    let value = || to_string(&op["value"]).ok();
+    let edit = match op["op"].as_str()? {
+      "replace" => Edit::Set { path, value: value()? },
+      "add" => Edit::Insert { path, value: value()? },
+      "remove" => Edit::Remove { path },
+      _ => return None,
+    };
+    if let Edit::Set { value, .. } | Edit::Insert { value, .. } = &edit { size += value.len(); };
+    edits.push(edit);
+  };
+  match edits.len() > MAX_EDITS || size > full.len() / 2 {
+    true => None,
+    false => Some(edits),
+  }
+}
+
+/// Возвращает выражение, применяющее изменения к колонке `column` по порядку, и добавляет в `params` их параметры.
+///
+/// Номера параметров выражения продолжают `params`. Если изменений нет, выражение возвращает колонку как есть.
+pub fn expression<'a>(column: &str, edits: &'a [Edit], params: &mut Vec<&'a (dyn ToSql + Sync)>) -> String {
+  let mut expr = column.to_string();
+  for edit in edits {
+    expr = match edit {
+      Edit::Set { path, value } => {
+        params.extend([path as &(dyn ToSql + Sync), value]);
+        format!("jsonb_set({}, ${}::text[], ${}::text::jsonb)", expr, params.len() - 1, params.len())
+      },
+      Edit::Insert { path, value } => {
+        params.extend([path as &(dyn ToSql + Sync), value]);
+        format!("jsonb_insert({}, ${}::text[], ${}::text::jsonb)", expr, params.len() - 1, params.len())
+      },
+      Edit::Remove { path } => {
+        params.push(path);
+        format!("({} #- ${}::text[])", expr, params.len())
+      },
+    };
+  };
+  expr
+}
+
+/// Выводит значение в JSON так, как Postgres выводит `jsonb`.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+  let mut out = String::new();
+  write(&serde_json::to_value(value)?, &mut out)?;
+  Ok(out)
+}
+
+/// Дописывает значение в `out` в виде, в котором Postgres выводит `jsonb`.
+fn write(value: &JsonValue, out: &mut String) -> serde_json::Result<()> {
+  match value {
+    JsonValue::Array(items) => {
+      out.push('[');
+      for (i, item) in items.iter().enumerate() {
+        if i > 0 { out.push_str(", "); };
+        write(item, out)?;
+      };
+      out.push(']');
+    },
+    JsonValue::Object(map) => {
+      let mut entries: Vec<_> = map.iter().collect();
+      entries.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.as_bytes().cmp(b.as_bytes())));
+      out.push('{');
+      for (i, (key, item)) in entries.into_iter().enumerate() {
+        if i > 0 { out.push_str(", "); };
+        out.push_str(&serde_json::to_string(key)?);
+        out.push_str(": ");
+        write(item, out)?;
+      };
+      out.push('}');
+    },
+    // Строки Postgres экранирует так же, как serde_json, а дробных чисел в данных сервера нет.
+    _ => out.push_str(&serde_json::to_string(value)?),
+  };
+  Ok(())
+}
@@ -0,0 +1,116 @@
+//! Отвечает за замеры времени выполнения запросов к Postgres.
+//!
+//! Каждый запрос `Db` замеряется и относится к методу API, при обработке которого выполнен (см. `scope`); запросы фоновых задач относятся к `background`. Для каждого метода хранятся число запросов, их общее время и последние `SAMPLES` замеров, по которым считаются процентили. Запросы дольше порога из конфигурации (`db_slow_query_ms`) записываются в журнал сервера вместе с идентификатором запроса клиента. Значения параметров в журнал не попадают: в них могут быть пароли и токены.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Число последних замеров метода, по которым считаются процентили.
+const SAMPLES: usize = 1024;
+
+/// Наибольшее число различных методов. Запросы остальных методов, например несуществующих путей, учитываются вместе.
+const MAX_ROUTES: usize = 256;
+
+/// Метод, к которому относятся запросы фоновых задач.
+const BACKGROUND: &str = "background";
+
+/// Метод, к которому относятся запросы сверх `MAX_ROUTES` различных методов.
+const OTHER: &str = "other";
+
+/// Запрос клиента, при обработке которого выполняются запросы к Postgres.
+#[derive(Clone)]
+struct RequestTag {
+  request_id: String,
+  route: String,
+}
+
+tokio::task_local! {
+  static REQUEST: RequestTag;
+}
+
+/// Накопленные замеры одного метода.
+#[derive(Default)]
+struct RouteStats {
+  statements: u64,
+  total: Duration,
+  samples: VecDeque<Duration>,
+}
+
+static STATS: Mutex<Option<HashMap<String, RouteStats>>> = Mutex::new(None);
+
+/// Замеры запросов к Postgres одного метода API.
+#[derive(Serialize)]
+pub struct RouteMetrics {
+  /// Метод и путь, например `PUT /card`.
+  pub route: String,
+  /// Число запросов с запуска сервера.
+  pub statements: u64,
+  /// Общее время запросов с запуска сервера.
+  pub total_ms: f64,
+  /// Процентили и наибольшее время по последним `SAMPLES` запросам.
+  pub p50_ms: f64,
+  pub p90_ms: f64,
+  pub p99_ms: f64,
+  pub max_ms: f64,
+}
+
+/// Выполняет `f`, относя его запросы к Postgres к данному запросу клиента.
+pub async fn scope<F: Future>(request_id: String, route: String, f: F) -> F::Output {
+  REQUEST.scope(RequestTag { request_id, route }, f).await
+}
+
+/// Учитывает выполненный запрос и, если он дольше `slow`, записывает его в журнал сервера.
+pub fn record(statement: &str, params: usize, elapsed: Duration, slow: Option<Duration>) {
+  let tag = REQUEST.try_with(RequestTag::clone).ok();
+  let route = tag.as_ref().map_or(BACKGROUND, |tag| tag.route.as_str());
+  {
+    let mut stats = STATS.lock().unwrap();
+    let stats = stats.get_or_insert_with(HashMap::new);
+    let route = match stats.contains_key(route) || stats.len() < MAX_ROUTES {
+      true => route,
+      false => OTHER,
+    };
+    let entry = stats.entry(route.to_string()).or_default();
+    entry.statements += 1;
+    entry.total += elapsed;
+    if entry.samples.len() == SAMPLES { entry.samples.pop_front(); };
+    entry.samples.push_back(elapsed);
+  }
+  if slow.is_some_and(|slow| elapsed >= slow) {
+    let request_id = tag.as_ref().map_or("-", |tag| tag.request_id.as_str());
+    let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+    eprintln!(
+      "[{}] Медленный запрос к базе данных ({} мс, {}): {} (значения параметров скрыты: {})",
+      request_id, elapsed.as_millis(), route, statement, params
+    );
+  };
+}
+
+/// Возвращает замеры всех методов, начиная с методов с наибольшим общим временем запросов.
+pub fn snapshot() -> Vec<RouteMetrics> {
+  let stats = STATS.lock().unwrap();
+  let mut metrics: Vec<RouteMetrics> = stats.iter().flatten().map(|(route, stats)| {
+    let mut samples: Vec<Duration> = stats.samples.iter().copied().collect();
+    samples.sort();
+    let percentile = |p: usize| samples.get((samples.len() * p / 100).min(samples.len().saturating_sub(1))).copied().map_or(0.0, ms);
+    RouteMetrics {
+      route: route.clone(),
+      statements: stats.statements,
+      total_ms: ms(stats.total),
+      p50_ms: percentile(50),
+      p90_ms: percentile(90),
+      p99_ms: percentile(99),
+      max_ms: samples.last().copied().map_or(0.0, ms),
+    }
+  }).collect();
+  metrics.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+  metrics
+}
+
+/// Переводит длительность в миллисекунды.
+fn ms(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
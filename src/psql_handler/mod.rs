@@ -1,68 +1,346 @@
 //! Отвечает за управление данными.
 
-use bb8::Pool;
+pub mod jsonb;
+pub mod metrics;
+pub mod transaction;
+
+use bb8::{Pool, PooledConnection, RunError};
 use bb8_postgres::PostgresConnectionManager as PgConManager;
 use custom_error::custom_error;
-use futures::future;
-use tokio_postgres::{ToStatement, types::ToSql, row::Row, NoTls};
+use futures::{future, Future, TryStreamExt};
+use hyper::{Body, body::HttpBody};
+use serde_json::Value as JsonValue;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedMutexGuard;
+use tokio_postgres::{Client, Config, IsolationLevel, ToStatement, Transaction, error::SqlState, types::ToSql, row::Row, NoTls};
+
+use crate::setup::AppConfig;
+use transaction::OpenTransaction;
 
 type MResult<T> = Result<T, Box<dyn std::error::Error>>;
 
 custom_error!{NFO{} = "Не удалось получить данные."}
 custom_error!{TNF{} = "Не удалось найти тег по идентификатору."}
 
+/// Политика повторного выполнения запросов при временных ошибках.
+#[derive(Clone)]
+pub struct RetryPolicy {
+  /// Число попыток, включая первую.
+  pub attempts: u32,
+  /// Задержка перед второй попыткой. Перед каждой следующей попыткой задержка удваивается.
+  pub backoff: Duration,
+}
+
+impl RetryPolicy {
+  /// Возвращает задержку перед попыткой, следующей за попыткой с данным номером (начиная с 1).
+  fn delay(&self, attempt: u32) -> Duration {
+    self.backoff.saturating_mul(1 << (attempt - 1).min(16))
+  }
+}
+
+/// Проверяет, что ошибка временная и запрос можно безопасно повторить.
+///
+/// Временными считаются ошибки получения соединения, а также ошибки сериализации и взаимоблокировки, при которых Postgres откатывает транзакцию. Ошибки, возникшие на уже установленном соединении, не повторяются: нельзя узнать, была ли применена транзакция.
+fn is_transient(e: &(dyn std::error::Error + 'static)) -> bool {
+  if let Some(e) = e.downcast_ref::<RunError<tokio_postgres::Error>>() {
+    return match e {
+      RunError::TimedOut => true,
+      RunError::User(e) => e.code().is_none() || is_transient(e),
+    };
+  };
+  match e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code()) {
+    Some(code) => [SqlState::T_R_SERIALIZATION_FAILURE, SqlState::T_R_DEADLOCK_DETECTED, SqlState::TOO_MANY_CONNECTIONS]
+      .contains(code),
+    None => false,
+  }
+}
+
+/// Соединение, на котором выполняется запрос: из пула или соединение транзакции текущей задачи (см. `transaction`).
+enum Conn<'a> {
+  Pooled(PooledConnection<'a, PgConManager<NoTls>>),
+  Transaction(OwnedMutexGuard<Option<OpenTransaction>>),
+}
+
+impl Deref for Conn<'_> {
+  type Target = Client;
+
+  fn deref(&self) -> &Client {
+    match self {
+      Conn::Pooled(cli) => cli,
+      Conn::Transaction(tr) => tr.as_ref().unwrap(),
+    }
+  }
+}
+
 /// Реализует операции ввода-вывода над пулом соединений с базой данных PostgreSQL.
+///
+/// Если текущая задача начала транзакцию (см. `transaction`), запросы выполняются в ней, а выражения, которые записываются в отдельной транзакции, становятся частью общей и повторно при ошибках не выполняются.
 #[derive(Clone)]
 pub struct Db {
   pool: Pool<PgConManager<NoTls>>,
+  retry: RetryPolicy,
+  /// Время, начиная с которого запрос записывается в журнал как медленный.
+  slow_query: Option<Duration>,
 }
 
 impl Db {
   /// Создаёт объект из пула соединений.
-  pub fn new(pool: Pool<PgConManager<NoTls>>) -> Db {
-    Db { pool }
+  pub fn new(pool: Pool<PgConManager<NoTls>>, retry: RetryPolicy, slow_query: Option<Duration>) -> Db {
+    Db { pool, retry, slow_query }
+  }
+  
+  /// Создаёт пул соединений по конфигурации сервера.
+  ///
+  /// Ограничение времени выполнения запросов передаётся Postgres при установке каждого соединения.
+  pub async fn connect(cfg: &AppConfig) -> MResult<Db> {
+    let connect_timeout = Duration::from_secs(cfg.db_connect_timeout_secs);
+    let mut pg: Config = cfg.pg.to_config()?;
+    pg.connect_timeout(connect_timeout);
+    pg.options(format!("-c statement_timeout={}", cfg.db_statement_timeout_secs * 1000));
+    let pool = Pool::builder()
+      .max_size(cfg.db_pool_size)
+      .connection_timeout(connect_timeout)
+      .build(PgConManager::new(pg, NoTls))
+      .await?;
+    let retry = RetryPolicy {
+      attempts: cfg.db_retry_attempts.max(1),
+      backoff: Duration::from_millis(cfg.db_retry_backoff_ms),
+    };
+    let slow_query = (cfg.db_slow_query_ms > 0).then(|| Duration::from_millis(cfg.db_slow_query_ms));
+    Ok(Db::new(pool, retry, slow_query))
+  }
+  
+  /// Выполняет операцию, повторяя её при временных ошибках согласно политике повторов.
+  async fn retrying<R, F, Fut>(&self, op: F) -> MResult<R>
+  where F: Fn() -> Fut, Fut: Future<Output = MResult<R>> {
+    let mut attempt = 1;
+    loop {
+      let delay = match op().await {
+        Ok(res) => return Ok(res),
+        Err(e) if attempt < self.retry.attempts && is_transient(e.as_ref()) => self.retry.delay(attempt),
+        Err(e) => return Err(e),
+      };
+      tokio::time::sleep(delay).await;
+      attempt += 1;
+    }
   }
 
+  /// Выполняет запрос, замеряя его время (см. `metrics`). Время ожидания соединения из пула в замер не входит.
+  async fn timed<R>(&self, statement: &str, params: usize, run: impl Future<Output = R>) -> R {
+    let started = Instant::now();
+    let res = run.await;
+    metrics::record(statement, params, started.elapsed(), self.slow_query);
+    res
+  }
+  
+  /// Возвращает соединение транзакции текущей задачи, если она начата, или соединение из пула.
+  async fn conn(&self) -> MResult<Conn<'_>> {
+    match transaction::current().await {
+      Some(tr) => Ok(Conn::Transaction(tr)),
+      None => Ok(Conn::Pooled(self.pool.get().await?)),
+    }
+  }
+  
+  /// Начинает транзакцию текущей задачи, если задача выполняется в пределах `transaction::scope`. Возвращает `true`, если транзакция начата сейчас или раньше.
+  pub async fn begin_transaction(&self) -> MResult<bool> {
+    let slot = match transaction::slot() {
+      Some(slot) => slot,
+      None => return Ok(false),
+    };
+    let mut slot = slot.lock().await;
+    if slot.is_none() {
+      let cli = self.retrying(|| async { Ok(self.pool.get_owned().await?) }).await?;
+      cli.batch_execute("begin;").await?;
+      *slot = Some(OpenTransaction::new(cli));
+    };
+    Ok(true)
+  }
+  
+  /// Фиксирует транзакцию текущей задачи, если она начата. Следующие запросы задачи выполняются без транзакции, пока она не начнёт новую.
+  pub async fn commit_transaction(&self) -> MResult<()> {
+    let tr = match transaction::slot() {
+      Some(slot) => slot.lock().await.take(),
+      None => None,
+    };
+    if let Some(tr) = tr {
+      tr.finish().batch_execute("commit;").await?;
+    };
+    Ok(())
+  }
+  
   /// Считывает одну строку из базы данных.
   pub async fn read<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> MResult<Row>
-  where T: ?Sized + ToStatement {
-    let cli = self.pool.get().await?;
-    Ok(cli.query_one(statement, params).await?)
+  where T: ?Sized + ToStatement + AsRef<str> {
+    self.retrying(|| async {
+      let cli = self.conn().await?;
+      Ok(self.timed(statement.as_ref(), params.len(), cli.query_one(statement, params)).await?)
+    }).await
+  }
+  
+  /// Считывает все строки, возвращаемые запросом.
+  pub async fn read_all<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> MResult<Vec<Row>>
+  where T: ?Sized + ToStatement + AsRef<str> {
+    self.retrying(|| async {
+      let cli = self.conn().await?;
+      Ok(self.timed(statement.as_ref(), params.len(), cli.query(statement, params)).await?)
+    }).await
   }
   
   /// Записывает одно выражение в базу данных.
   pub async fn write<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> MResult<()>
-  where T: ?Sized + ToStatement {
-    let mut cli = self.pool.get().await?;
-    let tr = cli.transaction().await?;
-    tr.execute(statement, params).await?;
-    tr.commit().await?;
-    Ok(())
+  where T: ?Sized + ToStatement + AsRef<str> {
+    if let Some(tr) = transaction::current().await {
+      self.timed(statement.as_ref(), params.len(), tr.as_ref().unwrap().execute(statement, params)).await?;
+      return Ok(());
+    };
+    self.retrying(|| async {
+      let mut cli = self.pool.get().await?;
+      let tr = cli.transaction().await?;
+      self.timed(statement.as_ref(), params.len(), tr.execute(statement, params)).await?;
+      tr.commit().await?;
+      Ok(())
+    }).await
   }
   
   /// Считывает несколько значений по одной строке из базы данных.
   pub async fn read_mul<T>(&self, parts: Vec<(&T, Vec<&(dyn ToSql + Sync)>)>) -> MResult<Vec<Row>>
-  where T: ?Sized + ToStatement + Send + Sync {
-    let cli = self.pool.get().await?;
-    let mut tasks = Vec::new();
-    for part in &parts {
-      tasks.push(cli.query_one(part.0, &part.1));
-    };
-    let results = future::try_join_all(tasks).await?;
-    Ok(results)
+  where T: ?Sized + ToStatement + AsRef<str> + Send + Sync {
+    self.retrying(|| async {
+      let cli = self.conn().await?;
+      let mut tasks = Vec::new();
+      for part in &parts {
+        tasks.push(self.timed(part.0.as_ref(), part.1.len(), cli.query_one(part.0, &part.1)));
+      };
+      let results = future::try_join_all(tasks).await?;
+      Ok(results)
+    }).await
   }
   
   /// Записывает несколько значений в базу данных.
   pub async fn write_mul<T>(&self, parts: Vec<(&T, Vec<&(dyn ToSql + Sync)>)>) -> MResult<()>
-  where T: ?Sized + ToStatement + Send + Sync {
+  where T: ?Sized + ToStatement + AsRef<str> + Send + Sync {
+    if let Some(tr) = transaction::current().await {
+      let tr = tr.as_ref().unwrap();
+      let tasks = parts.iter().map(|part| self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)));
+      future::try_join_all(tasks).await?;
+      return Ok(());
+    };
+    self.retrying(|| async {
+      let mut cli = self.pool.get().await?;
+      let tr = cli.transaction().await?;
+      let mut tasks = Vec::new();
+      for part in &parts {
+        tasks.push(self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)));
+      };
+      future::try_join_all(tasks).await?;
+      tr.commit().await?;
+      Ok(())
+    }).await
+  }
+  
+  /// Записывает несколько значений в базу данных, если первое выражение затронуло хотя бы одну строку.
+  ///
+  /// В противном случае транзакция откатывается, а функция возвращает `false`. В транзакции текущей задачи остальные выражения в этом случае просто не выполняются.
+  pub async fn write_mul_if<T>(&self, parts: Vec<(&T, Vec<&(dyn ToSql + Sync)>)>) -> MResult<bool>
+  where T: ?Sized + ToStatement + AsRef<str> + Send + Sync {
+    if let Some(tr) = transaction::current().await {
+      let tr = tr.as_ref().unwrap();
+      let mut parts = parts.iter();
+      if let Some(part) = parts.next() {
+        if self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)).await? == 0 { return Ok(false); };
+      };
+      let tasks = parts.map(|part| self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)));
+      future::try_join_all(tasks).await?;
+      return Ok(true);
+    };
+    self.retrying(|| async {
+      let mut cli = self.pool.get().await?;
+      let tr = cli.transaction().await?;
+      let mut parts = parts.iter();
+      if let Some(part) = parts.next() {
+        if self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)).await? == 0 { return Ok(false); };
+      };
+      let mut tasks = Vec::new();
+      for part in parts {
+        tasks.push(self.timed(part.0.as_ref(), part.1.len(), tr.execute(part.0, &part.1)));
+      };
+      future::try_join_all(tasks).await?;
+      tr.commit().await?;
+      Ok(true)
+    }).await
+  }
+  
+  /// Выгружает результаты запросов в поток, не загружая их в память целиком.
+  ///
+  /// Каждый запрос должен возвращать одну текстовую колонку с JSON. Строки результата передаются в теле ответа по одной на строку вида `{"table":"<таблица>","row":<JSON>}`. Все запросы выполняются в одном снимке базы данных, поэтому выгрузка согласована даже тогда, когда сервер продолжает принимать запросы.
+  pub async fn dump(&self, queries: Vec<(&'static str, String)>) -> MResult<Body> {
+    let mut cli = self.pool.get_owned().await?;
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+      let res: MResult<()> = async {
+        let tr = cli.build_transaction()
+                    .isolation_level(IsolationLevel::RepeatableRead)
+                    .read_only(true)
+                    .start()
+                    .await?;
+        // Выгрузка большой базы может занимать больше времени, чем допускается для обычных запросов.
+        tr.batch_execute("set local statement_timeout = 0;").await?;
+        for (table, query) in &queries {
+          let rows = tr.query_raw(query.as_str(), Vec::<String>::new()).await?;
+          futures::pin_mut!(rows);
+          while let Some(row) = rows.try_next().await? {
+            let row: &str = row.get(0);
+            sender.send_data(format!("{{\"table\":\"{}\",\"row\":{}}}\n", table, row).into()).await?;
+          };
+        };
+        tr.commit().await?;
+        Ok(())
+      }.await;
+      // Клиент должен узнать, что выгрузка оборвалась, а не получить неполную копию как целую.
+      if res.is_err() { sender.abort(); };
+    });
+    Ok(body)
+  }
+  
+  /// Загружает строки, выгруженные `dump`, заменяя ими содержимое перечисленных таблиц.
+  ///
+  /// Тело запроса считывается по частям. Все строки загружаются в одной транзакции, после чего в ней же выполняются выражения `finally`; при любой ошибке база данных остаётся в прежнем состоянии. Возвращает количество загруженных строк.
+  pub async fn restore(&self, tables: &[&str], mut body: Body, finally: &[&str]) -> MResult<u64> {
     let mut cli = self.pool.get().await?;
     let tr = cli.transaction().await?;
-    let mut tasks = Vec::new();
-    for part in &parts {
-      tasks.push(tr.execute(part.0, &part.1));
+    tr.batch_execute("set local statement_timeout = 0;").await?;
+    tr.batch_execute(&format!("truncate {};", tables.join(", "))).await?;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut count: u64 = 0;
+    while let Some(chunk) = body.data().await {
+      buf.extend_from_slice(&chunk?);
+      while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = buf.drain(..=pos).collect();
+        count += restore_line(&tr, tables, &line).await?;
+      };
+    };
+    count += restore_line(&tr, tables, &buf).await?;
+    for statement in finally {
+      tr.batch_execute(statement).await?;
     };
-    future::try_join_all(tasks).await?;
     tr.commit().await?;
-    Ok(())
+    Ok(count)
   }
 }
+
+/// Загружает одну строку резервной копии. Пустые строки пропускаются.
+async fn restore_line(tr: &Transaction<'_>, tables: &[&str], line: &[u8]) -> MResult<u64> {
+  custom_error!{UnknownTable{table: String} = "Таблица {table} отсутствует в списке восстанавливаемых."}
+  let line = std::str::from_utf8(line)?.trim();
+  if line.is_empty() { return Ok(0); };
+  let line: JsonValue = serde_json::from_str(line)?;
+  let table = line["table"].as_str().ok_or(NFO{})?;
+  // Имя таблицы подставляется в запрос, поэтому принимаются только заранее известные таблицы.
+  if !tables.contains(&table) { return Err(Box::new(UnknownTable{ table: table.to_string() })); };
+  let row = serde_json::to_string(line.get("row").ok_or(NFO{})?)?;
+  Ok(tr.execute(
+    format!("insert into {0} select * from json_populate_record(null::{0}, $1::text::json);", table).as_str(),
+    &[&row]
+  ).await?)
+}
@@ -0,0 +1,68 @@
+//! Отвечает за транзакции, охватывающие несколько запросов к Postgres.
+//!
+//! Доска изменяется в три шага: строка доски считывается, доска изменяется в памяти, и строка записывается обратно. Чтобы параллельный запрос не изменил доску между чтением и записью, изменяющий доску запрос блокирует её строку (`select ... for update`) в транзакции, которая длится до записи доски (см. `Storage::lock_board`).
+//!
+//! Транзакция относится к задаче tokio, выполняющей запрос клиента или фоновую работу (см. `scope`), и начинается по требованию (см. `Db::begin_transaction`). После этого все запросы `Db` этой задачи выполняются на соединении транзакции, поэтому функции, которым передан `Db`, ничего не знают о транзакции и не ждут освобождения строки, заблокированной их же задачей. Транзакция, которую не зафиксировали до конца `scope`, например из-за ошибки, откатывается.
+
+use bb8::PooledConnection;
+use bb8_postgres::PostgresConnectionManager as PgConManager;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio_postgres::{Client, NoTls};
+
+/// Соединение с открытой транзакцией.
+pub(super) struct OpenTransaction {
+  cli: Option<PooledConnection<'static, PgConManager<NoTls>>>,
+}
+
+impl OpenTransaction {
+  pub(super) fn new(cli: PooledConnection<'static, PgConManager<NoTls>>) -> OpenTransaction {
+    OpenTransaction { cli: Some(cli) }
+  }
+
+  /// Возвращает соединение транзакции, после чего транзакция больше не откатывается при удалении.
+  pub(super) fn finish(mut self) -> PooledConnection<'static, PgConManager<NoTls>> {
+    self.cli.take().unwrap()
+  }
+}
+
+impl Deref for OpenTransaction {
+  type Target = Client;
+
+  fn deref(&self) -> &Client {
+    self.cli.as_ref().unwrap()
+  }
+}
+
+impl Drop for OpenTransaction {
+  /// Откатывает транзакцию, прежде чем соединение вернётся в пул.
+  fn drop(&mut self) {
+    let (Some(cli), Ok(runtime)) = (self.cli.take(), tokio::runtime::Handle::try_current()) else { return };
+    runtime.spawn(async move { cli.batch_execute("rollback;").await.ok(); });
+  }
+}
+
+/// Транзакция задачи: пуста, пока транзакция не начата или после её фиксации.
+pub(super) type Slot = Arc<Mutex<Option<OpenTransaction>>>;
+
+tokio::task_local! {
+  static TRANSACTION: Slot;
+}
+
+/// Выполняет `f`, позволяя её запросам к Postgres выполняться в одной транзакции (см. `Db::begin_transaction`).
+pub async fn scope<F: Future>(f: F) -> F::Output {
+  TRANSACTION.scope(Arc::new(Mutex::new(None)), f).await
+}
+
+/// Возвращает транзакцию текущей задачи, если задача выполняется в пределах `scope`.
+pub(super) fn slot() -> Option<Slot> {
+  TRANSACTION.try_with(Arc::clone).ok()
+}
+
+/// Возвращает соединение открытой транзакции текущей задачи, если она начата. Пока соединение не освобождено, другие запросы задачи его ждут.
+pub(super) async fn current() -> Option<OwnedMutexGuard<Option<OpenTransaction>>> {
+  let guard = slot()?.lock_owned().await;
+  guard.is_some().then_some(guard)
+}
@@ -1,7 +1,15 @@
 //! Предоставляет структуры данных для управления аутентификацией.
 
-use chrono::{DateTime, Utc, serde::ts_seconds};
+use chrono::{DateTime, Utc, serde::{ts_seconds, ts_seconds_option}};
+use custom_error::custom_error;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{future::Future, pin::Pin};
+
+use crate::sec::ldap::Ldap;
+use crate::setup::AppConfig;
+
+custom_error!{pub DirectoryUnavailable{reason: String} = "Каталог пользователей недоступен: {reason}"}
+custom_error!{pub WrongCredentials{} = "Не получен валидный токен."}
 
 /// Сведения аутентификации администратора.
 #[derive(Deserialize, Serialize)]
@@ -10,13 +18,94 @@ pub struct AdminCredentials {
   pub key: String,
 }
 
+/// Область действия ключа администратора.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminScope {
+  /// Настройка базы данных, проверка досок и перезагрузка конфигурации.
+  Setup,
+  /// Управление пользователями.
+  UserManagement,
+  /// Управление оплатой аккаунтов.
+  Billing,
+  /// Резервное копирование и восстановление базы данных.
+  Backup,
+}
+
+/// Именованный ключ администратора.
+///
+/// Сам ключ сервер передаёт только при его выпуске, а хранит лишь его хэш.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AdminKey {
+  /// Название ключа, по которому им управляют.
+  pub name: String,
+  /// Области действия ключа.
+  pub scopes: Vec<AdminScope>,
+  /// Дата и время, после которых ключ становится недействительным. Если отсутствуют, ключ действует бессрочно.
+  #[serde(default, with = "ts_seconds_option")]
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Ключ регистрации, выпущенный администратором.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CcKey {
+  /// Ключ, который передаётся при регистрации.
+  pub key: String,
+  /// Заметка администратора, например, для кого выпущен ключ.
+  pub note: Option<String>,
+  /// Дата и время выпуска ключа.
+  #[serde(with = "ts_seconds")]
+  pub created_at: DateTime<Utc>,
+  /// Дата и время, после которых ключ становится недействительным. Если отсутствуют, ключ действует бессрочно.
+  #[serde(with = "ts_seconds_option")]
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
 /// Токен аутентификации. Используется при необходимости получить/передать данные.
 #[derive(Deserialize, Serialize, Clone)]
 pub struct TokenAuth {
   /// Идентификатор пользователя.
   pub id: i64,
-  /// Токен.
+  /// Токен доступа.
   pub token: String,
+  /// Токен обновления, которым можно получить новую пару токенов (см. `RefreshCredentials`).
+  ///
+  /// Сервер передаёт его только при выдаче токенов. В заголовке `App-Token` его передавать не нужно.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub refresh_token: Option<String>,
+  /// Сроки действия токенов.
+  ///
+  /// Сервер передаёт их при выдаче токенов, чтобы клиент мог заранее обновить токен доступа или предупредить пользователя о необходимости войти заново. В заголовке `App-Token` их передавать не нужно.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub lifetime: Option<TokenLifetime>,
+}
+
+/// Сроки действия пары токенов.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TokenLifetime {
+  /// Число минут, в течение которых действителен токен доступа.
+  pub access_ttl_minutes: i64,
+  /// Дата и время, после которых токен доступа станет недействительным.
+  #[serde(with = "ts_seconds")]
+  pub access_expires_at: DateTime<Utc>,
+  /// Число дней, в течение которых неиспользуемый токен обновления остаётся действительным.
+  pub ttl_days: i64,
+  /// Число дней с момента выдачи, после которых токен обновления недействителен, даже если им пользуются.
+  pub absolute_ttl_days: i64,
+  /// Дата и время, после которых токен обновления станет недействительным в любом случае.
+  #[serde(with = "ts_seconds")]
+  pub expires_at: DateTime<Utc>,
+}
+
+/// Сведения для обновления пары токенов.
+#[derive(Deserialize, Serialize)]
+pub struct RefreshCredentials {
+  /// Идентификатор пользователя.
+  pub id: i64,
+  /// Токен обновления.
+  ///
+  /// Вместо него можно передать долгоживущий токен, выданный предыдущими версиями сервера, чтобы обменять его на пару токенов.
+  pub refresh_token: String,
 }
 
 /// Представление токена аутентификации в базе данных.
@@ -26,9 +115,52 @@ pub struct Token {
   pub tk: Vec<u8>,
   /// Дата и время последнего использования токена.
   ///
-  /// Токены действительны не более пяти дней, в течение которых вы ими не пользуетесь.
+  /// Токены действительны не более `token_ttl_days` дней, в течение которых вы ими не пользуетесь (см. `AppConfig`).
   #[serde(with = "ts_seconds")]
   pub from_dt: DateTime<Utc>,
+  /// Дата и время выдачи токена.
+  ///
+  /// Токены недействительны по прошествии `token_absolute_ttl_days` дней с этого момента. У токенов, выданных предыдущими версиями сервера, отсутствует - для них используется дата последнего использования.
+  #[serde(default, with = "ts_seconds_option")]
+  pub created_dt: Option<DateTime<Utc>>,
+  /// Дата и время, после которых токен доступа становится недействительным.
+  ///
+  /// Отсутствует у токенов обновления, а также у долгоживущих токенов, выданных предыдущими версиями сервера: последние действуют по правилам токенов обновления, пока их не обменяют на пару токенов.
+  #[serde(default, with = "ts_seconds_option")]
+  pub expires_dt: Option<DateTime<Utc>>,
+  /// Сведения о клиенте, получившем токен. У токенов, выданных предыдущими версиями сервера, отсутствуют.
+  #[serde(default)]
+  pub client: ClientInfo,
+}
+
+/// Сведения о клиенте, по которым пользователь может узнать свой сеанс.
+///
+/// Клиент сообщает их сам, поэтому доверять им можно не больше, чем самому клиенту.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct ClientInfo {
+  /// Значение заголовка `User-Agent` при выдаче токена.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub user_agent: Option<String>,
+  /// Название устройства, переданное клиентом в заголовке `Device-Name`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub device_name: Option<String>,
+  /// Адрес клиента при выдаче токена (с учётом доверенных прокси).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ip: Option<String>,
+  /// Дата и время входа, с которого начался сеанс. При обновлении токенов переходит к новой паре.
+  #[serde(default, with = "ts_seconds_option", skip_serializing_if = "Option::is_none")]
+  pub signed_in_at: Option<DateTime<Utc>>,
+}
+
+/// Сеанс пользователя - токен обновления вместе с выданными по нему токенами доступа.
+#[derive(Deserialize, Serialize)]
+pub struct Session {
+  /// Дата и время последнего обновления токенов сеанса (или входа, если токены ещё не обновлялись).
+  #[serde(with = "ts_seconds")]
+  pub refreshed_at: DateTime<Utc>,
+  /// Сведения о клиенте, последним получившем токены сеанса.
+  #[serde(flatten)]
+  pub client: ClientInfo,
 }
 
 /// Сведения авторизации пользователя. При входе в аккаунт преобразуются в id и токен (см. ниже).
@@ -45,25 +177,71 @@ pub struct SignInCredentials {
 pub struct SignUpCredentials {
   /// Логин.
   ///
-  /// Должен быть уникальным для успешной регистрации. Может содержать любые спецсимволы, пробелы, в том числе в начале/конце.
+  /// Должен быть уникальным для успешной регистрации и соответствовать требованиям конфигурации (`credentials_policy`): по умолчанию - от 3 до 64 букв, цифр и символов `._-@+`, без пробелов в начале и конце.
   pub login: String,
   /// Пароль.
   ///
-  /// Должен быть не менее 8 символов в длину, если передаётся в чистом виде; или может быть представлен в виде хэша парольной строки, также преобразованный в строку.
+  /// Должен соответствовать требованиям конфигурации (`credentials_policy`): по умолчанию - не менее 8 символов и не слишком лёгкий для подбора.
+  pub pass: String,
+  /// Ключ регистрации, выданный администратором.
+  ///
+  /// Обязателен, если регистрация только по ключам включена в конфигурации (`cc_key_required`). Ключ действует однократно.
+  #[serde(default)]
+  pub cc_key: Option<String>,
+}
+
+/// Сведения пользователя для открытой регистрации с подтверждением адреса электронной почты (см. `core::signup`).
+#[derive(Deserialize, Serialize)]
+pub struct EmailSignUpCredentials {
+  /// Логин. Требования те же, что и в `SignUpCredentials`.
+  pub login: String,
+  /// Пароль. Требования те же, что и в `SignUpCredentials`.
+  pub pass: String,
+  /// Адрес электронной почты, на который отправляется ссылка подтверждения.
+  pub email: String,
+}
+
+/// Изменение логина и/или пароля пользователя.
+#[derive(Deserialize, Serialize)]
+pub struct CredentialsPatch {
+  /// Текущий пароль.
   pub pass: String,
+  /// Новый логин.
+  #[serde(default)]
+  pub new_login: Option<String>,
+  /// Новый пароль.
+  #[serde(default)]
+  pub new_pass: Option<String>,
 }
 
 /// Сведения авторизации пользователя. Используется для хранения данных в БД, так как сохраняет токены.
 ///
 /// Для недопущения компрометации паролей пользователей в базе данных хранятся не они сами - и даже не их хэши! - а две компоненты: соль и подсоленный пароль. Аутентификация проходит следующим образом: пароль, полученный от клиента, подсаливается и сравнивается с подсоленным паролем из базы данных.
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct UserCredentials {
+  /// Алгоритм, которым хэширован пароль. У сведений, записанных предыдущими версиями сервера, отсутствует - для них используется `bcrypt`.
+  #[serde(default)]
+  pub pass_hash: PassHash,
   /// Соль.
   pub salt: Vec<u8>,
-  /// Подсоленный пароль.
+  /// Подсоленный пароль. Для Argon2id - хэш в формате PHC, включающий параметры и соль.
   pub salted_pass: Vec<u8>,
-  /// Список токенов.
+  /// Список токенов доступа.
   pub tokens: Vec<Token>,
+  /// Список токенов обновления.
+  #[serde(default)]
+  pub refresh_tokens: Vec<Token>,
+}
+
+/// Алгоритм хэширования пароля (см. `key_gen`).
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PassHash {
+  /// bcrypt со стоимостью 10, которым пароли хэшировали предыдущие версии сервера.
+  #[default]
+  Bcrypt,
+  /// Argon2id с параметрами, записанными в самом хэше.
+  Argon2id,
 }
 
 /// Данные об оплате пользовательского аккаунта.
@@ -80,30 +258,67 @@ pub struct AccountPlanDetails {
   pub last_payment: DateTime<Utc>,
 }
 
+/// Пользователь по данным внешнего источника учётных записей: поставщика входа (см. `sec::oidc`) или каталога (см. `Authenticator`).
+pub struct Identity {
+  /// Постоянный идентификатор пользователя во внешнем источнике.
+  pub subject: String,
+  /// Логин или адрес электронной почты, из которого составляется логин нового пользователя.
+  pub login: Option<String>,
+  /// Отображаемое имя.
+  pub display_name: Option<String>,
+}
+
+/// Результат проверки логина и пароля в каталоге.
+pub type Authentication<'a> = Pin<Box<dyn Future<Output = Result<Option<Identity>, DirectoryUnavailable>> + Send + 'a>>;
+
+/// Каталог пользователей, проверяющий логины и пароли вместо сервера.
+///
+/// При первом входе пользователь каталога получает учётную запись на сервере, привязанную к нему так же, как к аккаунту поставщика входа (см. `core::identities`).
+pub trait Authenticator: Send + Sync {
+  /// Имя, под которым к каталогу привязываются учётные записи сервера.
+  fn name(&self) -> &'static str;
+  
+  /// Проверяет логин и пароль.
+  ///
+  /// Возвращает None, если каталог их отклонил, и `DirectoryUnavailable`, если проверить их не удалось.
+  fn authenticate<'a>(&'a self, login: &'a str, pass: &'a str) -> Authentication<'a>;
+}
+
+/// Возвращает каталог пользователей, заданный в конфигурации, или None, если пароли проверяет сервер.
+pub fn authenticator(cfg: &AppConfig) -> Option<Box<dyn Authenticator>> {
+  let ldap = cfg.ldap.as_ref()?;
+  Some(Box::new(Ldap { cfg: ldap.clone() }))
+}
+
 /// Парсит заголовок App-Token HTTP-запроса в необходимую структуру.
 ///
-/// Данные в заголовке передаются в base64-кодировке и представляют из себя JSON-структуру.
-pub fn extract_creds<T>(header: Option<&hyper::header::HeaderValue>) -> Result<T, ()> 
+/// Данные в заголовке передаются в base64-кодировке и представляют из себя JSON-структуру (см. `encode_creds`).
+pub fn extract_creds<T>(header: Option<&hyper::header::HeaderValue>) -> Result<T, WrongCredentials>
   where
     T: DeserializeOwned,
 {
   let creds = match header {
-    None => return Err(()),
+    None => return Err(WrongCredentials{}),
     Some(v) => v,
   };
   let creds = match creds.to_str() {
-    Err(_) => return Err(()),
+    Err(_) => return Err(WrongCredentials{}),
     Ok(v) => String::from(v),
   };
   let creds = match base64::decode(&creds) {
-    Err(_) => return Err(()),
+    Err(_) => return Err(WrongCredentials{}),
     Ok(v) => match String::from_utf8(v) {
-      Err(_) => return Err(()),
+      Err(_) => return Err(WrongCredentials{}),
       Ok(v) => v,
     },
   };
   match serde_json::from_str::<T>(&creds) {
-    Err(_) => Err(()),
+    Err(_) => Err(WrongCredentials{}),
     Ok(v) => Ok(v),
   }
 }
+
+/// Кодирует сведения аутентификации для заголовка App-Token: JSON в base64-кодировке.
+pub fn encode_creds<T: Serialize>(creds: &T) -> serde_json::Result<String> {
+  Ok(base64::encode(&serde_json::to_string(creds)?))
+}
@@ -0,0 +1,41 @@
+//! Отвечает за шифрование секретов, которые сервер хранит в базе данных.
+//!
+//! Секреты шифруются AES-256-GCM ключом из конфигурации, поэтому резервная копия базы данных без конфигурации их не раскрывает. Зашифрованное значение состоит из случайного nonce, шифротекста и тега аутентичности: изменённое или зашифрованное другим ключом значение не расшифровывается.
+
+use crypto::{aead::{AeadDecryptor, AeadEncryptor}, aes::KeySize, aes_gcm::AesGcm};
+
+use crate::sec::hex;
+
+/// Длина nonce в байтах.
+const NONCE_LEN: usize = 12;
+/// Длина тега аутентичности в байтах.
+const TAG_LEN: usize = 16;
+
+/// Разбирает ключ AES-256, заданный 64 шестнадцатеричными символами.
+pub fn key(s: &str) -> Option<[u8; 32]> {
+  hex::decode(s)?.try_into().ok()
+}
+
+/// Шифрует секрет. Возвращает ошибку, если генератор случайных чисел операционной системы недоступен.
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, getrandom::Error> {
+  // Все 12 байт nonce берутся из криптографически стойкого генератора операционной системы.
+  let mut nonce = [0u8; NONCE_LEN];
+  getrandom::getrandom(&mut nonce)?;
+  let mut sealed = vec![0u8; NONCE_LEN + plaintext.len() + TAG_LEN];
+  let (head, tag) = sealed.split_at_mut(NONCE_LEN + plaintext.len());
+  head[..NONCE_LEN].copy_from_slice(&nonce);
+  AesGcm::new(KeySize::KeySize256, key, &nonce, &[]).encrypt(plaintext, &mut head[NONCE_LEN..], tag);
+  Ok(sealed)
+}
+
+/// Расшифровывает секрет. Возвращает None, если значение изменено или зашифровано другим ключом.
+pub fn open(key: &[u8; 32], sealed: &[u8]) -> Option<Vec<u8>> {
+  if sealed.len() < NONCE_LEN + TAG_LEN { return None; };
+  let (nonce, rest) = sealed.split_at(NONCE_LEN);
+  let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+  let mut plaintext = vec![0u8; ciphertext.len()];
+  match AesGcm::new(KeySize::KeySize256, key, nonce, &[]).decrypt(ciphertext, &mut plaintext, tag) {
+    true => Some(plaintext),
+    false => None,
+  }
+}
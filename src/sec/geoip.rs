@@ -0,0 +1,85 @@
+//! Отвечает за определение страны клиента по его адресу.
+//!
+//! Страна нужна только для того, чтобы замечать входы в аккаунт из непривычных мест (см. `core::security_events`), поэтому источник данных подключается в конфигурации (`geoip`) и может отсутствовать: тогда новым местом считается новый адрес.
+
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use std::{fs, net::IpAddr, sync::{Arc, Mutex}};
+
+use crate::sec::proxy::IpNet;
+use crate::setup::AppConfig;
+
+custom_error!{pub WrongGeoIpDb{line: usize} = "Неверная строка {line} в базе адресов GeoIP."}
+
+/// Источник сведений о странах клиентов.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct GeoIpConfig {
+  /// Вид источника. Поддерживается `csv` - файл со строками `<подсеть>,<код страны>`, например, `203.0.113.0/24,RU`.
+  pub provider: String,
+  /// Путь к файлу источника.
+  pub path: String,
+}
+
+/// Источник сведений о странах клиентов.
+pub trait GeoIp: Send + Sync {
+  /// Возвращает код страны, которой принадлежит адрес, или None, если страна неизвестна.
+  fn country(&self, ip: &IpAddr) -> Option<String>;
+}
+
+/// Подсети стран, считанные из CSV-файла.
+struct CsvGeoIp {
+  nets: Vec<(IpNet, String)>,
+}
+
+impl CsvGeoIp {
+  /// Считывает подсети из файла. Пустые строки и строки, начинающиеся с `#`, пропускаются.
+  fn load(path: &str) -> Result<CsvGeoIp, Box<dyn std::error::Error>> {
+    let mut nets = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') { continue; };
+      let wrong = || WrongGeoIpDb{ line: i + 1 };
+      let (net, country) = line.split_once(',').ok_or_else(wrong)?;
+      let net: IpNet = net.parse().map_err(|_| wrong())?;
+      nets.push((net, country.trim().trim_matches('"').to_uppercase()));
+    };
+    Ok(CsvGeoIp { nets })
+  }
+}
+
+impl GeoIp for CsvGeoIp {
+  /// Возвращает страну первой подсети файла, которой принадлежит адрес.
+  fn country(&self, ip: &IpAddr) -> Option<String> {
+    self.nets.iter().find(|(net, _)| net.contains(ip)).map(|(_, country)| country.clone())
+  }
+}
+
+/// Последний загруженный источник и путь, из которого он загружен.
+///
+/// Конфигурация копируется для каждого запроса, поэтому файл считывается один раз и перечитывается, только если в конфигурации задан другой путь.
+static LOADED: Mutex<Option<(String, Arc<CsvGeoIp>)>> = Mutex::new(None);
+
+/// Возвращает источник сведений о странах, заданный в конфигурации, или None, если он не задан или не может быть загружен.
+pub fn provider(cfg: &AppConfig) -> Option<Arc<dyn GeoIp>> {
+  let geoip = cfg.geoip.as_ref()?;
+  match geoip.provider.as_str() {
+    "csv" => {
+      let mut loaded = LOADED.lock().unwrap();
+      if let Some((path, db)) = loaded.as_ref() {
+        if *path == geoip.path { return Some(db.clone()); };
+      };
+      match CsvGeoIp::load(&geoip.path) {
+        Ok(db) => {
+          let db = Arc::new(db);
+          *loaded = Some((geoip.path.clone(), db.clone()));
+          Some(db)
+        },
+        Err(e) => {
+          eprintln!("Не удалось загрузить базу адресов GeoIP {}: {}", geoip.path, e);
+          None
+        },
+      }
+    },
+    _ => None,
+  }
+}
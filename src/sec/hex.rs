@@ -0,0 +1,14 @@
+//! Отвечает за шестнадцатеричное представление байт: подписей HMAC, ключей и других секретов.
+
+/// Кодирует байты в шестнадцатеричное представление строчными буквами.
+pub fn encode(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Декодирует строку из шестнадцатеричного представления. Возвращает None, если строка содержит другие символы или нечётное их число.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+  s.as_bytes().chunks(2).map(|pair| match pair.len() {
+    2 => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+    _ => None,
+  }).collect()
+}
@@ -1,7 +1,15 @@
 //! Отвечает за пароли.
+//!
+//! Пароли хэшируются алгоритмом Argon2id с параметрами из конфигурации (`password_hashing`); хэш хранится в формате PHC вместе с параметрами и солью. Пароли, хэшированные предыдущими версиями сервера при помощи bcrypt, продолжают проверяться и при успешном входе хэшируются заново (см. `needs_rehash`), поэтому аккаунты переходят на новый алгоритм без сброса паролей.
 
+use argon2::{Algorithm, Argon2, Params, Version, password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString}};
 use passwords::{PasswordGenerator, hasher::{bcrypt, gen_salt}};
 
+use crate::sec::auth::{PassHash, UserCredentials};
+use crate::setup::PasswordHashing;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
 /// Генерирует пароль, строго соответствующий заданным условиям.
 pub fn generate_strong(length: usize) -> Result<String, &'static str> {
   let pg = PasswordGenerator {
@@ -17,14 +25,43 @@ pub fn generate_strong(length: usize) -> Result<String, &'static str> {
   pg.generate_one()
 }
 
-/// Солит пароль, подготавливая к хранению в базе данных.
-pub fn salt_pass(pass: String) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+/// Хэширует пароль алгоритмом Argon2id с параметрами `params` и записывает его в сведения авторизации.
+pub fn set_pass(user_credentials: &mut UserCredentials, pass: &str, params: &PasswordHashing) -> MResult<()> {
   let salt = Vec::from(gen_salt());
-  let salted_pass = Vec::from(bcrypt(10, &salt, &pass)?);
-  Ok((salt, salted_pass))
+  let salt_string = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+  let hash = argon2(params)?.hash_password(pass.as_bytes(), &salt_string).map_err(|e| e.to_string())?;
+  user_credentials.pass_hash = PassHash::Argon2id;
+  user_credentials.salt = salt;
+  user_credentials.salted_pass = hash.to_string().into_bytes();
+  Ok(())
 }
 
 /// Проверяет правильность пароля.
-pub fn check_pass(salt: Vec<u8>, salted_pass: Vec<u8>, guessed_pass: &String) -> bool {
-  salted_pass == bcrypt(10, &salt, &guessed_pass).unwrap()
+pub fn check_pass(user_credentials: &UserCredentials, guessed_pass: &str) -> bool {
+  match user_credentials.pass_hash {
+    PassHash::Bcrypt => bcrypt(10, &user_credentials.salt, guessed_pass).is_ok_and(|hash| user_credentials.salted_pass == hash),
+    PassHash::Argon2id => match std::str::from_utf8(&user_credentials.salted_pass).map(PasswordHash::new) {
+      Ok(Ok(hash)) => Argon2::default().verify_password(guessed_pass.as_bytes(), &hash).is_ok(),
+      _ => false,
+    },
+  }
+}
+
+/// Проверяет, что пароль хэширован устаревшим алгоритмом или с параметрами, отличными от `params`, и его нужно хэшировать заново.
+pub fn needs_rehash(user_credentials: &UserCredentials, params: &PasswordHashing) -> bool {
+  let hash = match (user_credentials.pass_hash, std::str::from_utf8(&user_credentials.salted_pass)) {
+    (PassHash::Argon2id, Ok(hash)) => hash,
+    _ => return true,
+  };
+  match PasswordHash::new(hash).as_ref().map(Params::try_from) {
+    Ok(Ok(current)) =>
+      (current.m_cost(), current.t_cost(), current.p_cost()) != (params.memory_kib, params.iterations, params.parallelism),
+    _ => true,
+  }
+}
+
+/// Возвращает хэшер Argon2id с данными параметрами.
+fn argon2(params: &PasswordHashing) -> MResult<Argon2<'static>> {
+  let params = Params::new(params.memory_kib, params.iterations, params.parallelism, None).map_err(|e| e.to_string())?;
+  Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
 }
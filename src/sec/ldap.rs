@@ -0,0 +1,84 @@
+//! Отвечает за проверку логинов и паролей в каталоге LDAP / Active Directory.
+//!
+//! Пароль проверяется привязкой (bind) к каталогу от имени пользователя. DN пользователя либо составляется по шаблону `bind_dn_template`, либо ищется в `search_base` по фильтру `search_filter` - от имени учётной записи `bind_dn` или анонимно. Перед подстановкой в DN и фильтр логин экранируется.
+
+use ldap3::{Ldap as LdapClient, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry, dn_escape, ldap_escape};
+use std::time::Duration;
+
+use crate::sec::auth::{Authentication, Authenticator, DirectoryUnavailable, Identity};
+use crate::setup::LdapConfig;
+
+/// Код ответа каталога на неверные логин или пароль.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Каталог LDAP / Active Directory.
+pub struct Ldap {
+  pub cfg: LdapConfig,
+}
+
+impl Authenticator for Ldap {
+  fn name(&self) -> &'static str { "ldap" }
+
+  fn authenticate<'a>(&'a self, login: &'a str, pass: &'a str) -> Authentication<'a> {
+    Box::pin(async move {
+      // Привязка с пустым паролем считается анонимной и всегда успешна.
+      if pass.is_empty() { return Ok(None); };
+      self.check(login, pass).await.map_err(|e| DirectoryUnavailable{ reason: e.to_string() })
+    })
+  }
+}
+
+impl Ldap {
+  /// Проверяет логин и пароль и возвращает пользователя каталога.
+  ///
+  /// Идентификатором пользователя служит его DN, приведённый к нижнему регистру, поскольку каталоги сравнивают DN без учёта регистра.
+  async fn check(&self, login: &str, pass: &str) -> Result<Option<Identity>, LdapError> {
+    let timeout = Duration::from_secs(self.cfg.timeout_secs);
+    let settings = LdapConnSettings::new().set_conn_timeout(timeout).set_starttls(self.cfg.starttls);
+    let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.cfg.url).await?;
+    ldap3::drive!(conn);
+    ldap.with_timeout(timeout);
+    let dn = match self.user_dn(&mut ldap, login).await? {
+      Some(dn) => dn,
+      None => {
+        ldap.unbind().await.ok();
+        return Ok(None);
+      },
+    };
+    let res = ldap.simple_bind(&dn, pass).await?;
+    if res.rc == INVALID_CREDENTIALS {
+      ldap.unbind().await.ok();
+      return Ok(None);
+    };
+    res.success()?;
+    let display_name = self.display_name(&mut ldap, &dn).await;
+    ldap.unbind().await.ok();
+    Ok(Some(Identity { subject: dn.to_lowercase(), login: Some(login.to_string()), display_name }))
+  }
+
+  /// Возвращает DN пользователя с данным логином или None, если логин не определяет пользователя однозначно.
+  async fn user_dn(&self, ldap: &mut LdapClient, login: &str) -> Result<Option<String>, LdapError> {
+    if let Some(template) = &self.cfg.bind_dn_template {
+      return Ok(Some(template.replace("{login}", &dn_escape(login))));
+    };
+    if let Some(bind_dn) = &self.cfg.bind_dn {
+      ldap.simple_bind(bind_dn, self.cfg.bind_password.as_deref().unwrap_or_default()).await?.success()?;
+    };
+    let filter = self.cfg.search_filter.replace("{login}", &ldap_escape(login));
+    let (entries, _) = ldap.search(&self.cfg.search_base, Scope::Subtree, &filter, vec!["1.1"]).await?.success()?;
+    match entries.len() {
+      1 => Ok(entries.into_iter().next().map(|entry| SearchEntry::construct(entry).dn)),
+      _ => Ok(None),
+    }
+  }
+
+  /// Возвращает отображаемое имя пользователя. Если каталог не позволяет пользователю читать свою запись, имя не возвращается.
+  async fn display_name(&self, ldap: &mut LdapClient, dn: &str) -> Option<String> {
+    let attrs = vec![self.cfg.name_attribute.as_str()];
+    let (entries, _) = ldap.search(dn, Scope::Base, "(objectClass=*)", attrs).await.ok()?.success().ok()?;
+    let entry = SearchEntry::construct(entries.into_iter().next()?);
+    // Каталоги сравнивают имена атрибутов без учёта регистра и могут вернуть их в другом регистре.
+    let (_, names) = entry.attrs.into_iter().find(|(attr, _)| attr.eq_ignore_ascii_case(&self.cfg.name_attribute))?;
+    names.into_iter().find(|name| !name.is_empty())
+  }
+}
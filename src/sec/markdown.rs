@@ -0,0 +1,91 @@
+//! Отвечает за очистку заметок задач и подзадач и их перевод в HTML.
+//!
+//! Заметки записываются в Markdown, который допускает вставки HTML и ссылки с любыми схемами, а веб-клиенты показывают их как HTML. Поэтому при записи из заметок удаляются опасные теги и атрибуты HTML и ссылки со схемами вроде `javascript:`, а разметка приводится к единому виду. HTML, который возвращается клиентам, очищается ещё раз, поэтому он безопасен и для заметок, записанных до появления очистки.
+
+use ammonia::clean;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html::push_html};
+use pulldown_cmark_to_cmark::cmark;
+
+/// Схемы, допустимые в адресах ссылок и изображений. Относительные адреса допустимы всегда.
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Расширения Markdown, которые поддерживает сервер.
+fn options() -> Options {
+  Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+/// Очищает заметку и приводит её разметку к единому виду.
+pub fn sanitize(notes: &str) -> String {
+  if notes.trim().is_empty() { return String::new(); };
+  let mut events = Vec::new();
+  let mut html_block: Option<String> = None;
+  for event in Parser::new_ext(notes, options()) {
+    let event = match (event, &mut html_block) {
+      // Блок HTML разбит на строки, поэтому очищается целиком.
+      (Event::Start(Tag::HtmlBlock), _) => { html_block = Some(String::new()); continue; },
+      (Event::Html(html), Some(block)) => { block.push_str(&html); continue; },
+      (Event::End(TagEnd::HtmlBlock), _) => {
+        let mut html = clean(&html_block.take().unwrap_or_default());
+        if html.trim().is_empty() { continue; };
+        if !html.ends_with('\n') { html.push('\n'); };
+        events.push(Event::Start(Tag::HtmlBlock));
+        events.push(Event::Html(html.into()));
+        Event::End(TagEnd::HtmlBlock)
+      },
+      (Event::Html(html), None) | (Event::InlineHtml(html), _) => match inline_tag(&html) {
+        Some(tag) => Event::InlineHtml(tag.into()),
+        None => continue,
+      },
+      (Event::Start(Tag::Link{ link_type, dest_url, title, id }), _) =>
+        Event::Start(Tag::Link{ link_type, dest_url: safe_url(dest_url), title, id }),
+      (Event::Start(Tag::Image{ link_type, dest_url, title, id }), _) =>
+        Event::Start(Tag::Image{ link_type, dest_url: safe_url(dest_url), title, id }),
+      (event, _) => event,
+    };
+    events.push(event);
+  };
+  let mut sanitized = String::with_capacity(notes.len());
+  // Запись в строку не завершается ошибкой.
+  cmark(events.into_iter(), &mut sanitized).ok();
+  sanitized
+}
+
+/// Переводит заметку в очищенный HTML.
+pub fn render(notes: &str) -> String {
+  let mut html = String::with_capacity(notes.len() * 3 / 2);
+  push_html(&mut html, Parser::new_ext(notes, options()));
+  clean(&html)
+}
+
+/// Очищает отдельный тег HTML внутри текста и возвращает None, если тег нужно удалить.
+///
+/// Парсер Markdown выделяет открывающие и закрывающие теги по отдельности. Открывающий тег очищается вместе с закрывающим, который затем отбрасывается; закрывающий тег оставляется, если допустим такой же открывающий.
+fn inline_tag(tag: &str) -> Option<String> {
+  let name: String = tag.trim_start_matches(['<', '/']).chars()
+    .take_while(|c| c.is_ascii_alphanumeric())
+    .collect::<String>()
+    .to_ascii_lowercase();
+  if name.is_empty() { return None; };
+  let closing = format!("</{}>", name);
+  if tag.starts_with("</") {
+    let allowed = !clean(&format!("<{}>", name)).is_empty();
+    return allowed.then_some(closing);
+  };
+  let cleaned = clean(&format!("{}{}", tag, closing));
+  let cleaned = cleaned.strip_suffix(&closing).unwrap_or(&cleaned);
+  (!cleaned.is_empty()).then(|| cleaned.to_string())
+}
+
+/// Возвращает адрес ссылки, если его схема безопасна, и пустой адрес в противном случае.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+  // Браузеры пропускают пробельные и управляющие символы в схеме, так что `java\tscript:` - тоже `javascript:`.
+  let compact: String = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect();
+  let scheme = match compact.find(':') {
+    Some(end) if !compact[..end].contains(['/', '?', '#']) => &compact[..end],
+    _ => return url,
+  };
+  match SAFE_SCHEMES.iter().any(|safe| scheme.eq_ignore_ascii_case(safe)) {
+    true => url,
+    false => CowStr::Borrowed(""),
+  }
+}
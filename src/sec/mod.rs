@@ -1,4 +1,13 @@
 pub mod auth;
+pub mod cipher;
 pub mod color_vld;
+pub mod geoip;
+pub mod hex;
 pub mod key_gen;
+pub mod markdown;
+pub mod ldap;
+pub mod oidc;
+pub mod policy;
+pub mod proxy;
+pub mod signing;
 pub mod tokens_vld;
@@ -0,0 +1,233 @@
+//! Отвечает за вход через внешних поставщиков OAuth 2.0 / OpenID Connect.
+//!
+//! Вход выполняется по схеме с кодом авторизации. Клиент получает от `GET /oauth/<поставщик>/start` адрес страницы входа поставщика и направляет туда пользователя. После входа поставщик возвращает пользователя на `redirect_uri` клиента с кодом авторизации и параметром `state`, которые клиент передаёт в `GET /oauth/<поставщик>/callback`. Сервер обменивает код на токен доступа поставщика, получает с ним профиль пользователя и находит по профилю пользователя сервера (см. `core::identities`).
+//!
+//! Профиль запрашивается у поставщика напрямую по HTTPS, поэтому подпись ID-токена OpenID Connect не проверяется: сам ID-токен не используется.
+
+use custom_error::custom_error;
+use hyper::{Body, Client, Method, Request, body::to_bytes, client::HttpConnector};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::Value as JsonValue;
+use std::time::Duration;
+
+use crate::sec::auth::Identity;
+use crate::setup::{AppConfig, OAuthProvider};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub UnknownProvider{} = "Поставщик входа не настроен."}
+custom_error!{pub ProviderError{reason: String} = "Поставщик входа вернул ошибку: {reason}"}
+
+/// Число секунд, в течение которых сервер ожидает ответа поставщика.
+const PROVIDER_TIMEOUT_SECS: u64 = 10;
+
+/// Поставщик входа с адресами и полями профиля, дополненными известными значениями.
+pub struct Provider {
+  pub name: String,
+  client_id: String,
+  client_secret: String,
+  redirect_uri: String,
+  authorize_url: String,
+  token_url: String,
+  userinfo_url: String,
+  scope: String,
+  subject_field: String,
+  login_field: String,
+  name_field: String,
+  /// Схема заголовка `Authorization` при запросе профиля.
+  auth_scheme: &'static str,
+}
+
+/// Адреса и поля профиля, используемые, если они не заданы в конфигурации.
+struct Preset {
+  authorize_url: &'static str,
+  token_url: &'static str,
+  userinfo_url: &'static str,
+  scope: &'static str,
+  subject_field: &'static str,
+  login_field: &'static str,
+  name_field: &'static str,
+  auth_scheme: &'static str,
+}
+
+/// Значения для поставщиков OpenID Connect. Адреса поставщика должны быть заданы в конфигурации.
+const OIDC: Preset = Preset {
+  authorize_url: "",
+  token_url: "",
+  userinfo_url: "",
+  scope: "openid email profile",
+  subject_field: "sub",
+  login_field: "email",
+  name_field: "name",
+  auth_scheme: "Bearer",
+};
+
+/// Возвращает значения для поставщика с данным именем.
+fn preset(name: &str) -> Preset {
+  match name {
+    "google" => Preset {
+      authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+      token_url: "https://oauth2.googleapis.com/token",
+      userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo",
+      ..OIDC
+    },
+    "github" => Preset {
+      authorize_url: "https://github.com/login/oauth/authorize",
+      token_url: "https://github.com/login/oauth/access_token",
+      userinfo_url: "https://api.github.com/user",
+      scope: "read:user",
+      subject_field: "id",
+      login_field: "login",
+      ..OIDC
+    },
+    "yandex" => Preset {
+      authorize_url: "https://oauth.yandex.ru/authorize",
+      token_url: "https://oauth.yandex.ru/token",
+      userinfo_url: "https://login.yandex.ru/info?format=json",
+      scope: "login:info",
+      subject_field: "id",
+      login_field: "login",
+      name_field: "display_name",
+      auth_scheme: "OAuth",
+    },
+    _ => OIDC,
+  }
+}
+
+/// Возвращает поставщика с данным именем из конфигурации.
+///
+/// Незаданные адреса и поля профиля берутся из известных значений для `google`, `github` и `yandex` или из значений, принятых в OpenID Connect; если поставщик не настроен или для него нельзя определить адреса, функция возвращает `UnknownProvider`.
+pub fn provider(cfg: &AppConfig, name: &str) -> Result<Provider, UnknownProvider> {
+  let p: &OAuthProvider = cfg.oauth_providers.iter().find(|p| p.name == name).ok_or(UnknownProvider{})?;
+  let preset = preset(name);
+  let or = |value: &Option<String>, default: &str| value.clone().unwrap_or_else(|| default.to_string());
+  let provider = Provider {
+    name: p.name.clone(),
+    client_id: p.client_id.clone(),
+    client_secret: p.client_secret.clone(),
+    redirect_uri: p.redirect_uri.clone(),
+    authorize_url: or(&p.authorize_url, preset.authorize_url),
+    token_url: or(&p.token_url, preset.token_url),
+    userinfo_url: or(&p.userinfo_url, preset.userinfo_url),
+    scope: or(&p.scope, preset.scope),
+    subject_field: or(&p.subject_field, preset.subject_field),
+    login_field: or(&p.login_field, preset.login_field),
+    name_field: or(&p.name_field, preset.name_field),
+    auth_scheme: preset.auth_scheme,
+  };
+  match [&provider.authorize_url, &provider.token_url, &provider.userinfo_url].iter().any(|url| url.is_empty()) {
+    true => Err(UnknownProvider{}),
+    false => Ok(provider),
+  }
+}
+
+/// Кодирует значение для передачи в строке запроса или в теле формы.
+fn encode(value: &str) -> String {
+  value.bytes().map(|b| match b {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+    _ => format!("%{:02X}", b),
+  }).collect()
+}
+
+/// Декодирует значение параметра строки запроса.
+pub fn decode(value: &str) -> Option<String> {
+  let mut bytes = Vec::with_capacity(value.len());
+  let mut iter = value.bytes();
+  while let Some(b) = iter.next() {
+    match b {
+      b'%' => {
+        let hex = [iter.next()?, iter.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+      },
+      b'+' => bytes.push(b' '),
+      b => bytes.push(b),
+    };
+  };
+  String::from_utf8(bytes).ok()
+}
+
+/// Собирает строку запроса или тело формы из пар параметров.
+fn form(params: &[(&str, &str)]) -> String {
+  params.iter().map(|(k, v)| format!("{}={}", k, encode(v))).collect::<Vec<_>>().join("&")
+}
+
+/// Возвращает адрес страницы входа поставщика.
+pub fn authorize_url(provider: &Provider, state: &str) -> String {
+  let separator = if provider.authorize_url.contains('?') { '&' } else { '?' };
+  format!("{}{}{}", provider.authorize_url, separator, form(&[
+    ("response_type", "code"),
+    ("client_id", &provider.client_id),
+    ("redirect_uri", &provider.redirect_uri),
+    ("scope", &provider.scope),
+    ("state", state),
+  ]))
+}
+
+/// Создаёт клиент для запросов к поставщикам.
+///
+/// Поставщики, заданные в конфигурации явно, могут быть доступны и по HTTP - например, внутри частной сети.
+fn client() -> Client<HttpsConnector<HttpConnector>> {
+  let connector = HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+  Client::builder().build(connector)
+}
+
+/// Выполняет запрос к поставщику и возвращает ответ в виде JSON.
+async fn send(req: Request<Body>) -> MResult<JsonValue> {
+  let res = tokio::time::timeout(Duration::from_secs(PROVIDER_TIMEOUT_SECS), client().request(req)).await
+    .map_err(|_| ProviderError{ reason: "поставщик не ответил вовремя".into() })??;
+  let status = res.status();
+  let body = to_bytes(res.into_body()).await?;
+  let body: JsonValue = serde_json::from_slice(&body)
+    .map_err(|_| ProviderError{ reason: format!("ответ с кодом {} не является JSON", status.as_u16()) })?;
+  if let Some(error) = body.get("error") {
+    let reason = body.get("error_description").unwrap_or(error);
+    return Err(Box::new(ProviderError{ reason: reason.as_str().unwrap_or("неизвестная ошибка").to_string() }));
+  };
+  match status.is_success() {
+    true => Ok(body),
+    false => Err(Box::new(ProviderError{ reason: format!("ответ с кодом {}", status.as_u16()) })),
+  }
+}
+
+/// Возвращает строковое значение поля профиля. Числа, которыми некоторые поставщики передают идентификаторы, переводятся в строку.
+fn field(profile: &JsonValue, name: &str) -> Option<String> {
+  match profile.get(name)? {
+    JsonValue::String(value) if !value.is_empty() => Some(value.clone()),
+    JsonValue::Number(value) => Some(value.to_string()),
+    _ => None,
+  }
+}
+
+/// Обменивает код авторизации на токен доступа поставщика и получает с ним профиль пользователя.
+pub async fn fetch_identity(provider: &Provider, code: &str) -> MResult<Identity> {
+  let token_req = Request::builder()
+    .method(Method::POST)
+    .uri(&provider.token_url)
+    .header("Content-Type", "application/x-www-form-urlencoded")
+    .header("Accept", "application/json")
+    .body(Body::from(form(&[
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", &provider.redirect_uri),
+      ("client_id", &provider.client_id),
+      ("client_secret", &provider.client_secret),
+    ])))?;
+  let token = send(token_req).await?;
+  let access_token = token["access_token"].as_str()
+    .ok_or(ProviderError{ reason: "не получен токен доступа".into() })?;
+  let profile_req = Request::builder()
+    .method(Method::GET)
+    .uri(&provider.userinfo_url)
+    .header("Authorization", format!("{} {}", provider.auth_scheme, access_token))
+    .header("Accept", "application/json")
+    // GitHub отклоняет запросы без заголовка User-Agent.
+    .header("User-Agent", "cc-taskboard-server")
+    .body(Body::empty())?;
+  let profile = send(profile_req).await?;
+  Ok(Identity {
+    subject: field(&profile, &provider.subject_field)
+      .ok_or(ProviderError{ reason: format!("в профиле нет поля {}", provider.subject_field) })?,
+    login: field(&profile, &provider.login_field),
+    display_name: field(&profile, &provider.name_field),
+  })
+}
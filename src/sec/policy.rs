@@ -0,0 +1,137 @@
+//! Отвечает за проверку логинов и паролей на соответствие требованиям.
+//!
+//! Требования задаются в конфигурации (см. `setup::CredentialsPolicy`). Все нарушения собираются вместе, чтобы клиент мог показать их пользователю разом, а не по одному на каждую попытку.
+
+use serde::Serialize;
+
+use crate::setup::CredentialsPolicy;
+
+#[derive(Debug)]
+pub struct PolicyViolations {
+  pub violations: Vec<Violation>,
+}
+
+impl std::fmt::Display for PolicyViolations {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Логин или пароль не соответствуют требованиям.")
+  }
+}
+
+impl std::error::Error for PolicyViolations {}
+
+/// Нарушение требования к логину или паролю.
+#[derive(Serialize, Debug)]
+pub struct Violation {
+  /// Поле, которое не соответствует требованию: `login` или `pass`.
+  pub field: &'static str,
+  /// Нарушенное требование.
+  pub rule: &'static str,
+  /// Описание нарушения для пользователя.
+  pub message: String,
+}
+
+impl Violation {
+  fn new(field: &'static str, rule: &'static str, message: String) -> Violation {
+    Violation { field, rule, message }
+  }
+}
+
+/// Проверяет логин на соответствие требованиям.
+pub fn check_login(policy: &CredentialsPolicy, login: &str) -> Vec<Violation> {
+  let mut violations = vec![];
+  let len = login.chars().count();
+  if len < policy.login_min_len {
+    violations.push(Violation::new(
+      "login", "too_short", format!("Логин должен содержать не менее {} символов.", policy.login_min_len)
+    ));
+  };
+  if len > policy.login_max_len {
+    violations.push(Violation::new(
+      "login", "too_long", format!("Логин должен содержать не более {} символов.", policy.login_max_len)
+    ));
+  };
+  if login.trim() != login {
+    violations.push(Violation::new(
+      "login", "surrounding_whitespace", String::from("Логин не может начинаться или заканчиваться пробелом.")
+    ));
+  };
+  if login.trim().chars().any(|c| !c.is_alphanumeric() && !policy.login_extra_chars.contains(c)) {
+    violations.push(Violation::new(
+      "login", "forbidden_chars",
+      format!("Логин может содержать только буквы, цифры и символы {}.", policy.login_extra_chars)
+    ));
+  };
+  violations
+}
+
+/// Проверяет пароль на соответствие требованиям.
+pub fn check_password(policy: &CredentialsPolicy, password: &str, login: &str) -> Vec<Violation> {
+  let mut violations = vec![];
+  if password.chars().count() < policy.password_min_len {
+    violations.push(Violation::new(
+      "pass", "too_short", format!("Пароль должен содержать не менее {} символов.", policy.password_min_len)
+    ));
+  } else if password_score(password, login, &policy.password_deny_list) < policy.password_min_score {
+    violations.push(Violation::new(
+      "pass", "too_weak", String::from("Пароль слишком легко подобрать: добавьте слов, цифр или символов.")
+    ));
+  };
+  violations
+}
+
+/// Проверяет логин и пароль, если они переданы, и возвращает все найденные нарушения.
+///
+/// Если проверяется только пароль, в `login` передаётся текущий логин пользователя: пароль не должен его содержать.
+pub fn validate(policy: &CredentialsPolicy, login: &str, new_login: bool, password: Option<&str>)
+  -> Result<(), PolicyViolations>
+{
+  let mut violations = match new_login {
+    true => check_login(policy, login),
+    false => vec![],
+  };
+  if let Some(password) = password {
+    violations.extend(check_password(policy, password, login));
+  };
+  match violations.is_empty() {
+    true => Ok(()),
+    false => Err(PolicyViolations{ violations }),
+  }
+}
+
+/// Оценивает стойкость пароля от 0 до 4 по шкале zxcvbn.
+///
+/// Оценка зависит от числа попыток, за которое пароль можно подобрать: менее 10^3 - 0, 10^6 - 1, 10^8 - 2, 10^10 - 3, больше - 4. Число попыток оценивается грубо: каждый символ добавляет log2 размера алфавита, из которого составлен пароль, символы, продолжающие повтор или последовательность (`aaa`, `abc`, `321`), - один бит, а слова из `deny_list` и логин - по log2 их числа.
+pub fn password_score(password: &str, login: &str, deny_list: &[String]) -> u8 {
+  let chars: Vec<char> = password.chars().collect();
+  let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+  let mut words: Vec<Vec<char>> = deny_list.iter().map(|word| word.to_lowercase().chars().collect()).collect();
+  if login.chars().count() >= 3 { words.push(login.to_lowercase().chars().collect()); };
+  words.retain(|word| !word.is_empty());
+  let word_bits = ((words.len() + 1) as f64).log2();
+  let mut bits = 0.0;
+  let mut covered = vec![false; chars.len()];
+  for word in &words {
+    let mut i = 0;
+    while i + word.len() <= lower.len() {
+      if lower[i..i + word.len()] == word[..] && !covered[i..i + word.len()].contains(&true) {
+        covered[i..i + word.len()].iter_mut().for_each(|c| *c = true);
+        bits += word_bits;
+        i += word.len();
+      } else {
+        i += 1;
+      };
+    };
+  };
+  let mut pool: f64 = 0.0;
+  if chars.iter().any(char::is_ascii_lowercase) { pool += 26.0; };
+  if chars.iter().any(char::is_ascii_uppercase) { pool += 26.0; };
+  if chars.iter().any(char::is_ascii_digit) { pool += 10.0; };
+  if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') { pool += 33.0; };
+  if chars.iter().any(|c| !c.is_ascii()) { pool += 100.0; };
+  let char_bits = pool.max(2.0).log2();
+  for i in (0..chars.len()).filter(|i| !covered[*i]) {
+    let continues = i > 0 && !covered[i - 1] && (lower[i] as i64 - lower[i - 1] as i64).abs() <= 1;
+    bits += if continues { 1.0 } else { char_bits };
+  };
+  [1e3_f64, 1e6, 1e8, 1e10].iter().take_while(|guesses| bits >= guesses.log2()).count() as u8
+}
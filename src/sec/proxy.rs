@@ -0,0 +1,118 @@
+//! Отвечает за определение адреса клиента, когда сервер работает за обратным прокси.
+//!
+//! За прокси адресом соединения всегда оказывается адрес самого прокси, а адрес клиента прокси передаёт в заголовке `Forwarded` (RFC 7239) или `X-Forwarded-For`. Эти заголовки может подделать и сам клиент, поэтому им верят, только если соединение установлено с доверенного прокси (см. `trusted_proxies` в конфигурации). Цепочка адресов в заголовке просматривается с конца, пока адреса принадлежат доверенным прокси: первый недоверенный адрес и есть адрес клиента.
+
+use custom_error::custom_error;
+use hyper::{Body, Request};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+custom_error!{pub WrongIpNet{value: String} = "Неверный адрес или подсеть доверенного прокси: {value}"}
+
+/// Адрес или подсеть в записи CIDR, например, `10.0.0.0/8`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNet {
+  addr: IpAddr,
+  prefix: u8,
+}
+
+impl IpNet {
+  /// Проверяет, что адрес принадлежит подсети.
+  pub fn contains(&self, ip: &IpAddr) -> bool {
+    let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net).into(), u32::from(ip).into(), 32),
+      (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+      _ => return false,
+    };
+    // Сдвиг на всю длину числа переполняет его, поэтому подсеть с нулевым префиксом проверяется отдельно.
+    self.prefix == 0 || net >> (bits - self.prefix) == ip >> (bits - self.prefix)
+  }
+}
+
+impl FromStr for IpNet {
+  type Err = WrongIpNet;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let wrong = || WrongIpNet{ value: s.to_string() };
+    let (addr, prefix) = match s.split_once('/') {
+      Some((addr, prefix)) => (addr, Some(prefix)),
+      None => (s, None),
+    };
+    let addr = IpAddr::from_str(addr.trim()).map_err(|_| wrong())?.to_canonical();
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+      Some(prefix) => prefix.trim().parse::<u8>().map_err(|_| wrong())?,
+      None => bits,
+    };
+    match prefix <= bits {
+      true => Ok(IpNet { addr, prefix }),
+      false => Err(wrong()),
+    }
+  }
+}
+
+impl TryFrom<String> for IpNet {
+  type Error = WrongIpNet;
+
+  fn try_from(s: String) -> Result<Self, Self::Error> { s.parse() }
+}
+
+impl From<IpNet> for String {
+  fn from(net: IpNet) -> String { net.to_string() }
+}
+
+impl fmt::Display for IpNet {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.addr, self.prefix)
+  }
+}
+
+/// Возвращает адрес клиента, отправившего запрос через соединение с адреса `peer`.
+pub fn client_ip(req: &Request<Body>, peer: IpAddr, trusted: &[IpNet]) -> IpAddr {
+  let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+  let mut client = peer.to_canonical();
+  if !is_trusted(&client) { return client; };
+  for hop in forwarded_chain(req).iter().rev() {
+    // Прокси может скрыть адрес (`unknown` или обфусцированный идентификатор) - тогда дальше цепочке верить нельзя.
+    client = match hop {
+      Some(ip) => *ip,
+      None => return client,
+    };
+    if !is_trusted(&client) { return client; };
+  };
+  client
+}
+
+/// Возвращает цепочку адресов из заголовка `Forwarded`, а если его нет - из `X-Forwarded-For`, от клиента к последнему прокси.
+///
+/// Заголовок может повторяться; повторы объединяются по порядку. Значения, не являющиеся адресами, заменяются `None`.
+fn forwarded_chain(req: &Request<Body>) -> Vec<Option<IpAddr>> {
+  let values = |name: &str| req.headers().get_all(name).iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .map(str::trim)
+    .filter(|hop| !hop.is_empty())
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+  let forwarded = values("Forwarded");
+  match forwarded.is_empty() {
+    false => forwarded.iter().map(|hop| {
+      hop.split(';')
+        .find_map(|pair| pair.trim().split_once('=').filter(|(key, _)| key.trim().eq_ignore_ascii_case("for")))
+        .and_then(|(_, value)| node_ip(value))
+    }).collect(),
+    true => values("X-Forwarded-For").iter().map(|hop| node_ip(hop)).collect(),
+  }
+}
+
+/// Разбирает адрес узла, возможно, в кавычках, в квадратных скобках и с портом.
+fn node_ip(node: &str) -> Option<IpAddr> {
+  let node = node.trim().trim_matches('"');
+  if let Ok(ip) = node.parse::<IpAddr>() { return Some(ip.to_canonical()); };
+  let host = match node.strip_prefix('[') {
+    Some(rest) => rest.split(']').next()?,
+    None => node.rsplit_once(':').map(|(host, _)| host).unwrap_or(node),
+  };
+  host.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
@@ -0,0 +1,68 @@
+//! Отвечает за подписанные ссылки на методы чтения.
+//!
+//! Календарные приложения и браузер при скачивании файла не могут передать заголовок `App-Token`, поэтому пользователь может получить ссылку, которая сама подтверждает его права. Ссылка содержит идентификатор пользователя, момент окончания действия и подпись HMAC-SHA256 от строки `<пользователь>\n<окончание действия>\n<путь и параметры>`, вычисленную ключом из конфигурации сервера. Подписанная ссылка даёт доступ только к методу `GET` по тому же пути с теми же параметрами.
+
+use crypto::{hmac::Hmac, mac::{Mac, MacResult}, sha2::Sha256};
+use custom_error::custom_error;
+
+use crate::sec::hex;
+
+custom_error!{pub WrongSignature{} = "Подпись ссылки недействительна или срок её действия истёк."}
+
+/// Число секунд, в течение которых действует ссылка, если срок не задан.
+pub const DEFAULT_TTL_SECS: i64 = 15 * 60;
+
+/// Наибольшее число секунд, в течение которых может действовать ссылка.
+pub const MAX_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Параметры ссылки, которые добавляет подпись.
+const SIGNING_PARAMS: [&str; 3] = ["uid", "expires", "signature"];
+
+/// Подписывает путь с параметрами `path_and_query` для пользователя и возвращает подписанную ссылку без адреса сервера.
+///
+/// Параметры подписи, уже бывшие в ссылке, заменяются.
+pub fn sign(secret: &str, user_id: i64, path_and_query: &str, expires_at: i64) -> String {
+  let (path, query) = path_and_query.split_once('?').unwrap_or((path_and_query, ""));
+  let target = target(path, query);
+  let signature = hex::encode(mac(secret, user_id, expires_at, &target).code());
+  let separator = if target.contains('?') { '&' } else { '?' };
+  format!("{}{}uid={}&expires={}&signature={}", target, separator, user_id, expires_at, signature)
+}
+
+/// Проверяет подпись ссылки.
+///
+/// Возвращает `None`, если ссылка не подписана, идентификатор пользователя - если подпись верна и срок действия не истёк к моменту `now`, и `WrongSignature` в остальных случаях.
+pub fn verify(secret: &str, path: &str, query: Option<&str>, now: i64) -> Result<Option<i64>, WrongSignature> {
+  let query = query.unwrap_or_default();
+  let param = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='));
+  let signature = match param("signature") {
+    Some(v) => hex::decode(v).ok_or(WrongSignature{})?,
+    None => return Ok(None),
+  };
+  let user_id: i64 = param("uid").and_then(|v| v.parse().ok()).ok_or(WrongSignature{})?;
+  let expires_at: i64 = param("expires").and_then(|v| v.parse().ok()).ok_or(WrongSignature{})?;
+  if expires_at < now { return Err(WrongSignature{}); };
+  // Сравнение MacResult выполняется за постоянное время.
+  match mac(secret, user_id, expires_at, &target(path, query)) == MacResult::new(&signature) {
+    true => Ok(Some(user_id)),
+    false => Err(WrongSignature{}),
+  }
+}
+
+/// Возвращает путь с параметрами ссылки без параметров подписи.
+fn target(path: &str, query: &str) -> String {
+  let params: Vec<&str> = query.split('&')
+    .filter(|pair| !pair.is_empty() && !SIGNING_PARAMS.contains(&pair.split('=').next().unwrap_or_default()))
+    .collect();
+  match params.is_empty() {
+    true => path.to_string(),
+    false => format!("{}?{}", path, params.join("&")),
+  }
+}
+
+/// Вычисляет подпись ссылки.
+fn mac(secret: &str, user_id: i64, expires_at: i64, target: &str) -> MacResult {
+  let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+  mac.input(format!("{}\n{}\n{}", user_id, expires_at, target).as_bytes());
+  mac.result()
+}
@@ -1,63 +1,117 @@
 //! Отвечает за токены и оплату аккаунта.
 
-use chrono::{Utc, Duration};
+use chrono::{DateTime, Utc, Duration};
 use sha3::{Digest, Sha3_256};
 
-use crate::core::{get_tokens_and_billing, write_tokens};
-use crate::psql_handler::Db;
-use crate::sec::auth::TokenAuth;
+use crate::core::get_tokens_and_billing;
+use crate::sec::auth::{AccountPlanDetails, Token, TokenAuth, UserCredentials};
+use crate::setup::AppConfig;
+use crate::storage::Storage;
 
-/// 1. Проверяет все токены пользователя на срок годности, проверяет наличие текущего токена и возвращает true, если пользователь определён.
-/// 2. Проверяет данные оплаты и возвращает true, если пользователь имеет оплаченный аккаунт.
+/// Длина хэша токена в байтах.
+pub const TOKEN_HASH_LEN: usize = 32;
+
+/// Хэширует токен для хранения в базе данных и сравнения с хранимыми хэшами.
 ///
-/// TODO сделать Redis-подключение и хранить данные по токенам вместо того, чтобы каждый раз валидировать их через базу данных.
-/// WARNING проверка оплаты идёт каждый 31 день, а не ровно в день оплаты
-/// TODO Не хранить токены в открытом виде!
-pub async fn verify_user(db: &Db, token_auth: &TokenAuth) -> (bool, bool) {
-  let (mut tokens, billing) = get_tokens_and_billing(db, &token_auth.id).await.unwrap();
-  // 1. Проверка токенов
-  let mut s: usize = 0;
-  let mut i: usize = 0;
-  let mut validated: bool = false;
-  while s + i < tokens.len() {
-    if s > 0 {
-      tokens[i].tk = tokens[i + s].tk.clone();
-      tokens[i].from_dt = tokens[i + s].from_dt;
-    }
-    let duration: Duration = Utc::now() - tokens[i].from_dt;
-    if duration.num_days() >= 5 {
-      s += 1;
-    } else {
-      let mut hasher = Sha3_256::new();
-      hasher.update(&token_auth.token);
-      let hashed = hasher.finalize();
-      if tokens[i].tk == hashed.to_vec() {
-        validated = true;
-        tokens[i].from_dt = Utc::now();
-      }
-      i += 1;
-    }
+/// Сервер хранит только хэши токенов SHA3-256, поэтому токен, переданный клиентом, хэшируется перед каждым сравнением. Токены, которые предыдущие версии сервера хранили в открытом виде, хэшируются при настройке базы данных (см. `core::compat`).
+pub fn hash_token(token: &str) -> Vec<u8> {
+  let mut hasher = Sha3_256::new();
+  hasher.update(token);
+  hasher.finalize().to_vec()
+}
+
+/// Проверяет, не истёк ли срок действия токена.
+///
+/// Токены доступа действительны до `expires_dt`. Токены обновления и долгоживущие токены, выданные предыдущими версиями сервера, действительны, пока ими пользуются не реже раза в `token_ttl_days` дней, но не дольше `token_absolute_ttl_days` дней с момента выдачи.
+pub fn is_alive(token: &Token, now: &DateTime<Utc>, cfg: &AppConfig) -> bool {
+  match token.expires_dt {
+    Some(expires_dt) => *now < expires_dt,
+    None => {
+      let idle: Duration = *now - token.from_dt;
+      let age: Duration = *now - token.created_dt.unwrap_or(token.from_dt);
+      idle.num_days() < cfg.token_ttl_days && age.num_days() < cfg.token_absolute_ttl_days
+    },
   }
-  tokens.truncate(tokens.len() - s);
-  // 2. Проверка оплаты
-  let mut billed: bool = false;
-  if !billing.billed_forever {
-    if billing.is_paid_whenever {
-      let duration: Duration = Utc::now() - billing.last_payment;
-      if duration.num_days() < 31 {
-        billed = true;
-      } /* else {} */ // Если время истекло, нам нужно узнать у сервера, оплачен ли текущий месяц.
+}
+
+/// Проверяет данные оплаты и возвращает true, если аккаунт оплачен.
+///
+/// Дату последнего платежа обновляет платёжный провайдер, присылая уведомления (см. `billing`).
+///
+/// WARNING проверка оплаты идёт каждый 31 день, а не ровно в день оплаты
+pub fn is_billed(billing: &AccountPlanDetails) -> bool {
+  if billing.billed_forever { return true; };
+  if billing.is_paid_whenever {
+    let duration: Duration = Utc::now() - billing.last_payment;
+    if duration.num_days() < 31 {
+      return true;
     }
-  } else {
-    billed = true;
   }
-  // X. Возврат результатов
-  if (s > 0) || validated {
-    match write_tokens(db, &token_auth.id, &tokens).await {
-      Err(_) => (false, billed),
-      Ok(_) => (validated, billed),
-    }
-  } else {
-    (validated, billed)
+  false
+}
+
+/// 1. Проверяет все токены пользователя на срок годности (см. `is_alive`), проверяет наличие текущего токена и возвращает true, если пользователь определён.
+/// 2. Проверяет данные оплаты (см. `is_billed`) и возвращает true, если пользователь имеет оплаченный аккаунт.
+///
+/// Истёкшие токены удаляются, а у долгоживущего токена обновляется дата последнего использования. Сведения авторизации записываются, только если не изменились с момента чтения (см. `Storage::swap_user_creds`), иначе проверка повторяется: так запись не теряет токены, выданные одновременно с ней, и не возвращает обменянные.
+///
+/// TODO сделать Redis-подключение и хранить данные по токенам вместо того, чтобы каждый раз валидировать их через базу данных.
+pub async fn verify_user(db: &dyn Storage, token_auth: &TokenAuth, cfg: &AppConfig) -> (bool, bool) {
+  const MAX_ATTEMPTS: usize = 5;
+  let hashed = hash_token(&token_auth.token);
+  let mut checked = (false, false);
+  for _ in 0..MAX_ATTEMPTS {
+    let user = match db.user(&token_auth.id).await {
+      Ok(v) => v,
+      _ => return (false, false),
+    };
+    let (mut user_credentials, billing) = match (
+      serde_json::from_str::<UserCredentials>(&user.user_creds), serde_json::from_str::<AccountPlanDetails>(&user.apd)
+    ) {
+      (Ok(creds), Ok(billing)) => (creds, billing),
+      _ => return (false, false),
+    };
+    // 1. Проверка токенов
+    let now = Utc::now();
+    let count = user_credentials.tokens.len();
+    user_credentials.tokens.retain(|t| is_alive(t, &now, cfg));
+    let pruned = user_credentials.tokens.len() < count;
+    let token = user_credentials.tokens.iter_mut().find(|t| t.tk == hashed);
+    let validated = token.is_some();
+    // Дату последнего использования нужно обновлять только у долгоживущих токенов.
+    let touched = match token {
+      Some(token) if token.expires_dt.is_none() => {
+        token.from_dt = now;
+        true
+      },
+      _ => false,
+    };
+    // 2. Проверка оплаты
+    checked = (validated, is_billed(&billing));
+    // X. Возврат результатов
+    if !pruned && !touched { return checked; };
+    let user_creds = match serde_json::to_string(&user_credentials) {
+      Ok(v) => v,
+      _ => return (false, checked.1),
+    };
+    match db.swap_user_creds(&token_auth.id, &user.user_creds, &user_creds).await {
+      Ok(true) => return checked,
+      Ok(false) => continue,
+      Err(_) => return (false, checked.1),
+    };
+  };
+  // Сведения авторизации всё время меняют другие запросы: токен проверен по последним прочитанным сведениям, а истёкшие токены удалит следующая проверка.
+  checked
+}
+
+/// Проверяет пользователя, которому выдана подписанная ссылка (см. `sec::signing`), и возвращает true, если пользователь имеет оплаченный аккаунт.
+///
+/// Ссылка действует, только пока у пользователя есть хотя бы один сеанс - действующий токен обновления, долгоживущий токен или токен доступа, поэтому выход из всех сессий лишает силы и выданные ранее ссылки. Возвращает `None`, если сеансов нет.
+pub async fn verify_signed_user(db: &dyn Storage, user_id: &i64, cfg: &AppConfig) -> Option<bool> {
+  let (tokens, refresh_tokens, billing) = get_tokens_and_billing(db, user_id).await.ok()?;
+  let now = Utc::now();
+  match tokens.iter().chain(refresh_tokens.iter()).any(|token| is_alive(token, &now, cfg)) {
+    true => Some(is_billed(&billing)),
+    false => None,
   }
 }
@@ -1,27 +1,695 @@
 use dotenv::{dotenv, from_filename};
-use std::{env, io, io::Read, process, fs, net::SocketAddr};
+use std::{collections::HashMap, env, io, io::Read, process, fs, net::SocketAddr, str::FromStr};
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio_postgres::config::SslMode;
+
+use crate::sec::auth::AdminKey;
+use crate::sec::geoip::GeoIpConfig;
+use crate::sec::proxy::IpNet;
+
+/// Источник значений переменных окружения: возвращает значение переменной по её имени.
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
 
 /// Конфигурация приложения.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct AppConfig {
-  /// Конфигурация Postgres.
-  pub pg: String,
+  /// Хранилище пользователей, досок и ключей (см. `storage`).
+  #[serde(default)]
+  pub storage: StorageBackend,
+  /// Параметры подключения к Postgres.
+  pub pg: PgConfig,
+  /// Путь к файлу базы данных SQLite, если выбрано хранилище `sqlite`. Если файла нет, он создаётся.
+  #[serde(default = "default_sqlite_path")]
+  pub sqlite_path: String,
   /// Ключ аутентификации администратора.
   pub admin_key: String,
   /// Порт прослушивания сервера.
   pub hyper_addr: SocketAddr,
+  /// Число минут, в течение которых действителен токен доступа.
+  #[serde(default = "default_access_token_ttl_minutes")]
+  pub access_token_ttl_minutes: i64,
+  /// Число дней, в течение которых неиспользуемый токен обновления остаётся действительным.
+  #[serde(default = "default_token_ttl_days")]
+  pub token_ttl_days: i64,
+  /// Число дней с момента выдачи токена обновления, после которых он становится недействительным, даже если им пользуются.
+  #[serde(default = "default_token_absolute_ttl_days")]
+  pub token_absolute_ttl_days: i64,
+  /// Ограничения тарифных планов.
+  #[serde(default)]
+  pub quotas: PlanQuotas,
+  /// Период в секундах, с которым фоновая задача обновляет признак просроченности задач.
+  #[serde(default = "default_overdue_scan_period_secs")]
+  pub overdue_scan_period_secs: u64,
+  /// Период в секундах, с которым фоновая задача проверяет и исправляет содержимое досок (см. `core::integrity`). Значение 0 отключает проверку.
+  #[serde(default = "default_revalidate_period_secs")]
+  pub revalidate_period_secs: u64,
+  /// Период в секундах, с которым фоновая задача убирает выполненные задачи досок (см. `core::recycle`).
+  #[serde(default = "default_recycle_scan_period_secs")]
+  pub recycle_scan_period_secs: u64,
+  /// Окно в миллисекундах, за которое собираются события одной доски, прежде чем по ним уведомляются наблюдатели и обновляются задачи GitHub (см. `core::events::Coalescer`). Значение 0 отключает сбор.
+  #[serde(default = "default_event_coalesce_ms")]
+  pub event_coalesce_ms: u64,
+  /// Приём уведомлений об оплате. Если не задан, уведомления не принимаются.
+  #[serde(default)]
+  pub billing: Option<BillingConfig>,
+  /// Наибольшее число соединений с Postgres в пуле.
+  #[serde(default = "default_db_pool_size")]
+  pub db_pool_size: u32,
+  /// Число секунд, в течение которых сервер ожидает соединения с Postgres, в том числе свободного соединения из пула.
+  #[serde(default = "default_db_connect_timeout_secs")]
+  pub db_connect_timeout_secs: u64,
+  /// Число секунд, после которых Postgres прерывает выполнение запроса. Значение 0 снимает ограничение.
+  #[serde(default = "default_db_statement_timeout_secs")]
+  pub db_statement_timeout_secs: u64,
+  /// Число миллисекунд, начиная с которого запрос к Postgres записывается в журнал сервера как медленный (см. `psql_handler::metrics`). Значение 0 отключает журнал медленных запросов.
+  #[serde(default = "default_db_slow_query_ms")]
+  pub db_slow_query_ms: u64,
+  /// Число попыток выполнить запрос к Postgres при временных ошибках, включая первую.
+  #[serde(default = "default_db_retry_attempts")]
+  pub db_retry_attempts: u32,
+  /// Задержка в миллисекундах перед второй попыткой. Перед каждой следующей попыткой задержка удваивается.
+  #[serde(default = "default_db_retry_backoff_ms")]
+  pub db_retry_backoff_ms: u64,
+  /// Число секунд, за которые сервер должен обработать запрос. Значение 0 снимает ограничение.
+  #[serde(default = "default_request_timeout_secs")]
+  pub request_timeout_secs: u64,
+  /// То же для методов администратора, в том числе восстановления из резервной копии.
+  #[serde(default = "default_admin_request_timeout_secs")]
+  pub admin_request_timeout_secs: u64,
+  /// Адреса клиентов, которым разрешены запросы из браузера. Первый адрес передаётся клиентам, не указанным в списке.
+  #[serde(default = "default_cors_origins")]
+  pub cors_origins: Vec<String>,
+  /// Заголовки, которые браузер может передавать в запросах с разрешённых адресов. Регистр не учитывается.
+  #[serde(default = "default_cors_allowed_headers")]
+  pub cors_allowed_headers: Vec<String>,
+  /// Число секунд, в течение которых браузер может не повторять предзапрос.
+  #[serde(default = "default_cors_max_age_secs")]
+  pub cors_max_age_secs: u64,
+  /// Заголовки безопасности, которые сервер передаёт во всех ответах.
+  #[serde(default)]
+  pub security_headers: SecurityHeaders,
+  /// Адреса и подсети обратных прокси, которым сервер доверяет определять адрес клиента (см. `sec::proxy`). Если список пуст, адресом клиента считается адрес соединения.
+  #[serde(default)]
+  pub trusted_proxies: Vec<IpNet>,
+  /// Число неудачных попыток входа, после которого аккаунт блокируется.
+  #[serde(default = "default_sign_in_max_failures")]
+  pub sign_in_max_failures: i64,
+  /// Период в секундах, в течение которого подсчитываются неудачные попытки входа.
+  #[serde(default = "default_sign_in_failures_window_secs")]
+  pub sign_in_failures_window_secs: i64,
+  /// Длительность блокировки входа в аккаунт в секундах.
+  #[serde(default = "default_sign_in_lockout_secs")]
+  pub sign_in_lockout_secs: i64,
+  /// Регистрация возможна только по ключам, выпущенным администратором.
+  #[serde(default)]
+  pub cc_key_required: bool,
+  /// Требования к логинам и паролям.
+  #[serde(default)]
+  pub credentials_policy: CredentialsPolicy,
+  /// Параметры хэширования паролей.
+  #[serde(default)]
+  pub password_hashing: PasswordHashing,
+  /// Поставщики входа через OAuth 2.0 / OpenID Connect. Если список пуст, вход через поставщиков невозможен.
+  #[serde(default)]
+  pub oauth_providers: Vec<OAuthProvider>,
+  /// Проверка логинов и паролей в каталоге LDAP / Active Directory. Если не задана, пароли проверяет сервер.
+  #[serde(default)]
+  pub ldap: Option<LdapConfig>,
+  /// Синхронизация задач досок с задачами репозиториев GitHub. Если не задана, доски нельзя связать с репозиториями.
+  #[serde(default)]
+  pub github: Option<GithubConfig>,
+  /// Отправка писем через почтовый шлюз. Если не задана, дайджесты изменений досок не отправляются.
+  #[serde(default)]
+  pub mailer: Option<MailerConfig>,
+  /// Открытая регистрация с подтверждением адреса электронной почты. Если не задана, пользователи регистрируются без подтверждения (см. `PUT /sign-up`).
+  #[serde(default)]
+  pub public_signup: Option<PublicSignupConfig>,
+  /// Источник сведений о странах клиентов для оповещений о входе из новых мест (см. `sec::geoip`). Если не задан, новым местом считается новый адрес.
+  #[serde(default)]
+  pub geoip: Option<GeoIpConfig>,
+  /// Отправка отчётов о досках по расписанию. Если не задана, отчёты нельзя настроить.
+  #[serde(default)]
+  pub reports: Option<ReportsConfig>,
+  /// Хранение неактивных досок. Если не задано, доски хранятся бессрочно.
+  #[serde(default)]
+  pub retention: Option<RetentionConfig>,
+  /// Кэш разобранных карточек досок.
+  #[serde(default)]
+  pub board_cache: BoardCacheConfig,
+  /// Жёсткие ограничения размера досок.
+  #[serde(default)]
+  pub board_limits: BoardLimits,
+  /// Очередь фоновых заданий.
+  #[serde(default)]
+  pub jobs: JobsConfig,
+  /// Путь к файлу, в который сервер при запуске записывает идентификатор своего процесса (см. `systemd::PidFile`). Если не задан, файл не создаётся.
+  #[serde(default)]
+  pub pid_file: Option<String>,
+}
+
+/// Параметры подключения к Postgres.
+///
+/// Задаются либо объектом с отдельными полями, либо, как в прежних версиях, строкой подключения: `host=localhost user=taskboard ...` или `postgresql://...`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PgConfig {
+  Params(PgParams),
+  Dsn(String),
+}
+
+/// Отдельные параметры подключения к Postgres. Значения не нужно экранировать: сервер передаёт их драйверу как есть.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PgParams {
+  #[serde(default = "default_pg_host")]
+  pub host: String,
+  #[serde(default = "default_pg_port")]
+  pub port: u16,
+  pub user: String,
+  #[serde(default)]
+  pub password: String,
+  /// База данных. Если отсутствует, используется база с именем пользователя.
+  #[serde(default)]
+  pub dbname: Option<String>,
+  #[serde(default)]
+  pub sslmode: PgSslMode,
+  /// Название приложения, под которым соединения сервера видны в `pg_stat_activity`.
+  #[serde(default = "default_pg_application_name")]
+  pub application_name: String,
+}
+
+/// Использование TLS при подключении к Postgres.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PgSslMode {
+  Disable,
+  /// TLS используется, если его поддерживают сервер и клиент.
+  #[default]
+  Prefer,
+  /// Без TLS соединение не устанавливается.
+  Require,
+}
+
+impl PgConfig {
+  /// Собирает параметры подключения для драйвера Postgres.
+  ///
+  /// Для отдельных параметров TCP keepalive отключается так же, как в строках подключения, которые сервер составлял раньше.
+  pub fn to_config(&self) -> Result<tokio_postgres::Config, tokio_postgres::Error> {
+    let params = match self {
+      PgConfig::Dsn(dsn) => return dsn.parse(),
+      PgConfig::Params(params) => params,
+    };
+    let mut pg = tokio_postgres::Config::new();
+    pg.host(&params.host)
+      .port(params.port)
+      .user(&params.user)
+      .password(&params.password)
+      .application_name(&params.application_name)
+      .keepalives(false)
+      .ssl_mode(match params.sslmode {
+        PgSslMode::Disable => SslMode::Disable,
+        PgSslMode::Prefer => SslMode::Prefer,
+        PgSslMode::Require => SslMode::Require,
+      });
+    if let Some(dbname) = &params.dbname {
+      pg.dbname(dbname);
+    };
+    Ok(pg)
+  }
+}
+
+/// Хранилище пользователей, досок и ключей.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+  /// PostgreSQL, заданный параметром `pg`.
+  #[default]
+  Postgres,
+  /// Файл SQLite, заданный параметром `sqlite_path`. Функции, данные которых хранятся только в PostgreSQL, недоступны (см. `storage`).
+  #[cfg(feature = "sqlite")]
+  Sqlite,
+}
+
+/// Кэш разобранных карточек досок (см. `core::board_cache`).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BoardCacheConfig {
+  /// Наибольшее число досок в кэше. Значение 0 отключает кэш.
+  pub capacity: usize,
+  /// Число секунд, после которых доска, к которой не обращались, удаляется из кэша.
+  pub ttl_secs: u64,
+}
+
+impl Default for BoardCacheConfig {
+  fn default() -> Self {
+    BoardCacheConfig { capacity: 256, ttl_secs: 10 * 60 }
+  }
+}
+
+/// Жёсткие ограничения размера досок (см. `core::board_size`). Отсутствующее ограничение означает, что размер не ограничен.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BoardLimits {
+  /// Наибольшее число задач в одной карточке.
+  pub max_tasks_per_card: Option<u64>,
+  /// Наибольший размер JSON доски в килобайтах (КиБ).
+  pub max_board_kb: Option<u64>,
+}
+
+/// Очередь фоновых заданий (см. `core::jobs`).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JobsConfig {
+  /// Число обработчиков заданий. Задания распределяются между ними по сегментам, и каждый обработчик выполняет задания своего сегмента по одному.
+  pub workers: u32,
+  /// Число секунд, на которое обработчик занимает задание. Пока задание выполняется, срок продлевается; если сервер остановился, не выполнив задание, по истечении срока его выполняет другой обработчик.
+  pub visibility_timeout_secs: u64,
+  /// Наибольшее число попыток выполнить задание, после которых оно считается невыполненным.
+  pub max_attempts: u32,
+  /// Число секунд до повторной попытки после первой неудачной; каждая следующая задержка вдвое дольше.
+  pub retry_delay_secs: u64,
+  /// Число миллисекунд между проверками очереди, пока в ней нет заданий.
+  pub poll_ms: u64,
+}
+
+impl Default for JobsConfig {
+  fn default() -> Self {
+    JobsConfig { workers: 4, visibility_timeout_secs: 5 * 60, max_attempts: 5, retry_delay_secs: 30, poll_ms: 1000 }
+  }
+}
+
+/// Заголовки безопасности, которые сервер передаёт во всех ответах.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SecurityHeaders {
+  /// Число секунд, в течение которых браузер обращается к серверу только по HTTPS (`Strict-Transport-Security`). Значение 0 отключает заголовок; включать его стоит, только если клиенты обращаются к серверу по HTTPS.
+  pub hsts_max_age_secs: u64,
+  /// Требовать HTTPS и для поддоменов сервера.
+  pub hsts_include_subdomains: bool,
+  /// Запретить браузеру угадывать тип содержимого ответа (`X-Content-Type-Options: nosniff`).
+  pub nosniff: bool,
+  /// Значение заголовка `Referrer-Policy`. Пустая строка отключает заголовок.
+  pub referrer_policy: String,
+}
+
+impl Default for SecurityHeaders {
+  fn default() -> Self {
+    SecurityHeaders { hsts_max_age_secs: 0, hsts_include_subdomains: false, nosniff: true, referrer_policy: String::from("no-referrer") }
+  }
+}
+
+/// Требования к логинам и паролям (см. `sec::policy`).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CredentialsPolicy {
+  /// Наименьшая длина логина в символах.
+  pub login_min_len: usize,
+  /// Наибольшая длина логина в символах.
+  pub login_max_len: usize,
+  /// Символы, которые допускаются в логине помимо букв и цифр.
+  pub login_extra_chars: String,
+  /// Наименьшая длина пароля в символах.
+  pub password_min_len: usize,
+  /// Наименьшая допустимая оценка стойкости пароля, от 0 до 4.
+  pub password_min_score: u8,
+  /// Слова, которые считаются легко угадываемыми частями пароля. Регистр не учитывается.
+  pub password_deny_list: Vec<String>,
+}
+
+impl Default for CredentialsPolicy {
+  fn default() -> Self {
+    CredentialsPolicy {
+      login_min_len: 3,
+      login_max_len: 64,
+      login_extra_chars: String::from("._-@+"),
+      password_min_len: 8,
+      password_min_score: 2,
+      password_deny_list: [
+        "password", "passw0rd", "qwerty", "123456", "111111", "letmein", "welcome", "admin", "iloveyou", "monkey",
+        "dragon", "sunshine", "football", "taskboard",
+      ].iter().map(|word| word.to_string()).collect(),
+    }
+  }
+}
+
+/// Параметры хэширования паролей алгоритмом Argon2id (см. `sec::key_gen`).
+///
+/// Пароли, хэшированные с другими параметрами, хэшируются заново при следующем входе пользователя.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasswordHashing {
+  /// Объём памяти в КиБ.
+  pub memory_kib: u32,
+  /// Число проходов.
+  pub iterations: u32,
+  /// Число потоков.
+  pub parallelism: u32,
+}
+
+impl Default for PasswordHashing {
+  fn default() -> Self {
+    PasswordHashing { memory_kib: 19 * 1024, iterations: 2, parallelism: 1 }
+  }
+}
+
+/// Поставщик входа через OAuth 2.0 / OpenID Connect (см. `sec::oidc`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct OAuthProvider {
+  /// Имя поставщика в адресах `/oauth/<имя>/...`. Для `google`, `github` и `yandex` адреса поставщика и поля профиля известны заранее, и их можно не задавать.
+  pub name: String,
+  /// Идентификатор клиента, выданный поставщиком.
+  pub client_id: String,
+  /// Секрет клиента, выданный поставщиком.
+  pub client_secret: String,
+  /// Адрес страницы клиента, на которую поставщик возвращает пользователя после входа.
+  pub redirect_uri: String,
+  /// Адрес страницы входа поставщика.
+  #[serde(default)]
+  pub authorize_url: Option<String>,
+  /// Адрес, по которому код авторизации обменивается на токен доступа.
+  #[serde(default)]
+  pub token_url: Option<String>,
+  /// Адрес, по которому с токеном доступа выдаётся профиль пользователя.
+  #[serde(default)]
+  pub userinfo_url: Option<String>,
+  /// Запрашиваемые разрешения через пробел.
+  #[serde(default)]
+  pub scope: Option<String>,
+  /// Поле профиля с постоянным идентификатором пользователя у поставщика. По умолчанию `sub`, как в OpenID Connect.
+  #[serde(default)]
+  pub subject_field: Option<String>,
+  /// Поле профиля, из которого берётся логин нового пользователя. По умолчанию `email`.
+  #[serde(default)]
+  pub login_field: Option<String>,
+  /// Поле профиля, из которого берётся отображаемое имя нового пользователя. По умолчанию `name`.
+  #[serde(default)]
+  pub name_field: Option<String>,
+}
+
+/// Проверка логинов и паролей в каталоге LDAP / Active Directory (см. `sec::ldap`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LdapConfig {
+  /// Адрес сервера каталога, например `ldaps://ldap.example.com`.
+  pub url: String,
+  /// Шаблон DN пользователя, в который вместо `{login}` подставляется логин, например `uid={login},ou=people,dc=example,dc=com`. Если не задан, DN пользователя ищется в `search_base`.
+  #[serde(default)]
+  pub bind_dn_template: Option<String>,
+  /// DN учётной записи, от имени которой ищется пользователь. Если не задан, поиск выполняется анонимно.
+  #[serde(default)]
+  pub bind_dn: Option<String>,
+  /// Пароль учётной записи `bind_dn`.
+  #[serde(default)]
+  pub bind_password: Option<String>,
+  /// DN, в пределах которого ищется пользователь.
+  #[serde(default)]
+  pub search_base: String,
+  /// Фильтр поиска пользователя, в который вместо `{login}` подставляется логин. Для Active Directory - `(sAMAccountName={login})`.
+  #[serde(default = "default_ldap_search_filter")]
+  pub search_filter: String,
+  /// Атрибут с отображаемым именем пользователя.
+  #[serde(default = "default_ldap_name_attribute")]
+  pub name_attribute: String,
+  /// Перейти на TLS командой StartTLS после подключения по `ldap://`.
+  #[serde(default)]
+  pub starttls: bool,
+  /// Число секунд, в течение которых сервер ожидает ответа каталога.
+  #[serde(default = "default_ldap_timeout_secs")]
+  pub timeout_secs: u64,
+  /// Пользователи, зарегистрированные на сервере, могут входить по своим паролям. Если выключено, входить могут только пользователи каталога.
+  #[serde(default = "default_ldap_allow_local_users")]
+  pub allow_local_users: bool,
+}
+
+/// Синхронизация задач досок с задачами репозиториев GitHub (см. `core::github`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct GithubConfig {
+  /// Ключ AES-256, которым шифруются токены доступа к GitHub, в виде 64 шестнадцатеричных символов. После замены ключа доски нужно связать с репозиториями заново.
+  pub secret_key: String,
+  /// Адрес GitHub REST API. Для GitHub Enterprise Server - `https://<сервер>/api/v3`.
+  #[serde(default = "default_github_api_url")]
+  pub api_url: String,
+  /// Период в секундах, с которым фоновая задача синхронизирует связанные доски.
+  #[serde(default = "default_github_sync_period_secs")]
+  pub sync_period_secs: u64,
+}
+
+/// Отправка писем через почтовый шлюз (см. `integrations::mailer`) и дайджестов изменений досок (см. `core::digest`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MailerConfig {
+  /// Адрес шлюза, на который отправляются письма.
+  pub url: String,
+  /// Токен доступа к шлюзу.
+  pub token: String,
+  /// Адрес отправителя писем.
+  pub from: String,
+  /// Период в секундах, с которым фоновая задача отправляет дайджесты пользователям, которым они причитаются.
+  #[serde(default = "default_digest_period_secs")]
+  pub digest_period_secs: u64,
+}
+
+/// Открытая регистрация с подтверждением адреса электронной почты (см. `core::signup`). Письма отправляются через почтовый шлюз (`AppConfig::mailer`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PublicSignupConfig {
+  /// Адрес страницы клиента, подтверждающей регистрацию. Письмо содержит ссылку на неё с параметром `token`.
+  pub confirm_url: String,
+  /// Число секунд, в течение которых действует ссылка подтверждения.
+  #[serde(default = "default_signup_token_ttl_secs")]
+  pub token_ttl_secs: i64,
+  /// Наибольшее число регистраций и повторных отправок письма с одного адреса клиента за час.
+  #[serde(default = "default_signup_max_per_ip_per_hour")]
+  pub max_per_ip_per_hour: i64,
+  /// Число секунд, которое должно пройти с отправки письма, прежде чем его можно отправить повторно.
+  #[serde(default = "default_signup_resend_cooldown_secs")]
+  pub resend_cooldown_secs: i64,
+  /// Период в секундах, с которым фоновая задача удаляет неподтверждённые регистрации с истёкшими ссылками.
+  #[serde(default = "default_signup_cleanup_period_secs")]
+  pub cleanup_period_secs: u64,
+}
+
+/// Отправка отчётов о досках по расписанию (см. `core::reports`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ReportsConfig {
+  /// Период в секундах, с которым фоновая задача отправляет отчёты, которым пора.
+  #[serde(default = "default_report_period_secs")]
+  pub period_secs: u64,
+  /// Токен бота Telegram, от имени которого отчёты отправляются в чаты. Если не задан, отчёты можно отправлять только вебхуками.
+  #[serde(default)]
+  pub telegram_bot_token: Option<String>,
+  /// Адрес Telegram Bot API.
+  #[serde(default = "default_telegram_api_url")]
+  pub telegram_api_url: String,
+  /// Разрешает вебхуки по HTTP - например, для сервисов внутри частной сети. По умолчанию вебхуки принимаются только по HTTPS.
+  #[serde(default)]
+  pub allow_http_webhooks: bool,
+}
+
+/// Хранение неактивных досок (см. `core::retention`).
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RetentionConfig {
+  /// Период в секундах, с которым фоновая задача проверяет доски.
+  #[serde(default = "default_retention_period_secs")]
+  pub period_secs: u64,
+  /// Число дней между предупреждением автора о неактивной доске и применением к ней `RetentionPolicy::action`.
+  #[serde(default = "default_retention_grace_days")]
+  pub grace_days: u64,
+  /// Число дней, в течение которых автор может получить выгрузку удалённой доски.
+  #[serde(default = "default_export_ttl_days")]
+  pub export_ttl_days: u64,
+  /// Правила для досок, автор которых пользуется бесплатным аккаунтом.
+  #[serde(default = "default_free_retention")]
+  pub free: RetentionPolicy,
+  /// Правила для досок, автор которых пользуется оплаченным аккаунтом.
+  #[serde(default)]
+  pub paid: RetentionPolicy,
+}
+
+/// Правила хранения досок тарифного плана.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct RetentionPolicy {
+  /// Число месяцев (по 30 дней) без изменений, после которых доска считается неактивной. Если не задано, доски хранятся бессрочно.
+  pub inactive_months: Option<u64>,
+  /// Что сервер делает с неактивной доской по прошествии `RetentionConfig::grace_days` после предупреждения автора.
+  #[serde(default)]
+  pub action: RetentionAction,
+}
+
+/// Действие с неактивной доской.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+  /// Доска остаётся как есть: автор только получает предупреждение.
+  #[default]
+  Flag,
+  /// Доска переносится в архив.
+  Archive,
+  /// Доска выгружается для автора и удаляется.
+  ExportAndDelete,
+}
+
+/// Настройки приёма уведомлений от платёжного провайдера.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BillingConfig {
+  /// Платёжный провайдер. Поддерживается `stripe`.
+  pub provider: String,
+  /// Секрет для проверки подписи уведомлений.
+  pub webhook_secret: String,
+}
+
+/// Ограничения тарифного плана. Отсутствующее ограничение означает, что количество не ограничено.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Quota {
+  /// Число досок, автором которых может быть пользователь.
+  pub max_boards: Option<u64>,
+  /// Число карточек на одной доске.
+  pub max_cards_per_board: Option<u64>,
+  /// Суммарный размер вложений в байтах.
+  pub max_attachments_bytes: Option<u64>,
+  /// Число участников одной доски, включая автора.
+  pub max_members: Option<u64>,
+}
+
+/// Ограничения бесплатного и оплаченного тарифных планов.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PlanQuotas {
+  /// Ограничения бесплатного аккаунта.
+  pub free: Quota,
+  /// Ограничения оплаченного аккаунта.
+  pub paid: Quota,
+}
+
+impl Default for PlanQuotas {
+  fn default() -> Self {
+    PlanQuotas {
+      free: Quota {
+        max_boards: Some(1),
+        max_cards_per_board: Some(100),
+        max_attachments_bytes: Some(100 * 1024 * 1024),
+        max_members: Some(5),
+      },
+      paid: Quota::default(),
+    }
+  }
+}
+
+fn default_sqlite_path() -> String { String::from("taskboard.sqlite3") }
+
+fn default_pg_host() -> String { String::from("localhost") }
+
+fn default_pg_port() -> u16 { 5432 }
+
+fn default_pg_application_name() -> String { String::from("cc-taskboard-server") }
+
+fn default_access_token_ttl_minutes() -> i64 { 15 }
+
+fn default_token_ttl_days() -> i64 { 5 }
+
+fn default_token_absolute_ttl_days() -> i64 { 30 }
+
+fn default_overdue_scan_period_secs() -> u64 { 60 }
+
+fn default_revalidate_period_secs() -> u64 { 24 * 60 * 60 }
+
+fn default_recycle_scan_period_secs() -> u64 { 60 * 60 }
+
+fn default_event_coalesce_ms() -> u64 { 2000 }
+
+fn default_db_pool_size() -> u32 { 15 }
+
+fn default_db_connect_timeout_secs() -> u64 { 10 }
+
+fn default_db_statement_timeout_secs() -> u64 { 30 }
+
+fn default_db_slow_query_ms() -> u64 { 500 }
+
+fn default_db_retry_attempts() -> u32 { 3 }
+
+fn default_db_retry_backoff_ms() -> u64 { 100 }
+
+fn default_request_timeout_secs() -> u64 { 30 }
+
+fn default_admin_request_timeout_secs() -> u64 { 10 * 60 }
+
+fn default_cors_origins() -> Vec<String> { vec![String::from("http://localhost:3000")] }
+
+fn default_cors_allowed_headers() -> Vec<String> {
+  ["App-Token", "X-Request-Id", "Content-Type", "Device-Name"].iter().map(|header| header.to_string()).collect()
+}
+
+fn default_cors_max_age_secs() -> u64 { 600 }
+
+fn default_sign_in_max_failures() -> i64 { 5 }
+
+fn default_sign_in_failures_window_secs() -> i64 { 15 * 60 }
+
+fn default_sign_in_lockout_secs() -> i64 { 15 * 60 }
+
+fn default_ldap_search_filter() -> String { String::from("(uid={login})") }
+
+fn default_ldap_name_attribute() -> String { String::from("displayName") }
+
+fn default_ldap_timeout_secs() -> u64 { 10 }
+
+fn default_ldap_allow_local_users() -> bool { true }
+
+fn default_github_api_url() -> String { String::from("https://api.github.com") }
+
+fn default_github_sync_period_secs() -> u64 { 300 }
+
+fn default_digest_period_secs() -> u64 { 60 * 60 }
+
+fn default_report_period_secs() -> u64 { 5 * 60 }
+
+fn default_signup_token_ttl_secs() -> i64 { 24 * 60 * 60 }
+
+fn default_signup_max_per_ip_per_hour() -> i64 { 5 }
+
+fn default_signup_resend_cooldown_secs() -> i64 { 60 }
+
+fn default_signup_cleanup_period_secs() -> u64 { 60 * 60 }
+
+fn default_telegram_api_url() -> String { String::from("https://api.telegram.org") }
+
+fn default_retention_period_secs() -> u64 { 60 * 60 }
+
+fn default_retention_grace_days() -> u64 { 14 }
+
+fn default_export_ttl_days() -> u64 { 90 }
+
+fn default_free_retention() -> RetentionPolicy { RetentionPolicy { inactive_months: Some(12), action: RetentionAction::Archive } }
+
+/// Считывает переменную окружения с данным префиксом или, если она не задана, возвращает значение по умолчанию.
+fn var_or<T>(vars: Vars, prefix: &str, name: &str, default: fn() -> T) -> Result<T, Box<dyn std::error::Error>>
+where T: FromStr, T::Err: std::error::Error + 'static {
+  match vars(&format!("{}{}", prefix, name)) {
+    Some(v) => Ok(v.parse()?),
+    _ => Ok(default()),
+  }
+}
+
+/// Считывает обязательную переменную окружения.
+fn var(vars: Vars, name: &str) -> Result<String, Box<dyn std::error::Error>> {
+  vars(name).ok_or_else(|| format!("Не задана переменная окружения {}.", name).into())
+}
+
+/// Считывает переменные окружения процесса.
+fn process_vars(name: &str) -> Option<String> {
+  env::var(name).ok()
 }
 
 impl AppConfig {
   /// Загружает конфигурацию.
   pub fn load() -> AppConfig {
-    match match env::args().nth(1) {
-      None => AppConfig::stdin_setup(),
-      Some(filepath) => AppConfig::parse_cfg_file(filepath),
-    } {
+    let conf = match AppConfig::taskboard_env_setup() {
+      Some(conf) => conf,
+      None => match config_arg() {
+        None => AppConfig::stdin_setup(),
+        Some(filepath) => AppConfig::parse_cfg_file(filepath),
+      },
+    };
+    match conf {
       Ok(conf) => {
-        println!("Конфигурация загружена.");
+        eprintln!("Конфигурация загружена.");
         conf
       },
       _ => {
@@ -38,13 +706,19 @@ impl AppConfig {
     println!("Введите имя пользователя PostgreSQL:");
     let mut buffer = String::new();
     stdin.read_line(&mut buffer)?;
-    let buffer = buffer.trim();
-    let pg = String::from("host=localhost user='") + buffer + &String::from("' password='");
+    let user = buffer.trim().to_string();
     println!("Введите пароль PostgreSQL:");
     let mut buffer = String::new();
     stdin.read_line(&mut buffer)?;
-    let buffer = buffer.trim();
-    let pg = pg + buffer + &String::from("' connect_timeout=10 keepalives=0");
+    let pg = PgConfig::Params(PgParams {
+      host: default_pg_host(),
+      port: default_pg_port(),
+      user,
+      password: buffer.trim().to_string(),
+      dbname: None,
+      sslmode: PgSslMode::default(),
+      application_name: default_pg_application_name(),
+    });
     println!("Введите IP-адрес и порт сервера:");
     let mut buffer = String::new();
     stdin.read_line(&mut buffer)?;
@@ -55,25 +729,240 @@ impl AppConfig {
     stdin.read_line(&mut buffer)?;
     let admin_key = String::from(buffer.strip_suffix('\n').ok_or("")?);
     match admin_key.len() < 64 {
-      true => Err(Box::new(io::Error::new(io::ErrorKind::Other, "Длина ключа администратора меньше 64 символов."))),
-      false => Ok(AppConfig { pg, admin_key, hyper_addr }),
+      true => Err(Box::new(io::Error::other("Длина ключа администратора меньше 64 символов."))),
+      false => Ok(AppConfig {
+        storage: StorageBackend::default(),
+        pg,
+        sqlite_path: default_sqlite_path(),
+        admin_key,
+        hyper_addr,
+        access_token_ttl_minutes: default_access_token_ttl_minutes(),
+        token_ttl_days: default_token_ttl_days(),
+        token_absolute_ttl_days: default_token_absolute_ttl_days(),
+        quotas: PlanQuotas::default(),
+        overdue_scan_period_secs: default_overdue_scan_period_secs(),
+        revalidate_period_secs: default_revalidate_period_secs(),
+        recycle_scan_period_secs: default_recycle_scan_period_secs(),
+        event_coalesce_ms: default_event_coalesce_ms(),
+        billing: None,
+        db_pool_size: default_db_pool_size(),
+        db_connect_timeout_secs: default_db_connect_timeout_secs(),
+        db_statement_timeout_secs: default_db_statement_timeout_secs(),
+        db_slow_query_ms: default_db_slow_query_ms(),
+        db_retry_attempts: default_db_retry_attempts(),
+        db_retry_backoff_ms: default_db_retry_backoff_ms(),
+        request_timeout_secs: default_request_timeout_secs(),
+        admin_request_timeout_secs: default_admin_request_timeout_secs(),
+        cors_origins: default_cors_origins(),
+        cors_allowed_headers: default_cors_allowed_headers(),
+        cors_max_age_secs: default_cors_max_age_secs(),
+        security_headers: SecurityHeaders::default(),
+        trusted_proxies: vec![],
+        sign_in_max_failures: default_sign_in_max_failures(),
+        sign_in_failures_window_secs: default_sign_in_failures_window_secs(),
+        sign_in_lockout_secs: default_sign_in_lockout_secs(),
+        cc_key_required: false,
+        credentials_policy: CredentialsPolicy::default(),
+        password_hashing: PasswordHashing::default(),
+        oauth_providers: vec![],
+        ldap: None,
+        github: None,
+        mailer: None,
+        public_signup: None,
+        geoip: None,
+        reports: None,
+        retention: None,
+        board_cache: BoardCacheConfig::default(),
+        board_limits: BoardLimits::default(),
+        jobs: JobsConfig::default(),
+        pid_file: None,
+      }),
     }
   }
   
-  /// Считывает информацию из переменных окружения.
+  /// Считывает информацию из переменных окружения, заданных для Docker Compose (см. `env.example`).
   fn env_setup() -> Result<AppConfig, Box<dyn std::error::Error>> {
     if dotenv().is_err() { from_filename("/etc/taskboard.conf").ok(); }
-    let pg = format!(
-      "host={} user='{}' password='{}' connect_timeout=10 keepalives=0",
-      std::env::var("POSTGRES_HOST").unwrap(),
-      std::env::var("POSTGRES_USER").unwrap(),
-      std::env::var("POSTGRES_PASSWORD").unwrap()
-    );
-    let hyper_addr: SocketAddr = std::env::var("SERVER_LISTEN").unwrap().parse()?;
-    let admin_key = std::env::var("ADMIN_KEY").unwrap();
-    match admin_key.len() < 64 {
-      true => Err(Box::new(io::Error::new(io::ErrorKind::Other, "Длина ключа администратора меньше 64 символов."))),
-      false => Ok(AppConfig { pg, admin_key, hyper_addr }),
+    AppConfig::env_setup_from(&process_vars)
+  }
+  
+  /// Собирает конфигурацию из переменных окружения, заданных для Docker Compose, получая их из `vars`.
+  fn env_setup_from(vars: Vars) -> Result<AppConfig, Box<dyn std::error::Error>> {
+    let pg = PgConfig::Params(PgParams {
+      host: var(vars, "POSTGRES_HOST")?,
+      port: var_or(vars, "", "POSTGRES_PORT", default_pg_port)?,
+      user: var(vars, "POSTGRES_USER")?,
+      password: var(vars, "POSTGRES_PASSWORD")?,
+      dbname: vars("POSTGRES_DB"),
+      sslmode: match vars("POSTGRES_SSLMODE") {
+        Some(v) => serde_json::from_value(serde_json::Value::String(v))?,
+        _ => PgSslMode::default(),
+      },
+      application_name: var_or(vars, "", "POSTGRES_APPLICATION_NAME", default_pg_application_name)?,
+    });
+    let hyper_addr: SocketAddr = var(vars, "SERVER_LISTEN")?.parse()?;
+    let admin_key = var(vars, "ADMIN_KEY")?;
+    AppConfig::from_env(vars, "", pg, admin_key, hyper_addr)
+  }
+  
+  /// Считывает информацию из переменных окружения с префиксом `TASKBOARD_`.
+  ///
+  /// Возвращает `None`, если не задана переменная `TASKBOARD_PG`: тогда конфигурация загружается из файла или запрашивается у пользователя.
+  fn taskboard_env_setup() -> Option<Result<AppConfig, Box<dyn std::error::Error>>> {
+    let pg = PgConfig::Dsn(env::var("TASKBOARD_PG").ok()?);
+    Some((|| {
+      let hyper_addr: SocketAddr = env::var("TASKBOARD_ADDR")?.parse()?;
+      let admin_key = env::var("TASKBOARD_ADMIN_KEY")?;
+      AppConfig::from_env(&process_vars, "TASKBOARD_", pg, admin_key, hyper_addr)
+    })())
+  }
+  
+  /// Дополняет обязательные параметры необязательными, считывая их из переменных окружения с данным префиксом.
+  fn from_env(vars: Vars, prefix: &str, pg: PgConfig, admin_key: String, hyper_addr: SocketAddr)
+    -> Result<AppConfig, Box<dyn std::error::Error>>
+  {
+    // Ограничения тарифных планов передаются одной переменной в том же JSON-виде, что и в файле конфигурации.
+    let quotas: PlanQuotas = match vars(&format!("{}QUOTAS", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => PlanQuotas::default(),
+    };
+    // Требования к логинам и паролям передаются одной переменной в том же JSON-виде, что и в файле конфигурации.
+    let credentials_policy: CredentialsPolicy = match vars(&format!("{}CREDENTIALS_POLICY", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => CredentialsPolicy::default(),
+    };
+    let password_hashing: PasswordHashing = match vars(&format!("{}PASSWORD_HASHING", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => PasswordHashing::default(),
+    };
+    // Поставщики входа передаются одной переменной в виде JSON-массива, как и в файле конфигурации.
+    let oauth_providers: Vec<OAuthProvider> = match vars(&format!("{}OAUTH_PROVIDERS", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => vec![],
+    };
+    // Настройки каталога пользователей передаются одной переменной в том же JSON-виде, что и в файле конфигурации.
+    let ldap: Option<LdapConfig> = match vars(&format!("{}LDAP", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let github: Option<GithubConfig> = match vars(&format!("{}GITHUB", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let mailer: Option<MailerConfig> = match vars(&format!("{}MAILER", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let public_signup: Option<PublicSignupConfig> = match vars(&format!("{}PUBLIC_SIGNUP", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let geoip: Option<GeoIpConfig> = match vars(&format!("{}GEOIP", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let reports: Option<ReportsConfig> = match vars(&format!("{}REPORTS", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let retention: Option<RetentionConfig> = match vars(&format!("{}RETENTION", prefix)) {
+      Some(v) => Some(serde_json::from_str(&v)?),
+      _ => None,
+    };
+    let board_cache: BoardCacheConfig = match vars(&format!("{}BOARD_CACHE", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => BoardCacheConfig::default(),
+    };
+    let board_limits: BoardLimits = match vars(&format!("{}BOARD_LIMITS", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => BoardLimits::default(),
+    };
+    let jobs: JobsConfig = match vars(&format!("{}JOBS", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => JobsConfig::default(),
+    };
+    // Адреса клиентов перечисляются через запятую.
+    let cors_origins = match vars(&format!("{}CORS_ORIGINS", prefix)) {
+      Some(v) => v.split(',').map(|origin| origin.trim().to_string()).filter(|origin| !origin.is_empty()).collect(),
+      _ => default_cors_origins(),
+    };
+    // Заголовки перечисляются через запятую.
+    let cors_allowed_headers = match vars(&format!("{}CORS_ALLOWED_HEADERS", prefix)) {
+      Some(v) => v.split(',').map(|header| header.trim().to_string()).filter(|header| !header.is_empty()).collect(),
+      _ => default_cors_allowed_headers(),
+    };
+    let security_headers: SecurityHeaders = match vars(&format!("{}SECURITY_HEADERS", prefix)) {
+      Some(v) => serde_json::from_str(&v)?,
+      _ => SecurityHeaders::default(),
+    };
+    // Адреса и подсети прокси перечисляются через запятую.
+    let trusted_proxies = match vars(&format!("{}TRUSTED_PROXIES", prefix)) {
+      Some(v) => v.split(',').map(str::trim).filter(|net| !net.is_empty()).map(str::parse).collect::<Result<_, _>>()?,
+      _ => vec![],
+    };
+    // Хранилище задаётся названием в том же виде, что и в файле конфигурации.
+    let storage: StorageBackend = match vars(&format!("{}STORAGE", prefix)) {
+      Some(v) => serde_json::from_value(serde_json::Value::String(v))?,
+      _ => StorageBackend::default(),
+    };
+    let billing = vars(&format!("{}STRIPE_WEBHOOK_SECRET", prefix)).map(|webhook_secret| BillingConfig {
+      provider: String::from("stripe"),
+      webhook_secret,
+    });
+    let conf = AppConfig {
+      storage,
+      pg,
+      sqlite_path: var_or(vars, prefix, "SQLITE_PATH", default_sqlite_path)?,
+      admin_key,
+      hyper_addr,
+      access_token_ttl_minutes: var_or(vars, prefix, "ACCESS_TOKEN_TTL_MINUTES", default_access_token_ttl_minutes)?,
+      token_ttl_days: var_or(vars, prefix, "TOKEN_TTL_DAYS", default_token_ttl_days)?,
+      token_absolute_ttl_days: var_or(vars, prefix, "TOKEN_ABSOLUTE_TTL_DAYS", default_token_absolute_ttl_days)?,
+      quotas,
+      overdue_scan_period_secs: var_or(vars, prefix, "OVERDUE_SCAN_PERIOD_SECS", default_overdue_scan_period_secs)?,
+      revalidate_period_secs: var_or(vars, prefix, "REVALIDATE_PERIOD_SECS", default_revalidate_period_secs)?,
+      recycle_scan_period_secs: var_or(vars, prefix, "RECYCLE_SCAN_PERIOD_SECS", default_recycle_scan_period_secs)?,
+      event_coalesce_ms: var_or(vars, prefix, "EVENT_COALESCE_MS", default_event_coalesce_ms)?,
+      billing,
+      db_pool_size: var_or(vars, prefix, "DB_POOL_SIZE", default_db_pool_size)?,
+      db_connect_timeout_secs: var_or(vars, prefix, "DB_CONNECT_TIMEOUT_SECS", default_db_connect_timeout_secs)?,
+      db_statement_timeout_secs: var_or(vars, prefix, "DB_STATEMENT_TIMEOUT_SECS", default_db_statement_timeout_secs)?,
+      db_slow_query_ms: var_or(vars, prefix, "DB_SLOW_QUERY_MS", default_db_slow_query_ms)?,
+      db_retry_attempts: var_or(vars, prefix, "DB_RETRY_ATTEMPTS", default_db_retry_attempts)?,
+      db_retry_backoff_ms: var_or(vars, prefix, "DB_RETRY_BACKOFF_MS", default_db_retry_backoff_ms)?,
+      request_timeout_secs: var_or(vars, prefix, "REQUEST_TIMEOUT_SECS", default_request_timeout_secs)?,
+      admin_request_timeout_secs: var_or(
+        vars, prefix, "ADMIN_REQUEST_TIMEOUT_SECS", default_admin_request_timeout_secs
+      )?,
+      cors_origins,
+      cors_allowed_headers,
+      cors_max_age_secs: var_or(vars, prefix, "CORS_MAX_AGE_SECS", default_cors_max_age_secs)?,
+      security_headers,
+      trusted_proxies,
+      sign_in_max_failures: var_or(vars, prefix, "SIGN_IN_MAX_FAILURES", default_sign_in_max_failures)?,
+      sign_in_failures_window_secs: var_or(
+        vars, prefix, "SIGN_IN_FAILURES_WINDOW_SECS", default_sign_in_failures_window_secs
+      )?,
+      sign_in_lockout_secs: var_or(vars, prefix, "SIGN_IN_LOCKOUT_SECS", default_sign_in_lockout_secs)?,
+      cc_key_required: var_or(vars, prefix, "CC_KEY_REQUIRED", bool::default)?,
+      credentials_policy,
+      password_hashing,
+      oauth_providers,
+      ldap,
+      github,
+      mailer,
+      public_signup,
+      geoip,
+      reports,
+      retention,
+      board_cache,
+      board_limits,
+      jobs,
+      pid_file: vars(&format!("{}PID_FILE", prefix)),
+    };
+    match conf.admin_key.len() < 64 {
+      true => Err(Box::new(io::Error::other("Длина ключа администратора меньше 64 символов."))),
+      false => Ok(conf),
     }
   }
   
@@ -85,13 +974,209 @@ impl AppConfig {
     file.read_to_string(&mut buffer)?;
     let conf: AppConfig = serde_json::from_str(&buffer)?;
     match conf.admin_key.len() < 64 {
-      true => Err(Box::new(io::Error::new(io::ErrorKind::Other, "Длина ключа администратора меньше 64 символов."))),
+      true => Err(Box::new(io::Error::other("Длина ключа администратора меньше 64 символов."))),
       false => Ok(conf),
     }
   }
+  
+  /// Повторно считывает конфигурацию из того источника, из которого она была загружена при запуске.
+  ///
+  /// Перечитать можно только файлы: конфигурацию из переменных окружения `TASKBOARD_*` и ответы, введённые при запуске, изменить нельзя. Значения из файла `.env` имеют приоритет над переменными окружения процесса, поскольку последние остались такими же, как при запуске.
+  // Файл `.env` считывается устаревшими функциями: только они возвращают значения из файла, а не записывают их в окружение процесса.
+  #[allow(deprecated)]
+  fn reread() -> Result<AppConfig, Box<dyn std::error::Error>> {
+    if env::var("TASKBOARD_PG").is_ok() {
+      return Err("Конфигурация из переменных окружения не может быть перезагружена.".into());
+    };
+    match config_arg() {
+      None => Err("Конфигурация, введённая при запуске, не может быть перезагружена.".into()),
+      Some(arg) if arg == "--env" => {
+        let file: HashMap<String, String> = match dotenv::dotenv_iter() {
+          Ok(iter) => iter.collect::<Result<_, _>>()?,
+          _ => dotenv::from_filename_iter("/etc/taskboard.conf")?.collect::<Result<_, _>>()?,
+        };
+        AppConfig::env_setup_from(&|name| file.get(name).cloned().or_else(|| process_vars(name)))
+      },
+      Some(filepath) => AppConfig::parse_cfg_file(filepath),
+    }
+  }
+  
+  /// Заменяет параметры, которые можно изменить без перезапуска сервера, значениями из `new`.
+  ///
+  /// Остальные параметры (подключение к Postgres, адрес сервера, ключ администратора, пул соединений, порог медленных запросов, период проверки просроченных задач, синхронизация с GitHub, отправка отчётов о досках, хранение неактивных досок, кэш досок и PID-файл) применяются только при запуске.
+  fn apply_tunables(&mut self, new: AppConfig) {
+    self.access_token_ttl_minutes = new.access_token_ttl_minutes;
+    self.token_ttl_days = new.token_ttl_days;
+    self.token_absolute_ttl_days = new.token_absolute_ttl_days;
+    self.quotas = new.quotas;
+    self.billing = new.billing;
+    self.request_timeout_secs = new.request_timeout_secs;
+    self.admin_request_timeout_secs = new.admin_request_timeout_secs;
+    self.cors_origins = new.cors_origins;
+    self.cors_allowed_headers = new.cors_allowed_headers;
+    self.cors_max_age_secs = new.cors_max_age_secs;
+    self.security_headers = new.security_headers;
+    self.trusted_proxies = new.trusted_proxies;
+    self.sign_in_max_failures = new.sign_in_max_failures;
+    self.sign_in_failures_window_secs = new.sign_in_failures_window_secs;
+    self.sign_in_lockout_secs = new.sign_in_lockout_secs;
+    self.cc_key_required = new.cc_key_required;
+    self.credentials_policy = new.credentials_policy;
+    self.password_hashing = new.password_hashing;
+    self.oauth_providers = new.oauth_providers;
+    self.ldap = new.ldap;
+    self.geoip = new.geoip;
+  }
+}
+
+/// Конфигурация, которую можно перезагрузить во время работы сервера.
+///
+/// Каждый запрос получает снимок конфигурации на момент своего начала, поэтому перезагрузка не затрагивает уже выполняющиеся запросы и не разрывает соединения.
+#[derive(Clone)]
+pub struct LiveConfig(Arc<RwLock<AppConfig>>);
+
+impl LiveConfig {
+  /// Создаёт перезагружаемую конфигурацию из загруженной при запуске.
+  pub fn new(cfg: AppConfig) -> LiveConfig {
+    LiveConfig(Arc::new(RwLock::new(cfg)))
+  }
+  
+  /// Возвращает снимок текущей конфигурации.
+  pub fn get(&self) -> AppConfig {
+    self.0.read().unwrap().clone()
+  }
+  
+  /// Перечитывает конфигурацию и применяет параметры, которые можно изменить без перезапуска сервера.
+  pub fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+    let new = AppConfig::reread()?;
+    self.0.write().unwrap().apply_tunables(new);
+    Ok(())
+  }
+}
+
+/// Перезагружает конфигурацию при получении сигнала SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(cfg: LiveConfig) {
+  use tokio::signal::unix::{signal, SignalKind};
+  let mut hangups = match signal(SignalKind::hangup()) {
+    Ok(v) => v,
+    Err(e) => {
+      eprintln!("Не удалось подписаться на сигнал SIGHUP: {}", e);
+      return;
+    },
+  };
+  while hangups.recv().await.is_some() {
+    match cfg.reload() {
+      Ok(_) => println!("Конфигурация перезагружена."),
+      Err(e) => eprintln!("Не удалось перезагрузить конфигурацию: {}", e),
+    };
+  };
 }
 
 /// Возвращает конфигурацию для запуска сервера.
 pub fn get_config() -> AppConfig {
   AppConfig::load()
 }
+
+/// Команда, переданная серверу в командной строке.
+pub enum Command {
+  /// Запуск сервера. Выполняется, если команда не указана.
+  Serve,
+  /// Настройка базы данных: создание таблиц и приведение данных к актуальной модели (см. `core::db_setup`).
+  Migrate,
+  /// Выпуск ключа администратора (см. `core::admin_keys`).
+  CreateAdminKey(AdminKey),
+  /// Выгрузка доски в JSON в стандартный вывод.
+  Export { board_id: i64 },
+}
+
+/// Разобранные аргументы командной строки.
+struct Args {
+  command: Command,
+  /// Источник конфигурации: путь к JSON-файлу или `--env`. Если отсутствует, конфигурация запрашивается у пользователя.
+  config: Option<String>,
+}
+
+/// Справка по командам сервера.
+const USAGE: &str = "Использование: cc-taskboard-server [команда] [параметры] [файл конфигурации | --env]
+
+Команды:
+  serve                                       запустить сервер (по умолчанию)
+  migrate                                     создать таблицы и привести данные к актуальной модели
+  create-admin-key --name N --scopes S1,S2 [--expires-at 2030-01-01T00:00:00Z]
+                                              выпустить ключ администратора и вывести его;
+                                              области действия: setup, user-management, billing, backup
+  export --board N                            вывести доску в JSON
+  help                                        показать эту справку";
+
+/// Разбирает аргументы командной строки без имени программы.
+///
+/// Первый аргумент, если он не является командой, считается источником конфигурации - так сервер запускался до появления команд.
+fn parse_args(args: &[String]) -> Result<Args, String> {
+  let (name, rest) = match args.split_first() {
+    Some((name, rest)) if ["serve", "migrate", "create-admin-key", "export"].contains(&name.as_str()) => (name.as_str(), rest),
+    _ => ("serve", args),
+  };
+  let mut options: HashMap<&str, &str> = HashMap::new();
+  let mut config = None;
+  let mut rest = rest.iter();
+  while let Some(arg) = rest.next() {
+    match arg.as_str() {
+      "--env" => config = Some(arg.clone()),
+      option if option.starts_with("--") => {
+        let value = rest.next().ok_or_else(|| format!("Не задано значение параметра {}.", option))?;
+        options.insert(option, value);
+      },
+      _ if config.is_some() => return Err(format!("Лишний аргумент: {}.", arg)),
+      _ => config = Some(arg.clone()),
+    };
+  };
+  let command = match name {
+    "migrate" => Command::Migrate,
+    "create-admin-key" => {
+      let scopes = required(&mut options, "--scopes")?.split(',')
+        .map(|scope| serde_json::from_value(serde_json::Value::from(scope.trim())).map_err(|_| format!("Неизвестная область действия: {}.", scope)))
+        .collect::<Result<_, _>>()?;
+      let expires_at = match options.remove("--expires-at") {
+        Some(v) => Some(DateTime::parse_from_rfc3339(v).map_err(|_| format!("Неверная дата: {}.", v))?.with_timezone(&Utc)),
+        None => None,
+      };
+      Command::CreateAdminKey(AdminKey { name: required(&mut options, "--name")?.to_string(), scopes, expires_at })
+    },
+    "export" => {
+      let board = required(&mut options, "--board")?;
+      Command::Export { board_id: board.parse().map_err(|_| format!("Неверный идентификатор доски: {}.", board))? }
+    },
+    _ => Command::Serve,
+  };
+  match options.keys().next() {
+    Some(option) => Err(format!("Неизвестный параметр: {}.", option)),
+    None => Ok(Args { command, config }),
+  }
+}
+
+/// Извлекает обязательный параметр команды.
+fn required<'a>(options: &mut HashMap<&str, &'a str>, name: &str) -> Result<&'a str, String> {
+  options.remove(name).ok_or_else(|| format!("Не задан параметр {}.", name))
+}
+
+/// Возвращает источник конфигурации из аргументов командной строки.
+fn config_arg() -> Option<String> {
+  parse_args(&env::args().skip(1).collect::<Vec<_>>()).ok().and_then(|args| args.config)
+}
+
+/// Возвращает команду из аргументов командной строки. Если аргументы неверны, выводит справку и завершает процесс.
+pub fn get_command() -> Command {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.first().is_some_and(|arg| ["help", "--help", "-h"].contains(&arg.as_str())) {
+    println!("{}", USAGE);
+    process::exit(0);
+  };
+  match parse_args(&args) {
+    Ok(args) => args.command,
+    Err(e) => {
+      eprintln!("{}\n\n{}", e, USAGE);
+      process::exit(2);
+    },
+  }
+}
@@ -0,0 +1,387 @@
+//! Хранилище в памяти для модульных тестов `core`.
+//!
+//! `MockDb` хранит заранее заданные строки (`MockData`) и ведёт себя так же, как остальные хранилища, поэтому логику приложения можно проверять без сервера баз данных. Сбои хранилища задаются по названиям методов `Storage` (см. `MockDb::fail`).
+//!
+//! `MockDb` заменяет только `Storage`: функции `core`, которые принимают подключение к PostgreSQL (`&Db`, см. `Storage::postgres`), с ним не работают и проверяются интеграционными тестами (`tests/`). Это резервное копирование, миграции (`core::compat`) и история задач (`core::get_task_history`), а также модули `admin_audit`, `delta`, `digest`, `github`, `identities`, `integrity`, `jobs`, `notifications`, `orgs`, `overdue`, `recycle`, `reports`, `retention`, `security_events`, `signup`, `sprints`, `task_history`, `undo` и `views`.
+//!
+//! Модуль собирается в тестах и с функцией сборки `test-util`.
+
+use async_trait::async_trait;
+use custom_error::custom_error;
+use serde_json::Value as JsonValue;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::core::BoardsCursor;
+use crate::model::{BoardPrefsPatch, BoardSort, UserProfile};
+use crate::psql_handler::jsonb::Edit;
+use crate::storage::{AdminKeyRow, BoardListRow, BoardRow, CcKeyRow, NewUser, Queries, Storage, UserRow};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub InjectedFailure{method: &'static str} = "Сбой хранилища в методе {method}."}
+custom_error!{NotFound{} = "Запись не найдена."}
+custom_error!{Duplicate{} = "Запись нарушает ограничение уникальности."}
+
+/// Неудачные попытки входа по логину.
+#[derive(Clone, Default)]
+pub struct SignInFailures {
+  pub failures: i64,
+  pub first_failure: i64,
+  pub locked_until: i64,
+}
+
+/// Настройки доски, заданные пользователем.
+#[derive(Clone, Default)]
+pub struct BoardPrefs {
+  pub favorite: bool,
+  pub muted: bool,
+  pub position: Option<i64>,
+}
+
+/// Данные хранилища в памяти.
+#[derive(Default)]
+pub struct MockData {
+  pub cc_keys: Vec<CcKeyRow>,
+  /// Ключи администраторов вместе с хэшами ключей.
+  pub admin_keys: Vec<(AdminKeyRow, Vec<u8>)>,
+  pub users: Vec<UserRow>,
+  /// Публичные профили пользователей по идентификаторам.
+  pub profiles: BTreeMap<i64, UserProfile>,
+  pub boards: Vec<BoardRow>,
+  /// Настройки досок по парам из идентификаторов пользователя и доски.
+  pub board_prefs: HashMap<(i64, i64), BoardPrefs>,
+  pub sign_in_failures: HashMap<String, SignInFailures>,
+  /// Последовательности идентификаторов: идентификатор, который будет выдан следующим.
+  pub id_seqs: HashMap<String, i64>,
+}
+
+/// Хранилище в памяти.
+#[derive(Default)]
+pub struct MockDb {
+  data: Mutex<MockData>,
+  failures: Mutex<HashSet<&'static str>>,
+}
+
+impl MockDb {
+  /// Создаёт хранилище с заданными строками.
+  pub fn new(data: MockData) -> MockDb {
+    MockDb { data: Mutex::new(data), failures: Mutex::new(HashSet::new()) }
+  }
+
+  /// Возвращает данные хранилища, чтобы проверить или изменить их в тесте.
+  pub fn data(&self) -> MutexGuard<'_, MockData> {
+    self.data.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// Задаёт сбой метода `Storage`: пока он не отменён (см. `recover`), метод ничего не изменяет и возвращает `InjectedFailure`.
+  pub fn fail(&self, method: &'static str) {
+    self.failures.lock().unwrap_or_else(PoisonError::into_inner).insert(method);
+  }
+
+  /// Отменяет сбой метода.
+  pub fn recover(&self, method: &'static str) {
+    self.failures.lock().unwrap_or_else(PoisonError::into_inner).remove(method);
+  }
+
+  /// Возвращает `InjectedFailure`, если для метода задан сбой.
+  fn check(&self, method: &'static str) -> MResult<()> {
+    match self.failures.lock().unwrap_or_else(PoisonError::into_inner).contains(method) {
+      true => Err(Box::new(InjectedFailure{ method })),
+      false => Ok(()),
+    }
+  }
+
+  /// Возвращает данные хранилища, если для метода не задан сбой.
+  fn call(&self, method: &'static str) -> MResult<MutexGuard<'_, MockData>> {
+    self.check(method)?;
+    Ok(self.data())
+  }
+}
+
+impl MockData {
+  fn user_mut(&mut self, id: &i64) -> MResult<&mut UserRow> {
+    self.users.iter_mut().find(|u| u.id == *id).ok_or_else(|| NotFound{}.into())
+  }
+}
+
+/// Возвращает название доски из JSON её заголовка.
+fn title(header: &str) -> String {
+  let header: JsonValue = serde_json::from_str(header).unwrap_or_default();
+  header["title"].as_str().unwrap_or_default().to_string()
+}
+
+#[async_trait]
+impl Storage for MockDb {
+  async fn insert_cc_keys(&self, keys: &[CcKeyRow]) -> MResult<()> {
+    let mut data = self.call("insert_cc_keys")?;
+    if keys.iter().any(|k| data.cc_keys.iter().any(|c| c.key == k.key)) { return Err(Box::new(Duplicate{})); };
+    data.cc_keys.extend(keys.iter().cloned());
+    Ok(())
+  }
+
+  async fn unused_cc_keys(&self, now: i64) -> MResult<Vec<CcKeyRow>> {
+    let data = self.call("unused_cc_keys")?;
+    let mut keys: Vec<CcKeyRow> = data.cc_keys.iter().filter(|k| k.expires_at.is_none_or(|e| e > now)).cloned().collect();
+    keys.sort_by_key(|k| k.created_at);
+    Ok(keys)
+  }
+
+  async fn delete_cc_keys(&self, keys: &[String]) -> MResult<usize> {
+    let mut data = self.call("delete_cc_keys")?;
+    let before = data.cc_keys.len();
+    data.cc_keys.retain(|k| !keys.contains(&k.key));
+    Ok(before - data.cc_keys.len())
+  }
+
+  async fn admin_key_by_hash(&self, key_hash: &[u8]) -> MResult<Option<AdminKeyRow>> {
+    let data = self.call("admin_key_by_hash")?;
+    Ok(data.admin_keys.iter().find(|(_, h)| h == key_hash).map(|(k, _)| k.clone()))
+  }
+
+  async fn put_admin_key(&self, key: &AdminKeyRow, key_hash: &[u8]) -> MResult<()> {
+    let mut data = self.call("put_admin_key")?;
+    if data.admin_keys.iter().any(|(k, h)| h == key_hash && k.name != key.name) { return Err(Box::new(Duplicate{})); };
+    data.admin_keys.retain(|(k, _)| k.name != key.name);
+    data.admin_keys.push((key.clone(), key_hash.to_vec()));
+    Ok(())
+  }
+
+  async fn delete_admin_key(&self, name: &str) -> MResult<bool> {
+    let mut data = self.call("delete_admin_key")?;
+    let before = data.admin_keys.len();
+    data.admin_keys.retain(|(k, _)| k.name != name);
+    Ok(data.admin_keys.len() < before)
+  }
+
+  async fn admin_keys(&self) -> MResult<Vec<AdminKeyRow>> {
+    let data = self.call("admin_keys")?;
+    let mut keys: Vec<AdminKeyRow> = data.admin_keys.iter().map(|(k, _)| k.clone()).collect();
+    keys.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(keys)
+  }
+
+  async fn insert_user(&self, user: &NewUser<'_>, cc_key: Option<(&str, i64)>) -> MResult<Option<i64>> {
+    let mut data = self.call("insert_user")?;
+    if data.users.iter().any(|u| u.login == user.login) { return Err(Box::new(Duplicate{})); };
+    if let Some((cc_key, now)) = cc_key {
+      let before = data.cc_keys.len();
+      data.cc_keys.retain(|k| k.key != cc_key || k.expires_at.is_some_and(|e| e <= now));
+      if data.cc_keys.len() == before { return Ok(None); };
+    };
+    let id = data.users.iter().map(|u| u.id).max().unwrap_or(0) + 1;
+    data.users.push(UserRow {
+      id,
+      login: user.login.to_string(),
+      shared_boards: String::from("[]"),
+      user_creds: user.user_creds.to_string(),
+      apd: user.apd.to_string(),
+    });
+    data.profiles.insert(id, UserProfile { id, display_name: user.login.to_string(), avatar_color: String::from("#808080") });
+    Ok(Some(id))
+  }
+
+  async fn user(&self, id: &i64) -> MResult<UserRow> {
+    let mut data = self.call("user")?;
+    Ok(data.user_mut(id)?.clone())
+  }
+
+  async fn user_by_login(&self, login: &str) -> MResult<Option<UserRow>> {
+    let data = self.call("user_by_login")?;
+    Ok(data.users.iter().find(|u| u.login == login).cloned())
+  }
+
+  async fn set_user_creds(&self, id: &i64, user_creds: &str) -> MResult<()> {
+    let mut data = self.call("set_user_creds")?;
+    if let Ok(user) = data.user_mut(id) { user.user_creds = user_creds.to_string(); };
+    Ok(())
+  }
+
+  async fn swap_user_creds(&self, id: &i64, expected: &str, user_creds: &str) -> MResult<bool> {
+    let mut data = self.call("swap_user_creds")?;
+    match data.user_mut(id) {
+      Ok(user) if user.user_creds == expected => user.user_creds = user_creds.to_string(),
+      _ => return Ok(false),
+    };
+    Ok(true)
+  }
+
+  async fn set_login_and_creds(&self, id: &i64, login: &str, user_creds: &str) -> MResult<bool> {
+    let mut data = self.call("set_login_and_creds")?;
+    if data.users.iter().any(|u| u.login == login && u.id != *id) { return Ok(false); };
+    if let Ok(user) = data.user_mut(id) {
+      user.login = login.to_string();
+      user.user_creds = user_creds.to_string();
+    };
+    Ok(true)
+  }
+
+  async fn set_billing(&self, id: &i64, apd: &str) -> MResult<()> {
+    let mut data = self.call("set_billing")?;
+    if let Ok(user) = data.user_mut(id) { user.apd = apd.to_string(); };
+    Ok(())
+  }
+
+  async fn sign_in_locked_until(&self, login: &str) -> MResult<Option<i64>> {
+    let data = self.call("sign_in_locked_until")?;
+    Ok(data.sign_in_failures.get(login).map(|f| f.locked_until))
+  }
+
+  async fn add_sign_in_failure(&self, login: &str, now: i64, window_start: i64) -> MResult<i64> {
+    let mut data = self.call("add_sign_in_failure")?;
+    let entry = data.sign_in_failures.entry(login.to_string()).or_insert(SignInFailures { failures: 0, first_failure: now, locked_until: 0 });
+    match entry.failures > 0 && entry.first_failure > window_start {
+      true => entry.failures += 1,
+      false => (entry.failures, entry.first_failure) = (1, now),
+    };
+    Ok(entry.failures)
+  }
+
+  async fn lock_sign_in(&self, login: &str, locked_until: i64) -> MResult<()> {
+    let mut data = self.call("lock_sign_in")?;
+    if let Some(entry) = data.sign_in_failures.get_mut(login) {
+      entry.failures = 0;
+      entry.locked_until = locked_until;
+    };
+    Ok(())
+  }
+
+  async fn clear_sign_in_failures(&self, login: &str) -> MResult<()> {
+    self.call("clear_sign_in_failures")?.sign_in_failures.remove(login);
+    Ok(())
+  }
+
+  async fn set_profile(&self, id: &i64, display_name: Option<&str>, avatar_color: Option<&str>) -> MResult<()> {
+    let mut data = self.call("set_profile")?;
+    if let Some(profile) = data.profiles.get_mut(id) {
+      if let Some(display_name) = display_name { profile.display_name = display_name.to_string(); };
+      if let Some(avatar_color) = avatar_color { profile.avatar_color = avatar_color.to_string(); };
+    };
+    Ok(())
+  }
+
+  async fn profiles(&self, ids: &[i64]) -> MResult<Vec<UserProfile>> {
+    let data = self.call("profiles")?;
+    Ok(data.profiles.values().filter(|p| ids.contains(&p.id)).cloned().collect())
+  }
+
+  async fn board(&self, id: &i64) -> MResult<Option<BoardRow>> {
+    let data = self.call("board")?;
+    Ok(data.boards.iter().find(|b| b.id == *id).cloned())
+  }
+
+  async fn insert_board(&self, board: &BoardRow) -> MResult<i64> {
+    let mut data = self.call("insert_board")?;
+    let id = data.boards.iter().map(|b| b.id).max().unwrap_or(0) + 1;
+    let author = data.user_mut(&board.author)?;
+    let mut shared_boards: Vec<i64> = serde_json::from_str(&author.shared_boards)?;
+    shared_boards.push(id);
+    author.shared_boards = serde_json::to_string(&shared_boards)?;
+    data.boards.push(BoardRow { id, revision: 0, ..board.clone() });
+    Ok(id)
+  }
+
+  /// Выражения PostgreSQL `queries` не выполняются.
+  async fn update_board(&self, board: &BoardRow, _cards_edits: Option<&[Edit]>, shared_boards: &[(i64, String)], _queries: Queries<'_>)
+    -> MResult<bool>
+  {
+    let mut data = self.call("update_board")?;
+    let stored = match data.boards.iter_mut().find(|b| b.id == board.id && b.revision == board.revision && b.archived_at == 0) {
+      Some(stored) => stored,
+      None => return Ok(false),
+    };
+    *stored = BoardRow {
+      created_at: stored.created_at,
+      archived_at: stored.archived_at,
+      revision: stored.revision + 1,
+      ..board.clone()
+    };
+    for (user_id, shared_boards) in shared_boards {
+      if let Ok(user) = data.user_mut(user_id) { user.shared_boards = shared_boards.clone(); };
+    };
+    Ok(true)
+  }
+
+  async fn delete_board(&self, id: &i64, shared_boards: &[(i64, String)]) -> MResult<()> {
+    let mut data = self.call("delete_board")?;
+    for (user_id, shared_boards) in shared_boards {
+      if let Ok(user) = data.user_mut(user_id) { user.shared_boards = shared_boards.clone(); };
+    };
+    data.boards.retain(|b| b.id != *id);
+    data.board_prefs.retain(|(_, board_id), _| board_id != id);
+    let prefix = format!("{}_", id);
+    data.id_seqs.retain(|seq, _| *seq != id.to_string() && !seq.starts_with(&prefix));
+    Ok(())
+  }
+
+  async fn list_boards(
+    &self, user_id: &i64, boards: &[i64], archived: bool, sort: BoardSort, cursor: Option<&BoardsCursor>, limit: Option<i64>
+  ) -> MResult<Vec<BoardListRow>> {
+    let data = self.call("list_boards")?;
+    let mut rows: Vec<BoardListRow> = boards.iter().zip(1..).filter_map(|(id, pos)| {
+      let board = data.boards.iter().find(|b| b.id == *id && (b.archived_at != 0) == archived)?;
+      let prefs = data.board_prefs.get(&(*user_id, *id)).cloned().unwrap_or_default();
+      Some(BoardListRow {
+        id: *id,
+        header: board.header.clone(),
+        created_at: board.created_at,
+        updated_at: board.updated_at,
+        pos,
+        favorite: prefs.favorite,
+        muted: prefs.muted,
+        position: prefs.position,
+      })
+    }).collect();
+    match sort {
+      BoardSort::Added => rows.sort_by_key(|r| r.pos),
+      BoardSort::Title => rows.sort_by_key(|r| (title(&r.header), r.id)),
+      BoardSort::Activity => rows.sort_by_key(|r| Reverse((r.updated_at, r.id))),
+      BoardSort::Created => rows.sort_by_key(|r| Reverse((r.created_at, r.id))),
+      BoardSort::Custom => rows.sort_by_key(|r| (r.position.unwrap_or(i64::MAX), r.pos)),
+    };
+    rows.retain(|r| match cursor {
+      None => true,
+      Some(BoardsCursor::Added { pos }) => r.pos > *pos,
+      Some(BoardsCursor::Title { title: t, id }) => (title(&r.header), r.id) > (t.clone(), *id),
+      Some(BoardsCursor::Activity { updated_at, id }) => (r.updated_at, r.id) < (*updated_at, *id),
+      Some(BoardsCursor::Created { created_at, id }) => (r.created_at, r.id) < (*created_at, *id),
+      Some(BoardsCursor::Custom { position, pos }) => (r.position.unwrap_or(i64::MAX), r.pos) > (*position, *pos),
+    });
+    if let Some(limit) = limit { rows.truncate(limit as usize); };
+    Ok(rows)
+  }
+
+  async fn set_board_prefs(&self, user_id: &i64, board_id: &i64, patch: &BoardPrefsPatch) -> MResult<()> {
+    let mut data = self.call("set_board_prefs")?;
+    let prefs = data.board_prefs.entry((*user_id, *board_id)).or_default();
+    if let Some(favorite) = patch.favorite { prefs.favorite = favorite; };
+    if let Some(muted) = patch.muted { prefs.muted = muted; };
+    if let Some(position) = patch.position { prefs.position = position; };
+    Ok(())
+  }
+
+  async fn set_board_archived(&self, id: &i64, archived_at: i64) -> MResult<()> {
+    let mut data = self.call("set_board_archived")?;
+    if let Some(board) = data.boards.iter_mut().find(|b| b.id == *id) { board.archived_at = archived_at; };
+    Ok(())
+  }
+
+  async fn count_boards(&self, author: &i64) -> MResult<u64> {
+    let data = self.call("count_boards")?;
+    Ok(data.boards.iter().filter(|b| b.author == *author).count() as u64)
+  }
+
+  async fn next_id(&self, seq: &str, min: i64) -> MResult<i64> {
+    self.check("next_id")?;
+    self.next_ids(seq, min, 1).await
+  }
+
+  async fn next_ids(&self, seq: &str, min: i64, count: i64) -> MResult<i64> {
+    let mut data = self.call("next_ids")?;
+    let val = data.id_seqs.entry(seq.to_string()).or_insert(min);
+    let first = (*val).max(min);
+    *val = first + count;
+    Ok(first)
+  }
+}
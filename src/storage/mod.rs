@@ -0,0 +1,239 @@
+//! Отвечает за хранилище пользователей, досок и ключей.
+//!
+//! Логика приложения (`core`) обращается к этим данным через `Storage`, не завися от того, где они хранятся. Хранилище работает с данными в том виде, в котором они записаны: JSON-колонки передаются строками, а разбирает и проверяет их `core`.
+//!
+//! Хранилище выбирается в конфигурации (`AppConfig::storage`):
+//!
+//! - PostgreSQL (см. `psql_handler`);
+//! - файл SQLite (см. `sqlite`) - для сервера одного пользователя или небольшой команды, которому не нужен отдельный сервер баз данных.
+//!
+//! Данные остальных функций сервера хранятся только в PostgreSQL: `core` обращается к ним через подключение из `Storage::postgres`, а не через `Storage`. С другими хранилищами:
+//!
+//! - методы этих функций возвращают код 501 (`PostgresRequired`, см. `hyper_router::extractors::postgres`): резервное копирование и восстановление, проверка досок, очередь заданий и журнал действий администраторов; регистрация с подтверждением адреса (`core::signup`), вход через внешних поставщиков и каталог пользователей (`core::identities`); отмена удалений (`core::undo`), история задач, отчёты спринтов, журнал изменений досок (`core::delta`), уборка выполненных задач, представления, отчёты, хранение и экспорт досок; организации, связи с GitHub, журнал безопасности, уведомления и дайджесты;
+//! - записи, которые сопровождают другие действия, не делаются: журнал действий администраторов, журнал безопасности, записи для отмены удалений, история задач и журнал изменений досок (выражения `Queries` в `Storage::update_board`);
+//! - фоновые задания (`core::jobs`) не запускаются, поэтому, например, устаревшие неудачные попытки входа не удаляются.
+
+use async_trait::async_trait;
+use custom_error::custom_error;
+use tokio_postgres::types::ToSql;
+
+use crate::core::BoardsCursor;
+use crate::model::{BoardPrefsPatch, BoardSort, UserProfile};
+use crate::psql_handler::Db;
+use crate::psql_handler::jsonb::Edit;
+
+mod postgres;
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod mock;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+custom_error!{pub PostgresRequired{} = "Функция недоступна с выбранным хранилищем данных."}
+
+/// Выражения PostgreSQL с параметрами.
+pub type Queries<'a> = Vec<(&'a str, Vec<&'a (dyn ToSql + Sync)>)>;
+
+/// Ключ регистрации (см. `core::cc_keys`).
+#[derive(Clone)]
+pub struct CcKeyRow {
+  pub key: String,
+  pub note: Option<String>,
+  pub created_at: i64,
+  pub expires_at: Option<i64>,
+}
+
+/// Ключ администратора без самого ключа (см. `core::admin_keys`).
+#[derive(Clone)]
+pub struct AdminKeyRow {
+  pub name: String,
+  /// Области действия в виде JSON-массива.
+  pub scopes: String,
+  pub expires_at: Option<i64>,
+}
+
+/// Новый пользователь.
+pub struct NewUser<'a> {
+  pub login: &'a str,
+  /// Сведения авторизации (`UserCredentials`) в виде JSON.
+  pub user_creds: &'a str,
+  /// Сведения об оплате (`AccountPlanDetails`) в виде JSON.
+  pub apd: &'a str,
+}
+
+/// Пользователь.
+#[derive(Clone)]
+pub struct UserRow {
+  pub id: i64,
+  pub login: String,
+  /// Идентификаторы досок, к которым у пользователя есть доступ, в виде JSON-массива.
+  pub shared_boards: String,
+  /// Сведения авторизации (`UserCredentials`) в виде JSON.
+  pub user_creds: String,
+  /// Сведения об оплате (`AccountPlanDetails`) в виде JSON.
+  pub apd: String,
+}
+
+/// Доска. Колонки `shared_with`, `header`, `cards`, `background`, `tags`, `settings`, `lanes`, `sprints` и `watchers` хранятся в виде JSON.
+#[derive(Clone)]
+pub struct BoardRow {
+  pub id: i64,
+  pub author: i64,
+  pub shared_with: String,
+  pub header: String,
+  pub cards: String,
+  pub background: String,
+  pub tags: String,
+  pub revision: i64,
+  pub settings: String,
+  pub created_at: i64,
+  pub updated_at: i64,
+  pub lanes: String,
+  pub sprints: String,
+  pub watchers: String,
+  /// Время переноса доски в архив; 0 - доска не в архиве. Не изменяется `Storage::update_board`.
+  pub archived_at: i64,
+}
+
+/// Доска в списке досок пользователя.
+pub struct BoardListRow {
+  pub id: i64,
+  /// Заголовок доски (`BoardHeader`) в виде JSON.
+  pub header: String,
+  pub created_at: i64,
+  pub updated_at: i64,
+  /// Позиция доски в списке досок пользователя, начиная с 1.
+  pub pos: i32,
+  pub favorite: bool,
+  pub muted: bool,
+  pub position: Option<i64>,
+}
+
+/// Хранилище пользователей, досок и ключей.
+///
+/// Методы, читающие одну сущность по идентификатору, возвращают ошибку, если её нет.
+#[async_trait]
+pub trait Storage: Send + Sync {
+  /// Возвращает подключение к PostgreSQL, если хранилище работает с ним, - для функций, данные которых хранятся только в PostgreSQL.
+  fn postgres(&self) -> Option<&Db> { None }
+
+  /// Записывает ключи регистрации.
+  async fn insert_cc_keys(&self, keys: &[CcKeyRow]) -> MResult<()>;
+
+  /// Возвращает ключи регистрации, срок действия которых не истёк к моменту `now`, в порядке выпуска.
+  async fn unused_cc_keys(&self, now: i64) -> MResult<Vec<CcKeyRow>>;
+
+  /// Удаляет ключи регистрации. Возвращает число удалённых ключей.
+  async fn delete_cc_keys(&self, keys: &[String]) -> MResult<usize>;
+
+  /// Возвращает ключ администратора по хэшу ключа.
+  async fn admin_key_by_hash(&self, key_hash: &[u8]) -> MResult<Option<AdminKeyRow>>;
+
+  /// Записывает ключ администратора, заменяя ключ с тем же названием, если он был.
+  async fn put_admin_key(&self, key: &AdminKeyRow, key_hash: &[u8]) -> MResult<()>;
+
+  /// Удаляет ключ администратора. Возвращает `false`, если такого ключа нет.
+  async fn delete_admin_key(&self, name: &str) -> MResult<bool>;
+
+  /// Возвращает все ключи администраторов в порядке названий.
+  async fn admin_keys(&self) -> MResult<Vec<AdminKeyRow>>;
+
+  /// Создаёт пользователя без досок; его отображаемым именем становится логин. Возвращает идентификатор пользователя.
+  ///
+  /// Если передан ключ регистрации вместе с текущим временем, ключ удаляется вместе с созданием пользователя. Если такого действующего ключа нет, пользователь не создаётся, и метод возвращает `None`.
+  async fn insert_user(&self, user: &NewUser<'_>, cc_key: Option<(&str, i64)>) -> MResult<Option<i64>>;
+
+  async fn user(&self, id: &i64) -> MResult<UserRow>;
+
+  async fn user_by_login(&self, login: &str) -> MResult<Option<UserRow>>;
+
+  async fn set_user_creds(&self, id: &i64, user_creds: &str) -> MResult<()>;
+
+  /// Заменяет сведения авторизации пользователя, только если они всё ещё равны `expected`. Возвращает `false`, если их успели изменить.
+  async fn swap_user_creds(&self, id: &i64, expected: &str, user_creds: &str) -> MResult<bool>;
+
+  /// Изменяет логин и сведения авторизации пользователя. Возвращает `false`, если логин занят другим пользователем.
+  async fn set_login_and_creds(&self, id: &i64, login: &str, user_creds: &str) -> MResult<bool>;
+
+  async fn set_billing(&self, id: &i64, apd: &str) -> MResult<()>;
+
+  /// Возвращает время, до которого вход по логину заблокирован, или `None`, если неудачных попыток входа не было.
+  async fn sign_in_locked_until(&self, login: &str) -> MResult<Option<i64>>;
+
+  /// Учитывает неудачную попытку входа по логину в момент `now`. Если первая учтённая попытка была не позже `window_start`, счёт начинается заново. Возвращает число учтённых попыток.
+  async fn add_sign_in_failure(&self, login: &str, now: i64, window_start: i64) -> MResult<i64>;
+
+  /// Блокирует вход по логину до `locked_until` и сбрасывает счёт неудачных попыток.
+  async fn lock_sign_in(&self, login: &str, locked_until: i64) -> MResult<()>;
+
+  async fn clear_sign_in_failures(&self, login: &str) -> MResult<()>;
+
+  /// Изменяет заданные поля публичного профиля пользователя.
+  async fn set_profile(&self, id: &i64, display_name: Option<&str>, avatar_color: Option<&str>) -> MResult<()>;
+
+  /// Возвращает публичные профили пользователей в порядке идентификаторов, пропуская несуществующие.
+  async fn profiles(&self, ids: &[i64]) -> MResult<Vec<UserProfile>>;
+
+  /// Возвращает доску или `None`, если её нет.
+  async fn board(&self, id: &i64) -> MResult<Option<BoardRow>>;
+
+  /// Возвращает доску, если пользователь - её участник, или `None`, если доски нет или пользователь не её участник.
+  ///
+  /// PostgreSQL проверяет участие в запросе, не считывая чужую доску; по умолчанию доска считывается и проверяется её `shared_with`.
+  async fn member_board(&self, id: &i64, user_id: &i64) -> MResult<Option<BoardRow>> {
+    let board = match self.board(id).await? {
+      Some(board) => board,
+      None => return Ok(None),
+    };
+    let shared_with: Vec<i64> = serde_json::from_str(&board.shared_with)?;
+    Ok(shared_with.contains(user_id).then_some(board))
+  }
+
+  /// Блокирует доску до фиксации транзакции текущей задачи (см. `commit`), чтобы параллельные изменения доски выполнялись по очереди, а не терялись.
+  ///
+  /// PostgreSQL блокирует строку доски в транзакции (см. `psql_handler::transaction`). Другие хранилища доску не блокируют: изменение, записанное параллельным запросом, не даёт записать доску из-за несовпадения ревизии (см. `update_board`).
+  async fn lock_board(&self, _id: &i64) -> MResult<()> { Ok(()) }
+
+  /// Фиксирует транзакцию текущей задачи и снимает блокировку досок (см. `lock_board`).
+  async fn commit(&self) -> MResult<()> { Ok(()) }
+
+  /// Создаёт доску и добавляет её в доски автора. Идентификатор и ревизия в `board` не учитываются. Возвращает идентификатор доски.
+  async fn insert_board(&self, board: &BoardRow) -> MResult<i64>;
+
+  /// Записывает содержимое и участников доски и время её изменения, увеличивая ревизию, если ревизия в хранилище равна `board.revision`, а доска не в архиве. Время создания доски и время её переноса в архив не изменяются.
+  ///
+  /// Если состав участников изменился, вместе с доской записываются новые списки досок пользователей: пары из идентификатора пользователя и `shared_boards`.
+  ///
+  /// `cards_edits` - изменения карточек относительно записанной ревизии, если их немного (см. `psql_handler::jsonb`). PostgreSQL применяет их вместо того, чтобы записывать карточки целиком; другие хранилища записывают `board.cards`.
+  ///
+  /// Вместе с доской в той же транзакции выполняются выражения PostgreSQL `queries` - записи истории задач, журналов отмены и изменений, служебные записи последовательностей идентификаторов. Другие хранилища их не выполняют, поэтому без них доска должна оставаться согласованной. Возвращает `false`, если ревизия не совпала или доска в архиве и ничего не записано.
+  async fn update_board(&self, board: &BoardRow, cards_edits: Option<&[Edit]>, shared_boards: &[(i64, String)], queries: Queries<'_>)
+    -> MResult<bool>;
+
+  /// Удаляет доску вместе с настройками досок пользователей и последовательностями идентификаторов доски, записывая участникам доски новые списки досок: пары из идентификатора пользователя и `shared_boards`.
+  async fn delete_board(&self, id: &i64, shared_boards: &[(i64, String)]) -> MResult<()>;
+
+  /// Возвращает доски `boards` пользователя вместе с его настройками досок в порядке `sort`, начиная после `cursor`, - не больше `limit` досок. Если установлен `archived`, возвращаются только доски в архиве, иначе - только доски вне архива.
+  async fn list_boards(
+    &self, user_id: &i64, boards: &[i64], archived: bool, sort: BoardSort, cursor: Option<&BoardsCursor>, limit: Option<i64>
+  ) -> MResult<Vec<BoardListRow>>;
+
+  /// Переносит доску в архив, записывая время переноса `archived_at`, или, если оно равно 0, возвращает доску из архива. Ревизия доски не изменяется.
+  async fn set_board_archived(&self, id: &i64, archived_at: i64) -> MResult<()>;
+
+  /// Изменяет настройки доски, заданные пользователем. Незаданные в патче настройки не изменяются.
+  async fn set_board_prefs(&self, user_id: &i64, board_id: &i64, patch: &BoardPrefsPatch) -> MResult<()>;
+
+  /// Возвращает число досок, автором которых является пользователь.
+  async fn count_boards(&self, author: &i64) -> MResult<u64>;
+
+  /// Выделяет следующий идентификатор из последовательности.
+  ///
+  /// Последовательность хранит идентификатор, который будет выдан следующим. Одновременные запросы никогда не получают один и тот же идентификатор. Выданный идентификатор не меньше `min`: так последовательность, которой ещё нет или которая отстала от данных, не выдаст уже занятый идентификатор.
+  async fn next_id(&self, seq: &str, min: i64) -> MResult<i64>;
+
+  /// Выделяет `count` идущих подряд идентификаторов из последовательности так же, как `next_id`, и возвращает первый из них.
+  async fn next_ids(&self, seq: &str, min: i64, count: i64) -> MResult<i64>;
+}
@@ -0,0 +1,391 @@
+//! Хранилище в PostgreSQL.
+
+use async_trait::async_trait;
+use tokio_postgres::{error::SqlState, row::Row, types::ToSql};
+
+use crate::core::BoardsCursor;
+use crate::model::{BoardPrefsPatch, BoardSort, UserProfile};
+use crate::psql_handler::Db;
+use crate::psql_handler::jsonb::{self, Edit};
+use crate::storage::{AdminKeyRow, BoardListRow, BoardRow, CcKeyRow, NewUser, Queries, Storage, UserRow};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+const USER_COLUMNS: &str = "id, login, shared_boards::text, user_creds::text, apd";
+
+fn user_from_row(row: &Row) -> UserRow {
+  UserRow {
+    id: row.get(0),
+    login: row.get(1),
+    shared_boards: row.get(2),
+    user_creds: row.get(3),
+    apd: row.get(4),
+  }
+}
+
+const BOARD_COLUMNS: &str =
+  "id, author, shared_with::text, header::text, cards::text, background, tags, revision, settings, created_at, updated_at, lanes, sprints, watchers, archived_at";
+
+fn board_from_row(row: &Row) -> BoardRow {
+  BoardRow {
+    id: row.get(0),
+    author: row.get(1),
+    shared_with: row.get(2),
+    header: row.get(3),
+    cards: row.get(4),
+    background: row.get(5),
+    tags: row.get(6),
+    revision: row.get(7),
+    settings: row.get(8),
+    created_at: row.get(9),
+    updated_at: row.get(10),
+    lanes: row.get(11),
+    sprints: row.get(12),
+    watchers: row.get(13),
+    archived_at: row.get(14),
+  }
+}
+
+fn admin_key_from_row(row: &Row) -> AdminKeyRow {
+  AdminKeyRow { name: row.get(0), scopes: row.get(1), expires_at: row.get(2) }
+}
+
+#[async_trait]
+impl Storage for Db {
+  fn postgres(&self) -> Option<&Db> { Some(self) }
+
+  async fn insert_cc_keys(&self, keys: &[CcKeyRow]) -> MResult<()> {
+    let parts = keys.iter()
+      .map(|k| -> (&str, Vec<&(dyn ToSql + Sync)>) {
+        ("insert into cc_keys values ($1, $2, $3, $4);", vec![&k.key, &k.note, &k.created_at, &k.expires_at])
+      })
+      .collect();
+    self.write_mul(parts).await
+  }
+
+  async fn unused_cc_keys(&self, now: i64) -> MResult<Vec<CcKeyRow>> {
+    let rows = self.read_all(
+      "select key, note, created_at, expires_at from cc_keys where expires_at is null or expires_at > $1 order by created_at;",
+      &[&now]
+    ).await?;
+    Ok(rows.iter().map(|row| CcKeyRow { key: row.get(0), note: row.get(1), created_at: row.get(2), expires_at: row.get(3) }).collect())
+  }
+
+  async fn delete_cc_keys(&self, keys: &[String]) -> MResult<usize> {
+    Ok(self.read_all("delete from cc_keys where key = any($1) returning key;", &[&keys]).await?.len())
+  }
+
+  async fn admin_key_by_hash(&self, key_hash: &[u8]) -> MResult<Option<AdminKeyRow>> {
+    let rows = self.read_all("select name, scopes, expires_at from admin_keys where key_hash = $1;", &[&key_hash]).await?;
+    Ok(rows.first().map(admin_key_from_row))
+  }
+
+  async fn put_admin_key(&self, key: &AdminKeyRow, key_hash: &[u8]) -> MResult<()> {
+    self.write(
+      "insert into admin_keys values ($1, $2, $3, $4) on conflict (name) do update set \
+         key_hash = excluded.key_hash, scopes = excluded.scopes, expires_at = excluded.expires_at;",
+      &[&key.name, &key_hash, &key.scopes, &key.expires_at]
+    ).await
+  }
+
+  async fn delete_admin_key(&self, name: &str) -> MResult<bool> {
+    Ok(!self.read_all("delete from admin_keys where name = $1 returning name;", &[&name]).await?.is_empty())
+  }
+
+  async fn admin_keys(&self) -> MResult<Vec<AdminKeyRow>> {
+    let rows = self.read_all("select name, scopes, expires_at from admin_keys order by name;", &[]).await?;
+    Ok(rows.iter().map(admin_key_from_row).collect())
+  }
+
+  async fn insert_user(&self, user: &NewUser<'_>, cc_key: Option<(&str, i64)>) -> MResult<Option<i64>> {
+    let id: i64 = self.read("select nextval(pg_get_serial_sequence('users', 'id'));", &[]).await?.get(0);
+    let insert = "insert into users (id, login, shared_boards, user_creds, apd, display_name) values ($1, $2, '[]', $3::text::jsonb, $4, $2);";
+    let (cc_key, now) = match cc_key {
+      Some(cc_key) => cc_key,
+      None => {
+        self.write(insert, &[&id, &user.login, &user.user_creds, &user.apd]).await?;
+        return Ok(Some(id));
+      },
+    };
+    let created = self.write_mul_if(vec![
+      ("delete from cc_keys where key = $1 and (expires_at is null or expires_at > $2);", vec![&cc_key, &now]),
+      (insert, vec![&id, &user.login, &user.user_creds, &user.apd]),
+    ]).await?;
+    Ok(created.then_some(id))
+  }
+
+  async fn user(&self, id: &i64) -> MResult<UserRow> {
+    Ok(user_from_row(&self.read(&format!("select {} from users where id = $1;", USER_COLUMNS), &[id]).await?))
+  }
+
+  async fn user_by_login(&self, login: &str) -> MResult<Option<UserRow>> {
+    let rows = self.read_all(&format!("select {} from users where login = $1;", USER_COLUMNS), &[&login]).await?;
+    Ok(rows.first().map(user_from_row))
+  }
+
+  async fn set_user_creds(&self, id: &i64, user_creds: &str) -> MResult<()> {
+    self.write("update users set user_creds = $1::text::jsonb where id = $2;", &[&user_creds, id]).await
+  }
+
+  async fn swap_user_creds(&self, id: &i64, expected: &str, user_creds: &str) -> MResult<bool> {
+    let rows = self.read_all(
+      "update users set user_creds = $1::text::jsonb where id = $2 and user_creds = $3::text::jsonb returning id;",
+      &[&user_creds, id, &expected]
+    ).await?;
+    Ok(!rows.is_empty())
+  }
+
+  async fn set_login_and_creds(&self, id: &i64, login: &str, user_creds: &str) -> MResult<bool> {
+    let res = self.write("update users set login = $1, user_creds = $2::text::jsonb where id = $3;", &[&login, &user_creds, id]).await;
+    match res {
+      Err(e) if e.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code()) == Some(&SqlState::UNIQUE_VIOLATION) => Ok(false),
+      res => res.map(|_| true),
+    }
+  }
+
+  async fn set_billing(&self, id: &i64, apd: &str) -> MResult<()> {
+    self.write("update users set apd = $1 where id = $2;", &[&apd, id]).await
+  }
+
+  async fn sign_in_locked_until(&self, login: &str) -> MResult<Option<i64>> {
+    let rows = self.read_all("select locked_until from sign_in_failures where login = $1;", &[&login]).await?;
+    Ok(rows.first().map(|row| row.get(0)))
+  }
+
+  async fn add_sign_in_failure(&self, login: &str, now: i64, window_start: i64) -> MResult<i64> {
+    let row = self.read(
+      "insert into sign_in_failures values ($1, 1, $2, 0) on conflict (login) do update set \
+         failures = case when sign_in_failures.first_failure > $3 then sign_in_failures.failures + 1 else 1 end, \
+         first_failure = case when sign_in_failures.first_failure > $3 then sign_in_failures.first_failure else $2 end \
+       returning failures;",
+      &[&login, &now, &window_start]
+    ).await?;
+    Ok(row.get(0))
+  }
+
+  async fn lock_sign_in(&self, login: &str, locked_until: i64) -> MResult<()> {
+    self.write("update sign_in_failures set failures = 0, locked_until = $2 where login = $1;", &[&login, &locked_until]).await
+  }
+
+  async fn clear_sign_in_failures(&self, login: &str) -> MResult<()> {
+    self.write("delete from sign_in_failures where login = $1;", &[&login]).await
+  }
+
+  async fn set_profile(&self, id: &i64, display_name: Option<&str>, avatar_color: Option<&str>) -> MResult<()> {
+    self.write(
+      "update users set display_name = coalesce($1, display_name), avatar_color = coalesce($2, avatar_color) where id = $3;",
+      &[&display_name, &avatar_color, id]
+    ).await
+  }
+
+  async fn profiles(&self, ids: &[i64]) -> MResult<Vec<UserProfile>> {
+    let rows = self.read_all(
+      "select id, display_name, avatar_color from users where id = any($1) order by id;",
+      &[&ids]
+    ).await?;
+    Ok(rows.iter().map(|row| UserProfile {
+      id: row.get(0),
+      display_name: row.get(1),
+      avatar_color: row.get(2),
+    }).collect())
+  }
+
+  async fn board(&self, id: &i64) -> MResult<Option<BoardRow>> {
+    let rows = self.read_all(&format!("select {} from boards where id = $1;", BOARD_COLUMNS), &[id]).await?;
+    Ok(rows.first().map(board_from_row))
+  }
+
+  /// Участие проверяется оператором `@>` по индексу `shared_with`.
+  async fn member_board(&self, id: &i64, user_id: &i64) -> MResult<Option<BoardRow>> {
+    let rows = self.read_all(
+      &format!("select {} from boards where id = $1 and shared_with @> to_jsonb($2::bigint);", BOARD_COLUMNS), &[id, user_id]
+    ).await?;
+    Ok(rows.first().map(board_from_row))
+  }
+
+  async fn lock_board(&self, id: &i64) -> MResult<()> {
+    if self.begin_transaction().await? {
+      self.read_all("select id from boards where id = $1 for update;", &[id]).await?;
+    };
+    Ok(())
+  }
+
+  async fn commit(&self) -> MResult<()> {
+    self.commit_transaction().await
+  }
+
+  async fn insert_board(&self, board: &BoardRow) -> MResult<i64> {
+    let data = self.read_mul(vec![
+      ("select nextval(pg_get_serial_sequence('boards', 'id'));", vec![]),
+      ("select shared_boards::text from users where id = $1;", vec![&board.author])
+    ]).await?;
+    let id: i64 = data[0].get(0);
+    let mut shared_boards = serde_json::from_str::<Vec<i64>>(data[1].get(0))?;
+    shared_boards.push(id);
+    let shared_boards = serde_json::to_string(&shared_boards)?;
+    self.write_mul(vec![
+      (
+        "insert into boards (id, author, shared_with, header, cards, background, tags, settings, created_at, updated_at, lanes, sprints, watchers) \
+           values ($1, $2, $3::text::jsonb, $4::text::jsonb, $5::text::jsonb, $6, $7, $8, $9, $10, $11, $12, $13);",
+        vec![
+          &id, &board.author, &board.shared_with, &board.header, &board.cards, &board.background, &board.tags, &board.settings,
+          &board.created_at, &board.updated_at, &board.lanes, &board.sprints, &board.watchers
+        ]
+      ),
+      ("update users set shared_boards = $1::text::jsonb where id = $2;", vec![&shared_boards, &board.author])
+    ]).await?;
+    Ok(id)
+  }
+
+  /// Если переданы изменения карточек, карточки изменяются операторами `jsonb` по путям к изменённым значениям, а не записываются целиком.
+  async fn update_board(&self, board: &BoardRow, cards_edits: Option<&[Edit]>, shared_boards: &[(i64, String)], queries: Queries<'_>)
+    -> MResult<bool>
+  {
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![
+      &board.header, &board.background, &board.tags, &board.settings, &board.id, &board.revision, &board.updated_at,
+      &board.lanes, &board.sprints, &board.author, &board.shared_with, &board.watchers
+    ];
+    let cards = match cards_edits {
+      Some(edits) => jsonb::expression("cards", edits, &mut params),
+      None => {
+        params.push(&board.cards);
+        format!("${}::text::jsonb", params.len())
+      },
+    };
+    let statement = format!(
+      "update boards set header = $1::text::jsonb, cards = {}, background = $2, tags = $3, settings = $4, revision = revision + 1, \
+         updated_at = $7, lanes = $8, sprints = $9, author = $10, shared_with = $11::text::jsonb, watchers = $12 where id = $5 and revision = $6 and archived_at = 0;",
+      cards
+    );
+    let mut board_queries: Queries = vec![(&statement, params)];
+    board_queries.extend(shared_boards.iter().map(|(user_id, shared_boards)| -> (&str, Vec<&(dyn ToSql + Sync)>) {
+      ("update users set shared_boards = $1::text::jsonb where id = $2;", vec![shared_boards, user_id])
+    }));
+    board_queries.extend(queries);
+    self.write_mul_if(board_queries).await
+  }
+
+  /// Вместе с доской удаляются и её записи в таблицах, которые хранятся только в PostgreSQL.
+  async fn delete_board(&self, id: &i64, shared_boards: &[(i64, String)]) -> MResult<()> {
+    let mut queries: Queries = shared_boards.iter()
+      .map(|(user_id, shared_boards)| -> (&str, Vec<&(dyn ToSql + Sync)>) {
+        ("update users set shared_boards = $1::text::jsonb where id = $2;", vec![shared_boards, user_id])
+      })
+      .collect();
+    queries.push(("delete from boards where id = $1;", vec![id]));
+    queries.push(("delete from user_board_prefs where board_id = $1;", vec![id]));
+    queries.push(("delete from task_history where board_id = $1;", vec![id]));
+    queries.push(("delete from undo_log where board_id = $1;", vec![id]));
+    queries.push(("delete from notifications where board_id = $1;", vec![id]));
+    queries.push(("delete from board_views where board_id = $1;", vec![id]));
+    queries.push(("delete from github_links where board_id = $1;", vec![id]));
+    queries.push(("delete from board_reports where board_id = $1;", vec![id]));
+    queries.push(("delete from org_boards where board_id = $1;", vec![id]));
+    queries.push(("delete from org_board_guests where board_id = $1;", vec![id]));
+    queries.push(("delete from board_retention where board_id = $1;", vec![id]));
+    queries.push(("delete from board_deltas where board_id = $1;", vec![id]));
+    queries.push(("delete from recycled_tasks where board_id = $1;", vec![id]));
+    let id_as_str = id.to_string();
+    queries.push(("delete from id_seqs where id = $1::varchar or id like $1::varchar || '\\_%';", vec![&id_as_str]));
+    self.write_mul(queries).await
+  }
+
+  async fn list_boards(
+    &self, user_id: &i64, boards: &[i64], archived: bool, sort: BoardSort, cursor: Option<&BoardsCursor>, limit: Option<i64>
+  ) -> MResult<Vec<BoardListRow>> {
+    // Доски без заданной пользователем позиции идут в конце.
+    let select = format!(
+      "select b.id, b.header::text, b.updated_at, array_position($1, b.id) pos, \
+         coalesce(p.favorite, false), coalesce(p.muted, false), p.position, \
+         coalesce(p.position, 9223372036854775807) custom, b.created_at \
+       from boards b left join user_board_prefs p on p.board_id = b.id and p.user_id = $3 \
+       where b.id = any($1) and b.archived_at {} 0",
+      if archived { "<>" } else { "=" }
+    );
+    let order = match sort {
+      BoardSort::Added => "order by pos",
+      BoardSort::Title => "order by b.header->>'title', b.id",
+      BoardSort::Activity => "order by b.updated_at desc, b.id desc",
+      BoardSort::Created => "order by b.created_at desc, b.id desc",
+      BoardSort::Custom => "order by custom, pos",
+    };
+    let rows = match cursor {
+      None => self.read_all(&format!("{} {} limit $2;", select, order), &[&boards, &limit, user_id]).await?,
+      Some(BoardsCursor::Added { pos }) => self.read_all(
+        &format!("{} and array_position($1, b.id) > $4 {} limit $2;", select, order),
+        &[&boards, &limit, user_id, pos]
+      ).await?,
+      Some(BoardsCursor::Title { title, id: after }) => self.read_all(
+        &format!("{} and (b.header->>'title', b.id) > ($4, $5) {} limit $2;", select, order),
+        &[&boards, &limit, user_id, title, after]
+      ).await?,
+      Some(BoardsCursor::Activity { updated_at, id: after }) => self.read_all(
+        &format!("{} and (b.updated_at, b.id) < ($4, $5) {} limit $2;", select, order),
+        &[&boards, &limit, user_id, updated_at, after]
+      ).await?,
+      Some(BoardsCursor::Created { created_at, id: after }) => self.read_all(
+        &format!("{} and (b.created_at, b.id) < ($4, $5) {} limit $2;", select, order),
+        &[&boards, &limit, user_id, created_at, after]
+      ).await?,
+      Some(BoardsCursor::Custom { position, pos }) => self.read_all(
+        &format!(
+          "{} and (coalesce(p.position, 9223372036854775807), array_position($1, b.id)) > ($4, $5) {} limit $2;",
+          select, order
+        ),
+        &[&boards, &limit, user_id, position, pos]
+      ).await?,
+    };
+    Ok(rows.iter().map(|row| BoardListRow {
+      id: row.get(0),
+      header: row.get(1),
+      created_at: row.get(8),
+      updated_at: row.get(2),
+      pos: row.get(3),
+      favorite: row.get(4),
+      muted: row.get(5),
+      position: row.get(6),
+    }).collect())
+  }
+
+  async fn set_board_prefs(&self, user_id: &i64, board_id: &i64, patch: &BoardPrefsPatch) -> MResult<()> {
+    let position = patch.position.flatten();
+    let position_patched = patch.position.is_some();
+    self.write(
+      "insert into user_board_prefs values ($1, $2, coalesce($3, false), coalesce($4, false), $5) \
+         on conflict (user_id, board_id) do update set \
+           favorite = coalesce($3, user_board_prefs.favorite), \
+           muted = coalesce($4, user_board_prefs.muted), \
+           position = case when $6 then $5 else user_board_prefs.position end;",
+      &[user_id, board_id, &patch.favorite, &patch.muted, &position, &position_patched]
+    ).await
+  }
+
+  async fn set_board_archived(&self, id: &i64, archived_at: i64) -> MResult<()> {
+    self.write("update boards set archived_at = $2 where id = $1;", &[id, &archived_at]).await
+  }
+
+  async fn count_boards(&self, author: &i64) -> MResult<u64> {
+    let count: i64 = self.read("select count(*) from boards where author = $1;", &[author]).await?.get(0);
+    Ok(count as u64)
+  }
+
+  /// Последовательности хранятся в таблице `id_seqs`; идентификатор выделяется одним выражением.
+  async fn next_id(&self, seq: &str, min: i64) -> MResult<i64> {
+    let row = self.read(
+      "insert into id_seqs values ($1, $2::bigint + 1) on conflict (id) do update set val = greatest(id_seqs.val, $2::bigint) + 1 \
+         returning val - 1;",
+      &[&seq, &min]
+    ).await?;
+    Ok(row.get(0))
+  }
+
+  async fn next_ids(&self, seq: &str, min: i64, count: i64) -> MResult<i64> {
+    let row = self.read(
+      "insert into id_seqs values ($1, $2::bigint + $3::bigint) \
+         on conflict (id) do update set val = greatest(id_seqs.val, $2::bigint) + $3::bigint \
+         returning val - $3::bigint;",
+      &[&seq, &min, &count]
+    ).await?;
+    Ok(row.get(0))
+  }
+}
@@ -0,0 +1,396 @@
+//! Хранилище в файле SQLite.
+//!
+//! Сервер работает с файлом через одно соединение, поэтому запросы к хранилищу выполняются по очереди. Запросы выполняются синхронно в потоке обработчика (см. `tokio::task::block_in_place`), поэтому хранилище работает только в многопоточной среде исполнения.
+//!
+//! Схема повторяет таблицы PostgreSQL, в которых хранятся данные `Storage` (см. `core::db_setup`), и создаётся при открытии файла.
+
+use async_trait::async_trait;
+use rusqlite::{params, params_from_iter, Connection, ErrorCode, OptionalExtension, Params, Row, types::Value};
+use std::sync::{Mutex, PoisonError};
+
+use crate::core::BoardsCursor;
+use crate::model::{BoardPrefsPatch, BoardSort, UserProfile};
+use crate::psql_handler::jsonb::Edit;
+use crate::storage::{AdminKeyRow, BoardListRow, BoardRow, CcKeyRow, NewUser, Queries, Storage, UserRow};
+
+type MResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+const SCHEMA: &str = "
+  create table if not exists admin_keys (name text unique, key_hash blob unique, scopes text, expires_at integer);
+  create table if not exists cc_keys (key text unique, note text, created_at integer, expires_at integer);
+  create table if not exists users (id integer primary key autoincrement, login text unique, shared_boards text, user_creds text, apd text, display_name text, avatar_color text default '#808080');
+  create table if not exists boards (id integer primary key autoincrement, author integer, shared_with text, header text, cards text, background text, tags text default '[]', lanes text default '[]', revision integer default 0, settings text default '{}', updated_at integer default 0, created_at integer default 0, sprints text default '[]', watchers text default '[]', archived_at integer default 0);
+  create table if not exists id_seqs (id text unique, val integer);
+  create table if not exists user_board_prefs (user_id integer, board_id integer, favorite integer default 0, muted integer default 0, position integer, unique (user_id, board_id));
+  create table if not exists sign_in_failures (login text unique, failures integer, first_failure integer, locked_until integer);
+";
+
+const USER_COLUMNS: &str = "id, login, shared_boards, user_creds, apd";
+
+fn user_from_row(row: &Row) -> rusqlite::Result<UserRow> {
+  Ok(UserRow {
+    id: row.get(0)?,
+    login: row.get(1)?,
+    shared_boards: row.get(2)?,
+    user_creds: row.get(3)?,
+    apd: row.get(4)?,
+  })
+}
+
+const BOARD_COLUMNS: &str =
+  "id, author, shared_with, header, cards, background, tags, revision, settings, created_at, updated_at, lanes, sprints, watchers, archived_at";
+
+fn board_from_row(row: &Row) -> rusqlite::Result<BoardRow> {
+  Ok(BoardRow {
+    id: row.get(0)?,
+    author: row.get(1)?,
+    shared_with: row.get(2)?,
+    header: row.get(3)?,
+    cards: row.get(4)?,
+    background: row.get(5)?,
+    tags: row.get(6)?,
+    revision: row.get(7)?,
+    settings: row.get(8)?,
+    created_at: row.get(9)?,
+    updated_at: row.get(10)?,
+    lanes: row.get(11)?,
+    sprints: row.get(12)?,
+    watchers: row.get(13)?,
+    archived_at: row.get(14)?,
+  })
+}
+
+fn admin_key_from_row(row: &Row) -> rusqlite::Result<AdminKeyRow> {
+  Ok(AdminKeyRow { name: row.get(0)?, scopes: row.get(1)?, expires_at: row.get(2)? })
+}
+
+/// Считывает все строки результата запроса.
+fn all<T, P: Params>(conn: &Connection, sql: &str, params: P, f: impl FnMut(&Row) -> rusqlite::Result<T>) -> rusqlite::Result<Vec<T>> {
+  let mut stmt = conn.prepare(sql)?;
+  let rows = stmt.query_map(params, f)?.collect();
+  rows
+}
+
+/// Хранилище в файле SQLite.
+pub struct SqliteStorage {
+  conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+  /// Открывает файл базы данных, создавая его и недостающие таблицы.
+  pub fn open(path: &str) -> MResult<SqliteStorage> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    // В файлах, созданных до появления наблюдателей доски, колонки `watchers` нет.
+    if !conn.prepare("select 1 from pragma_table_info('boards') where name = 'watchers';")?.exists([])? {
+      conn.execute("alter table boards add column watchers text default '[]';", [])?;
+    };
+    // В файлах, созданных до появления архива досок, колонки `archived_at` нет.
+    if !conn.prepare("select 1 from pragma_table_info('boards') where name = 'archived_at';")?.exists([])? {
+      conn.execute("alter table boards add column archived_at integer default 0;", [])?;
+    };
+    Ok(SqliteStorage { conn: Mutex::new(conn) })
+  }
+
+  /// Выполняет запросы к базе данных, дождавшись своей очереди.
+  fn with<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> MResult<T> {
+    tokio::task::block_in_place(|| {
+      let mut conn = self.conn.lock().unwrap_or_else(PoisonError::into_inner);
+      f(&mut conn)
+    }).map_err(Into::into)
+  }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+  async fn insert_cc_keys(&self, keys: &[CcKeyRow]) -> MResult<()> {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      for k in keys {
+        tx.execute("insert into cc_keys values (?1, ?2, ?3, ?4);", params![k.key, k.note, k.created_at, k.expires_at])?;
+      };
+      tx.commit()
+    })
+  }
+
+  async fn unused_cc_keys(&self, now: i64) -> MResult<Vec<CcKeyRow>> {
+    self.with(|conn| all(
+      conn,
+      "select key, note, created_at, expires_at from cc_keys where expires_at is null or expires_at > ?1 order by created_at;",
+      [now],
+      |row| Ok(CcKeyRow { key: row.get(0)?, note: row.get(1)?, created_at: row.get(2)?, expires_at: row.get(3)? })
+    ))
+  }
+
+  async fn delete_cc_keys(&self, keys: &[String]) -> MResult<usize> {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      let mut deleted = 0;
+      for key in keys {
+        deleted += tx.execute("delete from cc_keys where key = ?1;", [key])?;
+      };
+      tx.commit()?;
+      Ok(deleted)
+    })
+  }
+
+  async fn admin_key_by_hash(&self, key_hash: &[u8]) -> MResult<Option<AdminKeyRow>> {
+    self.with(|conn| conn.query_row(
+      "select name, scopes, expires_at from admin_keys where key_hash = ?1;", [key_hash], admin_key_from_row
+    ).optional())
+  }
+
+  async fn put_admin_key(&self, key: &AdminKeyRow, key_hash: &[u8]) -> MResult<()> {
+    self.with(|conn| conn.execute(
+      "insert into admin_keys values (?1, ?2, ?3, ?4) on conflict (name) do update set \
+         key_hash = excluded.key_hash, scopes = excluded.scopes, expires_at = excluded.expires_at;",
+      params![key.name, key_hash, key.scopes, key.expires_at]
+    ).map(|_| ()))
+  }
+
+  async fn delete_admin_key(&self, name: &str) -> MResult<bool> {
+    self.with(|conn| Ok(conn.execute("delete from admin_keys where name = ?1;", [name])? > 0))
+  }
+
+  async fn admin_keys(&self) -> MResult<Vec<AdminKeyRow>> {
+    self.with(|conn| all(conn, "select name, scopes, expires_at from admin_keys order by name;", [], admin_key_from_row))
+  }
+
+  async fn insert_user(&self, user: &NewUser<'_>, cc_key: Option<(&str, i64)>) -> MResult<Option<i64>> {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      if let Some((cc_key, now)) = cc_key {
+        let deleted = tx.execute(
+          "delete from cc_keys where key = ?1 and (expires_at is null or expires_at > ?2);", params![cc_key, now]
+        )?;
+        if deleted == 0 { return Ok(None); };
+      };
+      tx.execute(
+        "insert into users (login, shared_boards, user_creds, apd, display_name) values (?1, '[]', ?2, ?3, ?1);",
+        params![user.login, user.user_creds, user.apd]
+      )?;
+      let id = tx.last_insert_rowid();
+      tx.commit()?;
+      Ok(Some(id))
+    })
+  }
+
+  async fn user(&self, id: &i64) -> MResult<UserRow> {
+    self.with(|conn| conn.query_row(&format!("select {} from users where id = ?1;", USER_COLUMNS), [id], user_from_row))
+  }
+
+  async fn user_by_login(&self, login: &str) -> MResult<Option<UserRow>> {
+    self.with(|conn| conn.query_row(&format!("select {} from users where login = ?1;", USER_COLUMNS), [login], user_from_row).optional())
+  }
+
+  async fn set_user_creds(&self, id: &i64, user_creds: &str) -> MResult<()> {
+    self.with(|conn| conn.execute("update users set user_creds = ?1 where id = ?2;", params![user_creds, id]).map(|_| ()))
+  }
+
+  async fn swap_user_creds(&self, id: &i64, expected: &str, user_creds: &str) -> MResult<bool> {
+    self.with(|conn| conn.execute("update users set user_creds = ?1 where id = ?2 and user_creds = ?3;", params![user_creds, id, expected]).map(|n| n > 0))
+  }
+
+  async fn set_login_and_creds(&self, id: &i64, login: &str, user_creds: &str) -> MResult<bool> {
+    self.with(|conn| match conn.execute("update users set login = ?1, user_creds = ?2 where id = ?3;", params![login, user_creds, id]) {
+      Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => Ok(false),
+      res => res.map(|_| true),
+    })
+  }
+
+  async fn set_billing(&self, id: &i64, apd: &str) -> MResult<()> {
+    self.with(|conn| conn.execute("update users set apd = ?1 where id = ?2;", params![apd, id]).map(|_| ()))
+  }
+
+  async fn sign_in_locked_until(&self, login: &str) -> MResult<Option<i64>> {
+    self.with(|conn| conn.query_row("select locked_until from sign_in_failures where login = ?1;", [login], |row| row.get(0)).optional())
+  }
+
+  async fn add_sign_in_failure(&self, login: &str, now: i64, window_start: i64) -> MResult<i64> {
+    self.with(|conn| conn.query_row(
+      "insert into sign_in_failures values (?1, 1, ?2, 0) on conflict (login) do update set \
+         failures = case when sign_in_failures.first_failure > ?3 then sign_in_failures.failures + 1 else 1 end, \
+         first_failure = case when sign_in_failures.first_failure > ?3 then sign_in_failures.first_failure else ?2 end \
+       returning failures;",
+      params![login, now, window_start],
+      |row| row.get(0)
+    ))
+  }
+
+  async fn lock_sign_in(&self, login: &str, locked_until: i64) -> MResult<()> {
+    self.with(|conn| conn.execute(
+      "update sign_in_failures set failures = 0, locked_until = ?2 where login = ?1;", params![login, locked_until]
+    ).map(|_| ()))
+  }
+
+  async fn clear_sign_in_failures(&self, login: &str) -> MResult<()> {
+    self.with(|conn| conn.execute("delete from sign_in_failures where login = ?1;", [login]).map(|_| ()))
+  }
+
+  async fn set_profile(&self, id: &i64, display_name: Option<&str>, avatar_color: Option<&str>) -> MResult<()> {
+    self.with(|conn| conn.execute(
+      "update users set display_name = coalesce(?1, display_name), avatar_color = coalesce(?2, avatar_color) where id = ?3;",
+      params![display_name, avatar_color, id]
+    ).map(|_| ()))
+  }
+
+  async fn profiles(&self, ids: &[i64]) -> MResult<Vec<UserProfile>> {
+    let ids = serde_json::to_string(ids)?;
+    self.with(|conn| all(
+      conn,
+      "select id, display_name, avatar_color from users where id in (select value from json_each(?1)) order by id;",
+      [ids],
+      |row| Ok(UserProfile { id: row.get(0)?, display_name: row.get(1)?, avatar_color: row.get(2)? })
+    ))
+  }
+
+  async fn board(&self, id: &i64) -> MResult<Option<BoardRow>> {
+    self.with(|conn| conn.query_row(&format!("select {} from boards where id = ?1;", BOARD_COLUMNS), [id], board_from_row).optional())
+  }
+
+  async fn insert_board(&self, board: &BoardRow) -> MResult<i64> {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      tx.execute(
+        "insert into boards (author, shared_with, header, cards, background, tags, settings, created_at, updated_at, lanes, sprints, watchers) \
+           values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);",
+        params![
+          board.author, board.shared_with, board.header, board.cards, board.background, board.tags, board.settings,
+          board.created_at, board.updated_at, board.lanes, board.sprints, board.watchers
+        ]
+      )?;
+      let id = tx.last_insert_rowid();
+      tx.execute(
+        "update users set shared_boards = json_insert(shared_boards, '$[#]', ?1) where id = ?2;", params![id, board.author]
+      )?;
+      tx.commit()?;
+      Ok(id)
+    })
+  }
+
+  /// Выражения PostgreSQL `queries` не выполняются.
+  async fn update_board(&self, board: &BoardRow, _cards_edits: Option<&[Edit]>, shared_boards: &[(i64, String)], _queries: Queries<'_>)
+    -> MResult<bool>
+  {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      let updated = tx.execute(
+        "update boards set header = ?1, cards = ?2, background = ?3, tags = ?4, settings = ?5, revision = revision + 1, \
+           updated_at = ?8, lanes = ?9, sprints = ?10, author = ?11, shared_with = ?12, watchers = ?13 where id = ?6 and revision = ?7 and archived_at = 0;",
+        params![
+          board.header, board.cards, board.background, board.tags, board.settings, board.id, board.revision, board.updated_at,
+          board.lanes, board.sprints, board.author, board.shared_with, board.watchers
+        ]
+      )?;
+      if updated == 0 { return Ok(false); };
+      for (user_id, shared_boards) in shared_boards {
+        tx.execute("update users set shared_boards = ?1 where id = ?2;", params![shared_boards, user_id])?;
+      };
+      tx.commit()?;
+      Ok(true)
+    })
+  }
+
+  async fn delete_board(&self, id: &i64, shared_boards: &[(i64, String)]) -> MResult<()> {
+    self.with(|conn| {
+      let tx = conn.transaction()?;
+      for (user_id, shared_boards) in shared_boards {
+        tx.execute("update users set shared_boards = ?1 where id = ?2;", params![shared_boards, user_id])?;
+      };
+      tx.execute("delete from boards where id = ?1;", [id])?;
+      tx.execute("delete from user_board_prefs where board_id = ?1;", [id])?;
+      tx.execute("delete from id_seqs where id = ?1 or id like ?1 || '\\_%' escape '\\';", [id.to_string()])?;
+      tx.commit()
+    })
+  }
+
+  async fn list_boards(
+    &self, user_id: &i64, boards: &[i64], archived: bool, sort: BoardSort, cursor: Option<&BoardsCursor>, limit: Option<i64>
+  ) -> MResult<Vec<BoardListRow>> {
+    // Позиции досок в списке пользователя берутся из порядка идентификаторов в JSON-массиве.
+    let select = format!(
+      "with ids (id, pos) as (select value, key + 1 from json_each(?1)) \
+       select b.id, b.header, b.updated_at, ids.pos, \
+         coalesce(p.favorite, 0), coalesce(p.muted, 0), p.position, \
+         coalesce(p.position, 9223372036854775807) custom, b.created_at \
+       from boards b join ids on ids.id = b.id and b.archived_at {} 0 \
+         left join user_board_prefs p on p.board_id = b.id and p.user_id = ?3",
+      if archived { "<>" } else { "=" }
+    );
+    let order = match sort {
+      BoardSort::Added => "order by ids.pos",
+      BoardSort::Title => "order by json_extract(b.header, '$.title'), b.id",
+      BoardSort::Activity => "order by b.updated_at desc, b.id desc",
+      BoardSort::Created => "order by b.created_at desc, b.id desc",
+      BoardSort::Custom => "order by custom, ids.pos",
+    };
+    let (filter, after): (&str, Vec<Value>) = match cursor {
+      None => ("", vec![]),
+      Some(BoardsCursor::Added { pos }) => ("where ids.pos > ?4", vec![Value::Integer(*pos as i64)]),
+      Some(BoardsCursor::Title { title, id }) => (
+        "where (json_extract(b.header, '$.title'), b.id) > (?4, ?5)", vec![Value::Text(title.clone()), Value::Integer(*id)]
+      ),
+      Some(BoardsCursor::Activity { updated_at, id }) => (
+        "where (b.updated_at, b.id) < (?4, ?5)", vec![Value::Integer(*updated_at), Value::Integer(*id)]
+      ),
+      Some(BoardsCursor::Created { created_at, id }) => (
+        "where (b.created_at, b.id) < (?4, ?5)", vec![Value::Integer(*created_at), Value::Integer(*id)]
+      ),
+      Some(BoardsCursor::Custom { position, pos }) => (
+        "where (coalesce(p.position, 9223372036854775807), ids.pos) > (?4, ?5)",
+        vec![Value::Integer(*position), Value::Integer(*pos as i64)]
+      ),
+    };
+    let mut values = vec![Value::Text(serde_json::to_string(boards)?), limit.map_or(Value::Null, Value::Integer), Value::Integer(*user_id)];
+    values.extend(after);
+    self.with(|conn| all(
+      conn,
+      &format!("{} {} {} limit coalesce(?2, -1);", select, filter, order),
+      params_from_iter(values.iter()),
+      |row| Ok(BoardListRow {
+        id: row.get(0)?,
+        header: row.get(1)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(2)?,
+        pos: row.get(3)?,
+        favorite: row.get(4)?,
+        muted: row.get(5)?,
+        position: row.get(6)?,
+      })
+    ))
+  }
+
+  async fn set_board_prefs(&self, user_id: &i64, board_id: &i64, patch: &BoardPrefsPatch) -> MResult<()> {
+    let position = patch.position.flatten();
+    let position_patched = patch.position.is_some();
+    self.with(|conn| conn.execute(
+      "insert into user_board_prefs values (?1, ?2, coalesce(?3, 0), coalesce(?4, 0), ?5) \
+         on conflict (user_id, board_id) do update set \
+           favorite = coalesce(?3, user_board_prefs.favorite), \
+           muted = coalesce(?4, user_board_prefs.muted), \
+           position = case when ?6 then ?5 else user_board_prefs.position end;",
+      params![user_id, board_id, patch.favorite, patch.muted, position, position_patched]
+    ).map(|_| ()))
+  }
+
+  async fn set_board_archived(&self, id: &i64, archived_at: i64) -> MResult<()> {
+    self.with(|conn| conn.execute("update boards set archived_at = ?2 where id = ?1;", params![id, archived_at]).map(|_| ()))
+  }
+
+  async fn count_boards(&self, author: &i64) -> MResult<u64> {
+    let count: i64 = self.with(|conn| conn.query_row("select count(*) from boards where author = ?1;", [author], |row| row.get(0)))?;
+    Ok(count as u64)
+  }
+
+  async fn next_id(&self, seq: &str, min: i64) -> MResult<i64> {
+    self.next_ids(seq, min, 1).await
+  }
+
+  async fn next_ids(&self, seq: &str, min: i64, count: i64) -> MResult<i64> {
+    self.with(|conn| conn.query_row(
+      "insert into id_seqs values (?1, ?2 + ?3) on conflict (id) do update set val = max(id_seqs.val, ?2) + ?3 returning val - ?3;",
+      params![seq, min, count],
+      |row| row.get(0)
+    ))
+  }
+}
@@ -0,0 +1,88 @@
+//! Отвечает за работу сервера под управлением systemd.
+//!
+//! Если systemd передал серверу слушающий сокет (активация сокетом, см. `sd_listen_fds(3)`), сервер принимает соединения на нём, а не на `hyper_addr`. Сокет принадлежит systemd и остаётся открытым, пока служба перезапускается, поэтому соединения, пришедшие в это время, ждут нового процесса, а не получают отказ. О готовности принимать соединения и о начале выключения сервер сообщает в `NOTIFY_SOCKET` (см. `sd_notify(3)`): служба с `Type=notify` считается запущенной, только когда сервер действительно готов. Вне systemd переменных окружения нет, и функции модуля ничего не делают.
+
+use std::{env, fs, io, net::TcpListener, process};
+
+/// Первый дескриптор, который systemd передаёт при активации сокетом.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Возвращает слушающий сокет, переданный systemd, если сервер запущен активацией сокетом.
+///
+/// Используется только первый из переданных сокетов. Переменные `LISTEN_*`, предназначенные другому процессу, не учитываются.
+#[cfg(unix)]
+pub fn listener() -> io::Result<Option<TcpListener>> {
+  use std::os::fd::FromRawFd;
+
+  if env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) != Some(process::id()) { return Ok(None); };
+  let fds: i32 = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse().ok()).unwrap_or(0);
+  if fds < 1 { return Ok(None); };
+  // Дескриптор открыт systemd специально для этого процесса, и больше им никто не владеет.
+  let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+  listener.set_nonblocking(true)?;
+  listener.local_addr()?;
+  Ok(Some(listener))
+}
+
+/// Возвращает слушающий сокет, переданный systemd. Вне Unix активация сокетом не поддерживается.
+#[cfg(not(unix))]
+pub fn listener() -> io::Result<Option<TcpListener>> {
+  Ok(None)
+}
+
+/// Сообщает systemd о состоянии сервера, например `READY=1` или `STOPPING=1`.
+///
+/// Если сервер запущен не systemd, ничего не делает. Ошибку отправки выводит в журнал: работе сервера она не мешает.
+pub fn notify(state: &str) {
+  let socket = match env::var("NOTIFY_SOCKET") {
+    Ok(socket) if !socket.is_empty() => socket,
+    _ => return,
+  };
+  if let Err(e) = send(&socket, state) {
+    eprintln!("Не удалось уведомить systemd ({}): {}", state, e);
+  };
+}
+
+/// Отправляет состояние в сокет уведомлений. Имя, начинающееся с `@`, обозначает абстрактный сокет Linux.
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+  use std::os::unix::net::UnixDatagram;
+
+  let sender = UnixDatagram::unbound()?;
+  match socket.strip_prefix('@') {
+    #[cfg(target_os = "linux")]
+    Some(name) => {
+      use std::os::linux::net::SocketAddrExt;
+      let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+      sender.send_to_addr(state.as_bytes(), &addr)?;
+    },
+    _ => { sender.send_to(state.as_bytes(), socket)?; },
+  };
+  Ok(())
+}
+
+/// Отправляет состояние в сокет уведомлений. Вне Unix уведомления не поддерживаются.
+#[cfg(not(unix))]
+fn send(_socket: &str, _state: &str) -> io::Result<()> {
+  Ok(())
+}
+
+/// Файл с идентификатором процесса сервера. Удаляется, когда сервер выключается.
+pub struct PidFile {
+  path: String,
+}
+
+impl PidFile {
+  /// Записывает идентификатор процесса в файл, заменяя прежнее содержимое.
+  pub fn create(path: &str) -> io::Result<PidFile> {
+    fs::write(path, format!("{}\n", process::id()))?;
+    Ok(PidFile { path: path.to_string() })
+  }
+}
+
+impl Drop for PidFile {
+  fn drop(&mut self) {
+    fs::remove_file(&self.path).ok();
+  }
+}
@@ -0,0 +1,133 @@
+//! Ключи администраторов и ключи регистрации.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{ADMIN_KEY, TestServer};
+
+#[tokio::test]
+async fn scoped_keys_are_enforced() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let root = json!({ "key": ADMIN_KEY });
+  let (status, key) = server.request(
+    Method::PUT, "/admin/keys", Some(&root), Some(&json!({ "name": "backups", "scopes": ["backup"] }))
+  ).await;
+  assert_eq!(status, 200, "{}", key);
+  let key: JsonValue = serde_json::from_str(&key).unwrap();
+  let backups = json!({ "key": key["key"] });
+  
+  let (status, _) = server.request(Method::GET, "/admin/backup", Some(&backups), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&backups), None).await;
+  assert_eq!(status, 401);
+  // Управлять ключами можно только корневым ключом.
+  let (status, _) = server.request(Method::GET, "/admin/keys", Some(&backups), None).await;
+  assert_eq!(status, 401);
+  
+  let (status, list) = server.request(Method::GET, "/admin/keys", Some(&root), None).await;
+  assert_eq!(status, 200);
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  assert_eq!(list, json!([{ "name": "backups", "scopes": ["backup"], "expires_at": null }]));
+  
+  let (status, _) = server.request(Method::DELETE, "/admin/keys", Some(&root), Some(&json!({ "name": "backups" }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/admin/backup", Some(&backups), None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn expired_keys_are_rejected() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let root = json!({ "key": ADMIN_KEY });
+  let (status, key) = server.request(Method::PUT, "/admin/keys", Some(&root), Some(&json!({
+    "name": "old", "scopes": ["setup"], "expires_at": 1
+  }))).await;
+  assert_eq!(status, 200);
+  let key: JsonValue = serde_json::from_str(&key).unwrap();
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": key["key"] })), None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn sign_up_consumes_cc_keys() {
+  let server = match TestServer::start_with_env(&[("CC_KEY_REQUIRED", "true")]).await { Some(s) => s, None => return };
+  let root = json!({ "key": ADMIN_KEY });
+  let (status, keys) = server.request(
+    Method::POST, "/admin/cc-keys", Some(&root), Some(&json!({ "count": 3, "note": "Команда" }))
+  ).await;
+  assert_eq!(status, 200, "{}", keys);
+  let keys: Vec<JsonValue> = serde_json::from_str(&keys).unwrap();
+  assert_eq!(keys.len(), 3);
+  assert!(keys.iter().all(|k| k["note"] == "Команда" && k["key"].is_string()));
+  
+  let creds = |cc_key: &JsonValue| json!({ "login": "ivan", "pass": "Kettle-Orbit-42", "cc_key": cc_key });
+  let (status, _) = server.request(Method::PUT, "/sign-up", Some(&creds(&json!("wrong"))), None).await;
+  assert_eq!(status, 401);
+  let (status, _) = server.request(Method::PUT, "/sign-up", Some(&creds(&keys[0]["key"])), None).await;
+  assert_eq!(status, 200);
+  
+  let (status, revoked) = server.request(
+    Method::DELETE, "/admin/cc-keys", Some(&root), Some(&json!({ "keys": [keys[1]["key"]] }))
+  ).await;
+  assert_eq!((status, revoked.as_str()), (200, "1"));
+  let (status, unused) = server.request(Method::GET, "/admin/cc-keys", Some(&root), None).await;
+  assert_eq!(status, 200);
+  let unused: Vec<JsonValue> = serde_json::from_str(&unused).unwrap();
+  assert_eq!(unused.iter().map(|k| &k["key"]).collect::<Vec<_>>(), vec![&keys[2]["key"]]);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn admin_calls_are_audited() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let root = json!({ "key": ADMIN_KEY });
+  let (status, key) = server.request(
+    Method::PUT, "/admin/keys", Some(&root), Some(&json!({ "name": "ops", "scopes": ["user-management"] }))
+  ).await;
+  assert_eq!(status, 200, "{}", key);
+  let ops = json!({ "key": serde_json::from_str::<JsonValue>(&key).unwrap()["key"] });
+  let (status, keys) = server.request(Method::POST, "/admin/cc-keys", Some(&ops), Some(&json!({ "count": 2 }))).await;
+  assert_eq!(status, 200);
+  let keys: Vec<JsonValue> = serde_json::from_str(&keys).unwrap();
+  let (status, _) = server.request(
+    Method::DELETE, "/admin/cc-keys", Some(&ops), Some(&json!({ "keys": [keys[0]["key"]] }))
+  ).await;
+  assert_eq!(status, 200);
+  // Вызовы, не прошедшие проверку ключа, не записываются.
+  let (status, _) = server.request(Method::GET, "/admin/backup", Some(&ops), None).await;
+  assert_eq!(status, 401);
+  // Журнал доступен только с корневым ключом.
+  let (status, _) = server.request(Method::GET, "/admin/audit", Some(&ops), None).await;
+  assert_eq!(status, 401);
+
+  let (status, records) = server.request(Method::GET, "/admin/audit?key=ops", Some(&root), None).await;
+  assert_eq!(status, 200, "{}", records);
+  let records: Vec<JsonValue> = serde_json::from_str(&records).unwrap();
+  let routes: Vec<&JsonValue> = records.iter().map(|r| &r["route"]).collect();
+  assert_eq!(routes, vec!["DELETE /admin/cc-keys", "POST /admin/cc-keys"]);
+  assert_eq!(records[0]["summary"], json!({ "count": 1 }));
+  assert!(!records[0].to_string().contains(keys[0]["key"].as_str().unwrap()));
+
+  let (status, records) = server.request(Method::GET, "/admin/audit?route=PUT+/admin/keys", Some(&root), None).await;
+  assert_eq!(status, 200, "{}", records);
+  let records: Vec<JsonValue> = serde_json::from_str(&records).unwrap();
+  assert_eq!(records.len(), 1);
+  assert_eq!((&records[0]["key"], &records[0]["entity"]), (&JsonValue::Null, &json!("ops")));
+  assert_eq!(records[0]["summary"]["scopes"], json!(["user-management"]));
+  // Постраничное получение.
+  let (_, page) = server.request(Method::GET, "/admin/audit?limit=1", Some(&root), None).await;
+  let page: Vec<JsonValue> = serde_json::from_str(&page).unwrap();
+  assert_eq!(page[0]["route"], "GET /admin/audit");
+  let (_, next) = server.request(
+    Method::GET, &format!("/admin/audit?limit=1&before={}", page[0]["id"]), Some(&root), None
+  ).await;
+  let next: Vec<JsonValue> = serde_json::from_str(&next).unwrap();
+  assert!(next[0]["id"].as_i64() < page[0]["id"].as_i64());
+  let (status, _) = server.request(Method::GET, "/admin/audit?limit=0", Some(&root), None).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
@@ -0,0 +1,61 @@
+//! Резервное копирование и восстановление базы данных.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{ADMIN_KEY, TestServer};
+
+#[tokio::test]
+async fn backup_and_restore() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let admin = json!({ "key": ADMIN_KEY });
+  let token = server.sign_up("frank").await;
+  let board_id = server.create_board(&token, "Сохранённая").await;
+  
+  let (status, _) = server.request(Method::GET, "/admin/backup", Some(&json!({ "key": "wrong" })), None).await;
+  assert_eq!(status, 401);
+  let (status, dump) = server.request(Method::GET, "/admin/backup", Some(&admin), None).await;
+  assert_eq!(status, 200);
+  let rows: Vec<JsonValue> = dump.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+  assert!(rows.iter().any(|r| r["table"] == "boards" && r["row"]["id"] == board_id));
+  assert!(rows.iter().any(|r| r["table"] == "users" && r["row"]["user_creds"].is_object()));
+  assert!(rows.iter().any(|r| r["table"] == "admin_audit" && r["row"]["route"] == "GET /admin/backup"));
+  let (status, no_secrets) = server.request(Method::GET, "/admin/backup?no-secrets", Some(&admin), None).await;
+  assert_eq!(status, 200);
+  assert!(!no_secrets.contains("user_creds"));
+  
+  let other = server.sign_up("grace").await;
+  server.create_board(&other, "Лишняя").await;
+  server.sql(&format!(
+    "insert into undo_log (board_id, user_id, at, record) values ({}, {}, extract(epoch from now())::bigint, '{{}}');", board_id, token["id"]
+  )).await;
+  
+  let (status, _) = server.request_raw(Method::PUT, "/admin/restore", Some(&admin), Body::from(dump.clone())).await;
+  assert_eq!(status, 200);
+  
+  let (status, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  assert_eq!(list.as_array().unwrap().len(), 1);
+  assert_eq!(list[0]["id"], board_id);
+  let (status, _) = server.request(Method::GET, "/list", Some(&other), None).await;
+  assert_eq!(status, 401);
+  // Удаления, сделанные после резервного копирования, отменить нельзя.
+  let (status, _) = server.request(Method::POST, "/board/undo", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 404);
+  // Последовательности идентификаторов продолжаются после восстановленных данных.
+  let newcomer = server.sign_up("heidi").await;
+  let new_board_id = server.create_board(&newcomer, "Новая").await;
+  assert!(new_board_id > board_id);
+  
+  // Копия с неизвестной таблицей отклоняется целиком, не затрагивая данные.
+  let broken = dump + "{\"table\":\"pg_authid\",\"row\":{}}\n";
+  let (status, _) = server.request_raw(Method::PUT, "/admin/restore", Some(&admin), Body::from(broken)).await;
+  assert_eq!(status, 500);
+  let (status, list) = server.request(Method::GET, "/list", Some(&newcomer), None).await;
+  assert_eq!(status, 200);
+  assert_eq!(serde_json::from_str::<JsonValue>(&list).unwrap()[0]["id"], new_board_id);
+  server.stop().await;
+}
@@ -0,0 +1,87 @@
+//! Приём уведомлений об оплате от платёжного провайдера.
+
+mod test_support;
+
+use chrono::Utc;
+use crypto::{hmac::Hmac, mac::Mac, sha2::Sha256};
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::TestServer;
+
+const WEBHOOK_SECRET: &str = "whsec_test";
+
+/// Подписывает уведомление так же, как это делает Stripe.
+fn stripe_signature(secret: &str, timestamp: i64, body: &str) -> String {
+  let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+  mac.input(format!("{}.{}", timestamp, body).as_bytes());
+  let signature: String = mac.result().code().iter().map(|b| format!("{:02x}", b)).collect();
+  format!("t={},v1={}", timestamp, signature)
+}
+
+#[tokio::test]
+async fn paid_invoice_unlocks_paid_plan() {
+  let envs = [("STRIPE_WEBHOOK_SECRET", WEBHOOK_SECRET)];
+  let server = match TestServer::start_with_env(&envs).await { Some(s) => s, None => return };
+  let token = server.sign_up("judy").await;
+  let user_id = token["id"].as_i64().unwrap();
+  let now = Utc::now().timestamp();
+  let event = json!({
+    "type": "invoice.paid",
+    "created": now,
+    "data": { "object": { "metadata": { "user_id": user_id.to_string() } } }
+  }).to_string();
+  
+  let forged = stripe_signature("wrong-secret", now, &event);
+  let (status, _) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[("Stripe-Signature", &forged)], Body::from(event.clone())
+  ).await;
+  assert_eq!(status, 400);
+  let stale = stripe_signature(WEBHOOK_SECRET, now - 3600, &event);
+  let (status, _) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[("Stripe-Signature", &stale)], Body::from(event.clone())
+  ).await;
+  assert_eq!(status, 400);
+  let (_, quota) = server.request(Method::GET, "/user/quota", Some(&token), None).await;
+  assert_eq!(serde_json::from_str::<JsonValue>(&quota).unwrap()["billed"], false);
+  
+  let signature = stripe_signature(WEBHOOK_SECRET, now, &event);
+  let (status, body) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[("Stripe-Signature", &signature)], Body::from(event)
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  let (_, quota) = server.request(Method::GET, "/user/quota", Some(&token), None).await;
+  assert_eq!(serde_json::from_str::<JsonValue>(&quota).unwrap()["billed"], true);
+  
+  // Уведомления, не относящиеся к оплате, принимаются и игнорируются.
+  let other = json!({ "type": "customer.created", "created": now, "data": { "object": {} } }).to_string();
+  let signature = stripe_signature(WEBHOOK_SECRET, now, &other);
+  let (status, _) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[("Stripe-Signature", &signature)], Body::from(other)
+  ).await;
+  assert_eq!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn webhook_is_disabled_without_provider() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let (status, _) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[], Body::from("{}")
+  ).await;
+  assert_eq!(status, 404);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn oversized_webhook_is_rejected() {
+  let envs = [("STRIPE_WEBHOOK_SECRET", WEBHOOK_SECRET)];
+  let server = match TestServer::start_with_env(&envs).await { Some(s) => s, None => return };
+  let event = "x".repeat(8 * 1024 * 1024 + 1);
+  let signature = stripe_signature(WEBHOOK_SECRET, Utc::now().timestamp(), &event);
+  let (status, _) = server.request_with_headers(
+    Method::POST, "/billing/webhook", &[("Stripe-Signature", &signature)], Body::from(event)
+  ).await;
+  assert_eq!(status, 413);
+  server.stop().await;
+}
@@ -0,0 +1,61 @@
+//! Замеры и ограничения размера досок.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{no_timelines, TestServer, ADMIN_KEY};
+
+#[tokio::test]
+async fn boards_are_measured_and_limited() {
+  let limits = json!({ "max_tasks_per_card": 2, "max_board_kb": 4 }).to_string();
+  let server = match TestServer::start_with_env(&[("BOARD_LIMITS", &limits)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("lev").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({ "title": title, "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines() });
+  let card = |tasks: Vec<JsonValue>, description: &str| json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "description": description, "tasks": tasks,
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  });
+  let request = |method: Method, path: &'static str, body: JsonValue| {
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, body) = server.request(method, path, Some(token), Some(&body)).await;
+      (status, serde_json::from_str::<JsonValue>(&body).unwrap_or(JsonValue::String(body)))
+    }
+  };
+
+  // Карточка не может получить больше `max_tasks_per_card` задач ни при создании, ни при добавлении задачи.
+  let (status, error) = request(Method::PUT, "/card", card(vec![task("1"), task("2"), task("3")], "")).await;
+  assert_eq!(status, 413, "{}", error);
+  assert_eq!((&error["limit"], &error["max"], &error["actual"]), (&json!("max_tasks_per_card"), &json!(2), &json!(3)));
+  let (status, card_id) = request(Method::PUT, "/card", card(vec![task("1"), task("2")], "")).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let task_ref = json!({ "board_id": board_id, "card_id": card_id, "task": task("3") });
+  assert_eq!(request(Method::PUT, "/task", task_ref).await.0, 413);
+
+  // Доска не может вырасти больше `max_board_kb`, но изменения, которые её не увеличивают, принимаются.
+  let (status, error) = request(Method::PUT, "/card", card(vec![], &"а".repeat(4096))).await;
+  assert_eq!(status, 413, "{}", error);
+  assert_eq!((&error["limit"], &error["max"]), (&json!("max_board_kb"), &json!(4)));
+  assert!(error["request_id"].is_string(), "{}", error);
+  let delete = json!({ "board_id": board_id, "card_id": card_id, "task_id": 1 });
+  assert_eq!(request(Method::DELETE, "/task", delete).await.0, 200);
+
+  // Администратор видит размеры досок и число отклонённых изменений.
+  let admin = json!({ "key": ADMIN_KEY });
+  let (status, body) = server.request(Method::GET, "/admin/board-sizes", Some(&admin), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let sizes: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(sizes["limits"], json!({ "max_tasks_per_card": 2, "max_board_kb": 4 }));
+  assert_eq!(sizes["rejected"], json!({ "max_tasks_per_card": 2, "max_board_kb": 1 }));
+  let board = sizes["largest"].as_array().unwrap().iter().find(|b| b["board_id"] == board_id).unwrap();
+  assert_eq!((&board["cards"], &board["tasks"], &board["max_card_tasks"]), (&json!(1), &json!(1), &json!(1)));
+  assert!(board["bytes"].as_u64().unwrap() > 0 && board["bytes"].as_u64().unwrap() <= 4096);
+  assert_eq!(server.request(Method::GET, "/admin/board-sizes", Some(&token), None).await.0, 401);
+  server.stop().await;
+}
@@ -0,0 +1,51 @@
+//! Команды сервера: настройка базы данных, выпуск ключа администратора и выгрузка доски.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::TestServer;
+
+/// Проверяет команды на запущенном сервере с любым хранилищем.
+async fn check_commands(server: TestServer) {
+  let olga = server.sign_up("olga").await;
+  let board_id = server.create_board(&olga, "Релиз").await;
+
+  let (code, _, stderr) = server.command(&["migrate"]);
+  assert_eq!(code, 0, "{}", stderr);
+
+  let (code, stdout, stderr) = server.command(&["export", "--board", &board_id.to_string()]);
+  assert_eq!(code, 0, "{}", stderr);
+  let board: JsonValue = serde_json::from_str(&stdout).unwrap();
+  assert_eq!((&board["id"], &board["header"]["title"]), (&json!(board_id), &json!("Релиз")));
+  assert_eq!(server.command(&["export", "--board", "999"]).0, 1);
+
+  // Выпущенный ключ сразу действует в запущенном сервере.
+  let (code, key, stderr) = server.command(&["create-admin-key", "--name", "ops", "--scopes", "user-management,setup"]);
+  assert_eq!(code, 0, "{}", stderr);
+  let (status, body) = server.request(Method::GET, "/admin/keys", Some(&json!({ "key": test_support::ADMIN_KEY })), None).await;
+  assert_eq!(status, 200, "{}", body);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap()[0]["scopes"], json!(["user-management", "setup"]));
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": key.trim() })), None).await;
+  assert_eq!(status, 200);
+
+  let (code, _, stderr) = server.command(&["create-admin-key", "--name", "ops", "--scopes", "everything"]);
+  assert_eq!(code, 2);
+  assert!(stderr.contains("Неизвестная область действия: everything."), "{}", stderr);
+  assert_eq!(server.command(&["export"]).0, 2);
+  assert_eq!(server.command(&["export", "--board", "1", "--format", "xml"]).0, 2);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn commands_work_with_postgres() {
+  if let Some(server) = TestServer::start().await {
+    check_commands(server).await;
+  };
+}
+
+#[tokio::test]
+async fn commands_work_with_sqlite() {
+  check_commands(TestServer::start_sqlite(&[]).await).await;
+}
@@ -0,0 +1,196 @@
+//! Проверка миграций данных, записанных предыдущими версиями сервера.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{ADMIN_KEY, TestServer};
+
+#[tokio::test]
+async fn inline_tags_move_to_board_dictionary() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("erin").await;
+  let board_id = server.create_board(&token, "Старая доска").await;
+  let tag = json!({ "id": 7, "title": "Баг", "text_color": "#ffffff", "background_color": "#ff0000" });
+  let timelines = json!({ "preferred_time": 0, "max_time": 0, "expected_time": 0 });
+  let legacy_cards = json!([{
+    "id": 1, "author": 1, "title": "Карточка", "header_text_color": "#000000",
+    "header_background_color": "#ffffff", "background_color": "#ffffff",
+    "tasks": [{
+      "id": 1, "author": 1, "title": "Задача", "executors": [], "exec": false, "notes": "",
+      "tags": [tag], "timelines": timelines,
+      "subtasks": [{
+        "id": 1, "author": 1, "title": "Подзадача", "executors": [], "exec": false,
+        "tags": [tag, tag], "timelines": timelines
+      }]
+    }]
+  }]);
+  server.sql(&format!(
+    "update boards set cards = '{}', tags = null where id = {};", legacy_cards, board_id
+  )).await;
+  
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200);
+  // Повторный запуск миграции не должен ничего менять.
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200);
+  
+  let (status, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["tags"].as_array().unwrap().len(), 1);
+  assert_eq!(board["tags"][0]["title"], "Баг");
+  let tag_id = board["tags"][0]["id"].clone();
+  let task = &board["cards"][0]["tasks"][0];
+  assert_eq!(task["tags"], json!([tag_id]));
+  assert_eq!(task["subtasks"][0]["tags"], json!([tag_id]));
+  assert_eq!(task["description"], "");
+  assert_eq!(task["subtasks"][0]["notes"], "");
+  assert_eq!(task["priority"], "normal");
+  assert_eq!(task["subtasks"][0]["priority"], "normal");
+  
+  // Новые теги получают идентификаторы после перенесённых.
+  let (status, new_tag_id) = server.request(Method::PUT, "/board/tag", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "tag": { "id": 0, "title": "Фича", "text_color": "#ffffff", "background_color": "#00ff00" }
+  }))).await;
+  assert_eq!(status, 200);
+  assert_ne!(json!(new_tag_id.parse::<i64>().unwrap()), tag_id);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn legacy_tag_id_seq_is_renamed() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("grace").await;
+  let board_id = server.create_board(&token, "Старая доска").await;
+  // Раньше последовательность тегов хранила последний выданный идентификатор.
+  server.sql(&format!("insert into id_seqs values ('{}t', 3);", board_id)).await;
+  
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200);
+  
+  let (status, tag_id) = server.request(Method::PUT, "/board/tag", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "tag": { "id": 0, "title": "Фича", "text_color": "#ffffff", "background_color": "#00ff00" }
+  }))).await;
+  assert_eq!(status, 200, "{}", tag_id);
+  assert_eq!(tag_id, "4");
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn plaintext_tokens_are_hashed() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  server.sign_up("erin").await;
+  let (status, body) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "erin", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  let token: JsonValue = serde_json::from_str(&body).unwrap();
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  // Так токены хранили предыдущие версии сервера: в открытом виде, массивом байт или строкой.
+  let plaintext = json!(token["token"].as_str().unwrap().as_bytes());
+  server.sql(&format!(
+    "update users set user_creds = jsonb_set(jsonb_set(user_creds, '{{tokens,1,tk}}', '{}'), '{{refresh_tokens,1,tk}}', '{}');",
+    plaintext, token["refresh_token"]
+  )).await;
+
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let refresh = json!({ "id": token["id"], "refresh_token": token["refresh_token"] });
+  let (status, body) = server.request(Method::POST, "/token/refresh", Some(&refresh), None).await;
+  assert_eq!(status, 200, "{}", body);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn json_columns_are_converted_to_jsonb() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("erin").await;
+  let stranger = server.sign_up("gleb").await;
+  let board_id = server.create_board(&token, "Старая доска").await;
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  let admin = json!({ "key": ADMIN_KEY });
+  let cli = server.hold("").await;
+  let columns = [("boards", "cards"), ("boards", "header"), ("boards", "shared_with"), ("users", "shared_boards"), ("users", "user_creds")];
+  // Колонки, которые создали предыдущие версии сервера, и значения из их резервной копии, загруженные строкой JSON.
+  server.sql("drop index boards_shared_with_idx;").await;
+  for legacy in ["alter table {0} alter column {1} type varchar using {1}::text;", "update {0} set {1} = to_jsonb({1}::text);"] {
+    for (table, column) in columns {
+      server.sql(&legacy.replace("{0}", table).replace("{1}", column)).await;
+    };
+    let (status, _) = server.request(Method::GET, "/pg-setup", Some(&admin), None).await;
+    assert_eq!(status, 200);
+    for (table, column) in columns {
+      let row = cli.query_one(
+        "select data_type::text from information_schema.columns where table_name = $1 and column_name = $2;", &[&table, &column]
+      ).await.unwrap();
+      assert_eq!(row.get::<_, &str>(0), "jsonb", "{}.{}", table, column);
+    };
+    let (status, _) = server.request(Method::GET, "/sign-in", Some(&json!({ "login": "erin", "pass": "Kettle-Orbit-42" })), None).await;
+    assert_eq!(status, 200);
+    let (_, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+    assert_eq!(serde_json::from_str::<JsonValue>(&list).unwrap()[0]["title"], "Старая доска");
+    let (status, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+    assert_eq!(status, 200, "{}", board);
+    assert_eq!(serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["title"], "Карточка");
+    let (status, _) = server.request(Method::POST, "/board", Some(&stranger), Some(&json!({ "board_id": board_id }))).await;
+    assert_eq!(status, 401);
+  };
+  let index = cli.query("select 1 from pg_indexes where indexname = 'boards_shared_with_idx';", &[]).await.unwrap();
+  assert_eq!(index.len(), 1);
+  drop(cli);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn primary_keys_are_added() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("erin").await;
+  server.create_board(&token, "Старая доска").await;
+  let admin = json!({ "key": ADMIN_KEY });
+  // Так таблицы создавали предыдущие версии сервера.
+  server.sql(
+    "alter table users drop constraint users_pkey; alter table boards drop constraint boards_pkey; \
+     alter table id_seqs drop constraint id_seqs_pkey; alter table id_seqs add unique (id);"
+  ).await;
+  server.sql("insert into users (id, login) select id, 'copy' from users where login = 'erin';").await;
+  let (status, body) = server.request(Method::GET, "/pg-setup", Some(&admin), None).await;
+  assert_eq!(status, 409, "{}", body);
+  assert!(body.contains("users"), "{}", body);
+
+  server.sql("delete from users where login = 'copy';").await;
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&admin), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/pg-setup", Some(&admin), None).await;
+  assert_eq!(status, 200);
+  let cli = server.hold("").await;
+  let keys = cli.query(
+    "select table_name::text, constraint_type::text from information_schema.table_constraints \
+       where table_name in ('users', 'boards', 'id_seqs') and constraint_type in ('PRIMARY KEY', 'UNIQUE') \
+       order by table_name, constraint_type;",
+    &[]
+  ).await.unwrap();
+  let keys: Vec<(String, String)> = keys.iter().map(|row| (row.get(0), row.get(1))).collect();
+  let expected = [("boards", "PRIMARY KEY"), ("id_seqs", "PRIMARY KEY"), ("users", "PRIMARY KEY"), ("users", "UNIQUE")];
+  assert_eq!(keys, expected.map(|(table, kind)| (table.to_string(), kind.to_string())));
+  server.sign_up("gleb").await;
+  drop(cli);
+  server.stop().await;
+}
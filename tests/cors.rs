@@ -0,0 +1,69 @@
+//! Заголовки CORS и заголовки безопасности в ответах сервера, предзапросы браузера.
+
+mod test_support;
+
+use hyper::{Body, Method};
+
+use test_support::TestServer;
+
+const ORIGIN: &str = "https://app.example.com";
+
+#[tokio::test]
+async fn preflight_follows_cors_settings() {
+  let server = TestServer::start_sqlite(&[
+    ("CORS_ORIGINS", ORIGIN),
+    ("CORS_ALLOWED_HEADERS", "App-Token,Content-Type"),
+    ("CORS_MAX_AGE_SECS", "120"),
+  ]).await;
+  let preflight = |origin: &'static str, method: &'static str, headers: &'static str| {
+    let server = &server;
+    async move {
+      server.request_bytes(Method::OPTIONS, "/board", &[
+        ("Origin", origin),
+        ("Access-Control-Request-Method", method),
+        ("Access-Control-Request-Headers", headers),
+      ], Body::empty()).await
+    }
+  };
+
+  // Разрешённые заголовки перечисляются так, как их запросил браузер.
+  let (status, headers, _) = preflight(ORIGIN, "PATCH", "app-token, content-type").await;
+  assert_eq!(status, 204);
+  assert_eq!(headers["Access-Control-Allow-Origin"], ORIGIN);
+  assert_eq!(headers["Access-Control-Allow-Headers"], "app-token, content-type");
+  assert_eq!(headers["Access-Control-Allow-Methods"], "GET, POST, PUT, PATCH, DELETE");
+  assert_eq!(headers["Access-Control-Max-Age"], "120");
+
+  let (status, _, body) = preflight(ORIGIN, "PATCH", "App-Token, Device-Name").await;
+  assert_eq!(status, 403);
+  let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+  assert_eq!(body["error"], "Заголовок Device-Name не разрешён.");
+  assert_eq!(preflight(ORIGIN, "TRACE", "App-Token").await.0, 403);
+  assert_eq!(preflight("https://evil.example.com", "GET", "App-Token").await.0, 403);
+
+  // Запрос OPTIONS не из браузера получает список методов.
+  let (status, headers, _) = server.request_bytes(Method::OPTIONS, "/board", &[], Body::empty()).await;
+  assert_eq!(status, 204);
+  assert_eq!(headers["Allow"], "GET, POST, PUT, PATCH, DELETE, OPTIONS");
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn every_response_carries_security_headers() {
+  let server = TestServer::start_sqlite(&[
+    ("CORS_ORIGINS", ORIGIN),
+    ("SECURITY_HEADERS", r#"{"hsts_max_age_secs": 31536000, "hsts_include_subdomains": true, "referrer_policy": "same-origin"}"#),
+  ]).await;
+  // Заголовки получают и успешные ответы, и ответы с ошибкой.
+  let user = server.sign_up("headers").await;
+  let token = test_support::encode(&user);
+  for headers in [&[("App-Token", token.as_str()), ("Origin", ORIGIN)][..], &[("Origin", ORIGIN)][..]] {
+    let (_, headers, _) = server.request_bytes(Method::GET, "/list", headers, Body::empty()).await;
+    assert_eq!(headers["Strict-Transport-Security"], "max-age=31536000; includeSubDomains");
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    assert_eq!(headers["Referrer-Policy"], "same-origin");
+    assert_eq!(headers["Access-Control-Allow-Origin"], ORIGIN);
+    assert_eq!(headers["Access-Control-Allow-Credentials"], "true");
+  };
+  server.stop().await;
+}
@@ -0,0 +1,111 @@
+//! Дайджесты изменений досок по электронной почте.
+
+mod test_support;
+
+use hyper::{Body, Method, Request, Response, Server, body::to_bytes, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value as JsonValue};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use test_support::TestServer;
+
+const TOKEN: &str = "mailer-token";
+
+/// Принимает письма так же, как почтовый шлюз, и запоминает их.
+async fn mailer(mails: Arc<Mutex<Vec<JsonValue>>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let authorized = req.headers().get("Authorization").and_then(|h| h.to_str().ok()) == Some(&format!("Bearer {}", TOKEN));
+  let status = match authorized {
+    true => {
+      let mail = serde_json::from_slice(&to_bytes(req.into_body()).await.unwrap()).unwrap();
+      mails.lock().unwrap().push(mail);
+      202
+    },
+    false => 401,
+  };
+  Ok(Response::builder().status(status).body(Body::empty()).unwrap())
+}
+
+/// Запускает почтовый шлюз и возвращает его адрес.
+fn start_mailer(mails: Arc<Mutex<Vec<JsonValue>>>) -> SocketAddr {
+  let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+    let mails = mails.clone();
+    async move { Ok::<_, Infallible>(service_fn(move |req| mailer(mails.clone(), req))) }
+  }));
+  let addr = server.local_addr();
+  tokio::spawn(server);
+  addr
+}
+
+#[tokio::test]
+async fn digests_are_mailed() {
+  let mails = Arc::new(Mutex::new(Vec::new()));
+  let addr = start_mailer(mails.clone());
+  let cfg = json!({
+    "url": format!("http://{}/send", addr), "token": TOKEN, "from": "taskboard@example.com", "digest_period_secs": 1
+  }).to_string();
+  let server = match TestServer::start_with_env(&[("MAILER", &cfg)]).await { Some(s) => s, None => return };
+  let alla = server.sign_up("alla").await;
+  let boris = server.sign_up("boris").await;
+  let board_id = server.create_board(&boris, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    boris["id"], alla["id"], board_id
+  )).await;
+  let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+  let task = |title: &str, exec: bool, max_time: i64| json!({
+    "id": 0, "author": 0, "title": title, "executors": [alla["id"]], "exec": exec, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": max_time, "expected_time": 0 }
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&boris), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [
+        task("Сверстать отчёт", false, now + 3 * 3600),
+        task("Сдать черновик", true, now + 3 * 3600),
+        task("Собрать отзывы", false, now + 3 * 86400),
+      ]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let prefs = |token: &JsonValue, patch: JsonValue| {
+    let (server, token) = (&server, token.clone());
+    async move { server.request(Method::PATCH, "/user/notification-prefs", Some(&token), Some(&patch)).await.0 }
+  };
+
+  // Пока адрес не задан, дайджесты не отправляются; отписавшийся пользователь их тоже не получает.
+  let (status, body) = server.request(Method::GET, "/user/notification-prefs", Some(&alla), None).await;
+  assert_eq!(status, 200);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap(), json!({ "email": null, "digest": "daily", "unsubscribed": false }));
+  assert_eq!(prefs(&alla, json!({ "email": "alla" })).await, 400);
+  assert_eq!(prefs(&boris, json!({ "email": "boris@example.com", "unsubscribed": true })).await, 200);
+  assert_eq!(prefs(&alla, json!({ "email": " alla@example.com ", "digest": "weekly" })).await, 200);
+  let (_, body) = server.request(Method::GET, "/user/notification-prefs", Some(&alla), None).await;
+  assert_eq!(
+    serde_json::from_str::<JsonValue>(&body).unwrap(),
+    json!({ "email": "alla@example.com", "digest": "weekly", "unsubscribed": false })
+  );
+
+  for _ in 0..50 {
+    if !mails.lock().unwrap().is_empty() { break; };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  };
+  // Следующий дайджест причитается только через неделю.
+  tokio::time::sleep(Duration::from_secs(2)).await;
+  let mails = mails.lock().unwrap().clone();
+  assert_eq!(mails.len(), 1, "{:?}", mails);
+  assert_eq!(mails[0]["to"], "alla@example.com");
+  assert_eq!(mails[0]["from"], "taskboard@example.com");
+  let text = mails[0]["text"].as_str().unwrap();
+  assert!(
+    text.contains("Вас назначили исполнителем:\n- «Доска»: Сверстать отчёт\n- «Доска»: Сдать черновик\n- «Доска»: Собрать отзывы\n"),
+    "{}", text
+  );
+  assert!(text.contains("Срок наступает в ближайшие сутки:\n- «Доска»: Сверстать отчёт - до "), "{}", text);
+  assert!(!text.contains("Сдать черновик - до") && !text.contains("Собрать отзывы - до"), "{}", text);
+  assert!(text.contains("Изменились доски:\n- «Доска»\n"), "{}", text);
+  server.stop().await;
+}
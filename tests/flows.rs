@@ -0,0 +1,2403 @@
+//! Сквозные сценарии работы с сервером: регистрация, доски, карточки, задачи.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{no_timelines, TestServer, ADMIN_KEY};
+
+#[tokio::test]
+async fn sign_up_and_sign_in() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("alice").await;
+  assert!(token["id"].as_i64().is_some());
+  assert!(token["refresh_token"].is_string());
+  assert_eq!(token["lifetime"]["access_ttl_minutes"], 15);
+  assert_eq!(token["lifetime"]["ttl_days"], 5);
+  assert_eq!(token["lifetime"]["absolute_ttl_days"], 30);
+  assert!(token["lifetime"]["expires_at"].as_i64().is_some());
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "alice", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "alice", "pass": "wrong-password" })), None
+  ).await;
+  assert_eq!(status, 401);
+  let (status, _) = server.request(Method::GET, "/list", None, None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn access_token_expires_and_is_refreshed() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("judy").await;
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  server.sql(
+    "update users set user_creds = jsonb_set(user_creds, '{tokens,0,expires_dt}', '0');"
+  ).await;
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 401);
+  
+  let refresh = json!({ "id": token["id"], "refresh_token": token["refresh_token"] });
+  let (status, body) = server.request(Method::POST, "/token/refresh", Some(&refresh), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let refreshed: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_ne!(refreshed["refresh_token"], token["refresh_token"]);
+  let (status, _) = server.request(Method::GET, "/list", Some(&refreshed), None).await;
+  assert_eq!(status, 200);
+  // Токен обновления можно использовать только один раз.
+  let (status, _) = server.request(Method::POST, "/token/refresh", Some(&refresh), None).await;
+  assert_eq!(status, 401);
+  
+  // Токен обновления, выданный слишком давно, недействителен.
+  server.sql(
+    "update users set user_creds = jsonb_set(user_creds, '{refresh_tokens,0,created_dt}', '0');"
+  ).await;
+  let refresh = json!({ "id": refreshed["id"], "refresh_token": refreshed["refresh_token"] });
+  let (status, _) = server.request(Method::POST, "/token/refresh", Some(&refresh), None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn legacy_token_is_accepted_and_exchanged() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("kate").await;
+  // Так выглядят долгоживущие токены, выданные предыдущими версиями сервера.
+  server.sql(
+    "update users set user_creds = (user_creds #- '{tokens,0,expires_dt}' #- '{tokens,0,created_dt}') - 'refresh_tokens'::text;"
+  ).await;
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let legacy = json!({ "id": token["id"], "refresh_token": token["token"] });
+  let (status, body) = server.request(Method::POST, "/token/refresh", Some(&legacy), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let refreshed: JsonValue = serde_json::from_str(&body).unwrap();
+  let (status, _) = server.request(Method::GET, "/list", Some(&refreshed), None).await;
+  assert_eq!(status, 200);
+  // После обмена долгоживущий токен больше не действует.
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn sign_in_is_locked_after_failures() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  server.sign_up("ivan").await;
+  let wrong = json!({ "login": "ivan", "pass": "wrong-password" });
+  for _ in 0..4 {
+    let (status, _) = server.request(Method::GET, "/sign-in", Some(&wrong), None).await;
+    assert_eq!(status, 401);
+  };
+  let (status, body) = server.request(Method::GET, "/sign-in", Some(&wrong), None).await;
+  assert_eq!(status, 429);
+  let body: JsonValue = serde_json::from_str(&body).unwrap();
+  assert!(body["locked_until"].as_i64().is_some());
+  assert!(body["request_id"].is_string());
+  // Во время блокировки не помогает и верный пароль.
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "ivan", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 429);
+  server.sql("update sign_in_failures set locked_until = 0;").await;
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "ivan", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn weak_credentials_are_rejected() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let (status, body) = server.request(
+    Method::PUT, "/sign-up", Some(&json!({ "login": " mallory", "pass": "password-1234" })), None
+  ).await;
+  assert_eq!(status, 400);
+  let body: JsonValue = serde_json::from_str(&body).unwrap();
+  let rules: Vec<(&str, &str)> = body["violations"].as_array().unwrap().iter()
+    .map(|v| (v["field"].as_str().unwrap(), v["rule"].as_str().unwrap()))
+    .collect();
+  assert_eq!(rules, vec![("login", "surrounding_whitespace"), ("pass", "too_weak")]);
+  let (status, _) = server.request(
+    Method::PUT, "/sign-up", Some(&json!({ "login": "mallory", "pass": "mallory12345" })), None
+  ).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn credentials_are_changed() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("kate").await;
+  server.sign_up("judy").await;
+  let changes = [
+    (json!({ "pass": "wrong-password", "new_pass": "Violet-Tundra-7" }), 401),
+    (json!({ "pass": "Kettle-Orbit-42", "new_pass": "qwerty123" }), 400),
+    (json!({ "pass": "Kettle-Orbit-42", "new_login": "judy" }), 409),
+    (json!({ "pass": "Kettle-Orbit-42", "new_login": "katherine", "new_pass": "Violet-Tundra-7" }), 200),
+  ];
+  for (change, expected) in &changes {
+    let (status, body) = server.request(Method::PATCH, "/user/creds", Some(&token), Some(change)).await;
+    assert_eq!(status, *expected, "{}", body);
+  };
+  // Выданные токены остаются действительными.
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "katherine", "pass": "Violet-Tundra-7" })), None
+  ).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "kate", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn titles_are_validated() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("erin").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let card = |title: &str, task_title: &str| json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": title, "header_text_color": "#000000", "header_background_color": "#ffffff",
+      "background_color": "#ffffff", "tasks": [{
+        "id": 0, "author": 0, "title": task_title, "executors": [], "exec": false,
+        "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+      }]
+    }
+  });
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&card(" \n\t ", "Задача"))).await;
+  assert_eq!(status, 400);
+  // Длина считается в символах, а не в байтах: 256 кириллических букв - это 512 байт.
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&card("Карточка", &"я".repeat(257)))).await;
+  assert_eq!(status, 400);
+  let (status, card_id) = server.request(
+    Method::PUT, "/card", Some(&token), Some(&card(" Карточка\u{0}\n", &"я".repeat(256)))
+  ).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let (status, _) = server.request(Method::PATCH, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "title": "x".repeat(10_000)
+  }))).await;
+  assert_eq!(status, 400);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["header"]["title"], "Доска");
+  assert_eq!(board["cards"][0]["title"], "Карточка");
+  assert_eq!(board["cards"][0]["tasks"][0]["title"].as_str().unwrap().chars().count(), 256);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn malformed_bodies_are_rejected() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("frank").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, body) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [], "colour": "#ff0000",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 400);
+  assert!(body.contains("colour"), "{}", body);
+  let (status, body) = server.request(Method::PATCH, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "header_text_color": 0
+  }))).await;
+  assert_eq!(status, 400);
+  assert!(body.contains("/header_text_color"), "{}", body);
+  let mut nested = json!("дно");
+  for _ in 0..40 { nested = json!([nested]); };
+  let (status, _) = server.request(
+    Method::PATCH, "/board", Some(&token), Some(&json!({ "board_id": board_id, "title": nested }))
+  ).await;
+  assert_eq!(status, 400);
+  let (status, _) = server.request(
+    Method::PATCH, "/board", Some(&token), Some(&json!({ "board_id": board_id, "title": "x".repeat(7_000_000) }))
+  ).await;
+  assert_eq!(status, 413);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn boards_are_listed_in_order() {
+  let quotas = r#"{"free": {"max_boards": 3}, "paid": {}}"#;
+  let server = match TestServer::start_with_env(&[("QUOTAS", quotas)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("grace").await;
+  let mut ids = vec![];
+  for title in ["Первая", "Вторая", "Третья"] {
+    ids.push(server.create_board(&token, title).await);
+  };
+  let (status, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  let listed: Vec<(i64, &str)> = list.as_array().unwrap().iter()
+    .map(|b| (b["id"].as_i64().unwrap(), b["title"].as_str().unwrap()))
+    .collect();
+  assert_eq!(listed, vec![(ids[0], "Первая"), (ids[1], "Вторая"), (ids[2], "Третья")]);
+  
+  let page = |query: String| {
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, page) = server.request(Method::GET, &format!("/list?{}", query), Some(token), None).await;
+      assert_eq!(status, 200, "{}", page);
+      let page: JsonValue = serde_json::from_str(&page).unwrap();
+      let titles: Vec<String> = page["boards"].as_array().unwrap().iter()
+        .map(|b| b["title"].as_str().unwrap().to_string())
+        .collect();
+      (titles, page["next_cursor"].as_str().map(String::from))
+    }
+  };
+  let (titles, cursor) = page("sort=title&limit=2".into()).await;
+  assert_eq!(titles, vec!["Вторая", "Первая"]);
+  let (titles, cursor) = page(format!("sort=title&limit=2&cursor={}", cursor.unwrap())).await;
+  assert_eq!((titles, cursor), (vec!["Третья".to_string()], None));
+  server.sql(&format!("update boards set updated_at = 100 where id = {};", ids[1])).await;
+  let (titles, cursor) = page("sort=activity&limit=1".into()).await;
+  assert_eq!(titles, vec!["Третья"]);
+  let (titles, _) = page(format!("sort=activity&limit=5&cursor={}", cursor.as_ref().unwrap())).await;
+  assert_eq!(titles, vec!["Первая", "Вторая"]);
+  // Курсор, полученный для другого порядка, отклоняется.
+  let (status, _) = server.request(
+    Method::GET, &format!("/list?sort=title&cursor={}", cursor.unwrap()), Some(&token), None
+  ).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_prefs_are_merged_into_list() {
+  let quotas = r#"{"free": {"max_boards": 3}, "paid": {}}"#;
+  let server = match TestServer::start_with_env(&[("QUOTAS", quotas)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("heidi").await;
+  let stranger = server.sign_up("ivan").await;
+  let mut ids = vec![];
+  for title in ["Первая", "Вторая", "Третья"] {
+    ids.push(server.create_board(&token, title).await);
+  };
+  let prefs = |board_id: i64, prefs: JsonValue| {
+    let mut body = prefs;
+    body["board_id"] = json!(board_id);
+    body
+  };
+  let (status, _) = server.request(
+    Method::PATCH, "/user/board-prefs", Some(&stranger), Some(&prefs(ids[0], json!({ "favorite": true })))
+  ).await;
+  assert_eq!(status, 401);
+  for (board_id, body) in [
+    (ids[2], json!({ "favorite": true, "position": 1 })),
+    (ids[1], json!({ "muted": true, "position": 2 })),
+    (ids[1], json!({ "favorite": false })),
+  ] {
+    let (status, _) = server.request(
+      Method::PATCH, "/user/board-prefs", Some(&token), Some(&prefs(board_id, body))
+    ).await;
+    assert_eq!(status, 200);
+  };
+  let (status, list) = server.request(Method::GET, "/list?sort=custom", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  let listed: Vec<(&str, bool, bool, JsonValue)> = list.as_array().unwrap().iter()
+    .map(|b| {
+      (b["title"].as_str().unwrap(), b["favorite"].as_bool().unwrap(), b["muted"].as_bool().unwrap(), b["position"].clone())
+    })
+    .collect();
+  assert_eq!(listed, vec![
+    ("Третья", true, false, json!(1)),
+    ("Вторая", false, true, json!(2)),
+    ("Первая", false, false, JsonValue::Null),
+  ]);
+  // Позиция сбрасывается значением null, остальные настройки при этом не меняются.
+  let (status, _) = server.request(
+    Method::PATCH, "/user/board-prefs", Some(&token), Some(&prefs(ids[2], json!({ "position": null })))
+  ).await;
+  assert_eq!(status, 200);
+  let (_, page) = server.request(Method::GET, "/list?sort=custom&limit=1", Some(&token), None).await;
+  let page: JsonValue = serde_json::from_str(&page).unwrap();
+  assert_eq!(page["boards"][0]["title"], "Вторая");
+  let cursor = page["next_cursor"].as_str().unwrap();
+  let (_, page) = server.request(
+    Method::GET, &format!("/list?sort=custom&cursor={}", cursor), Some(&token), None
+  ).await;
+  let page: JsonValue = serde_json::from_str(&page).unwrap();
+  let titles: Vec<(&str, bool)> = page["boards"].as_array().unwrap().iter()
+    .map(|b| (b["title"].as_str().unwrap(), b["favorite"].as_bool().unwrap()))
+    .collect();
+  assert_eq!(titles, vec![("Первая", false), ("Третья", true)]);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn timestamps_are_maintained() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("judy").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  // Время, переданное клиентом, заменяется сервером.
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "created_at": 1, "updated_at": 1,
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "created_at": 1,
+        "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, _) = server.request(Method::PUT, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1,
+    "subtask": {
+      "id": 0, "author": 0, "title": "Подзадача", "executors": [], "exec": false, "tags": [],
+      "timelines": no_timelines()
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  let (_, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let card = &board["cards"][0];
+  let task = &card["tasks"][0];
+  let subtask = &task["subtasks"][0];
+  let created = board["created_at"].as_i64().unwrap();
+  assert!(created > 1);
+  for entity in [card, task, subtask] {
+    assert!(entity["created_at"].as_i64().unwrap() >= created, "{}", entity);
+  };
+  // Создание подзадачи изменяет задачу, карточку и доску.
+  let updated = subtask["created_at"].as_i64().unwrap();
+  for entity in [&board, card, task, subtask] {
+    assert_eq!(entity["updated_at"], updated, "{}", entity);
+  };
+  let (_, list) = server.request(Method::GET, "/list?sort=created", Some(&token), None).await;
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  assert_eq!(list[0]["created_at"], created);
+  assert_eq!(list[0]["updated_at"], updated);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn wip_limits_are_enforced() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("mila").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let card = |wip_limit: u32, tasks: JsonValue| json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "В работе", "wip_limit": wip_limit, "tasks": tasks,
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  });
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&card(1, json!([task, task])))).await;
+  assert_eq!(status, 409);
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&card(0, json!([])))).await;
+  assert_eq!(status, 400);
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&card(2, json!([task])))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let new_task = json!({ "board_id": board_id, "card_id": card_id, "task": task });
+  let (status, _) = server.request(Method::PUT, "/task", Some(&token), Some(&new_task)).await;
+  assert_eq!(status, 200);
+  let (status, body) = server.request(Method::PUT, "/task", Some(&token), Some(&new_task)).await;
+  assert_eq!(status, 409, "{}", body);
+  // Число задач отдаётся вместе с доской и не зависит от фильтра.
+  let (_, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id, "filter": { "exec": true } }))
+  ).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["cards"][0]["wip_limit"], 2);
+  assert_eq!(board["cards"][0]["task_count"], 2);
+  assert_eq!(board["cards"][0]["tasks"], json!([]));
+  // Карточку из ответа можно передать серверу как есть.
+  let mut copy = board["cards"][0].clone();
+  copy["tasks"] = json!([task]);
+  let (status, body) = server.request(
+    Method::PUT, "/card", Some(&token), Some(&json!({ "board_id": board_id, "card": copy }))
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  // Снятое ограничение больше не мешает добавлять задачи.
+  let (status, _) = server.request(Method::PATCH, "/card", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "wip_limit": null
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PUT, "/task", Some(&token), Some(&new_task)).await;
+  assert_eq!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn lanes_group_tasks() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("nora").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, lane_id) = server.request(Method::PUT, "/board/lane", Some(&token), Some(&json!({
+    "board_id": board_id, "lane": { "id": 0, "title": "Бэкенд", "color": "#ffcc00" }
+  }))).await;
+  assert_eq!(status, 200, "{}", lane_id);
+  let lane_id: i64 = lane_id.parse().unwrap();
+  let task = |title: &str, lane_id: Option<i64>| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false, "lane_id": lane_id,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  // Ссылка на несуществующую дорожку отбрасывается.
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("В дорожке", Some(lane_id)), task("Вне дорожек", None), task("В чужой", Some(lane_id + 1))]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, _) = server.request(Method::PATCH, "/board/lane", Some(&token), Some(&json!({
+    "board_id": board_id, "lane_id": lane_id, "title": "Сервер"
+  }))).await;
+  assert_eq!(status, 200);
+  let get_board = |filter: JsonValue| {
+    let body = json!({ "board_id": board_id, "filter": filter });
+    let server = &server;
+    let token = &token;
+    async move {
+      let (_, board) = server.request(Method::POST, "/board", Some(token), Some(&body)).await;
+      serde_json::from_str::<JsonValue>(&board).unwrap()
+    }
+  };
+  let board = get_board(json!({ "lane_id": lane_id })).await;
+  assert_eq!(board["lanes"], json!([{ "id": lane_id, "title": "Сервер", "color": "#ffcc00" }]));
+  let tasks = board["cards"][0]["tasks"].as_array().unwrap();
+  assert_eq!(tasks.len(), 1);
+  assert_eq!(tasks[0]["title"], "В дорожке");
+  // Задачу можно перенести в дорожку, а с удалением дорожки задачи из неё остаются вне дорожек.
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 2, "lane_id": lane_id
+  }))).await;
+  assert_eq!(status, 200);
+  assert_eq!(get_board(json!({ "lane_id": lane_id })).await["cards"][0]["tasks"].as_array().unwrap().len(), 2);
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 3, "lane_id": lane_id + 1
+  }))).await;
+  assert_eq!(status, 500);
+  let (status, _) = server.request(Method::DELETE, "/board/lane", Some(&token), Some(&json!({
+    "board_id": board_id, "lane_id": lane_id
+  }))).await;
+  assert_eq!(status, 200);
+  let board = get_board(json!({})).await;
+  assert_eq!(board["lanes"], json!([]));
+  for task in board["cards"][0]["tasks"].as_array().unwrap() {
+    assert_eq!(task["lane_id"], JsonValue::Null, "{}", task);
+  };
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn task_dependencies_block_tasks() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("olga").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Макет"), task("Вёрстка"), task("Релиз")]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let depend = |method: Method, task_id: i64, depends_on: i64| {
+    let body = json!({
+      "board_id": board_id, "card_id": card_id, "task_id": task_id,
+      "depends_on": { "card_id": card_id, "task_id": depends_on }
+    });
+    let (server, token) = (&server, &token);
+    async move { server.request(method, "/task/dependency", Some(token), Some(&body)).await.0 }
+  };
+  // Релиз зависит от вёрстки, вёрстка - от макета.
+  assert_eq!(depend(Method::PUT, 3, 2).await, 200);
+  assert_eq!(depend(Method::PUT, 2, 1).await, 200);
+  // Зависимости не образуют циклов.
+  assert_eq!(depend(Method::PUT, 1, 3).await, 409);
+  assert_eq!(depend(Method::PUT, 1, 1).await, 409);
+  assert_eq!(depend(Method::PUT, 1, 42).await, 500);
+  let blocked = || {
+    let (server, token) = (&server, &token);
+    async move {
+      let (_, board) = server.request(
+        Method::POST, "/board", Some(token), Some(&json!({ "board_id": board_id, "filter": { "blocked": true } }))
+      ).await;
+      let board: JsonValue = serde_json::from_str(&board).unwrap();
+      board["cards"][0]["tasks"].as_array().unwrap().iter().map(|t| t["id"].as_i64().unwrap()).collect::<Vec<_>>()
+    }
+  };
+  assert_eq!(blocked().await, vec![2, 3]);
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "exec": true
+  }))).await;
+  assert_eq!(status, 200);
+  assert_eq!(blocked().await, vec![3]);
+  // Удаление зависимости и удаление задачи снимают блокировку.
+  assert_eq!(depend(Method::DELETE, 3, 2).await, 200);
+  assert_eq!(blocked().await, Vec::<i64>::new());
+  assert_eq!(depend(Method::PUT, 3, 2).await, 200);
+  let (status, _) = server.request(Method::DELETE, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 2
+  }))).await;
+  assert_eq!(status, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["cards"][0]["tasks"][1]["depends_on"], json!([]));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn task_links_are_validated() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("lev").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false,
+        "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines(),
+        "links": [{ "id": 7, "title": "Макет", "url": "https://example.com/design", "kind": "document" }]
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let put = |link: JsonValue| {
+    let body = json!({ "board_id": board_id, "card_id": card_id, "task_id": 1, "link": link });
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::PUT, "/task/link", Some(token), Some(&body)).await }
+  };
+  // Адреса с другими схемами, без сервера и с именем пользователя не принимаются.
+  for url in ["javascript:alert(1)", "ftp://example.com", "https://", "https://good.example@evil.example/", "https://a b"] {
+    let (status, _) = put(json!({ "title": "Ссылка", "url": url })).await;
+    assert_eq!(status, 400, "{}", url);
+  };
+  let (status, link_id) = put(json!({ "title": " PR ", "url": "https://github.com/acme/app/pull/1", "kind": "pull_request" })).await;
+  assert_eq!((status, link_id.as_str()), (200, "2"));
+  let (status, _) = put(json!({ "id": 42, "title": "PR", "url": "https://github.com/acme/app/pull/1" })).await;
+  assert_eq!(status, 404);
+  let (status, _) = put(json!({ "id": 2, "title": "PR #1", "url": "https://github.com/acme/app/pull/1", "kind": "pull_request" })).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::DELETE, "/task/link", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "link_id": 1
+  }))).await;
+  assert_eq!(status, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["cards"][0]["tasks"][0]["links"], json!([
+    { "id": 2, "title": "PR #1", "url": "https://github.com/acme/app/pull/1", "kind": "pull_request" }
+  ]));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_document_is_patched() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("vera").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |id: i64, title: &str| json!({
+    "id": id, "author": 0, "title": title, "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task(0, "Макет")]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let patch = |operations: JsonValue| {
+    let body = json!({ "board_id": board_id, "patch": operations });
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::PATCH, "/board/document", Some(token), Some(&body)).await }
+  };
+
+  // Новые тег и задача получают идентификаторы сервера, а ссылки на их временные идентификаторы переписываются.
+  let mut release = task(100, "Релиз");
+  release["tags"] = json!([50]);
+  release["depends_on"] = json!([{ "card_id": card_id, "task_id": 1 }, { "card_id": card_id, "task_id": 42 }]);
+  let (status, board) = patch(json!([
+    { "op": "test", "path": "/revision", "value": 1 },
+    { "op": "replace", "path": "/cards/0/title", "value": "  Этап 1 " },
+    { "op": "add", "path": "/tags/-", "value": { "id": 50, "title": "Важно", "text_color": "#000000", "background_color": "#ff0000" } },
+    { "op": "add", "path": "/cards/0/tasks/-", "value": release },
+    { "op": "copy", "from": "/cards/0/tasks/1/title", "path": "/cards/0/description" },
+  ])).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["revision"], 2);
+  assert_eq!(board["cards"][0]["title"], "Этап 1");
+  assert_eq!(board["cards"][0]["description"], "Релиз");
+  let tag_id = board["tags"][0]["id"].as_i64().unwrap();
+  let release = &board["cards"][0]["tasks"][1];
+  assert_eq!(release["id"], 2);
+  assert_eq!(release["tags"], json!([tag_id]));
+  assert_eq!(release["depends_on"], json!([{ "card_id": card_id, "task_id": 1 }]));
+  assert_eq!(release["blocked"], true);
+
+  // Устаревшая ревизия, цикл зависимостей, неверный путь и неверная структура доски отклоняются без изменений.
+  let (status, _) = patch(json!([{ "op": "test", "path": "/revision", "value": 1 }])).await;
+  assert_eq!(status, 409);
+  let (status, _) = patch(json!([
+    { "op": "add", "path": "/cards/0/tasks/0/depends_on/-", "value": { "card_id": card_id, "task_id": 2 } }
+  ])).await;
+  assert_eq!(status, 409);
+  let (status, _) = patch(json!([{ "op": "remove", "path": "/cards/7" }])).await;
+  assert_eq!(status, 400);
+  let (status, _) = patch(json!([{ "op": "remove", "path": "/cards/0/title" }])).await;
+  assert_eq!(status, 400);
+  let (status, _) = patch(json!([{ "op": "replace", "path": "/cards/0/tasks/0/title", "value": "" }])).await;
+  assert_eq!(status, 400);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["revision"], 2);
+
+  // Поля, которые поддерживает сервер, сохраняют прежние значения, а удаление задачи удаляет зависимости от неё.
+  let (status, board) = patch(json!([
+    { "op": "replace", "path": "/author", "value": 0 },
+    { "op": "move", "from": "/cards/0/tasks/1", "path": "/cards/0/tasks/0" },
+    { "op": "remove", "path": "/cards/0/tasks/1" },
+  ])).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_ne!(board["author"], 0);
+  assert_eq!(board["cards"][0]["tasks"].as_array().unwrap().len(), 1);
+  assert_eq!(board["cards"][0]["tasks"][0]["depends_on"], json!([]));
+  assert_eq!(board["cards"][0]["tasks"][0]["blocked"], false);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_delta_syncs_offline_changes() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("zoya").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Макет"), task("Вёрстка")]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let (status, _) = server.request(Method::PATCH, "/board/document", Some(&token), Some(&json!({
+    "board_id": board_id, "patch": [{ "op": "replace", "path": "/cards/0/tasks/0/title", "value": "Макет 2" }]
+  }))).await;
+  assert_eq!(status, 200);
+  let delta = |body: JsonValue| {
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, delta) = server.request(Method::POST, "/board/delta", Some(token), Some(&body)).await;
+      assert_eq!(status, 200, "{}", delta);
+      serde_json::from_str::<JsonValue>(&delta).unwrap()
+    }
+  };
+
+  // Клиент с известной ревизией получает патч, а с неизвестной - доску целиком.
+  let changes = delta(json!({ "board_id": board_id, "revision": 1 })).await;
+  assert_eq!(changes["revision"], 2);
+  assert!(changes.get("board").is_none());
+  let patch = changes["patch"].as_array().unwrap();
+  assert!(patch.contains(&json!({ "op": "replace", "path": "/cards/0/tasks/0/title", "value": "Макет 2" })));
+  assert_eq!(patch.last().unwrap(), &json!({ "op": "replace", "path": "/revision", "value": 2 }));
+  let changes = delta(json!({ "board_id": board_id, "revision": 2 })).await;
+  assert_eq!(changes["patch"], json!([]));
+  let changes = delta(json!({ "board_id": board_id, "revision": 40 })).await;
+  assert!(changes.get("patch").is_none());
+  assert_eq!(changes["board"]["cards"][0]["tasks"][0]["title"], "Макет 2");
+
+  // Изменения без сети применяются по порядку; изменение того же поля после базовой ревизии конфликтует, если клиент не просит перезаписать его.
+  let title = |task: usize, title: &str| json!([{ "op": "replace", "path": format!("/cards/0/tasks/{}/title", task), "value": title }]);
+  let changes = delta(json!({
+    "board_id": board_id, "revision": 2,
+    "mutations": [
+      { "id": "a", "base_revision": 1, "patch": title(0, "Макет 3") },
+      { "id": "b", "base_revision": 1, "patch": title(1, "Вёрстка 2") },
+      { "id": "c", "base_revision": 1, "patch": title(0, "Макет 3"), "on_conflict": "overwrite" },
+      { "id": "d", "base_revision": 1, "patch": title(1, "Вёрстка 3") },
+      { "id": "e", "base_revision": 5, "patch": title(1, "") },
+    ]
+  })).await;
+  let results = &changes["results"];
+  assert_eq!(results[0], json!({ "id": "a", "status": "conflict", "conflicts": ["/cards/0/tasks/0/title"] }));
+  assert_eq!(results[1], json!({ "id": "b", "status": "applied", "revision": 3 }));
+  assert_eq!(results[2], json!({ "id": "c", "status": "applied", "revision": 4 }));
+  assert_eq!(results[3], json!({ "id": "d", "status": "applied", "revision": 5 }));
+  assert_eq!(results[4]["status"], "rejected");
+  assert!(results[4]["error"].is_string());
+  assert_eq!(changes["revision"], 5);
+  let patch = changes["patch"].as_array().unwrap();
+  assert!(patch.contains(&json!({ "op": "replace", "path": "/cards/0/tasks/0/title", "value": "Макет 3" })));
+  assert!(patch.contains(&json!({ "op": "replace", "path": "/cards/0/tasks/1/title", "value": "Вёрстка 3" })));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_capacity_is_planned() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("inna").await;
+  let member = server.sign_up("kirill").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let from: i64 = 1_900_000_000;
+  let task = |executors: JsonValue, exec: bool, preferred_time: i64, max_time: i64, expected_time: u32| json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": executors, "exec": exec, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": preferred_time, "max_time": max_time, "expected_time": expected_time }
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [
+        task(json!([token["id"], member["id"]]), false, from + 3600, from + 7200, 600),
+        task(json!([token["id"]]), false, 0, from + 86400, 800),
+        task(json!([token["id"]]), true, from + 3600, 0, 500),
+        task(json!([]), false, from + 3600, 0, 120),
+        task(json!([token["id"]]), false, from + 5 * 86400, 0, 300),
+      ]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let capacity = |extra: JsonValue| {
+    let mut body = json!({ "board_id": board_id, "from": from, "to": from + 2 * 86400 });
+    body.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::POST, "/board/capacity", Some(token), Some(&body)).await }
+  };
+
+  // Время общей задачи делится поровну, выполненные задачи и задачи вне периода не учитываются.
+  let (status, plan) = capacity(json!({})).await;
+  assert_eq!(status, 200, "{}", plan);
+  let plan: JsonValue = serde_json::from_str(&plan).unwrap();
+  assert_eq!(plan["capacity_minutes"], 960);
+  assert_eq!(plan["executors"], json!([
+    { "user_id": token["id"], "planned_minutes": 1100, "tasks": 2, "overloaded": true },
+    { "user_id": member["id"], "planned_minutes": 300, "tasks": 1, "overloaded": false },
+  ]));
+  assert_eq!(plan["unassigned"], json!({ "planned_minutes": 120, "tasks": 1 }));
+
+  let (_, plan) = capacity(json!({ "daily_minutes": 600 })).await;
+  let plan: JsonValue = serde_json::from_str(&plan).unwrap();
+  assert_eq!(plan["executors"][0]["overloaded"], false);
+  let (status, _) = capacity(json!({ "to": from })).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn executors_are_suggested() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("inna").await;
+  let member = server.sign_up("kirill").await;
+  let idle = server.sign_up("lev").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}, {2}]' where id = {3}; update users set shared_boards = '[{3}]' where id in ({1}, {2});",
+    token["id"], member["id"], idle["id"], board_id
+  )).await;
+  let task = |executors: JsonValue, exec: bool, expected_time: u32| json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": executors, "exec": exec, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": 0, "expected_time": expected_time }
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [
+        task(json!([]), false, 60),
+        task(json!([token["id"], member["id"]]), false, 120),
+        task(json!([member["id"]]), false, 300),
+        task(json!([token["id"]]), true, 900),
+        task(json!([idle["id"]]), false, 0),
+      ]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let suggest = |task_id: i64| {
+    let (server, token) = (&server, &token);
+    async move {
+      server.request(Method::POST, "/board/suggest-assignment", Some(token), Some(&json!({ "board_id": board_id, "card_id": card_id, "task_id": task_id }))).await
+    }
+  };
+
+  // Выполненные задачи не учитываются, а при равном времени меньше загружен тот, у кого меньше задач.
+  let (status, body) = suggest(1).await;
+  assert_eq!(status, 200, "{}", body);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap(), json!([
+    { "user_id": idle["id"], "open": 1, "overdue": 0, "open_minutes": 0 },
+    { "user_id": token["id"], "open": 1, "overdue": 0, "open_minutes": 120 },
+    { "user_id": member["id"], "open": 2, "overdue": 0, "open_minutes": 420 },
+  ]));
+  // Исполнители задачи не предлагаются.
+  let (_, body) = suggest(2).await;
+  let suggestions: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(suggestions.as_array().unwrap().iter().map(|s| &s["user_id"]).collect::<Vec<_>>(), vec![&idle["id"]]);
+  assert_eq!(suggest(10).await.0, 404);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn sprint_burndown_is_reported() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("vera").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+  let start = now - 3 * 86400 - 600;
+  let sprint = |start: i64, end: i64| json!({ "board_id": board_id, "sprint": { "id": 0, "title": "Спринт 1", "start": start, "end": end } });
+  let (status, _) = server.request(Method::PUT, "/board/sprint", Some(&token), Some(&sprint(start, start))).await;
+  assert_eq!(status, 400);
+  let (status, sprint_id) = server.request(Method::PUT, "/board/sprint", Some(&token), Some(&sprint(start, start + 7 * 86400))).await;
+  assert_eq!(status, 200, "{}", sprint_id);
+  let sprint_id: i64 = sprint_id.parse().unwrap();
+  let task = |sprint_id: Option<i64>, story_points: u32| json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "sprint_id": sprint_id, "story_points": story_points,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task(Some(sprint_id), 3), task(Some(sprint_id), 5), task(Some(sprint_id), 8), task(None, 13)]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  // Задачи запланированы в первый день спринта.
+  server.sql(&format!(
+    "update boards set cards = regexp_replace(cards::text, '\"created_at\": [0-9]+', '\"created_at\": {}', 'g')::jsonb where id = {};",
+    start + 60, board_id
+  )).await;
+  let patch_task = |task_id: i64, patch: JsonValue| {
+    let mut body = json!({ "board_id": board_id, "card_id": card_id, "task_id": task_id });
+    body.as_object_mut().unwrap().extend(patch.as_object().unwrap().clone());
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::PATCH, "/task", Some(token), Some(&body)).await.0 }
+  };
+  assert_eq!(patch_task(1, json!({ "exec": true })).await, 200);
+  assert_eq!(patch_task(3, json!({ "sprint_id": null })).await, 200);
+  assert_eq!(patch_task(4, json!({ "sprint_id": sprint_id + 1 })).await, 404);
+  let report = |unit: &str| {
+    let body = json!({ "board_id": board_id, "sprint_id": sprint_id, "unit": unit });
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, report) = server.request(Method::POST, "/board/sprint/report", Some(token), Some(&body)).await;
+      assert_eq!(status, 200, "{}", report);
+      serde_json::from_str::<JsonValue>(&report).unwrap()
+    }
+  };
+
+  // Прежние дни восстанавливаются по истории задач: до сегодняшних изменений в спринте были три задачи, и ни одна не была выполнена.
+  let days = report("tasks").await["days"].as_array().unwrap().clone();
+  assert_eq!(days.len(), 4);
+  assert_eq!(days[0], json!({ "date": days[0]["date"], "scope": 3, "remaining": 3, "ideal": 2.57 }));
+  assert_eq!((&days[2]["scope"], &days[2]["remaining"]), (&json!(3), &json!(3)));
+  assert_eq!((&days[3]["scope"], &days[3]["remaining"]), (&json!(2), &json!(1)));
+  let days = report("points").await["days"].as_array().unwrap().clone();
+  assert_eq!((&days[0]["scope"], &days[0]["remaining"], &days[0]["ideal"]), (&json!(16), &json!(16), &json!(13.71)));
+  assert_eq!((&days[3]["scope"], &days[3]["remaining"]), (&json!(8), &json!(5)));
+  let board = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id, "filter": { "sprint_id": sprint_id } }))).await.1;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["cards"][0]["tasks"].as_array().unwrap().len(), 2);
+
+  // С удалением спринта задачи остаются на доске вне спринтов.
+  let (status, _) = server.request(Method::DELETE, "/board/sprint", Some(&token), Some(&json!({
+    "board_id": board_id, "sprint_id": sprint_id
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::POST, "/board/sprint/report", Some(&token), Some(&json!({
+    "board_id": board_id, "sprint_id": sprint_id
+  }))).await;
+  assert_eq!(status, 404);
+  let board = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await.1;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["sprints"], json!([]));
+  for task in board["cards"][0]["tasks"].as_array().unwrap() {
+    assert_eq!(task["sprint_id"], JsonValue::Null, "{}", task);
+  };
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn notes_are_sanitized() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("petr").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [],
+        "notes": "**Важно** <img src=x onerror=alert(1)><script>alert(1)</script> [ссылка](javascript:alert(1))",
+        "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, _) = server.request(Method::PUT, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1,
+    "subtask": {
+      "id": 0, "author": 0, "title": "Подзадача", "executors": [], "exec": false, "tags": [],
+      "notes": "<a href=\"https://example.com\" onclick=\"alert(1)\">сайт</a>", "timelines": no_timelines()
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  let get_board = |render: Option<&str>| {
+    let mut body = json!({ "board_id": board_id });
+    if let Some(render) = render { body["render"] = json!(render); };
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::POST, "/board", Some(token), Some(&body)).await }
+  };
+  let (_, board) = get_board(None).await;
+  let task = &serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"][0];
+  assert_eq!(task["notes"], "**Важно** <img src=\"x\">alert(1) [ссылка]()");
+  assert_eq!(task["subtasks"][0]["notes"], "<a href=\"https://example.com\" rel=\"noopener noreferrer\">сайт</a>");
+  // Патч тоже очищает заметки.
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "notes": "*Готово*<iframe src=\"https://evil.example\"></iframe>"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, board) = get_board(Some("html")).await;
+  assert_eq!(status, 200, "{}", board);
+  let task = &serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"][0];
+  assert_eq!(task["notes"], "<p><em>Готово</em></p>\n");
+  assert_eq!(
+    task["subtasks"][0]["notes"], "<p><a href=\"https://example.com\" rel=\"noopener noreferrer\">сайт</a></p>\n"
+  );
+  let (status, _) = get_board(Some("markdown")).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_card_task_flow() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("bob").await;
+  let user_id = token["id"].as_i64().unwrap();
+  
+  let board_id = server.create_board(&token, "Доска").await;
+  
+  let (status, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  assert_eq!(list[0]["id"], board_id);
+  assert_eq!(list[0]["title"], "Доска");
+  
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  
+  let (status, tag_id) = server.request(Method::PUT, "/board/tag", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "tag": { "id": 0, "title": "Срочно", "text_color": "#ffffff", "background_color": "#ff0000" }
+  }))).await;
+  assert_eq!(status, 200, "{}", tag_id);
+  let tag_id: i64 = tag_id.parse().unwrap();
+  
+  let (status, task_id) = server.request(Method::PUT, "/task", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card_id": card_id,
+    "task": {
+      "id": 0, "author": 0, "title": "Задача", "executors": [user_id, 999999], "exec": false,
+      "subtasks": [], "notes": "", "tags": [tag_id, 999999], "timelines": no_timelines()
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", task_id);
+  let task_id: i64 = task_id.parse().unwrap();
+  
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": task_id, "exec": true, "description": "Подробности"
+  }))).await;
+  assert_eq!(status, 200);
+  
+  let (status, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let task = &board["cards"][0]["tasks"][0];
+  assert_eq!(task["id"], task_id);
+  assert_eq!(task["exec"], true);
+  assert_eq!(task["description"], "Подробности");
+  assert_eq!(task["executors"], json!([user_id]));
+  assert_eq!(task["tags"], json!([tag_id]));
+  // Карточка, тег, задача и патч задачи - четыре изменения доски.
+  assert_eq!(board["revision"], 4);
+  
+  let (status, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id, "filter": { "exec": false } }))
+  ).await;
+  assert_eq!(status, 200);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["cards"][0]["tasks"], json!([]));
+  
+  let (status, _) = server.request(Method::DELETE, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": task_id
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(
+    Method::DELETE, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  assert_eq!(status, 200);
+  let (status, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  assert_eq!(list, "[]");
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn subtasks_propagate_exec_to_task() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("erin").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (_, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  let card_id: i64 = card_id.parse().unwrap();
+  let (_, task_id) = server.request(Method::PUT, "/task", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card_id": card_id,
+    "task": {
+      "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false,
+      "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+    }
+  }))).await;
+  let task_id: i64 = task_id.parse().unwrap();
+  let mut subtask_ids = vec![];
+  for title in ["Первая", "Вторая"] {
+    let (status, subtask_id) = server.request(Method::PUT, "/subtask", Some(&token), Some(&json!({
+      "board_id": board_id, "card_id": card_id, "task_id": task_id,
+      "subtask": {
+        "id": 0, "author": 0, "title": title, "executors": [], "exec": false,
+        "tags": [], "timelines": no_timelines()
+      }
+    }))).await;
+    assert_eq!(status, 200, "{}", subtask_id);
+    subtask_ids.push(subtask_id.parse::<i64>().unwrap());
+  };
+  let task_exec = || async {
+    let (_, board) = server.request(
+      Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+    ).await;
+    let board: JsonValue = serde_json::from_str(&board).unwrap();
+    board["cards"][0]["tasks"][0]["exec"].as_bool().unwrap()
+  };
+  let set_exec = |subtask_id: i64, exec: bool| {
+    let body = json!({
+      "board_id": board_id, "card_id": card_id, "task_id": task_id, "subtask_id": subtask_id, "exec": exec
+    });
+    let server = &server;
+    let token = &token;
+    async move {
+      let (status, _) = server.request(Method::PATCH, "/subtask", Some(token), Some(&body)).await;
+      assert_eq!(status, 200);
+    }
+  };
+  
+  // По умолчанию статус задачи от подзадач не зависит.
+  set_exec(subtask_ids[0], true).await;
+  set_exec(subtask_ids[1], true).await;
+  assert!(!task_exec().await);
+  
+  let (status, _) = server.request(Method::PATCH, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "settings": { "exec_propagation": "complete" }
+  }))).await;
+  assert_eq!(status, 200);
+  set_exec(subtask_ids[1], false).await;
+  assert!(!task_exec().await);
+  set_exec(subtask_ids[1], true).await;
+  assert!(task_exec().await);
+  // В режиме complete снятие отметки задачу не открывает.
+  set_exec(subtask_ids[0], false).await;
+  assert!(task_exec().await);
+  
+  // Настройка задачи важнее настройки доски.
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": task_id, "exec_propagation": "complete_and_reopen"
+  }))).await;
+  assert_eq!(status, 200);
+  set_exec(subtask_ids[1], false).await;
+  assert!(!task_exec().await);
+  set_exec(subtask_ids[0], true).await;
+  set_exec(subtask_ids[1], true).await;
+  assert!(task_exec().await);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn profiles_are_resolved() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("frank").await;
+  let user_id = token["id"].as_i64().unwrap();
+  let (status, _) = server.request(Method::PATCH, "/user/profile", Some(&token), Some(&json!({
+    "display_name": "Фрэнк", "avatar_color": "#123456"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PATCH, "/user/profile", Some(&token), Some(&json!({
+    "avatar_color": "blue"
+  }))).await;
+  assert_eq!(status, 400);
+  
+  let (status, profiles) = server.request(
+    Method::GET, &format!("/users/resolve?ids={},999999", user_id), Some(&token), None
+  ).await;
+  assert_eq!(status, 200, "{}", profiles);
+  let profiles: JsonValue = serde_json::from_str(&profiles).unwrap();
+  assert_eq!(profiles, json!([{ "id": user_id, "display_name": "Фрэнк", "avatar_color": "#123456" }]));
+  let (status, _) = server.request(Method::GET, "/users/resolve?ids=one", Some(&token), None).await;
+  assert_eq!(status, 400);
+  
+  let board_id = server.create_board(&token, "Доска").await;
+  let (_, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert!(board.get("profiles").is_none());
+  let (status, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id, "with_profiles": true }))
+  ).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["profiles"][0]["display_name"], "Фрэнк");
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn concurrent_inserts_get_distinct_ids() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("heidi").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (_, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  let card_id: i64 = card_id.parse().unwrap();
+  let body = json!({
+    "board_id": board_id,
+    "card_id": card_id,
+    "task": {
+      "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false,
+      "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+    }
+  });
+  let results = futures::future::join_all(
+    (0..8).map(|_| server.request(Method::PUT, "/task", Some(&token), Some(&body)))
+  ).await;
+  // Изменения одной доски выполняются по очереди, поэтому применяются все запросы, а выданные идентификаторы не повторяются.
+  assert!(results.iter().all(|(status, _)| *status == 200), "{:?}", results);
+  let mut ids: Vec<i64> = results.iter().map(|(_, id)| id.parse().unwrap()).collect();
+  ids.sort_unstable();
+  ids.dedup();
+  assert_eq!(ids.len(), 8);
+  
+  let (_, board) = server.request(
+    Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+  ).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let mut task_ids: Vec<i64> = board["cards"][0]["tasks"].as_array().unwrap().iter()
+    .map(|t| t["id"].as_i64().unwrap())
+    .collect();
+  task_ids.sort_unstable();
+  assert_eq!(task_ids, ids);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn free_plan_quotas_are_enforced() {
+  let quotas = r#"{"free": {"max_boards": 1, "max_cards_per_board": 1}, "paid": {}}"#;
+  let server = match TestServer::start_with_env(&[("QUOTAS", quotas)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("ivan").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, body) = server.request(Method::PUT, "/board", Some(&token), Some(&json!({
+    "id": 0, "author": 0, "shared_with": [], "cards": [],
+    "header": { "title": "Вторая", "header_background_color": "#ffffff", "header_text_color": "#000000" },
+    "background": { "color": "#eeeeee" }
+  }))).await;
+  assert_eq!(status, 402);
+  let body: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!((&body["quota"], &body["limit"]), (&json!("max_boards"), &json!(1)));
+  
+  let card = json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  });
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&card)).await;
+  assert_eq!(status, 200);
+  let (status, body) = server.request(Method::PUT, "/card", Some(&token), Some(&card)).await;
+  assert_eq!(status, 402);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap()["quota"], "max_cards_per_board");
+  
+  let (status, quota) = server.request(Method::GET, "/user/quota", Some(&token), None).await;
+  assert_eq!(status, 200, "{}", quota);
+  let quota: JsonValue = serde_json::from_str(&quota).unwrap();
+  assert_eq!(quota["billed"], false);
+  assert_eq!(quota["quota"]["max_boards"], 1);
+  assert_eq!(quota["quota"]["max_members"], JsonValue::Null);
+  assert_eq!(quota["usage"]["boards"], 1);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn overdue_tasks_are_flagged() {
+  let server = match TestServer::start_with_env(&[("OVERDUE_SCAN_PERIOD_SECS", "1")]).await {
+    Some(s) => s,
+    None => return,
+  };
+  let token = server.sign_up("kate").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (_, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  let card_id: i64 = card_id.parse().unwrap();
+  let now = chrono::Utc::now().timestamp();
+  let mut task_ids = vec![];
+  for max_time in [now - 60, now + 2] {
+    let (status, task_id) = server.request(Method::PUT, "/task", Some(&token), Some(&json!({
+      "board_id": board_id,
+      "card_id": card_id,
+      "task": {
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "overdue": true,
+        "subtasks": [], "notes": "", "tags": [],
+        "timelines": { "preferred_time": 0, "max_time": max_time, "expected_time": 0 }
+      }
+    }))).await;
+    assert_eq!(status, 200, "{}", task_id);
+    task_ids.push(task_id.parse::<i64>().unwrap());
+  };
+  let overdue = || async {
+    let (_, board) = server.request(
+      Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))
+    ).await;
+    let board: JsonValue = serde_json::from_str(&board).unwrap();
+    board["cards"][0]["tasks"].as_array().unwrap().iter().map(|t| t["overdue"].as_bool().unwrap()).collect::<Vec<_>>()
+  };
+  // Признак, переданный клиентом, пересчитывается сервером при записи.
+  assert_eq!(overdue().await, vec![true, false]);
+  
+  // Срок второй задачи проходит, когда доску никто не меняет.
+  tokio::time::sleep(std::time::Duration::from_secs(4)).await;
+  assert_eq!(overdue().await, vec![true, true]);
+  
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": task_ids[0], "exec": true
+  }))).await;
+  assert_eq!(status, 200);
+  assert_eq!(overdue().await, vec![false, true]);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_is_hidden_from_strangers() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let owner = server.sign_up("carol").await;
+  let stranger = server.sign_up("dave").await;
+  let board_id = server.create_board(&owner, "Личная").await;
+  let (status, _) = server.request(
+    Method::POST, "/board", Some(&stranger), Some(&json!({ "board_id": board_id }))
+  ).await;
+  assert_eq!(status, 401);
+  let (status, _) = server.request(
+    Method::DELETE, "/board", Some(&stranger), Some(&json!({ "board_id": board_id }))
+  ).await;
+  assert_ne!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn errors_carry_request_id() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let (status, body) = server.request_with_headers(
+    Method::GET, "/list", &[("X-Request-Id", "support-42")], Body::empty()
+  ).await;
+  assert_eq!(status, 401);
+  let body: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(body["request_id"], "support-42");
+  assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()));
+  // Небезопасный для журнала идентификатор заменяется сгенерированным.
+  let (_, body) = server.request_with_headers(
+    Method::GET, "/list", &[("X-Request-Id", "bad id\\n")], Body::empty()
+  ).await;
+  let body: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(body["request_id"].as_str().unwrap().len(), 36);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn slow_requests_time_out() {
+  let envs = [("REQUEST_TIMEOUT_SECS", "1"), ("DB_POOL_SIZE", "1")];
+  let server = match TestServer::start_with_env(&envs).await { Some(s) => s, None => return };
+  let token = server.sign_up("rita").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let body = json!({ "board_id": board_id });
+  // Пока таблица досок заблокирована, доску не получить.
+  let lock = server.hold("begin; lock table boards in access exclusive mode;").await;
+  let (status, _) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+  assert_eq!(status, 504);
+  // Прерванный запрос не удерживает единственное соединение пула.
+  drop(lock);
+  let (status, _) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+  assert_eq!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn config_is_read_from_taskboard_env() {
+  let server = match TestServer::start_with_taskboard_env().await { Some(s) => s, None => return };
+  let token = server.sign_up("ivan").await;
+  let (status, _) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(status, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn config_accepts_separate_pg_params() {
+  let cfg = json!({ "pg": { "sslmode": "disable", "application_name": "taskboard-flows" } });
+  let server = match TestServer::start_with_config(cfg).await { Some(s) => s, None => return };
+  server.sign_up("ivan").await;
+  let cli = server.hold("").await;
+  let rows = cli.query("select 1 from pg_stat_activity where application_name = 'taskboard-flows';", &[]).await.unwrap();
+  assert!(!rows.is_empty());
+  drop(cli);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn config_is_reloaded() {
+  let server = match TestServer::start_with_config(json!({})).await { Some(s) => s, None => return };
+  server.sign_up("ivan").await;
+  let wrong = json!({ "login": "ivan", "pass": "wrong-password" });
+  let (status, _) = server.request(Method::GET, "/sign-in", Some(&wrong), None).await;
+  assert_eq!(status, 401);
+  server.rewrite_config(json!({ "sign_in_max_failures": 1 }));
+  let admin = json!({ "key": ADMIN_KEY });
+  let (status, _) = server.request(Method::POST, "/admin/reload-config", Some(&admin), None).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/sign-in", Some(&wrong), None).await;
+  assert_eq!(status, 429);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn db_statements_are_measured_per_route() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("ivan").await;
+  server.create_board(&token, "Доска").await;
+  let (status, _) = server.request(Method::GET, "/admin/db-metrics", Some(&token), None).await;
+  assert_eq!(status, 401);
+  let (status, body) = server.request(Method::GET, "/admin/db-metrics", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let metrics: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(metrics["slow_query_ms"], 500);
+  let routes = metrics["routes"].as_array().unwrap();
+  for route in ["PUT /sign-up", "PUT /board"] {
+    let measured = routes.iter().find(|r| r["route"] == route).unwrap_or_else(|| panic!("Нет замеров {}: {}", route, body));
+    assert!(measured["statements"].as_u64().unwrap() > 0);
+    assert!(measured["p50_ms"].as_f64().unwrap() <= measured["max_ms"].as_f64().unwrap());
+  };
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn boards_are_revalidated() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("vera").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false, "subtasks": [], "tags": [],
+    "notes": "", "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Первая"), task("Вторая")]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let body = json!({ "board_id": board_id });
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+  let mut cards = serde_json::from_str::<JsonValue>(&board).unwrap()["cards"].take();
+  // Повреждения, которые могли оставить старые версии сервера или ручные исправления.
+  cards[0]["tasks"][1]["id"] = json!(1);
+  cards[0]["tasks"][0]["executors"] = json!([999_999]);
+  let cards = cards.to_string().replace('\'', "''");
+  server.sql(&format!("update boards set cards = '{}' where id = {};", cards, board_id)).await;
+  let broken_id = server.create_board(&server.sign_up("gleb").await, "Сломанная доска").await;
+  server.sql(&format!("update boards set cards = '{{\"not\": \"cards\"}}' where id = {};", broken_id)).await;
+  // Настройки удалённой вручную доски.
+  server.sql("insert into user_board_prefs (user_id, board_id, favorite) values (1, 999999, true);").await;
+
+  let admin = json!({ "key": ADMIN_KEY });
+  let (status, report) = server.request(Method::POST, "/admin/revalidate-boards", Some(&admin), None).await;
+  assert_eq!(status, 200, "{}", report);
+  let report: JsonValue = serde_json::from_str(&report).unwrap();
+  assert_eq!(report["checked"], 2);
+  assert_eq!(report["repaired"], json!([board_id]));
+  assert_eq!(report["corrupt"][0]["board_id"], broken_id);
+  assert!(report["corrupt"][0]["reason"].as_str().unwrap().contains("cards"));
+  assert_eq!(report["orphans"], json!([{ "table": "user_board_prefs", "column": "board_id", "rows": 1 }]));
+
+  let (status, board) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+  assert_eq!(status, 200, "{}", board);
+  let tasks = &serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"];
+  assert_eq!((&tasks[0]["id"], &tasks[0]["title"]), (&json!(1), &json!("Первая")));
+  assert_eq!((&tasks[1]["id"], &tasks[1]["title"]), (&json!(3), &json!("Вторая")));
+  assert_eq!(tasks[0]["executors"], json!([]));
+  // Исправленная доска больше не исправляется, а повреждённая остаётся нетронутой.
+  let (_, report) = server.request(Method::POST, "/admin/revalidate-boards", Some(&admin), None).await;
+  let report: JsonValue = serde_json::from_str(&report).unwrap();
+  assert_eq!(report["repaired"], json!([]));
+  assert_eq!(report["corrupt"][0]["board_id"], broken_id);
+  assert_eq!(report["orphans"], json!([]));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn boards_are_fetched_in_bulk() {
+  let quotas = r#"{"free": {"max_boards": 3}, "paid": {}}"#;
+  let server = match TestServer::start_with_env(&[("QUOTAS", quotas)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("nina").await;
+  let first = server.create_board(&token, "Первая").await;
+  let second = server.create_board(&token, "Вторая").await;
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": second,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [],
+        "notes": "", "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200);
+
+  let (status, body) = server.request(
+    Method::POST, "/boards/bulk", Some(&token), Some(&json!({ "board_ids": [second, first, second] }))
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  let boards: JsonValue = serde_json::from_str(&body).unwrap();
+  let titles: Vec<&str> = boards.as_array().unwrap().iter().map(|b| b["title"].as_str().unwrap()).collect();
+  assert_eq!(titles, ["Вторая", "Первая"]);
+  assert!(boards[0].get("summary").is_none());
+
+  let (status, body) = server.request(
+    Method::POST, "/boards/bulk", Some(&token), Some(&json!({ "board_ids": [second], "summary": true }))
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  let boards: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(boards[0]["summary"], json!({ "cards": 1, "tasks": 1, "done": 0, "overdue": 0, "unassigned": 1 }));
+
+  let stranger = server.sign_up("oleg").await;
+  let (status, body) = server.request(
+    Method::POST, "/boards/bulk", Some(&stranger), Some(&json!({ "board_ids": [first] }))
+  ).await;
+  assert_eq!(status, 401);
+  assert!(body.contains(&first.to_string()), "{}", body);
+  let (status, _) = server.request(Method::POST, "/boards/bulk", Some(&token), Some(&json!({ "board_ids": [] }))).await;
+  assert_eq!(status, 400);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn card_edits_are_written_in_place() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("olga").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [task("Первая"), task("Вторая"), task("Третья")],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let cli = server.hold("").await;
+  let body = json!({ "board_id": board_id });
+
+  // Замена значения, удаление и добавление элементов массива записываются изменениями, и карточки в базе данных совпадают с отданными клиенту.
+  let changes = [
+    (Method::PATCH, json!({ "board_id": board_id, "card_id": card_id, "task_id": 1, "title": "Первая'\"", "exec": true })),
+    (Method::DELETE, json!({ "board_id": board_id, "card_id": card_id, "task_id": 2 })),
+    (Method::PUT, json!({ "board_id": board_id, "card_id": card_id, "task": task("Четвёртая") })),
+  ];
+  for (method, change) in changes {
+    let (status, response) = server.request(method, "/task", Some(&token), Some(&change)).await;
+    assert_eq!(status, 200, "{}", response);
+    let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+    let board: JsonValue = serde_json::from_str(&board).unwrap();
+    let row = cli.query_one("select cards::text, jsonb_typeof(cards) from boards where id = $1;", &[&board_id]).await.unwrap();
+    assert_eq!(row.get::<_, &str>(1), "array");
+    assert_eq!(serde_json::from_str::<JsonValue>(row.get(0)).unwrap(), board["cards"]);
+  };
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&body)).await;
+  let titles: Vec<JsonValue> = serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"].as_array().unwrap()
+    .iter().map(|task| task["title"].clone()).collect();
+  assert_eq!(titles, vec![json!("Первая'\""), json!("Третья"), json!("Четвёртая")]);
+  drop(cli);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn task_history_is_recorded() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("zoya").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [],
+        "notes": "", "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let task = json!({ "board_id": board_id, "card_id": card_id, "task_id": 1 });
+  let history = || async {
+    let (status, history) = server.request(Method::POST, "/task/history", Some(&token), Some(&task)).await;
+    assert_eq!(status, 200, "{}", history);
+    serde_json::from_str::<Vec<JsonValue>>(&history).unwrap()
+  };
+  assert!(history().await.is_empty());
+
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "title": "Отчёт", "exec": true
+  }))).await;
+  assert_eq!(status, 200);
+  let mut timelines = no_timelines();
+  timelines["max_time"] = json!(1_900_000_000);
+  let (status, _) = server.request(Method::PATCH, "/task/time", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "timelines": timelines
+  }))).await;
+  assert_eq!(status, 200);
+  let history = history().await;
+  let fields: Vec<&JsonValue> = history.iter().map(|c| &c["field"]).collect();
+  assert_eq!(fields, vec!["timelines", "title", "exec"]);
+  assert_eq!((&history[1]["old"], &history[1]["new"]), (&json!("Задача"), &json!("Отчёт")));
+  assert_eq!((&history[2]["old"], &history[2]["new"]), (&json!(false), &json!(true)));
+  assert_eq!(history[0]["new"]["max_time"], 1_900_000_000);
+  assert!(history.iter().all(|c| c["actor"] == history[0]["actor"] && c["at"].as_i64().unwrap() > 0));
+
+  // Хранятся только последние изменения.
+  for i in 0..100 {
+    let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+      "board_id": board_id, "card_id": card_id, "task_id": 1, "title": format!("Отчёт {}", i)
+    }))).await;
+    assert_eq!(status, 200);
+  };
+  let (_, history) = server.request(Method::POST, "/task/history", Some(&token), Some(&task)).await;
+  let history: Vec<JsonValue> = serde_json::from_str(&history).unwrap();
+  assert_eq!(history.len(), 100);
+  assert_eq!(history[0]["new"], "Отчёт 99");
+  assert_eq!(history[99]["old"], "Отчёт");
+
+  // История удаляется вместе с задачей.
+  let (status, _) = server.request(Method::DELETE, "/task", Some(&token), Some(&task)).await;
+  assert_eq!(status, 200);
+  server.sql(&format!(
+    "do $$ begin if exists (select 1 from task_history where board_id = {}) then raise 'history left'; end if; end $$;", board_id
+  )).await;
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn concurrent_task_edits_are_merged() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("gleb").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [],
+        "notes": "", "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let patch = |fields: JsonValue| {
+    let mut body = json!({ "board_id": board_id, "card_id": card_id, "task_id": 1 });
+    body.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::PATCH, "/task", Some(token), Some(&body)).await }
+  };
+
+  // Первый редактор меняет название, второй над той же ревизией меняет другие поля - изменения сливаются.
+  let (status, _) = patch(json!({ "title": "Отчёт" })).await;
+  assert_eq!(status, 200);
+  let (status, _) = patch(json!({ "notes": "Заметка", "exec": true, "base_revision": 1 })).await;
+  assert_eq!(status, 200);
+
+  // Другое значение того же поля - конфликт, то же значение - не конфликт.
+  let (status, conflicts) = patch(json!({ "title": "Сводка", "description": "Описание", "base_revision": 1 })).await;
+  assert_eq!(status, 409, "{}", conflicts);
+  let conflicts: JsonValue = serde_json::from_str(&conflicts).unwrap();
+  let conflict = &conflicts["conflicts"][0];
+  assert_eq!((&conflict["field"], &conflict["server_value"], &conflict["client_value"]), (&json!("title"), &json!("Отчёт"), &json!("Сводка")));
+  assert_eq!(conflict["stamp"]["revision"], 2);
+  assert_eq!(conflicts["conflicts"].as_array().unwrap().len(), 1);
+  let (status, _) = patch(json!({ "title": "Отчёт", "description": "Описание", "base_revision": 1 })).await;
+  assert_eq!(status, 200);
+
+  // Клиент, видевший изменение, может изменить поле, а без базовой ревизии изменение записывается поверх любых других.
+  let (status, _) = patch(json!({ "title": "Сводка", "base_revision": 2 })).await;
+  assert_eq!(status, 200);
+  let (status, _) = patch(json!({ "exec": false })).await;
+  assert_eq!(status, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let task = &board["cards"][0]["tasks"][0];
+  assert_eq!((&task["title"], &task["notes"], &task["description"], &task["exec"]), (&json!("Сводка"), &json!("Заметка"), &json!("Описание"), &json!(false)));
+  assert_eq!(task["field_stamps"]["title"]["revision"], 5);
+  assert_eq!(task["field_stamps"]["notes"]["revision"], 3);
+  assert_eq!(task["field_stamps"]["exec"]["revision"], 6);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn deletions_are_undone() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("lev").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false, "subtasks": [], "tags": [],
+    "notes": "", "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Макет"), task("Вёрстка")]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, _) = server.request(Method::PUT, "/task/dependency", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 2, "depends_on": { "card_id": card_id, "task_id": 1 }
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PUT, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1,
+    "subtask": {
+      "id": 0, "author": 0, "title": "Цвета", "executors": [], "exec": false, "tags": [], "notes": "",
+      "timelines": no_timelines()
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  let board = || async {
+    let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+    serde_json::from_str::<JsonValue>(&board).unwrap()
+  };
+  let undo = || async { server.request(Method::POST, "/board/undo", Some(&token), Some(&json!({ "board_id": board_id }))).await };
+
+  let (status, _) = server.request(Method::DELETE, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "subtask_id": 1
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::DELETE, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1
+  }))).await;
+  assert_eq!(status, 200);
+  let tasks = board().await["cards"][0]["tasks"].take();
+  assert_eq!((tasks.as_array().unwrap().len(), &tasks[0]["depends_on"]), (1, &json!([])));
+
+  // Удаления отменяются в обратном порядке вместе с зависимостями других задач.
+  let (status, event) = undo().await;
+  assert_eq!(status, 200, "{}", event);
+  assert_eq!(
+    serde_json::from_str::<JsonValue>(&event).unwrap(),
+    json!({ "type": "task_restored", "card_id": card_id, "task_id": 1 })
+  );
+  let tasks = board().await["cards"][0]["tasks"].take();
+  assert_eq!((&tasks[0]["title"], &tasks[0]["subtasks"]), (&json!("Макет"), &json!([])));
+  assert_eq!(tasks[1]["depends_on"], json!([{ "card_id": card_id, "task_id": 1 }]));
+  let (status, _) = undo().await;
+  assert_eq!(status, 200);
+  let tasks = board().await["cards"][0]["tasks"].take();
+  assert_eq!(tasks[0]["subtasks"][0]["title"], "Цвета");
+  let (status, _) = undo().await;
+  assert_eq!(status, 404);
+
+  let (status, _) = server.request(Method::DELETE, "/card", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, event) = undo().await;
+  assert_eq!(status, 200, "{}", event);
+  let cards = board().await["cards"].take();
+  assert_eq!(cards[0]["tasks"][1]["depends_on"], json!([{ "card_id": card_id, "task_id": 1 }]));
+  // Восстановленной карточке можно добавлять задачи.
+  let (status, task_id) = server.request(Method::PUT, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task": task("Релиз")
+  }))).await;
+  assert_eq!((status, task_id.as_str()), (200, "3"));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn notifications_are_delivered() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let member = server.sign_up("polina").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  // Открыть доступ к доске через API нельзя.
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let mut timelines = no_timelines();
+  timelines["max_time"] = json!(chrono::Utc::now().timestamp() + 3600);
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [member["id"]], "exec": false, "subtasks": [], "tags": [],
+        "notes": "Посмотри, @polina. Копия: @oleg, @nobody, mail@polina", "timelines": timelines
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let list = |token: JsonValue, query: &'static str| {
+    let server = &server;
+    async move {
+      let (status, body) = server.request(Method::GET, &format!("/user/notifications{}", query), Some(&token), None).await;
+      assert_eq!(status, 200, "{}", body);
+      serde_json::from_str::<JsonValue>(&body).unwrap()
+    }
+  };
+  // Уведомления создаются подписчиком канала событий, поэтому появляются не сразу.
+  let mut notifications = json!(null);
+  for _ in 0..50 {
+    notifications = list(member.clone(), "").await;
+    if notifications["unread"] == 3 { break; };
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  };
+  assert_eq!(notifications["unread"], 3, "{}", notifications);
+  let mut kinds: Vec<&str> = notifications["notifications"].as_array().unwrap().iter()
+    .map(|n| n["kind"].as_str().unwrap()).collect();
+  kinds.sort();
+  assert_eq!(kinds, vec!["assigned", "due_soon", "mentioned"]);
+  assert!(notifications["notifications"].as_array().unwrap().iter()
+    .all(|n| n["board_id"] == board_id && n["card_id"] == card_id && n["task_id"] == 1 && !n["read"].as_bool().unwrap()));
+  // Собственные действия и упоминания не создают уведомлений.
+  assert_eq!(list(token.clone(), "").await["unread"], 0);
+
+  let first = notifications["notifications"][0]["id"].clone();
+  let (status, _) = server.request(Method::PATCH, "/user/notifications/read", Some(&member), Some(&json!({ "ids": [first] }))).await;
+  assert_eq!(status, 200);
+  let unread = list(member.clone(), "?unread=true").await;
+  assert_eq!(unread["unread"], 2);
+  assert_eq!(unread["notifications"].as_array().unwrap().len(), 2);
+  let page = list(member.clone(), "?limit=1").await;
+  assert_eq!(page["notifications"].as_array().unwrap().len(), 1);
+  assert_eq!(page["notifications"][0]["id"], first);
+  let (status, _) = server.request(Method::PATCH, "/user/notifications/read", Some(&member), Some(&json!({}))).await;
+  assert_eq!(status, 200);
+  assert_eq!(list(member.clone(), "").await["unread"], 0);
+  let (status, _) = server.request(Method::GET, "/user/notifications?limit=0", Some(&member), None).await;
+  assert_eq!(status, 400);
+
+  // Повторное сохранение задачи не повторяет уведомлений, а отключённая доска не присылает новых.
+  let (status, _) = server.request(Method::PATCH, "/user/board-prefs", Some(&member), Some(&json!({
+    "board_id": board_id, "muted": true
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PUT, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1,
+    "subtask": {
+      "id": 0, "author": 0, "title": "Подзадача", "executors": [member["id"]], "exec": false, "notes": "@polina", "tags": [],
+      "timelines": no_timelines()
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+  assert_eq!(list(member.clone(), "").await["unread"], 0);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_views_are_applied() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("roman").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str, notes: &str, executors: JsonValue| json!({
+    "id": 0, "author": 0, "title": title, "executors": executors, "exec": false, "subtasks": [], "tags": [],
+    "notes": notes, "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [
+        task("Сверка", "Квартальный ОТЧЁТ", json!([token["id"]])),
+        task("Архив", "", json!([token["id"]])),
+        task("Бюджет", "Отчёт для совета", json!([]))
+      ]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let titles = |body: &str| {
+    let board: JsonValue = serde_json::from_str(body).unwrap();
+    board["cards"][0]["tasks"].as_array().unwrap().iter().map(|t| t["title"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+  };
+  let (status, view_id) = server.request(Method::PUT, "/board/view", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "view": { "name": "Отчёты", "filter": { "query": "отчёт" }, "sort": "title" }
+  }))).await;
+  assert_eq!(status, 200, "{}", view_id);
+  let view_id: i64 = view_id.parse().unwrap();
+  let (status, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "view_id": view_id
+  }))).await;
+  assert_eq!(status, 200, "{}", board);
+  assert_eq!(titles(&board), vec!["Бюджет", "Сверка"]);
+
+  // Представление с тем же названием заменяется.
+  let (status, replaced_id) = server.request(Method::PUT, "/board/view", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "view": { "name": "Отчёты", "filter": { "executors": [token["id"]] }, "sort": "title" }
+  }))).await;
+  assert_eq!(status, 200);
+  assert_eq!(replaced_id, view_id.to_string());
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "view_id": view_id
+  }))).await;
+  assert_eq!(titles(&board), vec!["Архив", "Сверка"]);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "sort": "title"
+  }))).await;
+  assert_eq!(titles(&board), vec!["Архив", "Бюджет", "Сверка"]);
+
+  let (status, views) = server.request(Method::GET, "/board/views", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200);
+  let views: JsonValue = serde_json::from_str(&views).unwrap();
+  assert_eq!(views.as_array().unwrap().len(), 1);
+  assert_eq!(views[0]["name"], "Отчёты");
+  assert_eq!(views[0]["sort"], "title");
+
+  // Представления видны только своему автору.
+  let other = server.sign_up("sofia").await;
+  let other_board = server.create_board(&other, "Чужая").await;
+  let (status, _) = server.request(Method::POST, "/board", Some(&other), Some(&json!({
+    "board_id": other_board, "view_id": view_id
+  }))).await;
+  assert_eq!(status, 404);
+
+  let (status, _) = server.request(Method::POST, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "view_id": view_id, "filter": {}
+  }))).await;
+  assert_eq!(status, 400);
+  let (status, _) = server.request(Method::PUT, "/board/view", Some(&token), Some(&json!({
+    "board_id": board_id, "view": { "name": " ", "sort": "title" }
+  }))).await;
+  assert_eq!(status, 400);
+  let (status, _) = server.request(Method::DELETE, "/board/view", Some(&token), Some(&json!({
+    "board_id": board_id, "view_id": view_id
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::POST, "/board", Some(&token), Some(&json!({
+    "board_id": board_id, "view_id": view_id
+  }))).await;
+  assert_eq!(status, 404);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn task_priorities_are_filtered_and_sorted() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("ulyana").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let task = |title: &str| json!({
+    "id": 0, "author": 0, "title": title, "executors": [], "exec": false, "tags": [], "notes": "",
+    "timelines": no_timelines(),
+    "subtasks": [{
+      "id": 0, "author": 0, "title": "Подзадача", "executors": [], "exec": false, "tags": [], "timelines": no_timelines()
+    }]
+  });
+  let mut urgent = task("Авария");
+  urgent["priority"] = json!("urgent");
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка",
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Отчёт"), task("Архив"), urgent]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 2, "priority": "low"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PATCH, "/subtask", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "subtask_id": 1, "priority": "high"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "priority": "critical"
+  }))).await;
+  assert_eq!(status, 400);
+
+  let board = |extra: JsonValue| {
+    let mut body = json!({ "board_id": board_id });
+    body.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, board) = server.request(Method::POST, "/board", Some(token), Some(&body)).await;
+      assert_eq!(status, 200, "{}", board);
+      serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"].as_array().unwrap().clone()
+    }
+  };
+  let tasks = board(json!({ "sort": "priority" })).await;
+  let priorities: Vec<(&JsonValue, &JsonValue)> = tasks.iter().map(|t| (&t["title"], &t["priority"])).collect();
+  assert_eq!(priorities, vec![(&json!("Авария"), &json!("urgent")), (&json!("Отчёт"), &json!("normal")), (&json!("Архив"), &json!("low"))]);
+  assert_eq!(tasks[1]["subtasks"][0]["priority"], "high");
+  let tasks = board(json!({ "filter": { "priorities": ["urgent", "low"] } })).await;
+  let titles: Vec<&JsonValue> = tasks.iter().map(|t| &t["title"]).collect();
+  assert_eq!(titles, vec!["Архив", "Авария"]);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn tasks_are_imported_from_csv() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("taras").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, tag_id) = server.request(Method::PUT, "/board/tag", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "tag": { "id": 0, "title": "Срочно", "text_color": "#ffffff", "background_color": "#ff0000" }
+  }))).await;
+  assert_eq!(status, 200, "{}", tag_id);
+  let tag_id: i64 = tag_id.parse().unwrap();
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Бэклог", "wip_limit": 3,
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{
+        "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [],
+        "notes": "", "timelines": no_timelines()
+      }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let import = |csv: &'static str| {
+    let (server, token) = (&server, &token);
+    async move {
+      server.request(Method::PUT, "/card/import/csv", Some(token), Some(&json!({
+        "board_id": board_id, "card_id": card_id, "csv": csv
+      }))).await
+    }
+  };
+
+  // Ни одна задача не создаётся, если хотя бы одна строка не прошла проверку.
+  let (status, errors) = import("title,executors,deadline\nОтчёт,@nobody,\n,,\n\nСмета,,31.02.2024\nПлан,taras,2024-05-01").await;
+  assert_eq!(status, 400, "{}", errors);
+  let errors: JsonValue = serde_json::from_str(&errors).unwrap();
+  let rows: Vec<i64> = errors["rows"].as_array().unwrap().iter().map(|e| e["row"].as_i64().unwrap()).collect();
+  assert_eq!(rows, vec![2, 5]);
+  let (status, _) = import("title;owner\nОтчёт;taras").await;
+  assert_eq!(status, 400);
+
+  let (status, ids) = import(
+    "\u{feff}Title;Executors;Deadline;Tags\r\n\"Отчёт; квартал\";@Taras;2024-05-01;срочно\r\n\"Смета \"\"Б\"\"\";;2024-05-01T12:00:00+03:00;\r\n"
+  ).await;
+  assert_eq!(status, 200, "{}", ids);
+  assert_eq!(ids, "[2,3]");
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let tasks = &board["cards"][0]["tasks"];
+  assert_eq!(tasks[1]["title"], "Отчёт; квартал");
+  assert_eq!(tasks[1]["executors"], json!([token["id"]]));
+  assert_eq!(tasks[1]["tags"], json!([tag_id]));
+  assert_eq!(tasks[1]["timelines"]["max_time"], 1714607999);
+  assert_eq!(tasks[2]["title"], "Смета \"Б\"");
+  assert_eq!(tasks[2]["timelines"]["max_time"], 1714554000);
+  assert!(tasks[2]["created_at"].as_i64().unwrap() > 0);
+
+  // Задачи должны поместиться в ограничение карточки целиком.
+  let (status, _) = import("title\nПервая\nВторая").await;
+  assert_eq!(status, 409);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn executors_are_assigned() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let member = server.sign_up("polina").await;
+  let outsider = server.sign_up("roman").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{ "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [], "notes": "", "timelines": no_timelines() }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let assign = |executors: JsonValue| {
+    let (server, token) = (&server, token.clone());
+    async move {
+      server.request(Method::PATCH, "/task/executors", Some(&token), Some(&json!({
+        "board_id": board_id, "card_id": card_id, "task_id": 1, "executors": executors
+      }))).await
+    }
+  };
+
+  // Пользователя без доступа к доске нельзя назначить исполнителем, и задача не изменяется.
+  let (status, error) = assign(json!([member["id"], outsider["id"]])).await;
+  assert_eq!(status, 400, "{}", error);
+  assert!(error.contains(&outsider["id"].to_string()));
+  let (status, assignments) = assign(json!([member["id"], member["id"]])).await;
+  assert_eq!(status, 200, "{}", assignments);
+  let assignments: JsonValue = serde_json::from_str(&assignments).unwrap();
+  assert_eq!((assignments.as_array().unwrap().len(), &assignments[0]["executor"]), (1, &member["id"]));
+  assert_eq!(assignments[0]["assigned_by"], token["id"]);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  let task = &board["cards"][0]["tasks"][0];
+  assert_eq!((&task["executors"], &task["assignments"]), (&json!([member["id"]]), &assignments));
+
+  let mut notifications = json!(null);
+  for _ in 0..50 {
+    let (_, body) = server.request(Method::GET, "/user/notifications", Some(&member), None).await;
+    notifications = serde_json::from_str(&body).unwrap();
+    if notifications["unread"] == 1 { break; };
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  };
+  assert_eq!(notifications["notifications"][0]["kind"], "assigned", "{}", notifications);
+  assert_eq!(notifications["notifications"][0]["actor"], token["id"]);
+
+  // Повторное назначение не меняет записи, а снятый исполнитель её теряет.
+  let (status, again) = assign(json!([member["id"]])).await;
+  assert_eq!((status, serde_json::from_str::<JsonValue>(&again).unwrap()), (200, assignments));
+  let (status, cleared) = assign(json!([])).await;
+  assert_eq!((status, cleared.as_str()), (200, "[]"));
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert!(board["cards"][0]["tasks"][0].get("assignments").is_none());
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_is_transferred() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let member = server.sign_up("polina").await;
+  let outsider = server.sign_up("roman").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let transfer = |token: &JsonValue, author: &JsonValue| {
+    let (server, token, author) = (&server, token.clone(), author.clone());
+    async move {
+      server.request(Method::POST, "/board/transfer", Some(&token), Some(&json!({ "board_id": board_id, "author": author["id"] }))).await.0
+    }
+  };
+  assert_eq!(transfer(&member, &member).await, 403);
+  assert_eq!(transfer(&token, &outsider).await, 400);
+  assert_eq!(transfer(&token, &member).await, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!((&board["author"], &board["revision"]), (&member["id"], &json!(1)));
+
+  // Управлять доской теперь может только новый автор.
+  let (status, _) = server.request(Method::PATCH, "/board", Some(&token), Some(&json!({ "board_id": board_id, "title": "Моя" }))).await;
+  assert_eq!(status, 500);
+  let (status, _) = server.request(Method::PATCH, "/board", Some(&member), Some(&json!({ "board_id": board_id, "title": "Моя" }))).await;
+  assert_eq!(status, 200);
+  let mut notifications = json!(null);
+  for _ in 0..50 {
+    let (_, body) = server.request(Method::GET, "/user/notifications", Some(&member), None).await;
+    notifications = serde_json::from_str(&body).unwrap();
+    if notifications["unread"] == 1 { break; };
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  };
+  assert_eq!(notifications["notifications"][0]["kind"], "board_transferred", "{}", notifications);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn member_leaves_board() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let member = server.sign_up("polina").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let task = json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": [token["id"], member["id"]], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, _) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [task],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200);
+  let leave = |token: &JsonValue| {
+    let (server, token) = (&server, token.clone());
+    async move { server.request(Method::DELETE, "/board/membership", Some(&token), Some(&json!({ "board_id": board_id }))).await.0 }
+  };
+  assert_eq!(leave(&token).await, 400);
+  assert_eq!(leave(&member).await, 200);
+  let (status, _) = server.request(Method::POST, "/board", Some(&member), Some(&json!({ "board_id": board_id }))).await;
+  assert_ne!(status, 200);
+  let (_, list) = server.request(Method::GET, "/list", Some(&member), None).await;
+  assert_eq!(serde_json::from_str::<JsonValue>(&list).unwrap(), json!([]));
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["shared_with"], json!([token["id"]]));
+  assert_eq!(board["cards"][0]["tasks"][0]["executors"], json!([token["id"]]));
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn watchers_are_notified() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let member = server.sign_up("polina").await;
+  let outsider = server.sign_up("roman").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    token["id"], member["id"], board_id
+  )).await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{ "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [], "notes": "", "timelines": no_timelines() }]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let watch = |method: Method, token: JsonValue, path: &'static str, task_id: i64| {
+    let server = &server;
+    async move {
+      server.request(method, path, Some(&token), Some(&json!({ "board_id": board_id, "card_id": card_id, "task_id": task_id }))).await.0
+    }
+  };
+  assert_eq!(watch(Method::PUT, outsider.clone(), "/task/watch", 1).await, 401);
+  assert_eq!(watch(Method::PUT, member.clone(), "/task/watch", 2).await, 404);
+  assert_eq!(watch(Method::PUT, member.clone(), "/task/watch", 1).await, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!((&board["watchers"], &board["cards"][0]["tasks"][0]["watchers"]), (&json!([]), &json!([member["id"]])));
+
+  let wait_for = |unread: i64| {
+    let (server, member) = (&server, member.clone());
+    async move {
+      let mut notifications = json!(null);
+      for _ in 0..50 {
+        let (_, body) = server.request(Method::GET, "/user/notifications", Some(&member), None).await;
+        notifications = serde_json::from_str(&body).unwrap();
+        if notifications["unread"] == unread { break; };
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+      };
+      assert_eq!(notifications["unread"], unread, "{}", notifications);
+      notifications
+    }
+  };
+  // Наблюдатель задачи узнаёт об изменениях задачи, но не об остальных изменениях доски.
+  let (status, _) = server.request(Method::PATCH, "/card", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "title": "Переименованная карточка"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": 1, "title": "Переименованная задача"
+  }))).await;
+  assert_eq!(status, 200);
+  let notifications = wait_for(1).await;
+  let notification = &notifications["notifications"][0];
+  assert_eq!((&notification["kind"], &notification["task_id"], &notification["actor"]), (&json!("task_changed"), &json!(1), &token["id"]));
+
+  // Наблюдатель доски узнаёт обо всех её изменениях, а свои изменения и отписка от задачи уведомлений не создают.
+  assert_eq!(watch(Method::PUT, member.clone(), "/board/watch", 1).await, 200);
+  assert_eq!(watch(Method::DELETE, member.clone(), "/task/watch", 1).await, 200);
+  let (status, _) = server.request(Method::PATCH, "/card", Some(&member), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "title": "Карточка"
+  }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PATCH, "/card", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "title": "Карточка автора"
+  }))).await;
+  assert_eq!(status, 200);
+  let notifications = wait_for(2).await;
+  let notification = &notifications["notifications"][0];
+  assert_eq!((&notification["kind"], &notification["card_id"]), (&json!("board_changed"), &JsonValue::Null));
+  assert_eq!(watch(Method::DELETE, member.clone(), "/board/watch", 1).await, 200);
+  let (_, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert!(board["watchers"] == json!([]) && board["cards"][0]["tasks"][0].get("watchers").is_none());
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn rules_act_on_tasks() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("oleg").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let mut card_ids = vec![];
+  for (title, tasks) in [("В работе", json!([{ "title": "Задача", "executors": [], "exec": false, "subtasks": [], "tags": [], "notes": "", "timelines": no_timelines() }])), ("Готово", json!([]))] {
+    let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+      "board_id": board_id,
+      "card": { "title": title, "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff", "tasks": tasks }
+    }))).await;
+    assert_eq!(status, 200, "{}", card_id);
+    card_ids.push(card_id.parse::<i64>().unwrap());
+  };
+  let mut tag_ids = vec![];
+  for i in 0..8 {
+    let (status, tag_id) = server.request(Method::PUT, "/board/tag", Some(&token), Some(&json!({
+      "board_id": board_id, "tag": { "id": 0, "title": format!("Тег {}", i), "text_color": "#000000", "background_color": "#ffffff" }
+    }))).await;
+    assert_eq!(status, 200, "{}", tag_id);
+    tag_ids.push(tag_id.parse::<i64>().unwrap());
+  };
+  // Выполненная задача переносится в карточку «Готово», а каждый тег, кроме первого и последнего, прикрепляет следующий.
+  let mut rules = vec![json!({
+    "trigger": { "type": "task_completed" }, "card_id": card_ids[0],
+    "actions": [{ "type": "move_to_card", "card_id": card_ids[1] }, { "type": "assign", "user_id": token["id"] }, { "type": "add_tag", "tag_id": tag_ids[0] }]
+  })];
+  rules.extend(tag_ids[1..].windows(2).map(|pair| json!({
+    "trigger": { "type": "tag_added", "tag_id": pair[0] }, "actions": [{ "type": "add_tag", "tag_id": pair[1] }]
+  })));
+  let settings = |rules: JsonValue| json!({ "board_id": board_id, "settings": { "exec_propagation": "off", "rules": rules } });
+  let (status, _) = server.request(Method::PATCH, "/board", Some(&token), Some(&settings(json!([{
+    "trigger": { "type": "task_completed" }, "actions": [{ "type": "move_to_card", "card_id": card_ids[1] + 1 }]
+  }])))).await;
+  assert_eq!(status, 400);
+  let (status, body) = server.request(Method::PATCH, "/board", Some(&token), Some(&settings(json!(rules)))).await;
+  assert_eq!(status, 200, "{}", body);
+
+  let wait_for = |ready: fn(&JsonValue) -> bool| {
+    let (server, token) = (&server, token.clone());
+    async move {
+      let mut board = json!(null);
+      for _ in 0..50 {
+        let (_, body) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+        board = serde_json::from_str(&body).unwrap();
+        if ready(&board) { break; };
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+      };
+      assert!(ready(&board), "{}", board);
+      board
+    }
+  };
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_ids[0], "task_id": 1, "exec": true
+  }))).await;
+  assert_eq!(status, 200);
+  let board = wait_for(|board| board["cards"][1]["tasks"].as_array().is_some_and(|tasks| !tasks.is_empty())).await;
+  let task = &board["cards"][1]["tasks"][0];
+  assert_eq!(board["cards"][0]["tasks"], json!([]));
+  assert_eq!((&task["id"], &task["executors"], &task["tags"]), (&json!(1), &json!([token["id"]]), &json!([tag_ids[0]])));
+
+  // Цепочка правил, запускающих друг друга, обрывается: последний тег не прикрепляется.
+  let (status, _) = server.request(Method::PUT, "/tag", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_ids[1], "task_id": 1, "tag_id": tag_ids[1]
+  }))).await;
+  assert_eq!(status, 200);
+  wait_for(|board| board["cards"][1]["tasks"][0]["tags"].as_array().unwrap().len() == 7).await;
+  tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+  let (_, body) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(board["cards"][1]["tasks"][0]["tags"], json!(tag_ids[..7]));
+  server.stop().await;
+}
@@ -0,0 +1,174 @@
+//! Синхронизация задач доски с задачами репозитория GitHub.
+
+mod test_support;
+
+use crypto::{hmac::Hmac, mac::Mac, sha2::Sha256};
+use hyper::{Body, Method, Request, Response, Server, body::to_bytes, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value as JsonValue};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use test_support::TestServer;
+
+const SECRET_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const TOKEN: &str = "ghp_test";
+
+/// Задачи репозитория `acme/tracker` и изменения их статуса, полученные от сервера.
+#[derive(Default)]
+struct Repo {
+  issues: Vec<JsonValue>,
+  patches: Vec<(String, JsonValue)>,
+}
+
+fn issue(number: i64, title: &str, state: &str) -> JsonValue {
+  json!({
+    "number": number,
+    "title": title,
+    "body": format!("Описание {}", number),
+    "state": state,
+    "html_url": format!("https://github.com/acme/tracker/issues/{}", number),
+    "updated_at": "2024-05-01T12:00:00Z",
+  })
+}
+
+/// Отвечает на запросы сервера так же, как GitHub REST API, но знает только репозиторий `acme/tracker`.
+async fn github(repo: Arc<Mutex<Repo>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let authorized = req.headers().get("Authorization").and_then(|h| h.to_str().ok()) == Some(&format!("Bearer {}", TOKEN));
+  let (status, res) = match (authorized, req.method().clone(), req.uri().path().to_string()) {
+    (false, _, _) => (401, json!({ "message": "Bad credentials" })),
+    (_, Method::GET, path) if path == "/repos/acme/tracker" => (200, json!({ "full_name": "acme/tracker" })),
+    (_, Method::GET, path) if path == "/repos/acme/tracker/issues" => (200, JsonValue::from(repo.lock().unwrap().issues.clone())),
+    (_, Method::PATCH, path) if path.starts_with("/repos/acme/tracker/issues/") => {
+      let body: JsonValue = serde_json::from_slice(&to_bytes(req.into_body()).await.unwrap()).unwrap();
+      repo.lock().unwrap().patches.push((path, body));
+      (200, json!({}))
+    },
+    _ => (404, json!({ "message": "Not Found" })),
+  };
+  Ok(Response::builder().status(status).body(Body::from(res.to_string())).unwrap())
+}
+
+/// Запускает GitHub и возвращает его адрес.
+fn start_github(repo: Arc<Mutex<Repo>>) -> SocketAddr {
+  let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+    let repo = repo.clone();
+    async move { Ok::<_, Infallible>(service_fn(move |req| github(repo.clone(), req))) }
+  }));
+  let addr = server.local_addr();
+  tokio::spawn(server);
+  addr
+}
+
+/// Подписывает вебхук так же, как это делает GitHub.
+fn signature(secret: &str, body: &str) -> String {
+  let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+  mac.input(body.as_bytes());
+  let signature: String = mac.result().code().iter().map(|b| format!("{:02x}", b)).collect();
+  format!("sha256={}", signature)
+}
+
+#[tokio::test]
+async fn issues_are_synced_both_ways() {
+  let repo = Arc::new(Mutex::new(Repo::default()));
+  repo.lock().unwrap().issues = vec![
+    issue(1, "Падает вход", "open"),
+    issue(2, "Старая ошибка", "closed"),
+    json!({ "number": 3, "title": "Запрос на слияние", "state": "open", "pull_request": {} }),
+  ];
+  let addr = start_github(repo.clone());
+  let cfg = json!({ "secret_key": SECRET_KEY, "api_url": format!("http://{}", addr), "sync_period_secs": 3600 }).to_string();
+  let server = match TestServer::start_with_env(&[("GITHUB", &cfg)]).await { Some(s) => s, None => return };
+  let token = server.sign_up("gleb").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "id": 0, "author": 0, "title": "Задачи GitHub", "tasks": [],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let link = |repo: &'static str, github_token: &'static str| {
+    let (server, token) = (&server, &token);
+    async move {
+      server.request(Method::PUT, "/board/github", Some(token), Some(&json!({
+        "board_id": board_id, "card_id": card_id, "repo": repo, "token": github_token
+      }))).await
+    }
+  };
+  let get_tasks = || {
+    let (server, token) = (&server, &token);
+    async move {
+      let (_, board) = server.request(Method::POST, "/board", Some(token), Some(&json!({ "board_id": board_id }))).await;
+      serde_json::from_str::<JsonValue>(&board).unwrap()["cards"][0]["tasks"].clone()
+    }
+  };
+
+  let (status, _) = link("acme", TOKEN).await;
+  assert_eq!(status, 400);
+  let (status, _) = link("acme/tracker", "ghp_wrong").await;
+  assert_eq!(status, 502);
+  let (status, body) = link("acme/tracker", TOKEN).await;
+  assert_eq!(status, 200, "{}", body);
+  let webhook_secret = serde_json::from_str::<JsonValue>(&body).unwrap()["webhook_secret"].as_str().unwrap().to_string();
+
+  // Переносятся только открытые задачи, запросы на слияние пропускаются, а повторная синхронизация не дублирует задачи.
+  let (status, changed) = server.request(Method::POST, "/board/github/sync", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!((status, changed.as_str()), (200, "1"));
+  let (_, changed) = server.request(Method::POST, "/board/github/sync", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(changed, "0");
+  let tasks = get_tasks().await;
+  assert_eq!(tasks.as_array().unwrap().len(), 1);
+  assert_eq!(tasks[0]["title"], "Падает вход");
+  assert_eq!(tasks[0]["description"], "Описание 1");
+  assert_eq!(tasks[0]["github_issue"]["url"], "https://github.com/acme/tracker/issues/1");
+  let task_id = tasks[0]["id"].as_i64().unwrap();
+
+  // Вебхуки закрывают и создают задачи доски.
+  let webhook = |event: JsonValue, secret: &str| {
+    let body = event.to_string();
+    let signature = signature(secret, &body);
+    let server = &server;
+    async move {
+      server.request_with_headers(
+        Method::POST, &format!("/integrations/github/webhook?board_id={}", board_id),
+        &[("X-GitHub-Event", "issues"), ("X-Hub-Signature-256", &signature)], Body::from(body)
+      ).await.0
+    }
+  };
+  let closed = json!({
+    "action": "closed", "repository": { "full_name": "acme/tracker" }, "issue": issue(1, "Падает вход", "closed")
+  });
+  assert_eq!(webhook(closed.clone(), "forged").await, 400);
+  let oversized = json!({ "action": "closed", "padding": "x".repeat(8 * 1024 * 1024) });
+  assert_eq!(webhook(oversized, &webhook_secret).await, 413);
+  assert_eq!(webhook(closed, &webhook_secret).await, 200);
+  let opened = json!({
+    "action": "opened", "repository": { "full_name": "acme/tracker" }, "issue": issue(4, "Новая ошибка", "open")
+  });
+  assert_eq!(webhook(opened, &webhook_secret).await, 200);
+  let tasks = get_tasks().await;
+  assert_eq!(tasks[0]["exec"], true);
+  assert_eq!(tasks[1]["title"], "Новая ошибка");
+
+  // Задача, снова открытая на доске, открывается и на GitHub.
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&token), Some(&json!({
+    "board_id": board_id, "card_id": card_id, "task_id": task_id, "exec": false
+  }))).await;
+  assert_eq!(status, 200);
+  let mut patches = vec![];
+  for _ in 0..50 {
+    patches = repo.lock().unwrap().patches.clone();
+    if !patches.is_empty() { break; };
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  };
+  assert_eq!(patches, vec![("/repos/acme/tracker/issues/1".to_string(), json!({ "state": "open" }))]);
+
+  let (status, _) = server.request(Method::DELETE, "/board/github", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::GET, "/board/github", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 404);
+  server.stop().await;
+}
@@ -0,0 +1,100 @@
+//! Очередь фоновых заданий: повторы, сроки выполнения и невыполненные задания.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+use test_support::{TestServer, ADMIN_KEY};
+
+const SECRET_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+/// Возвращает сводку очереди заданий.
+async fn jobs(server: &TestServer) -> JsonValue {
+  let (status, body) = server.request(Method::GET, "/admin/jobs", Some(&json!({ "key": ADMIN_KEY })), None).await;
+  assert_eq!(status, 200, "{}", body);
+  serde_json::from_str(&body).unwrap()
+}
+
+/// Ждёт, пока сводка очереди не станет удовлетворять `done`.
+async fn wait_for(server: &TestServer, done: impl Fn(&JsonValue) -> bool) -> JsonValue {
+  let mut snapshot = JsonValue::Null;
+  for _ in 0..50 {
+    snapshot = jobs(server).await;
+    if done(&snapshot) { return snapshot; };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  };
+  panic!("Очередь не пришла в ожидаемое состояние: {}", snapshot);
+}
+
+#[tokio::test]
+async fn jobs_are_retried_and_recovered() {
+  let github = json!({ "secret_key": SECRET_KEY, "api_url": "http://127.0.0.1:9", "sync_period_secs": 3600 }).to_string();
+  let queue = json!({ "workers": 2, "max_attempts": 2, "retry_delay_secs": 0, "poll_ms": 100 }).to_string();
+  let server = match TestServer::start_with_env(&[("GITHUB", &github), ("JOBS", &queue)]).await { Some(s) => s, None => return };
+
+  // Периодические задания ставятся в очередь при запуске сервера.
+  let snapshot = wait_for(&server, |s| s["kinds"]["github.sync"].is_object()).await;
+  assert_eq!(snapshot["workers"], 2);
+  assert_eq!(snapshot["kinds"]["overdue"], json!({ "queued": 1, "running": 0, "failed": 0 }));
+
+  // Задание с неверными данными повторяется `max_attempts` раз. Задание, обработчик которого остановился, берёт другой обработчик, а если попытки исчерпаны - оно считается невыполненным.
+  server.sql(
+    "insert into jobs (kind, shard_key, payload, run_at, created_at) values ('github.sync-board', 1, '\"доска\"', 0, 0); \
+     insert into jobs (kind, shard_key, payload, run_at, attempts, locked_until, created_at) values ('github.sync-board', 2, '999', 0, 1, 1, 0); \
+     insert into jobs (kind, shard_key, payload, run_at, attempts, locked_until, created_at) values ('github.sync-board', 3, '998', 0, 2, 1, 0);"
+  ).await;
+  let snapshot = wait_for(&server, |s| s["kinds"]["github.sync-board"] == json!({ "queued": 0, "running": 0, "failed": 2 })).await;
+  let failed = snapshot["failed"].as_array().unwrap();
+  let invalid = failed.iter().find(|job| job["payload"] == "доска").unwrap();
+  assert_eq!(invalid["attempts"], 2);
+  assert!(invalid["error"].as_str().unwrap().contains("invalid type"), "{}", invalid);
+  let expired = failed.iter().find(|job| job["payload"] == 998).unwrap();
+  assert_eq!(expired["error"], "Обработчик не завершил задание в срок.");
+
+  // Администратор может повторить невыполненное задание: доска не связана с репозиторием, поэтому синхронизировать нечего.
+  let admin = json!({ "key": ADMIN_KEY });
+  let (status, _) = server.request(Method::POST, "/admin/jobs/retry", Some(&admin), Some(&json!({ "id": expired["id"] }))).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::POST, "/admin/jobs/retry", Some(&admin), Some(&json!({ "id": 100000 }))).await;
+  assert_eq!(status, 404);
+  let snapshot = wait_for(&server, |s| s["kinds"]["github.sync-board"] == json!({ "queued": 0, "running": 0, "failed": 1 })).await;
+  assert_eq!(snapshot["failed"][0]["id"], invalid["id"]);
+
+  // Задание ключа ждёт, пока не будет выполнено более раннее задание того же ключа.
+  server.sql(
+    "insert into jobs (kind, shard_key, payload, run_at, created_at) values ('github.sync-board', 7, '\"доска\"', 4102444800, 0); \
+     insert into jobs (kind, shard_key, payload, run_at, created_at) values ('github.sync-board', 7, '997', 0, 0);"
+  ).await;
+  tokio::time::sleep(Duration::from_millis(500)).await;
+  assert_eq!(jobs(&server).await["kinds"]["github.sync-board"], json!({ "queued": 2, "running": 0, "failed": 1 }));
+  server.sql("update jobs set run_at = 0 where shard_key = 7;").await;
+  wait_for(&server, |s| s["kinds"]["github.sync-board"] == json!({ "queued": 0, "running": 0, "failed": 2 })).await;
+
+  let token = server.sign_up("yana").await;
+  assert_eq!(server.request(Method::GET, "/admin/jobs", Some(&token), None).await.0, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn stale_sign_in_failures_are_removed() {
+  let queue = json!({ "poll_ms": 100 }).to_string();
+  let server = match TestServer::start_with_env(&[("JOBS", &queue), ("SIGN_IN_FAILURES_WINDOW_SECS", "1")]).await { Some(s) => s, None => return };
+  let cli = server.hold(
+    "insert into sign_in_failures values ('old', 3, 0, 0); \
+     insert into sign_in_failures values ('locked', 5, 0, 4102444800); \
+     insert into sign_in_failures values ('fresh', 1, 4102444800, 0);"
+  ).await;
+
+  // Попытки, которые уже не учитываются и не блокируют вход, удаляются; блокировка и свежие попытки остаются.
+  let mut logins: Vec<String> = vec![];
+  for _ in 0..50 {
+    logins = cli.query("select login from sign_in_failures order by login;", &[]).await.unwrap().iter().map(|row| row.get(0)).collect();
+    if logins.len() < 3 { break; };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  };
+  assert_eq!(logins, ["fresh", "locked"]);
+  drop(cli);
+  server.stop().await;
+}
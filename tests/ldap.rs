@@ -0,0 +1,53 @@
+//! Проверка логинов и паролей в каталоге LDAP.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::json;
+
+use test_support::TestServer;
+
+/// Возвращает настройки каталога, к которому невозможно подключиться.
+fn unreachable_ldap(allow_local_users: bool) -> String {
+  json!({
+    "url": "ldap://127.0.0.1:1",
+    "bind_dn_template": "uid={login},ou=people,dc=example,dc=com",
+    "timeout_secs": 2,
+    "allow_local_users": allow_local_users,
+  }).to_string()
+}
+
+#[tokio::test]
+async fn directory_is_consulted_after_local_users() {
+  let server = match TestServer::start_with_env(&[("LDAP", &unreachable_ldap(true))]).await { Some(s) => s, None => return };
+  server.sign_up("alice").await;
+  // Пароль пользователя сервера проверяется без обращения к каталогу.
+  let (status, body) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "alice", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 200, "{}", body);
+  // Остальные логины и пароли проверяются в каталоге.
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "bob", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 503);
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "alice", "pass": "wrong-password" })), None
+  ).await;
+  assert_eq!(status, 503);
+  // Пустой пароль отклоняется без обращения к каталогу.
+  let (status, _) = server.request(Method::GET, "/sign-in", Some(&json!({ "login": "bob", "pass": "" })), None).await;
+  assert_eq!(status, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn local_users_can_be_disabled() {
+  let server = match TestServer::start_with_env(&[("LDAP", &unreachable_ldap(false))]).await { Some(s) => s, None => return };
+  server.sign_up("alice").await;
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "alice", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 503);
+  server.stop().await;
+}
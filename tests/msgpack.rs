@@ -0,0 +1,43 @@
+//! Обмен данными с сервером в кодировке MessagePack.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, no_timelines, TestServer};
+
+#[tokio::test]
+async fn board_is_exchanged_in_msgpack() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("olga").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let app_token = encode(&token);
+  let card = json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{ "title": "Задача", "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines() }]
+    }
+  });
+  let headers = [("App-Token", app_token.as_str()), ("Content-Type", "application/msgpack")];
+  let (status, _, body) = server.request_bytes(Method::PUT, "/card", &headers, Body::from(rmp_serde::to_vec_named(&card).unwrap())).await;
+  assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+
+  let headers = [("App-Token", app_token.as_str()), ("Content-Type", "application/msgpack"), ("Accept", "application/msgpack")];
+  let board_ref = rmp_serde::to_vec_named(&json!({ "board_id": board_id })).unwrap();
+  let (status, res_headers, body) = server.request_bytes(Method::POST, "/board", &headers, Body::from(board_ref)).await;
+  assert_eq!((status, res_headers["Content-Type"].to_str().unwrap()), (200, "application/msgpack"));
+  let board: JsonValue = rmp_serde::from_slice(&body).unwrap();
+  assert_eq!((&board["id"], &board["cards"][0]["tasks"][0]["title"]), (&json!(board_id), &json!("Задача")));
+
+  // Ошибки тоже передаются в MessagePack, а клиенты без заголовка Accept по-прежнему получают JSON.
+  let (status, _, body) = server.request_bytes(Method::POST, "/board", &headers, Body::from(vec![0xc1])).await;
+  let error: JsonValue = rmp_serde::from_slice(&body).unwrap();
+  assert_eq!(status, 400);
+  assert!(error["error"].as_str().unwrap().contains("MessagePack"), "{}", error);
+  let (status, body) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap()["id"], board_id);
+  server.stop().await;
+}
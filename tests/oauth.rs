@@ -0,0 +1,117 @@
+//! Вход через внешнего поставщика OAuth 2.0 / OpenID Connect.
+
+mod test_support;
+
+use hyper::{Body, Method, Request, Response, Server, body::to_bytes, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value as JsonValue};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use test_support::TestServer;
+
+/// Отвечает на запросы сервера так же, как поставщик OpenID Connect.
+///
+/// Код авторизации `code-<идентификатор>` обменивается на токен доступа `token-<идентификатор>`, а с ним выдаётся профиль пользователя с этим идентификатором. Остальные коды отклоняются.
+async fn provider(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let res = match (req.method().clone(), req.uri().path()) {
+    (Method::POST, "/token") => {
+      let form = String::from_utf8(to_bytes(req.into_body()).await.unwrap().to_vec()).unwrap();
+      let code = form.split('&').find_map(|p| p.strip_prefix("code=")).unwrap_or_default();
+      match (code.strip_prefix("code-"), form.contains("client_secret=secret")) {
+        (Some(subject), true) => json!({ "access_token": format!("token-{}", subject), "token_type": "Bearer" }),
+        _ => json!({ "error": "invalid_grant" }),
+      }
+    },
+    (Method::GET, "/userinfo") => {
+      let auth = req.headers().get("Authorization").and_then(|h| h.to_str().ok()).unwrap_or_default();
+      match auth.strip_prefix("Bearer token-") {
+        Some(subject) => json!({ "sub": subject, "email": "kim@example.com", "name": "Ким" }),
+        None => json!({ "error": "invalid_token" }),
+      }
+    },
+    _ => json!({ "error": "not_found" }),
+  };
+  Ok(Response::new(Body::from(res.to_string())))
+}
+
+/// Запускает поставщика и возвращает его адрес.
+fn start_provider() -> SocketAddr {
+  let server = Server::bind(&"127.0.0.1:0".parse().unwrap())
+    .serve(make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(provider)) }));
+  let addr = server.local_addr();
+  tokio::spawn(server);
+  addr
+}
+
+/// Начинает вход и возвращает `state` из адреса страницы входа.
+async fn start(server: &TestServer, token: Option<&JsonValue>) -> String {
+  let (status, body) = server.request(Method::GET, "/oauth/acme/start", token, None).await;
+  assert_eq!(status, 200, "{}", body);
+  let url = serde_json::from_str::<JsonValue>(&body).unwrap()["url"].as_str().unwrap().to_string();
+  assert!(url.contains("client_id=client&redirect_uri=http%3A%2F%2Flocalhost%3A3000%2Foauth"), "{}", url);
+  url.split('&').find_map(|p| p.strip_prefix("state=")).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn users_sign_in_with_provider() {
+  let addr = start_provider();
+  let providers = json!([{
+    "name": "acme",
+    "client_id": "client",
+    "client_secret": "secret",
+    "redirect_uri": "http://localhost:3000/oauth",
+    "authorize_url": format!("http://{}/authorize", addr),
+    "token_url": format!("http://{}/token", addr),
+    "userinfo_url": format!("http://{}/userinfo", addr),
+  }]).to_string();
+  let server = match TestServer::start_with_env(&[("OAUTH_PROVIDERS", &providers)]).await { Some(s) => s, None => return };
+  let (status, _) = server.request(Method::GET, "/oauth/other/start", None, None).await;
+  assert_eq!(status, 404);
+
+  // Первый вход создаёт пользователя с логином и именем из профиля.
+  let state = start(&server, None).await;
+  let callback = format!("/oauth/acme/callback?code=code-42&state={}", state);
+  let (status, token) = server.request(Method::GET, &callback, None, None).await;
+  assert_eq!(status, 200, "{}", token);
+  let token: JsonValue = serde_json::from_str(&token).unwrap();
+  let user_id = token["id"].as_i64().unwrap();
+  let (status, profiles) = server.request(
+    Method::GET, &format!("/users/resolve?ids={}", user_id), Some(&token), None
+  ).await;
+  assert_eq!(status, 200, "{}", profiles);
+  assert_eq!(serde_json::from_str::<JsonValue>(&profiles).unwrap()[0]["display_name"], "Ким");
+  // Каждый state принимается один раз.
+  let (status, _) = server.request(Method::GET, &callback, None, None).await;
+  assert_eq!(status, 401);
+
+  // Повторный вход возвращает того же пользователя.
+  let state = start(&server, None).await;
+  let (status, token) = server.request(
+    Method::GET, &format!("/oauth/acme/callback?code=code-42&state={}", state), None, None
+  ).await;
+  assert_eq!(status, 200, "{}", token);
+  assert_eq!(serde_json::from_str::<JsonValue>(&token).unwrap()["id"], user_id);
+
+  // Код, отклонённый поставщиком.
+  let state = start(&server, None).await;
+  let (status, _) = server.request(
+    Method::GET, &format!("/oauth/acme/callback?code=forged&state={}", state), None, None
+  ).await;
+  assert_eq!(status, 502);
+
+  // Вход с токеном привязывает аккаунт поставщика к существующему пользователю.
+  let lena = server.sign_up("lena").await;
+  let state = start(&server, Some(&lena)).await;
+  let (status, token) = server.request(
+    Method::GET, &format!("/oauth/acme/callback?code=code-43&state={}", state), None, None
+  ).await;
+  assert_eq!(status, 200, "{}", token);
+  assert_eq!(serde_json::from_str::<JsonValue>(&token).unwrap()["id"], lena["id"]);
+  // Аккаунт, уже привязанный к другому пользователю, не перепривязывается.
+  let state = start(&server, Some(&lena)).await;
+  let (status, _) = server.request(
+    Method::GET, &format!("/oauth/acme/callback?code=code-42&state={}", state), None, None
+  ).await;
+  assert_eq!(status, 409);
+  server.stop().await;
+}
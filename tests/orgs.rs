@@ -0,0 +1,172 @@
+//! Организации: участники и гости, доски организации, ограничения и правила организации.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, TestServer};
+
+/// Возвращает идентификаторы участников доски, если она доступна пользователю.
+async fn board_members(server: &TestServer, token: &JsonValue, board_id: i64) -> Option<Vec<i64>> {
+  let (status, body) = server.request(Method::POST, "/board", Some(token), Some(&json!({ "board_id": board_id }))).await;
+  match status {
+    200 => Some(serde_json::from_str::<JsonValue>(&body).unwrap()["shared_with"].as_array().unwrap().iter().map(|id| id.as_i64().unwrap()).collect()),
+    _ => None,
+  }
+}
+
+#[tokio::test]
+async fn org_members_share_org_boards() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let olga = server.sign_up("olga").await;
+  let boris = server.sign_up("boris").await;
+  let vera = server.sign_up("vera").await;
+  let (olga_id, boris_id) = (olga["id"].as_i64().unwrap(), boris["id"].as_i64().unwrap());
+
+  let (status, org_id) = server.request(Method::PUT, "/org", Some(&olga), Some(&json!({ "name": "  Команда релиза " }))).await;
+  assert_eq!(status, 200, "{}", org_id);
+  let org_id: i64 = org_id.parse().unwrap();
+  let board_id = server.create_board(&olga, "Релиз").await;
+  let (status, body) = server.request(Method::PUT, "/org/board", Some(&olga), Some(&json!({ "org_id": org_id, "board_id": board_id }))).await;
+  assert_eq!(status, 200, "{}", body);
+
+  // Вступивший в организацию получает доступ к её доскам и уведомление об этом.
+  let (status, body) = server.request(Method::PUT, "/org/member", Some(&olga), Some(&json!({ "org_id": org_id, "login": "boris" }))).await;
+  assert_eq!((status, body), (200, boris_id.to_string()));
+  assert_eq!(board_members(&server, &boris, board_id).await, Some(vec![olga_id, boris_id]));
+  let (_, body) = server.request(Method::GET, "/user/notifications", Some(&boris), None).await;
+  let notifications: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(notifications["notifications"][0]["kind"], "board_shared");
+
+  let (status, body) = server.request(Method::GET, "/org/list", Some(&boris), None).await;
+  assert_eq!(status, 200);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap(), json!([{ "id": org_id, "name": "Команда релиза", "role": "member" }]));
+  let (status, body) = server.request(Method::POST, "/org", Some(&boris), Some(&json!({ "org_id": org_id }))).await;
+  assert_eq!(status, 200);
+  let org: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(org["members"], json!([{ "user_id": olga_id, "role": "admin" }, { "user_id": boris_id, "role": "member" }]));
+  assert_eq!(org["boards"], json!([board_id]));
+
+  // Организация не видна посторонним, а управлять ей могут только администраторы.
+  assert_eq!(server.request(Method::POST, "/org", Some(&vera), Some(&json!({ "org_id": org_id }))).await.0, 404);
+  let add_vera = json!({ "org_id": org_id, "login": "vera" });
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&boris), Some(&add_vera)).await.0, 403);
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&json!({ "org_id": org_id, "login": "nobody" }))).await.0, 404);
+
+  // Ограничение числа участников задаёт администратор.
+  let quota = json!({ "org_id": org_id, "quota": { "max_members": 2, "max_boards": null } });
+  assert_eq!(server.request(Method::PATCH, "/org", Some(&olga), Some(&quota)).await.0, 200);
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&add_vera)).await.0, 409);
+
+  // Последний администратор не может выйти из организации или лишиться роли.
+  let leave = json!({ "org_id": org_id, "user_id": olga_id });
+  assert_eq!(server.request(Method::DELETE, "/org/member", Some(&olga), Some(&leave)).await.0, 409);
+  let demote = json!({ "org_id": org_id, "login": "olga", "role": "member" });
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&demote)).await.0, 409);
+
+  // Вышедший из организации теряет доступ к её доскам.
+  let leave = json!({ "org_id": org_id, "user_id": boris_id });
+  assert_eq!(server.request(Method::DELETE, "/org/member", Some(&boris), Some(&leave)).await.0, 200);
+  assert_eq!(board_members(&server, &boris, board_id).await, None);
+  assert_eq!(board_members(&server, &olga, board_id).await, Some(vec![olga_id]));
+
+  // Доска принадлежит только одной организации.
+  let (_, other_id) = server.request(Method::PUT, "/org", Some(&olga), Some(&json!({ "name": "Другая" }))).await;
+  let move_board = json!({ "org_id": other_id.parse::<i64>().unwrap(), "board_id": board_id });
+  assert_eq!(server.request(Method::PUT, "/org/board", Some(&olga), Some(&move_board)).await.0, 409);
+  let remove_board = json!({ "org_id": org_id, "board_id": board_id });
+  assert_eq!(server.request(Method::DELETE, "/org/board", Some(&olga), Some(&remove_board)).await.0, 200);
+  assert_eq!(server.request(Method::PUT, "/org/board", Some(&olga), Some(&move_board)).await.0, 200);
+
+  assert_eq!(server.request(Method::DELETE, "/org", Some(&olga), Some(&json!({ "org_id": org_id }))).await.0, 200);
+  let (_, body) = server.request(Method::GET, "/org/list", Some(&olga), None).await;
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap().as_array().unwrap().len(), 1);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn guests_and_org_policies() {
+  let quotas = r#"{"free": {"max_boards": 3}, "paid": {}}"#;
+  let server = match TestServer::start_with_env(&[("QUOTAS", quotas)]).await { Some(s) => s, None => return };
+  let olga = server.sign_up("olga").await;
+  let boris = server.sign_up("boris").await;
+  let boris_id = boris["id"].as_i64().unwrap();
+  let (_, org_id) = server.request(Method::PUT, "/org", Some(&olga), Some(&json!({ "name": "Команда" }))).await;
+  let org_id: i64 = org_id.parse().unwrap();
+
+  // По правилам организации новые участники становятся гостями, а доски создают только администраторы.
+  let policy = json!({ "org_id": org_id, "policy": { "board_creators": "admins", "default_role": "guest" } });
+  assert_eq!(server.request(Method::PATCH, "/org", Some(&olga), Some(&policy)).await.0, 200);
+  let wrong_policy = json!({ "org_id": org_id, "policy": { "default_role": "admin" } });
+  assert_eq!(server.request(Method::PATCH, "/org", Some(&olga), Some(&wrong_policy)).await.0, 400);
+  let mut board = json!({
+    "header": { "title": "Релиз", "header_background_color": "#ffffff", "header_text_color": "#000000" },
+    "background": { "color": "#eeeeee" },
+    "org_id": org_id
+  });
+  let (status, first) = server.request(Method::PUT, "/board", Some(&olga), Some(&board)).await;
+  assert_eq!(status, 200, "{}", first);
+  let (_, second) = server.request(Method::PUT, "/board", Some(&olga), Some(&board)).await;
+  let (first, second): (i64, i64) = (first.parse().unwrap(), second.parse().unwrap());
+
+  let (status, _) = server.request(Method::PUT, "/org/member", Some(&olga), Some(&json!({ "org_id": org_id, "login": "boris" }))).await;
+  assert_eq!(status, 200);
+  let (_, body) = server.request(Method::POST, "/org", Some(&olga), Some(&json!({ "org_id": org_id }))).await;
+  let org: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!(org["members"][1], json!({ "user_id": boris_id, "role": "guest" }));
+  assert_eq!(org["boards"], json!([first, second]));
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": first }))).await.0, 401);
+
+  // Гость видит только доски, которые ему открыли.
+  let share = json!({ "org_id": org_id, "board_id": first, "user_id": boris_id });
+  assert_eq!(server.request(Method::PUT, "/org/board/guest", Some(&olga), Some(&share)).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": first }))).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": second }))).await.0, 401);
+  board["org_id"] = json!(org_id);
+  assert_eq!(server.request(Method::PUT, "/board", Some(&boris), Some(&board)).await.0, 403);
+
+  // Участник получает доступ ко всем доскам, но по правилам организации не может добавлять в неё доски.
+  let promote = json!({ "org_id": org_id, "login": "boris", "role": "member" });
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&promote)).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": second }))).await.0, 200);
+  assert_eq!(server.request(Method::PUT, "/board", Some(&boris), Some(&board)).await.0, 403);
+  // Снова став гостем, он теряет доступ ко всем доскам, кроме открытых ему.
+  let demote = json!({ "org_id": org_id, "login": "boris", "role": "guest" });
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&demote)).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": first }))).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": second }))).await.0, 401);
+  assert_eq!(server.request(Method::DELETE, "/org/board/guest", Some(&olga), Some(&share)).await.0, 200);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": first }))).await.0, 401);
+
+  // Гостевая учётная запись не может создавать доски и организации и становиться автором доски.
+  let guest = json!({ "org_id": org_id, "login": "vera", "pass": "Kettle-Orbit-42" });
+  let (status, vera_id) = server.request(Method::PUT, "/org/guest", Some(&olga), Some(&guest)).await;
+  assert_eq!(status, 200, "{}", vera_id);
+  let vera_id: i64 = vera_id.parse().unwrap();
+  assert_eq!(server.request(Method::PUT, "/org/guest", Some(&olga), Some(&guest)).await.0, 409);
+  let creds = encode(&json!({ "login": "vera", "pass": "Kettle-Orbit-42" }));
+  let (status, vera) = server.request_with_headers(Method::GET, "/sign-in", &[("App-Token", creds.as_str())], hyper::Body::empty()).await;
+  assert_eq!(status, 200, "{}", vera);
+  let vera: JsonValue = serde_json::from_str(&vera).unwrap();
+  board.as_object_mut().unwrap().remove("org_id");
+  assert_eq!(server.request(Method::PUT, "/board", Some(&vera), Some(&board)).await.0, 403);
+  assert_eq!(server.request(Method::PUT, "/org", Some(&vera), Some(&json!({ "name": "Своя" }))).await.0, 403);
+  let member = json!({ "org_id": org_id, "login": "vera", "role": "member" });
+  assert_eq!(server.request(Method::PUT, "/org/member", Some(&olga), Some(&member)).await.0, 400);
+  let share = json!({ "org_id": org_id, "board_id": second, "user_id": vera_id });
+  assert_eq!(server.request(Method::PUT, "/org/board/guest", Some(&olga), Some(&share)).await.0, 200);
+  let transfer = json!({ "board_id": second, "author": vera_id });
+  assert_eq!(server.request(Method::POST, "/board/transfer", Some(&olga), Some(&transfer)).await.0, 403);
+  assert_eq!(server.request(Method::POST, "/board", Some(&vera), Some(&json!({ "board_id": second }))).await.0, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn orgs_require_postgres() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let olga = server.sign_up("olga").await;
+  assert_eq!(server.request(Method::PUT, "/org", Some(&olga), Some(&json!({ "name": "Команда" }))).await.0, 501);
+  assert_eq!(server.request(Method::GET, "/org/list", Some(&olga), None).await.0, 501);
+  server.stop().await;
+}
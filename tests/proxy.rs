@@ -0,0 +1,47 @@
+//! Определение адреса клиента за обратным прокси.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, ADMIN_KEY, TestServer};
+
+/// Вызывает метод администратора с данными заголовками и возвращает адрес клиента из записи журнала.
+async fn audited_ip(server: &TestServer, headers: &[(&str, &str)]) -> JsonValue {
+  let token = encode(&json!({ "key": ADMIN_KEY }));
+  let mut headers = headers.to_vec();
+  headers.push(("App-Token", &token));
+  let (status, _) = server.request_with_headers(Method::GET, "/admin/keys", &headers, Body::empty()).await;
+  assert_eq!(status, 200);
+  let (status, records) = server.request(
+    Method::GET, "/admin/audit?route=GET+/admin/keys&limit=1", Some(&json!({ "key": ADMIN_KEY })), None
+  ).await;
+  assert_eq!(status, 200, "{}", records);
+  serde_json::from_str::<JsonValue>(&records).unwrap()[0]["ip"].clone()
+}
+
+#[tokio::test]
+async fn forwarded_headers_are_trusted_only_from_proxies() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  // Без доверенных прокси заголовки игнорируются.
+  assert_eq!(audited_ip(&server, &[("X-Forwarded-For", "203.0.113.7")]).await, "127.0.0.1");
+  server.stop().await;
+
+  let server = match TestServer::start_with_env(&[("TRUSTED_PROXIES", "127.0.0.1, 10.0.0.0/8")]).await {
+    Some(s) => s,
+    None => return,
+  };
+  assert_eq!(audited_ip(&server, &[]).await, "127.0.0.1");
+  assert_eq!(audited_ip(&server, &[("X-Forwarded-For", "203.0.113.7")]).await, "203.0.113.7");
+  // Адреса, которые клиент дописал сам перед своим, не учитываются.
+  assert_eq!(audited_ip(&server, &[("X-Forwarded-For", "192.0.2.1, 203.0.113.7, 10.1.2.3")]).await, "203.0.113.7");
+  // Заголовок Forwarded имеет приоритет.
+  assert_eq!(audited_ip(&server, &[
+    ("Forwarded", "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.1"),
+    ("X-Forwarded-For", "203.0.113.7"),
+  ]).await, "2001:db8::1");
+  // Скрытый адрес обрывает цепочку на последнем доверенном прокси.
+  assert_eq!(audited_ip(&server, &[("Forwarded", "for=203.0.113.7, for=_hidden")]).await, "127.0.0.1");
+  server.stop().await;
+}
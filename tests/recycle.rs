@@ -0,0 +1,82 @@
+//! Уборка выполненных задач доски в карточку и в архив задач.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+use test_support::{no_timelines, TestServer};
+
+/// Возвращает названия задач в карточках доски.
+async fn titles(server: &TestServer, token: &JsonValue, board_id: i64) -> Vec<Vec<String>> {
+  let (_, body) = server.request(Method::POST, "/board", Some(token), Some(&json!({ "board_id": board_id }))).await;
+  let board: JsonValue = serde_json::from_str(&body).unwrap();
+  board["cards"].as_array().unwrap().iter()
+    .map(|card| card["tasks"].as_array().unwrap().iter().map(|task| task["title"].as_str().unwrap().to_string()).collect())
+    .collect()
+}
+
+/// Ждёт, пока задачи в карточках доски не станут такими, как `expected`.
+async fn wait_for(server: &TestServer, token: &JsonValue, board_id: i64, expected: &[&[&str]]) {
+  let mut current = vec![];
+  for _ in 0..50 {
+    current = titles(server, token, board_id).await;
+    if current == expected { return; };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  };
+  assert_eq!(current, expected);
+}
+
+#[tokio::test]
+async fn completed_tasks_are_recycled() {
+  let server = match TestServer::start_with_env(&[("RECYCLE_SCAN_PERIOD_SECS", "1")]).await { Some(s) => s, None => return };
+  let token = server.sign_up("zoya").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let card = |title: &str, tasks: &[(&str, bool)]| json!({
+    "board_id": board_id,
+    "card": {
+      "title": title, "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": tasks.iter().map(|(title, exec)| json!({
+        "title": title, "executors": [], "exec": exec, "subtasks": [], "tags": [], "notes": "", "timelines": no_timelines()
+      })).collect::<Vec<_>>()
+    }
+  });
+  let (status, work_id) = server.request(Method::PUT, "/card", Some(&token), Some(&card("Работа", &[("Старая", true), ("Открытая", false), ("Свежая", true)]))).await;
+  assert_eq!(status, 200, "{}", work_id);
+  let (status, done_id) = server.request(Method::PUT, "/card", Some(&token), Some(&card("Готово", &[]))).await;
+  assert_eq!(status, 200, "{}", done_id);
+  let (work_id, done_id): (i64, i64) = (work_id.parse().unwrap(), done_id.parse().unwrap());
+  server.sql(&format!("update boards set cards = jsonb_set(cards, '{{0,tasks,0,updated_at}}', '0') where id = {};", board_id)).await;
+  let settings = |recycle: JsonValue| {
+    let (server, token) = (&server, &token);
+    async move {
+      server.request(Method::PATCH, "/board", Some(token), Some(&json!({ "board_id": board_id, "settings": { "recycle": recycle } }))).await.0
+    }
+  };
+  assert_eq!(settings(json!({ "after_days": 1, "card_id": 999 })).await, 400);
+  assert_eq!(settings(json!({ "after_days": 5000 })).await, 400);
+
+  // В карточку переносятся только задачи, выполненные раньше срока.
+  assert_eq!(settings(json!({ "after_days": 1, "card_id": done_id })).await, 200);
+  wait_for(&server, &token, board_id, &[&["Открытая", "Свежая"], &["Старая"]]).await;
+
+  // Без карточки выполненные задачи убираются с доски в архив задач.
+  assert_eq!(settings(json!({ "after_days": 0 })).await, 200);
+  wait_for(&server, &token, board_id, &[&["Открытая"], &[]]).await;
+  let recycled = |body: JsonValue| {
+    let (server, token) = (&server, &token);
+    async move {
+      let (status, body) = server.request(Method::POST, "/board/recycled-tasks", Some(token), Some(&body)).await;
+      assert_eq!(status, 200, "{}", body);
+      serde_json::from_str::<Vec<JsonValue>>(&body).unwrap()
+    }
+  };
+  let tasks = recycled(json!({ "board_id": board_id })).await;
+  let mut archived: Vec<(&JsonValue, &JsonValue)> = tasks.iter().map(|t| (&t["card_id"], &t["task"]["title"])).collect();
+  archived.sort_by_key(|(card_id, _)| card_id.as_i64());
+  assert_eq!(archived, vec![(&json!(work_id), &json!("Свежая")), (&json!(done_id), &json!("Старая"))]);
+  let page = recycled(json!({ "board_id": board_id, "limit": 1, "before": tasks[0]["id"] })).await;
+  assert_eq!(page.iter().map(|t| &t["id"]).collect::<Vec<_>>(), vec![&tasks[1]["id"]]);
+  server.stop().await;
+}
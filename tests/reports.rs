@@ -0,0 +1,124 @@
+//! Статистика досок и отчёты о досках по расписанию.
+
+mod test_support;
+
+use hyper::{Body, Method, Request, Response, Server, body::to_bytes, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value as JsonValue};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use test_support::TestServer;
+
+const BOT_TOKEN: &str = "123456:bot-token";
+
+/// Принимает запросы вебхука и Telegram Bot API и запоминает путь и тело каждого.
+async fn receiver(received: Arc<Mutex<Vec<(String, JsonValue)>>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let path = req.uri().path().to_string();
+  let body = serde_json::from_slice(&to_bytes(req.into_body()).await.unwrap()).unwrap();
+  received.lock().unwrap().push((path, body));
+  Ok(Response::builder().status(200).body(Body::from("{\"ok\": true}")).unwrap())
+}
+
+/// Запускает получателя отчётов и возвращает его адрес.
+fn start_receiver(received: Arc<Mutex<Vec<(String, JsonValue)>>>) -> SocketAddr {
+  let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+    let received = received.clone();
+    async move { Ok::<_, Infallible>(service_fn(move |req| receiver(received.clone(), req))) }
+  }));
+  let addr = server.local_addr();
+  tokio::spawn(server);
+  addr
+}
+
+#[tokio::test]
+async fn reports_are_delivered() {
+  let received = Arc::new(Mutex::new(Vec::new()));
+  let addr = start_receiver(received.clone());
+  let cfg = json!({
+    "period_secs": 1, "telegram_bot_token": BOT_TOKEN, "telegram_api_url": format!("http://{}", addr), "allow_http_webhooks": true
+  }).to_string();
+  let server = match TestServer::start_with_env(&[("REPORTS", &cfg)]).await { Some(s) => s, None => return };
+  let olga = server.sign_up("olga").await;
+  let boris = server.sign_up("boris").await;
+  let board_id = server.create_board(&olga, "Релиз").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; update users set shared_boards = '[{2}]' where id = {1};",
+    olga["id"], boris["id"], board_id
+  )).await;
+  let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+  let task = |title: &str, max_time: i64| json!({
+    "title": title, "executors": [boris["id"]], "exec": false, "subtasks": [], "notes": "", "tags": [],
+    "timelines": { "preferred_time": 0, "max_time": max_time, "expected_time": 0 }
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&olga), Some(&json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [task("Собрать сборку", 0), task("Написать заметки", now - 3600)]
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let (status, _) = server.request(Method::PATCH, "/task", Some(&olga), Some(&json!({
+    "board_id": board_id, "card_id": card_id.parse::<i64>().unwrap(), "task_id": 1, "exec": true
+  }))).await;
+  assert_eq!(status, 200);
+
+  let (status, body) = server.request(Method::POST, "/board/stats", Some(&boris), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200, "{}", body);
+  let stats: JsonValue = serde_json::from_str(&body).unwrap();
+  assert_eq!((&stats["tasks"], &stats["done"], &stats["overdue"], &stats["unassigned"]), (&json!(2), &json!(1), &json!(1), &json!(0)));
+  assert_eq!(stats["executors"][0], json!({ "user_id": boris["id"], "open": 1, "overdue": 1 }));
+
+  // Отчётами управляет только автор доски, а получатель проверяется при сохранении.
+  let report = |target: JsonValue, format: &str| json!({ "board_id": board_id, "report": { "cadence": "daily", "format": format, "target": target } });
+  let webhook = json!({ "type": "webhook", "url": format!("http://{}/hook", addr) });
+  assert_eq!(server.request(Method::PUT, "/board/report", Some(&boris), Some(&report(webhook.clone(), "json"))).await.0, 403);
+  let wrong = json!({ "type": "webhook", "url": "ftp://example.com/hook" });
+  assert_eq!(server.request(Method::PUT, "/board/report", Some(&olga), Some(&report(wrong, "json"))).await.0, 400);
+  let wrong = json!({ "type": "telegram", "chat_id": "чат" });
+  assert_eq!(server.request(Method::PUT, "/board/report", Some(&olga), Some(&report(wrong, "markdown"))).await.0, 400);
+  let (status, webhook_id) = server.request(Method::PUT, "/board/report", Some(&olga), Some(&report(webhook, "json"))).await;
+  assert_eq!(status, 200, "{}", webhook_id);
+  let telegram = json!({ "type": "telegram", "chat_id": "-100123" });
+  let (status, _) = server.request(Method::PUT, "/board/report", Some(&olga), Some(&report(telegram, "markdown"))).await;
+  assert_eq!(status, 200);
+
+  for _ in 0..50 {
+    if received.lock().unwrap().len() >= 2 { break; };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  };
+  let mut received = received.lock().unwrap().clone();
+  received.sort_by(|a, b| a.0.cmp(&b.0));
+  assert_eq!(received.len(), 2, "{:?}", received);
+  let (path, message) = &received[0];
+  assert_eq!((path.as_str(), &message["chat_id"]), (format!("/bot{}/sendMessage", BOT_TOKEN).as_str(), &json!("-100123")));
+  let text = message["text"].as_str().unwrap();
+  assert!(text.starts_with("Отчёт о доске «Релиз»") && text.contains("- boris: 1 (просрочено 1)"), "{}", text);
+  let (path, report) = &received[1];
+  assert_eq!((path.as_str(), &report["cadence"], &report["completed"]), ("/hook", &json!("daily"), &json!(1)));
+  assert_eq!(report["to"].as_i64().unwrap() - report["from"].as_i64().unwrap(), 24 * 60 * 60);
+  assert_eq!((&report["stats"]["board_id"], &report["stats"]["tasks"]), (&json!(board_id), &json!(2)));
+
+  let (status, body) = server.request(Method::GET, "/board/reports", Some(&olga), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200, "{}", body);
+  let reports: Vec<JsonValue> = serde_json::from_str(&body).unwrap();
+  assert!(reports.len() == 2 && reports.iter().all(|r| r["sent_at"].as_i64().unwrap() >= now), "{:?}", reports);
+  let delete = json!({ "board_id": board_id, "report_id": webhook_id.parse::<i64>().unwrap() });
+  assert_eq!(server.request(Method::DELETE, "/board/report", Some(&olga), Some(&delete)).await.0, 200);
+  assert_eq!(server.request(Method::DELETE, "/board/report", Some(&olga), Some(&delete)).await.0, 404);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn reports_require_configuration() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("olga").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let (status, body) = server.request(Method::POST, "/board/stats", Some(&token), Some(&json!({ "board_id": board_id }))).await;
+  assert_eq!(status, 200, "{}", body);
+  let report = json!({ "board_id": board_id, "report": { "cadence": "weekly", "target": { "type": "telegram", "chat_id": "@releases" } } });
+  assert_eq!(server.request(Method::PUT, "/board/report", Some(&token), Some(&report)).await.0, 404);
+  server.stop().await;
+}
@@ -0,0 +1,200 @@
+//! Хранение неактивных досок: отметка, архив, выгрузка и продление хранения, а также архив досок по желанию автора.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+use test_support::TestServer;
+
+/// Возвращает идентификаторы досок из списка досок пользователя.
+async fn listed(server: &TestServer, token: &JsonValue) -> Vec<i64> {
+  let (status, body) = server.request(Method::GET, "/list", Some(token), None).await;
+  assert_eq!(status, 200, "{}", body);
+  serde_json::from_str::<Vec<JsonValue>>(&body).unwrap().iter().map(|board| board["id"].as_i64().unwrap()).collect()
+}
+
+/// Возвращает идентификаторы досок пользователя в архиве.
+async fn listed_archived(server: &TestServer, token: &JsonValue) -> Vec<i64> {
+  let (status, body) = server.request(Method::GET, "/list?archived=true", Some(token), None).await;
+  assert_eq!(status, 200, "{}", body);
+  serde_json::from_str::<Vec<JsonValue>>(&body).unwrap().iter().map(|board| board["id"].as_i64().unwrap()).collect()
+}
+
+/// Возвращает виды уведомлений пользователя, начиная с последних.
+async fn notification_kinds(server: &TestServer, token: &JsonValue) -> Vec<String> {
+  let (_, body) = server.request(Method::GET, "/user/notifications", Some(token), None).await;
+  let notifications: JsonValue = serde_json::from_str(&body).unwrap();
+  notifications["notifications"].as_array().unwrap().iter().map(|n| n["kind"].as_str().unwrap().to_string()).collect()
+}
+
+/// Создаёт карточку на доске и возвращает код ответа.
+async fn create_card(server: &TestServer, token: &JsonValue, board_id: i64) -> u16 {
+  server.request(Method::PUT, "/card", Some(token), Some(&json!({
+    "board_id": board_id,
+    "card": { "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff", "tasks": [] }
+  }))).await.0
+}
+
+#[tokio::test]
+async fn inactive_boards_are_archived_or_exported() {
+  let retention = json!({
+    "period_secs": 1, "grace_days": 0,
+    "free": { "inactive_months": 1, "action": "archive" },
+    "paid": { "inactive_months": 1, "action": "export_and_delete" }
+  }).to_string();
+  let envs = [("RETENTION", retention.as_str()), ("QUOTAS", r#"{"free": {"max_boards": 3}, "paid": {}}"#)];
+  let server = match TestServer::start_with_env(&envs).await { Some(s) => s, None => return };
+  let olga = server.sign_up("olga").await;
+  let boris = server.sign_up("boris").await;
+  let stale = server.create_board(&olga, "Прошлый релиз").await;
+  let fresh = server.create_board(&olga, "Релиз").await;
+  let exported = server.create_board(&boris, "Черновик").await;
+  server.sql(&format!(
+    "update users set apd = jsonb_set(apd::jsonb, '{{billed_forever}}', 'true')::text where id = {0}; \
+     update boards set updated_at = updated_at - 40 * 86400 where id in ({1}, {2});",
+    boris["id"], stale, exported
+  )).await;
+
+  // Доска отмечается при первом просмотре, а действие применяется при следующем.
+  let mut archived_at = 0;
+  for _ in 0..50 {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (_, body) = server.request(Method::POST, "/board", Some(&olga), Some(&json!({ "board_id": stale }))).await;
+    archived_at = serde_json::from_str::<JsonValue>(&body).unwrap()["archived_at"].as_i64().unwrap();
+    if archived_at != 0 { break; };
+  };
+  assert_ne!(archived_at, 0);
+  assert_eq!(listed(&server, &olga).await, vec![fresh]);
+  assert_eq!(create_card(&server, &olga, stale).await, 423);
+  assert_eq!(create_card(&server, &olga, fresh).await, 200);
+  assert_eq!(notification_kinds(&server, &olga).await, vec!["board_archived", "board_inactive"]);
+
+  // Выгрузку удалённой доски получает только её автор.
+  let mut exports: Vec<JsonValue> = vec![];
+  for _ in 0..50 {
+    let (_, body) = server.request(Method::GET, "/user/board-exports", Some(&boris), None).await;
+    exports = serde_json::from_str(&body).unwrap();
+    if !exports.is_empty() { break; };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  };
+  assert_eq!((exports.len(), &exports[0]["board_id"], &exports[0]["title"]), (1, &json!(exported), &json!("Черновик")));
+  let path = format!("/user/board-export?export_id={}", exports[0]["id"]);
+  let (status, body) = server.request(Method::GET, &path, Some(&boris), None).await;
+  assert_eq!(status, 200, "{}", body);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap()["header"]["title"], "Черновик");
+  assert_eq!(server.request(Method::GET, &path, Some(&olga), None).await.0, 404);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": exported }))).await.0, 401);
+  // Уведомления удалённой доски удаляются вместе с ней, кроме уведомления о выгрузке.
+  assert_eq!(notification_kinds(&server, &boris).await, vec!["board_exported"]);
+
+  // Автор возвращает доску из архива, и она снова изменяется.
+  let keep = json!({ "board_id": stale });
+  server.sql(&format!("update boards set shared_with = '[{0}, {1}]' where id = {2};", olga["id"], boris["id"], stale)).await;
+  assert_eq!(server.request(Method::PATCH, "/board/keep", Some(&boris), Some(&keep)).await.0, 403);
+  assert_eq!(server.request(Method::PATCH, "/board/keep", Some(&olga), Some(&keep)).await.0, 200);
+  assert_eq!(listed(&server, &olga).await, vec![stale, fresh]);
+  assert_eq!(create_card(&server, &olga, stale).await, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn board_changed_during_export_is_kept() {
+  let retention = json!({
+    "period_secs": 1, "grace_days": 0,
+    "free": { "inactive_months": 1, "action": "flag" },
+    "paid": { "inactive_months": 1, "action": "export_and_delete" }
+  }).to_string();
+  let server = match TestServer::start_with_env(&[("RETENTION", retention.as_str())]).await { Some(s) => s, None => return };
+  let boris = server.sign_up("boris").await;
+  let board_id = server.create_board(&boris, "Черновик").await;
+  // Доска уже отмечена, но бесплатный план только отмечает доски.
+  server.sql(&format!(
+    "update boards set updated_at = updated_at - 40 * 86400 where id = {0}; \
+     insert into board_retention (board_id, flagged_at) values ({0}, extract(epoch from now())::bigint - 86400);",
+    board_id
+  )).await;
+  // Параллельный запрос блокирует строку доски, а автор оплачивает аккаунт: теперь доску пора удалить.
+  let cli = server.hold(&format!("begin; select id from boards where id = {} for update;", board_id)).await;
+  server.sql(&format!(
+    "update users set apd = jsonb_set(apd::jsonb, '{{billed_forever}}', 'true')::text where id = {};", boris["id"]
+  )).await;
+  tokio::time::sleep(Duration::from_millis(2500)).await;
+  // Запрос изменяет доску, пока фоновая задача ждёт блокировки.
+  cli.batch_execute(&format!(
+    "update boards set updated_at = extract(epoch from now())::bigint where id = {}; commit;", board_id
+  )).await.unwrap();
+  drop(cli);
+  tokio::time::sleep(Duration::from_millis(2500)).await;
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": board_id }))).await.0, 200);
+  let (_, body) = server.request(Method::GET, "/user/board-exports", Some(&boris), None).await;
+  assert_eq!(serde_json::from_str::<Vec<JsonValue>>(&body).unwrap().len(), 0);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn author_archives_boards() {
+  let server = match TestServer::start_with_env(&[("QUOTAS", r#"{"free": {"max_boards": 3}, "paid": {}}"#)]).await {
+    Some(s) => s,
+    None => return,
+  };
+  let olga = server.sign_up("olga").await;
+  let boris = server.sign_up("boris").await;
+  let done = server.create_board(&olga, "Прошлый релиз").await;
+  let current = server.create_board(&olga, "Релиз").await;
+  server.sql(&format!(
+    "update boards set shared_with = '[{0}, {1}]' where id = {2}; \
+     update users set shared_boards = '[{2}]' where id = {1};",
+    olga["id"], boris["id"], done
+  )).await;
+
+  let archive = |archived: bool| json!({ "board_id": done, "archived": archived });
+  assert_eq!(server.request(Method::PATCH, "/board/archive", Some(&boris), Some(&archive(true))).await.0, 403);
+  let (status, body) = server.request(Method::PATCH, "/board/archive", Some(&olga), Some(&json!({ "board_id": done }))).await;
+  assert_eq!(status, 200, "{}", body);
+  // Повторный перенос ничего не меняет.
+  assert_eq!(server.request(Method::PATCH, "/board/archive", Some(&olga), Some(&archive(true))).await.0, 200);
+
+  // Доска в архиве перечисляется отдельно и доступна только для чтения.
+  assert_eq!(listed(&server, &olga).await, vec![current]);
+  assert_eq!(listed_archived(&server, &olga).await, vec![done]);
+  assert_eq!(listed_archived(&server, &boris).await, vec![done]);
+  assert_eq!(server.request(Method::POST, "/board", Some(&boris), Some(&json!({ "board_id": done }))).await.0, 200);
+  assert_eq!(create_card(&server, &boris, done).await, 423);
+  let (status, body) = server.request(Method::PATCH, "/board", Some(&olga), Some(&json!({ "board_id": done, "title": "Релиз 1.0" }))).await;
+  assert_eq!(status, 423);
+  assert_eq!(serde_json::from_str::<JsonValue>(&body).unwrap()["error"], "Доска в архиве и доступна только для чтения.");
+  assert!(!notification_kinds(&server, &olga).await.contains(&"board_archived".to_string()));
+
+  assert_eq!(server.request(Method::PATCH, "/board/archive", Some(&olga), Some(&archive(false))).await.0, 200);
+  assert_eq!(listed(&server, &olga).await, vec![done, current]);
+  assert!(listed_archived(&server, &olga).await.is_empty());
+  assert_eq!(create_card(&server, &boris, done).await, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn boards_are_archived_in_sqlite() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let olga = server.sign_up("olga").await;
+  let board_id = server.create_board(&olga, "Релиз").await;
+  let archive = |archived: bool| json!({ "board_id": board_id, "archived": archived });
+  assert_eq!(server.request(Method::PATCH, "/board/archive", Some(&olga), Some(&archive(true))).await.0, 200);
+  assert!(listed(&server, &olga).await.is_empty());
+  assert_eq!(listed_archived(&server, &olga).await, vec![board_id]);
+  assert_eq!(create_card(&server, &olga, board_id).await, 423);
+  assert_eq!(server.request(Method::PATCH, "/board/archive", Some(&olga), Some(&archive(false))).await.0, 200);
+  assert_eq!(create_card(&server, &olga, board_id).await, 200);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn retention_requires_postgres() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let olga = server.sign_up("olga").await;
+  let board_id = server.create_board(&olga, "Релиз").await;
+  assert_eq!(server.request(Method::PATCH, "/board/keep", Some(&olga), Some(&json!({ "board_id": board_id }))).await.0, 501);
+  assert_eq!(server.request(Method::GET, "/user/board-exports", Some(&olga), None).await.0, 501);
+  server.stop().await;
+}
@@ -0,0 +1,69 @@
+//! Проверка тел запросов по схемам методов.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, no_timelines, TestServer};
+
+#[tokio::test]
+async fn request_bodies_are_checked_against_schemas() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("inna").await;
+  let board_id = server.create_board(&token, "Доска").await;
+  let card = json!({
+    "board_id": board_id,
+    "card": {
+      "title": "Карточка", "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff",
+      "tasks": [{ "title": "Задача", "executors": [], "exec": false, "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines() }]
+    }
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&card)).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let patch_task = |patch: JsonValue| {
+    let (server, token) = (&server, &token);
+    let mut body = json!({ "board_id": board_id, "card_id": card_id, "task_id": 1 });
+    body.as_object_mut().unwrap().extend(patch.as_object().unwrap().clone());
+    async move {
+      let (status, body) = server.request(Method::PATCH, "/task", Some(token), Some(&body)).await;
+      (status, serde_json::from_str::<JsonValue>(&body).unwrap_or_default())
+    }
+  };
+
+  // Поле с опечаткой раньше молча игнорировалось, а теперь запрос отклоняется с путём к полю.
+  let (status, error) = patch_task(json!({ "titel": "Новое название" })).await;
+  assert_eq!(status, 400, "{}", error);
+  assert_eq!(error["violations"], json!([{ "path": "/titel", "message": "поле не предусмотрено" }]));
+  let (status, error) = patch_task(json!({ "story_points": -1, "priority": "asap", "executors": [1, "2"] })).await;
+  assert_eq!(status, 400, "{}", error);
+  let paths: Vec<&str> = error["violations"].as_array().unwrap().iter().map(|v| v["path"].as_str().unwrap()).collect();
+  assert_eq!(paths, ["/executors/1", "/priority", "/story_points"]);
+  let (status, error) = patch_task(json!({ "title": "Новое название", "story_points": null })).await;
+  assert_eq!(status, 200, "{}", error);
+
+  // Вложенные сущности проверяются так же, а в MessagePack нарушения находятся по тем же путям.
+  let mut card = card.clone();
+  card["card"]["tasks"][0]["subtasks"] = json!([{ "title": "Подзадача", "exec": "нет" }]);
+  let (status, error) = server.request(Method::PUT, "/card", Some(&token), Some(&card)).await;
+  assert_eq!(status, 400, "{}", error);
+  let error: JsonValue = serde_json::from_str(&error).unwrap();
+  assert_eq!(error["violations"][0], json!({ "path": "/card/tasks/0/subtasks/0/exec", "message": "ожидается логическое значение" }));
+  let app_token = encode(&token);
+  let headers = [("App-Token", app_token.as_str()), ("Content-Type", "application/msgpack")];
+  let body = rmp_serde::to_vec_named(&json!({ "board_id": board_id, "title": "Доска", "colour": "#ffffff" })).unwrap();
+  let (status, _, error) = server.request_bytes(Method::PATCH, "/board", &headers, Body::from(body)).await;
+  assert_eq!(status, 400);
+  assert_eq!(serde_json::from_slice::<JsonValue>(&error).unwrap()["violations"][0]["path"], "/colour");
+
+  // Схемы опубликованы в виде JSON Schema.
+  let (status, schemas) = server.request(Method::GET, "/schemas", None, None).await;
+  assert_eq!(status, 200);
+  let schemas: JsonValue = serde_json::from_str(&schemas).unwrap();
+  let task_patch = &schemas["PATCH /task"]["request"];
+  assert_eq!(task_patch["additionalProperties"], false);
+  assert_eq!(task_patch["properties"]["title"], json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] }));
+  assert_eq!(schemas["POST /token/refresh"]["response"]["properties"]["token"], json!({ "type": "string" }));
+  server.stop().await;
+}
@@ -0,0 +1,61 @@
+//! Журнал входов в аккаунт и оповещения о подозрительных входах.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, TestServer};
+
+/// Входит в аккаунт с данного адреса и возвращает код ответа.
+async fn sign_in(server: &TestServer, pass: &str, ip: &str) -> u16 {
+  let creds = encode(&json!({ "login": "olga", "pass": pass }));
+  let headers = [("App-Token", creds.as_str()), ("X-Forwarded-For", ip), ("User-Agent", "Taskboard/2.1")];
+  server.request_with_headers(Method::GET, "/sign-in", &headers, Body::empty()).await.0
+}
+
+#[tokio::test]
+async fn new_location_and_lockout_are_reported() {
+  let geoip = std::env::temp_dir().join(format!("taskboard-geoip-{}.csv", std::process::id()));
+  std::fs::write(&geoip, "# Тестовые подсети\n203.0.113.0/24,ru\n198.51.100.0/24,US\n").unwrap();
+  let geoip_cfg = json!({ "provider": "csv", "path": geoip }).to_string();
+  let envs = [("TRUSTED_PROXIES", "127.0.0.1"), ("GEOIP", geoip_cfg.as_str()), ("SIGN_IN_MAX_FAILURES", "2")];
+  let server = match TestServer::start_with_env(&envs).await { Some(s) => s, None => return };
+  let token = server.sign_up("olga").await;
+  // Первый вход и вход с другого адреса той же страны не считаются подозрительными.
+  assert_eq!(sign_in(&server, "Kettle-Orbit-42", "203.0.113.5").await, 200);
+  assert_eq!(sign_in(&server, "Kettle-Orbit-42", "203.0.113.9").await, 200);
+  assert_eq!(sign_in(&server, "Kettle-Orbit-42", "198.51.100.1").await, 200);
+  for _ in 0..3 {
+    assert_ne!(sign_in(&server, "wrong-password", "198.51.100.1").await, 200);
+  };
+
+  let (status, body) = server.request(Method::GET, "/user/security-events", Some(&token), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let events: Vec<JsonValue> = serde_json::from_str(&body).unwrap();
+  let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+  assert_eq!(kinds, vec!["sign_in_locked", "new_location", "sign_in", "sign_in"]);
+  assert_eq!((&events[1]["ip"], &events[1]["country"], &events[1]["user_agent"]), (&json!("198.51.100.1"), &json!("US"), &json!("Taskboard/2.1")));
+  assert_eq!(events[3]["country"], "RU");
+  let (status, body) = server.request(Method::GET, "/user/security-events?limit=1", Some(&token), None).await;
+  assert_eq!(status, 200);
+  assert_eq!(serde_json::from_str::<Vec<JsonValue>>(&body).unwrap().len(), 1);
+
+  let (status, body) = server.request(Method::GET, "/user/notifications", Some(&token), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let notifications: JsonValue = serde_json::from_str(&body).unwrap();
+  let kinds: Vec<(&str, &JsonValue)> = notifications["notifications"].as_array().unwrap().iter()
+    .map(|n| (n["kind"].as_str().unwrap(), &n["board_id"])).collect();
+  assert_eq!(kinds, vec![("sign_in_locked", &JsonValue::Null), ("new_location", &JsonValue::Null)]);
+  server.stop().await;
+  std::fs::remove_file(geoip).ok();
+}
+
+#[tokio::test]
+async fn security_events_require_postgres() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("olga").await;
+  assert_eq!(sign_in(&server, "Kettle-Orbit-42", "203.0.113.5").await, 200);
+  assert_eq!(server.request(Method::GET, "/user/security-events", Some(&token), None).await.0, 501);
+  server.stop().await;
+}
@@ -0,0 +1,84 @@
+//! Список сеансов пользователя со сведениями о клиентах.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{encode, TestServer};
+
+#[tokio::test]
+async fn sessions_show_client_metadata() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("olga").await;
+  let creds = encode(&json!({ "login": "olga", "pass": "Kettle-Orbit-42" }));
+  let headers = [("App-Token", creds.as_str()), ("User-Agent", "Taskboard/2.1 (Android)"), ("Device-Name", "%D0%A2%D0%B5%D0%BB%D0%B5%D1%84%D0%BE%D0%BD")];
+  let (status, body) = server.request_with_headers(Method::GET, "/sign-in", &headers, Body::empty()).await;
+  assert_eq!(status, 200, "{}", body);
+  let phone: JsonValue = serde_json::from_str(&body).unwrap();
+
+  let refresh = encode(&json!({ "id": phone["id"], "refresh_token": phone["refresh_token"] }));
+  let headers = [("App-Token", refresh.as_str()), ("User-Agent", "Taskboard/2.2 (Android)")];
+  let (status, body) = server.request_with_headers(Method::POST, "/token/refresh", &headers, Body::empty()).await;
+  assert_eq!(status, 200, "{}", body);
+
+  let (status, body) = server.request(Method::GET, "/user/sessions", Some(&token), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let sessions: Vec<JsonValue> = serde_json::from_str(&body).unwrap();
+  assert_eq!(sessions.len(), 2);
+  // После обновления токенов сеанс сохраняет название устройства и дату входа, а User-Agent берётся из последнего запроса.
+  let phone = sessions.iter().find(|s| s.get("device_name").is_some()).unwrap();
+  assert_eq!((&phone["device_name"], &phone["user_agent"], &phone["ip"]), (&json!("Телефон"), &json!("Taskboard/2.2 (Android)"), &json!("127.0.0.1")));
+  assert!(phone["signed_in_at"].as_i64().unwrap() <= phone["refreshed_at"].as_i64().unwrap());
+  server.stop().await;
+}
+
+/// Обменивает токен обновления дважды одновременно и проверяет, что удаётся только один обмен.
+async fn refresh_twice(server: &TestServer) {
+  let token = server.sign_up("pavel").await;
+  let refresh = json!({ "id": token["id"], "refresh_token": token["refresh_token"] });
+  let (first, second) = tokio::join!(
+    server.request(Method::POST, "/token/refresh", Some(&refresh), None),
+    server.request(Method::POST, "/token/refresh", Some(&refresh), None),
+  );
+  let mut statuses = [first.0, second.0];
+  statuses.sort();
+  assert_eq!(statuses, [200, 401], "{} {}", first.1, second.1);
+  let (_, body) = server.request(Method::GET, "/user/sessions", Some(&token), None).await;
+  assert_eq!(serde_json::from_str::<Vec<JsonValue>>(&body).unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn refresh_token_is_used_once() {
+  let server = TestServer::start_sqlite(&[]).await;
+  refresh_twice(&server).await;
+  server.stop().await;
+  if let Some(server) = TestServer::start_with_env(&[]).await {
+    refresh_twice(&server).await;
+    server.stop().await;
+  };
+}
+
+#[tokio::test]
+async fn refresh_keeps_absolute_lifetime() {
+  let server = match TestServer::start_with_env(&[]).await { Some(s) => s, None => return };
+  let token = server.sign_up("rita").await;
+  let refresh = |token: &JsonValue| json!({ "id": token["id"], "refresh_token": token["refresh_token"] });
+  // Сеанс начался 29 дней назад: обмен ещё возможен, но срок сеанса не продлевается.
+  server.sql(
+    "update users set user_creds = jsonb_set(user_creds, '{refresh_tokens,0,created_dt}', to_jsonb(extract(epoch from now())::bigint - 29 * 86400));"
+  ).await;
+  let (status, body) = server.request(Method::POST, "/token/refresh", Some(&refresh(&token)), None).await;
+  assert_eq!(status, 200, "{}", body);
+  let refreshed: JsonValue = serde_json::from_str(&body).unwrap();
+  let expires_at = refreshed["lifetime"]["expires_at"].as_i64().unwrap();
+  assert!(expires_at < token["lifetime"]["expires_at"].as_i64().unwrap() - 28 * 86400);
+
+  // Прошёл ещё день: через 30 дней после входа сеанс заканчивается, сколько бы токены ни обновлялись.
+  server.sql(
+    "update users set user_creds = jsonb_set(user_creds, '{refresh_tokens,0,created_dt}', \
+       to_jsonb((user_creds #>> '{refresh_tokens,0,created_dt}')::bigint - 86400 - 60));"
+  ).await;
+  assert_eq!(server.request(Method::POST, "/token/refresh", Some(&refresh(&refreshed)), None).await.0, 401);
+  server.stop().await;
+}
@@ -0,0 +1,54 @@
+//! Подписанные ссылки на методы чтения.
+
+mod test_support;
+
+use hyper::{Body, Method};
+use serde_json::{json, Value as JsonValue};
+
+use test_support::TestServer;
+
+#[tokio::test]
+async fn signed_url_replaces_token_for_reads() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let token = server.sign_up("olga").await;
+  server.create_board(&token, "Доска").await;
+  let sign = |body: JsonValue| {
+    let (server, token) = (&server, &token);
+    async move { server.request(Method::POST, "/user/signed-url", Some(token), Some(&body)).await }
+  };
+  assert_eq!(sign(json!({ "path": "list" })).await.0, 400);
+  assert_eq!(sign(json!({ "path": "/list", "ttl_secs": 0 })).await.0, 400);
+  let (status, body) = sign(json!({ "path": "/list?sort=title", "ttl_secs": 60 })).await;
+  assert_eq!(status, 200, "{}", body);
+  let signed: JsonValue = serde_json::from_str(&body).unwrap();
+  let url = signed["url"].as_str().unwrap();
+  assert!(url.starts_with("/list?sort=title&uid="), "{}", url);
+
+  let (status, list) = server.request_with_headers(Method::GET, url, &[], Body::empty()).await;
+  assert_eq!(status, 200, "{}", list);
+  assert_eq!(serde_json::from_str::<JsonValue>(&list).unwrap()[0]["title"], "Доска");
+
+  // Подпись действует только для того же пути с теми же параметрами и только для чтения.
+  let tampered = url.replace("sort=title", "sort=activity");
+  assert_eq!(server.request_with_headers(Method::GET, &tampered, &[], Body::empty()).await.0, 401);
+  let expired = url.replace(&format!("expires={}", signed["expires_at"]), "expires=1");
+  assert_eq!(server.request_with_headers(Method::GET, &expired, &[], Body::empty()).await.0, 401);
+  assert_eq!(server.request_with_headers(Method::PUT, url, &[], Body::empty()).await.0, 401);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn signed_url_outlives_access_token() {
+  let server = match TestServer::start().await { Some(s) => s, None => return };
+  let token = server.sign_up("vika").await;
+  let (status, body) = server.request(Method::POST, "/user/signed-url", Some(&token), Some(&json!({ "path": "/list", "ttl_secs": 86400 }))).await;
+  assert_eq!(status, 200, "{}", body);
+  let url = serde_json::from_str::<JsonValue>(&body).unwrap()["url"].as_str().unwrap().to_string();
+
+  // Токен доступа истёк, но сеанс продолжается, пока действует токен обновления.
+  server.sql("update users set user_creds = jsonb_set(user_creds, '{tokens}', '[]');").await;
+  assert_eq!(server.request_with_headers(Method::GET, &url, &[], Body::empty()).await.0, 200);
+  server.sql("update users set user_creds = jsonb_set(user_creds, '{refresh_tokens}', '[]');").await;
+  assert_eq!(server.request_with_headers(Method::GET, &url, &[], Body::empty()).await.0, 401);
+  server.stop().await;
+}
@@ -0,0 +1,117 @@
+//! Открытая регистрация с подтверждением адреса электронной почты.
+
+mod test_support;
+
+use hyper::{Body, Method, Request, Response, Server, body::to_bytes, service::{make_service_fn, service_fn}};
+use serde_json::{json, Value as JsonValue};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use test_support::TestServer;
+
+const TOKEN: &str = "mailer-token";
+
+/// Принимает письма так же, как почтовый шлюз, и запоминает их.
+async fn mailer(mails: Arc<Mutex<Vec<JsonValue>>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let mail = serde_json::from_slice(&to_bytes(req.into_body()).await.unwrap()).unwrap();
+  mails.lock().unwrap().push(mail);
+  Ok(Response::builder().status(202).body(Body::empty()).unwrap())
+}
+
+/// Запускает почтовый шлюз и возвращает его адрес.
+fn start_mailer(mails: Arc<Mutex<Vec<JsonValue>>>) -> SocketAddr {
+  let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+    let mails = mails.clone();
+    async move { Ok::<_, Infallible>(service_fn(move |req| mailer(mails.clone(), req))) }
+  }));
+  let addr = server.local_addr();
+  tokio::spawn(server);
+  addr
+}
+
+/// Возвращает токен из ссылки подтверждения в последнем письме.
+fn last_token(mails: &Mutex<Vec<JsonValue>>) -> String {
+  let mails = mails.lock().unwrap();
+  let text = mails.last().unwrap()["text"].as_str().unwrap();
+  let (_, rest) = text.split_once("?lang=ru&token=").unwrap();
+  rest.split_whitespace().next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn signup_requires_confirmed_email() {
+  let mails = Arc::new(Mutex::new(Vec::new()));
+  let addr = start_mailer(mails.clone());
+  let mailer = json!({ "url": format!("http://{}/send", addr), "token": TOKEN, "from": "taskboard@example.com" }).to_string();
+  let signup = json!({ "confirm_url": "https://taskboard.example.com/confirm?lang=ru", "max_per_ip_per_hour": 5 }).to_string();
+  let server = match TestServer::start_with_env(&[("MAILER", &mailer), ("PUBLIC_SIGNUP", &signup)]).await {
+    Some(s) => s,
+    None => return,
+  };
+  let sign_up = |login: &str, email: &str, pass: &str| {
+    let (server, creds) = (&server, json!({ "login": login, "pass": pass, "email": email }));
+    async move { server.request(Method::PUT, "/sign-up/email", Some(&creds), None).await }
+  };
+  let post = |path: &'static str, body: JsonValue| {
+    let server = &server;
+    async move { server.request(Method::POST, path, None, Some(&body)).await }
+  };
+
+  // Требования к паролю и адресу проверяются до отправки письма.
+  assert_eq!(sign_up("vera", "vera@example.com", "123").await.0, 400);
+  assert_eq!(sign_up("vera", "vera.example.com", "Kettle-Orbit-42").await.0, 400);
+  assert!(mails.lock().unwrap().is_empty());
+
+  // Аккаунт не создаётся, пока адрес не подтверждён, а логин занят регистрацией.
+  let (status, body) = sign_up("vera", "vera@example.com", "Kettle-Orbit-42").await;
+  assert_eq!(status, 200, "{}", body);
+  assert!(serde_json::from_str::<JsonValue>(&body).unwrap()["expires_at"].as_i64().unwrap() > 0);
+  assert_eq!(mails.lock().unwrap()[0]["to"], "vera@example.com");
+  let first_token = last_token(&mails);
+  let creds = json!({ "login": "vera", "pass": "Kettle-Orbit-42" });
+  assert_eq!(server.request(Method::GET, "/sign-in", Some(&creds), None).await.0, 401);
+  assert_eq!(sign_up("vera", "other@example.com", "Kettle-Orbit-42").await.0, 409);
+
+  // Без ключей регистрации аккаунт нельзя создать в обход подтверждения адреса.
+  let (status, body) = server.request(Method::PUT, "/sign-up", Some(&creds), None).await;
+  assert_eq!(status, 403, "{}", body);
+  assert_eq!(server.request(Method::PUT, "/sign-up", Some(&json!({ "login": "ivan", "pass": "Kettle-Orbit-42" })), None).await.0, 403);
+
+  // Письмо отправляется повторно не сразу, и новая ссылка заменяет прежнюю; о неизвестном логине сервер не сообщает.
+  assert_eq!(post("/sign-up/resend", json!({ "login": "vera" })).await.0, 429);
+  server.sql("update pending_users set sent_at = 0;").await;
+  assert_eq!(post("/sign-up/resend", json!({ "login": "vera" })).await.0, 200);
+  assert_eq!(post("/sign-up/resend", json!({ "login": "nobody" })).await.0, 200);
+  assert_eq!(mails.lock().unwrap().len(), 2);
+  let token = last_token(&mails);
+  assert_eq!(post("/sign-up/confirm", json!({ "token": first_token })).await.0, 404);
+
+  // Подтверждённый пользователь получает токены, а его адрес становится адресом дайджестов.
+  let (status, body) = post("/sign-up/confirm", json!({ "token": token })).await;
+  assert_eq!(status, 200, "{}", body);
+  let user: JsonValue = serde_json::from_str(&body).unwrap();
+  let (_, prefs) = server.request(Method::GET, "/user/notification-prefs", Some(&user), None).await;
+  assert_eq!(serde_json::from_str::<JsonValue>(&prefs).unwrap()["email"], "vera@example.com");
+  assert_eq!(server.request(Method::GET, "/sign-in", Some(&creds), None).await.0, 200);
+  assert_eq!(post("/sign-up/confirm", json!({ "token": token })).await.0, 404);
+
+  // С одного адреса клиента за час принимается не больше `max_per_ip_per_hour` регистраций и повторных отправок.
+  let (status, body) = sign_up("gleb", "gleb@example.com", "Kettle-Orbit-42").await;
+  assert_eq!(status, 429, "{}", body);
+  server.sql("delete from signup_attempts;").await;
+  assert_eq!(sign_up("gleb", "gleb@example.com", "Kettle-Orbit-42").await.0, 200);
+
+  // Просроченная ссылка не действует.
+  server.sql("update pending_users set expires_at = 0;").await;
+  assert_eq!(post("/sign-up/confirm", json!({ "token": last_token(&mails) })).await.0, 404);
+  server.stop().await;
+}
+
+#[tokio::test]
+async fn signup_by_email_is_off_by_default() {
+  let server = TestServer::start_sqlite(&[]).await;
+  let creds = json!({ "login": "vera", "pass": "Kettle-Orbit-42", "email": "vera@example.com" });
+  assert_eq!(server.request(Method::PUT, "/sign-up/email", Some(&creds), None).await.0, 404);
+  assert_eq!(server.request(Method::POST, "/sign-up/confirm", None, Some(&json!({ "token": "x" }))).await.0, 404);
+  server.stop().await;
+}
@@ -0,0 +1,77 @@
+//! Работа сервера с хранилищем в файле SQLite.
+
+mod test_support;
+
+use hyper::Method;
+use serde_json::{json, Value as JsonValue};
+
+use test_support::{no_timelines, TestServer};
+
+#[tokio::test]
+async fn boards_are_kept_in_sqlite() {
+  let quotas = r#"{"free": {"max_boards": 3}, "paid": {}}"#;
+  let server = TestServer::start_sqlite(&[("QUOTAS", quotas)]).await;
+  let token = server.sign_up("olga").await;
+  let (status, _) = server.request(
+    Method::GET, "/sign-in", Some(&json!({ "login": "olga", "pass": "Kettle-Orbit-42" })), None
+  ).await;
+  assert_eq!(status, 200);
+  let (status, _) = server.request(Method::PUT, "/sign-up", Some(&json!({ "login": "olga", "pass": "Kettle-Orbit-42" })), None).await;
+  assert_ne!(status, 200);
+
+  let first = server.create_board(&token, "Первая").await;
+  let second = server.create_board(&token, "Вторая").await;
+  let task = json!({
+    "id": 0, "author": 0, "title": "Задача", "executors": [], "exec": false,
+    "subtasks": [], "notes": "", "tags": [], "timelines": no_timelines()
+  });
+  let (status, card_id) = server.request(Method::PUT, "/card", Some(&token), Some(&json!({
+    "board_id": first,
+    "card": {
+      "id": 0, "author": 0, "title": "Карточка", "tasks": [task],
+      "header_text_color": "#000000", "header_background_color": "#ffffff", "background_color": "#ffffff"
+    }
+  }))).await;
+  assert_eq!(status, 200, "{}", card_id);
+  let card_id: i64 = card_id.parse().unwrap();
+  let (status, task_id) = server.request(
+    Method::PUT, "/task", Some(&token), Some(&json!({ "board_id": first, "card_id": card_id, "task": task }))
+  ).await;
+  assert_eq!(status, 200, "{}", task_id);
+  assert_eq!(task_id, "2");
+  let (status, board) = server.request(Method::POST, "/board", Some(&token), Some(&json!({ "board_id": first }))).await;
+  assert_eq!(status, 200, "{}", board);
+  let board: JsonValue = serde_json::from_str(&board).unwrap();
+  assert_eq!(board["revision"], 2);
+  assert_eq!(board["cards"][0]["task_count"], 2);
+
+  let (status, _) = server.request(
+    Method::PATCH, "/user/board-prefs", Some(&token), Some(&json!({ "board_id": second, "favorite": true, "position": 1 }))
+  ).await;
+  assert_eq!(status, 200);
+  let titles = |list: &JsonValue| -> Vec<String> {
+    list.as_array().unwrap().iter().map(|b| b["title"].as_str().unwrap().to_string()).collect()
+  };
+  let (_, list) = server.request(Method::GET, "/list?sort=custom", Some(&token), None).await;
+  let list: JsonValue = serde_json::from_str(&list).unwrap();
+  assert_eq!(titles(&list), vec!["Вторая", "Первая"]);
+  assert_eq!(list[0]["favorite"], true);
+  let (_, page) = server.request(Method::GET, "/list?sort=title&limit=1", Some(&token), None).await;
+  let page: JsonValue = serde_json::from_str(&page).unwrap();
+  assert_eq!(titles(&page["boards"]), vec!["Вторая"]);
+  let (_, page) = server.request(
+    Method::GET, &format!("/list?sort=title&limit=1&cursor={}", page["next_cursor"].as_str().unwrap()), Some(&token), None
+  ).await;
+  let page: JsonValue = serde_json::from_str(&page).unwrap();
+  assert_eq!((titles(&page["boards"]), page["next_cursor"].clone()), (vec!["Первая".to_string()], JsonValue::Null));
+
+  // Функции, данные которых хранятся только в PostgreSQL, недоступны.
+  let (status, _) = server.request(Method::GET, "/user/notifications", Some(&token), None).await;
+  assert_eq!(status, 501);
+
+  let (status, _) = server.request(Method::DELETE, "/board", Some(&token), Some(&json!({ "board_id": first }))).await;
+  assert_eq!(status, 200);
+  let (_, list) = server.request(Method::GET, "/list", Some(&token), None).await;
+  assert_eq!(titles(&serde_json::from_str(&list).unwrap()), vec!["Вторая"]);
+  server.stop().await;
+}
@@ -0,0 +1,46 @@
+//! Работа под управлением systemd: уведомления о готовности и выключении, PID-файл.
+
+#![cfg(unix)]
+
+mod test_support;
+
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use test_support::TestServer;
+
+/// Ожидает следующее уведомление сервера.
+fn next_state(socket: &UnixDatagram) -> String {
+  let mut buf = [0; 256];
+  let len = socket.recv(&mut buf).expect("Сервер не прислал уведомление.");
+  String::from_utf8(buf[..len].to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn notifies_systemd_and_writes_pid_file() {
+  let dir = std::env::temp_dir();
+  let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+  let name = format!("taskboard_systemd_{}_{}", std::process::id(), nanos);
+  let (socket_path, pid_path) = (dir.join(format!("{}.sock", name)), dir.join(format!("{}.pid", name)));
+  std::fs::remove_file(&socket_path).ok();
+  let socket = UnixDatagram::bind(&socket_path).unwrap();
+  socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  let server = TestServer::start_sqlite(&[
+    ("NOTIFY_SOCKET", socket_path.to_str().unwrap()),
+    ("PID_FILE", pid_path.to_str().unwrap()),
+  ]).await;
+  assert_eq!(next_state(&socket), "READY=1");
+  let pid = std::fs::read_to_string(&pid_path).unwrap().trim().to_string();
+  assert!(pid.parse::<u32>().is_ok(), "{}", pid);
+
+  // Остановка по SIGTERM, как это делает systemd, сообщает о выключении и удаляет PID-файл.
+  assert!(std::process::Command::new("kill").args(["-TERM", &pid]).status().unwrap().success());
+  assert_eq!(next_state(&socket), "STOPPING=1");
+  for _ in 0..100 {
+    if !pid_path.exists() { break; };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  };
+  assert!(!pid_path.exists());
+  server.stop().await;
+  std::fs::remove_file(&socket_path).ok();
+}
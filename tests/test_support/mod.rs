@@ -0,0 +1,371 @@
+//! Окружение для интеграционных тестов.
+//!
+//! Каждый тест получает собственную одноразовую базу данных PostgreSQL и отдельно запущенный процесс сервера, который с ней работает. Для подключения к PostgreSQL необходимо задать переменные окружения `TASKBOARD_TEST_PG_HOST`, `TASKBOARD_TEST_PG_USER` и `TASKBOARD_TEST_PG_PASSWORD`; пользователь должен иметь право создавать базы данных. Если переменные не заданы, тесты пропускаются.
+//!
+//! Тесты хранилища SQLite (см. `start_sqlite`) работают без PostgreSQL: сервер получает одноразовый файл базы данных во временном каталоге.
+
+#![allow(dead_code)]
+
+use hyper::{Body, Client, HeaderMap, Method, Request, body::to_bytes, client::HttpConnector};
+use serde_json::Value as JsonValue;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_postgres::NoTls;
+
+/// Ключ администратора, с которым запускается тестовый сервер.
+pub const ADMIN_KEY: &str = "test-admin-key-test-admin-key-test-admin-key-test-admin-key-0000";
+
+static DB_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Параметры подключения к PostgreSQL для тестов.
+struct PgParams {
+  host: String,
+  user: String,
+  password: String,
+}
+
+impl PgParams {
+  /// Считывает параметры из переменных окружения.
+  fn from_env() -> Option<PgParams> {
+    Some(PgParams {
+      host: std::env::var("TASKBOARD_TEST_PG_HOST").ok()?,
+      user: std::env::var("TASKBOARD_TEST_PG_USER").ok()?,
+      password: std::env::var("TASKBOARD_TEST_PG_PASSWORD").ok()?,
+    })
+  }
+  
+  /// Формирует строку подключения к заданной базе данных.
+  fn conn_str(&self, dbname: &str) -> String {
+    format!("host={} user='{}' password='{}' dbname='{}'", self.host, self.user, self.password, dbname)
+  }
+  
+  /// Выполняет служебный запрос к базе данных postgres.
+  async fn execute(&self, query: &str) {
+    let (cli, conn) = tokio_postgres::connect(&self.conn_str("postgres"), NoTls).await
+      .expect("Не удалось подключиться к PostgreSQL.");
+    tokio::spawn(conn);
+    cli.batch_execute(query).await.expect("Не удалось выполнить служебный запрос.");
+  }
+}
+
+/// Запущенный сервер с одноразовой базой данных.
+pub struct TestServer {
+  /// Адрес, по которому слушает сервер.
+  pub addr: SocketAddr,
+  /// Параметры подключения к PostgreSQL; `None`, если сервер работает с SQLite.
+  pg: Option<PgParams>,
+  /// Название одноразовой базы данных или файла SQLite.
+  dbname: String,
+  child: Child,
+  client: Client<HttpConnector>,
+}
+
+impl TestServer {
+  /// Создаёт базу данных, запускает сервер и настраивает таблицы через `/pg-setup`.
+  ///
+  /// Возвращает None, если PostgreSQL для тестов не настроен.
+  pub async fn start() -> Option<TestServer> {
+    TestServer::start_with_env(&[]).await
+  }
+  
+  /// Запускает сервер, передавая ему дополнительные переменные окружения.
+  pub async fn start_with_env(envs: &[(&str, &str)]) -> Option<TestServer> {
+    TestServer::launch(envs, |cmd, pg, dbname, addr| {
+      cmd.arg("--env")
+        .env("POSTGRES_HOST", &pg.host)
+        .env("POSTGRES_USER", &pg.user)
+        .env("POSTGRES_PASSWORD", &pg.password)
+        .env("POSTGRES_DB", dbname)
+        .env("SERVER_LISTEN", addr.to_string())
+        .env("ADMIN_KEY", ADMIN_KEY);
+    }).await
+  }
+  
+  /// Запускает сервер с хранилищем в одноразовом файле SQLite, передавая ему дополнительные переменные окружения.
+  ///
+  /// Параметры подключения к PostgreSQL обязательны в конфигурации, поэтому сервер получает их, но не использует.
+  pub async fn start_sqlite(envs: &[(&str, &str)]) -> TestServer {
+    TestServer::spawn(None, unique_name(), envs, |cmd, _, dbname, addr| {
+      cmd.arg("--env")
+        .env("STORAGE", "sqlite")
+        .env("SQLITE_PATH", sqlite_path(dbname))
+        .env("POSTGRES_HOST", "127.0.0.1")
+        .env("POSTGRES_USER", "unused")
+        .env("POSTGRES_PASSWORD", "unused")
+        .env("SERVER_LISTEN", addr.to_string())
+        .env("ADMIN_KEY", ADMIN_KEY);
+    }).await
+  }
+  
+  /// Запускает сервер без аргументов, передавая конфигурацию в переменных окружения `TASKBOARD_*`.
+  pub async fn start_with_taskboard_env() -> Option<TestServer> {
+    TestServer::launch(&[], |cmd, pg, dbname, addr| {
+      cmd.env("TASKBOARD_PG", pg.conn_str(dbname))
+        .env("TASKBOARD_ADDR", addr.to_string())
+        .env("TASKBOARD_ADMIN_KEY", ADMIN_KEY);
+    }).await
+  }
+  
+  /// Запускает сервер с конфигурацией из JSON-файла, дополняя `cfg` параметрами подключения.
+  pub async fn start_with_config(cfg: JsonValue) -> Option<TestServer> {
+    TestServer::launch(&[], |cmd, pg, dbname, addr| {
+      let path = config_path(dbname);
+      std::fs::write(&path, full_config(cfg, pg, dbname, addr).to_string()).unwrap();
+      cmd.arg(path);
+    }).await
+  }
+  
+  /// Перезаписывает файл конфигурации сервера, запущенного при помощи `start_with_config`.
+  pub fn rewrite_config(&self, cfg: JsonValue) {
+    let cfg = full_config(cfg, self.pg(), &self.dbname, self.addr);
+    std::fs::write(config_path(&self.dbname), cfg.to_string()).unwrap();
+  }
+  
+  /// Создаёт базу данных и запускает сервер, конфигурацию которого задаёт `configure`.
+  async fn launch(envs: &[(&str, &str)], configure: impl FnOnce(&mut Command, &PgParams, &str, SocketAddr))
+    -> Option<TestServer>
+  {
+    let pg = match PgParams::from_env() {
+      Some(pg) => pg,
+      None => {
+        eprintln!("TASKBOARD_TEST_PG_* не заданы, тест пропущен.");
+        return None;
+      },
+    };
+    let dbname = unique_name();
+    pg.execute(&format!("create database {};", dbname)).await;
+    Some(TestServer::spawn(Some(pg), dbname, envs, |cmd, pg, dbname, addr| configure(cmd, pg.unwrap(), dbname, addr)).await)
+  }
+  
+  /// Запускает сервер, конфигурацию которого задаёт `configure`, и настраивает таблицы через `/pg-setup`.
+  async fn spawn(
+    pg: Option<PgParams>,
+    dbname: String,
+    envs: &[(&str, &str)],
+    configure: impl FnOnce(&mut Command, Option<&PgParams>, &str, SocketAddr),
+  ) -> TestServer {
+    let addr = {
+      let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+      listener.local_addr().unwrap()
+    };
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_cc-taskboard-server"));
+    // Конфигурация из окружения, в котором запущены тесты, не должна попасть в тестовый сервер.
+    cmd.env_remove("TASKBOARD_PG");
+    configure(&mut cmd, pg.as_ref(), &dbname, addr);
+    let child = cmd
+      .envs(envs.iter().copied())
+      .stdout(Stdio::null())
+      .spawn()
+      .expect("Не удалось запустить сервер.");
+    let server = TestServer { addr, pg, dbname, child, client: Client::new() };
+    server.wait_until_ready().await;
+    let (status, body) = server.request(
+      Method::GET, "/pg-setup", Some(&serde_json::json!({ "key": ADMIN_KEY })), None
+    ).await;
+    assert_eq!(status, 200, "Не удалось настроить базу данных: {}", body);
+    server
+  }
+  
+  /// Выполняет команду сервера (например, `export`) с той же базой данных, что и у запущенного сервера.
+  ///
+  /// Возвращает код завершения процесса, стандартный вывод и вывод ошибок.
+  pub fn command(&self, args: &[&str]) -> (i32, String, String) {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_cc-taskboard-server"));
+    cmd.args(args).env("TASKBOARD_ADDR", self.addr.to_string()).env("TASKBOARD_ADMIN_KEY", ADMIN_KEY);
+    match &self.pg {
+      Some(pg) => cmd.env("TASKBOARD_PG", pg.conn_str(&self.dbname)),
+      None => cmd.env("TASKBOARD_PG", "host=127.0.0.1")
+        .env("TASKBOARD_STORAGE", "sqlite")
+        .env("TASKBOARD_SQLITE_PATH", sqlite_path(&self.dbname)),
+    };
+    let output = cmd.output().expect("Не удалось запустить команду сервера.");
+    (
+      output.status.code().unwrap_or(-1),
+      String::from_utf8(output.stdout).unwrap(),
+      String::from_utf8(output.stderr).unwrap(),
+    )
+  }
+  
+  /// Возвращает параметры подключения к PostgreSQL.
+  fn pg(&self) -> &PgParams {
+    self.pg.as_ref().expect("Сервер работает без PostgreSQL.")
+  }
+  
+  /// Ожидает, пока сервер начнёт принимать соединения.
+  async fn wait_until_ready(&self) {
+    for _ in 0..100 {
+      if tokio::net::TcpStream::connect(self.addr).await.is_ok() { return; };
+      tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    panic!("Сервер не запустился за отведённое время.");
+  }
+  
+  /// Отправляет запрос серверу и возвращает код ответа и его тело.
+  ///
+  /// Заголовок `App-Token` и тело запроса кодируются в base64 так же, как это делает клиент.
+  pub async fn request(
+    &self,
+    method: Method,
+    path: &str,
+    app_token: Option<&JsonValue>,
+    body: Option<&JsonValue>,
+  ) -> (u16, String) {
+    let body = match body {
+      Some(body) => Body::from(encode(body)),
+      None => Body::empty(),
+    };
+    self.request_raw(method, path, app_token, body).await
+  }
+  
+  /// Отправляет запрос с телом, переданным как есть, без кодирования в base64.
+  pub async fn request_raw(
+    &self,
+    method: Method,
+    path: &str,
+    app_token: Option<&JsonValue>,
+    body: Body,
+  ) -> (u16, String) {
+    let app_token = app_token.map(encode);
+    let headers: Vec<(&str, &str)> = app_token.iter().map(|t| ("App-Token", t.as_str())).collect();
+    self.request_with_headers(method, path, &headers, body).await
+  }
+  
+  /// Отправляет запрос с произвольными заголовками и телом, переданным как есть.
+  pub async fn request_with_headers(
+    &self,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Body,
+  ) -> (u16, String) {
+    let (status, _, body) = self.request_bytes(method, path, headers, body).await;
+    (status, String::from_utf8(body).unwrap())
+  }
+  
+  /// Отправляет запрос так же, как `request_with_headers`, и возвращает заголовки ответа и тело в виде байт.
+  pub async fn request_bytes(
+    &self,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Body,
+  ) -> (u16, HeaderMap, Vec<u8>) {
+    let mut req = Request::builder().method(method).uri(format!("http://{}{}", self.addr, path));
+    for (name, value) in headers {
+      req = req.header(*name, *value);
+    };
+    let req = req.body(body).unwrap();
+    let res = self.client.request(req).await.expect("Сервер не ответил на запрос.");
+    let (parts, body) = res.into_parts();
+    let body = to_bytes(body).await.unwrap();
+    (parts.status.as_u16(), parts.headers, body.to_vec())
+  }
+  
+  /// Регистрирует пользователя и возвращает его токен в виде JSON для заголовка `App-Token`.
+  pub async fn sign_up(&self, login: &str) -> JsonValue {
+    let (status, body) = self.request(
+      Method::PUT, "/sign-up", Some(&serde_json::json!({ "login": login, "pass": "Kettle-Orbit-42" })), None
+    ).await;
+    assert_eq!(status, 200, "Не удалось зарегистрировать пользователя: {}", body);
+    serde_json::from_str(&body).unwrap()
+  }
+  
+  /// Выполняет SQL-запросы напрямую в базе данных сервера.
+  ///
+  /// Нужен для подготовки данных, которые невозможно получить через API, например, записанных старыми версиями сервера.
+  pub async fn sql(&self, query: &str) {
+    let (cli, conn) = tokio_postgres::connect(&self.pg().conn_str(&self.dbname), NoTls).await
+      .expect("Не удалось подключиться к тестовой базе данных.");
+    tokio::spawn(conn);
+    cli.batch_execute(query).await.expect("Не удалось выполнить запрос к тестовой базе данных.");
+  }
+  
+  /// Выполняет SQL-запросы в отдельном соединении с базой данных сервера и оставляет соединение открытым.
+  ///
+  /// Соединение закрывается вместе с возвращённым клиентом, поэтому начатая в нём транзакция - например, с блокировкой таблицы - действует, пока клиент жив.
+  pub async fn hold(&self, query: &str) -> tokio_postgres::Client {
+    let (cli, conn) = tokio_postgres::connect(&self.pg().conn_str(&self.dbname), NoTls).await
+      .expect("Не удалось подключиться к тестовой базе данных.");
+    tokio::spawn(conn);
+    cli.batch_execute(query).await.expect("Не удалось выполнить запрос к тестовой базе данных.");
+    cli
+  }
+  
+  /// Создаёт доску и возвращает её идентификатор.
+  pub async fn create_board(&self, token: &JsonValue, title: &str) -> i64 {
+    let (status, board_id) = self.request(Method::PUT, "/board", Some(token), Some(&serde_json::json!({
+      "id": 0,
+      "author": 0,
+      "shared_with": [],
+      "header": { "title": title, "header_background_color": "#ffffff", "header_text_color": "#000000" },
+      "cards": [],
+      "background": { "color": "#eeeeee" }
+    }))).await;
+    assert_eq!(status, 200, "Не удалось создать доску: {}", board_id);
+    board_id.parse().unwrap()
+  }
+  
+  /// Останавливает сервер и удаляет базу данных.
+  pub async fn stop(mut self) {
+    self.child.kill().ok();
+    self.child.wait().ok();
+    match &self.pg {
+      Some(pg) => pg.execute(&format!("drop database if exists {};", self.dbname)).await,
+      None => { std::fs::remove_file(sqlite_path(&self.dbname)).ok(); },
+    };
+    std::fs::remove_file(config_path(&self.dbname)).ok();
+  }
+}
+
+/// Возвращает название для одноразовой базы данных, не совпадающее с названиями баз данных других тестов.
+fn unique_name() -> String {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+  format!("taskboard_test_{}_{}_{}", std::process::id(), DB_COUNTER.fetch_add(1, Ordering::SeqCst), nanos)
+}
+
+/// Возвращает путь к файлу SQLite сервера, запущенного при помощи `start_sqlite`.
+fn sqlite_path(dbname: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("{}.sqlite3", dbname))
+}
+
+/// Возвращает путь к файлу конфигурации сервера, работающего с данной базой данных.
+fn config_path(dbname: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("{}.json", dbname))
+}
+
+/// Дополняет конфигурацию параметрами подключения к базе данных, адресом сервера и ключом администратора.
+///
+/// Если в `cfg` поле `pg` задано объектом, параметры подключения дописываются в него, иначе передаются строкой подключения.
+fn full_config(mut cfg: JsonValue, pg: &PgParams, dbname: &str, addr: SocketAddr) -> JsonValue {
+  match cfg["pg"].as_object_mut() {
+    Some(params) => {
+      params.insert("host".into(), pg.host.clone().into());
+      params.insert("user".into(), pg.user.clone().into());
+      params.insert("password".into(), pg.password.clone().into());
+      params.insert("dbname".into(), dbname.into());
+    },
+    None => cfg["pg"] = pg.conn_str(dbname).into(),
+  };
+  cfg["hyper_addr"] = addr.to_string().into();
+  cfg["admin_key"] = ADMIN_KEY.into();
+  cfg
+}
+
+impl Drop for TestServer {
+  fn drop(&mut self) {
+    self.child.kill().ok();
+  }
+}
+
+/// Кодирует JSON в base64.
+pub fn encode(value: &JsonValue) -> String {
+  base64::encode(&serde_json::to_string(value).unwrap())
+}
+
+/// Возвращает пустые временные рамки.
+pub fn no_timelines() -> JsonValue {
+  serde_json::json!({ "preferred_time": 0, "max_time": 0, "expected_time": 0 })
+}